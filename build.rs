@@ -0,0 +1,6 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // Only the grpc feature has generated code
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/syndactyl.proto").expect("Failed to compile proto/syndactyl.proto");
+}
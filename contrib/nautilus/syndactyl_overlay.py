@@ -0,0 +1,121 @@
+"""Reference overlay client: sync emblems for Nautilus, fed by the syndactyl daemon.
+
+Install by copying this file to ~/.local/share/nautilus-python/extensions/
+(needs the nautilus-python package), then restart Nautilus with `nautilus -q`.
+
+Protocol, on the daemon's control socket (~/.config/syndactyl/control.sock by
+default, or $SYNDACTYL_CONTROL_SOCKET). Each message is one JSON object per line,
+and every request carries the token from ~/.config/syndactyl/control.token (or
+the file named by $SYNDACTYL_CONTROL_TOKEN) as "token"; it is left out below.
+
+  -> {"command": "file_status", "paths": ["/abs/path", ...]}
+  <- {"result": "file_statuses", "files": [{"path": ..., "observer": ..., "state": ...}]}
+
+  -> {"command": "watch_files"}
+  <- {"result": "done", "message": "Watching file status"}
+  <- {"result": "file_status", "path": ..., "observer": ..., "state": ...}   (per change)
+  <- {"result": "resync"}   (updates were dropped; query file_status again)
+
+States: unwatched, unavailable, paused, syncing, pending, offline, synced.
+Shims for other file managers (Finder Sync, Explorer overlay handlers) follow
+the same pattern: query the files on display, then apply watched changes.
+"""
+
+import json
+import os
+import socket
+import threading
+from urllib.parse import unquote, urlparse
+
+from gi.repository import GLib, GObject, Nautilus
+
+SOCKET_PATH = os.environ.get(
+    "SYNDACTYL_CONTROL_SOCKET",
+    os.path.expanduser("~/.config/syndactyl/control.sock"),
+)
+
+TOKEN_PATH = os.environ.get(
+    "SYNDACTYL_CONTROL_TOKEN",
+    os.path.expanduser("~/.config/syndactyl/control.token"),
+)
+
+EMBLEMS = {
+    "synced": "emblem-default",
+    "syncing": "emblem-synchronizing",
+    "pending": "emblem-synchronizing",
+    "paused": "emblem-important",
+    "offline": "emblem-unreadable",
+    "unavailable": "emblem-unreadable",
+}
+
+
+def encode(message):
+    """One request line, carrying the control token."""
+    with open(TOKEN_PATH) as token:
+        message = dict(message, token=token.read().strip())
+    return (json.dumps(message) + "\n").encode()
+
+
+def request(message):
+    """Send one request and return the reply, or None if the daemon is not running."""
+    try:
+        with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+            sock.settimeout(0.5)
+            sock.connect(SOCKET_PATH)
+            sock.sendall(encode(message))
+            return json.loads(sock.makefile().readline())
+    except (OSError, ValueError):
+        return None
+
+
+class SyndactylOverlay(GObject.GObject, Nautilus.InfoProvider):
+    def __init__(self):
+        super().__init__()
+        # Files Nautilus has shown us, so changes can be applied to them
+        self.files = {}
+        threading.Thread(target=self.watch, daemon=True).start()
+
+    def update_file_info(self, file):
+        path = unquote(urlparse(file.get_uri()).path)
+        self.files[path] = file
+        reply = request({"command": "file_status", "paths": [path]})
+        if reply and reply.get("result") == "file_statuses":
+            for status in reply["files"]:
+                self.apply(status)
+        return Nautilus.OperationResult.COMPLETE
+
+    def apply(self, status):
+        file = self.files.get(status["path"])
+        emblem = EMBLEMS.get(status["state"])
+        if file is not None and emblem:
+            file.add_emblem(emblem)
+
+    def watch(self):
+        """Follow the change feed, reconnecting whenever the daemon restarts."""
+        while True:
+            try:
+                with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as sock:
+                    sock.connect(SOCKET_PATH)
+                    sock.sendall(encode({"command": "watch_files"}))
+                    for line in sock.makefile():
+                        message = json.loads(line)
+                        if message.get("result") == "file_status":
+                            # Emblems can only be changed from the main loop
+                            GLib.idle_add(self.changed, message)
+                        elif message.get("result") == "resync":
+                            GLib.idle_add(self.invalidate_all)
+            except (OSError, ValueError):
+                pass
+            threading.Event().wait(5)
+
+    def changed(self, status):
+        file = self.files.get(status["path"])
+        if file is not None:
+            # Nautilus asks update_file_info again, which fetches the new state
+            file.invalidate_extension_info()
+        return False
+
+    def invalidate_all(self):
+        for file in self.files.values():
+            file.invalidate_extension_info()
+        return False
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use syndactyl::network::wire;
+
+// Gossip payloads: arbitrary bytes must never panic the decoder
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = wire::decode_file_event(data) {
+        // Anything accepted must re-encode and decode to the same message
+        let json = serde_json::to_vec(&msg).unwrap();
+        assert_eq!(wire::decode_file_event(&json).unwrap(), msg);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use syndactyl::core::models::SyndactylRequest;
+use syndactyl::network::wire;
+
+// request_response payloads arrive CBOR encoded, as libp2p's cbor codec produces them
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = cbor4ii::serde::from_slice::<SyndactylRequest>(data) {
+        let _ = wire::validate_request(&request);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use syndactyl::core::models::SyndactylResponse;
+use syndactyl::network::wire;
+
+// request_response payloads arrive CBOR encoded, as libp2p's cbor codec produces them
+fuzz_target!(|data: &[u8]| {
+    if let Ok(response) = cbor4ii::serde::from_slice::<SyndactylResponse>(data) {
+        let _ = wire::validate_response(&response);
+    }
+});
@@ -0,0 +1,115 @@
+#[cfg(feature = "bridges")]
+pub mod mqtt;
+#[cfg(feature = "bridges")]
+pub mod webhook;
+
+use tokio::sync::mpsc;
+use tracing::warn;
+use crate::control::ControlCommand;
+use crate::core::auth_failures::AuthAlert;
+use crate::core::config::BridgeConfig;
+use crate::core::disk_space::DiskSpaceAlert;
+use crate::core::models::FileEventMessage;
+use crate::core::secret;
+
+/// Events buffered per bridge before new ones are dropped
+const BRIDGE_QUEUE_SIZE: usize = 256;
+
+/// What a bridge forwards
+#[derive(Debug, Clone)]
+pub enum BridgeEvent {
+    File(FileEventMessage),
+    /// A peer keeps failing authentication; only sent to bridges with `alerts`
+    AuthAlert(AuthAlert),
+    /// An observer's volume ran low on space or recovered; only sent to bridges with `alerts`
+    DiskSpace(DiskSpaceAlert),
+}
+
+/// One running bridge: a filter plus the queue feeding its worker task
+struct Bridge {
+    name: String,
+    observers: Option<Vec<String>>,
+    alerts: bool,
+    queue: mpsc::Sender<BridgeEvent>,
+}
+
+/// Forwards verified file announcements to external systems
+/// Each bridge runs in its own task behind a bounded queue, so a slow or
+/// unreachable endpoint never stalls the network event loop.
+#[derive(Default)]
+pub struct BridgeSet {
+    bridges: Vec<Bridge>,
+}
+
+impl BridgeSet {
+    /// Spawn a worker for every configured bridge
+    /// Bridges that accept commands forward them to the manager via `commands`.
+    /// `node` is this node's peer ID, which names it to brokers.
+    /// Must be called from within the tokio runtime.
+    pub fn start(configs: &[BridgeConfig], node: &str, commands: mpsc::Sender<ControlCommand>) -> Result<Self, String> {
+        let mut bridges = Vec::new();
+        for (index, config) in configs.iter().enumerate() {
+            let (queue, rx) = mpsc::channel(BRIDGE_QUEUE_SIZE);
+            spawn(config, (node, index), rx, &commands)?;
+            bridges.push(Bridge {
+                name: format!("{} {}", config.kind, secret::redact_url(config.url.expose())),
+                observers: config.observers.clone(),
+                alerts: config.alerts == Some(true),
+                queue,
+            });
+        }
+        Ok(Self { bridges })
+    }
+
+    /// Queue an event for every bridge interested in its observer
+    pub fn publish(&self, event: &FileEventMessage) {
+        for bridge in &self.bridges {
+            let wanted = bridge.observers.as_ref()
+                .is_none_or(|observers| observers.contains(&event.observer));
+            if !wanted {
+                continue;
+            }
+            if bridge.queue.try_send(BridgeEvent::File(event.clone())).is_err() {
+                warn!(bridge = %bridge.name, observer = %event.observer, path = %event.path, "Bridge queue full, dropping event");
+            }
+        }
+    }
+
+    /// Queue an authentication alert for every bridge that forwards alerts
+    pub fn alert(&self, alert: &AuthAlert) {
+        for bridge in self.bridges.iter().filter(|bridge| bridge.alerts) {
+            if bridge.queue.try_send(BridgeEvent::AuthAlert(alert.clone())).is_err() {
+                warn!(bridge = %bridge.name, peer = %alert.peer, "Bridge queue full, dropping alert");
+            }
+        }
+    }
+
+    /// Queue a disk space alert for every bridge that forwards alerts and watches its observer
+    pub fn disk_space(&self, alert: &DiskSpaceAlert) {
+        for bridge in self.bridges.iter().filter(|bridge| bridge.alerts) {
+            let wanted = bridge.observers.as_ref()
+                .is_none_or(|observers| observers.contains(&alert.observer));
+            if !wanted {
+                continue;
+            }
+            if bridge.queue.try_send(BridgeEvent::DiskSpace(alert.clone())).is_err() {
+                warn!(bridge = %bridge.name, observer = %alert.observer, "Bridge queue full, dropping alert");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "bridges")]
+fn spawn(config: &BridgeConfig, (node, index): (&str, usize), rx: mpsc::Receiver<BridgeEvent>, commands: &mpsc::Sender<ControlCommand>) -> Result<(), String> {
+    match config.kind.as_str() {
+        "webhook" => webhook::spawn(config.clone(), rx),
+        "mqtt" => mqtt::spawn(config.clone(), mqtt::client_id(node, index), rx, commands.clone()),
+        other => Err(format!("Unknown bridge kind '{}'", other)),
+    }
+}
+
+/// Headless builds leave the bridge clients out, so any configured bridge is an error
+#[cfg(not(feature = "bridges"))]
+fn spawn(config: &BridgeConfig, _bridge: (&str, usize), _rx: mpsc::Receiver<BridgeEvent>, _commands: &mpsc::Sender<ControlCommand>) -> Result<(), String> {
+    Err(format!("Bridge kind '{}' is not available: built without the bridges feature", config.kind))
+}
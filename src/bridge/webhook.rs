@@ -0,0 +1,69 @@
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use crate::core::auth;
+use crate::bridge::BridgeEvent;
+use crate::core::config::BridgeConfig;
+use crate::core::secret;
+
+/// Delivery attempts per event before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Start a task POSTing each event as JSON to the configured URL
+/// With a secret set, the body's HMAC-SHA256 is sent as
+/// `X-Syndactyl-Signature: sha256=<hex>` so receivers can authenticate it.
+/// `X-Syndactyl-Event` tells file events from alerts.
+pub fn spawn(config: BridgeConfig, mut events: mpsc::Receiver<BridgeEvent>) -> Result<(), String> {
+    let url = secret::redact_url(config.url.expose());
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("Failed to create webhook client: {}", e))?;
+
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let (kind, observer, body) = match &event {
+                BridgeEvent::File(event) => ("file", Some(event.observer.as_str()), serde_json::to_vec(event)),
+                BridgeEvent::AuthAlert(alert) => ("auth_alert", None, serde_json::to_vec(alert)),
+                BridgeEvent::DiskSpace(alert) => ("disk_space", Some(alert.observer.as_str()), serde_json::to_vec(alert)),
+            };
+            let body = match body {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!(error = %e, "Failed to serialize event for webhook");
+                    continue;
+                }
+            };
+
+            for attempt in 1..=MAX_ATTEMPTS {
+                let mut request = client.post(config.url.expose())
+                    .header("Content-Type", "application/json")
+                    .header("X-Syndactyl-Event", kind)
+                    .body(body.clone());
+                if let Some(observer) = observer {
+                    request = request.header("X-Syndactyl-Observer", observer);
+                }
+                if let Some(secret) = &config.secret {
+                    request = request.header("X-Syndactyl-Signature", format!("sha256={}", auth::sign_bytes(&body, secret.expose())));
+                }
+
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        debug!(url = %url, kind, "Webhook delivered");
+                        break;
+                    }
+                    Ok(response) => {
+                        warn!(url = %url, status = %response.status(), attempt, "Webhook rejected event");
+                    }
+                    Err(e) => {
+                        warn!(url = %url, error = %e.without_url(), attempt, "Webhook delivery failed");
+                    }
+                }
+                if attempt < MAX_ATTEMPTS {
+                    tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                }
+            }
+        }
+    });
+    Ok(())
+}
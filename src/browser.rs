@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+use crate::control::{self, ControlRequest, ControlResponse, Preview};
+use crate::core::audit::AuditLog;
+use crate::core::auth;
+use crate::core::config::{BrowserConfig, ObserverConfig};
+use crate::core::file_handler;
+use crate::core::listen;
+use crate::core::secret::Secret;
+
+/// Largest request head accepted; requests carry no body
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// How long a client may take to send its request head before it's dropped
+const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a preview may take, the peer's reply included
+const PEEK_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Browser {
+    token: Secret,
+    /// Browsable observers, name -> root directory
+    roots: HashMap<String, PathBuf>,
+    /// Control socket and token file, for previewing peers' copies of files
+    control: Option<(PathBuf, PathBuf)>,
+    /// Where downloads are recorded, as files served to peers are
+    audit_log: Option<AuditLog>,
+}
+
+/// What a request resolved to
+#[derive(Debug, PartialEq)]
+enum Target {
+    /// The list of browsable observers
+    Index,
+    /// A path inside an observer, relative to its root
+    Entry { observer: String, relative: PathBuf },
+}
+
+/// Serve the configured observers read-only over HTTP
+/// Every request needs the token, either as `Authorization: Bearer <token>` or
+/// as a `?token=` query parameter for clients that can't set headers. Hidden
+/// and internal files are never listed or served, and symlinks are not followed.
+/// Encrypted and announce-only observers have no plain tree and can't be browsed.
+/// With `?peek` a file is shown as a peer holds it, read over the control socket.
+/// Downloads are recorded in `audit_log` under the client's address.
+pub async fn spawn(config: &BrowserConfig, observers: &[ObserverConfig], control: Option<(PathBuf, PathBuf)>, audit_log: Option<AuditLog>) -> Result<(), String> {
+    if config.token.is_empty() {
+        return Err("File browser token must not be empty".to_string());
+    }
+    let mut roots = HashMap::new();
+    for name in &config.observers {
+        let observer = observers.iter()
+            .find(|o| &o.name == name)
+            .ok_or_else(|| format!("File browser observer '{}' is not configured", name))?;
+        if observer.at_rest_key.is_some() || observer.announce_only == Some(true) {
+            return Err(format!("Observer '{}' has no plain files to browse", name));
+        }
+        roots.insert(name.clone(), PathBuf::from(&observer.path));
+    }
+
+    let addr = listen::resolve("File browser", &config.listen, config.public == Some(true))?;
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind file browser to {}: {}", addr, e))?;
+    info!(listen = %addr, observers = ?config.observers, "File browser listening");
+
+    let browser = Arc::new(Browser { token: config.token.clone(), roots, control, audit_log });
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let browser = browser.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = browser.handle_connection(stream, addr).await {
+                            debug!(%addr, error = %e, "File browser connection ended");
+                        }
+                    });
+                }
+                Err(e) => warn!(error = %e, "Failed to accept file browser connection"),
+            }
+        }
+    });
+    Ok(())
+}
+
+impl Browser {
+    async fn handle_connection(&self, mut stream: TcpStream, client: SocketAddr) -> std::io::Result<()> {
+        let head = tokio::time::timeout(HEAD_TIMEOUT, read_head(&mut stream)).await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request head not sent in time"))??;
+        let Some(head) = head else {
+            return respond(&mut stream, 400, "text/plain", b"Bad request").await;
+        };
+        let mut lines = head.lines();
+        let mut request_line = lines.next().unwrap_or_default().split(' ');
+        let (method, target) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+        let headers: HashMap<String, String> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+
+        if method != "GET" && method != "HEAD" {
+            return respond(&mut stream, 405, "text/plain", b"Method not allowed").await;
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if !self.authorized(headers.get("authorization"), query) {
+            return respond(&mut stream, 401, "text/plain", b"Unauthorized").await;
+        }
+        let Some(resolved) = resolve(path) else {
+            return respond(&mut stream, 404, "text/plain", b"Not found").await;
+        };
+        let head_only = method == "HEAD";
+        // Links carry the token along so query-authenticated clients can navigate
+        let link_query = if query.is_empty() { String::new() } else { format!("?{}", query) };
+
+        match resolved {
+            Target::Index => {
+                let mut names: Vec<&String> = self.roots.keys().collect();
+                names.sort();
+                let entries: Vec<(String, bool)> = names.into_iter().map(|n| (n.clone(), true)).collect();
+                let page = listing_page("/", &entries, &link_query);
+                respond_body(&mut stream, "text/html; charset=utf-8", page.as_bytes(), head_only).await
+            }
+            Target::Entry { observer, relative } => {
+                let Some(root) = self.roots.get(&observer) else {
+                    return respond(&mut stream, 404, "text/plain", b"Not found").await;
+                };
+                // A peer's copy, which may differ from ours or not exist here at all
+                if query.split('&').any(|pair| pair == "peek") {
+                    let page = match self.peek(&observer, &relative, query).await {
+                        Ok(preview) => preview_page(&preview),
+                        Err(e) => return respond(&mut stream, 502, "text/plain", e.as_bytes()).await,
+                    };
+                    return respond_body(&mut stream, "text/html; charset=utf-8", page.as_bytes(), head_only).await;
+                }
+                let absolute = root.join(&relative);
+                // Also catches symlinked directories earlier in the path
+                let inside_root = match (tokio::fs::canonicalize(root).await, tokio::fs::canonicalize(&absolute).await) {
+                    (Ok(root), Ok(resolved)) => resolved.starts_with(root),
+                    _ => false,
+                };
+                let metadata = match tokio::fs::symlink_metadata(&absolute).await {
+                    Ok(metadata) if inside_root && !metadata.file_type().is_symlink() => metadata,
+                    _ => return respond(&mut stream, 404, "text/plain", b"Not found").await,
+                };
+                if metadata.is_dir() {
+                    let entries = list_dir(&absolute, &relative).await?;
+                    let page = listing_page(path, &entries, &link_query);
+                    respond_body(&mut stream, "text/html; charset=utf-8", page.as_bytes(), head_only).await
+                } else {
+                    let mut sent = None;
+                    let result = send_file(&mut stream, &absolute, metadata.len(), head_only, &mut sent).await;
+                    if let Some(bytes) = sent {
+                        self.record_download(client, &observer, &relative, bytes);
+                    }
+                    result
+                }
+            }
+        }
+    }
+
+    fn authorized(&self, authorization: Option<&String>, query: &str) -> bool {
+        let from_header = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        let from_query = query.split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .and_then(percent_decode);
+        from_header.is_some_and(|token| auth::constant_time_compare(token, self.token.expose()))
+            || from_query.is_some_and(|token| auth::constant_time_compare(&token, self.token.expose()))
+    }
+
+    /// Record a download in the audit log, including one the client broke off
+    fn record_download(&self, client: SocketAddr, observer: &str, relative: &Path, bytes: u64) {
+        let Some(audit_log) = &self.audit_log else {
+            return;
+        };
+        let path = wire_path(relative);
+        if let Err(e) = audit_log.record(&format!("browser:{}", client), observer, &path, 0, bytes) {
+            warn!(%client, observer = %observer, path = %path, error = %e, "Failed to write audit log entry");
+        }
+    }
+
+    /// Read the start of a peer's copy of a file through the daemon, as `syndactyl peek` does
+    async fn peek(&self, observer: &str, relative: &Path, query: &str) -> Result<Preview, String> {
+        let Some((socket, token_path)) = self.control.clone() else {
+            return Err("Previews need the control socket, which isn't available".to_string());
+        };
+        let param = |name: &str| query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('=')).and_then(percent_decode);
+        let request = ControlRequest::Peek {
+            observer: observer.to_string(),
+            path: wire_path(relative),
+            peer: param("peer"),
+            bytes: None,
+            lines: param("lines").and_then(|lines| lines.parse().ok()),
+        };
+        let response = tokio::task::spawn_blocking(move || {
+            let token = control::read_token(&token_path)?;
+            control::request_within(&socket, &token, &request, Some(PEEK_TIMEOUT))
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        match response {
+            Ok(ControlResponse::Preview(preview)) => Ok(preview),
+            Ok(ControlResponse::Error { message }) => Err(message),
+            Ok(response) => Err(format!("Unexpected reply from daemon: {:?}", response)),
+            Err(e) => Err(format!("Could not reach the daemon: {}", e)),
+        }
+    }
+}
+
+/// A relative path as the daemon names it, with forward slashes on every platform
+fn wire_path(relative: &Path) -> String {
+    relative.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+/// Map a request path onto an observer and a relative path inside it
+/// Anything escaping the root or touching hidden/internal files resolves to None.
+fn resolve(path: &str) -> Option<Target> {
+    let decoded = percent_decode(path)?;
+    let mut segments = decoded.split('/').filter(|s| !s.is_empty());
+    let Some(observer) = segments.next() else {
+        return Some(Target::Index);
+    };
+    let relative: PathBuf = segments.collect();
+    let safe = relative.components().all(|c| matches!(c, Component::Normal(_)));
+    if !safe || decoded.contains('\0') {
+        return None;
+    }
+    if !relative.iter().all(|c| file_handler::should_sync_file(Path::new(c))) {
+        return None;
+    }
+    Some(Target::Entry { observer: observer.to_string(), relative })
+}
+
+/// Syncable entries of a directory, sorted, as (name, is_dir)
+async fn list_dir(absolute: &Path, relative: &Path) -> std::io::Result<Vec<(String, bool)>> {
+    let mut entries = Vec::new();
+    let mut dir = tokio::fs::read_dir(absolute).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let file_type = entry.file_type().await?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if file_type.is_symlink() || !file_handler::should_sync_file(&relative.join(&name)) {
+            continue;
+        }
+        entries.push((name, file_type.is_dir()));
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+fn listing_page(path: &str, entries: &[(String, bool)], link_query: &str) -> String {
+    let base = if path.ends_with('/') { path.to_string() } else { format!("{}/", path) };
+    let title = html_escape(&percent_decode(&base).unwrap_or_default());
+    let mut page = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n<ul>\n", title);
+    if base != "/" {
+        page.push_str(&format!("<li><a href=\"../{}\">../</a></li>\n", html_escape(link_query)));
+    }
+    let peek_query = if link_query.is_empty() { "?peek".to_string() } else { format!("{}&peek", link_query) };
+    for (name, is_dir) in entries {
+        let suffix = if *is_dir { "/" } else { "" };
+        let href = format!("{}{}{}", base, percent_encode(name), suffix);
+        // The base and query come from the request as sent, so they are escaped like any text
+        // Files link to a peer's copy too, to see what a conflicting version holds
+        let peek = if *is_dir { String::new() } else { format!(" <a href=\"{}\">[peer copy]</a>", html_escape(&format!("{}{}", href, peek_query))) };
+        page.push_str(&format!("<li><a href=\"{}\">{}{}</a>{}</li>\n", html_escape(&format!("{}{}", href, link_query)), html_escape(name), suffix, peek));
+    }
+    page.push_str("</ul>\n</body></html>\n");
+    page
+}
+
+/// A peer's copy of a file, shown as text
+fn preview_page(preview: &Preview) -> String {
+    let title = html_escape(&format!("{}/{} on {}", preview.observer, preview.path, preview.peer));
+    let mut page = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n", title);
+    page.push_str(&format!("<p>{} bytes, version {}</p>\n", preview.total_size, html_escape(&preview.hash)));
+    if preview.binary {
+        page.push_str("<p>Binary file, not shown</p>\n");
+    } else {
+        page.push_str(&format!("<pre>{}</pre>\n", html_escape(&preview.content)));
+        if preview.truncated {
+            page.push_str("<p>(truncated)</p>\n");
+        }
+    }
+    page.push_str("</body></html>\n");
+    page
+}
+
+/// Send a file, counting the body bytes written in `sent` once the body starts
+async fn send_file(stream: &mut TcpStream, path: &Path, len: u64, head_only: bool, sent: &mut Option<u64>) -> std::io::Result<()> {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(_) => return respond(stream, 404, "text/plain", b"Not found").await,
+    };
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        len,
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if !head_only {
+        // Never send more than was announced, even if the file grew meanwhile
+        let mut file = file.take(len);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let sent = sent.insert(0);
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            stream.write_all(&buffer[..read]).await?;
+            *sent += read as u64;
+        }
+    }
+    stream.shutdown().await
+}
+
+/// Read up to the blank line ending the request head, or None if it is too long or malformed
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 || buffer.len() + read > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    Ok(String::from_utf8(buffer).ok())
+}
+
+async fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    let reason = match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        502 => "Bad Gateway",
+        _ => "OK",
+    };
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status, reason, content_type, body.len(),
+    );
+    if status == 401 {
+        head.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await
+}
+
+async fn respond_body(stream: &mut TcpStream, content_type: &str, body: &[u8], head_only: bool) -> std::io::Result<()> {
+    respond(stream, 200, content_type, if head_only { &[] } else { body }).await
+}
+
+/// Decode %XX escapes; None if an escape is malformed or the result isn't UTF-8
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(input: &str) -> String {
+    input.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn html_escape(input: &str) -> String {
+    input.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_rejects_escapes_and_hidden_files() {
+        assert_eq!(resolve("/"), Some(Target::Index));
+        assert_eq!(resolve("/photos/2024/beach%20day.jpg"), Some(Target::Entry {
+            observer: "photos".to_string(),
+            relative: PathBuf::from("2024/beach day.jpg"),
+        }));
+        assert_eq!(resolve("/photos/../etc/passwd"), None);
+        assert_eq!(resolve("/photos/%2e%2e/secret"), None);
+        assert_eq!(resolve("/photos/.syndactyl/trash"), None);
+        assert_eq!(resolve("/photos/.hidden/file"), None);
+        assert_eq!(resolve("/photos/bad%zz"), None);
+    }
+
+    #[test]
+    fn test_percent_encoding_round_trips() {
+        let name = "holiday <2024> & more.jpg";
+        assert_eq!(percent_decode(&percent_encode(name)).as_deref(), Some(name));
+        assert_eq!(html_escape("<a href=\"x\">&"), "&lt;a href=&quot;x&quot;&gt;&amp;");
+    }
+
+    #[test]
+    fn test_listings_link_to_peer_copies_and_previews_are_escaped() {
+        let page = listing_page("/docs", &[("a <b>.txt".to_string(), false), ("sub".to_string(), true)], "?token=t");
+        assert!(page.contains("<a href=\"/docs/a%20%3Cb%3E.txt?token=t&amp;peek\">[peer copy]</a>"));
+        assert!(!page.contains("sub/?token=t&amp;peek"));
+
+        // Whatever the request path and token hold can't break out of the attribute
+        let page = listing_page("/docs/\"><script>", &[("a.txt".to_string(), false)], "?token=\"x");
+        assert!(page.contains("<a href=\"/docs/&quot;&gt;&lt;script&gt;/a.txt?token=&quot;x\">"));
+        assert!(!page.contains("<script>"));
+
+        let preview = Preview::new("docs".to_string(), "a.txt".to_string(), "peer-a".to_string(), "h1".to_string(), 40, b"<script>\nline 2\n", Some(1));
+        let page = preview_page(&preview);
+        assert!(page.contains("<pre>&lt;script&gt;\n</pre>"));
+        assert!(page.contains("(truncated)"));
+    }
+}
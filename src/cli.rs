@@ -0,0 +1,271 @@
+use std::path::PathBuf;
+
+/// Commands accepted on the syndactyl command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Run the sync daemon (default when no arguments are given)
+    /// With `takeover`, a daemon already running on the same state directory is stopped first.
+    /// With `local_only`, networking is disabled and changes are only reported.
+    Run { takeover: bool, local_only: bool },
+    /// Create the configuration by answering questions
+    Init,
+    /// Verify the hash chain of an audit log
+    /// Uses the configured audit_log path when no path is given
+    AuditVerify { path: Option<PathBuf> },
+    /// Print statistics from the persisted daemon state
+    /// Each flag selects a section; plain `stats` selects all of them
+    Stats { bandwidth: bool, history: bool },
+    /// Query the running daemon's status over the control socket
+    Status,
+    /// List connected peers with their latency, throughput and version
+    Peers,
+    /// Print this node's peer ID and reachable addresses, optionally as a QR code for pairing
+    Id { qr: bool },
+    /// Pause downloads for one observer, or all of them
+    Pause { observer: Option<String> },
+    /// Resume paused downloads
+    Resume { observer: Option<String> },
+    /// Re-announce local files so peers pick up anything they missed
+    Rescan { observer: Option<String> },
+    /// Switch the running daemon to a sync profile
+    ProfileUse { name: String },
+    /// Drop the selected sync profile, going back to the configured default
+    ProfileClear,
+    /// Announce deletions held by the mass-deletion guard
+    DeletionsConfirm { observer: Option<String> },
+    /// Drop held deletions so peers keep their copies
+    DeletionsDiscard { observer: Option<String> },
+    /// Show the start of a peer's copy of a file without syncing it
+    Peek { observer: String, path: String, peer: Option<String>, bytes: Option<u32>, lines: Option<usize> },
+    /// Compare one observer, or all of them, with a peer's copy without transferring anything
+    Diff { observer: Option<String>, peer: String },
+    /// Check the state file, setting it aside to be rebuilt if it is corrupt
+    Repair,
+    /// Print a shell completion script
+    Completions { shell: Shell },
+    /// Print a compact sync indicator for a directory, for shell prompts
+    /// Uses the current directory when no path is given
+    PromptStatus { path: Option<PathBuf> },
+    /// Print the daemon's tray state as a JSON line whenever it changes, for tray icons
+    TrayWatch,
+}
+
+/// Shells `syndactyl completions` can generate a script for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Top-level commands with their descriptions, for completion scripts
+const COMMANDS: &[(&str, &str)] = &[
+    ("init", "Create a configuration interactively"),
+    ("audit", "Verify the audit log hash chain"),
+    ("stats", "Show transfer statistics"),
+    ("status", "Show the running daemon's status"),
+    ("peers", "Show latency, throughput and version per peer"),
+    ("id", "Show this node's peer ID and addresses"),
+    ("pause", "Pause downloads"),
+    ("resume", "Resume paused downloads"),
+    ("rescan", "Re-announce local files to peers"),
+    ("profile", "Switch or clear the sync profile"),
+    ("deletions", "Confirm or discard held deletions"),
+    ("peek", "Show the start of a peer's copy of a file"),
+    ("diff", "Compare observers with a peer's copy"),
+    ("repair", "Check the state file"),
+    ("completions", "Print a shell completion script"),
+    ("prompt-status", "Print a sync indicator for a directory"),
+    ("tray-watch", "Follow peer count and observer activity"),
+];
+
+/// Words that may follow each command
+const ARGUMENTS: &[(&str, &[&str])] = &[
+    ("init", &["--interactive"]),
+    ("audit", &["verify"]),
+    ("stats", &["--bandwidth", "--history"]),
+    ("id", &["--qr"]),
+    ("profile", &["use", "clear"]),
+    ("deletions", &["confirm", "discard"]),
+    ("completions", &["bash", "zsh", "fish"]),
+    ("prompt-status", &["--path"]),
+    ("peek", &["--peer", "--bytes", "--lines"]),
+    ("diff", &["--peer"]),
+];
+
+pub const USAGE: &str = "\
+Usage:
+    syndactyl                       Run the sync daemon
+    syndactyl --takeover            Run the sync daemon, stopping one already running
+    syndactyl --local-only          Run the observers without networking, printing
+                                    what would be announced
+    syndactyl --tenant NAME ...     Run or address a single tenant of a shared daemon
+    syndactyl init --interactive    Create the configuration by answering questions
+    syndactyl audit verify [PATH]   Verify the audit log hash chain
+    syndactyl stats [--bandwidth|--history]
+                                    Show transfer statistics and the space taken
+                                    by version history
+    syndactyl status                Show the running daemon's status
+    syndactyl peers                 Show latency, throughput and version per peer
+    syndactyl id [--qr]             Show this node's peer ID, addresses and pairing
+                                    code, and the addresses as a QR code with --qr
+    syndactyl pause [OBSERVER]      Pause downloads
+    syndactyl resume [OBSERVER]     Resume paused downloads
+    syndactyl rescan [OBSERVER]     Re-announce local files to peers
+    syndactyl profile use NAME      Switch to a sync profile
+    syndactyl profile clear         Go back to the default profile, or to syncing
+                                    everything if there is none
+    syndactyl deletions confirm [OBSERVER]
+                                    Announce deletions held as a mass deletion
+    syndactyl deletions discard [OBSERVER]
+                                    Drop held deletions, keeping peers' copies
+    syndactyl peek OBSERVER/PATH [--peer PEER_ID] [--bytes N] [--lines N]
+                                    Show the start of a peer's copy of a file
+    syndactyl diff [OBSERVER] --peer PEER_ID
+                                    List what differs from a peer's copy, without
+                                    transferring anything
+    syndactyl repair                Check the state file and set it aside if corrupt
+    syndactyl completions SHELL     Print a completion script for bash, zsh or fish
+    syndactyl prompt-status [--path PATH]
+                                    Print a sync indicator for PATH (default: the
+                                    current directory), e.g. for starship or PS1";
+
+/// Split off a leading `--tenant NAME`, which points any command at one tenant
+pub fn split_tenant(args: &[String]) -> (Option<String>, &[String]) {
+    match args {
+        [flag, name, rest @ ..] if flag == "--tenant" => (Some(name.clone()), rest),
+        _ => (None, args),
+    }
+}
+
+/// Parse command line arguments (excluding the program name)
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    match args.as_slice() {
+        [] => Ok(Command::Run { takeover: false, local_only: false }),
+        ["--takeover"] => Ok(Command::Run { takeover: true, local_only: false }),
+        ["--local-only"] => Ok(Command::Run { takeover: false, local_only: true }),
+        ["init", "--interactive"] => Ok(Command::Init),
+        ["init"] => Err("Only interactive setup is available: syndactyl init --interactive".to_string()),
+        ["audit", "verify"] => Ok(Command::AuditVerify { path: None }),
+        ["audit", "verify", path] => Ok(Command::AuditVerify { path: Some(PathBuf::from(path)) }),
+        ["stats"] => Ok(Command::Stats { bandwidth: true, history: true }),
+        ["stats", "--bandwidth"] => Ok(Command::Stats { bandwidth: true, history: false }),
+        ["stats", "--history"] => Ok(Command::Stats { bandwidth: false, history: true }),
+        ["status"] => Ok(Command::Status),
+        ["peers"] => Ok(Command::Peers),
+        ["id"] => Ok(Command::Id { qr: false }),
+        ["id", "--qr"] => Ok(Command::Id { qr: true }),
+        ["pause"] => Ok(Command::Pause { observer: None }),
+        ["pause", observer] => Ok(Command::Pause { observer: Some(observer.to_string()) }),
+        ["resume"] => Ok(Command::Resume { observer: None }),
+        ["resume", observer] => Ok(Command::Resume { observer: Some(observer.to_string()) }),
+        ["rescan"] => Ok(Command::Rescan { observer: None }),
+        ["rescan", observer] => Ok(Command::Rescan { observer: Some(observer.to_string()) }),
+        ["profile", "use", name] => Ok(Command::ProfileUse { name: name.to_string() }),
+        ["profile", "clear"] => Ok(Command::ProfileClear),
+        ["deletions", "confirm"] => Ok(Command::DeletionsConfirm { observer: None }),
+        ["deletions", "confirm", observer] => Ok(Command::DeletionsConfirm { observer: Some(observer.to_string()) }),
+        ["deletions", "discard"] => Ok(Command::DeletionsDiscard { observer: None }),
+        ["deletions", "discard", observer] => Ok(Command::DeletionsDiscard { observer: Some(observer.to_string()) }),
+        ["peek", target, options @ ..] => parse_peek(target, options),
+        ["diff", "--peer", peer] => Ok(Command::Diff { observer: None, peer: peer.to_string() }),
+        ["diff", observer, "--peer", peer] | ["diff", "--peer", peer, observer] => {
+            Ok(Command::Diff { observer: Some(observer.to_string()), peer: peer.to_string() })
+        }
+        ["diff", ..] => Err("diff needs the peer to compare with: syndactyl diff [OBSERVER] --peer PEER_ID".to_string()),
+        ["repair"] => Ok(Command::Repair),
+        ["completions", shell] => Ok(Command::Completions { shell: Shell::from_name(shell)? }),
+        ["prompt-status"] => Ok(Command::PromptStatus { path: None }),
+        ["prompt-status", "--path", path] => Ok(Command::PromptStatus { path: Some(PathBuf::from(path)) }),
+        ["tray-watch"] => Ok(Command::TrayWatch),
+        _ => Err(format!("Unrecognised arguments: {}", args.join(" "))),
+    }
+}
+
+/// `peek OBSERVER/PATH` followed by its options in any order
+fn parse_peek(target: &str, options: &[&str]) -> Result<Command, String> {
+    let Some((observer, path)) = target.split_once('/').filter(|(observer, path)| !observer.is_empty() && !path.is_empty()) else {
+        return Err(format!("Expected OBSERVER/PATH, not '{}'", target));
+    };
+    let (mut peer, mut bytes, mut lines) = (None, None, None);
+    let number = |flag: &str, value: &str| value.parse::<usize>().map_err(|_| format!("{} expects a number, not '{}'", flag, value));
+    for option in options.chunks(2) {
+        match option {
+            ["--peer", value] => peer = Some(value.to_string()),
+            ["--bytes", value] => bytes = Some(number("--bytes", value)?.min(u32::MAX as usize) as u32),
+            ["--lines", value] => lines = Some(number("--lines", value)?),
+            _ => return Err(format!("Unrecognised peek options: {}", option.join(" "))),
+        }
+    }
+    Ok(Command::Peek { observer: observer.to_string(), path: path.to_string(), peer, bytes, lines })
+}
+
+impl Shell {
+    fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            other => Err(format!("Unsupported shell '{}' (expected bash, zsh or fish)", other)),
+        }
+    }
+}
+
+/// Completion script for `shell`, generated from the command tables above
+pub fn completions(shell: Shell) -> String {
+    let names: Vec<&str> = COMMANDS.iter().map(|(name, _)| *name).collect();
+    let mut script = String::new();
+    match shell {
+        Shell::Bash => {
+            script.push_str("_syndactyl() {\n");
+            script.push_str("    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}\n");
+            script.push_str("    case \"$prev\" in\n");
+            for (command, words) in ARGUMENTS {
+                script.push_str(&format!("        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n", command, words.join(" ")));
+            }
+            script.push_str("        verify) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n");
+            script.push_str("        --path) COMPREPLY=($(compgen -d -- \"$cur\")); return ;;\n");
+            script.push_str("        --tenant) return ;;\n");
+            script.push_str("    esac\n");
+            script.push_str("    if [ \"$COMP_CWORD\" -eq 1 ] || [ \"${COMP_WORDS[COMP_CWORD-2]}\" = \"--tenant\" ]; then\n");
+            script.push_str(&format!("        COMPREPLY=($(compgen -W \"{} --tenant --takeover --local-only\" -- \"$cur\"))\n", names.join(" ")));
+            script.push_str("    fi\n}\ncomplete -F _syndactyl syndactyl\n");
+        }
+        Shell::Zsh => {
+            script.push_str("#compdef syndactyl\n\n_syndactyl() {\n    local -a commands\n    commands=(\n");
+            for (command, description) in COMMANDS {
+                script.push_str(&format!("        '{}:{}'\n", command, description.replace('\'', "")));
+            }
+            script.push_str("    )\n");
+            script.push_str("    if [[ ${words[2]} == --tenant ]]; then\n");
+            script.push_str("        (( CURRENT > 3 )) || return\n");
+            script.push_str("        words=(${words[1]} ${words[4,-1]})\n");
+            script.push_str("        (( CURRENT -= 2 ))\n");
+            script.push_str("    fi\n");
+            script.push_str("    if (( CURRENT == 2 )); then\n        _describe 'command' commands\n        return\n    fi\n");
+            script.push_str("    case ${words[CURRENT-1]} in\n");
+            for (command, words) in ARGUMENTS {
+                script.push_str(&format!("        {}) compadd {} ;;\n", command, words.join(" ")));
+            }
+            script.push_str("        verify) _files ;;\n");
+            script.push_str("        --path) _files -/ ;;\n");
+            script.push_str("    esac\n}\n\n_syndactyl \"$@\"\n");
+        }
+        Shell::Fish => {
+            script.push_str("complete -c syndactyl -f\n");
+            script.push_str("complete -c syndactyl -n __fish_use_subcommand -l tenant -r -d 'Address a single tenant'\n");
+            script.push_str("complete -c syndactyl -n __fish_use_subcommand -l takeover -d 'Stop a daemon already running and take its place'\n");
+            script.push_str("complete -c syndactyl -n __fish_use_subcommand -l local-only -d 'Run without networking, printing what would be announced'\n");
+            for (command, description) in COMMANDS {
+                script.push_str(&format!("complete -c syndactyl -n __fish_use_subcommand -a {} -d '{}'\n", command, description.replace('\'', "")));
+            }
+            for (command, words) in ARGUMENTS {
+                script.push_str(&format!("complete -c syndactyl -n '__fish_seen_subcommand_from {}' -a '{}'\n", command, words.join(" ")));
+            }
+            script.push_str("complete -c syndactyl -n '__fish_seen_subcommand_from verify' -F\n");
+            script.push_str("complete -c syndactyl -n '__fish_seen_subcommand_from prompt-status' -l path -r -a '(__fish_complete_directories)'\n");
+        }
+    }
+    script
+}
@@ -0,0 +1,612 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::io::{BufRead, BufReader};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroize;
+#[cfg(unix)]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, Lines};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{broadcast, mpsc, oneshot};
+#[cfg(unix)]
+use tracing::{debug, info, warn};
+use crate::core::auth;
+use crate::core::conflict::ResolvedConflict;
+use crate::core::lifecycle::LifecycleStatus;
+use crate::core::secret::Secret;
+use crate::core::power::PowerStatus;
+use crate::core::setup;
+use crate::network::bootstrap::BootstrapPeerStatus;
+use crate::network::peer_diff::DiffReport;
+use crate::network::peer_stats::PeerInfo;
+use crate::network::transfer::TransferProgress;
+use crate::network::tray_feed::{TrayDelta, TrayFeed, TrayState};
+#[cfg(feature = "fault-injection")]
+use crate::network::faults::FaultPlan;
+
+/// Requests accepted on the control socket, one JSON object per line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+    /// Latency and throughput of connected peers
+    Peers,
+    /// Downloads in progress
+    Transfers,
+    /// Conflicts resolved since the daemon started
+    Conflicts,
+    /// Hold back downloads for one observer, or all of them
+    Pause {
+        #[serde(default)]
+        observer: Option<String>,
+    },
+    /// Undo a previous pause
+    Resume {
+        #[serde(default)]
+        observer: Option<String>,
+    },
+    /// Re-announce every local file for one observer, or all of them
+    Rescan {
+        #[serde(default)]
+        observer: Option<String>,
+    },
+    /// Switch to a configured sync profile
+    UseProfile { name: String },
+    /// Drop the profile picked at runtime, going back to the configured default
+    ClearProfile,
+    /// Announce deletions held back by the mass-deletion guard
+    ConfirmDeletions {
+        #[serde(default)]
+        observer: Option<String>,
+    },
+    /// Drop held deletions, so peers keep their copies
+    DiscardDeletions {
+        #[serde(default)]
+        observer: Option<String>,
+    },
+    /// Sync state of an absolute local path
+    PathStatus { path: String },
+    /// Sync state of individual files, by absolute local path
+    FileStatus { paths: Vec<String> },
+    /// Turn the connection into a feed of file status changes, for overlay icons
+    /// Acknowledged with Done, after which one FileStatus reply is written per
+    /// change until the client disconnects. Only served on the control socket.
+    WatchFiles,
+    /// Turn the connection into a feed of peer count and observer activity, for tray icons
+    /// Answered with the full Tray state, then one TrayDelta per change until the
+    /// client disconnects. A client that falls behind is sent a fresh Tray state
+    /// instead of the deltas it missed. Only served on the control socket.
+    WatchTray,
+    /// Read the start of a peer's copy of a file without syncing it
+    /// `bytes` is capped at one chunk; `lines` further trims the text returned.
+    Peek {
+        observer: String,
+        path: String,
+        /// Peer ID to read from; the fastest connected peer if omitted
+        #[serde(default)]
+        peer: Option<String>,
+        #[serde(default)]
+        bytes: Option<u32>,
+        #[serde(default)]
+        lines: Option<usize>,
+    },
+    /// Compare one observer, or all of them, with a peer's tree without transferring anything
+    Diff {
+        #[serde(default)]
+        observer: Option<String>,
+        peer: String,
+    },
+    /// Stop the daemon, so another one can take over its state directory
+    /// Not accepted from bridges.
+    Shutdown,
+    /// Replace the faults injected into received chunks; an empty plan stops them
+    #[cfg(feature = "fault-injection")]
+    InjectFaults { plan: FaultPlan },
+}
+
+/// A request as sent on the control socket, carrying the token that authorizes it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct AuthenticatedRequest {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(flatten)]
+    request: ControlRequest,
+}
+
+/// Replies written back on the control socket, one JSON object per line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status(DaemonStatus),
+    Peers { peers: Vec<PeerInfo> },
+    Transfers { transfers: Vec<TransferProgress> },
+    Conflicts { conflicts: Vec<ResolvedConflict> },
+    PathStatus(PathStatus),
+    FileStatuses { files: Vec<FileStatus> },
+    FileStatus(FileStatus),
+    Preview(Preview),
+    /// How each compared observer differs from the peer's copy
+    Diff { reports: Vec<DiffReport> },
+    /// The feed dropped updates; query the files on display again
+    Resync,
+    Tray(TrayState),
+    TrayDelta(TrayDelta),
+    Done { message: String },
+    Error { message: String },
+}
+
+/// Snapshot of the running daemon for `syndactyl status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DaemonStatus {
+    /// This node's peer ID
+    #[serde(default)]
+    pub peer_id: String,
+    pub connected_peers: usize,
+    pub active_downloads: usize,
+    pub queued_serve_requests: usize,
+    /// Announcements held back by sync windows or low battery
+    pub deferred_transfers: usize,
+    /// Announcements waiting for room to download, small files first
+    #[serde(default)]
+    pub queued_downloads: usize,
+    /// Power source, when power awareness is configured
+    pub power: Option<PowerStatus>,
+    /// Active sync profile, if one is selected
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Addresses the daemon is listening on
+    #[serde(default)]
+    pub listen_addresses: Vec<String>,
+    /// Addresses confirmed reachable from outside, e.g. through a UPnP mapping
+    #[serde(default)]
+    pub external_addresses: Vec<String>,
+    /// Observers whose directory is currently missing
+    #[serde(default)]
+    pub unavailable_observers: Vec<String>,
+    /// Observers whose volume is low on space, with downloads paused
+    #[serde(default)]
+    pub low_space_observers: Vec<String>,
+    /// Observers whose watcher dropped events and that are being rescanned
+    #[serde(default)]
+    pub stale_observers: Vec<String>,
+    /// Deletions held back by the mass-deletion guard, per observer
+    #[serde(default)]
+    pub held_deletions: BTreeMap<String, usize>,
+    /// Configured bootstrap peers and whether they are reachable
+    #[serde(default)]
+    pub bootstrap_peers: Vec<BootstrapPeerStatus>,
+    /// A Kademlia bootstrap query has completed
+    #[serde(default)]
+    pub dht_bootstrapped: bool,
+    /// Discovery backends in use, e.g. ["static", "dht"]
+    #[serde(default)]
+    pub discovery: Vec<String>,
+    /// Failed authentication attempts by peers since the daemon started
+    #[serde(default)]
+    pub auth_failures: u64,
+    /// Lifecycle state of each watched observer
+    #[serde(default)]
+    pub observer_states: BTreeMap<String, LifecycleStatus>,
+}
+
+/// The start of a remote file, for `syndactyl peek`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub observer: String,
+    pub path: String,
+    pub peer: String,
+    /// Version of the file the bytes were read from
+    pub hash: String,
+    pub total_size: u64,
+    /// The bytes read, as text; invalid UTF-8 is replaced
+    pub content: String,
+    /// The file looks binary, so `content` is unlikely to be meaningful
+    pub binary: bool,
+    /// Less than the whole file is shown
+    pub truncated: bool,
+}
+
+impl Preview {
+    /// Build a preview from the first bytes of a file, keeping at most `lines` lines
+    pub fn new(observer: String, path: String, peer: String, hash: String, total_size: u64, data: &[u8], lines: Option<usize>) -> Self {
+        let binary = data.contains(&0) || std::str::from_utf8(data).is_err_and(|e| e.error_len().is_some());
+        let mut content = String::from_utf8_lossy(data).into_owned();
+        let mut truncated = (data.len() as u64) < total_size;
+        if let Some(limit) = lines {
+            let end = match limit {
+                0 => 0,
+                n => content.match_indices('\n').nth(n - 1).map_or(content.len(), |(cut, _)| cut + 1),
+            };
+            truncated |= end < content.len();
+            content.truncate(end);
+        }
+        Self { observer, path, peer, hash, total_size, content, binary, truncated }
+    }
+}
+
+/// Sync state of one local path, for `syndactyl prompt-status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PathStatus {
+    /// Observer whose directory holds the path, if any
+    pub observer: Option<String>,
+    pub state: SyncState,
+    /// Downloads in progress below the path
+    pub downloading: usize,
+    /// Transfers and deletions held back below the path
+    pub pending: usize,
+}
+
+/// Sync state of one file, as reported to file manager extensions
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileStatus {
+    /// Absolute local path
+    pub path: String,
+    /// Observer the file belongs to; empty if it is outside every observer
+    pub observer: String,
+    pub state: SyncState,
+}
+
+/// Summary of a path's sync state, most pressing first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    /// Not inside any observer
+    Unwatched,
+    /// The observer's directory is missing
+    Unavailable,
+    /// Downloads are paused by hand
+    Paused,
+    Syncing,
+    /// Held back by a sync window, low battery or the deletion guard
+    Pending,
+    /// No peers are connected
+    Offline,
+    Synced,
+}
+
+impl SyncState {
+    /// Compact indicator for shell prompts; empty outside observers
+    pub fn indicator(self) -> &'static str {
+        match self {
+            Self::Unwatched => "",
+            Self::Unavailable => "✗",
+            Self::Paused => "⏸",
+            Self::Syncing => "⟳",
+            Self::Pending => "…",
+            Self::Offline => "⚠",
+            Self::Synced => "✓",
+        }
+    }
+}
+
+/// A request forwarded to the network manager along with where to send its reply
+pub struct ControlCommand {
+    pub request: ControlRequest,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+/// Read the control token, generating it first if there is none yet
+/// Only the owning user can read the file, so only they can drive the daemon,
+/// even where the socket itself ends up reachable by others.
+pub fn load_or_create_token(path: &Path) -> io::Result<Secret> {
+    match read_token(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let token = Secret::new(setup::generate_secret());
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    file.write_all(token.expose().as_bytes())?;
+    file.sync_all()?;
+    Ok(token)
+}
+
+/// Read the token clients present with every request
+/// Refuses a file other users can read, as they could drive the daemon with it.
+pub fn read_token(path: &Path) -> io::Result<Secret> {
+    #[cfg(unix)]
+    if std::fs::metadata(path)?.permissions().mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("control token file {} can be read by other users; chmod 600 it", path.display())));
+    }
+    let mut contents = std::fs::read_to_string(path)?;
+    let token = Secret::new(contents.trim());
+    contents.zeroize();
+    if token.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("control token file {} is empty", path.display())));
+    }
+    Ok(token)
+}
+
+/// Listen on a Unix socket and forward requests to the manager
+/// The socket is only accessible to the owning user, and every request must carry `token`.
+#[cfg(unix)]
+pub fn spawn_server(path: PathBuf, token: Secret, commands: mpsc::Sender<ControlCommand>, feed: broadcast::Sender<FileStatus>, tray: TrayFeed) -> io::Result<()> {
+    if path.exists() {
+        // A socket left behind by a crashed daemon; a live one would accept the connection
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "control socket already in use"));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    info!(path = %path.display(), "Control socket listening");
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, token.clone(), commands.clone(), feed.clone(), tray.clone()));
+                }
+                Err(e) => {
+                    warn!(error = %e, "Failed to accept control connection");
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn handle_connection(stream: UnixStream, token: Secret, commands: mpsc::Sender<ControlCommand>, feed: broadcast::Sender<FileStatus>, tray: TrayFeed) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let request = match serde_json::from_str::<AuthenticatedRequest>(&line) {
+            Ok(authenticated) if authenticated.token.as_deref().is_some_and(|given| auth::constant_time_compare(given, token.expose())) => {
+                Ok(authenticated.request)
+            }
+            Ok(_) => {
+                warn!("Control request without a valid token, closing the connection");
+                let refused = ControlResponse::Error { message: "Missing or wrong control token".to_string() };
+                let _ = write_response(&mut writer, &refused).await;
+                return;
+            }
+            Err(e) => Err(e),
+        };
+        let response = match request {
+            Ok(ControlRequest::WatchFiles) => {
+                let updates = feed.subscribe();
+                let ack = ControlResponse::Done { message: "Watching file status".to_string() };
+                if write_response(&mut writer, &ack).await.is_ok() {
+                    watch_files(&mut lines, &mut writer, updates).await;
+                }
+                return;
+            }
+            Ok(ControlRequest::WatchTray) => {
+                watch_tray(&mut lines, &mut writer, &tray).await;
+                return;
+            }
+            Ok(request) => {
+                debug!(?request, "Control request");
+                let (reply, rx) = oneshot::channel();
+                if commands.send(ControlCommand { request, reply }).await.is_err() {
+                    return;
+                }
+                rx.await.unwrap_or_else(|_| ControlResponse::Error {
+                    message: "Daemon is shutting down".to_string(),
+                })
+            }
+            Err(e) => ControlResponse::Error {
+                message: format!("Invalid request: {}", e),
+            },
+        };
+
+        if write_response(&mut writer, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn write_response(writer: &mut OwnedWriteHalf, response: &ControlResponse) -> io::Result<()> {
+    let mut json = serde_json::to_string(response)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await
+}
+
+/// Forward file status changes until the client disconnects
+#[cfg(unix)]
+async fn watch_files(lines: &mut Lines<tokio::io::BufReader<OwnedReadHalf>>, writer: &mut OwnedWriteHalf, mut updates: broadcast::Receiver<FileStatus>) {
+    loop {
+        let response = tokio::select! {
+            update = updates.recv() => match update {
+                Ok(status) => ControlResponse::FileStatus(status),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!(missed, "File status watcher fell behind");
+                    ControlResponse::Resync
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // Anything sent on a watching connection is ignored; EOF ends the watch
+            line = lines.next_line() => match line {
+                Ok(Some(_)) => continue,
+                _ => return,
+            },
+        };
+        if write_response(writer, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Send tray state changes until the client disconnects, starting with the full state
+#[cfg(unix)]
+async fn watch_tray(lines: &mut Lines<tokio::io::BufReader<OwnedReadHalf>>, writer: &mut OwnedWriteHalf, tray: &TrayFeed) {
+    let (state, mut updates) = tray.subscribe();
+    let mut sent = state.seq;
+    let mut response = ControlResponse::Tray(state);
+    loop {
+        if write_response(writer, &response).await.is_err() {
+            return;
+        }
+        // Only changes are sent; ignored input and deltas the last state already holds aren't
+        response = loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(delta) if delta.seq <= sent => continue,
+                    Ok(delta) => {
+                        sent = delta.seq;
+                        break ControlResponse::TrayDelta(delta);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!(missed, "Tray watcher fell behind, sending the full state");
+                        let (state, resubscribed) = tray.subscribe();
+                        updates = resubscribed;
+                        sent = state.seq;
+                        break ControlResponse::Tray(state);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                // Anything sent on a watching connection is ignored; EOF ends the watch
+                line = lines.next_line() => match line {
+                    Ok(Some(_)) => continue,
+                    _ => return,
+                },
+            }
+        };
+    }
+}
+
+/// Follow the tray state of a running daemon, for tray icon companions
+/// Calls `on_change` with the state after every change, and with None whenever
+/// the daemon can't be reached, reconnecting with a growing delay until it
+/// can. Returns once `on_change` returns false, or if the daemon refuses the token.
+#[cfg(unix)]
+pub fn follow_tray(path: &Path, token: &Secret, mut on_change: impl FnMut(Option<&TrayState>) -> bool) -> io::Result<()> {
+    const MIN_RETRY: Duration = Duration::from_secs(1);
+    const MAX_RETRY: Duration = Duration::from_secs(30);
+    let mut retry = MIN_RETRY;
+    loop {
+        match follow_tray_once(path, token, &mut on_change, &mut retry) {
+            Ok(false) => return Ok(()),
+            Ok(true) => {}
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Err(e),
+            Err(e) => debug!(error = %e, "Lost the daemon's tray feed"),
+        }
+        if !on_change(None) {
+            return Ok(());
+        }
+        std::thread::sleep(retry);
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+}
+
+/// One connection of `follow_tray`, returning whether to reconnect
+#[cfg(unix)]
+fn follow_tray_once(path: &Path, token: &Secret, on_change: &mut impl FnMut(Option<&TrayState>) -> bool, retry: &mut Duration) -> io::Result<bool> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    let request = AuthenticatedRequest { token: Some(token.expose().to_string()), request: ControlRequest::WatchTray };
+    let mut json = serde_json::to_string(&request)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+
+    let mut state: Option<TrayState> = None;
+    for line in BufReader::new(stream).lines() {
+        let response: ControlResponse = serde_json::from_str(&line?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        match response {
+            ControlResponse::Tray(snapshot) => state = Some(snapshot),
+            ControlResponse::TrayDelta(delta) => {
+                let Some(current) = state.as_mut() else { continue };
+                if delta.seq <= current.seq {
+                    continue;
+                }
+                if !current.apply(&delta) {
+                    // Can't happen with a well-behaved daemon; start over with a fresh snapshot
+                    return Ok(true);
+                }
+            }
+            ControlResponse::Error { message } => return Err(io::Error::new(io::ErrorKind::PermissionDenied, message)),
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected reply: {:?}", other))),
+        }
+        *retry = Duration::from_secs(1);
+        if !on_change(state.as_ref()) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Send one request to a running daemon and wait for the reply
+pub fn request(path: &Path, token: &Secret, request: &ControlRequest) -> io::Result<ControlResponse> {
+    request_within(path, token, request, None)
+}
+
+/// Like `request`, but give up if the daemon takes longer than `timeout` to answer
+/// For callers that must not hang, such as shell prompts.
+#[cfg(unix)]
+pub fn request_within(path: &Path, token: &Secret, request: &ControlRequest, timeout: Option<Duration>) -> io::Result<ControlResponse> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let request = AuthenticatedRequest { token: Some(token.expose().to_string()), request: request.clone() };
+    let mut json = serde_json::to_string(&request)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The control socket is a Unix domain socket; elsewhere the daemon runs without one
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "the control socket needs Unix domain sockets")
+}
+
+#[cfg(not(unix))]
+pub fn spawn_server(_path: PathBuf, _token: Secret, _commands: mpsc::Sender<ControlCommand>, _feed: broadcast::Sender<FileStatus>, _tray: TrayFeed) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub fn follow_tray(_path: &Path, _token: &Secret, _on_change: impl FnMut(Option<&TrayState>) -> bool) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(unix))]
+pub fn request_within(_path: &Path, _token: &Secret, _request: &ControlRequest, _timeout: Option<Duration>) -> io::Result<ControlResponse> {
+    Err(unsupported())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_files_other_users_can_read_are_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("control").join("token");
+        let token = load_or_create_token(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(read_token(&path).unwrap().expose(), token.expose());
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(read_token(&path).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(load_or_create_token(&path).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// How long a peer that hasn't been seen is remembered (30 days)
+pub const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Peers remembered at most; the least recently seen are forgotten first
+const MAX_PEERS: usize = 64;
+
+/// Addresses remembered per peer, most recently seen first
+const MAX_ADDRESSES_PER_PEER: usize = 8;
+
+/// Whether a multiaddr only reaches this machine, and so is no use to remember for another
+pub fn is_loopback(address: &str) -> bool {
+    address.starts_with("/ip4/127.") || address.starts_with("/ip6/::1/")
+}
+
+/// Where a peer was last reachable
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct KnownPeer {
+    /// Multiaddrs, most recently seen first
+    pub addresses: Vec<String>,
+    /// Unix timestamp the peer was last connected at
+    pub last_seen: u64,
+}
+
+/// Addresses of peers we have connected to, kept across restarts
+/// Dialed on startup, so devices that found each other once reconnect without
+/// bootstrap peers or waiting on the DHT.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AddressBook {
+    /// Keyed by peer ID
+    peers: BTreeMap<String, KnownPeer>,
+}
+
+impl AddressBook {
+    /// Note that `peer` is reachable at `addresses` as of `now`
+    pub fn record<'a>(&mut self, peer: &str, addresses: impl IntoIterator<Item = &'a str>, now: u64) {
+        let known = self.peers.entry(peer.to_string()).or_default();
+        known.last_seen = known.last_seen.max(now);
+        for address in addresses {
+            known.addresses.retain(|known| known != address);
+            known.addresses.insert(0, address.to_string());
+        }
+        known.addresses.truncate(MAX_ADDRESSES_PER_PEER);
+
+        while self.peers.len() > MAX_PEERS {
+            let Some(oldest) = self.peers.iter().min_by_key(|(_, known)| known.last_seen).map(|(peer, _)| peer.clone()) else {
+                break;
+            };
+            self.peers.remove(&oldest);
+        }
+    }
+
+    /// Forget peers not seen for `retention`
+    pub fn expire(&mut self, now: u64, retention: Duration) {
+        self.peers.retain(|_, known| known.last_seen.saturating_add(retention.as_secs()) > now);
+    }
+
+    /// Remembered peers with addresses, most recently seen first
+    pub fn peers(&self) -> Vec<(&String, &KnownPeer)> {
+        let mut peers: Vec<(&String, &KnownPeer)> = self.peers.iter()
+            .filter(|(_, known)| !known.addresses.is_empty())
+            .collect();
+        peers.sort_by_key(|(_, known)| std::cmp::Reverse(known.last_seen));
+        peers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_addresses_first_and_old_peers_forgotten() {
+        let mut book = AddressBook::default();
+        book.record("peer-a", ["/ip4/192.168.1.2/tcp/4001"], 100);
+        book.record("peer-b", ["/ip4/192.168.1.3/tcp/4001"], 200);
+        book.record("peer-a", ["/ip4/192.168.1.9/tcp/4001", "/ip4/192.168.1.2/tcp/4001"], 300);
+
+        let peers = book.peers();
+        assert_eq!(peers[0].0, "peer-a");
+        assert_eq!(peers[0].1.addresses, vec!["/ip4/192.168.1.2/tcp/4001", "/ip4/192.168.1.9/tcp/4001"]);
+        assert_eq!(peers[1].0, "peer-b");
+
+        for n in 0..MAX_PEERS {
+            book.record(&format!("peer-{}", n), ["/ip4/10.0.0.1/tcp/4001"], 400);
+        }
+        assert_eq!(book.peers().len(), MAX_PEERS);
+        assert!(book.peers().iter().all(|(peer, _)| *peer != "peer-b"));
+
+        book.expire(400 + RETENTION.as_secs(), RETENTION);
+        assert!(book.peers().is_empty());
+        assert!(is_loopback("/ip4/127.0.0.1/tcp/4001") && !is_loopback("/ip4/192.168.1.2/tcp/4001"));
+    }
+}
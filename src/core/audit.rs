@@ -0,0 +1,318 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use tracing::{error, warn};
+
+/// Hash used as `prev_hash` for the first entry in an audit log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A single served file/chunk record
+/// Each entry is chained to the previous one through `prev_hash`, so editing,
+/// removing or reordering any line breaks verification of every later entry.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: u64,            // Unix timestamp the chunk was served
+    pub peer: String,              // PeerId of the requesting peer, or browser:<address>
+    pub observer: String,
+    pub path: String,              // Relative path within the observer
+    pub offset: u64,               // Byte offset of the served chunk
+    pub bytes: u64,                // Number of bytes served
+    pub prev_hash: String,         // Hash of the previous entry
+    pub hash: String,              // Hash of this entry (including prev_hash)
+}
+
+impl AuditEntry {
+    /// Compute the chained hash for this entry
+    /// Every field is length-prefixed, so moving bytes from one field to the next changes the hash.
+    pub fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for field in [self.prev_hash.as_bytes(), self.peer.as_bytes(), self.observer.as_bytes(), self.path.as_bytes()] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        }
+        for number in [self.timestamp, self.offset, self.bytes] {
+            hasher.update(number.to_le_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// What the writer thread is asked to do
+enum Message {
+    Record(AuditEntry),
+    /// Answered once everything sent before it is on disk
+    Flush(mpsc::Sender<()>),
+}
+
+/// Append-only, hash-chained audit log of served files
+/// Entries are chained and written by a thread of their own, which syncs once per
+/// batch, so recording a served chunk never waits on the disk. Clones share the log.
+#[derive(Clone)]
+pub struct AuditLog {
+    tx: mpsc::Sender<Message>,
+}
+
+impl AuditLog {
+    /// Open (or create) the audit log at `path`, resuming the hash chain from its last entry
+    /// A last line cut short by a crash is dropped; anything else malformed is refused.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut last_hash = GENESIS_HASH.to_string();
+        // Length of the log up to the end of its last whole entry
+        let mut kept = 0;
+        let (mut torn, mut unterminated) = (false, false);
+        if path.exists() {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut line = Vec::new();
+            let mut offset = 0;
+            loop {
+                line.clear();
+                let read = reader.read_until(b'\n', &mut line)?;
+                if read == 0 {
+                    break;
+                }
+                offset += read as u64;
+                let complete = line.ends_with(b"\n");
+                let text = String::from_utf8_lossy(&line);
+                if text.trim().is_empty() {
+                    kept = offset;
+                    continue;
+                }
+                match serde_json::from_str::<AuditEntry>(&text) {
+                    Ok(entry) => {
+                        last_hash = entry.hash;
+                        kept = offset;
+                        unterminated = !complete;
+                    }
+                    Err(_) if !complete => {
+                        torn = true;
+                        warn!(path = %path.display(), bytes = read, "Dropping an audit log entry cut short by an interrupted write");
+                    }
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                }
+            }
+        }
+
+        if torn {
+            OpenOptions::new().write(true).open(path)?.set_len(kept)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        if unterminated {
+            file.write_all(b"\n")?;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let path = path.to_path_buf();
+        thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_entries(file, &path, last_hash, rx))?;
+        Ok(Self { tx })
+    }
+
+    /// Queue a record of a served chunk
+    pub fn record(
+        &self,
+        peer: &str,
+        observer: &str,
+        path: &str,
+        offset: u64,
+        bytes: u64,
+    ) -> io::Result<()> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let entry = AuditEntry {
+            timestamp,
+            peer: peer.to_string(),
+            observer: observer.to_string(),
+            path: path.to_string(),
+            offset,
+            bytes,
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        self.tx.send(Message::Record(entry)).map_err(|_| stopped())
+    }
+
+    /// Wait until every entry recorded so far is on disk
+    pub fn flush(&self) -> io::Result<()> {
+        let (done_tx, done_rx) = mpsc::channel();
+        self.tx.send(Message::Flush(done_tx)).map_err(|_| stopped())?;
+        done_rx.recv().map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "audit log writer stopped")
+}
+
+/// Chain and append queued entries until every `AuditLog` handle is dropped
+/// A batch that fails to write is cut off again, so the file never holds half an entry.
+fn write_entries(mut file: File, path: &Path, mut last_hash: String, rx: mpsc::Receiver<Message>) {
+    let mut len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    while let Ok(first) = rx.recv() {
+        let mut batch = String::new();
+        let mut entries = 0;
+        let mut flushed = Vec::new();
+        let mut chained = last_hash.clone();
+        for message in std::iter::once(first).chain(rx.try_iter()) {
+            match message {
+                Message::Record(mut entry) => {
+                    entry.prev_hash = chained;
+                    entry.hash = entry.compute_hash();
+                    chained = entry.hash.clone();
+                    batch.push_str(&serde_json::to_string(&entry).unwrap_or_default());
+                    batch.push('\n');
+                    entries += 1;
+                }
+                Message::Flush(done) => flushed.push(done),
+            }
+        }
+        if entries > 0 {
+            match file.write_all(batch.as_bytes()).and_then(|()| file.sync_data()) {
+                Ok(()) => {
+                    len += batch.len() as u64;
+                    last_hash = chained;
+                }
+                Err(e) => {
+                    error!(path = %path.display(), entries, error = %e, "Failed to write audit log entries");
+                    let _ = file.set_len(len);
+                }
+            }
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Verify the hash chain of an audit log
+/// Returns the number of valid entries, or a description of the first broken entry
+pub fn verify(path: &Path) -> Result<usize, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+    let mut reader = BufReader::new(file);
+
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    let mut line = Vec::new();
+
+    for line_number in 1.. {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)
+            .map_err(|e| format!("Failed to read line {}: {}", line_number, e))?;
+        if read == 0 {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let entry: AuditEntry = match serde_json::from_str(&text) {
+            Ok(entry) => entry,
+            Err(_) if !line.ends_with(b"\n") => {
+                return Err(format!("Line {}: incomplete entry left by an interrupted write; the daemon drops it when it next opens the log", line_number));
+            }
+            Err(e) => return Err(format!("Line {}: malformed entry: {}", line_number, e)),
+        };
+
+        if entry.prev_hash != prev_hash {
+            return Err(format!("Line {}: chain broken (prev_hash does not match previous entry)", line_number));
+        }
+        if entry.compute_hash() != entry.hash {
+            return Err(format!("Line {}: entry hash mismatch (entry was modified)", line_number));
+        }
+
+        prev_hash = entry.hash;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_log_chain_verifies() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("audit.log");
+
+        let log = AuditLog::open(&log_path).unwrap();
+        log.record("peer-a", "docs", "a.txt", 0, 1024).unwrap();
+        log.record("peer-b", "docs", "b.txt", 1024, 512).unwrap();
+        log.flush().unwrap();
+
+        // Reopening resumes the chain from the last entry
+        let log = AuditLog::open(&log_path).unwrap();
+        log.record("peer-a", "docs", "c.txt", 0, 10).unwrap();
+        log.flush().unwrap();
+
+        assert_eq!(verify(&log_path).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_audit_log_detects_tampering() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("audit.log");
+
+        let log = AuditLog::open(&log_path).unwrap();
+        log.record("peer-a", "docs", "a.txt", 0, 1024).unwrap();
+        log.record("peer-b", "docs", "b.txt", 0, 512).unwrap();
+        log.flush().unwrap();
+
+        let contents = fs::read_to_string(&log_path).unwrap();
+        fs::write(&log_path, contents.replace("peer-b", "peer-c")).unwrap();
+
+        let err = verify(&log_path).unwrap_err();
+        assert!(err.starts_with("Line 2"));
+    }
+
+    #[test]
+    fn test_a_torn_last_entry_is_reported_and_dropped_on_open() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("audit.log");
+
+        let log = AuditLog::open(&log_path).unwrap();
+        log.record("peer-a", "docs", "a.txt", 0, 1024).unwrap();
+        log.flush().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(br#"{"timestamp":1,"peer":"peer-b","obs"#).unwrap();
+
+        assert!(verify(&log_path).unwrap_err().contains("Line 2: incomplete entry"));
+        let log = AuditLog::open(&log_path).unwrap();
+        log.record("peer-b", "docs", "b.txt", 0, 512).unwrap();
+        log.flush().unwrap();
+        assert_eq!(verify(&log_path).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_field_boundaries_are_part_of_the_hash() {
+        let entry = |observer: &str, path: &str| AuditEntry {
+            timestamp: 1,
+            peer: "peer-a".to_string(),
+            observer: observer.to_string(),
+            path: path.to_string(),
+            offset: 0,
+            bytes: 10,
+            prev_hash: GENESIS_HASH.to_string(),
+            hash: String::new(),
+        };
+        assert_ne!(entry("docs||a", "b.txt").compute_hash(), entry("docs", "a||b.txt").compute_hash());
+    }
+}
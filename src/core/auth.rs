@@ -1,11 +1,11 @@
 use sha2::Sha256;
 use hmac::{Hmac, Mac};
-use crate::core::models::FileEventMessage;
+use crate::core::models::{FileEventMessage, FileTransferResponse};
 
 type HmacSha256 = Hmac<Sha256>;
 
 /// Compute HMAC-SHA256 for a FileEventMessage
-/// Message format: observer||event_type||path||hash||size||modified_time
+/// Message format: observer||event_type||path||hash||size||modified_time[||tx||id||size][||seq||sequence]
 pub fn compute_hmac(msg: &FileEventMessage, secret: &str) -> String {
     let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
         .expect("HMAC can take key of any size");
@@ -13,7 +13,7 @@ pub fn compute_hmac(msg: &FileEventMessage, secret: &str) -> String {
     // Build the message to authenticate
     mac.update(msg.observer.as_bytes());
     mac.update(b"||");
-    mac.update(msg.event_type.as_bytes());
+    mac.update(msg.event_type.wire_name().as_bytes());
     mac.update(b"||");
     mac.update(msg.path.as_bytes());
     mac.update(b"||");
@@ -32,6 +32,21 @@ pub fn compute_hmac(msg: &FileEventMessage, secret: &str) -> String {
         mac.update(mtime.to_string().as_bytes());
     }
     
+    // Transaction membership is only covered when present, so messages
+    // outside a transaction authenticate exactly as before
+    if let Some(ref transaction) = msg.transaction {
+        mac.update(b"||tx||");
+        mac.update(transaction.id.as_bytes());
+        mac.update(b"||");
+        mac.update(transaction.size.to_string().as_bytes());
+    }
+
+    // Likewise the sequence, so a replayed operation can't be made to look newer
+    if let Some(sequence) = msg.sequence {
+        mac.update(b"||seq||");
+        mac.update(sequence.to_string().as_bytes());
+    }
+    
     // Return hex-encoded HMAC
     format!("{:x}", mac.finalize().into_bytes())
 }
@@ -50,8 +65,43 @@ pub fn verify_hmac(msg: &FileEventMessage, secret: &str) -> bool {
     constant_time_compare(provided_hmac, &computed_hmac)
 }
 
+/// Signature of one chunk of a file version, by its segment hash and offset
+/// Chunks of a file fetched before it was hashed can't be checked against the
+/// full hash until the completing announcement; this lets each be trusted as it arrives.
+pub fn segment_hmac(secret: &str, observer: &str, path: &str, version: &str, offset: u64, segment_hash: &str) -> String {
+    let signed = format!("syndactyl-segment||{}||{}||{}||{}||{}", observer, path, version, offset, segment_hash);
+    sign_bytes(signed.as_bytes(), secret)
+}
+
+/// Whether a chunk carries a valid `segment_hmac` for its segment hash
+pub fn verify_segment_hmac(response: &FileTransferResponse, secret: &str) -> bool {
+    let (Some(segment_hash), Some(provided)) = (&response.segment_hash, &response.segment_hmac) else {
+        return false;
+    };
+    let computed = segment_hmac(secret, &response.observer, &response.path, &response.hash, response.offset, segment_hash);
+    constant_time_compare(provided, &computed)
+}
+
+/// Identity of an observer as seen on the network, derived from its name and shared secret
+/// Same-named observers with different secrets get different identities, without
+/// revealing anything about the secret.
+pub fn observer_id(observer: &str, secret: &str) -> String {
+    let mut id = sign_bytes(format!("syndactyl-observer||{}", observer).as_bytes(), secret);
+    id.truncate(32);
+    id
+}
+
+/// Sign arbitrary bytes with HMAC-SHA256, hex encoded
+/// Used for payloads leaving the network, such as webhook bodies
+pub fn sign_bytes(data: &[u8], secret: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(data);
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
 /// Constant-time string comparison to prevent timing attacks
-fn constant_time_compare(a: &str, b: &str) -> bool {
+pub(crate) fn constant_time_compare(a: &str, b: &str) -> bool {
     if a.len() != b.len() {
         return false;
     }
@@ -70,18 +120,23 @@ fn constant_time_compare(a: &str, b: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::models::EventType;
     
     #[test]
     fn test_hmac_computation() {
         let msg = FileEventMessage {
             observer: "test-observer".to_string(),
-            event_type: "Create".to_string(),
+            event_type: EventType::Create,
             path: "test.txt".to_string(),
             details: None,
             hash: Some("abcd1234".to_string()),
             size: Some(1024),
             modified_time: Some(1234567890),
             hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         };
         
         let secret = "test-secret";
@@ -97,13 +152,17 @@ mod tests {
         let secret = "test-secret";
         let mut msg = FileEventMessage {
             observer: "test-observer".to_string(),
-            event_type: "Create".to_string(),
+            event_type: EventType::Create,
             path: "test.txt".to_string(),
             details: None,
             hash: Some("abcd1234".to_string()),
             size: Some(1024),
             modified_time: Some(1234567890),
             hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         };
         
         // Compute and attach HMAC
@@ -121,13 +180,17 @@ mod tests {
         
         let mut msg = FileEventMessage {
             observer: "test-observer".to_string(),
-            event_type: "Create".to_string(),
+            event_type: EventType::Create,
             path: "test.txt".to_string(),
             details: None,
             hash: Some("abcd1234".to_string()),
             size: Some(1024),
             modified_time: Some(1234567890),
             hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         };
         
         // Compute HMAC with correct secret
@@ -144,13 +207,17 @@ mod tests {
         
         let mut msg = FileEventMessage {
             observer: "test-observer".to_string(),
-            event_type: "Create".to_string(),
+            event_type: EventType::Create,
             path: "test.txt".to_string(),
             details: None,
             hash: Some("abcd1234".to_string()),
             size: Some(1024),
             modified_time: Some(1234567890),
             hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         };
         
         // Compute HMAC
@@ -168,19 +235,49 @@ mod tests {
     fn test_hmac_verification_no_hmac() {
         let msg = FileEventMessage {
             observer: "test-observer".to_string(),
-            event_type: "Create".to_string(),
+            event_type: EventType::Create,
             path: "test.txt".to_string(),
             details: None,
             hash: Some("abcd1234".to_string()),
             size: Some(1024),
             modified_time: Some(1234567890),
             hmac: None, // No HMAC provided
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         };
         
         // Verification should fail when no HMAC is provided
         assert!(!verify_hmac(&msg, "test-secret"));
     }
     
+    #[test]
+    fn test_hmac_covers_transaction() {
+        let secret = "test-secret";
+        let mut msg = FileEventMessage {
+            observer: "test-observer".to_string(),
+            event_type: EventType::Modify,
+            path: "test.txt".to_string(),
+            details: None,
+            hash: Some("abcd1234".to_string()),
+            size: Some(1024),
+            modified_time: Some(1234567890),
+            hmac: None,
+            transaction: Some(crate::core::models::TransactionInfo { id: "tx1".to_string(), size: 2 }),
+            observer_id: None,
+            append: None,
+            sequence: None,
+        };
+        
+        msg.hmac = Some(compute_hmac(&msg, secret));
+        assert!(verify_hmac(&msg, secret));
+        
+        // Detaching a message from its transaction invalidates it
+        msg.transaction = None;
+        assert!(!verify_hmac(&msg, secret));
+    }
+    
     #[test]
     fn test_constant_time_compare() {
         assert!(constant_time_compare("hello", "hello"));
@@ -188,4 +285,61 @@ mod tests {
         assert!(!constant_time_compare("hello", "hell"));
         assert!(!constant_time_compare("hell", "hello"));
     }
+
+    #[test]
+    fn test_observer_id_depends_on_secret() {
+        assert_eq!(observer_id("documents", "secret-a"), observer_id("documents", "secret-a"));
+        assert_ne!(observer_id("documents", "secret-a"), observer_id("documents", "secret-b"));
+        assert_ne!(observer_id("documents", "secret-a"), observer_id("photos", "secret-a"));
+        assert_eq!(observer_id("documents", "secret-a").len(), 32);
+    }
+
+    #[test]
+    fn test_renames_verify_as_the_modify_peers_receive() {
+        let secret = "test-secret";
+        let mut msg = FileEventMessage {
+            observer: "test-observer".to_string(),
+            event_type: EventType::Rename,
+            path: "moved.txt".to_string(),
+            details: Some("Modify(Name(Both))".to_string()),
+            hash: Some("abcd1234".to_string()),
+            size: Some(1024),
+            modified_time: Some(1234567890),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        };
+        msg.hmac = Some(compute_hmac(&msg, secret));
+
+        let received: FileEventMessage = serde_json::from_str(&serde_json::to_string(&msg).unwrap()).unwrap();
+        assert_eq!(received.event_type, EventType::Modify);
+        assert!(verify_hmac(&received, secret));
+    }
+
+    #[test]
+    fn test_segment_hmac_binds_chunk_to_version_and_offset() {
+        let secret = "test-secret";
+        let mut chunk = FileTransferResponse {
+            observer: "docs".to_string(),
+            path: "big.iso".to_string(),
+            data: b"abc".to_vec(),
+            offset: 1 << 20,
+            total_size: 4 << 20,
+            hash: "pending:4194304:1700000000".to_string(),
+            is_last_chunk: false,
+            segment_hash: Some("ba7816bf".to_string()),
+            segment_hmac: None,
+            encoding: None,
+        };
+        assert!(!verify_segment_hmac(&chunk, secret));
+        chunk.segment_hmac = Some(segment_hmac(secret, "docs", "big.iso", &chunk.hash, chunk.offset, "ba7816bf"));
+        assert!(verify_segment_hmac(&chunk, secret));
+        assert!(!verify_segment_hmac(&chunk, "other-secret"));
+
+        // Replaying a signed chunk at another offset fails
+        chunk.offset = 0;
+        assert!(!verify_segment_hmac(&chunk, secret));
+    }
 }
@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::core::config::AuthAlertConfig;
+#[cfg(feature = "metrics")]
+use crate::core::metrics::Metrics;
+use crate::core::tombstone::unix_now;
+
+pub const DEFAULT_THRESHOLD: u32 = 10;
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+
+/// Peers counted individually; failures from any further peers are counted as "other",
+/// so a flood of throwaway identities can't grow the counters without bound
+const MAX_TRACKED_PEERS: usize = 256;
+const OTHER_PEERS: &str = "other";
+
+/// Why a peer failed to authenticate
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthFailure {
+    /// An announcement, heartbeat, tree request or tree node whose HMAC doesn't match the shared secret
+    BadSignature,
+    /// A request for an observer the peer is not in the `peers` of
+    Unauthorized,
+    /// A request or announcement from a peer whose key the observer hasn't pinned
+    UnpinnedKey,
+}
+
+impl AuthFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BadSignature => "bad_signature",
+            Self::Unauthorized => "unauthorized",
+            Self::UnpinnedKey => "unpinned_key",
+        }
+    }
+}
+
+/// Raised once per window when a peer's failures reach the threshold
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthAlert {
+    pub peer: String,
+    /// Failures within the window so far
+    pub failures: u32,
+    pub window_secs: u64,
+    pub reasons: BTreeMap<AuthFailure, u32>,
+    /// Observers the failed attempts were for
+    pub observers: BTreeSet<String>,
+    /// Unix timestamp of the alert
+    pub timestamp: u64,
+}
+
+/// Failures counted for one peer since its window opened
+struct Window {
+    started: Instant,
+    reasons: BTreeMap<AuthFailure, u32>,
+    observers: BTreeSet<String>,
+    alerted: bool,
+}
+
+/// Failed authentication attempts per peer
+/// Keeps running totals for metrics and `syndactyl status`, and raises an alert
+/// when one peer fails often enough within a window to look like probing or a
+/// misconfigured secret rather than a stray message.
+pub struct AuthFailures {
+    threshold: u32,
+    window: Duration,
+    totals: BTreeMap<(String, AuthFailure), u64>,
+    /// Peers counted individually so far
+    peers: HashSet<String>,
+    windows: HashMap<String, Window>,
+}
+
+impl AuthFailures {
+    pub fn new(config: &AuthAlertConfig) -> Self {
+        Self {
+            threshold: config.threshold.unwrap_or(DEFAULT_THRESHOLD).max(1),
+            window: config.window_secs.map(Duration::from_secs).unwrap_or(DEFAULT_WINDOW),
+            totals: BTreeMap::new(),
+            peers: HashSet::new(),
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Count a failure, returning an alert if it brings the peer to the threshold
+    pub fn record(&mut self, peer: &str, observer: &str, failure: AuthFailure, now: Instant) -> Option<AuthAlert> {
+        let peer = if self.peers.contains(peer) || self.peers.len() < MAX_TRACKED_PEERS {
+            self.peers.insert(peer.to_string());
+            peer
+        } else {
+            OTHER_PEERS
+        };
+        *self.totals.entry((peer.to_string(), failure)).or_default() += 1;
+
+        let window_len = self.window;
+        let window = self.windows.entry(peer.to_string())
+            .and_modify(|w| {
+                if now.duration_since(w.started) >= window_len {
+                    *w = Window { started: now, reasons: BTreeMap::new(), observers: BTreeSet::new(), alerted: false };
+                }
+            })
+            .or_insert_with(|| Window { started: now, reasons: BTreeMap::new(), observers: BTreeSet::new(), alerted: false });
+        *window.reasons.entry(failure).or_default() += 1;
+        window.observers.insert(observer.to_string());
+
+        let failures = window.reasons.values().sum();
+        if window.alerted || failures < self.threshold {
+            return None;
+        }
+        window.alerted = true;
+        Some(AuthAlert {
+            peer: peer.to_string(),
+            failures,
+            window_secs: window_len.as_secs(),
+            reasons: window.reasons.clone(),
+            observers: window.observers.clone(),
+            timestamp: unix_now(),
+        })
+    }
+
+    /// Forget windows that have closed
+    pub fn expire(&mut self, now: Instant) {
+        let window_len = self.window;
+        self.windows.retain(|_, w| now.duration_since(w.started) < window_len);
+    }
+
+    /// Failures since the daemon started
+    pub fn total(&self) -> u64 {
+        self.totals.values().sum()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn write_metrics(&self, metrics: &mut Metrics) {
+        metrics.describe("syndactyl_auth_failures_total", "counter", "Failed authentication attempts, by peer and reason");
+        for ((peer, failure), count) in &self.totals {
+            metrics.sample("syndactyl_auth_failures_total", &[("peer", peer), ("reason", failure.as_str())], *count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_once_per_window_at_threshold() {
+        let config = AuthAlertConfig { threshold: Some(3), window_secs: Some(60) };
+        let mut failures = AuthFailures::new(&config);
+        let start = Instant::now();
+
+        assert!(failures.record("peer-a", "docs", AuthFailure::BadSignature, start).is_none());
+        assert!(failures.record("peer-b", "docs", AuthFailure::Unauthorized, start).is_none());
+        assert!(failures.record("peer-a", "photos", AuthFailure::UnpinnedKey, start).is_none());
+        let alert = failures.record("peer-a", "docs", AuthFailure::BadSignature, start + Duration::from_secs(10)).unwrap();
+        assert_eq!(alert.failures, 3);
+        assert_eq!(alert.reasons[&AuthFailure::BadSignature], 2);
+        assert_eq!(alert.observers.len(), 2);
+        assert!(failures.record("peer-a", "docs", AuthFailure::BadSignature, start + Duration::from_secs(20)).is_none());
+
+        // A new window starts counting again
+        let later = start + Duration::from_secs(61);
+        failures.expire(later);
+        assert!(failures.record("peer-a", "docs", AuthFailure::BadSignature, later).is_none());
+        assert_eq!(failures.total(), 6);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+/// Cumulative bytes moved in each direction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ByteCounter {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl ByteCounter {
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+/// Bandwidth used by file transfers, broken down by observer and by peer
+/// Only file content is counted; gossip and protocol overhead are not.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BandwidthStats {
+    #[serde(default)]
+    pub observers: BTreeMap<String, ByteCounter>,
+    #[serde(default)]
+    pub peers: BTreeMap<String, ByteCounter>,
+}
+
+impl BandwidthStats {
+    /// Record file content served to a peer
+    pub fn record_sent(&mut self, observer: &str, peer: &str, bytes: u64) {
+        self.observers.entry(observer.to_string()).or_default().sent += bytes;
+        self.peers.entry(peer.to_string()).or_default().sent += bytes;
+    }
+
+    /// Record file content downloaded from a peer
+    pub fn record_received(&mut self, observer: &str, peer: &str, bytes: u64) {
+        self.observers.entry(observer.to_string()).or_default().received += bytes;
+        self.peers.entry(peer.to_string()).or_default().received += bytes;
+    }
+
+    /// Human readable report, heaviest users first
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for (title, counters) in [("Observer", &self.observers), ("Peer", &self.peers)] {
+            let mut rows: Vec<(&String, &ByteCounter)> = counters.iter().collect();
+            rows.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then(a.0.cmp(b.0)));
+
+            out.push_str(&format!("{:<54} {:>12} {:>12}\n", title, "Sent", "Received"));
+            if rows.is_empty() {
+                out.push_str("  (no transfers recorded)\n");
+            }
+            for (name, counter) in rows {
+                out.push_str(&format!(
+                    "{:<54} {:>12} {:>12}\n",
+                    name,
+                    format_bytes(counter.sent),
+                    format_bytes(counter.received)
+                ));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Format a byte count using binary units, e.g. "1.5 MiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_lists_heaviest_first_then_by_name() {
+        let mut stats = BandwidthStats::default();
+        stats.record_sent("music", "peer-b", 100);
+        stats.record_received("photos", "peer-c", 500);
+        stats.record_sent("docs", "peer-a", 100);
+
+        let report = stats.report();
+        let position = |name: &str| report.find(name).unwrap();
+        assert!(position("photos") < position("docs") && position("docs") < position("music"));
+        assert!(position("peer-c") < position("peer-a") && position("peer-a") < position("peer-b"));
+        assert!(report.contains("500 B"));
+        assert!(!report.contains("(no transfers recorded)"));
+
+        // Both tables say so when nothing was transferred
+        assert_eq!(BandwidthStats::default().report().matches("  (no transfers recorded)\n").count(), 2);
+    }
+
+    #[test]
+    fn test_format_bytes_switches_units_at_1024() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(1024u64.pow(4) - 1), "1024.0 GiB");
+        assert_eq!(format_bytes(1024u64.pow(4)), "1.0 TiB");
+        // TiB is the largest unit
+        assert_eq!(format_bytes(1024u64.pow(5)), "1024.0 TiB");
+    }
+}
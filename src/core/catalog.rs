@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use serde::{Serialize, Deserialize};
+use crate::core::models::FileEventMessage;
+
+/// A file known to exist on a peer, as last announced
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemoteEntry {
+    pub size: u64,
+    pub modified_time: u64,
+    pub hash: String,
+    /// Peer that announced this version
+    pub peer: String,
+    /// Hash of the copy in the local cache, if one has been fetched
+    #[serde(default)]
+    pub cached_hash: Option<String>,
+}
+
+impl RemoteEntry {
+    /// Whether the cached copy is the announced version
+    pub fn is_cached(&self) -> bool {
+        self.cached_hash.as_deref() == Some(self.hash.as_str())
+    }
+}
+
+/// Remote trees of on-demand observers, keyed by observer then relative path
+/// Paths use '/' separators and sort so that a directory's contents are contiguous.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Catalog {
+    pub observers: BTreeMap<String, BTreeMap<String, RemoteEntry>>,
+}
+
+/// Catalog shared between the network manager and mounted filesystems
+pub type SharedCatalog = Arc<RwLock<Catalog>>;
+
+/// A child of a catalog directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum CatalogNode {
+    File(RemoteEntry),
+    Dir,
+}
+
+impl Catalog {
+    /// Record an announced Create/Modify, keeping the cached hash of the previous version
+    /// Returns false if the announcement lacks a hash or size.
+    pub fn record(&mut self, event: &FileEventMessage, peer: &str) -> bool {
+        let (Some(hash), Some(size)) = (&event.hash, event.size) else {
+            return false;
+        };
+        let entries = self.observers.entry(event.observer.clone()).or_default();
+        let cached_hash = entries.get(&event.path).and_then(|e| e.cached_hash.clone());
+        entries.insert(event.path.clone(), RemoteEntry {
+            size,
+            modified_time: event.modified_time.unwrap_or(0),
+            hash: hash.clone(),
+            peer: peer.to_string(),
+            cached_hash,
+        });
+        true
+    }
+
+    pub fn remove(&mut self, observer: &str, path: &str) -> Option<RemoteEntry> {
+        self.observers.get_mut(observer)?.remove(path)
+    }
+
+    pub fn get(&self, observer: &str, path: &str) -> Option<&RemoteEntry> {
+        self.observers.get(observer)?.get(path)
+    }
+
+    /// Note that the cache now holds the file with `hash`
+    pub fn mark_cached(&mut self, observer: &str, path: &str, hash: &str) {
+        if let Some(entry) = self.observers.get_mut(observer).and_then(|e| e.get_mut(path)) {
+            entry.cached_hash = Some(hash.to_string());
+        }
+    }
+
+    /// Look up a path, which may be a file or a directory implied by the files below it
+    /// The empty path is the observer's root.
+    pub fn node(&self, observer: &str, path: &str) -> Option<CatalogNode> {
+        if path.is_empty() {
+            return Some(CatalogNode::Dir);
+        }
+        let entries = self.observers.get(observer)?;
+        if let Some(entry) = entries.get(path) {
+            return Some(CatalogNode::File(entry.clone()));
+        }
+        let prefix = format!("{}/", path);
+        entries.range(prefix.clone()..)
+            .next()
+            .filter(|(child, _)| child.starts_with(&prefix))
+            .map(|_| CatalogNode::Dir)
+    }
+
+    /// Immediate children of a directory, as (name, node), in name order
+    pub fn children(&self, observer: &str, dir: &str) -> Vec<(String, CatalogNode)> {
+        let Some(entries) = self.observers.get(observer) else {
+            return Vec::new();
+        };
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let mut children: Vec<(String, CatalogNode)> = Vec::new();
+        for (path, entry) in entries.range(prefix.clone()..).take_while(|(p, _)| p.starts_with(&prefix)) {
+            let rest = &path[prefix.len()..];
+            match rest.split_once('/') {
+                Some((name, _)) => {
+                    if children.last().is_none_or(|(last, _)| last != name) {
+                        children.push((name.to_string(), CatalogNode::Dir));
+                    }
+                }
+                None => children.push((rest.to_string(), CatalogNode::File(entry.clone()))),
+            }
+        }
+        // "a-b" sorts before "a/x" as a path, but after "a" as a name
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+        children
+    }
+}
+
+/// Requests from mounts to the network manager about catalogued files
+pub enum CatalogRequest {
+    /// Download the whole file into the local cache
+    /// The reply is sent once the download finished or failed.
+    Fetch {
+        observer: String,
+        path: String,
+        reply: std::sync::mpsc::Sender<Result<(), String>>,
+    },
+    /// Read a byte range straight from a peer, without caching it
+    Read {
+        observer: String,
+        path: String,
+        offset: u64,
+        length: u32,
+        reply: std::sync::mpsc::Sender<Result<Vec<u8>, String>>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::EventType;
+
+    fn announce(catalog: &mut Catalog, path: &str, hash: &str) {
+        let event = FileEventMessage {
+            observer: "media".to_string(),
+            event_type: EventType::Create,
+            path: path.to_string(),
+            details: None,
+            hash: Some(hash.to_string()),
+            size: Some(10),
+            modified_time: Some(1),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        };
+        assert!(catalog.record(&event, "peer-a"));
+    }
+
+    #[test]
+    fn test_directories_are_implied_by_files() {
+        let mut catalog = Catalog::default();
+        announce(&mut catalog, "films/a.mkv", "h1");
+        announce(&mut catalog, "films/extras/b.mkv", "h2");
+        announce(&mut catalog, "films-old.txt", "h3");
+
+        assert_eq!(catalog.node("media", "films"), Some(CatalogNode::Dir));
+        assert_eq!(catalog.node("media", "film"), None);
+        let names: Vec<String> = catalog.children("media", "").into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["films", "films-old.txt"]);
+        let names: Vec<String> = catalog.children("media", "films").into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["a.mkv", "extras"]);
+    }
+
+    #[test]
+    fn test_new_version_invalidates_cache() {
+        let mut catalog = Catalog::default();
+        announce(&mut catalog, "a.mkv", "h1");
+        catalog.mark_cached("media", "a.mkv", "h1");
+        assert!(catalog.get("media", "a.mkv").unwrap().is_cached());
+
+        announce(&mut catalog, "a.mkv", "h2");
+        assert!(!catalog.get("media", "a.mkv").unwrap().is_cached());
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::{self, File};
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::core::bandwidth::format_bytes;
+
+/// Ending added to the name of a file stored compressed
+pub const SUFFIX: &str = ".zst";
+
+/// zstd level; fast, and still shrinks text several times over
+#[cfg(feature = "compression")]
+const LEVEL: i32 = 3;
+
+/// Largest zstd frame header, which records the uncompressed size
+#[cfg(feature = "compression")]
+const FRAME_HEADER_MAX: u64 = 18;
+
+/// Where the compressed form of `path` is stored
+pub fn compressed(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(SUFFIX);
+    PathBuf::from(name)
+}
+
+fn staged(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Store `content` at `path`, or compressed next to it when `compress`
+/// The other form is removed, so switching the setting never leaves two copies.
+pub fn write(path: &Path, content: &[u8], compress: bool) -> io::Result<()> {
+    let (target, other) = if compress { (compressed(path), path.to_path_buf()) } else { (path.to_path_buf(), compressed(path)) };
+    let staged = staged(&target);
+    if compress {
+        fs::write(&staged, compress(content)?)?;
+    } else {
+        fs::write(&staged, content)?;
+    }
+    fs::rename(&staged, &target)?;
+    remove_file(&other)
+}
+
+/// Read what `write` stored at `path`, whichever form it is in
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    match File::open(compressed(path)) {
+        Ok(file) => decompress(file),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::read(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove both forms of `path`
+pub fn remove(path: &Path) -> io::Result<()> {
+    remove_file(path)?;
+    remove_file(&compressed(path))
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Space taken by the files in a directory, and what they hold uncompressed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub files: u64,
+    pub stored: u64,
+    pub original: u64,
+}
+
+/// Usage of the files directly in `dir`; a missing directory holds nothing
+pub fn usage(dir: &Path) -> io::Result<Usage> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Usage::default()),
+        Err(e) => return Err(e),
+    };
+    let mut usage = Usage::default();
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let path = entry.path();
+        // Half-written files are replaced or removed shortly
+        if !metadata.is_file() || path.to_string_lossy().ends_with(".tmp") {
+            continue;
+        }
+        usage.files += 1;
+        usage.stored += metadata.len();
+        usage.original += if path.to_string_lossy().ends_with(SUFFIX) { original_size(&path)? } else { metadata.len() };
+    }
+    Ok(usage)
+}
+
+#[cfg(feature = "compression")]
+fn compress(content: &[u8]) -> io::Result<Vec<u8>> {
+    zstd::bulk::compress(content, LEVEL)
+}
+
+#[cfg(feature = "compression")]
+fn decompress(file: File) -> io::Result<Vec<u8>> {
+    zstd::stream::decode_all(file)
+}
+
+/// Uncompressed size of a stored file, from its frame header when recorded there
+#[cfg(feature = "compression")]
+fn original_size(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut header = Vec::new();
+    (&mut file).take(FRAME_HEADER_MAX).read_to_end(&mut header)?;
+    if let Ok(Some(size)) = zstd::zstd_safe::get_frame_content_size(&header) {
+        return Ok(size);
+    }
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut zstd::stream::read::Decoder::new(file)?, &mut io::sink())
+}
+
+/// Built without compression, history is stored plain and compressed copies can't be read
+#[cfg(not(feature = "compression"))]
+fn unavailable() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "built without the compression feature")
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_content: &[u8]) -> io::Result<Vec<u8>> {
+    Err(unavailable())
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_file: File) -> io::Result<Vec<u8>> {
+    Err(unavailable())
+}
+
+#[cfg(not(feature = "compression"))]
+fn original_size(_path: &Path) -> io::Result<u64> {
+    Err(unavailable())
+}
+
+/// Table of stored and original sizes, one row per place
+pub fn report(rows: &[(String, Usage)]) -> String {
+    let mut out = format!("{:<42} {:>8} {:>12} {:>12} {:>7}\n", "Version history", "Files", "Stored", "Original", "Saved");
+    if rows.is_empty() {
+        out.push_str("  (nothing stored)\n");
+    }
+    for (name, usage) in rows {
+        let saved = match usage.original {
+            0 => 0.0,
+            original => 100.0 * original.saturating_sub(usage.stored) as f64 / original as f64,
+        };
+        out.push_str(&format!(
+            "{:<42} {:>8} {:>12} {:>12} {:>6.1}%\n",
+            name,
+            usage.files,
+            format_bytes(usage.stored),
+            format_bytes(usage.original),
+            saved
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_history_is_compressed_transparently_and_accounted() {
+        let dir = TempDir::new().unwrap();
+        let version = dir.path().join("version");
+        let content = "the same line again\n".repeat(1000).into_bytes();
+
+        write(&version, &content, true).unwrap();
+        assert!(compressed(&version).exists() && !version.exists());
+        assert_eq!(read(&version).unwrap(), content);
+        // Turning compression off replaces the compressed copy
+        write(&version, b"plain", false).unwrap();
+        assert!(version.exists() && !compressed(&version).exists());
+        assert_eq!(read(&version).unwrap(), b"plain");
+        write(&version, &content, true).unwrap();
+        let other = dir.path().join("other");
+        write(&other, b"small", false).unwrap();
+
+        let usage = usage(dir.path()).unwrap();
+        assert_eq!(usage.files, 2);
+        assert_eq!(usage.original, content.len() as u64 + 5);
+        assert!(usage.stored < content.len() as u64 / 10);
+        assert!(report(&[("Common versions".to_string(), usage)]).contains("Common versions"));
+
+        remove(&version).unwrap();
+        remove(&other).unwrap();
+        assert_eq!(super::usage(dir.path()).unwrap(), Usage::default());
+    }
+}
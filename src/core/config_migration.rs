@@ -0,0 +1,173 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::core::listen;
+
+/// Schema version written by this build
+/// Bump it together with a new entry in `MIGRATIONS`.
+pub const CURRENT_VERSION: u64 = 2;
+
+/// One upgrade step, from the version before `to`
+struct Migration {
+    to: u64,
+    description: &'static str,
+    apply: fn(&mut Value) -> Result<(), String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration { to: 1, description: "ports written as numbers become strings", apply: ports_to_strings },
+    Migration { to: 2, description: "file browsers already listening beyond localhost are marked public", apply: mark_public_browsers },
+];
+
+/// Schema version of a parsed configuration file; files from before versioning are version 0
+pub fn version_of(config: &Value) -> Result<u64, String> {
+    match config.get("config_version") {
+        None | Some(Value::Null) => Ok(0),
+        Some(version) => version.as_u64().ok_or_else(|| format!("config_version must be a whole number, not {}", version)),
+    }
+}
+
+/// Upgrade a parsed configuration file to `CURRENT_VERSION`, returning the steps applied
+/// Files written by a newer build are refused rather than guessed at.
+pub fn migrate(config: &mut Value) -> Result<Vec<&'static str>, String> {
+    if !config.is_object() {
+        return Err("The configuration must be a JSON object".to_string());
+    }
+    let version = version_of(config)?;
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "The configuration is version {}, but this build of syndactyl only understands up to version {}; upgrade syndactyl or restore a backup",
+            version, CURRENT_VERSION
+        ));
+    }
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > version) {
+        (migration.apply)(config).map_err(|e| format!("Upgrading the configuration to version {} failed: {}", migration.to, e))?;
+        config["config_version"] = Value::from(migration.to);
+        applied.push(migration.description);
+    }
+    Ok(applied)
+}
+
+/// Replace the file at `path` with its upgrade, keeping the original as a `.v{version}.bak` backup
+/// The staged file takes the original's permissions before any content is written, so
+/// secrets in a 0600 config are never readable by others. Returns the backup path.
+pub fn write_upgraded(path: &Path, upgraded: &Value, version: u64) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    let backup = PathBuf::from(backup);
+    let permissions = fs::metadata(path)?.permissions();
+    fs::copy(path, &backup)?;
+    fs::set_permissions(&backup, permissions.clone())?;
+    let staged = path.with_extension("json.tmp");
+    let contents = serde_json::to_string_pretty(upgraded).map_err(io::Error::other)?;
+    let written = fs::File::create(&staged)
+        .and_then(|file| file.set_permissions(permissions))
+        .and_then(|_| fs::write(&staged, contents))
+        .and_then(|_| fs::rename(&staged, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    Ok(backup)
+}
+
+/// Version 1: `"port": 4001` is accepted as `"port": "4001"`, for the daemon, bootstrap peers and tenants
+fn ports_to_strings(config: &mut Value) -> Result<(), String> {
+    let stringify = |port: &mut Value| {
+        if let Value::Number(number) = port {
+            *port = Value::String(number.to_string());
+        }
+    };
+    if let Some(network) = config.get_mut("network").filter(|network| network.is_object()) {
+        if let Some(port) = network.get_mut("port") {
+            stringify(port);
+        }
+        if let Some(Value::Array(peers)) = network.get_mut("bootstrap_peers") {
+            for peer in peers.iter_mut().filter_map(|peer| peer.get_mut("port")) {
+                stringify(peer);
+            }
+        }
+    }
+    if let Some(Value::Array(tenants)) = config.get_mut("tenants") {
+        for tenant in tenants.iter_mut().filter(|tenant| tenant.is_object()) {
+            ports_to_strings(tenant)?;
+        }
+    }
+    Ok(())
+}
+
+/// Version 2: local services must opt in to non-loopback addresses, so keep existing browsers reachable
+fn mark_public_browsers(config: &mut Value) -> Result<(), String> {
+    if let Some(browser) = config.get_mut("browser").filter(|browser| browser.is_object()) {
+        if browser.get("listen").and_then(Value::as_str).is_some_and(listen::is_public) && browser.get("public").is_none() {
+            browser["public"] = Value::Bool(true);
+        }
+    }
+    if let Some(Value::Array(tenants)) = config.get_mut("tenants") {
+        for tenant in tenants.iter_mut().filter(|tenant| tenant.is_object()) {
+            mark_public_browsers(tenant)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_old_files_are_upgraded_and_newer_ones_refused() {
+        let mut config = json!({
+            "observers": [],
+            "network": { "listen_addr": "0.0.0.0", "port": 4001, "dht_mode": "server", "bootstrap_peers": [{ "ip": "10.0.0.2", "port": 4001, "peer_id": "12D3KooWExample" }] },
+            "tenants": [{ "name": "alice", "observers": [], "network": { "listen_addr": "0.0.0.0", "port": 4002, "dht_mode": "server", "bootstrap_peers": [] } }],
+            "browser": { "listen": "0.0.0.0:8384", "token": "t", "observers": [] },
+        });
+        assert_eq!(migrate(&mut config).unwrap().len(), 2);
+        assert_eq!(config["config_version"], json!(CURRENT_VERSION));
+        assert_eq!(config["network"]["port"], json!("4001"));
+        assert_eq!(config["network"]["bootstrap_peers"][0]["port"], json!("4001"));
+        assert_eq!(config["tenants"][0]["network"]["port"], json!("4002"));
+        assert_eq!(config["browser"]["public"], json!(true));
+        let parsed: crate::core::config::Config = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(parsed.config_version, Some(CURRENT_VERSION));
+
+        // Current files are left alone
+        assert!(migrate(&mut config).unwrap().is_empty());
+
+        assert!(migrate(&mut json!({ "config_version": CURRENT_VERSION + 1, "observers": [] })).is_err());
+        assert!(migrate(&mut json!({ "config_version": "one", "observers": [] })).is_err());
+    }
+
+    #[test]
+    fn test_upgraded_file_keeps_its_permissions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.json");
+        fs::write(&path, r#"{"observers": []}"#).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let mut config: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        migrate(&mut config).unwrap();
+        let backup = write_upgraded(&path, &config, 0).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), r#"{"observers": []}"#);
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["config_version"], json!(CURRENT_VERSION));
+        assert!(!path.with_extension("json.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+            assert_eq!(fs::metadata(&backup).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        // A file that can't be read back is reported, not half-written
+        assert!(write_upgraded(&temp_dir.path().join("missing.json"), &config, 0).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::core::storage::StorageBackend;
+use crate::core::tombstone::unix_now;
+use crate::core::version_store::VersionStore;
+
+/// A received file about to replace a local copy with different content
+pub struct Conflict<'a> {
+    pub observer: &'a str,
+    pub path: &'a str,
+    /// Last version both sides had, when the version store keeps one for this file
+    pub base: Option<&'a [u8]>,
+    pub local: &'a [u8],
+    pub incoming: &'a [u8],
+}
+
+/// What to write in place of the local copy
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    /// The received file replaces the local copy, as without a resolver
+    TakeIncoming,
+    /// The local copy stays; it is written back so transactions still complete
+    KeepLocal,
+    /// Write this instead, e.g. a merge of both sides
+    /// It is announced like any local change, so peers converge on it.
+    Merged(Vec<u8>),
+}
+
+impl Resolution {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TakeIncoming => "take_incoming",
+            Self::KeepLocal => "keep_local",
+            Self::Merged(_) => "merged",
+        }
+    }
+}
+
+/// Resolved conflicts kept for the control API, oldest dropped first
+const RECENT_CONFLICTS: usize = 100;
+
+/// A conflict settled by a resolver
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ResolvedConflict {
+    pub observer: String,
+    pub path: String,
+    /// "take_incoming", "keep_local" or "merged"
+    pub resolution: String,
+    /// Unix timestamp of the resolution
+    pub timestamp: u64,
+}
+
+/// Domain-specific merging supplied by embedders, e.g. a CRDT merge of JSON documents
+/// Consulted when a received file would replace a local copy edited concurrently,
+/// instead of the built-in last-writer-wins policy.
+pub trait ConflictResolver: Send + Sync {
+    fn resolve(&self, conflict: &Conflict) -> Resolution;
+}
+
+/// Resolvers registered for particular observers, and one for all others
+#[derive(Default, Clone)]
+pub struct ConflictResolvers {
+    fallback: Option<Arc<dyn ConflictResolver>>,
+    observers: HashMap<String, Arc<dyn ConflictResolver>>,
+    versions: Option<Arc<VersionStore>>,
+    /// Most recent conflicts, newest last
+    recent: Arc<Mutex<VecDeque<ResolvedConflict>>>,
+}
+
+impl ConflictResolvers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a resolver for one observer, or for every observer without one when `observer` is None
+    pub fn register(&mut self, observer: Option<&str>, resolver: Arc<dyn ConflictResolver>) {
+        match observer {
+            Some(observer) => {
+                self.observers.insert(observer.to_string(), resolver);
+            }
+            None => self.fallback = Some(resolver),
+        }
+    }
+
+    /// Keep common versions in `versions`, for resolvers to merge against
+    pub fn set_version_store(&mut self, versions: Arc<VersionStore>) {
+        self.versions = Some(versions);
+    }
+
+    /// Record the local copy as common, once a peer announced the same content
+    pub fn record_synced(&self, observer: &str, path: &str, storage: &dyn StorageBackend) {
+        let Some(versions) = &self.versions else {
+            return;
+        };
+        if let Err(e) = versions.record_local(observer, path, storage) {
+            warn!(observer = %observer, path = %path, error = %e, "Failed to record common version");
+        }
+    }
+
+    /// Whether received files for `observer` are consulted on, and so must be held whole
+    pub fn applies(&self, observer: &str) -> bool {
+        self.versions.is_some() || self.fallback.is_some() || self.observers.contains_key(observer)
+    }
+
+    /// Conflicts resolved since the daemon started, up to the last hundred, newest last
+    /// Without a resolver the received file simply wins, and nothing is recorded.
+    pub fn recent(&self) -> Vec<ResolvedConflict> {
+        self.recent.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+
+    /// Content to write for a received file, after consulting the observer's resolver
+    /// `local_synced` is whether the local copy is the version last applied from a
+    /// peer and unedited since; the received file then simply succeeds it.
+    pub fn resolve(&self, observer: &str, path: &str, storage: &dyn StorageBackend, incoming: Vec<u8>, local_synced: bool) -> io::Result<Vec<u8>> {
+        // The sender has the received version, whatever ends up written here
+        if let Some(versions) = &self.versions {
+            let common = versions.common(observer, path);
+            if let Err(e) = versions.record(observer, path, &incoming) {
+                warn!(observer = %observer, path = %path, error = %e, "Failed to record common version");
+            }
+            return self.resolve_against(observer, path, common.as_deref(), storage, incoming, local_synced);
+        }
+        self.resolve_against(observer, path, None, storage, incoming, local_synced)
+    }
+
+    fn resolve_against(&self, observer: &str, path: &str, base: Option<&[u8]>, storage: &dyn StorageBackend, incoming: Vec<u8>, local_synced: bool) -> io::Result<Vec<u8>> {
+        let Some(resolver) = self.observers.get(observer).or(self.fallback.as_ref()) else {
+            return Ok(incoming);
+        };
+        if local_synced {
+            return Ok(incoming);
+        }
+        let relative_path = Path::new(path);
+        if !storage.exists(relative_path) {
+            return Ok(incoming);
+        }
+        let size = storage.size(relative_path)?;
+        let local = storage.read_chunk(relative_path, 0, size as usize)?;
+        // Only versions made concurrently conflict: a local copy still equal to the
+        // last common version is an ancestor of the received one
+        if local == incoming || base == Some(local.as_slice()) {
+            return Ok(incoming);
+        }
+        let resolution = resolver.resolve(&Conflict { observer, path, base, local: &local, incoming: &incoming });
+        {
+            let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+            if recent.len() == RECENT_CONFLICTS {
+                recent.pop_front();
+            }
+            recent.push_back(ResolvedConflict {
+                observer: observer.to_string(),
+                path: path.to_string(),
+                resolution: resolution.name().to_string(),
+                timestamp: unix_now(),
+            });
+        }
+        Ok(match resolution {
+            Resolution::TakeIncoming => incoming,
+            Resolution::KeepLocal => local,
+            Resolution::Merged(merged) => merged,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::PlainStorage;
+
+    /// Keeps every line either side has, like a grow-only set
+    struct LineUnion;
+
+    impl ConflictResolver for LineUnion {
+        fn resolve(&self, conflict: &Conflict) -> Resolution {
+            let mut lines: Vec<&[u8]> = conflict.local.split(|byte| *byte == b'\n').collect();
+            for line in conflict.incoming.split(|byte| *byte == b'\n') {
+                if !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
+            Resolution::Merged(lines.join(&b'\n'))
+        }
+    }
+
+    #[test]
+    fn test_registered_resolver_replaces_last_writer_wins() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path());
+        storage.write_file(Path::new("list.txt"), b"a\nb").unwrap();
+
+        let mut resolvers = ConflictResolvers::new();
+        assert_eq!(resolvers.resolve("docs", "list.txt", &storage, b"a\nc".to_vec(), false).unwrap(), b"a\nc");
+
+        resolvers.register(Some("docs"), Arc::new(LineUnion));
+        assert_eq!(resolvers.resolve("docs", "list.txt", &storage, b"a\nc".to_vec(), false).unwrap(), b"a\nb\nc");
+        assert_eq!(resolvers.resolve("notes", "list.txt", &storage, b"a\nc".to_vec(), false).unwrap(), b"a\nc");
+        assert_eq!(resolvers.resolve("docs", "new.txt", &storage, b"x".to_vec(), false).unwrap(), b"x");
+        // A local copy nobody edited since it was synced is succeeded, dropping the removed line
+        assert_eq!(resolvers.resolve("docs", "list.txt", &storage, b"a\nc".to_vec(), true).unwrap(), b"a\nc");
+
+        let recent = resolvers.recent();
+        assert_eq!(recent.len(), 1);
+        assert_eq!((recent[0].path.as_str(), recent[0].resolution.as_str()), ("list.txt", "merged"));
+    }
+
+    #[test]
+    fn test_local_copy_equal_to_the_common_version_is_not_a_conflict() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path().join("docs"));
+        storage.write_file(Path::new("list.txt"), b"a\nb").unwrap();
+
+        let mut resolvers = ConflictResolvers::new();
+        resolvers.register(None, Arc::new(LineUnion));
+        resolvers.set_version_store(Arc::new(VersionStore::new(&temp_dir.path().join("versions"), HashMap::from([("docs".to_string(), vec!["txt".to_string()])]))));
+        resolvers.record_synced("docs", "list.txt", &storage);
+
+        // The peer removed a line from the version both had: no merge brings it back
+        assert_eq!(resolvers.resolve("docs", "list.txt", &storage, b"a".to_vec(), false).unwrap(), b"a");
+        assert!(resolvers.recent().is_empty());
+
+        // Edited here since, the copies are concurrent and merged
+        storage.write_file(Path::new("list.txt"), b"a\nd").unwrap();
+        assert_eq!(resolvers.resolve("docs", "list.txt", &storage, b"a\ne".to_vec(), false).unwrap(), b"a\nd\ne");
+    }
+}
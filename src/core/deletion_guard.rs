@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use crate::core::config::DeletionGuardConfig;
+
+pub const DEFAULT_THRESHOLD_PERCENT: u8 = 25;
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+pub const DEFAULT_MIN_DELETIONS: usize = 20;
+
+/// Deletions counted for one observer since the window opened
+struct Window {
+    started: Instant,
+    /// Files the observer held when the window opened
+    baseline: usize,
+    deletions: usize,
+    /// Deletions were confirmed by hand, so the rest of this window passes
+    confirmed: bool,
+}
+
+/// Holds back local deletions once a large share of an observer's files
+/// disappears in a short time, until they are confirmed or discarded
+/// Protects peers from a failing disk or a mistaken `rm -r`.
+pub struct DeletionGuard {
+    enabled: bool,
+    threshold_percent: u8,
+    window: Duration,
+    min_deletions: usize,
+    windows: HashMap<String, Window>,
+    /// Held announcements per observer, in the order they happened
+    held: BTreeMap<String, Vec<String>>,
+}
+
+impl DeletionGuard {
+    pub fn new(config: &DeletionGuardConfig) -> Self {
+        Self {
+            enabled: config.enabled != Some(false),
+            threshold_percent: config.threshold_percent.unwrap_or(DEFAULT_THRESHOLD_PERCENT),
+            window: config.window_secs.map(Duration::from_secs).unwrap_or(DEFAULT_WINDOW),
+            min_deletions: config.min_deletions.unwrap_or(DEFAULT_MIN_DELETIONS),
+            windows: HashMap::new(),
+            held: BTreeMap::new(),
+        }
+    }
+
+    /// Deletions still held from before a restart
+    pub fn with_held(mut self, held: BTreeMap<String, Vec<String>>) -> Self {
+        self.held = held;
+        self
+    }
+
+    /// Count a deletion announcement, returning it if it may be published now
+    /// `indexed_files` counts the observer's files, the deleted one included, and
+    /// is only called when a new window opens.
+    pub fn admit(&mut self, observer: &str, msg: String, now: Instant, indexed_files: impl FnOnce() -> usize) -> Option<String> {
+        if !self.enabled {
+            return Some(msg);
+        }
+        if let Some(held) = self.held.get_mut(observer) {
+            held.push(msg);
+            return None;
+        }
+
+        let window_len = self.window;
+        let window = self.windows.entry(observer.to_string())
+            .and_modify(|w| {
+                if now.duration_since(w.started) >= window_len {
+                    w.started = now;
+                    w.baseline = 0;
+                    w.deletions = 0;
+                    w.confirmed = false;
+                }
+            })
+            .or_insert(Window { started: now, baseline: 0, deletions: 0, confirmed: false });
+        if window.deletions == 0 {
+            window.baseline = indexed_files();
+        }
+        window.deletions += 1;
+
+        let tripped = !window.confirmed
+            && window.deletions > self.min_deletions
+            && window.deletions * 100 > window.baseline * self.threshold_percent as usize;
+        if tripped {
+            self.held.insert(observer.to_string(), vec![msg]);
+            return None;
+        }
+        Some(msg)
+    }
+
+    /// Whether deletions for `observer` are being held
+    pub fn is_holding(&self, observer: &str) -> bool {
+        self.held.contains_key(observer)
+    }
+
+    /// Deletions counted in the current window and the file count it started from
+    pub fn window_counts(&self, observer: &str) -> Option<(usize, usize)> {
+        self.windows.get(observer).map(|w| (w.deletions, w.baseline))
+    }
+
+    /// Release held deletions for one observer, or all of them
+    /// Further deletions in the current window are let through.
+    pub fn confirm(&mut self, observer: Option<&str>) -> Vec<String> {
+        let observers = self.select(observer);
+        let mut released = Vec::new();
+        for name in observers {
+            if let Some(held) = self.held.remove(&name) {
+                released.extend(held);
+            }
+            if let Some(window) = self.windows.get_mut(&name) {
+                window.confirmed = true;
+            }
+        }
+        released
+    }
+
+    /// Drop held deletions without announcing them, returning how many were dropped
+    pub fn discard(&mut self, observer: Option<&str>) -> usize {
+        let observers = self.select(observer);
+        let mut dropped = 0;
+        for name in observers {
+            dropped += self.held.remove(&name).map_or(0, |held| held.len());
+            self.windows.remove(&name);
+        }
+        dropped
+    }
+
+    /// Number of held deletions per observer
+    pub fn held_counts(&self) -> BTreeMap<String, usize> {
+        self.held.iter().map(|(name, held)| (name.clone(), held.len())).collect()
+    }
+
+    /// Held deletions of `path` or anything below it ("" is the observer's root)
+    /// A held deletion of a directory holding `path` counts as well.
+    pub fn held_below(&self, observer: &str, path: &str) -> usize {
+        let Some(held) = self.held.get(observer) else {
+            return 0;
+        };
+        held.iter()
+            .filter_map(|msg| serde_json::from_str::<serde_json::Value>(msg).ok())
+            .filter(|event| {
+                let Some(deleted) = event.get("path").and_then(|deleted| deleted.as_str()) else {
+                    return false;
+                };
+                let below = |child: &str, parent: &str| parent.is_empty() || child == parent || child.starts_with(&format!("{}/", parent));
+                below(deleted, path) || below(path, deleted)
+            })
+            .count()
+    }
+
+    /// Held announcements per observer, persisted so a restart doesn't lose them
+    pub fn held(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.held
+    }
+
+    fn select(&self, observer: Option<&str>) -> Vec<String> {
+        match observer {
+            Some(name) => vec![name.to_string()],
+            None => self.held.keys().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mass_deletion_is_held_until_confirmed() {
+        let mut guard = DeletionGuard::new(&DeletionGuardConfig {
+            enabled: None,
+            threshold_percent: Some(25),
+            window_secs: None,
+            min_deletions: Some(2),
+        });
+        let now = Instant::now();
+
+        // 10 files; the third deletion is 30% of them
+        assert!(guard.admit("docs", "rm a".to_string(), now, || 10).is_some());
+        assert!(guard.admit("docs", "rm b".to_string(), now, || unreachable!()).is_some());
+        assert!(guard.admit("docs", "rm c".to_string(), now, || unreachable!()).is_none());
+        assert!(guard.admit("docs", "rm d".to_string(), now, || unreachable!()).is_none());
+        assert!(guard.is_holding("docs"));
+        assert_eq!(guard.held_counts().get("docs"), Some(&2));
+
+        assert_eq!(guard.confirm(Some("docs")), vec!["rm c".to_string(), "rm d".to_string()]);
+        assert!(guard.admit("docs", "rm e".to_string(), now, || unreachable!()).is_some());
+
+        // A new window starts counting afresh
+        let later = now + DEFAULT_WINDOW;
+        assert!(guard.admit("docs", "rm f".to_string(), later, || 5).is_some());
+        assert_eq!(guard.window_counts("docs"), Some((1, 5)));
+    }
+
+    #[test]
+    fn test_held_deletions_survive_a_restart() {
+        let config = DeletionGuardConfig { enabled: None, threshold_percent: Some(25), window_secs: None, min_deletions: Some(0) };
+        let mut guard = DeletionGuard::new(&config);
+        assert!(guard.admit("docs", "rm a".to_string(), Instant::now(), || 2).is_none());
+
+        let mut restarted = DeletionGuard::new(&config).with_held(guard.held().clone());
+        assert!(restarted.is_holding("docs"));
+        assert!(restarted.admit("docs", "rm b".to_string(), Instant::now(), || unreachable!()).is_none());
+        assert_eq!(restarted.confirm(None), vec!["rm a".to_string(), "rm b".to_string()]);
+    }
+
+    #[test]
+    fn test_held_deletions_are_counted_below_a_path() {
+        let config = DeletionGuardConfig { enabled: None, threshold_percent: Some(25), window_secs: None, min_deletions: Some(0) };
+        let mut guard = DeletionGuard::new(&config);
+        let removal = |path: &str| serde_json::json!({ "observer": "docs", "event_type": "Remove", "path": path }).to_string();
+        for path in ["notes/a.txt", "notes/b.txt", "photos", "notes-old/c.txt"] {
+            assert!(guard.admit("docs", removal(path), Instant::now(), || 2).is_none());
+        }
+
+        assert_eq!(guard.held_below("docs", ""), 4);
+        assert_eq!(guard.held_below("docs", "notes"), 2);
+        assert_eq!(guard.held_below("docs", "notes/a.txt"), 1);
+        // Inside a directory being deleted
+        assert_eq!(guard.held_below("docs", "photos/2024"), 1);
+        assert_eq!(guard.held_below("docs", "music"), 0);
+        assert_eq!(guard.held_below("notes", ""), 0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+/// Free space below which downloads into an observer pause, unless configured (1 GiB)
+pub const DEFAULT_MIN_FREE_MB: u64 = 1024;
+
+/// Extra free space, as a percentage of the threshold, needed before downloads resume
+/// Without it a volume hovering around the threshold would pause and resume every check.
+const RESUME_MARGIN_PERCENT: u64 = 10;
+
+/// Raised when an observer's volume runs low on space, and again once it recovers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiskSpaceAlert {
+    pub observer: String,
+    pub path: String,
+    /// Whether downloads are now paused (false once space has been freed)
+    pub low: bool,
+    pub available_bytes: u64,
+    pub min_free_bytes: u64,
+    /// Unix timestamp of the alert
+    pub timestamp: u64,
+}
+
+/// Whether a volume with `available` bytes free counts as low on space
+/// Once low, it stays low until the free space clears the threshold by a margin.
+pub fn is_low(was_low: bool, available: u64, min_free: u64) -> bool {
+    let threshold = if was_low {
+        min_free.saturating_add(min_free / 100 * RESUME_MARGIN_PERCENT)
+    } else {
+        min_free
+    };
+    available < threshold
+}
+
+/// Bytes available to this user on the volume holding `path`
+#[cfg(unix)]
+pub fn available_bytes(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs only writes into the zeroed struct we pass it
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is NUL-terminated and outlives the call
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(u64::from(stats.f_bavail).saturating_mul(u64::from(stats.f_frsize)))
+}
+
+/// Bytes available to this user on the volume holding `path`
+#[cfg(windows)]
+pub fn available_bytes(path: &Path) -> io::Result<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0u64;
+    // SAFETY: wide is NUL-terminated; the totals we don't need may be null
+    if unsafe { GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(available)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_space_resumes_only_past_the_margin() {
+        let min_free = 1000;
+        assert!(!is_low(false, 1000, min_free));
+        assert!(is_low(false, 999, min_free));
+
+        // Freeing just enough to cross the threshold isn't enough to resume
+        assert!(is_low(true, 1050, min_free));
+        assert!(!is_low(true, 1100, min_free));
+        assert!(!is_low(false, 0, 0));
+
+        assert!(available_bytes(&std::env::temp_dir()).unwrap() > 0);
+    }
+}
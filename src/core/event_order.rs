@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use crate::core::models::{EventType, FileEventMessage};
+
+/// How long the newest operation on a file is remembered (1 day)
+/// Longer than gossip keeps re-forwarding or a transfer stays queued.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Most files remembered; the least recently seen are forgotten first
+pub const MAX_ENTRIES: usize = 50_000;
+
+/// Newest operation seen on one file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Operation {
+    /// Modification time of the content, or when the file was deleted
+    pub time: u64,
+    pub removed: bool,
+    /// Unix timestamp the operation was seen at
+    pub seen_at: u64,
+    /// Per-file sequence the operation was announced with, if any
+    #[serde(default)]
+    pub sequence: Option<u64>,
+}
+
+impl Operation {
+    /// Whether this operation supersedes `other`
+    /// Sequences decide when both have one; times are only compared otherwise,
+    /// since a deletion is stamped with one peer's clock and content with another's.
+    /// On a tie a removal loses, so content is never dropped.
+    fn supersedes(&self, other: &Operation) -> bool {
+        let (newest, candidate) = match (self.sequence, other.sequence) {
+            (Some(newest), Some(candidate)) => (newest, candidate),
+            _ => (self.time, other.time),
+        };
+        newest > candidate || (newest == candidate && other.removed && !self.removed)
+    }
+}
+
+/// Newest create, modify or remove seen per file, local or announced
+/// Gossip doesn't preserve order, so a create, delete and recreate can arrive
+/// in any order. Local operations are announced with a per-file sequence one
+/// past the newest seen, and one below the newest already seen is stale. Events
+/// from older peers are ordered by modification or deletion time instead, and
+/// events with neither can't be ordered and are let through.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EventOrder {
+    entries: HashMap<String, Operation>,
+}
+
+fn key(observer: &str, path: &str) -> String {
+    format!("{}\0{}", observer, path)
+}
+
+/// The operation an event announces, if it is one on a file that can be ordered
+fn operation(event: &FileEventMessage, now: u64) -> Option<Operation> {
+    let removed = match event.event_type {
+        EventType::Remove => true,
+        event_type if event_type.has_content() => false,
+        _ => return None,
+    };
+    Some(Operation { time: event.modified_time?, removed, seen_at: now, sequence: event.sequence })
+}
+
+impl EventOrder {
+    /// Whether an operation newer than `event` was already seen for its file
+    pub fn is_stale(&self, event: &FileEventMessage) -> bool {
+        operation(event, 0).is_some_and(|operation| {
+            self.entries.get(&key(&event.observer, &event.path)).is_some_and(|newest| newest.supersedes(&operation))
+        })
+    }
+
+    /// Remember `event` unless it is stale, returning false if it was
+    pub fn admit(&mut self, event: &FileEventMessage, now: u64) -> bool {
+        if self.is_stale(event) {
+            return false;
+        }
+        if let Some(operation) = operation(event, now) {
+            self.insert(&event.observer, &event.path, operation);
+        }
+        true
+    }
+
+    /// Sequence to announce the next local operation on a file with
+    pub fn next_sequence(&self, observer: &str, path: &str) -> u64 {
+        self.get(observer, path).and_then(|newest| newest.sequence).map_or(1, |sequence| sequence + 1)
+    }
+
+    /// Remember a local operation, which is always the newest for its file
+    /// The local file system already reflects it, whatever was announced before.
+    pub fn record_local(&mut self, event: &FileEventMessage, now: u64) {
+        if let Some(operation) = operation(event, now) {
+            self.insert(&event.observer, &event.path, operation);
+        }
+    }
+
+    pub fn get(&self, observer: &str, path: &str) -> Option<&Operation> {
+        self.entries.get(&key(observer, path))
+    }
+
+    fn insert(&mut self, observer: &str, path: &str, operation: Operation) {
+        self.entries.insert(key(observer, path), operation);
+        if self.entries.len() > MAX_ENTRIES {
+            // Drop the oldest tenth in one go rather than one entry per insert; counted
+            // by entry, since a burst can see thousands of files in the same second
+            let mut by_age: Vec<(u64, String)> = self.entries.iter().map(|(key, op)| (op.seen_at, key.clone())).collect();
+            by_age.sort_unstable();
+            for (_, key) in by_age.into_iter().take(MAX_ENTRIES / 10) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Forget operations seen longer than `ttl` ago, returning how many were dropped
+    pub fn expire(&mut self, now: u64, ttl: Duration) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, op| op.seen_at.saturating_add(ttl.as_secs()) > now);
+        before - self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::auth;
+    use crate::network::wire;
+
+    fn event(event_type: EventType, hash: Option<&str>, time: Option<u64>) -> FileEventMessage {
+        FileEventMessage {
+            observer: "docs".to_string(),
+            event_type,
+            path: "a.txt".to_string(),
+            details: None,
+            hash: hash.map(str::to_string),
+            size: hash.map(|_| 1),
+            modified_time: time,
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        }
+    }
+
+    /// End state of a replica applying every admitted event of `events` in order
+    fn replay(events: &[&FileEventMessage]) -> Option<String> {
+        let mut order = EventOrder::default();
+        let mut file = None;
+        for event in events {
+            if !order.admit(event, 0) {
+                continue;
+            }
+            file = match event.event_type {
+                EventType::Remove => None,
+                _ => event.hash.clone(),
+            };
+        }
+        file
+    }
+
+    #[test]
+    fn test_racing_operations_converge_on_the_newest() {
+        let create = event(EventType::Create, Some("h1"), Some(10));
+        let remove = event(EventType::Remove, None, Some(20));
+        let recreate = event(EventType::Create, Some("h2"), Some(30));
+
+        // Every arrival order of create, delete, recreate ends with the recreated file
+        let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        let events = [&create, &remove, &recreate];
+        for order in orders {
+            let arrived: Vec<&FileEventMessage> = order.iter().map(|i| events[*i]).collect();
+            assert_eq!(replay(&arrived).as_deref(), Some("h2"), "{:?}", order);
+        }
+
+        // A create that lost to a later delete stays deleted whichever arrives first
+        assert_eq!(replay(&[&create, &remove]), None);
+        assert_eq!(replay(&[&remove, &create]), None);
+
+        // A delete in the same second as a recreate doesn't drop the content
+        let same_second = event(EventType::Remove, None, Some(30));
+        assert_eq!(replay(&[&recreate, &same_second]).as_deref(), Some("h2"));
+
+        // Untimed events can't be ordered and always apply
+        let mut order = EventOrder::default();
+        assert!(order.admit(&recreate, 0));
+        assert!(order.admit(&event(EventType::Remove, None, None), 0));
+
+        // A local change wins over anything announced before it
+        order.record_local(&create, 0);
+        assert!(!order.is_stale(&event(EventType::Modify, Some("h3"), Some(15))));
+        assert_eq!(order.expire(DEFAULT_TTL.as_secs(), DEFAULT_TTL), 1);
+        assert!(order.is_empty());
+    }
+
+    /// A replica applying its own operations, and others' as they arrive over gossip
+    #[derive(Default)]
+    struct Peer {
+        order: EventOrder,
+        file: Option<String>,
+    }
+
+    impl Peer {
+        /// Announce a local operation stamped and signed the way the network manager does
+        fn local(&mut self, event_type: EventType, hash: Option<&str>, time: u64) -> Vec<u8> {
+            let mut op = event(event_type, hash, Some(time));
+            op.sequence = Some(self.order.next_sequence("docs", "a.txt"));
+            op.hmac = Some(auth::compute_hmac(&op, SECRET));
+            self.order.record_local(&op, time);
+            self.apply(&op);
+            serde_json::to_vec(&op).unwrap()
+        }
+
+        fn receive(&mut self, data: &[u8]) {
+            let op = wire::decode_file_event(data).unwrap();
+            assert!(auth::verify_hmac(&op, SECRET));
+            if self.order.admit(&op, 0) {
+                self.apply(&op);
+            }
+        }
+
+        fn apply(&mut self, op: &FileEventMessage) {
+            self.file = match op.event_type {
+                EventType::Remove => None,
+                _ => op.hash.clone(),
+            };
+        }
+    }
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn test_racing_sequences_converge_across_skewed_clocks() {
+        // `a`'s clock runs well ahead of `b`'s, so `b`'s delete is stamped before `a`'s create
+        let (mut a, mut b) = (Peer::default(), Peer::default());
+        let create = a.local(EventType::Create, Some("h1"), 1100);
+        b.receive(&create);
+        let remove = b.local(EventType::Remove, None, 900);
+        a.receive(&remove);
+        assert_eq!(a.file, None);
+        let recreate = a.local(EventType::Create, Some("h2"), 1150);
+        b.receive(&recreate);
+        assert_eq!(b.file.as_deref(), Some("h2"));
+
+        // A third peer ends up the same whichever order gossip delivers them in
+        let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        let messages = [&create, &remove, &recreate];
+        for order in orders {
+            let mut c = Peer::default();
+            for i in order {
+                c.receive(messages[i]);
+            }
+            assert_eq!(c.file.as_deref(), Some("h2"), "{:?}", order);
+        }
+
+        // The delete follows the create, though its clock says otherwise
+        let mut c = Peer::default();
+        c.receive(&remove);
+        c.receive(&create);
+        assert_eq!(c.file, None);
+
+        // A sequence bumped in transit no longer verifies
+        let mut replayed: FileEventMessage = serde_json::from_slice(&create).unwrap();
+        replayed.sequence = Some(10);
+        assert!(!auth::verify_hmac(&replayed, SECRET));
+    }
+
+    #[test]
+    fn test_full_order_forgets_a_tenth_even_when_seen_at_once() {
+        let mut order = EventOrder::default();
+        for i in 0..=MAX_ENTRIES {
+            order.insert("docs", &i.to_string(), Operation { time: i as u64, removed: false, seen_at: 7, sequence: Some(1) });
+        }
+        assert_eq!(order.entries.len(), MAX_ENTRIES + 1 - MAX_ENTRIES / 10);
+    }
+}
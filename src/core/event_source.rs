@@ -0,0 +1,89 @@
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(not(feature = "native-watcher"))]
+use std::time::Duration;
+use notify::{Event, RecursiveMode, Result, Watcher};
+
+#[cfg(feature = "native-watcher")]
+type PlatformWatcher = notify::RecommendedWatcher;
+
+/// Some minimal targets (Android sandboxes, old NAS kernels) have no usable
+/// inotify, so headless builds scan the tree instead
+#[cfg(not(feature = "native-watcher"))]
+type PlatformWatcher = notify::PollWatcher;
+
+/// How often the polling watcher rescans
+#[cfg(not(feature = "native-watcher"))]
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A producer of raw file events for one observer
+/// Events use notify's `Event` type, so any source feeds the same pipeline
+/// (hashing, filtering, signing) as the filesystem watcher.
+pub trait EventSource: Send {
+    /// Start delivering events for the tree at `root` into `sink`
+    /// The source must stay alive (and keep delivering) until it is dropped.
+    fn start(&mut self, root: &Path, sink: mpsc::Sender<Result<Event>>) -> Result<()>;
+}
+
+/// The default source: the platform's native filesystem watcher, or a
+/// polling one when built without the native-watcher feature
+#[derive(Default)]
+pub struct NotifySource {
+    watcher: Option<PlatformWatcher>,
+}
+
+impl NotifySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventSource for NotifySource {
+    fn start(&mut self, root: &Path, sink: mpsc::Sender<Result<Event>>) -> Result<()> {
+        #[cfg(feature = "native-watcher")]
+        let mut watcher = notify::recommended_watcher(sink)?;
+        #[cfg(not(feature = "native-watcher"))]
+        let mut watcher = notify::PollWatcher::new(sink, notify::Config::default().with_poll_interval(POLL_INTERVAL))?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        self.watcher = Some(watcher);
+        Ok(())
+    }
+}
+
+/// A source driven by hand, for tests and embedders
+/// Clones share the same sink, so one clone can be given to the observer
+/// while another is kept to `emit` synthetic events.
+#[derive(Clone, Default)]
+pub struct VirtualSource {
+    sink: Arc<Mutex<Option<mpsc::Sender<Result<Event>>>>>,
+}
+
+impl VirtualSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver an event, returning false if the source has not started or was closed
+    pub fn emit(&self, event: Event) -> bool {
+        match self.sink.lock() {
+            Ok(sink) => sink.as_ref().is_some_and(|sink| sink.send(Ok(event)).is_ok()),
+            Err(_) => false,
+        }
+    }
+
+    /// Stop delivering events, which lets the observer thread exit
+    pub fn close(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            sink.take();
+        }
+    }
+}
+
+impl EventSource for VirtualSource {
+    fn start(&mut self, _root: &Path, sink: mpsc::Sender<Result<Event>>) -> Result<()> {
+        if let Ok(mut current) = self.sink.lock() {
+            *current = Some(sink);
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,13 @@
+use std::borrow::Cow;
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use sha2::{Sha256, Digest};
-use tracing::info;
+use tracing::{info, warn};
+
+/// Longest path the classic Windows file APIs accept
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
 
 /// Calculate SHA-256 hash of a file
 pub fn calculate_file_hash(path: &Path) -> io::Result<String> {
@@ -21,6 +26,31 @@ pub fn calculate_file_hash(path: &Path) -> io::Result<String> {
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// Whether an observer's root directory is present
+/// A removable root must also be on a different filesystem than its parent, so
+/// an empty mountpoint left behind by an unplugged drive doesn't count.
+pub fn root_available(root: &Path, removable: bool) -> bool {
+    let Ok(metadata) = fs::metadata(root) else {
+        return false;
+    };
+    metadata.is_dir() && (!removable || is_mounted(root, &metadata))
+}
+
+/// Whether `root` is on a different filesystem than its parent directory
+#[cfg(unix)]
+fn is_mounted(root: &Path, metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let parent = root.parent().and_then(|parent| fs::metadata(parent).ok());
+    parent.is_none_or(|parent| parent.dev() != metadata.dev())
+}
+
+/// Drives are roots of their own elsewhere, and an unplugged one is simply missing
+#[cfg(not(unix))]
+fn is_mounted(_root: &Path, _metadata: &fs::Metadata) -> bool {
+    true
+}
+
 /// Read entire file into memory (for files up to reasonable size)
 pub fn read_file_content(path: &Path) -> io::Result<Vec<u8>> {
     fs::read(path)
@@ -87,6 +117,14 @@ pub fn get_file_metadata(path: &Path) -> io::Result<(u64, u64)> {
     Ok((size, modified_time))
 }
 
+/// Modification time in nanoseconds since the epoch
+pub fn modified_nanos(path: &Path) -> io::Result<u128> {
+    Ok(fs::metadata(path)?.modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0))
+}
+
 /// Convert absolute path to relative path within observer base path
 pub fn to_relative_path(absolute_path: &Path, base_path: &Path) -> Option<PathBuf> {
     absolute_path.strip_prefix(base_path).ok().map(|p| p.to_path_buf())
@@ -94,12 +132,99 @@ pub fn to_relative_path(absolute_path: &Path, base_path: &Path) -> Option<PathBu
 
 /// Convert relative path to absolute path using observer base path
 pub fn to_absolute_path(relative_path: &Path, base_path: &Path) -> PathBuf {
-    base_path.join(relative_path)
+    long_path(&base_path.join(relative_path)).into_owned()
+}
+
+/// The form of an absolute path the OS accepts however long it is
+/// On Windows, paths past MAX_PATH are rewritten in extended-length (`\\?\`) form,
+/// which skips the usual normalization, so `.` and `..` are resolved and mixed
+/// separators unified here. Other platforms have no such limit on a single path.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::ffi::OsString;
+    use std::path::{Component, Prefix};
+
+    if path.as_os_str().len() < MAX_PATH || !path.is_absolute() {
+        return Cow::Borrowed(path);
+    }
+    let mut components = path.components();
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(drive) => OsString::from(format!(r"\\?\{}:", drive as char)),
+            Prefix::UNC(server, share) => {
+                let mut long = OsString::from(r"\\?\UNC\");
+                long.push(server);
+                long.push("\\");
+                long.push(share);
+                long
+            }
+            // Already extended-length, or a device path
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    };
+    let mut names = Vec::new();
+    for component in components {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::ParentDir => {
+                names.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    for name in names {
+        long.push("\\");
+        long.push(name);
+    }
+    Cow::Owned(PathBuf::from(long))
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Move a file, copying it when `to` is on another filesystem
+/// The copy is written and synced next to `to` under a hidden name and then
+/// renamed over it, so `to` is still replaced atomically; `from` is removed last.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_into_place(from, to)?;
+            fs::remove_file(from)
+        }
+        moved => moved,
+    }
+}
+
+fn partial_copy_path(to: &Path) -> PathBuf {
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    to.with_file_name(format!(".{}{}", name, PARTIAL_SUFFIX))
+}
+
+/// Remove the copy a cross-device `move_file` interrupted by a crash left next to `to`
+pub fn remove_partial_copy(to: &Path) -> io::Result<()> {
+    match fs::remove_file(partial_copy_path(to)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn copy_into_place(from: &Path, to: &Path) -> io::Result<()> {
+    let partial = partial_copy_path(to);
+    let copied = fs::copy(from, &partial)
+        .and_then(|_| File::open(&partial)?.sync_all())
+        .and_then(|_| fs::rename(&partial, to));
+    if copied.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    copied
 }
 
 /// Move file to trash directory
 pub fn move_to_trash(path: &Path, base_path: &Path) -> io::Result<()> {
-    let trash_dir = base_path.join(".syndactyl").join("trash");
+    let trash_dir = base_path.join(INTERNAL_DIR).join("trash");
     fs::create_dir_all(&trash_dir)?;
     
     // Generate unique trash filename with timestamp
@@ -117,10 +242,23 @@ pub fn move_to_trash(path: &Path, base_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Directory inside each observer holding syndactyl's own state: trash, tmp and staging
+pub const INTERNAL_DIR: &str = ".syndactyl";
+
+/// Ending of the partial copies left next to their destination by cross-device moves
+const PARTIAL_SUFFIX: &str = ".syndactyl-partial";
+
+/// Whether a path, relative or absolute, is syndactyl's own state rather than a user file
+/// `.syndactyl` matches at any depth, as an observer nested in another keeps its own.
+/// Every component that announces, lists, serves or applies files checks this.
+pub fn is_internal_path(path: &Path) -> bool {
+    path.components().any(|component| component.as_os_str() == INTERNAL_DIR)
+        || path.file_name().is_some_and(|name| name.to_string_lossy().ends_with(PARTIAL_SUFFIX))
+}
+
 /// Check if file should be synced (not in .syndactyl directory, etc.)
 pub fn should_sync_file(relative_path: &Path) -> bool {
-    // Skip .syndactyl internal directory
-    if relative_path.starts_with(".syndactyl") {
+    if is_internal_path(relative_path) {
         return false;
     }
     
@@ -134,6 +272,46 @@ pub fn should_sync_file(relative_path: &Path) -> bool {
     true
 }
 
+/// List every syncable file under `base_path`, as paths relative to it
+/// Symlinks are not followed, and internal/hidden entries are skipped. The walk
+/// keeps its own stack, so tree depth is bounded by memory rather than the call
+/// stack. Only an unreadable `base_path` is an error; a subdirectory that can't
+/// be read, or disappears mid-walk, is skipped so one bad corner doesn't hide
+/// the rest of the tree.
+pub fn list_files(base_path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        let entries = match fs::read_dir(to_absolute_path(&relative_dir, base_path)) {
+            Ok(entries) => entries,
+            Err(e) if relative_dir.as_os_str().is_empty() => return Err(e),
+            Err(e) => {
+                warn!(dir = %relative_dir.display(), error = %e, "Cannot list directory, skipping it");
+                continue;
+            }
+        };
+        for entry in entries {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let relative_path = relative_dir.join(entry.file_name());
+            if !should_sync_file(&relative_path) {
+                continue;
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                pending.push(relative_path);
+            } else if file_type.is_file() {
+                files.push(relative_path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -164,4 +342,69 @@ mod tests {
         let back_to_absolute = to_absolute_path(&relative, &base);
         assert_eq!(back_to_absolute, absolute);
     }
+
+    #[test]
+    fn test_internal_paths_are_never_synced() {
+        for path in [".syndactyl/trash/a.txt.1700000000", "nested/.syndactyl/tmp/staging/tx1/b.txt", "/home/user/sync/.syndactyl/tmp/c", "docs/.report.pdf.syndactyl-partial"] {
+            assert!(is_internal_path(Path::new(path)), "{}", path);
+            assert!(!should_sync_file(Path::new(path)), "{}", path);
+        }
+        assert!(should_sync_file(Path::new("notes/syndactyl.md")));
+        assert!(!is_internal_path(Path::new("notes/.syndactyl.bak/plan.txt")));
+    }
+
+    #[test]
+    fn test_copy_into_place_replaces_the_destination() {
+        let temp_dir = TempDir::new().unwrap();
+        let (from, to) = (temp_dir.path().join("incoming"), temp_dir.path().join("notes.txt"));
+        fs::write(&from, b"new").unwrap();
+        fs::write(&to, b"old").unwrap();
+
+        copy_into_place(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), b"new");
+        assert!(!temp_dir.path().join(".notes.txt.syndactyl-partial").exists());
+
+        move_file(&from, &temp_dir.path().join("moved.txt")).unwrap();
+        assert!(!from.exists());
+    }
+
+    #[test]
+    fn test_list_files_walks_deep_and_wide_trees() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut deep = PathBuf::new();
+        for _ in 0..1000 {
+            deep.push("d");
+        }
+        fs::create_dir_all(temp_dir.path().join(&deep)).unwrap();
+        fs::write(temp_dir.path().join(&deep).join("bottom.txt"), b"").unwrap();
+
+        let wide = temp_dir.path().join("wide");
+        fs::create_dir(&wide).unwrap();
+        for i in 0..10_000 {
+            File::create(wide.join(format!("{:05}.txt", i))).unwrap();
+        }
+        fs::create_dir_all(temp_dir.path().join(".syndactyl").join("tmp")).unwrap();
+        fs::write(temp_dir.path().join(".syndactyl").join("tmp").join("partial"), b"").unwrap();
+
+        let files = list_files(temp_dir.path()).unwrap();
+        assert_eq!(files.len(), 10_001);
+        assert_eq!(files[0], deep.join("bottom.txt"));
+        assert_eq!(files[1], PathBuf::from("wide/00000.txt"));
+        assert!(list_files(&temp_dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_root_available() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(root_available(temp_dir.path(), false));
+        assert!(!root_available(&temp_dir.path().join("missing"), false));
+        assert!(!root_available(&temp_dir.path().join("missing"), true));
+
+        // A plain subdirectory shares its parent's filesystem, like an empty mountpoint
+        let mountpoint = temp_dir.path().join("usb");
+        fs::create_dir(&mountpoint).unwrap();
+        assert!(root_available(&mountpoint, false));
+        #[cfg(unix)]
+        assert!(!root_available(&mountpoint, true));
+    }
 }
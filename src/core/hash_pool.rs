@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use tracing::{debug, warn};
+use crate::core::file_handler;
+use crate::core::models::FileEventMessage;
+use crate::core::power::PowerMonitor;
+use crate::core::secret::Secret;
+use crate::core::storage::{self, StorageBackend};
+
+/// Files at least this large are announced straight away and hashed in the background
+pub const BACKGROUND_HASH_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Worker threads shared by all observers
+pub const HASH_WORKERS: usize = 2;
+
+/// `details` of an announcement whose hash follows in a later one
+pub const HASH_PENDING: &str = "HashPending";
+
+/// Stands in for the hash when fetching a file announced before it was hashed
+/// Names the version by size and modification time, which servers check instead.
+pub fn pending_version(size: u64, modified_time: u64) -> String {
+    format!("pending:{}:{}", size, modified_time)
+}
+
+/// Size and modification time named by a `pending_version` token
+pub fn parse_pending_version(hash: &str) -> Option<(u64, u64)> {
+    let (size, modified_time) = hash.strip_prefix("pending:")?.split_once(':')?;
+    Some((size.parse().ok()?, modified_time.parse().ok()?))
+}
+
+/// A file waiting to be hashed, with the pending announcement to complete
+struct HashJob {
+    msg: FileEventMessage,
+    secret: Option<Secret>,
+    tx: mpsc::Sender<String>,
+}
+
+/// A stored file hashed for a caller rather than for an announcement
+struct HashCheck {
+    storage: Arc<dyn StorageBackend>,
+    relative_path: PathBuf,
+    /// Hash only this many bytes from the start
+    prefix: Option<u64>,
+    done: Box<dyn FnOnce(io::Result<String>) + Send>,
+}
+
+enum Work {
+    /// Complete the pending announcement queued for a file
+    Announce(PathBuf),
+    Check(HashCheck),
+}
+
+/// Hashes large files off the watcher threads and the event loop
+/// A file changed again before its turn is only hashed once, for the latest
+/// announcement. Completed announcements are signed and sent like any other.
+#[derive(Clone)]
+pub struct HashPool {
+    queue: mpsc::Sender<Work>,
+    /// Latest pending announcement per queued file
+    jobs: Arc<Mutex<HashMap<PathBuf, HashJob>>>,
+}
+
+impl HashPool {
+    pub fn new(workers: usize, power: PowerMonitor) -> Self {
+        let (queue, rx) = mpsc::channel::<Work>();
+        let rx = Arc::new(Mutex::new(rx));
+        let jobs: Arc<Mutex<HashMap<PathBuf, HashJob>>> = Arc::new(Mutex::new(HashMap::new()));
+        for _ in 0..workers.max(1) {
+            let rx = rx.clone();
+            let jobs = jobs.clone();
+            let power = power.clone();
+            thread::spawn(move || loop {
+                let next = rx.lock().ok().and_then(|rx| rx.recv().ok());
+                match next {
+                    Some(Work::Announce(path)) => {
+                        let job = jobs.lock().ok().and_then(|mut jobs| jobs.remove(&path));
+                        if let Some(job) = job {
+                            complete(path, job, &power);
+                        }
+                    }
+                    Some(Work::Check(check)) => {
+                        let hash = match check.prefix {
+                            Some(len) => storage::prefix_hash(check.storage.as_ref(), &check.relative_path, len),
+                            None => check.storage.hash(&check.relative_path),
+                        };
+                        (check.done)(hash)
+                    }
+                    None => return,
+                }
+            });
+        }
+        Self { queue, jobs }
+    }
+
+    /// Queue `absolute_path` to be hashed, completing `msg` once done
+    pub fn submit(&self, absolute_path: PathBuf, msg: FileEventMessage, secret: Option<Secret>, tx: mpsc::Sender<String>) {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return;
+        };
+        let queued = jobs.insert(absolute_path.clone(), HashJob { msg, secret, tx }).is_some();
+        drop(jobs);
+        if !queued {
+            let _ = self.queue.send(Work::Announce(absolute_path));
+        }
+    }
+
+    /// Hash a stored file, handing the result to `done` on a worker thread
+    /// For the daemon's own checks, which would otherwise read whole files on the event loop.
+    pub fn check(
+        &self,
+        storage: Arc<dyn StorageBackend>,
+        relative_path: PathBuf,
+        done: impl FnOnce(io::Result<String>) + Send + 'static,
+    ) {
+        let _ = self.queue.send(Work::Check(HashCheck { storage, relative_path, prefix: None, done: Box::new(done) }));
+    }
+
+    /// Like `check`, hashing only the first `len` bytes
+    pub fn check_prefix(
+        &self,
+        storage: Arc<dyn StorageBackend>,
+        relative_path: PathBuf,
+        len: u64,
+        done: impl FnOnce(io::Result<String>) + Send + 'static,
+    ) {
+        let _ = self.queue.send(Work::Check(HashCheck { storage, relative_path, prefix: Some(len), done: Box::new(done) }));
+    }
+}
+
+fn complete(path: PathBuf, job: HashJob, power: &PowerMonitor) {
+    let HashJob { mut msg, secret, tx } = job;
+    let started = Instant::now();
+    let hash = match file_handler::calculate_file_hash(&path) {
+        Ok(hash) => hash,
+        Err(e) => {
+            // Deleted or replaced since; its own events cover that
+            debug!(path = %path.display(), error = %e, "Background hashing failed");
+            return;
+        }
+    };
+    // On low battery, idle for as long as hashing took to halve its CPU use
+    if power.is_constrained() {
+        thread::sleep(started.elapsed());
+    }
+    // Written to while hashing, so a later event will announce the final content
+    match file_handler::get_file_metadata(&path) {
+        Ok((size, modified_time)) if Some(size) == msg.size && Some(modified_time) == msg.modified_time => {}
+        Ok(_) => return,
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Failed to read metadata after hashing");
+            return;
+        }
+    }
+    msg.hash = Some(hash);
+    msg.details = Some("Hashed".to_string());
+    crate::core::observer::send_event(msg, &secret, &tx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::sync::Barrier;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use crate::core::models::EventType;
+    use crate::core::storage::PlainStorage;
+
+    fn pending(path: &Path, details: &str) -> FileEventMessage {
+        let (size, modified_time) = file_handler::get_file_metadata(path).unwrap();
+        FileEventMessage {
+            observer: "docs".to_string(),
+            event_type: EventType::Modify,
+            path: "big.bin".to_string(),
+            details: Some(details.to_string()),
+            hash: Some(pending_version(size, modified_time)),
+            size: Some(size),
+            modified_time: Some(modified_time),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        }
+    }
+
+    /// Hold the only worker of `pool` until the returned sender is dropped
+    fn occupy(pool: &HashPool, storage: &Arc<dyn StorageBackend>) -> mpsc::Sender<()> {
+        let (release, held) = mpsc::channel::<()>();
+        pool.check(storage.clone(), PathBuf::from("missing"), move |_| {
+            let _ = held.recv();
+        });
+        release
+    }
+
+    #[test]
+    fn test_checks_hash_stored_files_off_the_caller() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        storage.write_file(Path::new("restored/photo.jpg"), b"same bytes").unwrap();
+        let pool = HashPool::new(1, PowerMonitor::new(None));
+
+        let (tx, rx) = mpsc::channel();
+        for path in ["restored/photo.jpg", "missing.jpg"] {
+            let tx = tx.clone();
+            pool.check(storage.clone(), PathBuf::from(path), move |hash| {
+                let _ = tx.send((path, hash));
+            });
+        }
+        let (path, hash) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(path, "restored/photo.jpg");
+        assert_eq!(hash.unwrap(), storage.hash(Path::new("restored/photo.jpg")).unwrap());
+        let (path, hash) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(path, "missing.jpg");
+        assert_eq!(hash.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_workers_hash_in_parallel() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let pool = HashPool::new(2, PowerMonitor::new(None));
+
+        // Each check waits for the other, so both must be running at once
+        let barrier = Arc::new(Barrier::new(2));
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..2 {
+            let (barrier, tx) = (barrier.clone(), tx.clone());
+            pool.check(storage.clone(), PathBuf::from("missing"), move |_| {
+                barrier.wait();
+                let _ = tx.send(());
+            });
+        }
+        for _ in 0..2 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_a_file_changed_again_before_its_turn_is_announced_once() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let path = temp_dir.path().join("big.bin");
+        std::fs::write(&path, b"final content").unwrap();
+        let pool = HashPool::new(1, PowerMonitor::new(None));
+
+        let release = occupy(&pool, &storage);
+        let (tx, rx) = mpsc::channel();
+        pool.submit(path.clone(), pending(&path, "first"), None, tx.clone());
+        pool.submit(path.clone(), pending(&path, "second"), None, tx.clone());
+        drop(release);
+
+        let announced: FileEventMessage = serde_json::from_str(&rx.recv_timeout(Duration::from_secs(5)).unwrap()).unwrap();
+        assert_eq!(announced.hash, Some(file_handler::calculate_file_hash(&path).unwrap()));
+        assert_eq!(announced.details.as_deref(), Some("Hashed"));
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        // Written to after it was announced: a later event covers the new content
+        let mut stale = pending(&path, "stale");
+        stale.size = Some(1);
+        pool.submit(path.clone(), stale, None, tx);
+        assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn test_checks_complete_in_submission_order_on_one_worker() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        storage.write_file(Path::new("log.txt"), b"first line\nsecond line\n").unwrap();
+        let pool = HashPool::new(1, PowerMonitor::new(None));
+
+        let release = occupy(&pool, &storage);
+        let (tx, rx) = mpsc::channel();
+        for (n, prefix) in [(0, None), (1, Some(11)), (2, None), (3, Some(0))] {
+            let tx = tx.clone();
+            let done = move |hash: io::Result<String>| {
+                let _ = tx.send((n, hash.unwrap()));
+            };
+            match prefix {
+                Some(len) => pool.check_prefix(storage.clone(), PathBuf::from("log.txt"), len, done),
+                None => pool.check(storage.clone(), PathBuf::from("log.txt"), done),
+            }
+        }
+        drop(release);
+
+        let results: Vec<(usize, String)> = (0..4).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        assert_eq!(results.iter().map(|(n, _)| *n).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        let whole = storage.hash(Path::new("log.txt")).unwrap();
+        assert_eq!(results[0].1, whole);
+        assert_eq!(results[1].1, storage::prefix_hash(storage.as_ref(), Path::new("log.txt"), 11).unwrap());
+        assert_ne!(results[1].1, whole);
+        assert_eq!(results[3].1, storage::prefix_hash(storage.as_ref(), Path::new("log.txt"), 0).unwrap());
+    }
+}
@@ -0,0 +1,134 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+use crate::core::file_handler;
+
+/// Pauses before each further attempt to replace a file that is open or running
+/// Attempts are rescheduled by the network manager, so nothing waits in between.
+pub const RETRY_DELAYS: [Duration; 5] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(15),
+    Duration::from_secs(60),
+];
+
+/// What to do when a received file can't replace the local copy because it is in use
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InUsePolicy {
+    /// Retry for a while, then fail the transfer (default)
+    #[default]
+    Retry,
+    /// Fail the transfer straight away
+    Fail,
+    /// Retry for a while, then have Windows replace the file at the next reboot
+    Reboot,
+}
+
+/// What became of a received file meant to replace the local copy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Replaced {
+    Now,
+    /// The local copy is in use; the received file stays aside for another attempt
+    Later,
+    /// Windows moves the received file into place at the next reboot
+    AtReboot,
+}
+
+impl InUsePolicy {
+    pub fn from_config(value: Option<&str>) -> Result<Self, String> {
+        match value {
+            None | Some("retry") => Ok(Self::Retry),
+            Some("fail") => Ok(Self::Fail),
+            Some("reboot") => Ok(Self::Reboot),
+            Some(other) => Err(format!("Unknown in_use policy '{}' (expected retry, fail or reboot)", other)),
+        }
+    }
+}
+
+/// Whether an error means another process holds the file open or is running it
+pub fn is_in_use(error: &io::Error) -> bool {
+    if cfg!(windows) {
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+        matches!(error.raw_os_error(), Some(32 | 33))
+    } else {
+        // ETXTBSY: a running executable opened for writing
+        error.raw_os_error() == Some(26)
+    }
+}
+
+/// Move `staged` over `destination` once, handling a destination that is in use per `policy`
+/// Unless the policy fails straight away, `Later` leaves `staged` for the caller
+/// to try again; on the `last_attempt` it gives up, or with `Reboot` schedules the
+/// move, after which `staged` must stay where it is until the machine restarts.
+pub fn replace(staged: &Path, destination: &Path, policy: InUsePolicy, last_attempt: bool) -> io::Result<Replaced> {
+    let error = match file_handler::move_file(staged, destination) {
+        Ok(()) => return Ok(Replaced::Now),
+        Err(e) if is_in_use(&e) => e,
+        Err(e) => return Err(e),
+    };
+    match policy {
+        InUsePolicy::Retry | InUsePolicy::Reboot if !last_attempt => Ok(Replaced::Later),
+        InUsePolicy::Reboot => {
+            replace_on_reboot(staged, destination)?;
+            info!(path = %destination.display(), "File is in use, it will be replaced at the next reboot");
+            Ok(Replaced::AtReboot)
+        }
+        InUsePolicy::Retry | InUsePolicy::Fail => {
+            warn!(path = %destination.display(), error = %error, "File is in use, not replacing it");
+            Err(error)
+        }
+    }
+}
+
+/// Error for a received file left aside because the local copy is in use
+/// Callers tell it apart by its kind and try again with `replace` later.
+pub fn waiting(destination: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::ResourceBusy, format!("{} is in use, the received copy waits to replace it", destination.display()))
+}
+
+#[cfg(windows)]
+fn replace_on_reboot(staged: &Path, destination: &Path) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::{MoveFileExW, MOVEFILE_DELAY_UNTIL_REBOOT, MOVEFILE_REPLACE_EXISTING};
+
+    let wide = |path: &Path| path.as_os_str().encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+    let (from, to) = (wide(staged), wide(destination));
+    // SAFETY: both pointers are NUL-terminated wide strings that outlive the call
+    let moved = unsafe { MoveFileExW(from.as_ptr(), to.as_ptr(), MOVEFILE_DELAY_UNTIL_REBOOT | MOVEFILE_REPLACE_EXISTING) };
+    if moved == 0 {
+        // Usually missing administrator rights
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Only Windows keeps a list of renames to perform at boot
+#[cfg(not(windows))]
+fn replace_on_reboot(_staged: &Path, destination: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is in use and replacing files at reboot is only supported on Windows", destination.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_parsing_and_plain_replace() {
+        assert_eq!(InUsePolicy::from_config(None), Ok(InUsePolicy::Retry));
+        assert_eq!(InUsePolicy::from_config(Some("reboot")), Ok(InUsePolicy::Reboot));
+        assert!(InUsePolicy::from_config(Some("later")).is_err());
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (staged, destination) = (temp_dir.path().join("staged"), temp_dir.path().join("app.dll"));
+        std::fs::write(&staged, b"new").unwrap();
+        std::fs::write(&destination, b"old").unwrap();
+        assert_eq!(replace(&staged, &destination, InUsePolicy::Fail, false).unwrap(), Replaced::Now);
+        assert_eq!(std::fs::read(&destination).unwrap(), b"new");
+        assert!(!staged.exists());
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Name of the lock file inside the state directory
+pub const LOCK_FILE: &str = "daemon.lock";
+
+/// Exclusive hold on a state directory by one running daemon
+/// The OS drops the lock when the process exits, however it exits, so a lock
+/// file left behind by a crash never blocks the next start.
+pub struct InstanceLock {
+    file: File,
+    path: PathBuf,
+    /// PID found in the file: a previous daemon exited without releasing it
+    stale_pid: Option<u32>,
+}
+
+impl InstanceLock {
+    /// Lock `dir`, or return None while another daemon holds it
+    pub fn try_acquire(dir: &Path) -> io::Result<Option<Self>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        let mut previous = String::new();
+        file.read_to_string(&mut previous)?;
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(Some(Self { file, path, stale_pid: previous.trim().parse().ok() }))
+    }
+
+    /// Keep trying to lock `dir` until `timeout` passes, e.g. while the holder shuts down
+    pub fn wait(dir: &Path, timeout: Duration) -> io::Result<Option<Self>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(lock) = Self::try_acquire(dir)? {
+                return Ok(Some(lock));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// PID of the daemon holding the lock on `dir`, where the platform lets us read it
+    pub fn holder(dir: &Path) -> Option<u32> {
+        fs::read_to_string(dir.join(LOCK_FILE)).ok()?.trim().parse().ok()
+    }
+
+    /// PID of a daemon that held the directory before and did not shut down cleanly
+    pub fn stale_pid(&self) -> Option<u32> {
+        self.stale_pid
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    /// An empty file marks a clean shutdown; the lock itself goes with the file handle
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_is_refused_until_the_first_releases() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path().join("state");
+
+        let first = InstanceLock::try_acquire(&dir).unwrap().unwrap();
+        assert_eq!(first.stale_pid(), None);
+        assert!(InstanceLock::try_acquire(&dir).unwrap().is_none());
+        assert_eq!(InstanceLock::holder(&dir), Some(std::process::id()));
+        drop(first);
+        assert!(InstanceLock::holder(&dir).is_none());
+
+        // A PID left behind by a crash is reported, not obeyed
+        fs::write(dir.join(LOCK_FILE), "999999").unwrap();
+        let second = InstanceLock::wait(&dir, Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(second.stale_pid(), Some(999999));
+    }
+}
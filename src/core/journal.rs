@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::core::in_use::Replaced;
+use crate::core::storage::StorageBackend;
+
+/// Bytes the journal may grow to before it is truncated at the next quiet moment
+const COMPACT_BYTES: u64 = 1024 * 1024;
+
+/// A destructive step, recorded before it starts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Intent {
+    /// A received file written to the incoming area and renamed over the local copy
+    /// Rolled back after a crash: the incoming file, and any copy a rename across
+    /// filesystems left beside the local one, may be partial. The peer's tree
+    /// still differs, so reconciliation fetches it again.
+    Replace { observer: String, path: String },
+    /// A transaction's staged files renamed into place
+    /// Rolled forward after a crash: every member was complete when it started.
+    Commit { observer: String, transaction: String, paths: Vec<String> },
+    /// An aborted or rolled back transaction's staging area deleted
+    /// Rolled forward after a crash, as the deletion may have stopped halfway.
+    Discard { observer: String, transaction: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Begin { id: u64, intent: Intent },
+    End { id: u64 },
+}
+
+/// Write-ahead log of destructive file operations
+/// Each step is logged, and flushed to disk, before it touches a file and
+/// marked done afterwards, so the startup recovery pass knows exactly which
+/// steps a crash interrupted.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    next_id: u64,
+    /// Steps begun but not ended, including any a previous run left behind
+    open: BTreeMap<u64, Intent>,
+}
+
+pub type SharedJournal = Arc<Mutex<Journal>>;
+
+impl Journal {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut open = BTreeMap::new();
+        let mut next_id = 0;
+        match fs::read(path) {
+            Ok(contents) => {
+                // Drop a torn last record, so the next one starts on a line of its own
+                if !contents.is_empty() && !contents.ends_with(b"\n") {
+                    let keep = contents.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+                    OpenOptions::new().write(true).open(path)?.set_len(keep as u64)?;
+                }
+                for line in String::from_utf8_lossy(&contents).lines() {
+                    // The last line is torn if the crash hit while it was written
+                    match serde_json::from_str::<Record>(line) {
+                        Ok(Record::Begin { id, intent }) => {
+                            next_id = next_id.max(id + 1);
+                            open.insert(id, intent);
+                        }
+                        Ok(Record::End { id }) => {
+                            open.remove(&id);
+                        }
+                        Err(_) => warn!(path = %path.display(), "Skipping unreadable journal record"),
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { path: path.to_path_buf(), file, next_id, open })
+    }
+
+    /// Steps that were started but never finished, oldest first
+    pub fn unfinished(&self) -> Vec<(u64, Intent)> {
+        self.open.iter().map(|(id, intent)| (*id, intent.clone())).collect()
+    }
+
+    /// Record that `intent` is about to be carried out, returning its id for `end`
+    pub fn begin(&mut self, intent: Intent) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.append(&Record::Begin { id, intent: intent.clone() })?;
+        // The record must be on disk before the step touches any file
+        self.file.sync_data()?;
+        self.open.insert(id, intent);
+        Ok(id)
+    }
+
+    /// Record that a step is complete, or has been resolved by recovery
+    /// Not flushed: losing it only means recovery redoes a step that is safe to redo.
+    pub fn end(&mut self, id: u64) -> io::Result<()> {
+        self.append(&Record::End { id })?;
+        self.open.remove(&id);
+        if self.open.is_empty() && self.file.metadata()?.len() > COMPACT_BYTES {
+            self.file.set_len(0)?;
+        }
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_string(record).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// Storage that journals the writes of another backend
+pub struct JournaledStorage {
+    inner: Arc<dyn StorageBackend>,
+    observer: String,
+    journal: SharedJournal,
+}
+
+impl JournaledStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, observer: &str, journal: SharedJournal) -> Self {
+        Self { inner, observer: observer.to_string(), journal }
+    }
+
+    /// Journal `replace` as replacing the file at `relative_path`
+    fn replacing<T>(&self, relative_path: &Path, replace: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let intent = Intent::Replace {
+            observer: self.observer.clone(),
+            path: relative_path.to_string_lossy().into_owned(),
+        };
+        self.journaled(intent, replace)
+    }
+
+    /// Carry out `step` between the begin and end records of `intent`
+    fn journaled<T>(&self, intent: Intent, step: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+        let id = self.journal.lock().map_err(|_| io::Error::other("journal lock poisoned"))?.begin(intent)?;
+        let done = step();
+        // A failed step cleans up after itself, so it is finished either way
+        if let Ok(mut journal) = self.journal.lock() {
+            if let Err(e) = journal.end(id) {
+                warn!(path = %journal.path().display(), error = %e, "Failed to update journal");
+            }
+        }
+        done
+    }
+}
+
+impl StorageBackend for JournaledStorage {
+    fn exists(&self, relative_path: &Path) -> bool {
+        self.inner.exists(relative_path)
+    }
+
+    fn size(&self, relative_path: &Path) -> io::Result<u64> {
+        self.inner.size(relative_path)
+    }
+
+    fn modified_time(&self, relative_path: &Path) -> io::Result<u64> {
+        self.inner.modified_time(relative_path)
+    }
+
+    fn modified_nanos(&self, relative_path: &Path) -> io::Result<u128> {
+        self.inner.modified_nanos(relative_path)
+    }
+
+    fn hash(&self, relative_path: &Path) -> io::Result<String> {
+        self.inner.hash(relative_path)
+    }
+
+    fn read_chunk(&self, relative_path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        self.inner.read_chunk(relative_path, offset, len)
+    }
+
+    fn write_file(&self, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        self.replacing(relative_path, || self.inner.write_file(relative_path, content))
+    }
+
+    fn append_file(&self, relative_path: &Path, offset: u64, content: &[u8]) -> io::Result<PathBuf> {
+        // Nothing to roll back: the bytes already appended are the peer's, at the same offsets
+        self.inner.append_file(relative_path, offset, content)
+    }
+
+    fn write_staged(&self, transaction: &str, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        // Nothing outside the staging area changes until the commit, which is journaled
+        self.inner.write_staged(transaction, relative_path, content)
+    }
+
+    fn commit_staged(&self, transaction: &str, relative_path: &Path) -> io::Result<PathBuf> {
+        self.inner.commit_staged(transaction, relative_path)
+    }
+
+    fn discard_staged(&self, transaction: &str) -> io::Result<()> {
+        let intent = Intent::Discard { observer: self.observer.clone(), transaction: transaction.to_string() };
+        self.journaled(intent, || self.inner.discard_staged(transaction))
+    }
+
+    fn discard_incoming(&self, relative_path: &Path) -> io::Result<()> {
+        self.inner.discard_incoming(relative_path)
+    }
+
+    fn supports_partial(&self) -> bool {
+        self.inner.supports_partial()
+    }
+
+    fn write_partial(&self, relative_path: &Path, offset: u64, content: &[u8]) -> io::Result<()> {
+        // Only the incoming area is written until the commit, which is journaled
+        self.inner.write_partial(relative_path, offset, content)
+    }
+
+    fn commit_partial(&self, relative_path: &Path, hash: &str) -> io::Result<PathBuf> {
+        self.replacing(relative_path, || self.inner.commit_partial(relative_path, hash))
+    }
+
+    fn retry_replace(&self, relative_path: &Path, last_attempt: bool) -> io::Result<Replaced> {
+        self.replacing(relative_path, || self.inner.retry_replace(relative_path, last_attempt))
+    }
+
+    fn clean_orphans(&self) -> io::Result<usize> {
+        self.inner.clean_orphans()
+    }
+}
+
+/// Resolve the steps an interrupted run left unfinished, then clear out leftovers
+/// Returns the number of steps resolved and of orphaned files removed.
+pub fn recover(journal: &mut Journal, storages: &HashMap<String, Arc<dyn StorageBackend>>) -> (usize, usize) {
+    let unfinished = journal.unfinished();
+    for (id, intent) in &unfinished {
+        match intent {
+            Intent::Replace { observer, path } => {
+                if let Some(storage) = storages.get(observer) {
+                    if let Err(e) = storage.discard_incoming(Path::new(path)) {
+                        warn!(observer = %observer, path = %path, error = %e, "Failed to roll back interrupted write");
+                    }
+                }
+            }
+            Intent::Commit { observer, transaction, paths } => {
+                let Some(storage) = storages.get(observer) else {
+                    continue;
+                };
+                for path in paths {
+                    match storage.commit_staged(transaction, Path::new(path)) {
+                        Ok(_) => {}
+                        // Moved into place before the crash
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => warn!(observer = %observer, path = %path, transaction = %transaction, error = %e, "Failed to finish interrupted transaction"),
+                    }
+                }
+                if let Err(e) = storage.discard_staged(transaction) {
+                    warn!(observer = %observer, transaction = %transaction, error = %e, "Failed to clean up transaction staging");
+                }
+            }
+            Intent::Discard { observer, transaction } => {
+                if let Some(storage) = storages.get(observer) {
+                    if let Err(e) = storage.discard_staged(transaction) {
+                        warn!(observer = %observer, transaction = %transaction, error = %e, "Failed to finish interrupted transaction abort");
+                    }
+                }
+            }
+        }
+        if let Err(e) = journal.end(*id) {
+            warn!(path = %journal.path().display(), error = %e, "Failed to update journal");
+        }
+    }
+
+    // Transactions and downloads don't survive a restart, so whatever is left is orphaned
+    let mut removed = 0;
+    for (observer, storage) in storages {
+        match storage.clean_orphans() {
+            Ok(count) => removed += count,
+            Err(e) => warn!(observer = %observer, error = %e, "Failed to remove leftover files"),
+        }
+    }
+    (unfinished.len(), removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::storage::PlainStorage;
+
+    #[test]
+    fn test_interrupted_commit_is_rolled_forward_and_leftovers_removed() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("docs");
+        let journal_path = temp_dir.path().join("journal.log");
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(&root));
+        storage.write_staged("a1", Path::new("a.txt"), b"a").unwrap();
+        storage.write_staged("a1", Path::new("b.txt"), b"b").unwrap();
+        storage.write_staged("a2", Path::new("c.txt"), b"c").unwrap();
+
+        // Crash after the first of two renames
+        {
+            let mut journal = Journal::open(&journal_path).unwrap();
+            let paths = vec!["a.txt".to_string(), "b.txt".to_string()];
+            journal.begin(Intent::Commit { observer: "docs".to_string(), transaction: "a1".to_string(), paths }).unwrap();
+            storage.commit_staged("a1", Path::new("a.txt")).unwrap();
+            let done = journal.begin(Intent::Replace { observer: "docs".to_string(), path: "d.txt".to_string() }).unwrap();
+            journal.end(done).unwrap();
+        }
+        // A torn record from the crash is skipped
+        OpenOptions::new().append(true).open(&journal_path).unwrap().write_all(b"{\"op\":\"beg").unwrap();
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+        assert_eq!(journal.unfinished().len(), 1);
+        let storages = HashMap::from([("docs".to_string(), storage)]);
+        let (resolved, removed) = recover(&mut journal, &storages);
+        assert_eq!((resolved, removed), (1, 1));
+        assert_eq!(fs::read(root.join("b.txt")).unwrap(), b"b");
+        assert!(!root.join(".syndactyl/tmp/staging/a1").exists());
+        assert!(!root.join(".syndactyl/tmp/staging/a2").exists());
+        assert!(Journal::open(&journal_path).unwrap().unfinished().is_empty());
+    }
+
+    #[test]
+    fn test_journaled_storage_writes_downloads_aside_as_they_arrive() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("docs");
+        let journal: SharedJournal = Arc::new(Mutex::new(Journal::open(&temp_dir.path().join("journal.log")).unwrap()));
+        let storage = JournaledStorage::new(Arc::new(PlainStorage::new(&root)), "docs", journal.clone());
+        assert!(storage.supports_partial());
+
+        storage.write_partial(Path::new("a.txt"), 0, b"hello ").unwrap();
+        storage.write_partial(Path::new("a.txt"), 6, b"world").unwrap();
+        let hash = format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(b"hello world"));
+        storage.commit_partial(Path::new("a.txt"), &hash).unwrap();
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"hello world");
+        assert!(journal.lock().unwrap().unfinished().is_empty());
+    }
+
+    #[test]
+    fn test_interrupted_move_across_filesystems_is_rolled_back() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("docs");
+        let journal_path = temp_dir.path().join("journal.log");
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(&root));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), b"old").unwrap();
+
+        // Crash while the received file was being copied over from another filesystem
+        {
+            let mut journal = Journal::open(&journal_path).unwrap();
+            journal.begin(Intent::Replace { observer: "docs".to_string(), path: "a.txt".to_string() }).unwrap();
+            storage.write_partial(Path::new("a.txt"), 0, b"ne").unwrap();
+            fs::write(root.join(".a.txt.syndactyl-partial"), b"n").unwrap();
+        }
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+        let storages = HashMap::from([("docs".to_string(), storage)]);
+        assert_eq!(recover(&mut journal, &storages).0, 1);
+        assert_eq!(fs::read(root.join("a.txt")).unwrap(), b"old");
+        assert!(!root.join(".a.txt.syndactyl-partial").exists());
+        assert_eq!(fs::read_dir(root.join(".syndactyl/tmp/incoming")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_interrupted_transaction_abort_is_finished() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("docs");
+        let journal_path = temp_dir.path().join("journal.log");
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(&root));
+        storage.write_staged("b1", Path::new("a.txt"), b"a").unwrap();
+
+        let journal: SharedJournal = Arc::new(Mutex::new(Journal::open(&journal_path).unwrap()));
+        let journaled = JournaledStorage::new(storage.clone(), "docs", journal.clone());
+        journaled.discard_staged("b1").unwrap();
+        assert!(!root.join(".syndactyl/tmp/staging/b1").exists());
+        assert!(journal.lock().unwrap().unfinished().is_empty());
+        // An id that would escape the staging area is refused, and nothing is left open
+        assert!(journaled.discard_staged("../..").is_err());
+        assert!(journal.lock().unwrap().unfinished().is_empty());
+
+        // Crash partway through deleting the staging area
+        storage.write_staged("b2", Path::new("a.txt"), b"a").unwrap();
+        storage.write_staged("b2", Path::new("b.txt"), b"b").unwrap();
+        journal.lock().unwrap().begin(Intent::Discard { observer: "docs".to_string(), transaction: "b2".to_string() }).unwrap();
+        fs::remove_file(root.join(".syndactyl/tmp/staging/b2/a.txt")).unwrap();
+        drop((journaled, journal));
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+        assert_eq!(journal.unfinished(), vec![(2, Intent::Discard { observer: "docs".to_string(), transaction: "b2".to_string() })]);
+        let storages = HashMap::from([("docs".to_string(), storage)]);
+        assert_eq!(recover(&mut journal, &storages), (1, 0));
+        assert!(!root.join(".syndactyl/tmp/staging/b2").exists());
+    }
+}
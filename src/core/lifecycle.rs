@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+use crate::core::models::{EventType, FileEventMessage};
+
+/// Where an observer is in its life
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObserverState {
+    /// The watcher thread is starting
+    Initializing,
+    /// Files are being listed and compared with the index
+    Scanning,
+    /// Local changes are picked up as they happen
+    Watching,
+    /// Changes can't be watched for now, e.g. the directory is missing
+    Degraded,
+    /// Downloads are paused by request
+    Paused,
+    /// The watcher has given up; changes are no longer picked up
+    Stopped,
+}
+
+impl ObserverState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObserverState::Initializing => "initializing",
+            ObserverState::Scanning => "scanning",
+            ObserverState::Watching => "watching",
+            ObserverState::Degraded => "degraded",
+            ObserverState::Paused => "paused",
+            ObserverState::Stopped => "stopped",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Self::Initializing, Self::Scanning, Self::Watching, Self::Degraded, Self::Paused, Self::Stopped]
+            .into_iter()
+            .find(|state| state.as_str() == name)
+    }
+
+    /// Whether the lifecycle may move from this state to `next`
+    /// Nothing leaves Stopped, and nothing returns to Initializing.
+    pub fn can_become(self, next: ObserverState) -> bool {
+        use ObserverState::*;
+        match (self, next) {
+            (Stopped, _) | (_, Initializing) => false,
+            (current, next) => current != next,
+        }
+    }
+}
+
+impl fmt::Display for ObserverState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The message a watcher sends on entering `state`, e.g. "degraded: directory unavailable"
+pub fn message(observer: &str, state: ObserverState, reason: &str) -> FileEventMessage {
+    FileEventMessage {
+        observer: observer.to_string(),
+        event_type: EventType::Lifecycle,
+        path: String::new(),
+        details: Some(format!("{}: {}", state, reason)),
+        hash: None,
+        size: None,
+        modified_time: None,
+        hmac: None,
+        transaction: None,
+        observer_id: None,
+        append: None,
+        sequence: None,
+    }
+}
+
+/// The state and reason a lifecycle message reports
+pub fn parse(event: &FileEventMessage) -> Option<(ObserverState, String)> {
+    let details = event.details.as_deref()?;
+    let (state, reason) = details.split_once(": ").unwrap_or((details, ""));
+    Some((ObserverState::parse(state)?, reason.to_string()))
+}
+
+/// An observer's current state, for `syndactyl status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LifecycleStatus {
+    pub state: ObserverState,
+    /// Unix timestamp of the transition into `state`
+    pub since: u64,
+    pub reason: String,
+}
+
+/// A change of an observer's state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    pub observer: String,
+    pub from: ObserverState,
+    pub to: ObserverState,
+    pub reason: String,
+}
+
+impl Transition {
+    /// Log the change, as a warning when local changes are no longer picked up
+    pub fn log(&self) {
+        match self.to {
+            ObserverState::Degraded | ObserverState::Stopped => {
+                warn!(observer = %self.observer, from = %self.from, to = %self.to, reason = %self.reason, "Observer lifecycle changed");
+            }
+            _ => info!(observer = %self.observer, from = %self.from, to = %self.to, reason = %self.reason, "Observer lifecycle changed"),
+        }
+    }
+}
+
+struct Entry {
+    /// Last state the watcher reported
+    reported: ObserverState,
+    reason: String,
+    /// Rescans in progress that the manager started
+    scans: usize,
+    paused: bool,
+    status: LifecycleStatus,
+}
+
+/// Lifecycle of every observer, from what its watcher reports and what the manager does
+/// A pause or rescan overrides the watcher's own state until it ends, unless the
+/// watcher is degraded or stopped, which matters more.
+#[derive(Default)]
+pub struct Lifecycles {
+    observers: BTreeMap<String, Entry>,
+}
+
+impl Lifecycles {
+    /// Apply a state a watcher reported, ignoring transitions the state machine doesn't allow
+    /// The first report adds the observer; the others only change observers that have one.
+    pub fn report(&mut self, observer: &str, state: ObserverState, reason: &str, now: u64) -> Option<Transition> {
+        let entry = self.observers.entry(observer.to_string()).or_insert_with(|| Entry {
+            reported: ObserverState::Initializing,
+            reason: String::new(),
+            scans: 0,
+            paused: false,
+            status: LifecycleStatus { state: ObserverState::Initializing, since: now, reason: String::new() },
+        });
+        if state == ObserverState::Paused || !(entry.reported == state || entry.reported.can_become(state)) {
+            return None;
+        }
+        entry.reported = state;
+        entry.reason = reason.to_string();
+        settle(observer, entry, now)
+    }
+
+    /// Pause or resume an observer's downloads
+    pub fn set_paused(&mut self, observer: &str, paused: bool, now: u64) -> Option<Transition> {
+        let entry = self.observers.get_mut(observer)?;
+        entry.paused = paused;
+        settle(observer, entry, now)
+    }
+
+    /// Note that the manager started a rescan of the observer
+    pub fn scan_started(&mut self, observer: &str, now: u64) -> Option<Transition> {
+        let entry = self.observers.get_mut(observer)?;
+        entry.scans += 1;
+        settle(observer, entry, now)
+    }
+
+    pub fn scan_finished(&mut self, observer: &str, now: u64) -> Option<Transition> {
+        let entry = self.observers.get_mut(observer)?;
+        entry.scans = entry.scans.saturating_sub(1);
+        settle(observer, entry, now)
+    }
+
+    pub fn state(&self, observer: &str) -> Option<ObserverState> {
+        self.observers.get(observer).map(|entry| entry.status.state)
+    }
+
+    /// Every observer's current state
+    pub fn statuses(&self) -> BTreeMap<String, LifecycleStatus> {
+        self.observers.iter().map(|(name, entry)| (name.clone(), entry.status.clone())).collect()
+    }
+}
+
+/// Move an observer into the state its watcher, pause and rescans add up to
+fn settle(observer: &str, entry: &mut Entry, now: u64) -> Option<Transition> {
+    let (state, reason) = match entry.reported {
+        ObserverState::Stopped | ObserverState::Degraded => (entry.reported, entry.reason.clone()),
+        _ if entry.paused => (ObserverState::Paused, "paused by request".to_string()),
+        _ if entry.scans > 0 => (ObserverState::Scanning, "rescanning".to_string()),
+        reported => (reported, entry.reason.clone()),
+    };
+    let from = entry.status.state;
+    if !from.can_become(state) {
+        return None;
+    }
+    entry.status = LifecycleStatus { state, since: now, reason: reason.clone() };
+    Some(Transition { observer: observer.to_string(), from, to: state, reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observer_moves_through_its_lifecycle() {
+        let mut lifecycles = Lifecycles::default();
+        let event = message("docs", ObserverState::Degraded, "directory unavailable");
+        assert_eq!(parse(&event), Some((ObserverState::Degraded, "directory unavailable".to_string())));
+
+        assert_eq!(lifecycles.report("docs", ObserverState::Initializing, "starting", 1), None);
+        let watching = lifecycles.report("docs", ObserverState::Watching, "watching for changes", 2).unwrap();
+        assert_eq!((watching.from, watching.to), (ObserverState::Initializing, ObserverState::Watching));
+
+        // Pauses and rescans show over watching, and end where they started
+        assert_eq!(lifecycles.set_paused("docs", true, 3).unwrap().to, ObserverState::Paused);
+        assert_eq!(lifecycles.scan_started("docs", 4), None);
+        assert_eq!(lifecycles.set_paused("docs", false, 5).unwrap().to, ObserverState::Scanning);
+        assert_eq!(lifecycles.scan_finished("docs", 6).unwrap().to, ObserverState::Watching);
+        assert_eq!(lifecycles.statuses()["docs"].since, 6);
+
+        // A missing directory wins over a pause
+        lifecycles.set_paused("docs", true, 7);
+        assert_eq!(lifecycles.report("docs", ObserverState::Degraded, "directory unavailable", 8).unwrap().to, ObserverState::Degraded);
+        assert_eq!(lifecycles.report("docs", ObserverState::Watching, "directory returned", 9).unwrap().to, ObserverState::Paused);
+
+        // Nothing comes back from stopped
+        assert_eq!(lifecycles.report("docs", ObserverState::Stopped, "event source failed", 10).unwrap().to, ObserverState::Stopped);
+        assert_eq!(lifecycles.report("docs", ObserverState::Watching, "watching for changes", 11), None);
+        assert_eq!(lifecycles.set_paused("docs", false, 12), None);
+        assert_eq!(lifecycles.state("docs"), Some(ObserverState::Stopped));
+    }
+}
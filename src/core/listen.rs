@@ -0,0 +1,47 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+/// Resolve the listen address of a local service such as the file browser
+/// A bare port binds to localhost. Anything other machines could reach is
+/// refused unless the service is explicitly marked `public`, so a copied
+/// "0.0.0.0" doesn't expose it by accident. Only IP addresses are accepted;
+/// a hostname could resolve to a public interface without it being obvious.
+pub fn resolve(service: &str, listen: &str, public: bool) -> Result<SocketAddr, String> {
+    let listen = listen.trim();
+    let addr = match listen.parse::<u16>() {
+        Ok(port) => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port),
+        Err(_) => listen.parse::<SocketAddr>()
+            .map_err(|_| format!("{} listen address '{}' must be a port or IP:PORT, e.g. \"127.0.0.1:8384\"", service, listen))?,
+    };
+    if !addr.ip().is_loopback() && !public {
+        return Err(format!(
+            "{} would listen on {}, which other machines can reach; use \"127.0.0.1:{}\" or set \"public\": true",
+            service, addr, addr.port()
+        ));
+    }
+    Ok(addr)
+}
+
+/// Whether a configured listen address is reachable beyond this machine
+/// Addresses that don't parse count as local; `resolve` reports them.
+pub fn is_public(listen: &str) -> bool {
+    listen.trim().parse::<SocketAddr>().is_ok_and(|addr| !addr.ip().is_loopback())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_services_default_to_localhost_and_refuse_public_addresses() {
+        assert_eq!(resolve("metrics", "9464", false).unwrap(), "127.0.0.1:9464".parse().unwrap());
+        assert_eq!(resolve("metrics", "[::1]:9464", false).unwrap(), "[::1]:9464".parse().unwrap());
+        assert!(resolve("browser", "0.0.0.0:8384", false).is_err());
+        assert!(resolve("browser", "192.168.1.10:8384", false).is_err());
+        assert_eq!(resolve("browser", "0.0.0.0:8384", true).unwrap(), "0.0.0.0:8384".parse().unwrap());
+        assert!(resolve("browser", "localhost:8384", true).is_err());
+
+        assert!(is_public("0.0.0.0:8384"));
+        assert!(!is_public("127.0.0.1:8384"));
+        assert!(!is_public("8384"));
+    }
+}
@@ -0,0 +1,117 @@
+use std::io;
+use crate::core::config::ObserverConfig;
+use crate::core::merkle::FileIndex;
+use crate::core::models::{EventType, FileEventMessage};
+use crate::core::observer;
+
+/// File index of a daemon running with networking disabled
+/// Observer events are recorded as usual and reported as what would have been
+/// announced, so filters on a new observer can be checked before it joins the mesh.
+/// Starts from a copy of the persisted index that is never saved back: the real
+/// daemon still has to announce everything once it runs.
+pub struct LocalOnly {
+    index: FileIndex,
+    announced: usize,
+}
+
+impl LocalOnly {
+    pub fn new(index: FileIndex) -> Self {
+        Self { index, announced: 0 }
+    }
+
+    /// Compare an observer's files below `subtree` ("" for all of them) with the index,
+    /// returning a line per announcement the difference would make
+    pub fn scan(&mut self, observer: &ObserverConfig, subtree: &str) -> io::Result<Vec<String>> {
+        let known = self.index.under(&observer.name, subtree).into_iter().collect();
+        let mut messages = Vec::new();
+        observer::rescan_subtree(observer, subtree, &known, |msg| messages.push(msg))?;
+        Ok(messages.iter().filter_map(|msg| self.apply(msg)).collect())
+    }
+
+    /// Record an observer message, returning the announcement it would make
+    /// Rescan requests and watcher errors are never announced.
+    pub fn apply(&mut self, msg: &str) -> Option<String> {
+        let event: FileEventMessage = serde_json::from_str(msg).ok()?;
+        if !event.event_type.has_content() && event.event_type != EventType::Remove {
+            return None;
+        }
+        self.index.record(&event);
+        self.announced += 1;
+        Some(describe(&event))
+    }
+
+    /// Announcements that would have been made so far
+    pub fn announced(&self) -> usize {
+        self.announced
+    }
+
+    pub fn index(&self) -> &FileIndex {
+        &self.index
+    }
+}
+
+/// One line describing an announcement, e.g. for the console
+fn describe(event: &FileEventMessage) -> String {
+    match (&event.hash, event.size) {
+        (Some(hash), Some(size)) => format!(
+            "{} {}/{} ({} bytes, sha256 {})",
+            event.event_type,
+            event.observer,
+            event.path,
+            size,
+            &hash[..hash.len().min(12)]
+        ),
+        _ => format!("{} {}/{}", event.event_type, event.observer, event.path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_staged_observer_reports_what_it_would_announce() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), b"hello").unwrap();
+        fs::write(dir.path().join("b.txt.syndactyl-partial"), b"half").unwrap();
+        let observer = ObserverConfig {
+            name: "docs".to_string(),
+            path: dir.path().display().to_string(),
+            shared_secret: None,
+            at_rest_key: None,
+            transaction_window_ms: None,
+            schedule: None,
+            announce_only: None,
+            mount: None,
+            removable: None,
+            escape_names: None,
+            in_use: None,
+            peers: None,
+            distribution: None,
+            merge_extensions: None,
+            verify_writes: None,
+            temp_dir: None,
+            min_free_mb: None,
+            transfer_tuning: None,
+            pinned_keys: None,
+            compress_history: None,
+        };
+
+        // Internal files are filtered before anything is announced
+        let mut local = LocalOnly::new(FileIndex::default());
+        let lines = local.scan(&observer, "").unwrap();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("Modify docs/a.txt (5 bytes, sha256 "), "{}", lines[0]);
+        assert!(local.index().get("docs", "a.txt").is_some());
+
+        // Unchanged files aren't announced again, deleted ones are
+        assert!(local.scan(&observer, "").unwrap().is_empty());
+        fs::remove_file(dir.path().join("a.txt")).unwrap();
+        assert_eq!(local.scan(&observer, "").unwrap(), vec!["Remove docs/a.txt".to_string()]);
+        assert_eq!(local.announced(), 2);
+
+        assert_eq!(local.apply(r#"{"observer":"docs","event_type":"Rescan","path":""}"#), None);
+    }
+}
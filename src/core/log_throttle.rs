@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::info;
+use crate::core::config::LoggingConfig;
+
+/// Default aggregation window for throttled subsystems
+pub const DEFAULT_WINDOW_SECS: u64 = 10;
+
+/// Default number of individual log lines per subsystem per window
+pub const DEFAULT_MAX_PER_WINDOW: u32 = 20;
+
+/// Counts events for one subsystem and decides which ones get their own log line
+#[derive(Debug)]
+struct Throttle {
+    max_per_window: u32,
+    window_start: Instant,
+    count: u64,
+    suppressed: u64,
+}
+
+impl Throttle {
+    fn new(max_per_window: u32, now: Instant) -> Self {
+        Self {
+            max_per_window,
+            window_start: now,
+            count: 0,
+            suppressed: 0,
+        }
+    }
+
+    fn allow(&mut self) -> bool {
+        self.count += 1;
+        if self.count > self.max_per_window as u64 {
+            self.suppressed += 1;
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// Summary of one subsystem's activity over an elapsed window
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrottleSummary {
+    pub subsystem: String,
+    pub count: u64,
+    pub suppressed: u64,
+    pub window: Duration,
+}
+
+/// Per-subsystem log throttling with periodic aggregate summaries
+/// Once a subsystem exceeds its budget within a window, individual lines are
+/// suppressed and a single "N events in the last Xs" line is emitted instead.
+#[derive(Debug)]
+pub struct LogThrottle {
+    window: Duration,
+    default_max: u32,
+    overrides: HashMap<String, u32>,
+    throttles: HashMap<String, Throttle>,
+}
+
+impl LogThrottle {
+    pub fn new(config: &LoggingConfig) -> Self {
+        Self {
+            window: Duration::from_secs(config.window_secs.unwrap_or(DEFAULT_WINDOW_SECS)),
+            default_max: config.max_per_window.unwrap_or(DEFAULT_MAX_PER_WINDOW),
+            overrides: config.subsystems.clone(),
+            throttles: HashMap::new(),
+        }
+    }
+
+    /// Aggregation window length
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record an event for a subsystem, returning true if it should be logged individually
+    pub fn allow(&mut self, subsystem: &str) -> bool {
+        let max = self.overrides.get(subsystem).copied().unwrap_or(self.default_max);
+        self.throttles
+            .entry(subsystem.to_string())
+            .or_insert_with(|| Throttle::new(max, Instant::now()))
+            .allow()
+    }
+
+    /// Close every window that has elapsed, returning summaries for windows that suppressed lines
+    pub fn take_summaries(&mut self, now: Instant) -> Vec<ThrottleSummary> {
+        self.close_windows(now, false)
+    }
+
+    /// Close every window, elapsed or not, so lines suppressed just before exiting are still counted
+    pub fn take_all_summaries(&mut self, now: Instant) -> Vec<ThrottleSummary> {
+        self.close_windows(now, true)
+    }
+
+    /// When the earliest window that suppressed lines ends and its summary is due
+    pub fn next_summary_at(&self) -> Option<Instant> {
+        self.throttles.values()
+            .filter(|throttle| throttle.suppressed > 0)
+            .map(|throttle| throttle.window_start + self.window)
+            .min()
+    }
+
+    fn close_windows(&mut self, now: Instant, all: bool) -> Vec<ThrottleSummary> {
+        let mut summaries = Vec::new();
+        for (subsystem, throttle) in self.throttles.iter_mut() {
+            let elapsed = now.saturating_duration_since(throttle.window_start);
+            if elapsed < self.window && !all {
+                continue;
+            }
+            if throttle.suppressed > 0 {
+                summaries.push(ThrottleSummary {
+                    subsystem: subsystem.clone(),
+                    count: throttle.count,
+                    suppressed: throttle.suppressed,
+                    window: elapsed,
+                });
+            }
+            *throttle = Throttle::new(throttle.max_per_window, now);
+        }
+        summaries.sort_by(|a, b| a.subsystem.cmp(&b.subsystem));
+        summaries
+    }
+
+    /// Emit aggregate log lines for all elapsed windows
+    pub fn flush(&mut self) {
+        log_summaries(self.take_summaries(Instant::now()));
+    }
+
+    /// Emit aggregate log lines for every window, on shutdown
+    pub fn flush_all(&mut self) {
+        log_summaries(self.take_all_summaries(Instant::now()));
+    }
+}
+
+fn log_summaries(summaries: Vec<ThrottleSummary>) {
+    for summary in summaries {
+        info!(
+            subsystem = %summary.subsystem,
+            count = summary.count,
+            suppressed = summary.suppressed,
+            "{}: {} events in the last {}s ({} not logged individually)",
+            summary.subsystem,
+            summary.count,
+            summary.window.as_secs(),
+            summary.suppressed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_suppresses_after_budget() {
+        let config = LoggingConfig {
+            max_per_window: Some(2),
+            subsystems: HashMap::from([("serve".to_string(), 1)]),
+            ..Default::default()
+        };
+        let mut throttle = LogThrottle::new(&config);
+
+        assert!(throttle.allow("transfer"));
+        assert!(throttle.allow("transfer"));
+        assert!(!throttle.allow("transfer"));
+
+        assert!(throttle.allow("serve"));
+        assert!(!throttle.allow("serve"));
+    }
+
+    #[test]
+    fn test_throttle_summaries_reset_window() {
+        let config = LoggingConfig {
+            max_per_window: Some(1),
+            ..Default::default()
+        };
+        let mut throttle = LogThrottle::new(&config);
+
+        for _ in 0..5 {
+            throttle.allow("transfer");
+        }
+
+        // Window has not elapsed yet
+        assert!(throttle.take_summaries(Instant::now()).is_empty());
+
+        let later = Instant::now() + throttle.window();
+        let summaries = throttle.take_summaries(later);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].count, 5);
+        assert_eq!(summaries[0].suppressed, 4);
+
+        // New window starts with a fresh budget
+        assert!(throttle.allow("transfer"));
+    }
+
+    #[test]
+    fn test_unfinished_windows_are_summarized_on_exit() {
+        let config = LoggingConfig {
+            max_per_window: Some(1),
+            ..Default::default()
+        };
+        let mut throttle = LogThrottle::new(&config);
+        assert_eq!(throttle.next_summary_at(), None);
+
+        throttle.allow("observer");
+        // Nothing suppressed yet, so there is nothing to summarize
+        assert_eq!(throttle.next_summary_at(), None);
+        throttle.allow("observer");
+        let due = throttle.next_summary_at().unwrap();
+        assert!(due > Instant::now());
+
+        let summaries = throttle.take_all_summaries(Instant::now());
+        assert_eq!(summaries.len(), 1);
+        assert_eq!((summaries[0].count, summaries[0].suppressed), (2, 1));
+        assert_eq!(throttle.next_summary_at(), None);
+    }
+}
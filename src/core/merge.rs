@@ -0,0 +1,166 @@
+use std::path::Path;
+use tracing::{info, warn};
+use crate::core::conflict::{Conflict, ConflictResolver, Resolution};
+
+/// Largest table of line comparisons one diff may use
+/// Common leading and trailing lines are skipped first, so this only bounds
+/// the region one side changed.
+const MAX_DIFF_CELLS: usize = 4 * 1024 * 1024;
+
+/// Three-way merge of UTF-8 text files with the configured extensions
+/// Falls back to the received copy, as without a resolver, when there is no
+/// common version or both sides changed the same lines.
+pub struct ThreeWayMerge {
+    extensions: Vec<String>,
+}
+
+impl ThreeWayMerge {
+    pub fn new(extensions: Vec<String>) -> Self {
+        Self { extensions }
+    }
+
+    fn handles(&self, path: &str) -> bool {
+        Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.iter().any(|handled| handled.trim_start_matches('.').eq_ignore_ascii_case(ext)))
+    }
+}
+
+impl ConflictResolver for ThreeWayMerge {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        if !self.handles(conflict.path) {
+            return Resolution::TakeIncoming;
+        }
+        let Some(base) = conflict.base else {
+            return Resolution::TakeIncoming;
+        };
+        let (Ok(base), Ok(local), Ok(incoming)) = (
+            std::str::from_utf8(base),
+            std::str::from_utf8(conflict.local),
+            std::str::from_utf8(conflict.incoming),
+        ) else {
+            return Resolution::TakeIncoming;
+        };
+        match merge3(base, local, incoming) {
+            Some(merged) => {
+                info!(observer = %conflict.observer, path = %conflict.path, "Merged local and received changes");
+                Resolution::Merged(merged.into_bytes())
+            }
+            None => {
+                warn!(observer = %conflict.observer, path = %conflict.path, "Both sides changed the same lines, received copy wins");
+                Resolution::TakeIncoming
+            }
+        }
+    }
+}
+
+/// Combine the changes `local` and `incoming` each made to `base`, diff3-style
+/// Returns None when both changed the same lines differently, or the changes
+/// are too large to diff.
+pub fn merge3(base: &str, local: &str, incoming: &str) -> Option<String> {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let local: Vec<&str> = local.split_inclusive('\n').collect();
+    let incoming: Vec<&str> = incoming.split_inclusive('\n').collect();
+    let to_local = line_map(base.len(), &common_lines(&base, &local)?);
+    let to_incoming = line_map(base.len(), &common_lines(&base, &incoming)?);
+
+    // Walk from one base line both sides kept to the next, merging what lies between
+    let mut merged = String::new();
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        let stable = (o..base.len()).find_map(|line| Some((line, to_local[line]?, to_incoming[line]?)));
+        let (so, sa, sb) = stable.unwrap_or((base.len(), local.len(), incoming.len()));
+        let (original, ours, theirs) = (&base[o..so], &local[a..sa], &incoming[b..sb]);
+        let chosen = if ours == original {
+            theirs
+        } else if theirs == original || ours == theirs {
+            ours
+        } else {
+            return None;
+        };
+        merged.extend(chosen.iter().copied());
+        if stable.is_none() {
+            return Some(merged);
+        }
+        merged.push_str(base[so]);
+        (o, a, b) = (so + 1, sa + 1, sb + 1);
+    }
+}
+
+fn line_map(len: usize, pairs: &[(usize, usize)]) -> Vec<Option<usize>> {
+    let mut map = vec![None; len];
+    for &(from, to) in pairs {
+        map[from] = Some(to);
+    }
+    map
+}
+
+/// Index pairs of a longest common subsequence of lines
+fn common_lines(base: &[&str], other: &[&str]) -> Option<Vec<(usize, usize)>> {
+    let prefix = base.iter().zip(other).take_while(|(x, y)| x == y).count();
+    let suffix = base[prefix..].iter().rev()
+        .zip(other[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (n, m) = (base.len() - prefix - suffix, other.len() - prefix - suffix);
+    if (n + 1).saturating_mul(m + 1) > MAX_DIFF_CELLS {
+        return None;
+    }
+    let (base_mid, other_mid) = (&base[prefix..prefix + n], &other[prefix..prefix + m]);
+
+    // lengths[i * width + j]: LCS length of base_mid[i..] and other_mid[j..]
+    let width = m + 1;
+    let mut lengths = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * width + j] = if base_mid[i] == other_mid[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|line| (line, line)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if base_mid[i] == other_mid[j] {
+            pairs.push((prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs.extend((0..suffix).map(|line| (prefix + n + line, prefix + m + line)));
+    Some(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge3_combines_separate_changes_and_refuses_overlaps() {
+        let base = "title\none\ntwo\nthree\nfour\n";
+        let local = "title\nONE\ntwo\nthree\nfour\n";
+        let incoming = "title\none\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(merge3(base, local, incoming).as_deref(), Some("title\nONE\ntwo\nthree\nfour\nfive\n"));
+        assert_eq!(merge3(base, incoming, local).as_deref(), Some("title\nONE\ntwo\nthree\nfour\nfive\n"));
+
+        // The same edit on both sides is not a conflict
+        assert_eq!(merge3(base, local, local).as_deref(), Some(local));
+        // Removed on one side, untouched on the other
+        assert_eq!(merge3(base, "title\none\nthree\nfour\n", incoming).as_deref(), Some("title\none\nthree\nfour\nfive\n"));
+
+        assert_eq!(merge3(base, local, "title\nuno\ntwo\nthree\nfour\n"), None);
+
+        let resolver = ThreeWayMerge::new(vec!["md".to_string()]);
+        let conflict = Conflict { observer: "docs", path: "a.md", base: Some(base.as_bytes()), local: local.as_bytes(), incoming: incoming.as_bytes() };
+        assert_eq!(resolver.resolve(&conflict), Resolution::Merged(b"title\nONE\ntwo\nthree\nfour\nfive\n".to_vec()));
+        let unrelated = Conflict { path: "a.bin", ..conflict };
+        assert_eq!(resolver.resolve(&unrelated), Resolution::TakeIncoming);
+    }
+}
@@ -0,0 +1,324 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use crate::core::auth;
+use crate::core::models::{EventType, FileEventMessage, TombstoneEntry, TreeEntry};
+
+/// Most entries served in one page of a directory listing
+/// Keeps a listing well inside the response size limit whatever the names.
+pub const PAGE_ENTRIES: usize = 4096;
+
+/// What the index knows about one local file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexedFile {
+    pub hash: String,
+    pub size: u64,
+    pub modified_time: u64,
+}
+
+/// Hashes of local files per observer, kept up to date from local announcements
+/// Persisted so the tree is known straight after a restart, without rehashing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FileIndex {
+    observers: BTreeMap<String, BTreeMap<String, IndexedFile>>,
+}
+
+impl FileIndex {
+    /// Apply a local announcement, returning true if the index changed
+    pub fn record(&mut self, event: &FileEventMessage) -> bool {
+        match event.event_type {
+            event_type if event_type.has_content() => {
+                let (Some(hash), Some(size), Some(modified_time)) = (&event.hash, event.size, event.modified_time) else {
+                    return false;
+                };
+                let file = IndexedFile { hash: hash.clone(), size, modified_time };
+                let files = self.observers.entry(event.observer.clone()).or_default();
+                files.insert(event.path.clone(), file.clone()) != Some(file)
+            }
+            EventType::Remove => {
+                let Some(files) = self.observers.get_mut(&event.observer) else {
+                    return false;
+                };
+                // A removed directory takes everything below it along
+                let prefix = format!("{}/", event.path);
+                let before = files.len();
+                files.retain(|path, _| path != &event.path && !path.starts_with(&prefix));
+                files.len() != before
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget an observer's files, e.g. before a rescan announces them again
+    pub fn clear(&mut self, observer: &str) {
+        self.observers.remove(observer);
+    }
+
+    pub fn get(&self, observer: &str, path: &str) -> Option<&IndexedFile> {
+        self.observers.get(observer)?.get(path)
+    }
+
+    pub fn file_count(&self, observer: &str) -> usize {
+        self.observers.get(observer).map_or(0, BTreeMap::len)
+    }
+
+    /// Every file of an observer, by relative path
+    pub fn files(&self, observer: &str) -> impl Iterator<Item = (&String, &IndexedFile)> {
+        self.observers.get(observer).into_iter().flatten()
+    }
+
+    /// The file at `path`, or every file below it if it is a directory ("" is the root)
+    pub fn under(&self, observer: &str, path: &str) -> Vec<(String, IndexedFile)> {
+        let prefix = format!("{}/", path);
+        self.observers.get(observer).into_iter()
+            .flatten()
+            .filter(|(file, _)| path.is_empty() || *file == path || file.starts_with(&prefix))
+            .map(|(file, indexed)| (file.clone(), indexed.clone()))
+            .collect()
+    }
+
+    pub fn tree(&self, observer: &str) -> MerkleTree {
+        MerkleTree::build(self.observers.get(observer).unwrap_or(&BTreeMap::new()))
+    }
+}
+
+/// Hash tree over an observer's directory structure
+/// Each directory hashes the names, kinds and hashes of its children, so two
+/// trees with equal roots hold the same content and a differing root can be
+/// narrowed down one directory at a time.
+pub struct MerkleTree {
+    /// Children of every directory, by relative path ("" is the root)
+    nodes: HashMap<String, Vec<TreeEntry>>,
+    root: String,
+    file_count: usize,
+}
+
+impl MerkleTree {
+    pub fn build(files: &BTreeMap<String, IndexedFile>) -> Self {
+        let mut children: BTreeMap<String, BTreeMap<String, TreeEntry>> = BTreeMap::new();
+        children.insert(String::new(), BTreeMap::new());
+        for (path, file) in files {
+            let (dir, name) = split(path);
+            children.entry(dir.to_string()).or_default().insert(name.to_string(), TreeEntry {
+                name: name.to_string(),
+                hash: file.hash.clone(),
+                is_dir: false,
+                size: Some(file.size),
+                modified_time: Some(file.modified_time),
+            });
+            let mut ancestor = dir;
+            while !ancestor.is_empty() {
+                ancestor = split(ancestor).0;
+                children.entry(ancestor.to_string()).or_default();
+            }
+        }
+
+        // Deepest directories first, so every child is hashed before its parent
+        let mut dirs: Vec<String> = children.keys().cloned().collect();
+        dirs.sort_by_key(|dir| Reverse(depth(dir)));
+        let mut nodes = HashMap::new();
+        let mut root = node_hash(&[]);
+        for dir in dirs {
+            let entries: Vec<TreeEntry> = children.remove(&dir).unwrap_or_default().into_values().collect();
+            let hash = node_hash(&entries);
+            if dir.is_empty() {
+                root = hash;
+            } else {
+                let (parent, name) = split(&dir);
+                if let Some(siblings) = children.get_mut(parent) {
+                    siblings.insert(name.to_string(), TreeEntry {
+                        name: name.to_string(),
+                        hash,
+                        is_dir: true,
+                        size: None,
+                        modified_time: None,
+                    });
+                }
+            }
+            nodes.insert(dir, entries);
+        }
+        Self { nodes, root, file_count: files.len() }
+    }
+
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.file_count
+    }
+
+    pub fn has_dir(&self, dir: &str) -> bool {
+        self.nodes.contains_key(dir)
+    }
+
+    /// Children of `dir`, in name order, empty if it is not in the tree
+    pub fn entries(&self, dir: &str) -> &[TreeEntry] {
+        self.nodes.get(dir).map_or(&[], |entries| entries.as_slice())
+    }
+
+    /// Up to `limit` children of `dir` named after `after`, and the name to continue after
+    /// Continuing by name rather than position stays correct if the tree changes between pages.
+    pub fn page(&self, dir: &str, after: Option<&str>, limit: usize) -> (&[TreeEntry], Option<String>) {
+        let entries = self.entries(dir);
+        let start = after.map_or(0, |after| entries.partition_point(|entry| entry.name.as_str() <= after));
+        let end = entries.len().min(start + limit);
+        let next = (end < entries.len()).then(|| entries[end - 1].name.clone());
+        (&entries[start..end], next)
+    }
+}
+
+/// Remote entries that are missing locally or hash differently
+pub fn differing<'a>(local: &[TreeEntry], remote: &'a [TreeEntry]) -> Vec<&'a TreeEntry> {
+    let local: HashMap<&str, &TreeEntry> = local.iter().map(|entry| (entry.name.as_str(), entry)).collect();
+    remote.iter()
+        .filter(|entry| local.get(entry.name.as_str()).is_none_or(|local| local.hash != entry.hash || local.is_dir != entry.is_dir))
+        .collect()
+}
+
+/// Relative path of `name` inside `dir`
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Tag proving a heartbeat's root and file count come from a holder of the observer's secret
+pub fn sign_root(observer: &str, root: &str, file_count: u64, secret: &str) -> String {
+    auth::sign_bytes(format!("syndactyl-root||{}||{}||{}", observer, root, file_count).as_bytes(), secret)
+}
+
+/// Tag proving a tree request comes from a holder of the observer's secret
+/// Bound to the requesting peer, so it can't be replayed by another one.
+pub fn sign_node_request(observer: &str, dir: &str, requester: &str, secret: &str) -> String {
+    auth::sign_bytes(format!("syndactyl-tree-request||{}||{}||{}", observer, dir, requester).as_bytes(), secret)
+}
+
+/// Tag over a served directory listing and the tombstones served with it
+pub fn sign_node(observer: &str, dir: &str, entries: &[TreeEntry], tombstones: &[TombstoneEntry], next: Option<&str>, secret: &str) -> String {
+    let mut data = format!("syndactyl-tree||{}||{}", observer, dir);
+    for entry in entries {
+        data.push_str(&format!(
+            "||{}|{}|{}|{}|{}",
+            entry.name,
+            entry.is_dir,
+            entry.hash,
+            entry.size.map(|size| size.to_string()).unwrap_or_default(),
+            entry.modified_time.map(|time| time.to_string()).unwrap_or_default(),
+        ));
+    }
+    // Listings without tombstones keep the tag peers from before tombstones compute
+    for tombstone in tombstones {
+        let version: Vec<String> = tombstone.version.iter().map(|(node, count)| format!("{}={}", node, count)).collect();
+        data.push_str(&format!("||deleted|{}|{}|{}|{}", tombstone.path, tombstone.hash, tombstone.deleted_at, version.join(",")));
+    }
+    // Likewise for complete listings, so a page can't pass for the whole directory
+    if let Some(next) = next {
+        data.push_str(&format!("||next|{}", next));
+    }
+    auth::sign_bytes(data.as_bytes(), secret)
+}
+
+/// Directory and file name of a relative path
+fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+fn depth(dir: &str) -> usize {
+    if dir.is_empty() { 0 } else { dir.matches('/').count() + 1 }
+}
+
+/// Modification times are left out, so peers agree on trees with the same content
+fn node_hash(entries: &[TreeEntry]) -> String {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        hasher.update(entry.name.as_bytes());
+        hasher.update(if entry.is_dir { b"\0d\0" } else { b"\0f\0" });
+        hasher.update(entry.hash.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: EventType, path: &str, hash: &str) -> FileEventMessage {
+        FileEventMessage {
+            observer: "docs".to_string(),
+            event_type,
+            path: path.to_string(),
+            details: None,
+            hash: Some(hash.to_string()),
+            size: Some(1),
+            modified_time: Some(1),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_differences_are_found_by_descending_changed_directories() {
+        let mut local = FileIndex::default();
+        for path in ["readme.md", "src/main.rs", "src/net/p2p.rs", "assets/logo.png"] {
+            assert!(local.record(&event(EventType::Create, path, "a")));
+        }
+        let mut remote = local.clone();
+        assert!(!remote.record(&event(EventType::Modify, "src/main.rs", "a")));
+        assert_eq!(local.tree("docs").root(), remote.tree("docs").root());
+        assert_eq!(local.tree("docs").file_count(), 4);
+
+        remote.record(&event(EventType::Modify, "src/net/p2p.rs", "b"));
+        let (local_tree, remote_tree) = (local.tree("docs"), remote.tree("docs"));
+        assert_ne!(local_tree.root(), remote_tree.root());
+
+        // Only the changed branch differs at each level
+        let top = differing(local_tree.entries(""), remote_tree.entries(""));
+        assert_eq!(top.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["src"]);
+        let src = differing(local_tree.entries("src"), remote_tree.entries("src"));
+        assert_eq!(src.len(), 1);
+        assert!(src[0].is_dir);
+        let net = differing(local_tree.entries("src/net"), remote_tree.entries("src/net"));
+        assert_eq!((net[0].name.as_str(), net[0].hash.as_str()), ("p2p.rs", "b"));
+
+        // Removing a directory drops everything below it
+        assert!(remote.record(&event(EventType::Remove, "src", "")));
+        assert!(remote.get("docs", "src/net/p2p.rs").is_none());
+        assert_eq!(remote.tree("docs").entries("").len(), 2);
+        assert_eq!(join("src/net", "p2p.rs"), "src/net/p2p.rs");
+    }
+
+    #[test]
+    fn test_large_directories_are_listed_in_pages() {
+        let mut index = FileIndex::default();
+        for i in 0..10 {
+            index.record(&event(EventType::Create, &format!("big/{:02}.txt", i), "a"));
+        }
+        let tree = index.tree("docs");
+        let mut names = Vec::new();
+        let mut after = None;
+        loop {
+            let (page, next) = tree.page("big", after.as_deref(), 4);
+            assert!(page.len() <= 4);
+            names.extend(page.iter().map(|entry| entry.name.clone()));
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        let all: Vec<String> = tree.entries("big").iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, all);
+
+        // A page stays valid after the entry it continues from is removed
+        let (rest, next) = tree.page("big", Some("03.txt.gone"), 100);
+        assert_eq!((rest.len(), next), (6, None));
+        assert_ne!(sign_node("docs", "big", &[], &[], None, "s"), sign_node("docs", "big", &[], &[], Some("03.txt"), "s"));
+    }
+}
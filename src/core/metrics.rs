@@ -0,0 +1,179 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+use crate::core::bandwidth::BandwidthStats;
+
+/// Builder for metrics in the Prometheus text exposition format
+/// The daemon writes the result to `metrics_file`, which node_exporter's
+/// textfile collector (or anything else that reads the format) can pick up.
+#[derive(Default)]
+pub struct Metrics {
+    out: String,
+    /// TYPE and HELP of the current metric family
+    family: (String, String),
+    samples: Vec<Sample>,
+}
+
+/// One sample as added, for exporters that don't read the text format
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    /// "counter" or "gauge", from the family's TYPE
+    pub kind: String,
+    pub help: String,
+    pub labels: Vec<(String, String)>,
+    pub value: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a metric family with its HELP and TYPE lines
+    pub fn describe(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+        self.family = (kind.to_string(), help.to_string());
+    }
+
+    /// Add a sample to the current metric family
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.out, " {}", value);
+        self.samples.push(Sample {
+            name: name.to_string(),
+            kind: self.family.0.clone(),
+            help: self.family.1.clone(),
+            labels: labels.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            value,
+        });
+    }
+
+    /// Add per-observer and per-peer byte counters
+    pub fn bandwidth(&mut self, stats: &BandwidthStats) {
+        for (metric, label, counters) in [
+            ("syndactyl_observer_bytes_total", "observer", &stats.observers),
+            ("syndactyl_peer_bytes_total", "peer", &stats.peers),
+        ] {
+            self.describe(metric, "counter", &format!("File content bytes transferred, by {} and direction", label));
+            for (name, counter) in counters {
+                self.sample(metric, &[(label, name), ("direction", "sent")], counter.sent);
+                self.sample(metric, &[(label, name), ("direction", "received")], counter.received);
+            }
+        }
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn render(self) -> String {
+        self.out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Atomically replace the metrics file so readers never see a partial write
+pub fn write_textfile(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Largest request head read before answering; scrapes carry no body
+const MAX_REQUEST_HEAD: usize = 4 * 1024;
+
+/// How long one scrape may take from connecting to the last byte of the answer
+/// A client that sends its request slowly, or not at all, is dropped after it.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the latest rendered metrics at `GET /metrics`
+/// `page` is replaced by the daemon whenever it refreshes the metrics.
+pub async fn serve_http(addr: SocketAddr, page: Arc<RwLock<String>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(listen = %addr, "Metrics endpoint listening");
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let page = page.clone();
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(SCRAPE_TIMEOUT, answer_scrape(stream, &page)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => debug!(%peer, error = %e, "Metrics connection ended"),
+                            Err(_) => debug!(%peer, "Metrics client too slow, closing the connection"),
+                        }
+                    });
+                }
+                Err(e) => warn!(error = %e, "Failed to accept metrics connection"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn answer_scrape(mut stream: TcpStream, page: &RwLock<String>) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    let request_line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+    let (status, body) = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", page.read().map(|page| page.clone()).unwrap_or_default()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bandwidth_metrics_format() {
+        let mut stats = BandwidthStats::default();
+        stats.record_sent("my \"docs\"", "peer-a", 10);
+        stats.record_received("my \"docs\"", "peer-a", 5);
+
+        let mut metrics = Metrics::new();
+        metrics.bandwidth(&stats);
+        let sent = &metrics.samples()[0];
+        assert_eq!((sent.kind.as_str(), sent.value), ("counter", 10));
+        assert_eq!(sent.labels, vec![("observer".to_string(), "my \"docs\"".to_string()), ("direction".to_string(), "sent".to_string())]);
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE syndactyl_observer_bytes_total counter\n"));
+        assert!(text.contains("syndactyl_observer_bytes_total{observer=\"my \\\"docs\\\"\",direction=\"sent\"} 10\n"));
+        assert!(text.contains("syndactyl_peer_bytes_total{peer=\"peer-a\",direction=\"received\"} 5\n"));
+    }
+}
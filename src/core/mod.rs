@@ -1,5 +1,48 @@
 pub mod observer;
 pub mod config;
+pub mod config_migration;
 pub mod models;
 pub mod file_handler;
 pub mod auth;
+pub mod audit;
+pub mod storage;
+pub mod log_throttle;
+pub mod transaction;
+pub mod state;
+pub mod bandwidth;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod schedule;
+pub mod power;
+pub mod event_source;
+pub mod catalog;
+pub mod profile;
+pub mod deletion_guard;
+pub mod path_encoding;
+pub mod sync_id;
+pub mod hash_pool;
+pub mod secret_guard;
+pub mod in_use;
+pub mod merkle;
+pub mod tenant;
+pub mod journal;
+pub mod sync_groups;
+pub mod conflict;
+pub mod version_store;
+pub mod compression;
+pub mod merge;
+pub mod tombstone;
+pub mod setup;
+pub mod instance_lock;
+pub mod auth_failures;
+pub mod seen_events;
+pub mod listen;
+pub mod removed_observers;
+pub mod disk_space;
+pub mod address_book;
+pub mod event_order;
+pub mod local_only;
+pub mod secret;
+pub mod lifecycle;
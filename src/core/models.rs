@@ -1,9 +1,75 @@
+use std::fmt;
 use serde::{Serialize, Deserialize};
+use crate::core::tombstone::VersionVector;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// What happened to a file, as announced in a FileEventMessage
+/// Variants serialize as the bare names older peers send ("Create", "Modify", ...),
+/// so messages and their HMACs are unchanged; Metadata and Rename go out as
+/// Modify, with notify's kind in `details`. Names this build doesn't know
+/// deserialize as `Other`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    Create,
+    Modify,
+    /// Only permissions, ownership or timestamps changed
+    #[serde(rename(serialize = "Modify"))]
+    Metadata,
+    /// Moved or renamed, announced for the new path
+    #[serde(rename(serialize = "Modify"))]
+    Rename,
+    Remove,
+    /// The watcher dropped events below `path`; never announced to peers
+    Rescan,
+    /// An observer changed state; never announced to peers
+    Lifecycle,
+    /// The watcher failed; never announced to peers
+    Error,
+    /// A change notify couldn't classify
+    Any,
+    #[serde(other)]
+    Other,
+}
+
+impl EventType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Create => "Create",
+            Self::Modify => "Modify",
+            Self::Metadata => "Metadata",
+            Self::Rename => "Rename",
+            Self::Remove => "Remove",
+            Self::Rescan => "Rescan",
+            Self::Lifecycle => "Lifecycle",
+            Self::Error => "Error",
+            Self::Any => "Any",
+            Self::Other => "Other",
+        }
+    }
+
+    /// The name peers see, which the HMAC covers
+    pub fn wire_name(self) -> &'static str {
+        match self {
+            Self::Metadata | Self::Rename => Self::Modify.as_str(),
+            other => other.as_str(),
+        }
+    }
+
+    /// Whether the file exists afterwards, with the content the event's hash names
+    pub fn has_content(self) -> bool {
+        matches!(self, Self::Create | Self::Modify | Self::Metadata | Self::Rename)
+    }
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileEventMessage {
     pub observer: String,
-    pub event_type: String,
+    pub event_type: EventType,
     pub path: String,              // Relative path within the observer
     pub details: Option<String>,
     pub hash: Option<String>,      // SHA-256 hash of file content
@@ -11,17 +77,47 @@ pub struct FileEventMessage {
     pub modified_time: Option<u64>, // Unix timestamp of last modification
     /// HMAC-SHA256 authentication tag
     /// Computed over: observer||event_type||path||hash||size||modified_time
+    /// (followed by ||tx||id||size when part of a transaction)
     pub hmac: Option<String>,
+    /// Set when this change belongs to a group that must be applied together
+    #[serde(default)]
+    pub transaction: Option<TransactionInfo>,
+    /// Identity derived from the observer name and shared secret
+    /// Lets peers tell a same-named observer with a different secret from a forgery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observer_id: Option<String>,
+    /// Set when a Modify only appended to the previously announced version
+    /// Not signed: peers fetch just the new bytes only if their copy matches, and
+    /// the whole file must still hash to `hash` before the bytes are appended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append: Option<AppendInfo>,
+    /// Operations on the path the sender knew of, this one included
+    /// Orders racing creates and deletes without comparing clocks across peers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+}
+
+/// Where a file that only grew continues from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppendInfo {
+    pub offset: u64,               // Size of the previous version
+    pub prefix_hash: String,       // Hash of the previous version, the first `offset` bytes
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransactionInfo {
+    pub id: String,                // Shared by every message in the transaction
+    pub size: u32,                 // Number of messages in the transaction
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileTransferRequest {
     pub observer: String,          // Which observer/share this belongs to
     pub path: String,              // Relative path within the observer
     pub hash: String,              // Expected hash for verification
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileTransferResponse {
     pub observer: String,
     pub path: String,
@@ -30,20 +126,220 @@ pub struct FileTransferResponse {
     pub total_size: u64,           // Total file size
     pub hash: String,              // Hash of complete file
     pub is_last_chunk: bool,       // Is this the final chunk?
+    /// SHA-256 of `data`, so each chunk is checked as it arrives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_hash: Option<String>,
+    /// `segment_hash` signed with the observer's shared secret, for files not hashed yet
+    /// The segment hash alone only shows the chunk arrived as sent; this shows a
+    /// member of the observer sent it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_hmac: Option<String>,
+    /// Codec `data` is compressed with, raw if unset; `segment_hash` is of the decoded bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FileChunkRequest {
     pub observer: String,          // Which observer/share this belongs to
     pub path: String,              // Relative path within the observer
     pub offset: u64,               // Byte offset to request
     pub hash: String,              // Expected hash for verification
+    /// Bytes wanted from `offset`; CHUNK_SIZE if unset, as older peers don't send it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    /// Codec the response may be compressed with; older peers send raw bytes regardless
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CancelTransferRequest {
+    pub observer: String,          // Which observer/share this belongs to
+    pub path: String,              // Relative path within the observer
+    pub reason: String,            // Human readable reason, e.g. "deleted locally"
+}
+
+/// A one-off read of part of a file, outside of any transfer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RangeReadRequest {
+    pub observer: String,
+    pub path: String,
+    pub offset: u64,
+    pub length: u32,               // At most CHUNK_SIZE bytes
+    pub hash: String,              // Version the bytes must come from
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RangeReadResponse {
+    pub observer: String,
+    pub path: String,
+    pub offset: u64,
+    pub data: Vec<u8>,             // Shorter than requested at end of file
+    pub total_size: u64,
+    pub hash: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// One child of a directory in an observer's Merkle tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreeEntry {
+    pub name: String,              // File or directory name, without its parent
+    pub hash: String,              // Content hash of a file, subtree hash of a directory
+    pub is_dir: bool,
+    pub size: Option<u64>,         // Files only
+    pub modified_time: Option<u64>, // Files only
+}
+
+/// Ask for the children of one directory of a peer's tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreeNodeRequest {
+    pub observer: String,
+    pub dir: String,               // Relative path, "" for the root
+    /// Proof the requester holds the observer's shared secret, bound to its peer id
+    pub hmac: Option<String>,
+    /// Continue a paged listing after this entry name, the previous page's `next`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TreeNodeResponse {
+    pub observer: String,
+    pub dir: String,
+    pub entries: Vec<TreeEntry>,
+    pub hmac: Option<String>,
+    /// Files deleted below `dir`, so peers don't fetch the copies others still hold
+    /// Sent with the first page of a paged listing only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tombstones: Vec<TombstoneEntry>,
+    /// Entry name to ask for the rest after, when the listing continues in another page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+    /// Entries in the whole directory, for progress across pages
+    #[serde(default)]
+    pub total_entries: u64,
+}
+
+/// A deletion a peer remembers, as served with its tree
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TombstoneEntry {
+    pub path: String,              // Relative path within the observer
+    pub hash: String,              // Content the file had when deleted
+    pub deleted_at: u64,           // Unix timestamp of the deletion
+    pub version: VersionVector,
+}
+
+/// Root of one observer's tree, as gossiped in heartbeats
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ObserverDigest {
+    pub observer: String,
+    pub observer_id: Option<String>,
+    pub root: String,
+    /// Files in the tree, so a diverging peer can say how far apart the two are
+    #[serde(default)]
+    pub file_count: u64,
+    pub hmac: Option<String>,
+    /// Bytes free on the volume holding the observer; advisory, so not signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+}
+
+/// Periodic summary of a peer's observers, published on the heartbeat topic
+/// Also sent directly to a newly connected peer, which may not have joined the topic yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub observers: Vec<ObserverDigest>,
+    /// Observers the publisher stopped syncing; the gossip signature shows it came from them
+    #[serde(default)]
+    pub removed: Vec<String>,
+    /// How busy the publisher is serving transfers, so requesters can go elsewhere
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load: Option<PeerLoad>,
+}
+
+/// Transfers a peer is serving, against how many it serves at once
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct PeerLoad {
+    pub serving: u32,
+    pub capacity: u32,             // 0 if unlimited
+}
+
+/// What a peer supports, exchanged when it connects
+/// Fields a newer peer adds are ignored, and missing ones default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Capabilities {
+    /// Agent version, e.g. "syndactyl/0.3.0"
+    pub version: String,
+    /// Optional protocol features, as named in `network::capabilities`
+    pub features: Vec<String>,
+    /// Largest chunk sent or accepted, in bytes
+    pub max_chunk_size: u64,
+    /// Largest file served, in bytes
+    pub max_file_size: u64,
+    /// Content hash algorithms understood, most preferred first
+    pub hash_algorithms: Vec<String>,
+    /// Compression codecs understood for transfers, most preferred first
+    pub compression: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum SyndactylRequest {
     FileTransfer(FileTransferRequest),
     FileChunk(FileChunkRequest),
+    /// Abort an in-flight transfer; sent by either the requester or the server
+    CancelTransfer(CancelTransferRequest),
+    /// Read a byte range without starting a transfer
+    RangeRead(RangeReadRequest),
+    /// List one directory of an observer's Merkle tree
+    TreeNode(TreeNodeRequest),
+    /// The sender's capabilities, answered with ours
+    Capabilities(Capabilities),
+    /// The sender's tree roots, sent on connecting; answered with ours
+    Heads(Heartbeat),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TransferErrorKind {
+    /// The file no longer matches the requested hash; carries the current version if known
+    FileChanged {
+        current_size: Option<u64>,
+        current_hash: Option<String>,
+    },
+    /// The file no longer exists on the serving peer
+    NotFound,
+    /// The serving peer doesn't sync the observer (any more); stop asking it
+    NotServing,
+    /// The serving peer is at capacity; ask again after the hint, or another peer sooner
+    Busy {
+        retry_after_secs: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferError {
+    pub observer: String,
+    pub path: String,
+    pub requested_hash: String,    // Hash the failed request asked for
+    pub kind: TransferErrorKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SyndactylResponse {
+    /// File data for a FileTransfer or FileChunk request
+    Chunk(FileTransferResponse),
+    /// Acknowledges a CancelTransfer request
+    CancelAck { observer: String, path: String },
+    /// Bytes for a RangeRead request
+    Range(RangeReadResponse),
+    /// Directory listing for a TreeNode request
+    TreeNode(TreeNodeResponse),
+    /// Answer to a Capabilities request
+    Capabilities(Capabilities),
+    /// Answer to a Heads request
+    Heads(Heartbeat),
+    /// The request could not be served
+    Error(TransferError),
 }
 
 
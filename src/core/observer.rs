@@ -1,105 +1,291 @@
-use notify::{Event, EventKind, RecursiveMode, Result, Watcher};
+use notify::{Event, EventKind, Result};
+use notify::event::ModifyKind;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::{path::Path, sync::mpsc, thread};
-use crate::core::config::ObserverConfig;
-use tracing::{info, error, warn};
-use crate::core::models::FileEventMessage;
+use std::time::{Duration, Instant};
+use crate::core::config::{ObserverConfig, LoggingConfig};
+use crate::core::log_throttle::LogThrottle;
+use crate::core::power::PowerMonitor;
+use crate::core::event_source::{EventSource, NotifySource};
+use tracing::{debug, info, info_span, error, warn};
+use crate::core::models::{EventType, FileEventMessage};
 use crate::core::file_handler;
+use crate::core::path_encoding;
+use crate::core::sync_id::{sync_id, traced};
+use crate::core::hash_pool::{HashPool, BACKGROUND_HASH_THRESHOLD, HASH_PENDING, HASH_WORKERS};
 use crate::core::auth;
+use crate::core::secret::Secret;
+use crate::core::transaction::TransactionBatcher;
+use crate::core::merkle::IndexedFile;
+use crate::core::tombstone::unix_now;
+use crate::core::lifecycle::{self, ObserverState};
 use serde_json;
 use std::path::PathBuf;
 
-pub fn event_listener(observers: Vec<ObserverConfig>, tx: mpsc::Sender<String>) -> Result<()> {
+/// How often an idle watcher thread wakes up when not batching transactions
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a watcher checks that its directory still exists, and re-checks while it is missing
+#[cfg(not(test))]
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+pub fn event_listener(observers: Vec<ObserverConfig>, logging: LoggingConfig, power: PowerMonitor, tx: mpsc::Sender<String>) -> Result<()> {
     let mut handles = Vec::new();
+    let hash_pool = HashPool::new(HASH_WORKERS, power.clone());
 
     // TODO: You will have to write a dynamic limiter for this so it
     // cant run away with too many threads
     // start a thread for each observer
     for observer in observers {
-        let observer_name = observer.name.clone();
-        let observer_path = observer.path.clone();
-        let observer_secret = observer.shared_secret.clone();
-        let tx = tx.clone();
+        // Encrypted mirrors hold ciphertext under obfuscated names, so local
+        // changes there are never announced
+        if observer.at_rest_key.is_some() {
+            info!(observer = %observer.name, "Observer is an encrypted mirror, not watching for local changes");
+            continue;
+        }
+        // Announce-only observers have no local copy to watch, and on-demand
+        // observers only hold a cache of fetched files
+        if observer.announce_only == Some(true) || observer.mount.is_some() {
+            continue;
+        }
 
-        let handle = thread::spawn(move || {
-            let (event_tx, rx) = mpsc::channel::<Result<Event>>();
-            let mut watcher = notify::recommended_watcher(event_tx).expect("Failed to create watcher");
-            watcher.watch(Path::new(&observer_path), RecursiveMode::Recursive).expect("Failed to watch path");
+        handles.push(spawn_observer(observer, Box::new(NotifySource::new()), &logging, power.clone(), hash_pool.clone(), tx.clone()));
+    }
+
+    // Wait for all threads to finish (they won't, unless the channel closes)
+    for handle in handles {
+        handle.join().expect("Thread panicked");
+    }
+
+    Ok(())
+}
+
+/// Spawn a thread turning raw events from `source` into signed FileEventMessages on `tx`
+/// The daemon uses a `NotifySource`; tests and embedders can supply their own source.
+/// Large files are announced before `hash_pool` has hashed them.
+pub fn spawn_observer(
+    observer: ObserverConfig,
+    mut source: Box<dyn EventSource>,
+    logging: &LoggingConfig,
+    power: PowerMonitor,
+    hash_pool: HashPool,
+    tx: mpsc::Sender<String>,
+) -> thread::JoinHandle<()> {
+    let observer_name = observer.name.clone();
+    let observer_path = observer.path.clone();
+    let observer_secret = observer.shared_secret.clone();
+    let mut throttle = LogThrottle::new(logging);
+    let mut batcher = observer.transaction_window_ms
+        .map(|ms| TransactionBatcher::new(Duration::from_millis(ms)));
+
+    thread::spawn(move || {
+        report(&observer_name, ObserverState::Initializing, "starting", &tx);
+        if observer_secret.is_none() {
+            warn!(observer = %observer_name, "No shared secret configured - messages will not be authenticated");
+        }
+        let root = PathBuf::from(&observer_path);
+        let removable = observer.removable == Some(true);
+        let escape_names = observer.escape_names.unwrap_or(path_encoding::ESCAPE_BY_DEFAULT);
+        let root_available = || file_handler::root_available(&root, removable);
+        let poll_interval = batcher.as_ref().map(|b| b.window()).unwrap_or(IDLE_POLL_INTERVAL);
+        let mut was_unavailable = false;
 
+        'watch: loop {
+            if !root_available() {
+                warn!(observer = %observer_name, path = %observer_path, "Observer directory unavailable, waiting for it to return");
+                report(&observer_name, ObserverState::Degraded, "directory unavailable", &tx);
+                while !root_available() {
+                    thread::sleep(ROOT_CHECK_INTERVAL);
+                }
+                was_unavailable = true;
+            }
+
+            let (event_tx, rx) = mpsc::channel::<Result<Event>>();
+            if let Err(e) = source.start(&root, event_tx) {
+                if !root_available() {
+                    // Removed again between the check and the watch
+                    continue 'watch;
+                }
+                error!(observer = %observer_name, path = %observer_path, error = %e, "Failed to start event source");
+                throttle.flush_all();
+                report(&observer_name, ObserverState::Stopped, &format!("event source failed: {}", e), &tx);
+                return;
+            }
             info!(path = %observer_path, observer = %observer_name, "Watching path");
+
+            // Changes made while the directory was away produced no events
+            if was_unavailable {
+                was_unavailable = false;
+                report(&observer_name, ObserverState::Scanning, "directory returned", &tx);
+                if let Err(e) = rescan(&observer, |msg| { let _ = tx.send(msg); }) {
+                    warn!(observer = %observer_name, error = %e, "Reconciliation after directory returned failed");
+                }
+            }
+            report(&observer_name, ObserverState::Watching, "watching for changes", &tx);
+
+            let mut last_root_check = Instant::now();
+            loop {
+                throttle.flush();
+
+                if last_root_check.elapsed() >= ROOT_CHECK_INTERVAL {
+                    last_root_check = Instant::now();
+                    if !root_available() {
+                        was_unavailable = true;
+                        continue 'watch;
+                    }
+                }
+            
+                // Release a transaction once the observer has gone quiet
+                if let Some(batcher) = batcher.as_mut() {
+                    if batcher.is_due(Instant::now()) {
+                        for msg in batcher.take() {
+                            send_event(msg, &observer_secret, &tx);
+                        }
+                    }
+                }
             
-            for res in rx {
+                // Wake for a summary due before the next poll, so a burst is reported once it's over
+                let now = Instant::now();
+                let wait = throttle.next_summary_at()
+                    .map_or(poll_interval, |due| due.saturating_duration_since(now).min(poll_interval));
+                let res = match rx.recv_timeout(wait) {
+                    Ok(res) => res,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        throttle.flush_all();
+                        report(&observer_name, ObserverState::Stopped, "event source closed", &tx);
+                        return;
+                    }
+                };
                 match res {
+                    // The watcher's queue overflowed and changes were lost
+                    Ok(event) if event.need_rescan() => {
+                        let subtree = event.paths.first()
+                            .and_then(|path| file_handler::to_relative_path(path, Path::new(&observer_path)))
+                            .map(|relative| wire_path(&relative, escape_names))
+                            .unwrap_or_default();
+                        warn!(observer = %observer_name, subtree = %subtree, "File watcher dropped events, asking for a rescan");
+                        let msg = FileEventMessage {
+                            observer: observer_name.clone(),
+                            event_type: EventType::Rescan,
+                            path: subtree,
+                            details: Some("Watcher dropped events".to_string()),
+                            hash: None,
+                            size: None,
+                            modified_time: None,
+                            hmac: None,
+                            transaction: None,
+                            observer_id: None,
+                            append: None,
+                            sequence: None,
+                        };
+                        send_event(msg, &observer_secret, &tx);
+                    }
                     Ok(event) => {
-                        match event.kind {
-                            EventKind::Any => info!(observer = %observer_name, ?event, "any event"),
-                            EventKind::Access(_access_kind) => {
-                                // Do not handle or send access events
-                                continue;
-                            },
-                            EventKind::Create(ref create_kind) => {
-                                if let Some(path) = event.paths.get(0) {
-                                    info!(observer = %observer_name, kind = ?create_kind, path = %path.display(), "created");
-                                } else {
-                                    info!(observer = %observer_name, kind = ?create_kind, "created, but path unknown");
-                                }
-                            },
-                            EventKind::Modify(ref modify_kind) => {
-                                if let Some(path) = event.paths.get(0) {
-                                    info!(observer = %observer_name, kind = ?modify_kind, path = %path.display(), "modified");
-                                } else {
-                                    info!(observer = %observer_name, kind = ?modify_kind, "modified, but path unknown");
-                                }
-                            },
-                            EventKind::Remove(ref remove_kind) => {
-                                if let Some(path) = event.paths.get(0) {
-                                    info!(observer = %observer_name, kind = ?remove_kind, path = %path.display(), "removed");
-                                } else {
-                                    info!(observer = %observer_name, kind = ?remove_kind, "removed, but path unknown");
-                                }
-                            },
-                            EventKind::Other => {
-                                if let Some(path) = event.paths.get(0) {
-                                    info!(observer = %observer_name, path = %path.display(), "other event");
-                                } else {
-                                    info!(observer = %observer_name, "other event, but path unknown");
-                                }
-                            },
+                        // Access events are never logged or sent; everything else
+                        // counts against the observer's log budget
+                        let log_event = match event.kind {
+                            EventKind::Access(_) => continue,
+                            _ => throttle.allow("observer"),
+                        };
+                        if log_event {
+                            match event.kind {
+                                EventKind::Any => info!(observer = %observer_name, ?event, "any event"),
+                                EventKind::Access(_) => {}
+                                EventKind::Create(ref create_kind) => {
+                                    if let Some(path) = event.paths.get(0) {
+                                        info!(observer = %observer_name, kind = ?create_kind, path = %path.display(), "created");
+                                    } else {
+                                        info!(observer = %observer_name, kind = ?create_kind, "created, but path unknown");
+                                    }
+                                },
+                                EventKind::Modify(ref modify_kind) => {
+                                    if let Some(path) = event.paths.get(0) {
+                                        info!(observer = %observer_name, kind = ?modify_kind, path = %path.display(), "modified");
+                                    } else {
+                                        info!(observer = %observer_name, kind = ?modify_kind, "modified, but path unknown");
+                                    }
+                                },
+                                EventKind::Remove(ref remove_kind) => {
+                                    if let Some(path) = event.paths.get(0) {
+                                        info!(observer = %observer_name, kind = ?remove_kind, path = %path.display(), "removed");
+                                    } else {
+                                        info!(observer = %observer_name, kind = ?remove_kind, "removed, but path unknown");
+                                    }
+                                },
+                                EventKind::Other => {
+                                    if let Some(path) = event.paths.get(0) {
+                                        info!(observer = %observer_name, path = %path.display(), "other event");
+                                    } else {
+                                        info!(observer = %observer_name, "other event, but path unknown");
+                                    }
+                                },
+                            }
                         }
                         // Build and send FileEventMessage as JSON, but skip Access events
-                        let event_type = match &event.kind {
-                            EventKind::Any => "Any",
-                            EventKind::Access(_) => continue,
-                            EventKind::Create(_) => "Create",
-                            EventKind::Modify(_) => "Modify",
-                            EventKind::Remove(_) => "Remove",
-                            EventKind::Other => "Other",
-                        }.to_string();
-                        
-                        let absolute_path = event.paths.get(0)
+                        let Some(event_type) = event_type(&event.kind) else {
+                            continue;
+                        };
+
+                        // Files vanishing with an unplugged drive were not deleted
+                        if removable && event_type == EventType::Remove && !root_available() {
+                            continue;
+                        }
+                    
+                        // A rename reported with both names is announced for where the file went
+                        let absolute_path = if event_type == EventType::Rename { event.paths.last() } else { event.paths.first() }
                             .map(|p| p.to_path_buf())
                             .unwrap_or_else(|| PathBuf::from("unknown"));
-                        
+                    
                         // Convert to relative path
                         let base_path = Path::new(&observer_path);
                         let relative_path = file_handler::to_relative_path(&absolute_path, base_path)
                             .unwrap_or_else(|| absolute_path.clone());
-                        
+                    
                         // Skip files that shouldn't be synced
                         if !file_handler::should_sync_file(&relative_path) {
                             continue;
                         }
-                        
-                        let path_str = relative_path.display().to_string();
+                    
+                        let path_str = wire_path(&relative_path, escape_names);
                         let details = Some(format!("{:?}", event.kind));
-                        
+                    
                         // For Create/Modify events, calculate hash and get metadata
-                        let (hash, size, modified_time) = if matches!(event_type.as_str(), "Create" | "Modify") {
+                        let (hash, size, modified_time) = if event_type.has_content() {
                             if absolute_path.is_file() {
+                                // Outside transactions, large files are announced now and completed once hashed
+                                let metadata = file_handler::get_file_metadata(&absolute_path).ok();
+                                if let Some((file_size, mtime)) = metadata.filter(|(file_size, _)| batcher.is_none() && *file_size >= BACKGROUND_HASH_THRESHOLD) {
+                                    let msg = FileEventMessage {
+                                        observer: observer_name.clone(),
+                                        event_type,
+                                        path: path_str,
+                                        details: Some(HASH_PENDING.to_string()),
+                                        hash: None,
+                                        size: Some(file_size),
+                                        modified_time: Some(mtime),
+                                        hmac: None,
+                                        transaction: None,
+                                        observer_id: None,
+                                        append: None,
+                                        sequence: None,
+                                    };
+                                    send_event(msg.clone(), &observer_secret, &tx);
+                                    hash_pool.submit(absolute_path, msg, observer_secret.clone(), tx.clone());
+                                    continue;
+                                }
+                                let hash_started = Instant::now();
                                 let hash = file_handler::calculate_file_hash(&absolute_path)
                                     .ok();
+                                // On low battery, idle for as long as hashing took to halve its CPU use
+                                if power.is_constrained() {
+                                    thread::sleep(hash_started.elapsed());
+                                }
                                 let metadata = file_handler::get_file_metadata(&absolute_path)
                                     .ok();
-                                
+                            
                                 if let Some((file_size, mtime)) = metadata {
                                     (hash, Some(file_size), Some(mtime))
                                 } else {
@@ -109,11 +295,14 @@ pub fn event_listener(observers: Vec<ObserverConfig>, tx: mpsc::Sender<String>)
                                 // Skip directory events for now
                                 continue;
                             }
+                        } else if event_type == EventType::Remove {
+                            // Deletion time, so peers can order it against creates
+                            (None, None, Some(unix_now()))
                         } else {
                             (None, None, None)
                         };
-                        
-                        let mut msg = FileEventMessage {
+                    
+                        let msg = FileEventMessage {
                             observer: observer_name.clone(),
                             event_type,
                             path: path_str,
@@ -122,54 +311,408 @@ pub fn event_listener(observers: Vec<ObserverConfig>, tx: mpsc::Sender<String>)
                             size,
                             modified_time,
                             hmac: None,
+                            transaction: None,
+                            observer_id: None,
+                            append: None,
+                            sequence: None,
                         };
-                        
-                        // Compute HMAC if shared secret is configured
-                        if let Some(ref secret) = observer_secret {
-                            let hmac = auth::compute_hmac(&msg, secret);
-                            msg.hmac = Some(hmac);
-                        } else {
-                            warn!(observer = %observer_name, "No shared secret configured - messages will not be authenticated");
-                        }
-                        
-                        if let Ok(json) = serde_json::to_string(&msg) {
-                            let _ = tx.send(json);
+                    
+                        // Bursts are grouped into a transaction when configured
+                        match batcher.as_mut() {
+                            Some(batcher) => batcher.push(msg, Instant::now()),
+                            None => send_event(msg, &observer_secret, &tx),
                         }
                     },
                     Err(e) => {
                         error!(observer = %observer_name, error = ?e, "watch error");
-                        let mut msg = FileEventMessage {
+                        let msg = FileEventMessage {
                             observer: observer_name.clone(),
-                            event_type: "Error".to_string(),
+                            event_type: EventType::Error,
                             path: "error".to_string(),
                             details: Some(format!("watch error: {:?}", e)),
                             hash: None,
                             size: None,
                             modified_time: None,
                             hmac: None,
+                            transaction: None,
+                            observer_id: None,
+                            append: None,
+                            sequence: None,
                         };
-                        
-                        // Compute HMAC for error messages too if secret is configured
-                        if let Some(ref secret) = observer_secret {
-                            let hmac = auth::compute_hmac(&msg, secret);
-                            msg.hmac = Some(hmac);
-                        }
-                        
-                        if let Ok(json) = serde_json::to_string(&msg) {
-                            let _ = tx.send(json);
-                        }
+                    
+                        // Errors are never batched
+                        send_event(msg, &observer_secret, &tx);
                     },
                 }
             }
+        }
+    })
+}
+
+/// What a notify event did to its file, or None for accesses, which aren't announced
+pub fn event_type(kind: &EventKind) -> Option<EventType> {
+    Some(match kind {
+        EventKind::Access(_) => return None,
+        EventKind::Any => EventType::Any,
+        EventKind::Create(_) => EventType::Create,
+        EventKind::Modify(ModifyKind::Metadata(_)) => EventType::Metadata,
+        EventKind::Modify(ModifyKind::Name(_)) => EventType::Rename,
+        EventKind::Modify(_) => EventType::Modify,
+        EventKind::Remove(_) => EventType::Remove,
+        EventKind::Other => EventType::Other,
+    })
+}
+
+/// Sign a message with the observer's shared secret (if configured) and hand it to the network layer
+pub(crate) fn send_event(msg: FileEventMessage, observer_secret: &Option<Secret>, tx: &mpsc::Sender<String>) {
+    let sync = sync_id(&msg.observer, &msg.path, msg.hash.as_deref());
+    let _span = traced(info_span!("observe", sync = %sync), &sync).entered();
+    debug!(event_type = %msg.event_type, path = %msg.path, "Announcing local change");
+    if let Ok(json) = serde_json::to_string(&sign(msg, observer_secret)) {
+        let _ = tx.send(json);
+    }
+}
+
+/// Tell the network manager the watcher entered `state`
+/// Lifecycle messages stay on this node, so they aren't signed.
+fn report(observer: &str, state: ObserverState, reason: &str, tx: &mpsc::Sender<String>) {
+    debug!(observer = %observer, state = %state, reason = %reason, "Observer lifecycle");
+    if let Ok(json) = serde_json::to_string(&lifecycle::message(observer, state, reason)) {
+        let _ = tx.send(json);
+    }
+}
+
+fn sign(mut msg: FileEventMessage, observer_secret: &Option<Secret>) -> FileEventMessage {
+    if let Some(secret) = observer_secret {
+        let hmac = auth::compute_hmac(&msg, secret.expose());
+        msg.hmac = Some(hmac);
+        msg.observer_id = Some(auth::observer_id(&msg.observer, secret.expose()));
+    }
+    msg
+}
+
+/// The wire form of a path relative to an observer's root
+fn wire_path(relative_path: &Path, escape_names: bool) -> String {
+    let path = relative_path.display().to_string();
+    let path = if escape_names { path_encoding::unescape_name(&path) } else { path };
+    path_encoding::wire_path(&path)
+}
+
+/// Re-announce every file in an observer as a signed Modify event
+/// Used when events may have been missed; peers skip files they already have.
+/// Returns the number of files announced.
+pub fn rescan(observer: &ObserverConfig, mut emit: impl FnMut(String)) -> std::io::Result<usize> {
+    let base_path = Path::new(&observer.path);
+    let escape_names = observer.escape_names.unwrap_or(path_encoding::ESCAPE_BY_DEFAULT);
+    let mut announced = 0;
+    for relative_path in file_handler::list_files(base_path)? {
+        let absolute_path = base_path.join(&relative_path);
+        let (Ok(hash), Ok((size, modified_time))) = (
+            file_handler::calculate_file_hash(&absolute_path),
+            file_handler::get_file_metadata(&absolute_path),
+        ) else {
+            // Removed or unreadable since listing
+            continue;
+        };
+        let msg = FileEventMessage {
+            observer: observer.name.clone(),
+            event_type: EventType::Modify,
+            path: wire_path(&relative_path, escape_names),
+            details: Some("Rescan".to_string()),
+            hash: Some(hash),
+            size: Some(size),
+            modified_time: Some(modified_time),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        };
+        if let Ok(json) = serde_json::to_string(&sign(msg, &observer.shared_secret)) {
+            emit(json);
+            announced += 1;
+        }
+    }
+    info!(observer = %observer.name, files = announced, "Rescan complete");
+    Ok(announced)
+}
+
+/// Re-announce what changed below `subtree` (a wire path, "" for the whole observer)
+/// since `known` was indexed. Files whose size and modification time still match
+/// are skipped without hashing, and known files that are gone are announced as removed.
+/// Returns the number of events announced.
+pub fn rescan_subtree(
+    observer: &ObserverConfig,
+    subtree: &str,
+    known: &HashMap<String, IndexedFile>,
+    mut emit: impl FnMut(String),
+) -> std::io::Result<usize> {
+    let base_path = Path::new(&observer.path);
+    // An unmounted drive is not a deleted tree
+    if !base_path.is_dir() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "observer directory is unavailable"));
+    }
+    let escape_names = observer.escape_names.unwrap_or(path_encoding::ESCAPE_BY_DEFAULT);
+    let local_subtree = if escape_names { path_encoding::escape_path(Path::new(subtree)) } else { PathBuf::from(subtree) };
+    let absolute_subtree = base_path.join(&local_subtree);
+    let found = if absolute_subtree.is_dir() {
+        file_handler::list_files(&absolute_subtree)?.into_iter().map(|path| local_subtree.join(path)).collect()
+    } else if absolute_subtree.is_file() && file_handler::should_sync_file(&local_subtree) {
+        vec![local_subtree]
+    } else {
+        Vec::new()
+    };
+
+    let mut seen = HashSet::new();
+    let mut announced = 0;
+    let mut announce = |msg: FileEventMessage| {
+        if let Ok(json) = serde_json::to_string(&sign(msg, &observer.shared_secret)) {
+            emit(json);
+            announced += 1;
+        }
+    };
+    for relative_path in found {
+        let absolute_path = base_path.join(&relative_path);
+        let path = wire_path(&relative_path, escape_names);
+        seen.insert(path.clone());
+        let Ok((size, modified_time)) = file_handler::get_file_metadata(&absolute_path) else {
+            continue;
+        };
+        if known.get(&path).is_some_and(|file| file.size == size && file.modified_time == modified_time) {
+            continue;
+        }
+        let Ok(hash) = file_handler::calculate_file_hash(&absolute_path) else {
+            continue;
+        };
+        announce(FileEventMessage {
+            observer: observer.name.clone(),
+            event_type: EventType::Modify,
+            path,
+            details: Some("Rescan".to_string()),
+            hash: Some(hash),
+            size: Some(size),
+            modified_time: Some(modified_time),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        });
+    }
+    for path in known.keys().filter(|path| !seen.contains(*path)) {
+        announce(FileEventMessage {
+            observer: observer.name.clone(),
+            event_type: EventType::Remove,
+            path: path.clone(),
+            details: Some("Rescan".to_string()),
+            hash: None,
+            size: None,
+            modified_time: Some(unix_now()),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
         });
+    }
+    info!(observer = %observer.name, subtree = %subtree, changes = announced, "Rescan of subtree complete");
+    Ok(announced)
+}
 
-        handles.push(handle);
+/// Add a subtree to rescan, keeping only the outermost of nested ones
+pub fn add_subtree(subtrees: &mut BTreeSet<String>, subtree: String) {
+    let contains = |outer: &str, inner: &str| outer.is_empty() || inner == outer || inner.starts_with(&format!("{}/", outer));
+    if subtrees.iter().any(|existing| contains(existing, &subtree)) {
+        return;
     }
+    subtrees.retain(|existing| !contains(&subtree, existing));
+    subtrees.insert(subtree);
+}
 
-    // Wait for all threads to finish (they won't, unless the channel closes)
-    for handle in handles {
-        handle.join().expect("Thread panicked");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::event_source::VirtualSource;
+    use notify::event::CreateKind;
+    use tempfile::TempDir;
+
+    /// The next file event from a watcher, and the lifecycle states it reported before it
+    fn next_change(rx: &mpsc::Receiver<String>) -> (FileEventMessage, Vec<ObserverState>) {
+        let mut states = Vec::new();
+        loop {
+            let json = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            let msg: FileEventMessage = serde_json::from_str(&json).unwrap();
+            match lifecycle::parse(&msg).filter(|_| msg.event_type == EventType::Lifecycle) {
+                Some((state, _)) => states.push(state),
+                None => return (msg, states),
+            }
+        }
     }
 
-    Ok(())
+    #[test]
+    fn test_virtual_source_events_are_announced() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a.txt");
+        std::fs::write(&file_path, b"hello").unwrap();
+
+        let observer = ObserverConfig {
+            name: "docs".to_string(),
+            path: dir.path().display().to_string(),
+            shared_secret: Some(Secret::new("secret")),
+            at_rest_key: None,
+            transaction_window_ms: None,
+            schedule: None,
+            announce_only: None,
+            mount: None,
+            removable: None,
+            escape_names: None,
+            in_use: None,
+            peers: None,
+            distribution: None,
+            merge_extensions: None,
+            verify_writes: None,
+            temp_dir: None,
+            min_free_mb: None,
+            transfer_tuning: None,
+            pinned_keys: None,
+            compress_history: None,
+        };
+        let source = VirtualSource::new();
+        let (tx, rx) = mpsc::channel();
+        let handle = spawn_observer(
+            observer,
+            Box::new(source.clone()),
+            &LoggingConfig::default(),
+            PowerMonitor::new(None),
+            HashPool::new(1, PowerMonitor::new(None)),
+            tx,
+        );
+
+        // The source only accepts events once the observer thread has started it
+        let event = Event::new(EventKind::Create(CreateKind::File)).add_path(file_path);
+        while !source.emit(event.clone()) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (msg, states) = next_change(&rx);
+        assert_eq!(states, vec![ObserverState::Initializing, ObserverState::Watching]);
+        assert_eq!(msg.event_type, EventType::Create);
+        assert_eq!(msg.path, "a.txt");
+        assert_eq!(msg.size, Some(5));
+        assert!(auth::verify_hmac(&msg, "secret"));
+
+        source.close();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_missing_directory_is_reconciled_when_it_returns() {
+        let parent = TempDir::new().unwrap();
+        let root = parent.path().join("docs");
+        let observer = ObserverConfig {
+            name: "docs".to_string(),
+            path: root.display().to_string(),
+            shared_secret: None,
+            at_rest_key: None,
+            transaction_window_ms: None,
+            schedule: None,
+            announce_only: None,
+            mount: None,
+            removable: None,
+            escape_names: None,
+            in_use: None,
+            peers: None,
+            distribution: None,
+            merge_extensions: None,
+            verify_writes: None,
+            temp_dir: None,
+            min_free_mb: None,
+            transfer_tuning: None,
+            pinned_keys: None,
+            compress_history: None,
+        };
+        let source = VirtualSource::new();
+        let (tx, rx) = mpsc::channel();
+        let handle = spawn_observer(
+            observer,
+            Box::new(source.clone()),
+            &LoggingConfig::default(),
+            PowerMonitor::new(None),
+            HashPool::new(1, PowerMonitor::new(None)),
+            tx,
+        );
+
+        // Written while the watcher was waiting, so only a rescan can announce it
+        thread::sleep(ROOT_CHECK_INTERVAL * 2);
+        let staging = parent.path().join("staging");
+        std::fs::create_dir(&staging).unwrap();
+        std::fs::write(staging.join("a.txt"), b"hello").unwrap();
+        std::fs::rename(&staging, &root).unwrap();
+
+        let (msg, states) = next_change(&rx);
+        assert_eq!(states, vec![ObserverState::Initializing, ObserverState::Degraded, ObserverState::Scanning]);
+        assert_eq!(msg.path, "a.txt");
+        assert_eq!(msg.details.as_deref(), Some("Rescan"));
+
+        source.close();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_subtree_rescan_announces_only_what_changed() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("notes/old")).unwrap();
+        std::fs::write(dir.path().join("notes/same.txt"), b"same").unwrap();
+        std::fs::write(dir.path().join("notes/new.txt"), b"new").unwrap();
+        std::fs::write(dir.path().join("other.txt"), b"outside").unwrap();
+        let observer: ObserverConfig = serde_json::from_value(serde_json::json!({
+            "name": "docs", "path": dir.path().display().to_string(),
+        })).unwrap();
+
+        let (size, modified_time) = file_handler::get_file_metadata(&dir.path().join("notes/same.txt")).unwrap();
+        let known = HashMap::from([
+            ("notes/same.txt".to_string(), IndexedFile { hash: "h1".to_string(), size, modified_time }),
+            ("notes/old/gone.txt".to_string(), IndexedFile { hash: "h2".to_string(), size: 4, modified_time: 1 }),
+        ]);
+        let mut events = Vec::new();
+        let announced = rescan_subtree(&observer, "notes", &known, |json| {
+            events.push(serde_json::from_str::<FileEventMessage>(&json).unwrap());
+        }).unwrap();
+        assert_eq!(announced, 2);
+        let summary: Vec<(&str, &str)> = events.iter().map(|msg| (msg.event_type.as_str(), msg.path.as_str())).collect();
+        assert_eq!(summary, vec![("Modify", "notes/new.txt"), ("Remove", "notes/old/gone.txt")]);
+
+        let mut subtrees = BTreeSet::new();
+        add_subtree(&mut subtrees, "notes/old".to_string());
+        add_subtree(&mut subtrees, "music".to_string());
+        add_subtree(&mut subtrees, "notes".to_string());
+        add_subtree(&mut subtrees, "notes/old/deeper".to_string());
+        assert_eq!(subtrees.into_iter().collect::<Vec<_>>(), vec!["music".to_string(), "notes".to_string()]);
+    }
+
+    #[test]
+    fn test_event_kinds_map_to_event_types() {
+        use notify::event::{AccessKind, DataChange, MetadataKind, RemoveKind, RenameMode};
+        assert_eq!(event_type(&EventKind::Access(AccessKind::Any)), None);
+        assert_eq!(event_type(&EventKind::Create(CreateKind::File)), Some(EventType::Create));
+        assert_eq!(event_type(&EventKind::Modify(ModifyKind::Data(DataChange::Content))), Some(EventType::Modify));
+        assert_eq!(event_type(&EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions))), Some(EventType::Metadata));
+        assert_eq!(event_type(&EventKind::Modify(ModifyKind::Name(RenameMode::Both))), Some(EventType::Rename));
+        assert_eq!(event_type(&EventKind::Remove(RemoveKind::File)), Some(EventType::Remove));
+
+        // The names older peers send read back unchanged, so their HMACs still verify
+        for name in ["Create", "Modify", "Remove", "Any", "Other"] {
+            let event_type: EventType = serde_json::from_str(&format!("\"{}\"", name)).unwrap();
+            assert_eq!(event_type.as_str(), name);
+            assert_eq!(serde_json::to_string(&event_type).unwrap(), format!("\"{}\"", name));
+        }
+        assert_eq!(serde_json::from_str::<EventType>("\"Truncate\"").unwrap(), EventType::Other);
+
+        // Older peers only know Modify for these
+        for event_type in [EventType::Metadata, EventType::Rename] {
+            assert_eq!(serde_json::to_string(&event_type).unwrap(), "\"Modify\"");
+            assert_eq!(event_type.wire_name(), "Modify");
+        }
+        assert!(EventType::Rename.has_content() && !EventType::Remove.has_content());
+    }
 }
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use opentelemetry::{Context, KeyValue};
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt as _, TraceFlags, TraceId, TraceState, TracerProvider as _};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use tracing::{info, warn};
+use sha2::{Digest, Sha256};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt as _};
+use tracing_subscriber::{Registry, reload};
+use crate::core::config::OtlpConfig;
+use crate::core::metrics::Sample;
+
+/// Collector URL used when none is configured, the standard OTLP/HTTP port
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:4318";
+
+pub const DEFAULT_SERVICE_NAME: &str = "syndactyl";
+
+pub const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Instrumentation scope of everything syndactyl exports
+const SCOPE: &str = "syndactyl";
+
+type SpanLayer = Option<OpenTelemetryLayer<Registry, SdkTracer>>;
+
+/// Layer exporting spans, empty until the configuration has been read
+/// Logging starts before the configuration is loaded, so the layer is
+/// installed empty and filled in by `start_tracing`.
+pub type TraceLayer = reload::Layer<SpanLayer, Registry>;
+pub type TraceHandle = reload::Handle<SpanLayer, Registry>;
+
+pub fn trace_layer() -> (TraceLayer, TraceHandle) {
+    reload::Layer::new(None)
+}
+
+fn signal_url(config: &OtlpConfig, signal: &str) -> String {
+    let endpoint = config.endpoint.as_deref().unwrap_or(DEFAULT_ENDPOINT);
+    format!("{}/v1/{}", endpoint.trim_end_matches('/'), signal)
+}
+
+fn resource(config: &OtlpConfig) -> Resource {
+    let service_name = config.service_name.clone().unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+    Resource::builder().with_service_name(service_name).build()
+}
+
+/// Start exporting spans to the configured collector
+/// Returns the provider, to be shut down on exit so buffered spans are sent.
+pub fn start_tracing(config: &OtlpConfig, handle: &TraceHandle) -> Result<Option<SdkTracerProvider>, Box<dyn Error>> {
+    if config.traces == Some(false) {
+        return Ok(None);
+    }
+    let url = signal_url(config, "traces");
+    let exporter = SpanExporter::builder().with_http().with_endpoint(&url).build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource(config))
+        .build();
+    handle.reload(Some(tracing_opentelemetry::layer().with_tracer(provider.tracer(SCOPE))))?;
+    info!(endpoint = %url, "Exporting spans over OTLP");
+    Ok(Some(provider))
+}
+
+/// Remote parent every node gives the spans of one sync
+/// Derived from the sync ID, so the observe, publish, receive, request, serve and
+/// write spans of one version share a trace across nodes without a trace
+/// context on the wire.
+fn sync_parent(sync: &str) -> SpanContext {
+    let digest = Sha256::digest(sync.as_bytes());
+    let mut trace_id = [0u8; 16];
+    trace_id.copy_from_slice(&digest[..16]);
+    let mut span_id = [0u8; 8];
+    span_id.copy_from_slice(&digest[16..24]);
+    SpanContext::new(TraceId::from_bytes(trace_id), SpanId::from_bytes(span_id), TraceFlags::SAMPLED, true, TraceState::default())
+}
+
+/// Put a span in the trace of its sync
+pub fn join_sync_trace(span: &tracing::Span, sync: &str) {
+    span.set_parent(Context::new().with_remote_span_context(sync_parent(sync)));
+}
+
+/// Latest value of each series, by instrument name
+type Latest = Arc<RwLock<HashMap<String, Vec<(Vec<KeyValue>, u64)>>>>;
+
+/// The Prometheus metrics, exported to the configured collector as well
+/// Instruments are observable and read the samples of the last `update`, so the
+/// collector sees the same values as the textfile and /metrics.
+pub struct MetricsExport {
+    provider: SdkMeterProvider,
+    meter: Meter,
+    latest: Latest,
+    registered: HashSet<String>,
+}
+
+impl MetricsExport {
+    pub fn start(config: &OtlpConfig) -> Result<Option<Self>, Box<dyn Error>> {
+        if config.metrics == Some(false) {
+            return Ok(None);
+        }
+        let url = signal_url(config, "metrics");
+        let exporter = MetricExporter::builder().with_http().with_endpoint(&url).build()?;
+        let interval = config.export_interval_secs.map_or(DEFAULT_EXPORT_INTERVAL, Duration::from_secs);
+        let reader = PeriodicReader::builder(exporter).with_interval(interval).build();
+        let provider = SdkMeterProvider::builder().with_reader(reader).with_resource(resource(config)).build();
+        info!(endpoint = %url, interval_secs = interval.as_secs(), "Exporting metrics over OTLP");
+        Ok(Some(Self {
+            meter: provider.meter(SCOPE),
+            provider,
+            latest: Arc::default(),
+            registered: HashSet::new(),
+        }))
+    }
+
+    /// Publish the samples of a refresh, registering instruments for new families
+    pub fn update(&mut self, samples: &[Sample]) {
+        for sample in samples {
+            let name = instrument_name(sample);
+            if self.registered.insert(name.clone()) {
+                self.register(&name, sample);
+            }
+        }
+        if let Ok(mut latest) = self.latest.write() {
+            *latest = group(samples);
+        }
+    }
+
+    fn register(&self, name: &str, sample: &Sample) {
+        let latest = self.latest.clone();
+        let series = name.to_string();
+        let read = move || latest.read().ok().and_then(|latest| latest.get(&series).cloned()).unwrap_or_default();
+        if sample.kind == "counter" {
+            self.meter.u64_observable_counter(name.to_string())
+                .with_description(sample.help.clone())
+                .with_callback(move |observer| {
+                    for (labels, value) in read() {
+                        observer.observe(value, &labels);
+                    }
+                })
+                .build();
+        } else {
+            self.meter.u64_observable_gauge(name.to_string())
+                .with_description(sample.help.clone())
+                .with_callback(move |observer| {
+                    for (labels, value) in read() {
+                        observer.observe(value, &labels);
+                    }
+                })
+                .build();
+        }
+    }
+
+    /// Send the last values and stop exporting
+    pub fn shutdown(&self) {
+        if let Err(e) = self.provider.shutdown() {
+            warn!(error = %e, "Failed to flush OTLP metrics");
+        }
+    }
+}
+
+/// OpenTelemetry name of a sample's instrument
+/// Counters drop the `_total` suffix, which Prometheus-compatible backends add back.
+fn instrument_name(sample: &Sample) -> String {
+    match sample.name.strip_suffix("_total") {
+        Some(name) if sample.kind == "counter" => name.to_string(),
+        _ => sample.name.clone(),
+    }
+}
+
+fn group(samples: &[Sample]) -> HashMap<String, Vec<(Vec<KeyValue>, u64)>> {
+    let mut grouped: HashMap<String, Vec<(Vec<KeyValue>, u64)>> = HashMap::new();
+    for sample in samples {
+        let labels = sample.labels.iter().map(|(key, value)| KeyValue::new(key.clone(), value.clone())).collect();
+        grouped.entry(instrument_name(sample)).or_default().push((labels, sample.value));
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::metrics::Metrics;
+
+    #[test]
+    fn test_samples_map_to_otlp_series() {
+        let config = OtlpConfig { endpoint: Some("http://collector:4318/".to_string()), ..OtlpConfig::default() };
+        assert_eq!(signal_url(&config, "traces"), "http://collector:4318/v1/traces");
+        assert_eq!(signal_url(&OtlpConfig::default(), "metrics"), "http://localhost:4318/v1/metrics");
+
+        let mut metrics = Metrics::new();
+        metrics.describe("syndactyl_auth_failures_total", "counter", "Failed authentication attempts");
+        metrics.sample("syndactyl_auth_failures_total", &[("peer", "a")], 2);
+        metrics.sample("syndactyl_auth_failures_total", &[("peer", "b")], 5);
+        metrics.describe("syndactyl_buffered_total", "gauge", "Not a counter despite its name");
+        metrics.sample("syndactyl_buffered_total", &[], 7);
+
+        let grouped = group(metrics.samples());
+        assert_eq!(grouped["syndactyl_auth_failures"], vec![
+            (vec![KeyValue::new("peer", "a")], 2),
+            (vec![KeyValue::new("peer", "b")], 5),
+        ]);
+        assert_eq!(grouped["syndactyl_buffered_total"], vec![(Vec::new(), 7)]);
+        assert_eq!(grouped.len(), 2);
+    }
+
+    #[test]
+    fn test_every_node_puts_a_sync_in_the_same_trace() {
+        let sync = crate::core::sync_id::sync_id("docs", "a.txt", Some("h1"));
+        let parent = sync_parent(&sync);
+        assert!(parent.is_valid() && parent.is_remote() && parent.is_sampled());
+        assert_eq!(parent, sync_parent(&sync));
+        let other = crate::core::sync_id::sync_id("docs", "a.txt", Some("h2"));
+        assert_ne!(sync_parent(&other).trace_id(), parent.trace_id());
+    }
+}
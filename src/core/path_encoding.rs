@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// The form a relative path takes in announcements and requests
+/// macOS hands out decomposed (NFD) names while Linux keeps whatever was written,
+/// usually composed (NFC); everything on the wire uses NFC so both sides agree.
+pub fn wire_path(path: &str) -> String {
+    path.nfc().collect()
+}
+
+/// Find the on-disk spelling of a wire path under `base`
+/// Each component that doesn't exist as given is matched against the directory's
+/// entries by normalized name. Components with no match keep the wire spelling.
+pub fn resolve_local(base: &Path, relative: &Path) -> PathBuf {
+    let mut local = PathBuf::new();
+    for component in relative.components() {
+        let name = component.as_os_str();
+        let exact = base.join(&local).join(name);
+        if exact.exists() {
+            local.push(name);
+            continue;
+        }
+        let matched = name.to_str().map(wire_path).and_then(|wanted| {
+            fs::read_dir(base.join(&local)).ok()?
+                .flatten()
+                .map(|entry| entry.file_name())
+                .find(|entry| entry.to_str().is_some_and(|entry| wire_path(entry) == wanted))
+        });
+        match matched {
+            Some(entry) => local.push(entry),
+            None => local.push(name),
+        }
+    }
+    local
+}
+
+/// Characters Windows filesystems reject, besides control characters
+const RESERVED_CHARS: &str = "<>:\"\\|?*";
+
+/// Device names Windows won't accept as a file name, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Start of the private-use block characters are escaped into, as Cygwin and WSL do
+const ESCAPE_BASE: u32 = 0xF000;
+
+/// Whether names are escaped for Windows unless configured otherwise
+pub const ESCAPE_BY_DEFAULT: bool = cfg!(windows);
+
+fn escape_char(c: char) -> char {
+    char::from_u32(ESCAPE_BASE + c as u32).unwrap_or(c)
+}
+
+/// Make one path component storable on Windows
+/// Reserved and control characters, trailing dots and spaces, and the last letter
+/// of device names are moved into the private-use range, which `unescape_name` reverses.
+pub fn escape_name(name: &str) -> String {
+    let mut escaped: Vec<char> = name.chars()
+        .map(|c| if (c as u32) < 0x20 || RESERVED_CHARS.contains(c) { escape_char(c) } else { c })
+        .collect();
+    for c in escaped.iter_mut().rev() {
+        if *c != '.' && *c != ' ' {
+            break;
+        }
+        *c = escape_char(*c);
+    }
+    let stem_len = escaped.iter().position(|c| *c == '.').unwrap_or(escaped.len());
+    let stem: String = escaped[..stem_len].iter().collect();
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(&stem)) {
+        escaped[stem_len - 1] = escape_char(escaped[stem_len - 1]);
+    }
+    escaped.into_iter().collect()
+}
+
+/// Recover the original name from an escaped one
+/// Separators are never escaped, so this works on whole relative paths too.
+pub fn unescape_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c as u32 {
+            code @ 0xF001..=0xF07F => char::from_u32(code - ESCAPE_BASE).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Apply `escape_name` to every component of a relative path
+pub fn escape_path(relative: &Path) -> PathBuf {
+    relative.components()
+        .map(|component| match component.as_os_str().to_str() {
+            Some(name) => PathBuf::from(escape_name(name)),
+            None => PathBuf::from(component.as_os_str()),
+        })
+        .collect()
+}
+
+/// Local spellings of paths whose on-disk form differs from the wire form,
+/// keyed by observer then wire path
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LocalNames {
+    pub observers: BTreeMap<String, BTreeMap<String, String>>,
+    /// Changed since the network manager last copied it into the state store
+    #[serde(skip)]
+    pub dirty: bool,
+}
+
+/// Local spellings shared between storage backends and the network manager
+pub type SharedLocalNames = Arc<RwLock<LocalNames>>;
+
+impl LocalNames {
+    pub fn get(&self, observer: &str, wire: &str) -> Option<&String> {
+        self.observers.get(observer)?.get(wire)
+    }
+
+    pub fn insert(&mut self, observer: &str, wire: String, local: String) {
+        let entries = self.observers.entry(observer.to_string()).or_default();
+        if entries.get(&wire) != Some(&local) {
+            entries.insert(wire, local);
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, observer: &str, wire: &str) {
+        if self.observers.get_mut(observer).and_then(|entries| entries.remove(wire)).is_some() {
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_decomposed_names_resolve_to_local_spelling() {
+        let temp_dir = TempDir::new().unwrap();
+        // "Café/Résumé.txt" as macOS would write it
+        let dir_nfd = "Cafe\u{301}";
+        let file_nfd = "Re\u{301}sume\u{301}.txt";
+        fs::create_dir(temp_dir.path().join(dir_nfd)).unwrap();
+        fs::write(temp_dir.path().join(dir_nfd).join(file_nfd), b"cv").unwrap();
+
+        let wire = wire_path(&format!("{}/{}", dir_nfd, file_nfd));
+        assert_eq!(wire, "Caf\u{e9}/R\u{e9}sum\u{e9}.txt");
+
+        let local = resolve_local(temp_dir.path(), Path::new(&wire));
+        assert_eq!(local, Path::new(dir_nfd).join(file_nfd));
+
+        // New files keep the wire spelling
+        let new = resolve_local(temp_dir.path(), Path::new("Caf\u{e9}/new.txt"));
+        assert_eq!(new, Path::new(dir_nfd).join("new.txt"));
+    }
+
+    #[test]
+    fn test_escaping_round_trips() {
+        for name in ["report:final?.txt", "trailing. ", "con.txt", "Aux", "plain.txt", "a<b>|c*\"d"] {
+            let escaped = escape_name(name);
+            assert!(!escaped.chars().any(|c| RESERVED_CHARS.contains(c)));
+            assert_eq!(unescape_name(&escaped), name);
+        }
+        assert_eq!(escape_name("plain.txt"), "plain.txt");
+        assert_ne!(escape_name("con.txt").to_ascii_uppercase(), "CON.TXT");
+        assert!(!escape_name("trailing. ").ends_with(' '));
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::core::config::PowerConfig;
+
+/// Where the kernel exposes batteries and AC adapters (the same data upower reads)
+pub const POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+/// Whether the power source can be read on this platform
+pub const POWER_SOURCE_BACKEND: bool = cfg!(any(target_os = "linux", target_os = "macos", windows));
+
+/// Default battery percentage below which work is throttled
+pub const DEFAULT_BATTERY_THRESHOLD: u8 = 20;
+
+/// Default size above which transfers count as "large" and are paused on low battery
+pub const DEFAULT_LARGE_TRANSFER_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Snapshot of the machine's power source
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    /// Remaining charge of all batteries together, if any are present
+    pub battery_percent: Option<u8>,
+    /// Whether hashing and large transfers are currently throttled
+    pub constrained: bool,
+}
+
+/// Read the power source from a sysfs power_supply directory
+/// Returns None on machines without a battery (desktops, servers). With several
+/// batteries the charge is their energy together, so a nearly empty second
+/// battery doesn't count as the whole machine running low.
+pub fn read_power_supply(root: &Path) -> Option<(bool, u8)> {
+    let mut ac_online = false;
+    let mut discharging = false;
+    // (batteries, now, full) summed over batteries reporting energy, else charge
+    let (mut energy, mut charge) = ((0, 0u64, 0u64), (0, 0u64, 0u64));
+    let mut capacities = Vec::new();
+
+    for entry in fs::read_dir(root).ok()?.flatten() {
+        let dir = entry.path();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok().map(|s| s.trim().to_string());
+        match read("type").as_deref() {
+            Some("Mains") | Some("USB") => {
+                ac_online |= read("online").as_deref() == Some("1");
+            }
+            Some("Battery") => {
+                // Peripheral batteries (mice, headsets) report scope "Device"
+                if read("scope").as_deref() == Some("Device") {
+                    continue;
+                }
+                let level = |prefix: &str| {
+                    let value = |name: &str| read(&format!("{}_{}", prefix, name)).and_then(|v| v.parse::<u64>().ok());
+                    value("now").zip(value("full")).filter(|(_, full)| *full > 0)
+                };
+                let Some(capacity) = read("capacity").and_then(|c| c.parse::<u8>().ok()) else {
+                    continue;
+                };
+                capacities.push(capacity);
+                if let Some((now, full)) = level("energy") {
+                    energy = (energy.0 + 1, energy.1 + now, energy.2 + full);
+                } else if let Some((now, full)) = level("charge") {
+                    charge = (charge.0 + 1, charge.1 + now, charge.2 + full);
+                }
+                discharging |= read("status").as_deref() == Some("Discharging");
+            }
+            _ => {}
+        }
+    }
+
+    if capacities.is_empty() {
+        return None;
+    }
+    // Energy and charge can't be added up, so mixed or missing readings fall back to the mean capacity
+    let total = |(batteries, now, full): (usize, u64, u64)| (batteries == capacities.len()).then(|| (now * 100 / full).min(100) as u8);
+    let percent = total(energy)
+        .or_else(|| total(charge))
+        .unwrap_or_else(|| (capacities.iter().map(|c| *c as u32).sum::<u32>() / capacities.len() as u32) as u8);
+    Some((discharging && !ac_online, percent))
+}
+
+/// Read the machine's power source, from sysfs
+#[cfg(target_os = "linux")]
+pub fn read_power_source() -> Option<(bool, u8)> {
+    read_power_supply(Path::new(POWER_SUPPLY_ROOT))
+}
+
+/// Read the machine's power source, from `pmset`, which reports what IOKit's power sources say
+#[cfg(target_os = "macos")]
+pub fn read_power_source() -> Option<(bool, u8)> {
+    let output = std::process::Command::new("pmset").args(["-g", "batt"]).output().ok()?;
+    parse_pmset(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Read the machine's power source, from GetSystemPowerStatus
+#[cfg(windows)]
+pub fn read_power_source() -> Option<(bool, u8)> {
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    // SAFETY: GetSystemPowerStatus only writes into the zeroed struct we pass it
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+    // SAFETY: status is valid for writes for the duration of the call
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    // Flag 128 means no system battery, 255 an unknown status
+    if status.BatteryFlag & 128 != 0 || status.BatteryLifePercent > 100 {
+        return None;
+    }
+    Some((status.ACLineStatus == 0, status.BatteryLifePercent))
+}
+
+/// The power source can't be read here, so the machine counts as on AC power
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn read_power_source() -> Option<(bool, u8)> {
+    None
+}
+
+/// Power source and charge in the output of `pmset -g batt`
+/// With several batteries the charge is their mean, as pmset gives no capacities.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_pmset(output: &str) -> Option<(bool, u8)> {
+    let mut lines = output.lines();
+    let on_battery = lines.next()?.contains("'Battery Power'");
+    let percents: Vec<u32> = lines
+        .filter(|line| line.contains("InternalBattery"))
+        .filter_map(|line| line.split_whitespace().find_map(|word| word.strip_suffix("%;")?.parse().ok()))
+        .collect();
+    if percents.is_empty() {
+        return None;
+    }
+    Some((on_battery, (percents.iter().sum::<u32>() / percents.len() as u32).min(100) as u8))
+}
+
+/// Shared view of whether the machine is on low battery
+/// Cloned into the watcher threads; refreshed periodically by the network manager.
+#[derive(Clone)]
+pub struct PowerMonitor {
+    enabled: bool,
+    threshold: u8,
+    large_transfer_bytes: u64,
+    constrained: Arc<AtomicBool>,
+}
+
+impl PowerMonitor {
+    /// Build a monitor from config; without a `power` section it never reports constraints
+    pub fn new(config: Option<&PowerConfig>) -> Self {
+        if config.is_some() && !POWER_SOURCE_BACKEND {
+            warn!("The power source can't be read on this platform; the power settings have no effect");
+        }
+        Self {
+            enabled: config.is_some(),
+            threshold: config.and_then(|c| c.battery_threshold).unwrap_or(DEFAULT_BATTERY_THRESHOLD),
+            large_transfer_bytes: config.and_then(|c| c.large_transfer_bytes).unwrap_or(DEFAULT_LARGE_TRANSFER_BYTES),
+            constrained: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether hashing and large transfers should currently be throttled
+    pub fn is_constrained(&self) -> bool {
+        self.constrained.load(Ordering::Relaxed)
+    }
+
+    /// Whether a transfer of `size` bytes should wait for AC power
+    pub fn should_defer_transfer(&self, size: u64) -> bool {
+        self.is_constrained() && size >= self.large_transfer_bytes
+    }
+
+    /// Re-read the power source, returning the new status
+    pub fn refresh(&self) -> Option<PowerStatus> {
+        if !self.enabled {
+            return None;
+        }
+        let status = self.evaluate(read_power_source());
+        self.constrained.store(status.constrained, Ordering::Relaxed);
+        Some(status)
+    }
+
+    fn evaluate(&self, supply: Option<(bool, u8)>) -> PowerStatus {
+        match supply {
+            Some((on_battery, percent)) => PowerStatus {
+                on_battery,
+                battery_percent: Some(percent),
+                constrained: on_battery && percent < self.threshold,
+            },
+            None => PowerStatus {
+                on_battery: false,
+                battery_percent: None,
+                constrained: false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), format!("{}\n", contents)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reads_battery_and_ac() {
+        let root = TempDir::new().unwrap();
+        supply(root.path(), "AC", &[("type", "Mains"), ("online", "0")]);
+        supply(root.path(), "BAT0", &[("type", "Battery"), ("capacity", "15"), ("status", "Discharging")]);
+        supply(root.path(), "hid-mouse", &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")]);
+        assert_eq!(read_power_supply(root.path()), Some((true, 15)));
+
+        supply(root.path(), "AC", &[("online", "1")]);
+        assert_eq!(read_power_supply(root.path()), Some((false, 15)));
+
+        // Two batteries count together, by what they hold rather than their percentages
+        supply(root.path(), "BAT1", &[("type", "Battery"), ("capacity", "95"), ("energy_now", "38000000"), ("energy_full", "40000000")]);
+        supply(root.path(), "BAT0", &[("energy_now", "3000000"), ("energy_full", "20000000")]);
+        assert_eq!(read_power_supply(root.path()), Some((false, 68)));
+
+        let pmset = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t42%; discharging; 2:13 remaining present: true\n";
+        assert_eq!(parse_pmset(pmset), Some((true, 42)));
+        assert_eq!(parse_pmset("Now drawing from 'AC Power'\n"), None);
+
+        let desktop = TempDir::new().unwrap();
+        supply(desktop.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        assert_eq!(read_power_supply(desktop.path()), None);
+    }
+
+    #[test]
+    fn test_constrained_below_threshold_on_battery() {
+        let monitor = PowerMonitor::new(Some(&PowerConfig {
+            battery_threshold: Some(30),
+            large_transfer_bytes: None,
+        }));
+        assert!(monitor.evaluate(Some((true, 25))).constrained);
+        assert!(!monitor.evaluate(Some((true, 35))).constrained);
+        assert!(!monitor.evaluate(Some((false, 5))).constrained);
+        assert!(!monitor.evaluate(None).constrained);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
+use crate::core::config::ProfileConfig;
+
+/// A named set of observers and peers to sync with
+/// Anything a profile leaves out is not restricted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    observers: Option<HashSet<String>>,
+    peers: Option<HashSet<String>>,
+}
+
+impl Profile {
+    pub fn from_config(name: &str, config: &ProfileConfig, known_observers: &HashSet<String>) -> Result<Self, String> {
+        if let Some(unknown) = config.observers.iter().flatten().find(|o| !known_observers.contains(*o)) {
+            return Err(format!("Profile {} enables unknown observer '{}'", name, unknown));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            observers: config.observers.as_ref().map(|o| o.iter().cloned().collect()),
+            peers: config.peers.as_ref().map(|p| p.iter().cloned().collect()),
+        })
+    }
+
+    /// The profile used when none is selected: everything syncs
+    pub fn unrestricted() -> Self {
+        Self::default()
+    }
+
+    pub fn allows_observer(&self, observer: &str) -> bool {
+        self.observers.as_ref().is_none_or(|observers| observers.contains(observer))
+    }
+
+    pub fn allows_peer(&self, peer: &str) -> bool {
+        self.peers.as_ref().is_none_or(|peers| peers.contains(peer))
+    }
+}
+
+/// Parse every configured profile, checking that they refer to real observers
+pub fn load_profiles(configs: &HashMap<String, ProfileConfig>, known_observers: &HashSet<String>) -> Result<HashMap<String, Profile>, String> {
+    configs.iter()
+        .map(|(name, config)| Ok((name.clone(), Profile::from_config(name, config, known_observers)?)))
+        .collect()
+}
+
+/// The profile to start with: the one picked at runtime, else the configured default
+/// A picked profile since removed from the configuration falls back to syncing
+/// everything rather than stopping the daemon.
+pub fn initial(profiles: &HashMap<String, Profile>, selected: Option<&str>, default: Option<&str>) -> Result<Profile, String> {
+    match (selected, default) {
+        (Some(name), _) => Ok(profiles.get(name).cloned().unwrap_or_else(|| {
+            warn!(profile = %name, "Selected sync profile is no longer configured, syncing everything");
+            Profile::unrestricted()
+        })),
+        (None, Some(name)) => profiles.get(name).cloned().ok_or_else(|| format!("Unknown sync profile '{}'", name)),
+        (None, None) => Ok(Profile::unrestricted()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_restricts_only_what_it_lists() {
+        let known: HashSet<String> = ["docs", "media"].iter().map(|s| s.to_string()).collect();
+        let travel = Profile::from_config("travel", &ProfileConfig {
+            observers: Some(vec!["docs".to_string()]),
+            peers: None,
+        }, &known).unwrap();
+        assert!(travel.allows_observer("docs"));
+        assert!(!travel.allows_observer("media"));
+        assert!(travel.allows_peer("12D3KooWAnyPeer"));
+
+        let bad = ProfileConfig { observers: Some(vec!["music".to_string()]), peers: None };
+        assert!(Profile::from_config("bad", &bad, &known).is_err());
+    }
+
+    #[test]
+    fn test_a_removed_selected_profile_falls_back_to_unrestricted() {
+        let known: HashSet<String> = ["docs"].iter().map(|s| s.to_string()).collect();
+        let configs = HashMap::from([("home".to_string(), ProfileConfig { observers: Some(vec!["docs".to_string()]), peers: None })]);
+        let profiles = load_profiles(&configs, &known).unwrap();
+        assert_eq!(initial(&profiles, Some("home"), None).unwrap().name, "home");
+        assert_eq!(initial(&profiles, None, Some("home")).unwrap().name, "home");
+        assert_eq!(initial(&profiles, Some("travel"), Some("home")).unwrap(), Profile::unrestricted());
+        assert_eq!(initial(&profiles, None, None).unwrap(), Profile::unrestricted());
+        assert!(initial(&profiles, None, Some("travel")).is_err());
+    }
+}
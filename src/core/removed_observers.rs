@@ -0,0 +1,71 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// How long peers are told about a removed observer (7 days)
+/// Long enough for a peer that was away for a while to hear it from a heartbeat.
+pub const NOTICE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Observers dropped from the configuration, kept across restarts
+/// Peers only learn an observer is gone if we tell them; otherwise they keep
+/// announcing its files to us and asking us for them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RemovedObservers {
+    /// Observers configured when the daemon last started
+    configured: BTreeSet<String>,
+    /// Unix timestamp each removed observer was noticed missing at
+    removed: BTreeMap<String, u64>,
+}
+
+impl RemovedObservers {
+    /// Compare the observers now configured with the last run's, returning those newly removed
+    /// An observer added back is no longer reported as removed.
+    pub fn update<'a>(&mut self, configured: impl IntoIterator<Item = &'a str>, now: u64) -> Vec<String> {
+        let configured: BTreeSet<String> = configured.into_iter().map(str::to_string).collect();
+        let newly_removed: Vec<String> = self.configured.difference(&configured).cloned().collect();
+        for name in &newly_removed {
+            self.removed.insert(name.clone(), now);
+        }
+        self.removed.retain(|name, _| !configured.contains(name));
+        self.configured = configured;
+        newly_removed
+    }
+
+    /// Stop announcing removals older than `period`
+    pub fn expire(&mut self, now: u64, period: Duration) {
+        self.removed.retain(|_, removed_at| removed_at.saturating_add(period.as_secs()) > now);
+    }
+
+    /// Observers peers should be told we no longer sync
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.removed.keys()
+    }
+
+    pub fn contains(&self, observer: &str) -> bool {
+        self.removed.contains_key(observer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removed_observers_are_reported_until_readded_or_expired() {
+        let mut removed = RemovedObservers::default();
+        assert!(removed.update(["docs", "photos"], 100).is_empty());
+        assert_eq!(removed.update(["docs"], 200), vec!["photos".to_string()]);
+        assert!(removed.contains("photos"));
+
+        // Still reported after later restarts, but only once as newly removed
+        assert!(removed.update(["docs"], 300).is_empty());
+        assert_eq!(removed.names().collect::<Vec<_>>(), vec!["photos"]);
+
+        removed.update(["docs", "photos"], 400);
+        assert!(!removed.contains("photos"));
+
+        removed.update(["photos"], 500);
+        removed.expire(500 + NOTICE_PERIOD.as_secs(), NOTICE_PERIOD);
+        assert!(!removed.contains("docs"));
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use chrono::{Datelike, Local, Timelike, Weekday};
+use crate::core::config::SyncWindowConfig;
+
+/// What transfers are allowed to do at a given moment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferPolicy {
+    Normal,
+    /// Downloads are limited to this many bytes per second
+    Limited(u64),
+    /// No new downloads start and in-flight ones stop requesting chunks
+    Paused,
+}
+
+/// A parsed sync window
+#[derive(Debug, Clone, PartialEq)]
+struct SyncWindow {
+    /// Days the window starts on; empty means every day
+    days: Vec<Weekday>,
+    /// Minutes since local midnight
+    start: u32,
+    end: u32,
+    limit_bytes_per_sec: Option<u64>,
+}
+
+impl SyncWindow {
+    fn applies_to(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, day: Weekday, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.applies_to(day) && minute >= self.start && minute < self.end
+        } else {
+            // Window runs past midnight: the tail belongs to the previous day's window
+            (self.applies_to(day) && minute >= self.start)
+                || (self.applies_to(day.pred()) && minute < self.end)
+        }
+    }
+}
+
+/// Time windows during which transfers are paused or rate limited
+/// Windows use local time. Announcements keep being collected while a window
+/// is active; the transfers they trigger run once it ends.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    windows: Vec<SyncWindow>,
+}
+
+impl Schedule {
+    pub fn from_config(windows: &[SyncWindowConfig]) -> Result<Self, String> {
+        let windows = windows.iter()
+            .map(|window| {
+                let days = match &window.days {
+                    Some(days) => days.iter().map(|day| parse_day(day)).collect::<Result<Vec<_>, _>>()?,
+                    None => Vec::new(),
+                };
+                Ok(SyncWindow {
+                    days,
+                    start: parse_time(&window.start)?,
+                    end: parse_time(&window.end)?,
+                    limit_bytes_per_sec: window.limit_kbps.map(|kbps| kbps * 1024),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { windows })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    /// Policy for a day and minute of the day; overlapping windows take the strictest setting
+    pub fn policy_at(&self, day: Weekday, minute: u32) -> TransferPolicy {
+        let mut policy = TransferPolicy::Normal;
+        for window in self.windows.iter().filter(|w| w.contains(day, minute)) {
+            policy = match (policy, window.limit_bytes_per_sec) {
+                (_, None) | (TransferPolicy::Paused, _) => TransferPolicy::Paused,
+                (TransferPolicy::Limited(current), Some(limit)) => TransferPolicy::Limited(current.min(limit)),
+                (TransferPolicy::Normal, Some(limit)) => TransferPolicy::Limited(limit),
+            };
+        }
+        policy
+    }
+
+    /// Policy right now, in local time
+    pub fn current_policy(&self) -> TransferPolicy {
+        if self.windows.is_empty() {
+            return TransferPolicy::Normal;
+        }
+        let now = Local::now();
+        self.policy_at(now.weekday(), now.hour() * 60 + now.minute())
+    }
+}
+
+/// Paces rate-limited downloads per peer, so every download from a peer shares its limit
+/// Each request reserves its bytes and may be sent once the bytes reserved
+/// before it have had their time at the limit.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    free_at: HashMap<K, Instant>,
+}
+
+impl<K: Hash + Eq + Clone> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self { free_at: HashMap::new() }
+    }
+}
+
+impl<K: Hash + Eq + Clone> RateLimiter<K> {
+    /// Reserve `bytes` from `peer` at `bytes_per_sec`, returning when they may be requested
+    pub fn reserve(&mut self, peer: &K, bytes: u64, bytes_per_sec: u64, now: Instant) -> Instant {
+        let free_at = self.free_at.entry(peer.clone()).or_insert(now);
+        let start = (*free_at).max(now);
+        *free_at = start + Duration::from_secs_f64(bytes as f64 / bytes_per_sec.max(1) as f64);
+        start
+    }
+
+    pub fn forget(&mut self, peer: &K) {
+        self.free_at.remove(peer);
+    }
+}
+
+/// Parse "HH:MM" into minutes since midnight
+fn parse_time(time: &str) -> Result<u32, String> {
+    let (hours, minutes) = time.split_once(':')
+        .ok_or_else(|| format!("Invalid time '{}', expected HH:MM", time))?;
+    let hours: u32 = hours.parse().map_err(|_| format!("Invalid hour in '{}'", time))?;
+    let minutes: u32 = minutes.parse().map_err(|_| format!("Invalid minute in '{}'", time))?;
+    // 24:00 is allowed as an end-of-day marker
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes != 0) {
+        return Err(format!("Time '{}' out of range", time));
+    }
+    Ok(hours * 60 + minutes)
+}
+
+fn parse_day(day: &str) -> Result<Weekday, String> {
+    match day.to_ascii_lowercase().as_str() {
+        "mon" | "monday" => Ok(Weekday::Mon),
+        "tue" | "tuesday" => Ok(Weekday::Tue),
+        "wed" | "wednesday" => Ok(Weekday::Wed),
+        "thu" | "thursday" => Ok(Weekday::Thu),
+        "fri" | "friday" => Ok(Weekday::Fri),
+        "sat" | "saturday" => Ok(Weekday::Sat),
+        "sun" | "sunday" => Ok(Weekday::Sun),
+        _ => Err(format!("Unknown day '{}'", day)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: Option<&[&str]>, start: &str, end: &str, limit_kbps: Option<u64>) -> SyncWindowConfig {
+        SyncWindowConfig {
+            days: days.map(|days| days.iter().map(|d| d.to_string()).collect()),
+            start: start.to_string(),
+            end: end.to_string(),
+            limit_kbps,
+        }
+    }
+
+    #[test]
+    fn test_weekday_pause_window() {
+        let schedule = Schedule::from_config(&[
+            window(Some(&["mon", "tue", "wed", "thu", "fri"]), "09:00", "17:00", None),
+        ]).unwrap();
+
+        assert_eq!(schedule.policy_at(Weekday::Mon, 9 * 60), TransferPolicy::Paused);
+        assert_eq!(schedule.policy_at(Weekday::Mon, 17 * 60), TransferPolicy::Normal);
+        assert_eq!(schedule.policy_at(Weekday::Sat, 12 * 60), TransferPolicy::Normal);
+    }
+
+    #[test]
+    fn test_overnight_and_overlapping_windows() {
+        let schedule = Schedule::from_config(&[
+            window(Some(&["fri"]), "22:00", "06:00", Some(100)),
+            window(None, "05:00", "05:30", Some(50)),
+        ]).unwrap();
+
+        assert_eq!(schedule.policy_at(Weekday::Fri, 23 * 60), TransferPolicy::Limited(100 * 1024));
+        // Friday's window carries on into Saturday morning
+        assert_eq!(schedule.policy_at(Weekday::Sat, 60), TransferPolicy::Limited(100 * 1024));
+        assert_eq!(schedule.policy_at(Weekday::Sat, 5 * 60 + 10), TransferPolicy::Limited(50 * 1024));
+        assert_eq!(schedule.policy_at(Weekday::Sun, 60), TransferPolicy::Normal);
+
+        assert!(Schedule::from_config(&[window(None, "25:00", "06:00", None)]).is_err());
+        assert!(Schedule::from_config(&[window(Some(&["someday"]), "01:00", "02:00", None)]).is_err());
+    }
+
+    #[test]
+    fn test_rate_limit_is_shared_by_a_peers_downloads() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        // A first chunk goes out at once; everything after it from the peer waits its turn
+        assert_eq!(limiter.reserve(&"a", 1000, 1000, now), now);
+        assert_eq!(limiter.reserve(&"a", 500, 1000, now), now + Duration::from_secs(1));
+        assert_eq!(limiter.reserve(&"a", 1000, 1000, now), now + Duration::from_millis(1500));
+        // Other peers have their own allowance
+        assert_eq!(limiter.reserve(&"b", 1000, 1000, now), now);
+        // Time spent idle isn't saved up for a burst
+        let later = now + Duration::from_secs(60);
+        assert_eq!(limiter.reserve(&"a", 1000, 1000, later), later);
+        assert_eq!(limiter.reserve(&"a", 1000, 1000, later), later + Duration::from_secs(1));
+        limiter.forget(&"a");
+        assert_eq!(limiter.reserve(&"a", 1000, 1000, now), now);
+    }
+}
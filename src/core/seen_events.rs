@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// How long an applied announcement is remembered (1 hour)
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most announcements remembered; the oldest are forgotten first
+pub const MAX_ENTRIES: usize = 20_000;
+
+/// Versions of files recently applied from peers, kept across restarts
+/// Gossip only deduplicates messages in memory, so after a restart a peer
+/// re-forwarding an old announcement would have it fetched or merged again,
+/// possibly over a newer local edit. Only the last version of each file is kept,
+/// so a file reverted to an earlier version is still applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SeenEvents {
+    /// Last version applied to each (observer, path)
+    applied: HashMap<String, Applied>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Applied {
+    version: String,
+    /// Unix timestamp it was applied at
+    at: u64,
+}
+
+fn key(observer: &str, path: &str) -> String {
+    format!("{}\0{}", observer, path)
+}
+
+impl SeenEvents {
+    /// Remember that `version` of a file was applied
+    pub fn record(&mut self, observer: &str, path: &str, version: &str, now: u64) {
+        self.applied.insert(key(observer, path), Applied { version: version.to_string(), at: now });
+        if self.applied.len() > MAX_ENTRIES {
+            // Drop the oldest tenth in one go rather than one entry per insert
+            let mut times: Vec<u64> = self.applied.values().map(|applied| applied.at).collect();
+            times.sort_unstable();
+            let cutoff = times[MAX_ENTRIES / 10];
+            self.applied.retain(|_, applied| applied.at > cutoff);
+        }
+    }
+
+    /// Whether `version` is the last one applied to the file
+    pub fn contains(&self, observer: &str, path: &str, version: &str) -> bool {
+        self.applied.get(&key(observer, path)).is_some_and(|applied| applied.version == version)
+    }
+
+    /// Whether the local copy is still the version last applied from a peer, unedited since
+    pub fn applied(&self, observer: &str, path: &str) -> bool {
+        self.applied.contains_key(&key(observer, path))
+    }
+
+    /// Forget the applied version once the file has moved on from it locally
+    /// `version` is the file's new hash, or `None` once it is deleted.
+    pub fn changed(&mut self, observer: &str, path: &str, version: Option<&str>) {
+        let key = key(observer, path);
+        if self.applied.get(&key).is_some_and(|applied| Some(applied.version.as_str()) != version) {
+            self.applied.remove(&key);
+        }
+    }
+
+    /// Forget announcements older than `ttl`, returning how many were dropped
+    pub fn expire(&mut self, now: u64, ttl: Duration) -> usize {
+        let before = self.applied.len();
+        self.applied.retain(|_, applied| applied.at.saturating_add(ttl.as_secs()) > now);
+        before - self.applied.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.applied.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applied_versions_are_remembered_until_they_expire() {
+        let mut seen = SeenEvents::default();
+        seen.record("docs", "a.txt", "h1", 100);
+        assert!(seen.contains("docs", "a.txt", "h1"));
+        assert!(!seen.contains("docs", "a.txt", "h2"));
+        assert!(!seen.contains("photos", "a.txt", "h1"));
+
+        // Survives a save and load
+        let reloaded: SeenEvents = serde_json::from_str(&serde_json::to_string(&seen).unwrap()).unwrap();
+        assert_eq!(reloaded, seen);
+
+        assert_eq!(seen.expire(100 + DEFAULT_TTL.as_secs(), DEFAULT_TTL), 1);
+        assert!(seen.is_empty());
+
+        for i in 0..=MAX_ENTRIES as u64 {
+            seen.record("docs", &format!("{}.txt", i), "h", i);
+        }
+        assert!(seen.len() < MAX_ENTRIES);
+        assert!(seen.contains("docs", &format!("{}.txt", MAX_ENTRIES), "h"));
+        assert!(!seen.contains("docs", "0.txt", "h"));
+    }
+
+    #[test]
+    fn test_a_file_reverted_to_an_earlier_version_is_applied_again() {
+        let mut seen = SeenEvents::default();
+        seen.record("docs", "a.txt", "A", 100);
+
+        // Applied from a peer: A then B, so A is no longer the version held
+        seen.record("docs", "a.txt", "B", 110);
+        assert!(!seen.contains("docs", "a.txt", "A"));
+        assert!(seen.contains("docs", "a.txt", "B"));
+
+        // Edited locally: the applied version is forgotten, the same one kept
+        seen.changed("docs", "a.txt", Some("B"));
+        assert!(seen.contains("docs", "a.txt", "B") && seen.applied("docs", "a.txt"));
+        seen.changed("docs", "a.txt", Some("C"));
+        assert!(!seen.contains("docs", "a.txt", "B") && !seen.applied("docs", "a.txt"));
+        assert!(seen.is_empty());
+
+        seen.record("docs", "a.txt", "A", 120);
+        seen.changed("docs", "a.txt", None);
+        assert!(!seen.contains("docs", "a.txt", "A"));
+    }
+}
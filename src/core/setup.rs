@@ -0,0 +1,254 @@
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use libp2p::identity::ed25519;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use crate::core::config::{BootstrapPeer, Config};
+use crate::core::config_migration::CURRENT_VERSION;
+
+/// Port suggested for new configurations
+pub const DEFAULT_PORT: u16 = 4001;
+
+/// Start of every pairing code, so one is told apart from an address
+const PAIRING_PREFIX: &str = "syn-";
+
+/// RFC 4648 base32 alphabet; pairing codes are read out and typed, so they have a single case
+const BASE32: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// One observer collected by the setup wizard
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewObserver {
+    pub name: String,
+    pub path: PathBuf,
+    pub shared_secret: String,
+}
+
+/// 32 bytes from the operating system's random source
+/// Drawn through libp2p's key generation, which every build includes.
+pub fn random_bytes() -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(ed25519::SecretKey::generate().as_ref());
+    bytes
+}
+
+/// A fresh shared secret: 32 random bytes, hex encoded
+pub fn generate_secret() -> String {
+    random_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Check an observer name; peers must use the same one to sync it
+pub fn check_name(name: &str, taken: &[NewObserver]) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        return Err("Use letters, digits, '-', '_' and '.'".to_string());
+    }
+    if taken.iter().any(|observer| observer.name == name) {
+        return Err(format!("There is already an observer named {}", name));
+    }
+    Ok(())
+}
+
+/// Resolve the directory an observer watches, expanding a leading `~`
+/// It may not exist yet, so the caller can offer to create it, but it can't be
+/// a file or overlap a directory another observer watches.
+pub fn resolve_path(path: &str, taken: &[NewObserver]) -> Result<PathBuf, String> {
+    let path = match path.strip_prefix('~') {
+        Some(rest) => dirs::home_dir().ok_or("Could not find home directory")?.join(rest.trim_start_matches(['/', '\\'])),
+        None => PathBuf::from(path),
+    };
+    if !path.is_absolute() {
+        return Err("Use an absolute path, e.g. ~/Documents".to_string());
+    }
+    if path.exists() && !path.is_dir() {
+        return Err(format!("{} is not a directory", path.display()));
+    }
+    let path = path.canonicalize().unwrap_or(path);
+    if let Some(other) = taken.iter().find(|observer| overlaps(&observer.path, &path)) {
+        return Err(format!("{} overlaps {}, which observer {} watches", path.display(), other.path.display(), other.name));
+    }
+    Ok(path)
+}
+
+fn overlaps(a: &Path, b: &Path) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+/// Parse a listen port, refusing one another program already listens on
+pub fn check_port(port: &str) -> Result<u16, String> {
+    let port: u16 = port.parse().map_err(|_| format!("'{}' is not a port number", port))?;
+    if port == 0 {
+        return Ok(port);
+    }
+    TcpListener::bind(("0.0.0.0", port))
+        .map(|_| port)
+        .map_err(|e| format!("Port {} can't be used: {}", port, e))
+}
+
+/// Bootstrap peer from the address another node listens on
+/// e.g. /ip4/192.168.1.10/tcp/4001/p2p/12D3KooW... or, for a WebSocket relay,
+/// /dns/relay.example.com/tcp/443/wss/p2p/12D3KooW...
+pub fn parse_bootstrap(address: &str) -> Result<BootstrapPeer, String> {
+    let parts: Vec<&str> = address.trim().trim_start_matches('/').split('/').collect();
+    let invalid = || format!("Expected an address like /ip4/192.168.1.10/tcp/{}/p2p/PEER_ID", DEFAULT_PORT);
+    let (host_kind, ip, port, websocket, peer_id) = match parts.as_slice() {
+        [host_kind, ip, "tcp", port, "p2p", peer_id] => (*host_kind, *ip, *port, false, *peer_id),
+        [host_kind, ip, "tcp", port, "wss", "p2p", peer_id] => (*host_kind, *ip, *port, true, *peer_id),
+        _ => return Err(invalid()),
+    };
+    match host_kind {
+        "ip4" if ip.parse::<std::net::Ipv4Addr>().is_ok() => {}
+        "ip6" | "dns" | "dns4" | "dns6" if websocket => {}
+        "ip6" | "dns" | "dns4" | "dns6" => return Err("Plain TCP peers need an /ip4 address; hostnames and IPv6 work over /wss".to_string()),
+        _ => return Err(invalid()),
+    }
+    port.parse::<u16>().map_err(|_| format!("'{}' is not a port number", port))?;
+    if peer_id.is_empty() || !peer_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("'{}' is not a peer ID", peer_id));
+    }
+    Ok(BootstrapPeer {
+        address: None,
+        ip: ip.to_string(),
+        port: port.to_string(),
+        peer_id: peer_id.to_string(),
+        websocket: websocket.then_some(true),
+    })
+}
+
+/// Pairing code for the address another device can dial us on
+/// The address is base32 encoded with a short checksum, so a mistyped code is
+/// refused instead of sending the new device to a wrong peer.
+pub fn pairing_code(address: &str) -> String {
+    let mut bytes = address.as_bytes().to_vec();
+    bytes.extend_from_slice(&Sha256::digest(address.as_bytes())[..2]);
+    let mut code = String::from(PAIRING_PREFIX);
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = block.iter().fold(0u64, |bits, byte| bits << 8 | *byte as u64);
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            code.push(BASE32[(bits >> (35 - i * 5) & 31) as usize] as char);
+        }
+    }
+    code
+}
+
+/// Bootstrap peer from a pairing code printed by `syndactyl id`
+pub fn parse_pairing_code(code: &str) -> Result<BootstrapPeer, String> {
+    let invalid = || "Not a valid pairing code; check it was copied completely".to_string();
+    let code = code.trim();
+    let encoded = code.get(..PAIRING_PREFIX.len())
+        .filter(|prefix| prefix.eq_ignore_ascii_case(PAIRING_PREFIX))
+        .map(|_| &code[PAIRING_PREFIX.len()..])
+        .ok_or_else(invalid)?;
+    let mut bytes = Vec::new();
+    let (mut bits, mut count) = (0u64, 0);
+    for c in encoded.chars().filter(|c| *c != '-') {
+        let value = BASE32.iter().position(|b| *b as char == c.to_ascii_uppercase()).ok_or_else(invalid)?;
+        bits = bits << 5 | value as u64;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    if bytes.len() < 2 {
+        return Err(invalid());
+    }
+    let (address, checksum) = bytes.split_at(bytes.len() - 2);
+    if Sha256::digest(address)[..2] != *checksum {
+        return Err(invalid());
+    }
+    parse_bootstrap(std::str::from_utf8(address).map_err(|_| invalid())?)
+}
+
+/// Bootstrap peer from either a pairing code or an address
+pub fn parse_peer(input: &str) -> Result<BootstrapPeer, String> {
+    if input.trim().starts_with('/') {
+        parse_bootstrap(input)
+    } else {
+        parse_pairing_code(input)
+    }
+}
+
+/// Configuration file contents for what the wizard collected
+/// Parsed back before it is returned, so what gets written is known to load.
+pub fn render(observers: &[NewObserver], port: u16, bootstrap: Option<&BootstrapPeer>) -> Result<String, String> {
+    if observers.is_empty() {
+        return Err("Add at least one observer".to_string());
+    }
+    let observers: Vec<_> = observers.iter()
+        .map(|observer| json!({
+            "name": observer.name,
+            "path": observer.path.to_string_lossy(),
+            "shared_secret": observer.shared_secret,
+        }))
+        .collect();
+    let bootstrap_peers: Vec<_> = bootstrap.into_iter()
+        .map(|peer| match peer.websocket {
+            Some(true) => json!({ "ip": peer.ip, "port": peer.port, "peer_id": peer.peer_id, "websocket": true }),
+            _ => json!({ "ip": peer.ip, "port": peer.port, "peer_id": peer.peer_id }),
+        })
+        .collect();
+    let value = json!({
+        "config_version": CURRENT_VERSION,
+        "observers": observers,
+        "network": {
+            "listen_addr": "0.0.0.0",
+            "port": port.to_string(),
+            "dht_mode": "server",
+            "bootstrap_peers": bootstrap_peers,
+        },
+    });
+    serde_json::from_value::<Config>(value.clone()).map_err(|e| format!("Generated configuration is invalid: {}", e))?;
+    serde_json::to_string_pretty(&value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wizard_input_is_validated_and_rendered() {
+        let peer = parse_bootstrap("/ip4/192.168.1.10/tcp/4001/p2p/12D3KooWExample").unwrap();
+        assert_eq!(peer.multiaddr(), "/ip4/192.168.1.10/tcp/4001/p2p/12D3KooWExample");
+        let relay = parse_bootstrap("/dns/relay.example.com/tcp/443/wss/p2p/12D3KooWRelay").unwrap();
+        assert_eq!(relay.multiaddr(), "/dns/relay.example.com/tcp/443/wss/p2p/12D3KooWRelay");
+        assert!(parse_bootstrap("/dns/host.example.com/tcp/4001/p2p/12D3KooWExample").is_err());
+        assert!(parse_bootstrap("192.168.1.10:4001").is_err());
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let docs = NewObserver {
+            name: "docs".to_string(),
+            path: resolve_path(&temp_dir.path().to_string_lossy(), &[]).unwrap(),
+            shared_secret: generate_secret(),
+        };
+        assert_eq!(docs.shared_secret.len(), 64);
+        assert_ne!(docs.shared_secret, generate_secret());
+        assert!(check_name("docs", std::slice::from_ref(&docs)).is_err());
+        assert!(check_name("my docs", &[]).is_err());
+        assert!(resolve_path(&temp_dir.path().join("nested").to_string_lossy(), std::slice::from_ref(&docs)).is_err());
+        assert!(resolve_path("relative/dir", &[]).is_err());
+
+        let rendered = render(&[docs], DEFAULT_PORT, Some(&peer)).unwrap();
+        let config: Config = serde_json::from_str(&rendered).unwrap();
+        assert_eq!(config.observers[0].name, "docs");
+        assert_eq!(config.network.unwrap().bootstrap_peers[0].peer_id, "12D3KooWExample");
+        assert!(render(&[], DEFAULT_PORT, None).is_err());
+    }
+
+    #[test]
+    fn test_pairing_codes_carry_the_address_and_catch_typos() {
+        let address = "/ip4/192.168.1.10/tcp/4001/p2p/12D3KooWExample";
+        let code = pairing_code(address);
+        assert!(code.starts_with("syn-"));
+        assert_eq!(parse_peer(&code).unwrap().multiaddr(), address);
+        assert_eq!(parse_peer(&code.to_lowercase()).unwrap().multiaddr(), address);
+        assert_eq!(parse_peer(address).unwrap().multiaddr(), address);
+
+        // A changed or cut off code is refused rather than naming another peer
+        let swapped = if code.as_bytes()[10] == b'Q' { "R" } else { "Q" };
+        let typo = format!("{}{}{}", &code[..10], swapped, &code[11..]);
+        assert!(parse_peer(&typo).is_err());
+        assert!(parse_peer(&code[..code.len() - 4]).is_err());
+        assert!(parse_peer("syn-").is_err());
+    }
+}
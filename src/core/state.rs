@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use crate::core::bandwidth::BandwidthStats;
+use crate::core::catalog::Catalog;
+use crate::core::merkle::FileIndex;
+use crate::core::path_encoding::LocalNames;
+use crate::core::tombstone::{Replicas, Tombstones};
+use crate::core::seen_events::SeenEvents;
+use crate::core::event_order::EventOrder;
+use crate::core::removed_observers::RemovedObservers;
+use crate::core::address_book::AddressBook;
+
+/// Daemon state persisted between runs
+/// Every section defaults when missing, so older state files keep loading.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct State {
+    #[serde(default)]
+    pub bandwidth: BandwidthStats,
+    /// Remote trees of on-demand observers
+    #[serde(default)]
+    pub catalog: Catalog,
+    /// Sync profile selected at runtime, kept across restarts
+    #[serde(default)]
+    pub active_profile: Option<String>,
+    /// On-disk spellings of paths whose Unicode form differs from the wire form
+    #[serde(default)]
+    pub local_names: LocalNames,
+    /// Hashes of local files, the basis of the Merkle trees gossiped in heartbeats
+    #[serde(default)]
+    pub index: FileIndex,
+    /// Recently deleted files, so stale copies on peers don't bring them back
+    #[serde(default)]
+    pub tombstones: Tombstones,
+    /// Peers syncing each observer, which must acknowledge deletions before they are pruned
+    #[serde(default)]
+    pub replicas: Replicas,
+    /// File versions recently applied from peers, so re-forwarded announcements aren't applied twice
+    #[serde(default)]
+    pub seen_events: SeenEvents,
+    /// Newest operation seen on each file, so announcements arriving out of order aren't applied
+    #[serde(default)]
+    pub event_order: EventOrder,
+    /// Observers dropped from the configuration, announced to peers for a while
+    #[serde(default)]
+    pub removed_observers: RemovedObservers,
+    /// Where connected peers were reachable, dialed again on startup
+    #[serde(default)]
+    pub address_book: AddressBook,
+    /// Local deletions held back by the mass-deletion guard, per observer
+    #[serde(default)]
+    pub held_deletions: BTreeMap<String, Vec<String>>,
+}
+
+/// First line of a state file, followed by the SHA-256 of the JSON below it
+const CHECKSUM_HEADER: &str = "syndactyl-state sha256=";
+
+/// JSON file backed store for `State`
+/// Writes go to a temporary file that is flushed to disk and renamed over the
+/// old one, so a crash mid-save never leaves a truncated state file behind.
+pub struct StateStore {
+    path: PathBuf,
+    state: State,
+    dirty: bool,
+    /// Where a corrupt state file found on open was moved to
+    recovered_from: Option<PathBuf>,
+}
+
+impl StateStore {
+    /// Open the store at `path`, starting from empty state if the file does not exist yet
+    /// A corrupt file is moved aside rather than failing startup; the caller
+    /// rebuilds what it can, see `recovered_from`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (state, recovered_from) = match load(path) {
+            Ok(state) => (state, None),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => (State::default(), Some(quarantine(path)?)),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            state,
+            dirty: recovered_from.is_some(),
+            recovered_from,
+        })
+    }
+
+    /// Where the corrupt state file went, if the store started empty because of it
+    pub fn recovered_from(&self) -> Option<&Path> {
+        self.recovered_from.as_deref()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Mutable access to the state, marking it as needing a save
+    pub fn state_mut(&mut self) -> &mut State {
+        self.dirty = true;
+        &mut self.state
+    }
+
+    /// Write the state to disk if it changed since the last save
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("json.tmp");
+        let mut file = fs::File::create(&tmp)?;
+        writeln!(file, "{}{:x}", CHECKSUM_HEADER, Sha256::digest(&json))?;
+        file.write_all(&json)?;
+        // Without this a power cut can leave the renamed file empty
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Read a state file without opening a store, e.g. for CLI reporting
+/// Fails with `InvalidData` if the file is corrupt.
+pub fn load(path: &Path) -> io::Result<State> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(State::default()),
+        Err(e) => return Err(e),
+    };
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    // Files written before checksums were added are plain JSON
+    let json = match contents.strip_prefix(CHECKSUM_HEADER.as_bytes()) {
+        Some(rest) => {
+            let newline = rest.iter().position(|byte| *byte == b'\n').ok_or_else(|| invalid("truncated checksum header".to_string()))?;
+            let (expected, json) = (String::from_utf8_lossy(&rest[..newline]), &rest[newline + 1..]);
+            if format!("{:x}", Sha256::digest(json)) != expected {
+                return Err(invalid("checksum mismatch".to_string()));
+            }
+            json
+        }
+        None => &contents[..],
+    };
+    serde_json::from_slice(json).map_err(|e| invalid(e.to_string()))
+}
+
+/// Move a corrupt state file aside, next to where it was, returning its new path
+pub fn quarantine(path: &Path) -> io::Result<PathBuf> {
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".corrupt-{}", stamp));
+    let moved = path.with_file_name(name);
+    fs::rename(path, &moved)?;
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+
+        let mut store = StateStore::open(&path).unwrap();
+        assert_eq!(store.state(), &State::default());
+        store.state_mut().bandwidth.record_received("docs", "peer-a", 42);
+        store.save().unwrap();
+
+        let reopened = StateStore::open(&path).unwrap();
+        assert_eq!(reopened.state(), store.state());
+        assert!(!dir.path().join("state.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_corrupt_state_is_detected_and_moved_aside() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state.json");
+        let mut store = StateStore::open(&path).unwrap();
+        store.state_mut().active_profile = Some("travel".to_string());
+        store.save().unwrap();
+
+        // A flipped byte still parses as JSON, but fails the checksum
+        let written = fs::read_to_string(&path).unwrap();
+        fs::write(&path, written.replace("travel", "trave1")).unwrap();
+        assert_eq!(load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let recovered = StateStore::open(&path).unwrap();
+        assert_eq!(recovered.state(), &State::default());
+        let moved = recovered.recovered_from().unwrap();
+        assert!(moved.exists() && !path.exists());
+
+        // State files from before checksums still load
+        fs::write(&path, r#"{"active_profile": "home"}"#).unwrap();
+        assert_eq!(load(&path).unwrap().active_profile.as_deref(), Some("home"));
+    }
+}
@@ -0,0 +1,1082 @@
+use std::fs::{self, File};
+use std::io;
+#[cfg(feature = "encryption")]
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+#[cfg(feature = "encryption")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+#[cfg(feature = "encryption")]
+use hkdf::Hkdf;
+#[cfg(feature = "encryption")]
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Digest};
+#[cfg(feature = "encryption")]
+use zeroize::Zeroize;
+use std::sync::Arc;
+use crate::core::config::{ObserverConfig, Owner};
+use crate::core::file_handler;
+use crate::core::in_use::{self, InUsePolicy, Replaced};
+use crate::core::path_encoding::{self, SharedLocalNames};
+use crate::core::tenant;
+
+#[cfg(feature = "encryption")]
+type HmacSha256 = Hmac<Sha256>;
+
+/// Where an observer's synced file contents live on disk
+/// The network layer reads and writes files only through this trait, so the
+/// on-disk representation can differ from the relative paths used on the wire.
+pub trait StorageBackend: Send + Sync {
+    /// Check whether a file exists at the relative path
+    fn exists(&self, relative_path: &Path) -> bool;
+
+    /// Size in bytes of the file content
+    fn size(&self, relative_path: &Path) -> io::Result<u64>;
+
+    /// Unix timestamp of the last modification of the stored file
+    fn modified_time(&self, relative_path: &Path) -> io::Result<u64>;
+
+    /// Modification time in nanoseconds, telling apart rewrites within one second
+    fn modified_nanos(&self, relative_path: &Path) -> io::Result<u128>;
+
+    /// SHA-256 hash of the file content
+    fn hash(&self, relative_path: &Path) -> io::Result<String>;
+
+    /// Read up to `len` bytes of content starting at `offset`
+    fn read_chunk(&self, relative_path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Write complete file content, returning the path written on disk
+    fn write_file(&self, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf>;
+
+    /// Add `content` to the end of a stored file that holds exactly `offset` bytes
+    /// Backends that can't extend a file in place rewrite it whole.
+    fn append_file(&self, relative_path: &Path, offset: u64, content: &[u8]) -> io::Result<PathBuf> {
+        let mut existing = self.read_chunk(relative_path, 0, offset as usize)?;
+        if existing.len() as u64 != offset || self.size(relative_path)? != offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stored file is not the version being appended to"));
+        }
+        existing.extend_from_slice(content);
+        self.write_file(relative_path, &existing)
+    }
+
+    /// Write content into a transaction's staging area without touching the final path
+    fn write_staged(&self, transaction: &str, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf>;
+
+    /// Move a staged file into its final location, returning the final path
+    fn commit_staged(&self, transaction: &str, relative_path: &Path) -> io::Result<PathBuf>;
+
+    /// Remove a transaction's staging area and anything left in it
+    fn discard_staged(&self, transaction: &str) -> io::Result<()>;
+
+    /// Remove a received file that was written aside but never moved into place
+    fn discard_incoming(&self, relative_path: &Path) -> io::Result<()>;
+
+    /// Whether received files can be written aside a chunk at a time
+    /// Backends that can't are handed whole files, buffered in memory.
+    fn supports_partial(&self) -> bool {
+        false
+    }
+
+    /// Write the bytes of a received file from `offset` on into the incoming area
+    /// Chunks arrive in order; the first truncates anything left from an earlier attempt.
+    fn write_partial(&self, _relative_path: &Path, _offset: u64, _content: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "storage can't write files a chunk at a time"))
+    }
+
+    /// Move a file written with `write_partial` into place, once it is known to hash to `hash`
+    fn commit_partial(&self, _relative_path: &Path, _hash: &str) -> io::Result<PathBuf> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "storage can't write files a chunk at a time"))
+    }
+
+    /// Try again to move a received file over a local copy that was in use
+    /// Writes report `in_use::waiting` when the received file was left aside for this.
+    fn retry_replace(&self, _relative_path: &Path, _last_attempt: bool) -> io::Result<Replaced> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "storage doesn't leave received files waiting"))
+    }
+
+    /// Remove everything an interrupted run left in the staging and incoming areas,
+    /// returning how many entries were removed
+    fn clean_orphans(&self) -> io::Result<usize>;
+}
+
+/// Where received files are written before they are moved into place, unless configured
+/// Inside the observer, so the final rename stays on one filesystem.
+pub fn default_temp_dir(base_path: &Path) -> PathBuf {
+    base_path.join(file_handler::INTERNAL_DIR).join("tmp")
+}
+
+/// The directory received files wait in for an observer, refusing one that would be synced
+/// Anything under the observer outside `.syndactyl` is watched like any other file,
+/// so partial downloads there would be announced to peers. A configured directory
+/// gets a subdirectory per observer, so observers can share one without emptying
+/// each other's files on startup.
+pub fn temp_dir_for(observer: &ObserverConfig) -> Result<PathBuf, String> {
+    let base_path = Path::new(&observer.path);
+    let Some(temp_dir) = observer.temp_dir.as_deref().map(PathBuf::from) else {
+        return Ok(default_temp_dir(base_path));
+    };
+    if temp_dir.starts_with(base_path) && !file_handler::is_internal_path(temp_dir.strip_prefix(base_path).unwrap_or(&temp_dir)) {
+        return Err(format!("temp_dir {} is inside the observer; use a directory outside it or under {}", temp_dir.display(), file_handler::INTERNAL_DIR));
+    }
+    let mut components = Path::new(&observer.name).components();
+    if !matches!((components.next(), components.next()), (Some(std::path::Component::Normal(_)), None)) {
+        return Err(format!("Observer {} can't have a temp_dir: its name isn't usable as a directory name", observer.name));
+    }
+    Ok(temp_dir.join(&observer.name))
+}
+
+/// Where a received file is written before it replaces the local copy
+/// Named after the path, so a newer version replaces a file still waiting for a reboot.
+fn incoming_path(temp_dir: &Path, relative_path: &Path) -> PathBuf {
+    let name = format!("{:x}", Sha256::digest(relative_path.to_string_lossy().as_bytes()));
+    temp_dir.join("incoming").join(name)
+}
+
+/// Replace `destination` with `staged`, keeping the old file's permissions
+/// A destination in use leaves `staged` aside, reported as `in_use::waiting`.
+fn replace_in_place(staged: &Path, destination: &Path, policy: InUsePolicy) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // An executable must stay executable
+    if let Ok(metadata) = fs::metadata(destination) {
+        fs::set_permissions(staged, metadata.permissions())?;
+    }
+    match in_use::replace(staged, destination, policy, false) {
+        Ok(Replaced::Now) => Ok(()),
+        Ok(_) => Err(in_use::waiting(destination)),
+        Err(e) => {
+            let _ = fs::remove_file(staged);
+            Err(e)
+        }
+    }
+}
+
+/// Bytes read at a time when hashing the start of a stored file
+const PREFIX_READ_SIZE: usize = 1024 * 1024;
+
+/// SHA-256 of the first `len` bytes of a stored file
+/// Matches the hash of an earlier version when the file has only been appended to.
+pub fn prefix_hash(storage: &dyn StorageBackend, relative_path: &Path, len: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hash_stored_prefix(&mut hasher, storage, relative_path, len)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn hash_stored_prefix(hasher: &mut Sha256, storage: &dyn StorageBackend, relative_path: &Path, len: u64) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < len {
+        let chunk = storage.read_chunk(relative_path, offset, PREFIX_READ_SIZE.min((len - offset) as usize))?;
+        if chunk.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("file is shorter than {} bytes", len)));
+        }
+        hasher.update(&chunk);
+        offset += chunk.len() as u64;
+    }
+    Ok(())
+}
+
+/// Check a freshly written file read back as `content`, removing it if not
+/// Done before the file replaces anything, so a bad write leaves the local copy
+/// alone. The read may be served from the page cache: this catches corruption on
+/// the way to the filesystem (faulty RAM, drivers, FUSE layers) rather than
+/// media errors the cache still hides.
+fn verify_written(path: &Path, content: &[u8], read_back: io::Result<String>) -> io::Result<()> {
+    let expected = format!("{:x}", Sha256::digest(content));
+    let problem = match read_back {
+        Ok(hash) if hash == expected => return Ok(()),
+        Ok(hash) => format!("read back with hash {} instead of {}", hash, expected),
+        Err(e) => format!("could not be read back: {}", e),
+    };
+    let _ = fs::remove_file(path);
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} {}", path.display(), problem)))
+}
+
+/// Check appended bytes read back as `content`, cutting the file back to `offset` if not
+/// The file then holds the version it was appended to, as after an interrupted append.
+fn verify_appended(path: &Path, offset: u64, content: &[u8]) -> io::Result<()> {
+    let problem = match file_handler::read_file_chunk(path, offset, content.len()) {
+        Ok(read_back) if read_back == content => return Ok(()),
+        Ok(_) => "read back differently".to_string(),
+        Err(e) => format!("could not be read back: {}", e),
+    };
+    File::options().write(true).open(path)?.set_len(offset)?;
+    Err(io::Error::new(io::ErrorKind::InvalidData, format!("bytes appended to {} {}", path.display(), problem)))
+}
+
+/// Refuse to write to `path` through a symlink anywhere below `root`
+/// Peers name paths inside the observer, and a symlink there such as `x -> /etc`
+/// would otherwise have `x/passwd` written outside it, as the daemon's user.
+#[cfg(unix)]
+fn refuse_symlinks(root: &Path, path: &Path) -> io::Result<()> {
+    let relative = path.strip_prefix(root)
+        .map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is outside {}", path.display(), root.display())))?;
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is a symlink, not writing through it", current.display())));
+            }
+            Ok(_) => {}
+            // Nothing below a missing directory can be a symlink yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn refuse_symlinks(_root: &Path, _path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Give a written file, and the directories between it and `root`, to the observer's owner
+fn hand_over(owner: Option<Owner>, root: &Path, path: &Path) -> io::Result<()> {
+    if owner.is_none() {
+        return Ok(());
+    }
+    let mut current = Some(path);
+    while let Some(path) = current.filter(|path| path.starts_with(root) && *path != root) {
+        tenant::hand_over(path, owner)?;
+        current = path.parent();
+    }
+    Ok(())
+}
+
+/// Staging directory for a transaction under an observer's temp directory
+/// Transaction ids come from peers and pick a directory that is later removed
+/// whole, so anything but a plain hex id is refused.
+fn staging_dir(temp_dir: &Path, transaction: &str) -> io::Result<PathBuf> {
+    let staging = temp_dir.join("staging");
+    let dir = staging.join(transaction);
+    let hex = !transaction.is_empty() && transaction.bytes().all(|b| b.is_ascii_hexdigit());
+    if !hex || dir.parent() != Some(staging.as_path()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid transaction id '{}'", transaction)));
+    }
+    Ok(dir)
+}
+
+/// Rename a staged file into place, creating parent directories as needed
+fn rename_into_place(staged: &Path, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    file_handler::move_file(staged, destination)
+}
+
+fn remove_staging_dir(temp_dir: &Path, transaction: &str) -> io::Result<()> {
+    let dir = staging_dir(temp_dir, transaction)?;
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+fn remove_incoming(temp_dir: &Path, relative_path: &Path) -> io::Result<()> {
+    match fs::remove_file(incoming_path(temp_dir, relative_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Empty the staging area and, unless files there wait for a reboot, the incoming area
+fn remove_leftovers(temp_dir: &Path, keep_incoming: bool) -> io::Result<usize> {
+    let mut removed = 0;
+    for (area, keep) in [("staging", false), ("incoming", keep_incoming)] {
+        let entries = match fs::read_dir(temp_dir.join(area)) {
+            Ok(entries) if !keep => entries,
+            _ => continue,
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Build the storage backend an observer is configured for
+/// Plain storage records local spellings of wire paths in `local_names`.
+/// Files written are handed to `owner`, the tenant's user, when there is one.
+pub fn for_observer(observer: &ObserverConfig, local_names: SharedLocalNames, owner: Option<Owner>) -> Result<Arc<dyn StorageBackend>, String> {
+    let base_path = Path::new(&observer.path);
+    let temp_dir = temp_dir_for(observer)?;
+    Ok(match &observer.at_rest_key {
+        #[cfg(feature = "encryption")]
+        Some(passphrase) => Arc::new(
+            EncryptedStorage::new(base_path, &observer.name, passphrase.expose())
+                .with_temp_dir(&temp_dir)
+                .with_owner(owner)
+                .with_verified_writes(observer.verify_writes == Some(true)),
+        ),
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => return Err("at_rest_key is set but syndactyl was built without the encryption feature".to_string()),
+        None => Arc::new(
+            PlainStorage::new(base_path)
+                .with_temp_dir(&temp_dir)
+                .with_owner(owner)
+                .with_local_names(&observer.name, local_names)
+                .with_escaping(observer.escape_names.unwrap_or(path_encoding::ESCAPE_BY_DEFAULT))
+                .with_in_use_policy(InUsePolicy::from_config(observer.in_use.as_deref()).unwrap_or_default())
+                .with_verified_writes(observer.verify_writes == Some(true)),
+        ),
+    })
+}
+
+/// Stores files as-is under the observer's base path
+/// Wire paths are mapped to the spelling already on disk, so a file written
+/// decomposed on macOS is updated in place rather than duplicated.
+pub struct PlainStorage {
+    base_path: PathBuf,
+    /// Where received files wait before being moved into place
+    temp_dir: PathBuf,
+    /// Observer name and where resolved spellings are remembered
+    local_names: Option<(String, SharedLocalNames)>,
+    /// Whether names are escaped for Windows on disk
+    escape_names: bool,
+    /// Handling of local files that are open or running when replaced
+    in_use: InUsePolicy,
+    /// Re-read received files before they replace the local copy
+    verify_writes: bool,
+    /// User written files are handed to, for tenants of a daemon running as another
+    owner: Option<Owner>,
+}
+
+impl PlainStorage {
+    pub fn new(base_path: &Path) -> Self {
+        Self {
+            base_path: base_path.to_path_buf(),
+            temp_dir: default_temp_dir(base_path),
+            local_names: None,
+            escape_names: false,
+            in_use: InUsePolicy::default(),
+            verify_writes: false,
+            owner: None,
+        }
+    }
+
+    pub fn with_owner(mut self, owner: Option<Owner>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// Check a path about to be written, in the observer or its temp directory, isn't reached through a symlink
+    fn writable(&self, path: &Path) -> io::Result<()> {
+        let root = if path.starts_with(&self.base_path) { &self.base_path } else { &self.temp_dir };
+        refuse_symlinks(root, path)
+    }
+
+    /// Write received files under `temp_dir` before moving them into place
+    pub fn with_temp_dir(mut self, temp_dir: &Path) -> Self {
+        self.temp_dir = temp_dir.to_path_buf();
+        self
+    }
+
+    pub fn with_verified_writes(mut self, verify_writes: bool) -> Self {
+        self.verify_writes = verify_writes;
+        self
+    }
+
+    pub fn with_in_use_policy(mut self, policy: InUsePolicy) -> Self {
+        self.in_use = policy;
+        self
+    }
+
+    pub fn with_escaping(mut self, escape_names: bool) -> Self {
+        self.escape_names = escape_names;
+        self
+    }
+
+    /// Remember local spellings that differ from the wire form, so they survive restarts
+    pub fn with_local_names(mut self, observer: &str, local_names: SharedLocalNames) -> Self {
+        self.local_names = Some((observer.to_string(), local_names));
+        self
+    }
+
+    fn absolute(&self, relative_path: &Path) -> PathBuf {
+        let wire = relative_path.to_string_lossy();
+        if let Some((observer, names)) = &self.local_names {
+            let known = names.read().ok().and_then(|names| names.get(observer, &wire).cloned());
+            if let Some(local) = known {
+                let absolute_path = file_handler::to_absolute_path(Path::new(&local), &self.base_path);
+                if absolute_path.exists() {
+                    return absolute_path;
+                }
+            }
+        }
+
+        let local = if self.escape_names {
+            path_encoding::resolve_local(&self.base_path, &path_encoding::escape_path(relative_path))
+        } else {
+            path_encoding::resolve_local(&self.base_path, relative_path)
+        };
+        if let Some((observer, names)) = &self.local_names {
+            if let Ok(mut names) = names.write() {
+                if local.as_path() != relative_path {
+                    names.insert(observer, wire.to_string(), local.to_string_lossy().to_string());
+                } else {
+                    names.remove(observer, &wire);
+                }
+            }
+        }
+        file_handler::to_absolute_path(&local, &self.base_path)
+    }
+}
+
+impl StorageBackend for PlainStorage {
+    fn exists(&self, relative_path: &Path) -> bool {
+        self.absolute(relative_path).is_file()
+    }
+
+    fn size(&self, relative_path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(self.absolute(relative_path))?.len())
+    }
+
+    fn modified_time(&self, relative_path: &Path) -> io::Result<u64> {
+        Ok(file_handler::get_file_metadata(&self.absolute(relative_path))?.1)
+    }
+
+    fn modified_nanos(&self, relative_path: &Path) -> io::Result<u128> {
+        file_handler::modified_nanos(&self.absolute(relative_path))
+    }
+
+    fn hash(&self, relative_path: &Path) -> io::Result<String> {
+        file_handler::calculate_file_hash(&self.absolute(relative_path))
+    }
+
+    fn read_chunk(&self, relative_path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        file_handler::read_file_chunk(&self.absolute(relative_path), offset, len)
+    }
+
+    fn write_file(&self, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        // Written aside and renamed, so a running program or open document is
+        // replaced as a whole rather than truncated under it
+        let absolute_path = self.absolute(relative_path);
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        self.writable(&absolute_path)?;
+        self.writable(&incoming)?;
+        file_handler::write_file_content(&incoming, content)?;
+        if self.verify_writes {
+            verify_written(&incoming, content, file_handler::calculate_file_hash(&incoming))?;
+        }
+        replace_in_place(&incoming, &absolute_path, self.in_use)?;
+        hand_over(self.owner, &self.base_path, &absolute_path)?;
+        Ok(absolute_path)
+    }
+
+    fn append_file(&self, relative_path: &Path, offset: u64, content: &[u8]) -> io::Result<PathBuf> {
+        // Written in place, so a program following the log sees the new lines;
+        // an interrupted append still leaves a prefix of the version appended to
+        let absolute_path = self.absolute(relative_path);
+        self.writable(&absolute_path)?;
+        if fs::metadata(&absolute_path)?.len() != offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "stored file is not the version being appended to"));
+        }
+        file_handler::append_file_chunk(&absolute_path, content, offset)?;
+        if self.verify_writes {
+            verify_appended(&absolute_path, offset, content)?;
+        }
+        Ok(absolute_path)
+    }
+
+    fn write_staged(&self, transaction: &str, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        let staged = file_handler::to_absolute_path(relative_path, &staging_dir(&self.temp_dir, transaction)?);
+        self.writable(&staged)?;
+        file_handler::write_file_content(&staged, content)?;
+        if self.verify_writes {
+            verify_written(&staged, content, file_handler::calculate_file_hash(&staged))?;
+        }
+        Ok(staged)
+    }
+
+    fn commit_staged(&self, transaction: &str, relative_path: &Path) -> io::Result<PathBuf> {
+        let staged = file_handler::to_absolute_path(relative_path, &staging_dir(&self.temp_dir, transaction)?);
+        let absolute_path = self.absolute(relative_path);
+        // Out of the staging area first, which is removed once the transaction is applied
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        self.writable(&absolute_path)?;
+        self.writable(&incoming)?;
+        rename_into_place(&staged, &incoming)?;
+        replace_in_place(&incoming, &absolute_path, self.in_use)?;
+        hand_over(self.owner, &self.base_path, &absolute_path)?;
+        Ok(absolute_path)
+    }
+
+    fn discard_staged(&self, transaction: &str) -> io::Result<()> {
+        remove_staging_dir(&self.temp_dir, transaction)
+    }
+
+    fn discard_incoming(&self, relative_path: &Path) -> io::Result<()> {
+        remove_incoming(&self.temp_dir, relative_path)?;
+        // Left beside the local copy if a move onto another filesystem was cut short
+        file_handler::remove_partial_copy(&self.absolute(relative_path))
+    }
+
+    fn supports_partial(&self) -> bool {
+        true
+    }
+
+    fn write_partial(&self, relative_path: &Path, offset: u64, content: &[u8]) -> io::Result<()> {
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        if offset == 0 {
+            self.writable(&incoming)?;
+            return file_handler::write_file_content(&incoming, content);
+        }
+        file_handler::append_file_chunk(&incoming, content, offset)
+    }
+
+    fn commit_partial(&self, relative_path: &Path, hash: &str) -> io::Result<PathBuf> {
+        let absolute_path = self.absolute(relative_path);
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        self.writable(&absolute_path)?;
+        if self.verify_writes {
+            let read_back = file_handler::calculate_file_hash(&incoming)?;
+            if read_back != hash {
+                let _ = fs::remove_file(&incoming);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} read back with hash {} instead of {}", incoming.display(), read_back, hash)));
+            }
+        }
+        replace_in_place(&incoming, &absolute_path, self.in_use)?;
+        hand_over(self.owner, &self.base_path, &absolute_path)?;
+        Ok(absolute_path)
+    }
+
+    fn retry_replace(&self, relative_path: &Path, last_attempt: bool) -> io::Result<Replaced> {
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        let absolute_path = self.absolute(relative_path);
+        let replaced = self.writable(&absolute_path)
+            .and_then(|()| in_use::replace(&incoming, &absolute_path, self.in_use, last_attempt));
+        match &replaced {
+            Ok(Replaced::Now) => hand_over(self.owner, &self.base_path, &absolute_path)?,
+            Ok(_) => {}
+            Err(_) => {
+                let _ = fs::remove_file(&incoming);
+            }
+        }
+        replaced
+    }
+
+    fn clean_orphans(&self) -> io::Result<usize> {
+        // Files scheduled to replace an in-use file at reboot live in the incoming area
+        remove_leftovers(&self.temp_dir, self.in_use == InUsePolicy::Reboot)
+    }
+}
+
+/// Magic bytes at the start of every encrypted file
+#[cfg(feature = "encryption")]
+const ENCRYPTED_MAGIC: &[u8; 8] = b"SYNENC01";
+
+/// Plaintext bytes per encrypted block
+/// Blocks are encrypted independently so chunks can be served without decrypting the whole file
+#[cfg(feature = "encryption")]
+pub const ENCRYPTED_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Poly1305 authentication tag length appended to each block
+#[cfg(feature = "encryption")]
+const TAG_SIZE: usize = 16;
+
+/// Header: magic || plaintext size (u64 LE) || base nonce (12 bytes)
+#[cfg(feature = "encryption")]
+const HEADER_SIZE: usize = 8 + 8 + 12;
+
+/// Stores files encrypted with ChaCha20-Poly1305 under obfuscated names
+/// Intended for mirror nodes on untrusted hosts: the host sees neither file
+/// names nor contents, but the node can still serve plaintext chunks to peers.
+#[cfg(feature = "encryption")]
+pub struct EncryptedStorage {
+    base_path: PathBuf,
+    /// Where received files wait before being moved into place
+    temp_dir: PathBuf,
+    content_key: [u8; 32],
+    name_key: [u8; 32],
+    /// Decrypt received files again before they replace the stored copy
+    verify_writes: bool,
+    /// User written files are handed to, for tenants of a daemon running as another
+    owner: Option<Owner>,
+}
+
+#[cfg(feature = "encryption")]
+impl Drop for EncryptedStorage {
+    fn drop(&mut self) {
+        self.content_key.zeroize();
+        self.name_key.zeroize();
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptedStorage {
+    /// Create an encrypted store, deriving content and name keys from the passphrase
+    /// The observer name salts the keys, so observers sharing a passphrase don't share keys.
+    pub fn new(base_path: &Path, observer: &str, passphrase: &str) -> Self {
+        Self {
+            base_path: base_path.to_path_buf(),
+            temp_dir: default_temp_dir(base_path),
+            content_key: derive_key(passphrase, observer, b"syndactyl-at-rest-content"),
+            name_key: derive_key(passphrase, observer, b"syndactyl-at-rest-names"),
+            verify_writes: false,
+            owner: None,
+        }
+    }
+
+    /// Write received files under `temp_dir` before moving them into place
+    pub fn with_temp_dir(mut self, temp_dir: &Path) -> Self {
+        self.temp_dir = temp_dir.to_path_buf();
+        self
+    }
+
+    pub fn with_verified_writes(mut self, verify_writes: bool) -> Self {
+        self.verify_writes = verify_writes;
+        self
+    }
+
+    pub fn with_owner(mut self, owner: Option<Owner>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// Check a path about to be written, in the store or its temp directory, isn't reached through a symlink
+    fn writable(&self, path: &Path) -> io::Result<()> {
+        let root = if path.starts_with(&self.base_path) { &self.base_path } else { &self.temp_dir };
+        refuse_symlinks(root, path)
+    }
+
+    /// Obfuscated on-disk location for a relative path
+    /// Files are fanned out by the first byte of the name HMAC to keep directories small
+    pub fn stored_path(&self, relative_path: &Path) -> PathBuf {
+        let mut mac = HmacSha256::new_from_slice(&self.name_key)
+            .expect("HMAC can take key of any size");
+        // Spellings of the same name must land on the same file
+        mac.update(path_encoding::wire_path(&relative_path.to_string_lossy()).as_bytes());
+        let name = format!("{:x}", mac.finalize().into_bytes());
+        self.base_path.join(&name[..2]).join(name)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.content_key))
+    }
+
+    /// Read the header, returning (plaintext size, base nonce)
+    fn read_header(file: &mut File) -> io::Result<(u64, [u8; 12])> {
+        let mut header = [0u8; HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)?;
+        if &header[..8] != ENCRYPTED_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a syndactyl encrypted file"));
+        }
+        let mut size_bytes = [0u8; 8];
+        size_bytes.copy_from_slice(&header[8..16]);
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&header[16..]);
+        Ok((u64::from_le_bytes(size_bytes), nonce))
+    }
+
+    /// Decrypt a single block by index
+    fn read_block(
+        &self,
+        file: &mut File,
+        total_size: u64,
+        base_nonce: &[u8; 12],
+        index: u64,
+    ) -> io::Result<Vec<u8>> {
+        let plain_start = index * ENCRYPTED_BLOCK_SIZE as u64;
+        let plain_len = (total_size - plain_start).min(ENCRYPTED_BLOCK_SIZE as u64) as usize;
+        let stored_offset = HEADER_SIZE as u64 + index * (ENCRYPTED_BLOCK_SIZE + TAG_SIZE) as u64;
+
+        let mut ciphertext = vec![0u8; plain_len + TAG_SIZE];
+        file.seek(SeekFrom::Start(stored_offset))?;
+        file.read_exact(&mut ciphertext)?;
+
+        let nonce = block_nonce(base_nonce, index);
+        let aad = block_aad(index, total_size);
+        self.cipher()
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Encrypted block failed authentication"))
+    }
+
+    /// Staged location keeps the obfuscated name so nothing leaks during staging
+    fn staged_path(&self, transaction: &str, relative_path: &Path) -> io::Result<PathBuf> {
+        let stored_path = self.stored_path(relative_path);
+        let name = stored_path.file_name().unwrap_or_default();
+        Ok(staging_dir(&self.temp_dir, transaction)?.join(name))
+    }
+
+    /// SHA-256 of the decrypted content of an encrypted file at `stored_path`
+    fn plaintext_hash(&self, stored_path: &Path) -> io::Result<String> {
+        let mut file = File::open(stored_path)?;
+        let (total_size, base_nonce) = Self::read_header(&mut file)?;
+        let block_count = total_size.div_ceil(ENCRYPTED_BLOCK_SIZE as u64);
+
+        let mut hasher = Sha256::new();
+        for index in 0..block_count {
+            hasher.update(self.read_block(&mut file, total_size, &base_nonce, index)?);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Encrypt content block by block and write it to `destination`
+    fn encrypt_to(&self, destination: &Path, content: &[u8]) -> io::Result<()> {
+        let cipher = self.cipher();
+        let total_size = content.len() as u64;
+
+        let mut base_nonce = [0u8; 12];
+        base_nonce.copy_from_slice(&ChaCha20Poly1305::generate_nonce(&mut OsRng));
+
+        let mut encrypted = Vec::with_capacity(HEADER_SIZE + content.len() + TAG_SIZE * (content.len() / ENCRYPTED_BLOCK_SIZE + 1));
+        encrypted.extend_from_slice(ENCRYPTED_MAGIC);
+        encrypted.extend_from_slice(&total_size.to_le_bytes());
+        encrypted.extend_from_slice(&base_nonce);
+
+        for (index, block) in content.chunks(ENCRYPTED_BLOCK_SIZE).enumerate() {
+            let nonce = block_nonce(&base_nonce, index as u64);
+            let aad = block_aad(index as u64, total_size);
+            let ciphertext = cipher
+                .encrypt(Nonce::from_slice(&nonce), Payload { msg: block, aad: &aad })
+                .map_err(|_| io::Error::other("Failed to encrypt block"))?;
+            encrypted.extend_from_slice(&ciphertext);
+        }
+
+        file_handler::write_file_content(destination, &encrypted)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl StorageBackend for EncryptedStorage {
+    fn exists(&self, relative_path: &Path) -> bool {
+        self.stored_path(relative_path).is_file()
+    }
+
+    fn size(&self, relative_path: &Path) -> io::Result<u64> {
+        let mut file = File::open(self.stored_path(relative_path))?;
+        Ok(Self::read_header(&mut file)?.0)
+    }
+
+    fn modified_time(&self, relative_path: &Path) -> io::Result<u64> {
+        Ok(file_handler::get_file_metadata(&self.stored_path(relative_path))?.1)
+    }
+
+    fn modified_nanos(&self, relative_path: &Path) -> io::Result<u128> {
+        file_handler::modified_nanos(&self.stored_path(relative_path))
+    }
+
+    fn hash(&self, relative_path: &Path) -> io::Result<String> {
+        self.plaintext_hash(&self.stored_path(relative_path))
+    }
+
+    fn read_chunk(&self, relative_path: &Path, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut file = File::open(self.stored_path(relative_path))?;
+        let (total_size, base_nonce) = Self::read_header(&mut file)?;
+        if offset >= total_size {
+            return Ok(Vec::new());
+        }
+
+        let end = (offset + len as u64).min(total_size);
+        let first_block = offset / ENCRYPTED_BLOCK_SIZE as u64;
+        let last_block = (end - 1) / ENCRYPTED_BLOCK_SIZE as u64;
+
+        let mut data = Vec::with_capacity((end - offset) as usize);
+        for index in first_block..=last_block {
+            let block = self.read_block(&mut file, total_size, &base_nonce, index)?;
+            let block_start = index * ENCRYPTED_BLOCK_SIZE as u64;
+            let from = offset.saturating_sub(block_start) as usize;
+            let to = ((end - block_start) as usize).min(block.len());
+            data.extend_from_slice(&block[from..to]);
+        }
+        Ok(data)
+    }
+
+    fn write_file(&self, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        // Written aside and renamed, so a crash never leaves a truncated file in place
+        let stored_path = self.stored_path(relative_path);
+        let incoming = incoming_path(&self.temp_dir, relative_path);
+        self.writable(&stored_path)?;
+        self.writable(&incoming)?;
+        self.encrypt_to(&incoming, content)?;
+        if self.verify_writes {
+            verify_written(&incoming, content, self.plaintext_hash(&incoming))?;
+        }
+        rename_into_place(&incoming, &stored_path)?;
+        hand_over(self.owner, &self.base_path, &stored_path)?;
+        Ok(stored_path)
+    }
+
+    fn write_staged(&self, transaction: &str, relative_path: &Path, content: &[u8]) -> io::Result<PathBuf> {
+        let staged = self.staged_path(transaction, relative_path)?;
+        self.writable(&staged)?;
+        self.encrypt_to(&staged, content)?;
+        if self.verify_writes {
+            verify_written(&staged, content, self.plaintext_hash(&staged))?;
+        }
+        Ok(staged)
+    }
+
+    fn commit_staged(&self, transaction: &str, relative_path: &Path) -> io::Result<PathBuf> {
+        let stored_path = self.stored_path(relative_path);
+        self.writable(&stored_path)?;
+        rename_into_place(&self.staged_path(transaction, relative_path)?, &stored_path)?;
+        hand_over(self.owner, &self.base_path, &stored_path)?;
+        Ok(stored_path)
+    }
+
+    fn discard_staged(&self, transaction: &str) -> io::Result<()> {
+        remove_staging_dir(&self.temp_dir, transaction)
+    }
+
+    fn discard_incoming(&self, relative_path: &Path) -> io::Result<()> {
+        remove_incoming(&self.temp_dir, relative_path)
+    }
+
+    fn clean_orphans(&self) -> io::Result<usize> {
+        remove_leftovers(&self.temp_dir, false)
+    }
+}
+
+/// Derive a 32-byte key for a given purpose from a passphrase, with HKDF salted by the observer
+#[cfg(feature = "encryption")]
+fn derive_key(passphrase: &str, observer: &str, purpose: &[u8]) -> [u8; 32] {
+    let mut salt = b"syndactyl-at-rest:".to_vec();
+    salt.extend_from_slice(observer.as_bytes());
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), passphrase.as_bytes())
+        .expand(purpose, &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    key
+}
+
+/// Per-block nonce: base nonce with the block index XORed into the last 8 bytes
+#[cfg(feature = "encryption")]
+fn block_nonce(base_nonce: &[u8; 12], index: u64) -> [u8; 12] {
+    let mut nonce = *base_nonce;
+    for (n, i) in nonce[4..].iter_mut().zip(index.to_le_bytes()) {
+        *n ^= i;
+    }
+    nonce
+}
+
+/// Associated data binding each block to its position and the file size,
+/// so blocks cannot be reordered or the file silently truncated
+#[cfg(feature = "encryption")]
+fn block_aad(index: u64, total_size: u64) -> [u8; 16] {
+    let mut aad = [0u8; 16];
+    aad[..8].copy_from_slice(&index.to_le_bytes());
+    aad[8..].copy_from_slice(&total_size.to_le_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_plain_storage_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path());
+        let path = Path::new("dir/file.txt");
+
+        let written = storage.write_file(path, b"hello world").unwrap();
+        assert_eq!(written, temp_dir.path().join("dir/file.txt"));
+        assert!(storage.exists(path));
+        assert_eq!(storage.size(path).unwrap(), 11);
+        assert_eq!(storage.read_chunk(path, 6, 100).unwrap(), b"world");
+    }
+
+    #[test]
+    fn test_staged_files_appear_on_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path());
+        let path = Path::new("src/lib.rs");
+
+        storage.write_staged("a1", path, b"fn main() {}").unwrap();
+        assert!(!storage.exists(path));
+
+        storage.commit_staged("a1", path).unwrap();
+        assert!(storage.exists(path));
+        storage.discard_staged("a1").unwrap();
+        assert!(!temp_dir.path().join(".syndactyl/tmp/staging/a1").exists());
+    }
+
+    #[test]
+    fn test_received_files_wait_in_the_configured_temp_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let (root, scratch) = (temp_dir.path().join("docs"), temp_dir.path().join("scratch"));
+        let storage = PlainStorage::new(&root).with_temp_dir(&scratch);
+        let path = Path::new("src/lib.rs");
+
+        storage.write_staged("a1", path, b"fn main() {}").unwrap();
+        assert!(scratch.join("staging/a1/src/lib.rs").exists());
+        storage.commit_staged("a1", path).unwrap();
+        assert_eq!(fs::read(root.join("src/lib.rs")).unwrap(), b"fn main() {}");
+
+        storage.write_file(Path::new("notes.txt"), b"hello").unwrap();
+        assert!(!root.join(".syndactyl").exists());
+        storage.discard_staged("a1").unwrap();
+        assert_eq!(storage.clean_orphans().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_temp_dir_inside_the_observer_must_be_internal() {
+        let mut observer: ObserverConfig = serde_json::from_str(r#"{"name": "docs", "path": "/srv/docs"}"#).unwrap();
+        assert_eq!(temp_dir_for(&observer).unwrap(), Path::new("/srv/docs/.syndactyl/tmp"));
+        observer.temp_dir = Some("/srv/docs/.syndactyl/incoming".to_string());
+        assert!(temp_dir_for(&observer).is_ok());
+        observer.temp_dir = Some("/var/tmp/syndactyl".to_string());
+        assert_eq!(temp_dir_for(&observer).unwrap(), Path::new("/var/tmp/syndactyl/docs"));
+        observer.temp_dir = Some("/srv/docs/tmp".to_string());
+        assert!(temp_dir_for(&observer).is_err());
+
+        // Observers sharing a temp_dir each get their own area in it
+        let mut photos: ObserverConfig = serde_json::from_str(r#"{"name": "photos", "path": "/srv/photos", "temp_dir": "/var/tmp/syndactyl"}"#).unwrap();
+        assert_eq!(temp_dir_for(&photos).unwrap(), Path::new("/var/tmp/syndactyl/photos"));
+        photos.name = "../docs".to_string();
+        assert!(temp_dir_for(&photos).is_err());
+    }
+
+    #[test]
+    fn test_verified_writes_refuse_files_that_read_back_differently() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path()).with_verified_writes(true);
+        let path = Path::new("dir/file.txt");
+        storage.write_file(path, b"hello world").unwrap();
+        assert_eq!(storage.read_chunk(path, 0, 100).unwrap(), b"hello world");
+
+        // A write that didn't land as intended is removed before it replaces anything
+        let written = temp_dir.path().join("written.tmp");
+        fs::write(&written, b"hello w0rld").unwrap();
+        let read_back = file_handler::calculate_file_hash(&written);
+        assert!(verify_written(&written, b"hello world", read_back).is_err());
+        assert!(!written.exists());
+
+        // Appends are checked too, and a bad one is cut back to the version appended to
+        storage.append_file(path, 11, b"!\n").unwrap();
+        assert_eq!(storage.read_chunk(path, 0, 100).unwrap(), b"hello world!\n");
+        let absolute = temp_dir.path().join(path);
+        assert!(verify_appended(&absolute, 11, b"?\n").is_err());
+        assert_eq!(fs::read(&absolute).unwrap(), b"hello world");
+
+        #[cfg(feature = "encryption")]
+        {
+            let encrypted = EncryptedStorage::new(temp_dir.path(), "docs", "passphrase").with_verified_writes(true);
+            encrypted.write_staged("a1", path, b"top secret").unwrap();
+            encrypted.commit_staged("a1", path).unwrap();
+            assert_eq!(encrypted.read_chunk(path, 0, 100).unwrap(), b"top secret");
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_storage_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = EncryptedStorage::new(temp_dir.path(), "docs", "passphrase");
+        let path = Path::new("secret/report.txt");
+
+        // Spans three blocks so chunk reads cross block boundaries
+        let content: Vec<u8> = (0..(ENCRYPTED_BLOCK_SIZE * 2 + 100)).map(|i| (i % 251) as u8).collect();
+        let written = storage.write_file(path, &content).unwrap();
+
+        // Neither the name nor the plaintext appear on disk
+        assert!(!written.to_string_lossy().contains("report"));
+        let raw = fs::read(&written).unwrap();
+        assert_ne!(&raw[HEADER_SIZE..HEADER_SIZE + 64], &content[..64]);
+
+        assert!(storage.exists(path));
+        assert_eq!(storage.size(path).unwrap(), content.len() as u64);
+
+        let offset = ENCRYPTED_BLOCK_SIZE - 10;
+        let chunk = storage.read_chunk(path, offset as u64, ENCRYPTED_BLOCK_SIZE + 20).unwrap();
+        assert_eq!(chunk, &content[offset..offset + ENCRYPTED_BLOCK_SIZE + 20]);
+
+        let expected_hash = format!("{:x}", Sha256::digest(&content));
+        assert_eq!(storage.hash(path).unwrap(), expected_hash);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_storage_wrong_passphrase() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = EncryptedStorage::new(temp_dir.path(), "docs", "passphrase");
+        let path = Path::new("file.txt");
+        let stored = storage.write_file(path, b"top secret").unwrap();
+
+        // Same stored file read with a different key fails authentication
+        // as does the same passphrase salted for another observer
+        for other in [EncryptedStorage::new(temp_dir.path(), "docs", "other"), EncryptedStorage::new(temp_dir.path(), "photos", "passphrase")] {
+            let other_stored = other.stored_path(path);
+            assert_ne!(other_stored, stored);
+            fs::create_dir_all(other_stored.parent().unwrap()).unwrap();
+            fs::copy(&stored, &other_stored).unwrap();
+            assert!(other.read_chunk(path, 0, 10).is_err());
+        }
+    }
+
+    #[test]
+    fn test_transaction_ids_cannot_leave_the_staging_area() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path());
+        let outside = temp_dir.path().join(".syndactyl/keep");
+        fs::create_dir_all(&outside).unwrap();
+
+        for id in ["..", "../keep", "../../.syndactyl", "a/../..", ""] {
+            assert!(storage.discard_staged(id).is_err(), "{}", id);
+            assert!(storage.write_staged(id, Path::new("a.txt"), b"a").is_err(), "{}", id);
+        }
+        assert!(outside.exists());
+    }
+
+    #[test]
+    fn test_a_waiting_download_replaces_the_local_copy_on_retry() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = PlainStorage::new(temp_dir.path()).with_in_use_policy(InUsePolicy::Retry);
+        let path = Path::new("app.exe");
+        storage.write_file(path, b"old").unwrap();
+
+        // As left by a write whose destination was in use
+        storage.write_partial(path, 0, b"new").unwrap();
+        assert_eq!(storage.retry_replace(path, false).unwrap(), Replaced::Now);
+        assert_eq!(fs::read(temp_dir.path().join(path)).unwrap(), b"new");
+        assert!(storage.retry_replace(path, true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_symlink_in_a_tenant_observer_is_not_written_through() {
+        let temp_dir = TempDir::new().unwrap();
+        let (observer, outside) = (temp_dir.path().join("alice"), temp_dir.path().join("etc"));
+        fs::create_dir_all(&observer).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("passwd"), b"root").unwrap();
+        std::os::unix::fs::symlink(&outside, observer.join("x")).unwrap();
+        std::os::unix::fs::symlink(outside.join("passwd"), observer.join("link")).unwrap();
+
+        let storage = PlainStorage::new(&observer);
+        let escaped = Path::new("x/passwd");
+        assert_eq!(storage.write_file(escaped, b"owned").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(storage.append_file(escaped, 4, b"!").is_err());
+        storage.write_staged("ab12", escaped, b"owned").unwrap();
+        assert!(storage.commit_staged("ab12", escaped).is_err());
+        assert!(storage.append_file(Path::new("link"), 4, b"!").is_err());
+        assert_eq!(fs::read(outside.join("passwd")).unwrap(), b"root");
+
+        // Encrypted stores fan files out into directories a user could replace as well
+        #[cfg(feature = "encryption")]
+        {
+            let encrypted = EncryptedStorage::new(&temp_dir.path().join("vault"), "vault", "passphrase");
+            let stored = encrypted.stored_path(Path::new("a.txt"));
+            fs::create_dir_all(stored.parent().unwrap().parent().unwrap()).unwrap();
+            std::os::unix::fs::symlink(&outside, stored.parent().unwrap()).unwrap();
+            assert!(encrypted.write_file(Path::new("a.txt"), b"owned").is_err());
+            assert_eq!(fs::read_dir(&outside).unwrap().count(), 1);
+        }
+
+        // Nothing in the way: written as before
+        storage.write_file(Path::new("docs/a.txt"), b"fine").unwrap();
+        assert_eq!(fs::read(observer.join("docs/a.txt")).unwrap(), b"fine");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use libp2p::PeerId;
+use crate::core::auth;
+use crate::core::config::ObserverConfig;
+
+/// Topic announcements go out on for observers shared with every peer
+pub const DEFAULT_TOPIC: &str = "syndactyl-gossip";
+
+/// Peers each observer is shared with
+/// Observers without a `peers` list are shared with everyone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncGroups {
+    observers: HashMap<String, HashSet<String>>,
+}
+
+impl SyncGroups {
+    /// Resolve every observer's `peers`, expanding group names into their members
+    /// Every member must be a peer ID, so a misspelt group name isn't taken for one, and
+    /// the observer needs a shared secret: without it anyone can sign its announcements.
+    pub fn from_config(observers: &[ObserverConfig], groups: &HashMap<String, Vec<String>>) -> Result<Self, String> {
+        let mut resolved = HashMap::new();
+        for observer in observers {
+            let Some(entries) = &observer.peers else {
+                continue;
+            };
+            if observer.shared_secret.is_none() {
+                return Err(format!("Observer {} lists peers, so it needs a shared_secret", observer.name));
+            }
+            let mut peers = HashSet::new();
+            for entry in entries {
+                match groups.get(entry) {
+                    Some(members) => peers.extend(members.iter().cloned()),
+                    None => {
+                        peers.insert(entry.clone());
+                    }
+                }
+            }
+            if let Some(invalid) = peers.iter().find(|peer| PeerId::from_str(peer).is_err()) {
+                return Err(format!("Observer {} lists {}, which is neither a peer group nor a peer ID", observer.name, invalid));
+            }
+            if peers.is_empty() {
+                return Err(format!("Observer {} lists peers, but none of them resolve to a peer ID", observer.name));
+            }
+            resolved.insert(observer.name.clone(), peers);
+        }
+        Ok(Self { observers: resolved })
+    }
+
+    /// Whether `peer` may sync `observer` with us
+    pub fn allows(&self, observer: &str, peer: &str) -> bool {
+        self.observers.get(observer).is_none_or(|peers| peers.contains(peer))
+    }
+
+    pub fn is_restricted(&self, observer: &str) -> bool {
+        self.observers.contains_key(observer)
+    }
+}
+
+/// Gossip topic of an observer, so announcements only travel the mesh of peers
+/// that subscribe to it
+/// Derived from the shared secret when there is one, which keeps the
+/// observer's name out of the topic.
+pub fn topic(observer: &str, secret: Option<&str>) -> String {
+    format!("{}/{}", DEFAULT_TOPIC, auth::observer_id(observer, secret.unwrap_or(observer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observer(name: &str, peers: Option<Vec<&str>>) -> ObserverConfig {
+        serde_json::from_value(serde_json::json!({ "name": name, "path": "/tmp", "shared_secret": "s", "peers": peers })).unwrap()
+    }
+
+    #[test]
+    fn test_groups_expand_and_restrict_only_listed_observers() {
+        let [peer_a, peer_b, peer_c] = [(); 3].map(|_| PeerId::random().to_string());
+        let groups = HashMap::from([("family".to_string(), vec![peer_a.clone(), peer_b.clone()])]);
+        let observers = [
+            observer("photos", Some(vec!["family"])),
+            observer("work", Some(vec![&peer_c])),
+            observer("music", None),
+        ];
+        let sync_groups = SyncGroups::from_config(&observers, &groups).unwrap();
+        assert!(sync_groups.allows("photos", &peer_b));
+        assert!(!sync_groups.allows("photos", &peer_c));
+        assert!(sync_groups.allows("work", &peer_c));
+        assert!(!sync_groups.allows("work", &peer_a));
+        assert!(sync_groups.allows("music", &peer_c));
+        assert!(!sync_groups.is_restricted("music"));
+
+        assert_ne!(topic("photos", Some("secret")), topic("photos", None));
+        assert!(topic("photos", Some("secret")).starts_with("syndactyl-gossip/"));
+        assert!(SyncGroups::from_config(&[observer("empty", Some(vec![]))], &groups).is_err());
+
+        // A misspelt group name is not a peer, and a private observer needs a secret
+        assert!(SyncGroups::from_config(&[observer("photos", Some(vec!["famly"]))], &groups).is_err());
+        let mut unsigned = observer("photos", Some(vec!["family"]));
+        unsigned.shared_secret = None;
+        assert!(SyncGroups::from_config(&[unsigned], &groups).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use sha2::{Sha256, Digest};
+
+/// Correlation ID for one version of one file, the same on every node
+/// Derived from the observer, path and hash that announcements, requests and
+/// responses already carry, so each node can tag its log lines for a sync
+/// without an extra field on the wire (and peers on older versions line up too).
+pub fn sync_id(observer: &str, path: &str, hash: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(observer.as_bytes());
+    hasher.update(b"||");
+    hasher.update(path.as_bytes());
+    hasher.update(b"||");
+    hasher.update(hash.unwrap_or("").as_bytes());
+    let mut id = format!("{:x}", hasher.finalize());
+    id.truncate(12);
+    id
+}
+
+/// Place the span of one stage of a sync in the trace of that sync, when spans are exported
+/// Returns the span, to be entered as usual.
+pub fn traced(span: tracing::Span, sync: &str) -> tracing::Span {
+    #[cfg(feature = "otlp")]
+    crate::core::otlp::join_sync_trace(&span, sync);
+    #[cfg(not(feature = "otlp"))]
+    let _ = sync;
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_id_changes_with_version() {
+        let first = sync_id("docs", "a.txt", Some("h1"));
+        assert_eq!(first, sync_id("docs", "a.txt", Some("h1")));
+        assert_eq!(first.len(), 12);
+        assert_ne!(first, sync_id("docs", "a.txt", Some("h2")));
+        assert_ne!(first, sync_id("docs", "b.txt", Some("h1")));
+    }
+}
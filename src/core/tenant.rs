@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::core::config::{Config, NetworkConfig, Owner};
+
+/// Configurations of the tenants in `config`, with their state directories
+/// filled in and checked to share nothing with each other or the top level
+/// Two tenants must never watch the same directory, listen on the same port or
+/// write the same state, so one user's peers can't reach another user's files.
+pub fn resolve(config: &Config) -> Result<Vec<(String, Config)>, String> {
+    let Some(tenants) = &config.tenants else {
+        return Ok(Vec::new());
+    };
+    let base = config.state_dir().map_err(|e| e.to_string())?;
+    let mut names = HashSet::new();
+    let mut resolved = Vec::new();
+    for tenant in tenants {
+        let valid_name = !tenant.name.is_empty()
+            && tenant.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(format!("Invalid tenant name '{}': use letters, digits, '-' and '_'", tenant.name));
+        }
+        if !names.insert(tenant.name.as_str()) {
+            return Err(format!("Tenant '{}' is configured twice", tenant.name));
+        }
+        if tenant.config.tenants.is_some() {
+            return Err(format!("Tenant '{}' can't have tenants of its own", tenant.name));
+        }
+        let mut tenant_config = tenant.config.clone();
+        if tenant_config.state_dir.is_none() {
+            tenant_config.state_dir = Some(base.join("tenants").join(&tenant.name).to_string_lossy().into_owned());
+        }
+        match tenant.uid {
+            Some(uid) if cfg!(unix) => tenant_config.owner = Some(Owner { uid, gid: tenant.gid }),
+            Some(_) => return Err(format!("Tenant '{}' has a uid, which is only supported on Unix", tenant.name)),
+            None if tenant.gid.is_some() => return Err(format!("Tenant '{}' has a gid but no uid", tenant.name)),
+            None => {}
+        }
+        resolved.push((tenant.name.clone(), tenant_config));
+    }
+
+    let everyone: Vec<(&str, &Config)> = std::iter::once(("(top level)", config))
+        .chain(resolved.iter().map(|(name, config)| (name.as_str(), config)))
+        .collect();
+    for (i, (name, tenant)) in everyone.iter().enumerate() {
+        for (other_name, other) in &everyone[i + 1..] {
+            check_isolated(name, tenant, other_name, other)?;
+        }
+    }
+    Ok(resolved)
+}
+
+/// The tenant named `name`
+pub fn find(config: &Config, name: &str) -> Result<Config, String> {
+    resolve(config)?
+        .into_iter()
+        .find(|(tenant, _)| tenant == name)
+        .map(|(_, config)| config)
+        .ok_or_else(|| format!("No tenant named '{}' is configured", name))
+}
+
+/// Give `path` to the tenant's user, if it has one
+/// The path itself is changed, never a symlink's target.
+#[cfg(unix)]
+pub fn hand_over(path: &Path, owner: Option<Owner>) -> io::Result<()> {
+    match owner {
+        Some(owner) => std::os::unix::fs::lchown(path, Some(owner.uid), owner.gid),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn hand_over(_path: &Path, _owner: Option<Owner>) -> io::Result<()> {
+    Ok(())
+}
+
+/// `path` with symlinks resolved, so two spellings of one directory compare equal
+/// Paths that don't exist yet resolve through their nearest existing ancestor.
+fn real_path(path: &Path) -> PathBuf {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        if let Ok(resolved) = existing.canonicalize() {
+            return missing.iter().rev().fold(resolved, |resolved, name| resolved.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+fn check_isolated(name: &str, config: &Config, other_name: &str, other: &Config) -> Result<(), String> {
+    let shared = |what: &str| Err(format!("{} and {} share {}", name, other_name, what));
+    for observer in config.observers.iter().filter(|obs| !obs.path.is_empty()) {
+        for other_observer in other.observers.iter().filter(|obs| !obs.path.is_empty()) {
+            let (a, b) = (real_path(Path::new(&observer.path)), real_path(Path::new(&other_observer.path)));
+            if a.starts_with(&b) || b.starts_with(&a) {
+                return shared(&format!("the directory of observers {} and {}", observer.name, other_observer.name));
+            }
+        }
+    }
+    if let (Some(network), Some(other_network)) = (&config.network, &other.network) {
+        let listening = |network: &NetworkConfig| network.outbound_only != Some(true) && network.port != "0";
+        if listening(network) && listening(other_network) && network.port == other_network.port {
+            return shared(&format!("port {}", network.port));
+        }
+    }
+    let paths = |config: &Config| -> Result<[PathBuf; 3], String> {
+        Ok([
+            real_path(&config.state_path().map_err(|e| e.to_string())?),
+            real_path(&config.control_socket_path().map_err(|e| e.to_string())?),
+            real_path(&config.journal_path().map_err(|e| e.to_string())?),
+        ])
+    };
+    let (ours, theirs) = (paths(config)?, paths(other)?);
+    if let Some(path) = ours.iter().find(|path| theirs.contains(path)) {
+        return shared(&path.display().to_string());
+    }
+    if config.keypair_path().is_some_and(|key| other.keypair_path().is_some_and(|other_key| real_path(&key) == real_path(&other_key))) {
+        return shared("a keypair");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: &str) -> Config {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_tenants_get_their_own_state_and_must_not_overlap() {
+        let shared_host = config(r#"{
+            "observers": [],
+            "network": null,
+            "state_dir": "/var/lib/syndactyl",
+            "tenants": [
+                {"name": "alice", "observers": [{"name": "home", "path": "/home/alice"}], "network": null},
+                {"name": "bob", "observers": [{"name": "home", "path": "/home/bob"}], "network": null}
+            ]
+        }"#);
+        let tenants = resolve(&shared_host).unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants[0].1.state_path().unwrap(), PathBuf::from("/var/lib/syndactyl/tenants/alice/state.json"));
+        assert_eq!(find(&shared_host, "bob").unwrap().keypair_path(), Some(PathBuf::from("/var/lib/syndactyl/tenants/bob/syndactyl_keypair.key")));
+        assert!(find(&shared_host, "carol").is_err());
+
+        let overlapping = config(r#"{
+            "observers": [],
+            "network": null,
+            "state_dir": "/var/lib/syndactyl",
+            "tenants": [
+                {"name": "alice", "observers": [{"name": "home", "path": "/home"}], "network": null},
+                {"name": "bob", "observers": [{"name": "home", "path": "/home/bob"}], "network": null}
+            ]
+        }"#);
+        assert!(resolve(&overlapping).unwrap_err().contains("directory"));
+
+        let bad_name = config(r#"{"observers": [], "network": null, "tenants": [{"name": "../x", "observers": [], "network": null}]}"#);
+        assert!(resolve(&bad_name).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_symlink_doesnt_hide_a_shared_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("shared")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("shared"), dir.path().join("link")).unwrap();
+        let json = format!(r#"{{
+            "observers": [],
+            "network": null,
+            "state_dir": "{0}/state",
+            "tenants": [
+                {{"name": "alice", "observers": [{{"name": "home", "path": "{0}/shared/alice"}}], "network": null}},
+                {{"name": "bob", "observers": [{{"name": "home", "path": "{0}/link"}}], "network": null}}
+            ]
+        }}"#, dir.path().display());
+        assert!(resolve(&config(&json)).unwrap_err().contains("directory"));
+        assert_eq!(real_path(&dir.path().join("link/not/yet")), dir.path().canonicalize().unwrap().join("shared/not/yet"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tenants_with_a_uid_own_their_files() {
+        let host = config(r#"{
+            "observers": [],
+            "network": null,
+            "state_dir": "/var/lib/syndactyl",
+            "tenants": [
+                {"name": "alice", "uid": 1000, "gid": 100, "observers": [], "network": null},
+                {"name": "bob", "observers": [], "network": null}
+            ]
+        }"#);
+        let tenants = resolve(&host).unwrap();
+        assert_eq!(tenants[0].1.owner, Some(Owner { uid: 1000, gid: Some(100) }));
+        assert_eq!(tenants[1].1.owner, None);
+
+        let group_only = config(r#"{"observers": [], "network": null, "tenants": [{"name": "carol", "gid": 100, "observers": [], "network": null}]}"#);
+        assert!(resolve(&group_only).is_err());
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use crate::core::models::TombstoneEntry;
+
+/// How long a deletion wins over copies peers still hold (30 days)
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How long a deletion is kept at most while peers haven't acknowledged it (365 days)
+/// Also how long a peer that stopped syncing an observer keeps holding deletions back.
+pub const DEFAULT_MAX_RETENTION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Most deletions adopted from one peer per observer
+/// A peer serving made-up tombstones would otherwise grow the state without bound.
+pub const MAX_ADOPTED_PER_PEER: usize = 10_000;
+
+/// Deletions of one path counted per peer ID
+/// Tells a deletion that already accounts for another from two made independently.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    /// Count one more deletion made by `node`
+    pub fn bump(&mut self, node: &str) {
+        *self.0.entry(node.to_string()).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.0 {
+            let ours = self.0.entry(node.clone()).or_default();
+            *ours = (*ours).max(*count);
+        }
+    }
+
+    /// Whether every deletion counted in `other` is counted here too
+    pub fn dominates(&self, other: &Self) -> bool {
+        other.0.iter().all(|(node, count)| self.0.get(node).is_some_and(|ours| ours >= count))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        self.0.iter()
+    }
+}
+
+/// Record of a deleted file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tombstone {
+    /// Content the file had when it was deleted
+    pub hash: String,
+    /// Unix timestamp of the deletion
+    pub deleted_at: u64,
+    pub version: VersionVector,
+    /// Peers known not to hold the deleted copy any more
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub acked_by: BTreeSet<String>,
+    /// Peer the deletion was adopted from, if it wasn't made here
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub learned_from: Option<String>,
+}
+
+impl Tombstone {
+    /// Whether a copy with `hash`, last modified at `modified_time`, predates the deletion
+    pub fn buries(&self, hash: &str, modified_time: Option<u64>) -> bool {
+        hash == self.hash || modified_time.is_some_and(|time| time <= self.deleted_at)
+    }
+}
+
+/// Deleted files per observer, kept for the retention period
+/// Stops peers that were offline during a deletion from bringing the file
+/// back, by announcing or serving the copy they still hold.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Tombstones {
+    observers: BTreeMap<String, BTreeMap<String, Tombstone>>,
+}
+
+impl Tombstones {
+    /// Record a local deletion of a file that had content `hash`
+    pub fn record(&mut self, observer: &str, path: &str, hash: String, node: &str, now: u64) {
+        let files = self.observers.entry(observer.to_string()).or_default();
+        let mut version = files.remove(path).map(|tombstone| tombstone.version).unwrap_or_default();
+        version.bump(node);
+        files.insert(path.to_string(), Tombstone { hash, deleted_at: now, version, acked_by: BTreeSet::new(), learned_from: None });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn get(&self, observer: &str, path: &str) -> Option<&Tombstone> {
+        self.observers.get(observer)?.get(path)
+    }
+
+    /// Forget a deletion, e.g. because the file was created again
+    pub fn clear(&mut self, observer: &str, path: &str) -> bool {
+        let Some(files) = self.observers.get_mut(observer) else {
+            return false;
+        };
+        let removed = files.remove(path).is_some();
+        if files.is_empty() {
+            self.observers.remove(observer);
+        }
+        removed
+    }
+
+    /// Adopt a peer's tombstone unless ours already accounts for it, returning true if ours changed
+    /// Of two independent deletions, the later one's content is kept.
+    pub fn merge(&mut self, observer: &str, path: &str, mut theirs: Tombstone) -> bool {
+        let files = self.observers.entry(observer.to_string()).or_default();
+        match files.get_mut(path) {
+            Some(ours) if ours.version.dominates(&theirs.version) => false,
+            Some(ours) => {
+                if !theirs.version.dominates(&ours.version) {
+                    theirs.version.merge(&ours.version);
+                    if ours.deleted_at > theirs.deleted_at {
+                        theirs.hash = ours.hash.clone();
+                        theirs.deleted_at = ours.deleted_at;
+                    }
+                }
+                *ours = theirs;
+                true
+            }
+            None => {
+                files.insert(path.to_string(), theirs);
+                true
+            }
+        }
+    }
+
+    /// Merge the deletions `peer` served for `observer`, returning how many changed ours
+    /// Deletion times are clamped to `now`, so a peer's future-dated tombstone can't
+    /// bury copies made after it, and at most `MAX_ADOPTED_PER_PEER` are taken from a peer.
+    pub fn adopt(
+        &mut self,
+        observer: &str,
+        peer: &str,
+        entries: impl IntoIterator<Item = TombstoneEntry>,
+        now: u64,
+    ) -> usize {
+        let mut learned = self.observers.get(observer)
+            .map_or(0, |files| files.values().filter(|tombstone| tombstone.learned_from.as_deref() == Some(peer)).count());
+        let mut adopted = 0;
+        for entry in entries {
+            let is_new = self.get(observer, &entry.path).is_none();
+            if is_new && learned >= MAX_ADOPTED_PER_PEER {
+                continue;
+            }
+            let path = entry.path.clone();
+            let mut theirs = Tombstone::from(entry);
+            theirs.deleted_at = theirs.deleted_at.min(now);
+            theirs.learned_from = Some(peer.to_string());
+            if self.merge(observer, &path, theirs) {
+                adopted += 1;
+                if is_new {
+                    learned += 1;
+                }
+            }
+        }
+        adopted
+    }
+
+    /// Whether `peer` hasn't acknowledged some deletion in `observer` yet
+    pub fn awaits(&self, observer: &str, peer: &str) -> bool {
+        self.observers.get(observer).is_some_and(|files| files.values().any(|tombstone| !tombstone.acked_by.contains(peer)))
+    }
+
+    /// Note that `peer` doesn't hold the files of `observer` matching `deleted`, returning how many were new to it
+    /// A newer deletion of the same path starts over with no acknowledgements.
+    pub fn acknowledge(&mut self, observer: &str, peer: &str, deleted: impl Fn(&str) -> bool) -> usize {
+        let Some(files) = self.observers.get_mut(observer) else {
+            return 0;
+        };
+        let mut acknowledged = 0;
+        for (_, tombstone) in files.iter_mut().filter(|(path, _)| deleted(path)) {
+            if tombstone.acked_by.insert(peer.to_string()) {
+                acknowledged += 1;
+            }
+        }
+        acknowledged
+    }
+
+    /// Drop tombstones past `retention` that every active peer of their observer acknowledged,
+    /// and any past `max_retention`, returning how many were dropped
+    /// A peer that was offline during a deletion still holds the old copy; forgetting
+    /// the deletion before it catches up would let that copy come back.
+    pub fn prune(
+        &mut self,
+        now: u64,
+        retention: Duration,
+        max_retention: Duration,
+        active: impl Fn(&str) -> BTreeSet<String>,
+    ) -> usize {
+        let mut pruned = 0;
+        for (observer, files) in self.observers.iter_mut() {
+            let peers = active(observer);
+            let before = files.len();
+            files.retain(|_, tombstone| {
+                let age = now.saturating_sub(tombstone.deleted_at);
+                age < retention.as_secs() || (age < max_retention.as_secs() && !peers.is_subset(&tombstone.acked_by))
+            });
+            pruned += before - files.len();
+        }
+        self.observers.retain(|_, files| !files.is_empty());
+        pruned
+    }
+
+    /// Drop tombstones older than `retention`, returning how many were dropped
+    pub fn expire(&mut self, now: u64, retention: Duration) -> usize {
+        let mut expired = 0;
+        for files in self.observers.values_mut() {
+            let before = files.len();
+            files.retain(|_, tombstone| tombstone.deleted_at.saturating_add(retention.as_secs()) > now);
+            expired += before - files.len();
+        }
+        self.observers.retain(|_, files| !files.is_empty());
+        expired
+    }
+
+    /// Tombstones to serve along with the tree node of `dir`
+    /// A deleted directory has no node of its own, so its files' tombstones go
+    /// with the deepest directory that still exists.
+    pub fn served_in(&self, observer: &str, dir: &str, has_dir: impl Fn(&str) -> bool) -> Vec<TombstoneEntry> {
+        let Some(files) = self.observers.get(observer) else {
+            return Vec::new();
+        };
+        files.iter()
+            .filter(|(path, _)| {
+                let mut parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                while !parent.is_empty() && !has_dir(parent) {
+                    parent = parent.rsplit_once('/').map_or("", |(parent, _)| parent);
+                }
+                parent == dir
+            })
+            .map(|(path, tombstone)| TombstoneEntry {
+                path: path.clone(),
+                hash: tombstone.hash.clone(),
+                deleted_at: tombstone.deleted_at,
+                version: tombstone.version.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Whether a complete listing of `dir` holding `listed` shows the peer has nothing at `path`
+/// A deleted file directly in `dir` must be missing from it; one further down
+/// is gone too when the subdirectory it was in isn't listed.
+pub fn missing_from_listing(dir: &str, listed: &HashSet<&str>, path: &str) -> bool {
+    let below = if dir.is_empty() {
+        Some(path)
+    } else {
+        path.strip_prefix(dir).and_then(|rest| rest.strip_prefix('/'))
+    };
+    below.and_then(|rest| rest.split('/').next())
+        .is_some_and(|first| !first.is_empty() && !listed.contains(first))
+}
+
+/// Current Unix timestamp, the clock deletions are stamped with
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl From<TombstoneEntry> for Tombstone {
+    fn from(entry: TombstoneEntry) -> Self {
+        Self { hash: entry.hash, deleted_at: entry.deleted_at, version: entry.version, acked_by: BTreeSet::new(), learned_from: None }
+    }
+}
+
+/// Peers seen syncing each observer, whose acknowledgements pruning waits for
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Replicas {
+    /// Unix timestamp each peer was last seen at, per observer
+    observers: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl Replicas {
+    pub fn record(&mut self, observer: &str, peer: &str, now: u64) {
+        self.observers.entry(observer.to_string()).or_default().insert(peer.to_string(), now);
+    }
+
+    pub fn last_seen(&self, observer: &str, peer: &str) -> Option<u64> {
+        self.observers.get(observer)?.get(peer).copied()
+    }
+
+    /// Stop waiting on a peer that no longer syncs `observer`
+    pub fn forget(&mut self, observer: &str, peer: &str) {
+        if let Some(peers) = self.observers.get_mut(observer) {
+            peers.remove(peer);
+            if peers.is_empty() {
+                self.observers.remove(observer);
+            }
+        }
+    }
+
+    /// Peers seen syncing `observer` within `window`
+    pub fn active(&self, observer: &str, now: u64, window: Duration) -> BTreeSet<String> {
+        self.observers.get(observer).into_iter()
+            .flatten()
+            .filter(|(_, seen)| seen.saturating_add(window.as_secs()) > now)
+            .map(|(peer, _)| peer.clone())
+            .collect()
+    }
+
+    /// Forget peers not seen for `window`
+    pub fn expire(&mut self, now: u64, window: Duration) {
+        for peers in self.observers.values_mut() {
+            peers.retain(|_, seen| seen.saturating_add(window.as_secs()) > now);
+        }
+        self.observers.retain(|_, peers| !peers.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deletions_bury_stale_copies_and_merge_by_version() {
+        let mut ours = Tombstones::default();
+        ours.record("docs", "a.txt", "old".to_string(), "peer-a", 100);
+        let tombstone = ours.get("docs", "a.txt").unwrap();
+        assert!(tombstone.buries("old", Some(500)));
+        assert!(tombstone.buries("other", Some(90)));
+        assert!(!tombstone.buries("new", Some(200)));
+
+        // A peer that already saw our deletion adds nothing
+        let mut theirs = Tombstones::default();
+        assert!(theirs.merge("docs", "a.txt", tombstone.clone()));
+        assert!(!ours.merge("docs", "a.txt", theirs.get("docs", "a.txt").unwrap().clone()));
+
+        // Deleted again independently after being recreated: the later deletion's content wins
+        theirs.record("docs", "a.txt", "newer".to_string(), "peer-b", 300);
+        ours.record("docs", "a.txt", "recreated".to_string(), "peer-a", 200);
+        assert!(ours.merge("docs", "a.txt", theirs.get("docs", "a.txt").unwrap().clone()));
+        let merged = ours.get("docs", "a.txt").unwrap();
+        assert_eq!((merged.hash.as_str(), merged.deleted_at), ("newer", 300));
+        assert!(merged.version.dominates(&theirs.get("docs", "a.txt").unwrap().version));
+
+        ours.record("docs", "gone/deep/b.txt", "b".to_string(), "peer-a", 300);
+        let served: Vec<String> = ours.served_in("docs", "", |dir| dir == "gone").into_iter().map(|entry| entry.path).collect();
+        assert_eq!(served, vec!["a.txt".to_string()]);
+        assert_eq!(ours.served_in("docs", "gone", |dir| dir == "gone").len(), 1);
+
+        assert_eq!(ours.expire(300 + DEFAULT_RETENTION.as_secs(), DEFAULT_RETENTION), 2);
+        assert!(ours.is_empty());
+    }
+
+    #[test]
+    fn test_deletions_are_pruned_once_every_active_peer_acknowledged() {
+        let mut tombstones = Tombstones::default();
+        tombstones.record("docs", "a.txt", "a".to_string(), "peer-a", 100);
+        tombstones.record("docs", "b.txt", "b".to_string(), "peer-a", 100);
+        let mut replicas = Replicas::default();
+        replicas.record("docs", "laptop", 50);
+        replicas.record("docs", "desktop", 100);
+
+        // The laptop was offline during the deletions and still holds b.txt
+        let later = 100 + DEFAULT_RETENTION.as_secs();
+        assert_eq!(tombstones.acknowledge("docs", "desktop", |_| true), 2);
+        assert_eq!(tombstones.acknowledge("docs", "laptop", |path| path == "a.txt"), 1);
+        assert!(tombstones.awaits("docs", "laptop") && !tombstones.awaits("docs", "desktop"));
+        let active = |observer: &str| replicas.active(observer, later, DEFAULT_MAX_RETENTION);
+        assert_eq!(tombstones.prune(later, DEFAULT_RETENTION, DEFAULT_MAX_RETENTION, active), 1);
+        assert!(tombstones.get("docs", "a.txt").is_none() && tombstones.get("docs", "b.txt").is_some());
+
+        // Deleted again: earlier acknowledgements don't count for the new deletion
+        tombstones.record("docs", "b.txt", "b2".to_string(), "peer-a", later);
+        assert!(tombstones.awaits("docs", "desktop"));
+
+        // A peer that left the observer stops holding pruning back, and nothing is kept forever
+        replicas.forget("docs", "laptop");
+        assert_eq!(replicas.active("docs", later, DEFAULT_MAX_RETENTION).len(), 1);
+        let much_later = later + DEFAULT_MAX_RETENTION.as_secs();
+        assert_eq!(tombstones.prune(much_later, DEFAULT_RETENTION, DEFAULT_MAX_RETENTION, |_| BTreeSet::from(["desktop".to_string()])), 1);
+        assert!(tombstones.is_empty());
+        replicas.expire(much_later, DEFAULT_MAX_RETENTION);
+        assert_eq!(replicas, Replicas::default());
+    }
+
+    #[test]
+    fn test_adopted_deletions_are_clamped_and_capped_per_peer() {
+        let entry = |path: &str, deleted_at: u64| {
+            let mut version = VersionVector::default();
+            version.bump("peer-b");
+            TombstoneEntry { path: path.to_string(), hash: "h".to_string(), deleted_at, version }
+        };
+        let mut tombstones = Tombstones::default();
+
+        // Dated far in the future, it would otherwise bury every later copy
+        assert_eq!(tombstones.adopt("docs", "peer-b", [entry("a.txt", u64::MAX)], 100), 1);
+        let adopted = tombstones.get("docs", "a.txt").unwrap();
+        assert_eq!(adopted.deleted_at, 100);
+        assert!(!adopted.buries("new", Some(200)));
+
+        let flood = (0..MAX_ADOPTED_PER_PEER).map(|i| entry(&format!("{}.txt", i), 100));
+        assert_eq!(tombstones.adopt("docs", "peer-b", flood, 100), MAX_ADOPTED_PER_PEER - 1);
+        assert_eq!(tombstones.adopt("docs", "peer-b", [entry("more.txt", 100)], 100), 0);
+        // Other peers and observers have their own allowance
+        assert_eq!(tombstones.adopt("docs", "peer-c", [entry("more.txt", 100)], 100), 1);
+        assert_eq!(tombstones.adopt("photos", "peer-b", [entry("more.txt", 100)], 100), 1);
+    }
+
+    #[test]
+    fn test_a_listing_acknowledges_deletions_in_subdirectories_it_no_longer_has() {
+        let listed = HashSet::from(["c.txt", "b"]);
+        assert!(missing_from_listing("a", &listed, "a/gone.txt"));
+        assert!(!missing_from_listing("a", &listed, "a/c.txt"));
+        // Whatever was below a subdirectory the peer no longer has is gone with it
+        assert!(!missing_from_listing("a", &listed, "x/y.txt"));
+        assert!(missing_from_listing("a", &listed, "a/d/e/f.txt"));
+        assert!(!missing_from_listing("a", &listed, "a/b/e.txt"));
+        // Only paths below the listed directory count, not ones sharing its name as a prefix
+        assert!(!missing_from_listing("a", &listed, "ab/e.txt"));
+        assert!(missing_from_listing("", &HashSet::from(["a"]), "d/e.txt"));
+        assert!(!missing_from_listing("", &HashSet::from(["a"]), "a/e.txt"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use sha2::{Sha256, Digest};
+use crate::core::models::{FileEventMessage, TransactionInfo};
+
+/// How long a receiver waits for a transaction to complete before applying what arrived
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Groups a burst of local changes into one transaction
+/// Events are buffered until the observer has been quiet for `window`, then
+/// released together, each tagged with the same transaction id and size.
+pub struct TransactionBatcher {
+    window: Duration,
+    pending: Vec<FileEventMessage>,
+    last_event: Option<Instant>,
+}
+
+impl TransactionBatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Vec::new(),
+            last_event: None,
+        }
+    }
+
+    /// Quiet period that closes a batch
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Buffer an event, extending the current batch
+    pub fn push(&mut self, msg: FileEventMessage, now: Instant) {
+        self.pending.push(msg);
+        self.last_event = Some(now);
+    }
+
+    /// Whether the current batch has been quiet long enough to release
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_event {
+            Some(last) => !self.pending.is_empty() && now.saturating_duration_since(last) >= self.window,
+            None => false,
+        }
+    }
+
+    /// Release the buffered batch
+    /// Single events are released untagged, since there is nothing to group.
+    pub fn take(&mut self) -> Vec<FileEventMessage> {
+        let mut batch = std::mem::take(&mut self.pending);
+        self.last_event = None;
+        if batch.len() > 1 {
+            let info = TransactionInfo {
+                id: transaction_id(&batch),
+                size: batch.len() as u32,
+            };
+            for msg in batch.iter_mut() {
+                msg.transaction = Some(info.clone());
+            }
+        }
+        batch
+    }
+}
+
+/// Derive a transaction id from the batch contents and the current time
+fn transaction_id(batch: &[FileEventMessage]) -> String {
+    let mut hasher = Sha256::new();
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    hasher.update(nanos.to_string().as_bytes());
+    for msg in batch {
+        hasher.update(msg.observer.as_bytes());
+        hasher.update(b"||");
+        hasher.update(msg.path.as_bytes());
+        hasher.update(b"||");
+    }
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Receiver-side state for one incoming transaction
+#[derive(Debug)]
+pub struct PendingTransaction {
+    pub observer: String,
+    pub expected: usize,
+    /// Paths announced so far
+    pub announced: HashSet<String>,
+    /// Paths still being transferred
+    pub awaiting: HashSet<String>,
+    /// Paths whose content is staged and ready to move into place
+    pub staged: HashSet<String>,
+    pub started: Instant,
+}
+
+impl PendingTransaction {
+    /// All announcements arrived and no transfers are outstanding
+    pub fn is_ready(&self) -> bool {
+        self.announced.len() >= self.expected && self.awaiting.is_empty()
+    }
+}
+
+/// Tracks incoming transactions until all of their content is staged
+#[derive(Default)]
+pub struct TransactionTracker {
+    transactions: HashMap<String, PendingTransaction>,
+}
+
+impl TransactionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an announcement belonging to a transaction
+    /// `needs_transfer` is false when the local copy is already up to date.
+    pub fn announce(&mut self, observer: &str, info: &TransactionInfo, path: &str, needs_transfer: bool) {
+        let transaction = self.transactions
+            .entry(info.id.clone())
+            .or_insert_with(|| PendingTransaction {
+                observer: observer.to_string(),
+                expected: info.size as usize,
+                announced: HashSet::new(),
+                awaiting: HashSet::new(),
+                staged: HashSet::new(),
+                started: Instant::now(),
+            });
+        transaction.announced.insert(path.to_string());
+        if needs_transfer {
+            transaction.awaiting.insert(path.to_string());
+        }
+    }
+
+    /// Mark a transfer's content as staged
+    pub fn staged(&mut self, id: &str, path: &str) {
+        if let Some(transaction) = self.transactions.get_mut(id) {
+            if transaction.awaiting.remove(path) {
+                transaction.staged.insert(path.to_string());
+            }
+        }
+    }
+
+    /// Give up on one member transfer so the rest of the transaction can still apply
+    pub fn abandon(&mut self, id: &str, path: &str) {
+        if let Some(transaction) = self.transactions.get_mut(id) {
+            transaction.awaiting.remove(path);
+        }
+    }
+
+    /// Remove and return the transaction if it is ready to commit
+    pub fn take_ready(&mut self, id: &str) -> Option<PendingTransaction> {
+        if self.transactions.get(id).is_some_and(|t| t.is_ready()) {
+            self.transactions.remove(id)
+        } else {
+            None
+        }
+    }
+
+    /// Remove and return transactions older than `timeout`
+    pub fn take_expired(&mut self, now: Instant, timeout: Duration) -> Vec<(String, PendingTransaction)> {
+        let expired: Vec<String> = self.transactions.iter()
+            .filter(|(_, t)| now.saturating_duration_since(t.started) >= timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired.into_iter()
+            .filter_map(|id| self.transactions.remove(&id).map(|t| (id, t)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::EventType;
+
+    fn event(path: &str) -> FileEventMessage {
+        FileEventMessage {
+            observer: "docs".to_string(),
+            event_type: EventType::Modify,
+            path: path.to_string(),
+            details: None,
+            hash: Some("abcd".to_string()),
+            size: Some(4),
+            modified_time: Some(0),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_batcher_groups_burst() {
+        let mut batcher = TransactionBatcher::new(Duration::from_millis(500));
+        let start = Instant::now();
+        batcher.push(event("a.txt"), start);
+        batcher.push(event("b.txt"), start + Duration::from_millis(100));
+
+        assert!(!batcher.is_due(start + Duration::from_millis(400)));
+        assert!(batcher.is_due(start + Duration::from_millis(600)));
+
+        let batch = batcher.take();
+        assert_eq!(batch.len(), 2);
+        let info = batch[0].transaction.clone().unwrap();
+        assert_eq!(info.size, 2);
+        assert_eq!(batch[1].transaction.as_ref().unwrap().id, info.id);
+        assert!(!batcher.is_due(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_single_event_is_untagged() {
+        let mut batcher = TransactionBatcher::new(Duration::from_millis(500));
+        batcher.push(event("a.txt"), Instant::now());
+        let batch = batcher.take();
+        assert!(batch[0].transaction.is_none());
+    }
+
+    #[test]
+    fn test_tracker_ready_after_all_staged() {
+        let mut tracker = TransactionTracker::new();
+        let info = TransactionInfo { id: "tx1".to_string(), size: 3 };
+
+        tracker.announce("docs", &info, "a.txt", true);
+        tracker.announce("docs", &info, "b.txt", false);
+        assert!(tracker.take_ready("tx1").is_none());
+
+        tracker.announce("docs", &info, "c.txt", true);
+        tracker.staged("tx1", "a.txt");
+        assert!(tracker.take_ready("tx1").is_none());
+
+        tracker.staged("tx1", "c.txt");
+        let transaction = tracker.take_ready("tx1").unwrap();
+        assert_eq!(transaction.staged.len(), 2);
+        assert!(tracker.take_ready("tx1").is_none());
+    }
+}
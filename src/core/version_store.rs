@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use sha2::{Sha256, Digest};
+use crate::core::compression;
+use crate::core::storage::StorageBackend;
+
+/// Largest file whose common version is kept
+const MAX_VERSION_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Last version of each mergeable file known to be on both sides
+/// Recorded when a file is received, and when a peer announces content equal
+/// to ours, so it is the common ancestor when both sides change the file.
+pub struct VersionStore {
+    dir: PathBuf,
+    /// Extensions tracked per observer
+    extensions: HashMap<String, Vec<String>>,
+    /// Observers whose versions are stored compressed
+    compressed: HashSet<String>,
+}
+
+impl VersionStore {
+    pub fn new(dir: &Path, extensions: HashMap<String, Vec<String>>) -> Self {
+        Self { dir: dir.to_path_buf(), extensions, compressed: HashSet::new() }
+    }
+
+    /// Store the versions of these observers compressed
+    pub fn with_compression(mut self, observers: HashSet<String>) -> Self {
+        self.compressed = observers;
+        self
+    }
+
+    /// Whether versions of this file are kept
+    pub fn tracks(&self, observer: &str, path: &str) -> bool {
+        let Some(extensions) = self.extensions.get(observer) else {
+            return false;
+        };
+        Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.iter().any(|tracked| tracked.trim_start_matches('.').eq_ignore_ascii_case(ext)))
+    }
+
+    /// The common version of a file, if one was recorded
+    pub fn common(&self, observer: &str, path: &str) -> Option<Vec<u8>> {
+        compression::read(&self.location(observer, path)).ok()
+    }
+
+    /// Remember `content` as the version both sides have
+    pub fn record(&self, observer: &str, path: &str, content: &[u8]) -> io::Result<()> {
+        if !self.tracks(observer, path) {
+            return Ok(());
+        }
+        if content.len() as u64 > MAX_VERSION_BYTES {
+            return self.forget(observer, path);
+        }
+        fs::create_dir_all(&self.dir)?;
+        compression::write(&self.location(observer, path), content, self.compressed.contains(observer))
+    }
+
+    /// Remember the local copy as the version both sides have
+    pub fn record_local(&self, observer: &str, path: &str, storage: &dyn StorageBackend) -> io::Result<()> {
+        if !self.tracks(observer, path) {
+            return Ok(());
+        }
+        let relative_path = Path::new(path);
+        let size = storage.size(relative_path)?;
+        if size > MAX_VERSION_BYTES {
+            return self.forget(observer, path);
+        }
+        self.record(observer, path, &storage.read_chunk(relative_path, 0, size as usize)?)
+    }
+
+    /// Drop a file's common version; an outdated one would be worse than none
+    fn forget(&self, observer: &str, path: &str) -> io::Result<()> {
+        compression::remove(&self.location(observer, path))
+    }
+
+    /// Hashed names keep arbitrary paths out of the store's directory structure
+    fn location(&self, observer: &str, path: &str) -> PathBuf {
+        let name = format!("{:x}", Sha256::digest(format!("{}\0{}", observer, path).as_bytes()));
+        self.dir.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_tracked_extensions_are_recorded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let extensions = HashMap::from([("docs".to_string(), vec![".md".to_string(), "txt".to_string()])]);
+        let store = VersionStore::new(&temp_dir.path().join("versions"), extensions);
+
+        store.record("docs", "notes/a.MD", b"v1").unwrap();
+        store.record("docs", "image.png", b"png").unwrap();
+        store.record("other", "b.txt", b"v1").unwrap();
+        assert_eq!(store.common("docs", "notes/a.MD"), Some(b"v1".to_vec()));
+        assert_eq!(store.common("docs", "image.png"), None);
+        assert_eq!(store.common("other", "b.txt"), None);
+
+        store.record("docs", "notes/a.MD", b"v2").unwrap();
+        assert_eq!(store.common("docs", "notes/a.MD"), Some(b"v2".to_vec()));
+
+        // Compressed versions read back the same, replacing the plain copy
+        let store = store.with_compression(HashSet::from(["docs".to_string()]));
+        store.record("docs", "notes/a.MD", b"v3").unwrap();
+        assert_eq!(store.common("docs", "notes/a.MD"), Some(b"v3".to_vec()));
+        assert_eq!(fs::read_dir(temp_dir.path().join("versions")).unwrap().count(), 1);
+    }
+}
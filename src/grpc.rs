@@ -0,0 +1,244 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use futures::{Stream, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tonic::{Request, Response, Status as RpcStatus};
+use tonic::metadata::MetadataMap;
+use tracing::{debug, info, warn};
+use crate::control::{ControlCommand, ControlRequest, ControlResponse, DaemonStatus, FileStatus, SyncState};
+use crate::core::auth;
+use crate::core::config::GrpcConfig;
+use crate::core::conflict::ResolvedConflict;
+use crate::core::listen;
+use crate::core::secret::Secret;
+use crate::network::transfer::TransferProgress;
+
+/// Types and service generated from proto/syndactyl.proto
+pub mod proto {
+    tonic::include_proto!("syndactyl");
+}
+
+use proto::syndactyl_server::{Syndactyl, SyndactylServer};
+
+/// Serve the control API over gRPC, for typed clients such as GUIs
+/// Requests go to the network manager like those on the control socket, and
+/// every call must carry the same token as `authorization: Bearer <token>`.
+pub async fn spawn(addr: SocketAddr, token: Secret, commands: mpsc::Sender<ControlCommand>, feed: broadcast::Sender<FileStatus>) -> Result<(), String> {
+    let listener = TcpListener::bind(addr).await
+        .map_err(|e| format!("Failed to bind gRPC server to {}: {}", addr, e))?;
+    info!(listen = %addr, "gRPC control API listening");
+
+    let service = SyndactylServer::with_interceptor(Service { commands, feed }, move |request: Request<()>| {
+        if authorized(request.metadata(), token.expose()) {
+            Ok(request)
+        } else {
+            warn!("gRPC request without a valid token");
+            Err(RpcStatus::unauthenticated("Missing or wrong control token"))
+        }
+    });
+    tokio::spawn(async move {
+        let served = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await;
+        if let Err(e) = served {
+            warn!(error = %e, "gRPC server stopped");
+        }
+    });
+    Ok(())
+}
+
+/// The address to serve on, which must be local
+/// Without TLS every call's token and reply would be readable on the network.
+pub fn listen_addr(config: &GrpcConfig) -> Result<SocketAddr, String> {
+    if config.public == Some(true) {
+        return Err("The gRPC server has no TLS and can't be public; reach it through an SSH tunnel or a local TLS proxy".to_string());
+    }
+    listen::resolve("gRPC server", &config.listen, false)
+}
+
+/// Whether the request metadata carries `token`
+fn authorized(metadata: &MetadataMap, token: &str) -> bool {
+    metadata.get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| auth::constant_time_compare(given.trim(), token))
+}
+
+struct Service {
+    commands: mpsc::Sender<ControlCommand>,
+    feed: broadcast::Sender<FileStatus>,
+}
+
+impl Service {
+    /// Forward a request to the network manager and wait for its reply
+    async fn call(&self, request: ControlRequest) -> Result<ControlResponse, RpcStatus> {
+        debug!(?request, "gRPC request");
+        let (reply, rx) = oneshot::channel();
+        self.commands.send(ControlCommand { request, reply }).await
+            .map_err(|_| RpcStatus::unavailable("Daemon is shutting down"))?;
+        match rx.await.map_err(|_| RpcStatus::unavailable("Daemon is shutting down"))? {
+            ControlResponse::Error { message } => Err(RpcStatus::failed_precondition(message)),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: ControlResponse) -> RpcStatus {
+    RpcStatus::internal(format!("Unexpected reply from the daemon: {:?}", response))
+}
+
+#[tonic::async_trait]
+impl Syndactyl for Service {
+    async fn get_status(&self, _request: Request<proto::StatusRequest>) -> Result<Response<proto::Status>, RpcStatus> {
+        match self.call(ControlRequest::Status).await? {
+            ControlResponse::Status(status) => Ok(Response::new(status.into())),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn list_transfers(&self, _request: Request<proto::TransfersRequest>) -> Result<Response<proto::TransfersReply>, RpcStatus> {
+        match self.call(ControlRequest::Transfers).await? {
+            ControlResponse::Transfers { transfers } => Ok(Response::new(proto::TransfersReply {
+                transfers: transfers.into_iter().map(Into::into).collect(),
+            })),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn list_conflicts(&self, _request: Request<proto::ConflictsRequest>) -> Result<Response<proto::ConflictsReply>, RpcStatus> {
+        match self.call(ControlRequest::Conflicts).await? {
+            ControlResponse::Conflicts { conflicts } => Ok(Response::new(proto::ConflictsReply {
+                conflicts: conflicts.into_iter().map(Into::into).collect(),
+            })),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn get_file_status(&self, request: Request<proto::FileStatusRequest>) -> Result<Response<proto::FileStatusReply>, RpcStatus> {
+        let paths = request.into_inner().paths;
+        match self.call(ControlRequest::FileStatus { paths }).await? {
+            ControlResponse::FileStatuses { files } => Ok(Response::new(proto::FileStatusReply {
+                files: files.into_iter().map(Into::into).collect(),
+            })),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    type WatchFilesStream = Pin<Box<dyn Stream<Item = Result<proto::FileUpdate, RpcStatus>> + Send>>;
+
+    async fn watch_files(&self, _request: Request<proto::WatchFilesRequest>) -> Result<Response<Self::WatchFilesStream>, RpcStatus> {
+        let updates = BroadcastStream::new(self.feed.subscribe()).map(|update| Ok(match update {
+            Ok(status) => proto::FileUpdate { status: Some(status.into()), resync: false },
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                debug!(missed, "gRPC file status watcher fell behind");
+                proto::FileUpdate { status: None, resync: true }
+            }
+        }));
+        Ok(Response::new(Box::pin(updates)))
+    }
+}
+
+impl From<DaemonStatus> for proto::Status {
+    fn from(status: DaemonStatus) -> Self {
+        Self {
+            peer_id: status.peer_id,
+            connected_peers: status.connected_peers as u64,
+            active_downloads: status.active_downloads as u64,
+            queued_serve_requests: status.queued_serve_requests as u64,
+            deferred_transfers: status.deferred_transfers as u64,
+            queued_downloads: status.queued_downloads as u64,
+            profile: status.profile,
+            listen_addresses: status.listen_addresses,
+            external_addresses: status.external_addresses,
+            unavailable_observers: status.unavailable_observers,
+            low_space_observers: status.low_space_observers,
+            stale_observers: status.stale_observers,
+            held_deletions: status.held_deletions.into_iter().map(|(observer, count)| (observer, count as u64)).collect(),
+            dht_bootstrapped: status.dht_bootstrapped,
+            discovery: status.discovery,
+            auth_failures: status.auth_failures,
+        }
+    }
+}
+
+impl From<TransferProgress> for proto::Transfer {
+    fn from(transfer: TransferProgress) -> Self {
+        Self {
+            observer: transfer.observer,
+            path: transfer.path,
+            peer: transfer.peer,
+            total_size: transfer.total_size,
+            chunks_received: transfer.chunks_received as u64,
+            total_chunks: transfer.total_chunks as u64,
+            elapsed_secs: transfer.elapsed_secs,
+        }
+    }
+}
+
+impl From<ResolvedConflict> for proto::Conflict {
+    fn from(conflict: ResolvedConflict) -> Self {
+        Self {
+            observer: conflict.observer,
+            path: conflict.path,
+            resolution: conflict.resolution,
+            timestamp: conflict.timestamp,
+        }
+    }
+}
+
+impl From<SyncState> for proto::SyncState {
+    fn from(state: SyncState) -> Self {
+        match state {
+            SyncState::Unwatched => Self::Unwatched,
+            SyncState::Unavailable => Self::Unavailable,
+            SyncState::Paused => Self::Paused,
+            SyncState::Syncing => Self::Syncing,
+            SyncState::Pending => Self::Pending,
+            SyncState::Offline => Self::Offline,
+            SyncState::Synced => Self::Synced,
+        }
+    }
+}
+
+impl From<FileStatus> for proto::FileStatus {
+    fn from(status: FileStatus) -> Self {
+        Self {
+            path: status.path,
+            observer: status.observer,
+            state: proto::SyncState::from(status.state) as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calls_need_the_control_token() {
+        let mut metadata = MetadataMap::new();
+        assert!(!authorized(&metadata, "secret"));
+        metadata.insert("authorization", "Bearer wrong".parse().unwrap());
+        assert!(!authorized(&metadata, "secret"));
+        metadata.insert("authorization", "secret".parse().unwrap());
+        assert!(!authorized(&metadata, "secret"));
+        metadata.insert("authorization", "Bearer secret".parse().unwrap());
+        assert!(authorized(&metadata, "secret"));
+
+        let status = proto::FileStatus::from(FileStatus { path: "/a".to_string(), observer: "docs".to_string(), state: SyncState::Pending });
+        assert_eq!(status.state(), proto::SyncState::Pending);
+    }
+
+    #[test]
+    fn test_only_local_addresses_are_served_without_tls() {
+        let config = |listen: &str, public: Option<bool>| GrpcConfig { listen: listen.to_string(), public };
+        assert_eq!(listen_addr(&config("50051", None)).unwrap(), "127.0.0.1:50051".parse().unwrap());
+        assert!(listen_addr(&config("0.0.0.0:50051", None)).is_err());
+        assert!(listen_addr(&config("0.0.0.0:50051", Some(true))).unwrap_err().contains("TLS"));
+        assert!(listen_addr(&config("127.0.0.1:50051", Some(true))).is_err());
+    }
+}
@@ -0,0 +1,16 @@
+//! Syndactyl peer-to-peer file sync
+//!
+//! The daemon binary is a thin wrapper around these modules; they are also
+//! usable directly by embedders, tests and fuzz targets.
+
+pub mod core;
+pub mod network;
+pub mod node;
+pub mod control;
+pub mod bridge;
+#[cfg(feature = "browser")]
+pub mod browser;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "fuse")]
+pub mod mount;
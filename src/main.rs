@@ -1,23 +1,136 @@
-mod core;
-mod network;
+mod cli;
+mod wizard;
 
 use std::sync::mpsc as std_mpsc;
+use std::sync::OnceLock;
 use std::thread;
 
-use crate::network::manager::NetworkManager;
-use crate::core::observer;
-use crate::core::config;
+use syndactyl::node::SyndactylNode;
+use syndactyl::core::observer;
+use syndactyl::core::config;
+use syndactyl::core::audit;
+use syndactyl::core::state;
+use syndactyl::core::compression;
+use syndactyl::core::bandwidth::format_bytes;
+use syndactyl::core::power::PowerMonitor;
+use syndactyl::core::tenant;
+use syndactyl::core::instance_lock::InstanceLock;
+use syndactyl::core::local_only::LocalOnly;
+use syndactyl::core::models::{EventType, FileEventMessage};
+use syndactyl::control::{self, ControlRequest, ControlResponse, SyncState};
+use crate::cli::Command;
 
-use tracing::{info, error};
+use futures::FutureExt;
+
+use tracing::{info, info_span, error, Instrument};
+
+/// Tenant selected with `--tenant`, if any
+static TENANT: OnceLock<Option<String>> = OnceLock::new();
+
+/// How long `--takeover` waits for the running daemon to stop
+const TAKEOVER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
 
 #[tokio::main]
 async fn main() {
     // Initialize logging
+    // The console layer also installs the usual formatted output
+    #[cfg(feature = "console")]
+    console_subscriber::init();
+    #[cfg(all(not(feature = "console"), not(feature = "otlp")))]
     tracing_subscriber::fmt::init();
+    // Spans are exported once the configuration names a collector
+    #[cfg(all(not(feature = "console"), feature = "otlp"))]
+    let trace_handle = {
+        use tracing_subscriber::prelude::*;
+        let (otlp_layer, handle) = syndactyl::core::otlp::trace_layer();
+        tracing_subscriber::registry()
+            .with(otlp_layer)
+            .with(tracing_subscriber::fmt::layer())
+            .with(tracing_subscriber::filter::LevelFilter::INFO)
+            .init();
+        handle
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (tenant, args) = cli::split_tenant(&args);
+    TENANT.get_or_init(|| tenant);
+    let command = match cli::parse(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let takeover = matches!(command, Command::Run { takeover: true, .. });
+    match command {
+        Command::Run { local_only: true, .. } => {
+            std::process::exit(run_local_only().await);
+        }
+        Command::Run { .. } => {}
+        Command::Init => {
+            std::process::exit(wizard::run());
+        }
+        Command::AuditVerify { path } => {
+            std::process::exit(run_audit_verify(path));
+        }
+        Command::Stats { bandwidth, history } => {
+            std::process::exit(run_stats(bandwidth, history));
+        }
+        Command::Status => {
+            std::process::exit(run_status());
+        }
+        Command::Peers => {
+            std::process::exit(run_peers());
+        }
+        Command::Id { qr } => {
+            std::process::exit(run_id(qr));
+        }
+        Command::Pause { observer } => {
+            std::process::exit(run_control(ControlRequest::Pause { observer }));
+        }
+        Command::Resume { observer } => {
+            std::process::exit(run_control(ControlRequest::Resume { observer }));
+        }
+        Command::Rescan { observer } => {
+            std::process::exit(run_control(ControlRequest::Rescan { observer }));
+        }
+        Command::ProfileUse { name } => {
+            std::process::exit(run_control(ControlRequest::UseProfile { name }));
+        }
+        Command::ProfileClear => {
+            std::process::exit(run_control(ControlRequest::ClearProfile));
+        }
+        Command::DeletionsConfirm { observer } => {
+            std::process::exit(run_control(ControlRequest::ConfirmDeletions { observer }));
+        }
+        Command::DeletionsDiscard { observer } => {
+            std::process::exit(run_control(ControlRequest::DiscardDeletions { observer }));
+        }
+        Command::Peek { observer, path, peer, bytes, lines } => {
+            std::process::exit(run_peek(ControlRequest::Peek { observer, path, peer, bytes, lines }));
+        }
+        Command::Diff { observer, peer } => {
+            std::process::exit(run_diff(ControlRequest::Diff { observer, peer }));
+        }
+        Command::Repair => {
+            std::process::exit(run_repair());
+        }
+        Command::Completions { shell } => {
+            print!("{}", cli::completions(shell));
+            return;
+        }
+        Command::PromptStatus { path } => {
+            std::process::exit(run_prompt_status(path));
+        }
+        Command::TrayWatch => {
+            std::process::exit(run_tray_watch());
+        }
+    }
 
     //  Begin application startup
     // Initialize configuration
-    let configuration = match config::get_config() {
+    let configuration = match load_config() {
         Ok(configuration) => {
             info!(?configuration, "Configuration loaded successfully");
             configuration
@@ -27,32 +140,633 @@ async fn main() {
             return;
         }
     };
+    let tenants = match tenant::resolve(&configuration) {
+        Ok(tenants) => tenants,
+        Err(e) => {
+            error!(%e, "Invalid tenant configuration");
+            return;
+        }
+    };
+    #[cfg(all(not(feature = "console"), feature = "otlp"))]
+    let tracer_provider = match configuration.otlp.as_ref().map(|otlp| syndactyl::core::otlp::start_tracing(otlp, &trace_handle)) {
+        Some(Ok(provider)) => provider,
+        Some(Err(e)) => {
+            error!(%e, "Failed to start exporting spans over OTLP");
+            return;
+        }
+        None => None,
+    };
+    // One daemon per state directory; a second one would corrupt the state and announce every event twice
+    let mut locks = Vec::new();
+    for (name, daemon_config) in std::iter::once(("(top level)", &configuration)).chain(tenants.iter().map(|(name, config)| (name.as_str(), config))) {
+        match lock_state_dir(daemon_config, takeover) {
+            Ok(lock) => locks.push(lock),
+            Err(e) => {
+                error!(tenant = %name, %e, "Not starting");
+                return;
+            }
+        }
+    }
     // End application startup
 
-    // Spawn Observer and set up channel for file events
+    // Tenants run side by side with the top level, each in complete isolation
+    let mut locks = locks.into_iter();
+    let mut daemons = vec![run_daemon(configuration, locks.next()).boxed_local()];
+    for ((name, tenant_config), lock) in tenants.into_iter().zip(locks) {
+        info!(tenant = %name, "Starting tenant");
+        daemons.push(run_daemon(tenant_config, Some(lock)).instrument(info_span!("tenant", name = %name)).boxed_local());
+    }
+    futures::future::join_all(daemons).await;
+    // Send the spans still buffered
+    #[cfg(all(not(feature = "console"), feature = "otlp"))]
+    if let Some(provider) = tracer_provider {
+        // The exporter's blocking HTTP client can't run on the runtime's threads
+        match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+            Ok(Err(e)) => error!(%e, "Failed to flush OTLP spans"),
+            Err(e) => error!(%e, "Failed to flush OTLP spans"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
+
+/// Lock the state directory of a configuration, first asking a running daemon to stop with `takeover`
+fn lock_state_dir(configuration: &config::Config, takeover: bool) -> Result<InstanceLock, String> {
+    let dir = configuration.state_dir().map_err(|e| e.to_string())?;
+    let lock_failed = |e: std::io::Error| format!("Failed to lock state directory {}: {}", dir.display(), e);
+    if let Some(lock) = InstanceLock::try_acquire(&dir).map_err(lock_failed)? {
+        if let Some(pid) = lock.stale_pid() {
+            info!(pid, "The previous daemon did not shut down cleanly");
+        }
+        return Ok(lock);
+    }
+
+    let holder = InstanceLock::holder(&dir).map(|pid| format!(" (pid {})", pid)).unwrap_or_default();
+    if !takeover {
+        return Err(format!(
+            "Another syndactyl daemon{} is already running with state directory {}; stop it or start with --takeover",
+            holder,
+            dir.display()
+        ));
+    }
+    info!("Asking the running daemon{} to stop", holder);
+    let socket_path = configuration.control_socket_path().map_err(|e| e.to_string())?;
+    let token_path = configuration.control_token_path().map_err(|e| e.to_string())?;
+    let token = control::read_token(&token_path)
+        .map_err(|e| format!("Could not read control token {}: {}", token_path.display(), e))?;
+    match control::request(&socket_path, &token, &ControlRequest::Shutdown) {
+        Ok(ControlResponse::Done { .. }) => {}
+        Ok(response) => return Err(format!("The running daemon refused to stop: {:?}", response)),
+        Err(e) => return Err(format!("Could not reach the running daemon at {}: {}", socket_path.display(), e)),
+    }
+    InstanceLock::wait(&dir, TAKEOVER_TIMEOUT)
+        .map_err(lock_failed)?
+        .ok_or_else(|| format!("The running daemon{} did not stop within {} seconds", holder, TAKEOVER_TIMEOUT.as_secs()))
+}
+
+/// The configuration commands act on: the whole file, or the tenant chosen with `--tenant`
+fn load_config() -> Result<config::Config, Box<dyn std::error::Error>> {
+    let configuration = config::get_config()?;
+    match TENANT.get().and_then(|tenant| tenant.as_deref()) {
+        Some(name) => Ok(tenant::find(&configuration, name)?),
+        None => Ok(configuration),
+    }
+}
+
+/// Run the observers, file browser and network manager of one configuration
+/// The state directory stays locked until the daemon returns.
+async fn run_daemon(configuration: config::Config, _lock: Option<InstanceLock>) {
+    SyndactylNode::new(configuration).with_all_features().run().await;
+}
+
+/// Run the observers with networking disabled, printing what would be announced
+/// Nothing is written to the state file and the state directory isn't locked, so
+/// a new observer can be staged next to a running daemon. Stops on Ctrl-C.
+async fn run_local_only() -> i32 {
+    let configuration = match load_config() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 2;
+        }
+    };
+    let index = match configuration.state_path().map(|path| state::load(&path)) {
+        Ok(Ok(state)) => state.index,
+        Ok(Err(e)) => {
+            eprintln!("Failed to read state file: {}", e);
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 2;
+        }
+    };
+    let mut local = LocalOnly::new(index);
+    // Only observers that watch a plain local tree announce anything
+    let watched: Vec<config::ObserverConfig> = configuration.observers.iter()
+        .filter(|obs| obs.announce_only != Some(true) && obs.at_rest_key.is_none() && obs.mount.is_none())
+        .cloned()
+        .collect();
+    println!("Local-only mode: networking disabled, nothing is announced or saved\n");
+
+    // Watching starts before the initial scan, so changes made while it runs aren't missed
     let (observer_tx, observer_rx) = std_mpsc::channel::<String>();
-    let observer_config = configuration.observers.clone();
-    let observer_thread = thread::spawn(move || {
-        let _observer = observer::event_listener(observer_config, observer_tx);
-        info!("Observer started");
+    let observers = watched.clone();
+    let logging_config = configuration.logging.clone().unwrap_or_default();
+    let power = PowerMonitor::new(configuration.power.as_ref());
+    power.refresh();
+    thread::spawn(move || {
+        let _observer = observer::event_listener(observers, logging_config, power, observer_tx);
+    });
+    let (events_tx, mut events_rx) = tokio::sync::mpsc::channel::<String>(100);
+    thread::spawn(move || {
+        while let Ok(msg) = observer_rx.recv() {
+            if events_tx.blocking_send(msg).is_err() {
+                break;
+            }
+        }
     });
 
-    // P2P networking and encryption (async)
-    if configuration.network.is_some() {
-        // Create and run the network manager
-        match NetworkManager::new(configuration).await {
-            Ok(network_manager) => {
-                info!("Network manager created successfully");
-                // Run the network manager with observer events
-                network_manager.run(observer_rx).await;
+    // What the daemon would announce on startup, then what changes as the observers run
+    for obs in &watched {
+        match local.scan(obs, "") {
+            Ok(lines) => lines.iter().for_each(|line| println!("would announce: {}", line)),
+            Err(e) => eprintln!("Failed to scan observer {}: {}", obs.name, e),
+        }
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            msg = events_rx.recv() => {
+                let Some(msg) = msg else { break };
+                let rescan = serde_json::from_str::<FileEventMessage>(&msg).ok()
+                    .filter(|event| event.event_type == EventType::Rescan);
+                let lines = match rescan {
+                    // The watcher dropped events, compare the subtree with the index instead
+                    Some(event) => match watched.iter().find(|obs| obs.name == event.observer) {
+                        Some(obs) => local.scan(obs, &event.path).unwrap_or_else(|e| {
+                            eprintln!("Failed to rescan observer {}: {}", obs.name, e);
+                            Vec::new()
+                        }),
+                        None => Vec::new(),
+                    },
+                    None => local.apply(&msg).into_iter().collect(),
+                };
+                lines.iter().for_each(|line| println!("would announce: {}", line));
             }
+        }
+    }
+
+    println!("\n{} announcement(s) would have been made", local.announced());
+    for obs in &watched {
+        println!("{}: {} file(s) indexed", obs.name, local.index().files(&obs.name).count());
+    }
+    0
+}
+
+/// Verify the audit log hash chain, returning the process exit code
+fn run_audit_verify(path: Option<std::path::PathBuf>) -> i32 {
+    let path = match path {
+        Some(path) => path,
+        None => match load_config() {
+            Ok(configuration) => match configuration.audit_log {
+                Some(path) => std::path::PathBuf::from(path),
+                None => {
+                    eprintln!("No audit_log configured and no path given");
+                    return 2;
+                }
+            },
             Err(e) => {
-                error!(%e, "Failed to create network manager");
-                return;
+                eprintln!("Failed to load configuration: {}", e);
+                return 2;
             }
+        },
+    };
+
+    match audit::verify(&path) {
+        Ok(count) => {
+            println!("{}: OK ({} entries)", path.display(), count);
+            0
+        }
+        Err(e) => {
+            println!("{}: FAILED - {}", path.display(), e);
+            1
+        }
+    }
+}
+
+/// Print statistics from the state file, returning the process exit code
+fn run_stats(bandwidth: bool, history: bool) -> i32 {
+    let configuration = match load_config() {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 2;
+        }
+    };
+    let (state_path, versions_dir) = match configuration.state_path().and_then(|path| Ok((path, configuration.versions_dir()?))) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 2;
         }
+    };
+
+    let state = match state::load(&state_path) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Failed to read state file {}: {}", state_path.display(), e);
+            return 1;
+        }
+    };
+
+    if bandwidth {
+        println!("Bandwidth usage (file content only)\n");
+        print!("{}", state.bandwidth.report());
+    }
+    if history {
+        // Versions of every observer share one directory under hashed names
+        let mut rows = Vec::new();
+        match compression::usage(&versions_dir) {
+            Ok(usage) => rows.push(("Common versions".to_string(), usage)),
+            Err(e) => eprintln!("Failed to measure {}: {}", versions_dir.display(), e),
+        }
+        print!("{}", compression::report(&rows));
     }
+    0
+}
 
-    // Wait for observer thread to finish
-    let _ = observer_thread.join();
+/// Check the state file, moving it aside if corrupt, returning the process exit code
+/// The daemon starts afresh from a missing state file and rebuilds it from a
+/// rescan and peers' trees, exactly as when it finds the corruption itself.
+fn run_repair() -> i32 {
+    let state_path = match load_config().and_then(|configuration| configuration.state_path()) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 2;
+        }
+    };
+
+    match state::load(&state_path) {
+        Ok(_) => {
+            println!("{}: OK", state_path.display());
+            0
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => match state::quarantine(&state_path) {
+            Ok(moved) => {
+                println!("{}: CORRUPT - {}", state_path.display(), e);
+                println!("Moved to {}; it will be rebuilt when the daemon next starts", moved.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("Failed to move {} aside: {}", state_path.display(), e);
+                1
+            }
+        },
+        Err(e) => {
+            eprintln!("Failed to read state file {}: {}", state_path.display(), e);
+            1
+        }
+    }
+}
+
+/// Send a request to the running daemon, or return the process exit code on failure
+fn send_control(request: &ControlRequest) -> Result<ControlResponse, i32> {
+    let paths = load_config().and_then(|configuration| Ok((configuration.control_socket_path()?, configuration.control_token_path()?)));
+    let (socket_path, token_path) = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return Err(2);
+        }
+    };
+    let token = match control::read_token(&token_path) {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Could not read control token {} (the daemon creates it when it starts): {}", token_path.display(), e);
+            return Err(1);
+        }
+    };
+
+    match control::request(&socket_path, &token, request) {
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("Daemon returned an error: {}", message);
+            Err(1)
+        }
+        Ok(response) => Ok(response),
+        Err(e) => {
+            eprintln!("Could not reach daemon at {}: {}", socket_path.display(), e);
+            Err(1)
+        }
+    }
+}
+
+/// Send a request answered with a plain message, returning the process exit code
+fn run_control(request: ControlRequest) -> i32 {
+    match send_control(&request) {
+        Ok(ControlResponse::Done { message }) => {
+            println!("{}", message);
+            0
+        }
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            1
+        }
+        Err(code) => code,
+    }
+}
+
+/// Print a compact sync indicator for a directory, returning the process exit code
+/// Prints nothing on failure, and gives up quickly, so a prompt never shows errors or stalls.
+fn run_prompt_status(path: Option<std::path::PathBuf>) -> i32 {
+    let Some(path) = path.or_else(|| std::env::current_dir().ok()) else {
+        return 1;
+    };
+    let path = path.canonicalize().unwrap_or(path);
+    let Ok((socket_path, token_path)) = load_config().and_then(|configuration| Ok((configuration.control_socket_path()?, configuration.control_token_path()?))) else {
+        return 1;
+    };
+    let Ok(token) = control::read_token(&token_path) else {
+        return 1;
+    };
+    let request = ControlRequest::PathStatus { path: path.to_string_lossy().into_owned() };
+    match control::request_within(&socket_path, &token, &request, Some(std::time::Duration::from_millis(200))) {
+        Ok(ControlResponse::PathStatus(status)) => {
+            let indicator = status.state.indicator();
+            match status.state {
+                SyncState::Syncing => println!("{}{}", indicator, status.downloading),
+                SyncState::Pending => println!("{}{}", indicator, status.pending),
+                _ if !indicator.is_empty() => println!("{}", indicator),
+                _ => {}
+            }
+            0
+        }
+        _ => 1,
+    }
+}
+
+/// Print the tray state as one JSON line per change, returning the process exit code
+/// Prints `null` while the daemon is unreachable and keeps reconnecting, so a
+/// tray companion can spawn this once and read its output for as long as it runs.
+fn run_tray_watch() -> i32 {
+    let (socket_path, token_path) = match load_config().and_then(|configuration| Ok((configuration.control_socket_path()?, configuration.control_token_path()?))) {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return 1;
+        }
+    };
+    let token = match control::read_token(&token_path) {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Failed to read the control token at {}: {}", token_path.display(), e);
+            return 1;
+        }
+    };
+    use std::io::Write;
+    let mut stdout = std::io::stdout();
+    let followed = control::follow_tray(&socket_path, &token, |state| {
+        let line = serde_json::to_string(&state).unwrap_or_default();
+        // Stop once the reader goes away
+        writeln!(stdout, "{}", line).and_then(|_| stdout.flush()).is_ok()
+    });
+    match followed {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Daemon refused the tray feed: {}", e);
+            1
+        }
+    }
+}
+
+fn address_list(addresses: &[String]) -> String {
+    if addresses.is_empty() { "(none)".to_string() } else { addresses.join(", ") }
+}
+
+/// Print the start of a peer's copy of a file, returning the process exit code
+fn run_peek(request: ControlRequest) -> i32 {
+    match send_control(&request) {
+        Ok(ControlResponse::Preview(preview)) => {
+            eprintln!("{}/{} on {} ({} bytes, version {})", preview.observer, preview.path, preview.peer, preview.total_size, preview.hash);
+            if preview.binary {
+                eprintln!("(binary file, not shown)");
+                return 0;
+            }
+            print!("{}", preview.content);
+            if preview.truncated {
+                eprintln!("\n(truncated)");
+            }
+            0
+        }
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            1
+        }
+        Err(code) => code,
+    }
+}
+
+/// Paths listed per kind of difference before the rest are only counted
+const DIFF_LISTED: usize = 20;
+
+/// Print how observers differ from a peer's copy, returning the process exit code
+fn run_diff(request: ControlRequest) -> i32 {
+    match send_control(&request) {
+        Ok(ControlResponse::Diff { reports }) => {
+            for report in &reports {
+                println!("{} against {}: {} identical", report.observer, report.peer, report.identical);
+                if report.is_empty() {
+                    println!("  No differences");
+                    continue;
+                }
+                let kinds = [
+                    ("Missing here", &report.missing_here),
+                    ("Missing on peer", &report.missing_there),
+                    ("Differing", &report.differing),
+                    ("Conflicting", &report.conflicts),
+                ];
+                for (kind, entries) in kinds {
+                    println!("  {}: {}", kind, entries.len());
+                    for entry in entries.iter().take(DIFF_LISTED) {
+                        let sizes = match (entry.local_size, entry.remote_size) {
+                            (Some(local), Some(remote)) => format!(" ({} here, {} there)", format_bytes(local), format_bytes(remote)),
+                            (Some(size), None) | (None, Some(size)) => format!(" ({})", format_bytes(size)),
+                            (None, None) => String::new(),
+                        };
+                        println!("    {}{}", entry.path, sizes);
+                    }
+                    if entries.len() > DIFF_LISTED {
+                        println!("    ... and {} more", entries.len() - DIFF_LISTED);
+                    }
+                }
+            }
+            0
+        }
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            1
+        }
+        Err(code) => code,
+    }
+}
+
+/// List connected peers with their latency, throughput and version, returning the process exit code
+fn run_peers() -> i32 {
+    match send_control(&ControlRequest::Peers) {
+        Ok(ControlResponse::Peers { peers }) => {
+            if peers.is_empty() {
+                println!("No peers connected");
+                return 0;
+            }
+            println!("{:<54} {:>8} {:>12} {:>12}  {}", "PEER", "PING", "DOWN KiB/s", "UP KiB/s", "VERSION");
+            for peer in peers {
+                let rtt = peer.rtt_ms.map(|ms| format!("{}ms", ms)).unwrap_or_else(|| "-".to_string());
+                let version = match (&peer.agent_version, peer.compatible) {
+                    (Some(agent), Some(false)) => format!("{} (incompatible)", agent),
+                    (Some(agent), _) => agent.clone(),
+                    (None, _) => "-".to_string(),
+                };
+                println!("{:<54} {:>8} {:>12} {:>12}  {}", peer.peer_id, rtt, peer.download_bps / 1024, peer.upload_bps / 1024, version);
+            }
+            0
+        }
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            1
+        }
+        Err(code) => code,
+    }
+}
+
+/// Print the daemon's peer ID and the addresses other devices can dial it on, returning the process exit code
+/// The addresses are full multiaddrs ending in /p2p/PEER_ID, ready to use as bootstrap
+/// peers. Loopback addresses are left out; they are no use to another machine.
+fn run_id(qr: bool) -> i32 {
+    let status = match send_control(&ControlRequest::Status) {
+        Ok(ControlResponse::Status(status)) => status,
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            return 1;
+        }
+        Err(code) => return code,
+    };
+    if status.peer_id.is_empty() {
+        eprintln!("The running daemon is too old to report its peer ID");
+        return 1;
+    }
+    // Confirmed external addresses first, as they work from outside the local network
+    let mut addresses: Vec<String> = Vec::new();
+    for address in status.external_addresses.iter().chain(&status.listen_addresses) {
+        let loopback = syndactyl::core::address_book::is_loopback(address);
+        let address = format!("{}/p2p/{}", address, status.peer_id);
+        if !loopback && !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    println!("Peer ID: {}", status.peer_id);
+    if addresses.is_empty() {
+        println!("No addresses reachable from other machines yet");
+    }
+    for address in &addresses {
+        println!("  {}", address);
+    }
+    // The first address is the likeliest to work from another device
+    if let Some(address) = addresses.first() {
+        println!("Pairing code: {}", syndactyl::core::setup::pairing_code(address));
+    }
+    if qr {
+        // Without addresses, the peer ID alone still saves typing it on the other device
+        let payload = if addresses.is_empty() { status.peer_id.clone() } else { addresses.join("\n") };
+        match qr_code(&payload) {
+            Ok(image) => println!("\n{}", image),
+            Err(e) => {
+                eprintln!("Cannot encode the addresses as a QR code: {}", e);
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+/// Render `payload` as a QR code for the terminal
+#[cfg(feature = "qr")]
+fn qr_code(payload: &str) -> Result<String, String> {
+    let code = qrcode::QrCode::new(payload.as_bytes()).map_err(|e| e.to_string())?;
+    // Inverted so it scans on the usual dark terminal background
+    Ok(code.render::<qrcode::render::unicode::Dense1x2>()
+        .dark_color(qrcode::render::unicode::Dense1x2::Light)
+        .light_color(qrcode::render::unicode::Dense1x2::Dark)
+        .build())
+}
+
+#[cfg(not(feature = "qr"))]
+fn qr_code(_payload: &str) -> Result<String, String> {
+    Err("syndactyl was built without the qr feature".to_string())
+}
+
+/// Ask the running daemon for its status, returning the process exit code
+fn run_status() -> i32 {
+    match send_control(&ControlRequest::Status) {
+        Ok(ControlResponse::Status(status)) => {
+            println!("Connected peers:       {}", status.connected_peers);
+            println!("Active downloads:      {}", status.active_downloads);
+            println!("Queued serve requests: {}", status.queued_serve_requests);
+            println!("Deferred transfers:    {}", status.deferred_transfers);
+            println!("Queued downloads:      {}", status.queued_downloads);
+            println!("Profile:               {}", status.profile.as_deref().unwrap_or("(none)"));
+            println!("Listening on:          {}", address_list(&status.listen_addresses));
+            println!("External addresses:    {}", address_list(&status.external_addresses));
+            if !status.discovery.is_empty() {
+                println!("Discovery:             {}", status.discovery.join(", "));
+            }
+            if !status.bootstrap_peers.is_empty() {
+                let reachable = status.bootstrap_peers.iter().filter(|peer| peer.reachable).count();
+                let dht = if status.dht_bootstrapped { "DHT bootstrapped" } else { "DHT not bootstrapped" };
+                println!("Bootstrap peers:       {}/{} reachable, {}", reachable, status.bootstrap_peers.len(), dht);
+                for peer in status.bootstrap_peers.iter().filter(|peer| !peer.reachable) {
+                    let error = peer.last_error.as_deref().unwrap_or("no reply yet");
+                    println!("  unreachable:         {} after {} attempt(s): {}", peer.address, peer.attempts, error);
+                }
+            }
+            for (observer, lifecycle) in &status.observer_states {
+                let reason = if lifecycle.reason.is_empty() { String::new() } else { format!(" ({})", lifecycle.reason) };
+                let since = chrono::DateTime::from_timestamp(lifecycle.since as i64, 0)
+                    .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!("Observer {:<13} {}{} since {}", format!("{}:", observer), lifecycle.state, reason, since);
+            }
+            if !status.unavailable_observers.is_empty() {
+                println!("Unavailable observers: {}", status.unavailable_observers.join(", "));
+            }
+            if !status.low_space_observers.is_empty() {
+                println!("Low on disk space:     {} (downloads paused)", status.low_space_observers.join(", "));
+            }
+            if !status.stale_observers.is_empty() {
+                println!("Rescanning observers:  {} (the file watcher dropped events)", status.stale_observers.join(", "));
+            }
+            if status.auth_failures > 0 {
+                println!("Auth failures:         {} (see the warnings in the log)", status.auth_failures);
+            }
+            for (observer, count) in &status.held_deletions {
+                println!("Held deletions:        {} in {} (syndactyl deletions confirm|discard {})", count, observer, observer);
+            }
+            match status.power {
+                Some(power) => {
+                    let source = if power.on_battery { "battery" } else { "AC" };
+                    let percent = power.battery_percent.map(|p| format!(" ({}%)", p)).unwrap_or_default();
+                    let mode = if power.constrained { ", throttling hashing and large transfers" } else { "" };
+                    println!("Power:                 {}{}{}", source, percent, mode);
+                }
+                None => println!("Power:                 not monitored"),
+            }
+            0
+        }
+        Ok(response) => {
+            eprintln!("Unexpected reply from daemon: {:?}", response);
+            1
+        }
+        Err(code) => code,
+    }
 }
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use fuser::{
+    BackgroundSession, FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, Request,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use crate::core::catalog::{CatalogNode, CatalogRequest, SharedCatalog};
+use crate::network::transfer::CHUNK_SIZE;
+
+/// How long a read of an uncached file waits for a peer
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the kernel may cache attributes; announcements can change them at any time
+const ATTR_TTL: Duration = Duration::from_secs(1);
+
+const ROOT_INODE: u64 = 1;
+
+/// An open file: the cached copy once it exists, otherwise read from peers
+struct OpenFile {
+    path: String,
+    cached: Option<File>,
+}
+
+/// A read-only view of one observer's catalog
+/// Listings come from announcements alone. Opening an uncached file starts
+/// fetching it into the observer's directory (used as a cache) in the
+/// background; until it arrives, reads are served by range reads from peers,
+/// so playback can start straight away.
+struct CatalogFs {
+    observer: String,
+    cache_root: PathBuf,
+    catalog: SharedCatalog,
+    requests: mpsc::Sender<CatalogRequest>,
+    /// Inode numbers handed to the kernel, both ways
+    paths: HashMap<u64, String>,
+    inodes: HashMap<String, u64>,
+    next_inode: u64,
+    /// Open files by handle
+    handles: HashMap<u64, OpenFile>,
+    next_handle: u64,
+    /// Everything on the mount appears owned by the daemon's user
+    uid: u32,
+    gid: u32,
+}
+
+/// Mount an observer's catalog at `mountpoint` until the returned session is dropped
+pub fn mount(
+    observer: &str,
+    cache_root: &Path,
+    mountpoint: &Path,
+    catalog: SharedCatalog,
+    requests: mpsc::Sender<CatalogRequest>,
+) -> std::io::Result<BackgroundSession> {
+    let fs = CatalogFs {
+        observer: observer.to_string(),
+        cache_root: cache_root.to_path_buf(),
+        catalog,
+        requests,
+        paths: HashMap::from([(ROOT_INODE, String::new())]),
+        inodes: HashMap::from([(String::new(), ROOT_INODE)]),
+        next_inode: ROOT_INODE + 1,
+        handles: HashMap::new(),
+        next_handle: 1,
+        // SAFETY: getuid/getgid cannot fail and have no preconditions
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(format!("syndactyl:{}", observer)),
+        MountOption::AutoUnmount,
+    ];
+    let session = fuser::spawn_mount2(fs, mountpoint, &options)?;
+    info!(observer = %observer, mountpoint = %mountpoint.display(), "Mounted on-demand observer");
+    Ok(session)
+}
+
+impl CatalogFs {
+    fn inode_for(&mut self, path: &str) -> u64 {
+        if let Some(inode) = self.inodes.get(path) {
+            return *inode;
+        }
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(path.to_string(), inode);
+        self.paths.insert(inode, path.to_string());
+        inode
+    }
+
+    fn node(&self, path: &str) -> Option<CatalogNode> {
+        self.catalog.read().ok()?.node(&self.observer, path)
+    }
+
+    fn attr(&self, inode: u64, node: &CatalogNode) -> FileAttr {
+        let (kind, size, mtime, perm) = match node {
+            CatalogNode::File(entry) => (FileType::RegularFile, entry.size, entry.modified_time, 0o444),
+            CatalogNode::Dir => (FileType::Directory, 0, 0, 0o555),
+        };
+        let time = UNIX_EPOCH + Duration::from_secs(mtime);
+        FileAttr {
+            ino: inode,
+            size,
+            blocks: size.div_ceil(512),
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind,
+            perm,
+            nlink: 1,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        }
+    }
+
+    /// The cached copy, if it holds the announced version
+    fn open_cached(&self, path: &str) -> Option<File> {
+        let cached = self.catalog.read().ok()?
+            .get(&self.observer, path)
+            .is_some_and(|entry| entry.is_cached());
+        if !cached {
+            return None;
+        }
+        File::open(self.cache_root.join(path)).ok()
+    }
+
+    /// Read up to `size` bytes from peers, one range request per chunk
+    fn read_remote(&self, path: &str, offset: u64, size: u32) -> Result<Vec<u8>, String> {
+        let mut data = Vec::with_capacity(size as usize);
+        while data.len() < size as usize {
+            let (reply, done) = std::sync::mpsc::channel();
+            self.requests.blocking_send(CatalogRequest::Read {
+                observer: self.observer.clone(),
+                path: path.to_string(),
+                offset: offset + data.len() as u64,
+                length: (size as usize - data.len()).min(CHUNK_SIZE) as u32,
+                reply,
+            }).map_err(|_| "daemon is shutting down".to_string())?;
+            let chunk = done.recv_timeout(READ_TIMEOUT)
+                .map_err(|_| "timed out waiting for peers".to_string())??;
+            if chunk.is_empty() {
+                // End of file
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+impl Filesystem for CatalogFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let (Some(parent_path), Some(name)) = (self.paths.get(&parent).cloned(), name.to_str()) else {
+            return reply.error(libc::ENOENT);
+        };
+        let path = if parent_path.is_empty() { name.to_string() } else { format!("{}/{}", parent_path, name) };
+        match self.node(&path) {
+            Some(node) => {
+                let inode = self.inode_for(&path);
+                reply.entry(&ATTR_TTL, &self.attr(inode, &node), 0);
+            }
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.paths.get(&ino).and_then(|path| self.node(path)) {
+            Some(node) => reply.attr(&ATTR_TTL, &self.attr(ino, &node)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let Some(dir) = self.paths.get(&ino).cloned() else {
+            return reply.error(libc::ENOENT);
+        };
+        let children = match self.catalog.read() {
+            Ok(catalog) => catalog.children(&self.observer, &dir),
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let mut entries = vec![(ino, FileType::Directory, ".".to_string()), (ino, FileType::Directory, "..".to_string())];
+        for (name, node) in children {
+            let path = if dir.is_empty() { name.clone() } else { format!("{}/{}", dir, name) };
+            let kind = match node {
+                CatalogNode::File(_) => FileType::RegularFile,
+                CatalogNode::Dir => FileType::Directory,
+            };
+            entries.push((self.inode_for(&path), kind, name));
+        }
+        for (i, (inode, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(inode, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let Some(path) = self.paths.get(&ino).cloned() else {
+            return reply.error(libc::ENOENT);
+        };
+        let cached = self.open_cached(&path);
+        if cached.is_none() {
+            // Nobody waits for this reply; later reads pick up the cached copy once it lands
+            let (fetch_reply, _) = std::sync::mpsc::channel();
+            let fetch = CatalogRequest::Fetch { observer: self.observer.clone(), path: path.clone(), reply: fetch_reply };
+            if self.requests.blocking_send(fetch).is_err() {
+                return reply.error(libc::EIO);
+            }
+        }
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, OpenFile { path, cached });
+        reply.opened(handle, 0);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(path) = self.handles.get(&fh).map(|open| open.path.clone()) else {
+            return reply.error(libc::EBADF);
+        };
+        if self.handles.get(&fh).is_some_and(|open| open.cached.is_none()) {
+            let cached = self.open_cached(&path);
+            if let Some(open) = self.handles.get_mut(&fh) {
+                open.cached = cached;
+            }
+        }
+
+        match self.handles.get(&fh).and_then(|open| open.cached.as_ref()) {
+            Some(file) => {
+                let mut buffer = vec![0u8; size as usize];
+                match file.read_at(&mut buffer, offset as u64) {
+                    Ok(read) => reply.data(&buffer[..read]),
+                    Err(_) => reply.error(libc::EIO),
+                }
+            }
+            None => match self.read_remote(&path, offset as u64, size) {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    warn!(observer = %self.observer, path = %path, error = %e, "Range read failed");
+                    reply.error(libc::EIO);
+                }
+            },
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        self.handles.remove(&fh);
+        reply.ok();
+    }
+}
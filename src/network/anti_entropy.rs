@@ -0,0 +1,84 @@
+use std::time::Duration;
+use crate::core::auth;
+use crate::core::merkle::IndexedFile;
+use crate::core::models::{EventType, FileEventMessage};
+
+/// How often a sample of current versions is re-announced (10 minutes)
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Files re-announced per observer each round
+pub const DEFAULT_SAMPLE_SIZE: usize = 16;
+
+/// `details` of re-announcements, so receivers can tell them from fresh changes
+pub const DETAILS: &str = "AntiEntropy";
+
+/// Up to `count` items chosen uniformly at random, in one pass
+/// `random(n)` must return a number below `n`.
+pub fn sample<T>(items: impl IntoIterator<Item = T>, count: usize, mut random: impl FnMut(u64) -> u64) -> Vec<T> {
+    let mut chosen = Vec::with_capacity(count);
+    for (seen, item) in items.into_iter().enumerate() {
+        if chosen.len() < count {
+            chosen.push(item);
+            continue;
+        }
+        let slot = random(seen as u64 + 1) as usize;
+        if slot < count {
+            chosen[slot] = item;
+        }
+    }
+    chosen
+}
+
+/// A signed re-announcement of the version of a file we hold
+pub fn announcement(observer: &str, path: &str, file: &IndexedFile, secret: Option<&str>) -> FileEventMessage {
+    let mut msg = FileEventMessage {
+        observer: observer.to_string(),
+        event_type: EventType::Modify,
+        path: path.to_string(),
+        details: Some(DETAILS.to_string()),
+        hash: Some(file.hash.clone()),
+        size: Some(file.size),
+        modified_time: Some(file.modified_time),
+        hmac: None,
+        transaction: None,
+        observer_id: None,
+        append: None,
+        sequence: None,
+    };
+    if let Some(secret) = secret {
+        msg.hmac = Some(auth::compute_hmac(&msg, secret));
+        msg.observer_id = Some(auth::observer_id(observer, secret));
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_are_bounded_and_cover_every_item_over_time() {
+        let mut state = 7u64;
+        let mut random = |bound: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % bound
+        };
+        assert_eq!(sample(0..3, 16, &mut random), vec![0, 1, 2]);
+        assert!(sample(0..100, 0, &mut random).is_empty());
+
+        let mut picked = [false; 100];
+        for _ in 0..200 {
+            let chosen = sample(0..100, 10, &mut random);
+            assert_eq!(chosen.len(), 10);
+            for item in chosen {
+                picked[item] = true;
+            }
+        }
+        assert!(picked.iter().all(|picked| *picked));
+
+        let file = IndexedFile { hash: "h".to_string(), size: 3, modified_time: 5 };
+        let msg = announcement("docs", "a.txt", &file, Some("secret"));
+        assert!(auth::verify_hmac(&msg, "secret"));
+        assert_eq!(msg.details.as_deref(), Some(DETAILS));
+    }
+}
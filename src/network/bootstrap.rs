@@ -0,0 +1,241 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+use crate::core::config::BootstrapPeer;
+
+/// Delay before the first redial of an unreachable bootstrap peer
+const INITIAL_RETRY: Duration = Duration::from_secs(5);
+
+/// Longest delay between redials
+const MAX_RETRY: Duration = Duration::from_secs(300);
+
+/// Peer ID and address to dial of a configured bootstrap peer
+/// A full `address` may use any transport the swarm dials (tcp, dns, ws and wss);
+/// otherwise one is built from the structured fields. QUIC and relay circuits are
+/// refused, as an address the swarm can't dial would just fail forever.
+pub fn target(peer: &BootstrapPeer) -> Result<(PeerId, Multiaddr), String> {
+    let Some(address) = &peer.address else {
+        let peer_id = PeerId::from_str(&peer.peer_id)
+            .map_err(|_| format!("Bootstrap peer ID '{}' is invalid", peer.peer_id))?;
+        let multiaddr = peer.multiaddr().parse::<Multiaddr>()
+            .map_err(|e| format!("Bootstrap peer {} has an invalid address: {}", peer.peer_id, e))?;
+        return Ok((peer_id, multiaddr));
+    };
+    let multiaddr: Multiaddr = address.parse()
+        .map_err(|e| format!("Invalid bootstrap address '{}': {}", address, e))?;
+    if let Some(unsupported) = multiaddr.iter().find(|protocol| {
+        matches!(protocol, Protocol::Quic | Protocol::QuicV1 | Protocol::Udp(_) | Protocol::P2pCircuit | Protocol::WebTransport | Protocol::WebRTCDirect)
+    }) {
+        return Err(format!("Bootstrap address '{}' uses {}, which syndactyl can't dial; use tcp, ws or wss", address, unsupported.tag()));
+    }
+    match multiaddr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok((peer_id, multiaddr)),
+        _ => Err(format!("Bootstrap address '{}' must end in /p2p/<peer ID>", address)),
+    }
+}
+
+/// Whether a bootstrap peer was filled in at all
+/// The structured form may be left empty, e.g. by an unfinished setup.
+pub fn is_configured(peer: &BootstrapPeer) -> bool {
+    peer.address.is_some() || (!peer.ip.is_empty() && !peer.peer_id.is_empty())
+}
+
+/// Check the full bootstrap addresses, so a typo fails startup rather than never dialing
+/// Incomplete structured entries are only warned about, as before addresses existed.
+pub fn validate(peers: &[BootstrapPeer]) -> Result<(), String> {
+    for peer in peers.iter().filter(|peer| peer.address.is_some()) {
+        target(peer)?;
+    }
+    Ok(())
+}
+
+/// One configured bootstrap peer as reported by `syndactyl status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BootstrapPeerStatus {
+    pub peer_id: String,
+    pub address: String,
+    pub reachable: bool,
+    /// Dials since the peer was last reachable
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+struct Target {
+    peer_id: PeerId,
+    address: Multiaddr,
+    reachable: bool,
+    attempts: u32,
+    next_dial: Instant,
+    last_error: Option<String>,
+}
+
+/// Dials configured bootstrap peers until they are reachable, backing off
+/// between attempts, and redials them after they disconnect
+pub struct BootstrapTracker {
+    targets: Vec<Target>,
+}
+
+impl BootstrapTracker {
+    /// Parse the configured peers, skipping (with a warning) any that are incomplete or malformed
+    pub fn new(peers: &[BootstrapPeer], now: Instant) -> Self {
+        let targets = peers.iter()
+            .filter(|peer| is_configured(peer))
+            .filter_map(|peer| match target(peer) {
+                Ok(parsed) => Some(parsed),
+                Err(e) => {
+                    warn!(error = %e, "Ignoring invalid bootstrap peer");
+                    None
+                }
+            })
+            .map(|(peer_id, address)| Target {
+                peer_id,
+                address,
+                reachable: false,
+                attempts: 0,
+                next_dial: now,
+                last_error: None,
+            })
+            .collect();
+        Self { targets }
+    }
+
+    /// Peers and addresses to add to the routing table
+    pub fn addresses(&self) -> impl Iterator<Item = (&PeerId, &Multiaddr)> {
+        self.targets.iter().map(|target| (&target.peer_id, &target.address))
+    }
+
+    /// Whether any bootstrap peer still needs dialing
+    pub fn is_waiting(&self) -> bool {
+        self.targets.iter().any(|target| !target.reachable)
+    }
+
+    /// Addresses to dial now, scheduling the next attempt for each
+    pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        self.targets.iter_mut()
+            .filter(|target| !target.reachable && target.next_dial <= now)
+            .map(|target| {
+                let backoff = INITIAL_RETRY.saturating_mul(1 << target.attempts.min(6)).min(MAX_RETRY);
+                target.attempts += 1;
+                target.next_dial = now + backoff;
+                target.address.clone()
+            })
+            .collect()
+    }
+
+    /// Record a connection, returning true if it made a bootstrap peer reachable
+    pub fn connected(&mut self, peer_id: &PeerId) -> bool {
+        let Some(target) = self.targets.iter_mut().find(|target| &target.peer_id == peer_id && !target.reachable) else {
+            return false;
+        };
+        target.reachable = true;
+        target.attempts = 0;
+        target.last_error = None;
+        true
+    }
+
+    /// Record a failed dial; the retry is already scheduled
+    pub fn dial_failed(&mut self, peer_id: &PeerId, error: String) {
+        if let Some(target) = self.targets.iter_mut().find(|target| &target.peer_id == peer_id) {
+            target.last_error = Some(error);
+        }
+    }
+
+    /// Record that the last connection to a peer closed, returning true if it was a bootstrap peer
+    pub fn disconnected(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        let Some(target) = self.targets.iter_mut().find(|target| &target.peer_id == peer_id && target.reachable) else {
+            return false;
+        };
+        target.reachable = false;
+        target.next_dial = now + INITIAL_RETRY;
+        true
+    }
+
+    /// Reachable and configured bootstrap peers
+    pub fn progress(&self) -> (usize, usize) {
+        (self.targets.iter().filter(|target| target.reachable).count(), self.targets.len())
+    }
+
+    pub fn status(&self) -> Vec<BootstrapPeerStatus> {
+        self.targets.iter()
+            .map(|target| BootstrapPeerStatus {
+                peer_id: target.peer_id.to_string(),
+                address: target.address.to_string(),
+                reachable: target.reachable,
+                attempts: target.attempts,
+                last_error: target.last_error.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreachable_peers_are_retried_with_backoff() {
+        let peer_id = PeerId::random();
+        let peers = vec![
+            BootstrapPeer { address: None, ip: "10.0.0.2".to_string(), port: "4001".to_string(), peer_id: peer_id.to_string(), websocket: None },
+            BootstrapPeer { address: None, ip: String::new(), port: String::new(), peer_id: String::new(), websocket: None },
+            BootstrapPeer { address: None, ip: "10.0.0.3".to_string(), port: "4001".to_string(), peer_id: "not-a-peer-id".to_string(), websocket: None },
+        ];
+        let now = Instant::now();
+        let mut tracker = BootstrapTracker::new(&peers, now);
+        assert_eq!(tracker.progress(), (0, 1));
+
+        assert_eq!(tracker.due(now).len(), 1);
+        assert!(tracker.due(now).is_empty());
+        tracker.dial_failed(&peer_id, "connection refused".to_string());
+        assert_eq!(tracker.due(now + INITIAL_RETRY).len(), 1);
+        // The second retry waits twice as long
+        assert!(tracker.due(now + INITIAL_RETRY * 2).is_empty());
+        assert_eq!(tracker.status()[0].last_error.as_deref(), Some("connection refused"));
+
+        assert!(tracker.connected(&peer_id));
+        assert_eq!(tracker.progress(), (1, 1));
+        assert!(!tracker.is_waiting());
+        assert_eq!(tracker.status()[0].attempts, 0);
+
+        assert!(tracker.disconnected(&peer_id, now));
+        assert!(tracker.due(now).is_empty());
+        assert_eq!(tracker.due(now + INITIAL_RETRY).len(), 1);
+    }
+
+    #[test]
+    fn test_full_addresses_are_accepted_next_to_structured_peers() {
+        let (relay, target_peer) = (PeerId::random(), PeerId::random());
+        let config = format!(
+            r#"[
+                {{"address": "/dns4/peer.example.com/tcp/4001/p2p/{target}"}},
+                {{"address": "/dns/relay.example.com/tcp/443/wss/p2p/{target}"}},
+                {{"ip": "10.0.0.2", "port": "4001", "peer_id": "{relay}"}}
+            ]"#,
+            target = target_peer,
+            relay = relay,
+        );
+        let peers: Vec<BootstrapPeer> = serde_json::from_str(&config).unwrap();
+        assert!(validate(&peers).is_ok());
+        assert_eq!(target(&peers[0]).unwrap().0, target_peer);
+        assert_eq!(target(&peers[1]).unwrap().0, target_peer);
+        assert_eq!(target(&peers[2]).unwrap().1.to_string(), format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", relay));
+        assert_eq!(BootstrapTracker::new(&peers, Instant::now()).progress(), (0, 3));
+
+        let missing_id: Vec<BootstrapPeer> = serde_json::from_str(r#"[{"address": "/dns/peer.example.com/tcp/443/wss"}]"#).unwrap();
+        assert!(validate(&missing_id).unwrap_err().contains("/p2p/"));
+        let garbled: Vec<BootstrapPeer> = serde_json::from_str(r#"[{"address": "peer.example.com:4001"}]"#).unwrap();
+        assert!(validate(&garbled).is_err());
+
+        // Transports the swarm isn't built with fail at startup rather than on every dial
+        for address in [
+            format!("/dns4/peer.example.com/udp/4001/quic-v1/p2p/{}", target_peer),
+            format!("/ip4/10.0.0.9/tcp/4001/p2p/{}/p2p-circuit/p2p/{}", relay, target_peer),
+        ] {
+            let unsupported: Vec<BootstrapPeer> = serde_json::from_str(&format!(r#"[{{"address": "{}"}}]"#, address)).unwrap();
+            assert!(validate(&unsupported).unwrap_err().contains("can't dial"));
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use libp2p::stream::{Control, OpenStreamError};
+use libp2p::{PeerId, Stream, StreamProtocol};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use crate::core::auth;
+use crate::core::hash_pool;
+use crate::core::models::{FileChunkRequest, FileTransferResponse, SyndactylRequest, TransferError, TransferErrorKind};
+use crate::core::secret::Secret;
+use crate::core::storage::StorageBackend;
+use crate::network::transfer::{segment_hash, CHUNK_SIZE, MAX_CHUNK_SIZE};
+use crate::network::wire;
+
+/// Bulk transfer protocol: one stream per download, data flowing without per-chunk requests
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/syndactyl/bulk/1.0.0");
+
+/// Bytes a sender streams ahead of the receiver's last ack (8 chunks)
+pub const WINDOW: u64 = 8 * CHUNK_SIZE as u64;
+
+/// Bytes a receiver takes in between acks (2 chunks)
+const ACK_EVERY: u64 = 2 * CHUNK_SIZE as u64;
+
+/// Streams closed when the other side neither sends nor acks for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest request, ack or frame header accepted; data follows headers separately
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Streams one peer may have us serve at once
+pub const MAX_STREAMS_PER_PEER: usize = 4;
+
+/// What a receiver asks for when it opens a stream: one version of a file, from `offset` on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BulkRequest {
+    pub observer: String,
+    pub path: String,
+    pub hash: String,
+    pub offset: u64,
+}
+
+/// Sent by the receiver every ACK_EVERY bytes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Ack {
+    /// Every byte before this offset has arrived
+    received_to: u64,
+}
+
+/// Header of each frame the sender writes; Data is followed by `len` raw bytes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+enum Frame {
+    Data {
+        offset: u64,
+        len: u32,
+        total_size: u64,
+        is_last_chunk: bool,
+        segment_hash: String,
+        #[serde(default)]
+        segment_hmac: Option<String>,
+    },
+    Error(TransferError),
+}
+
+/// What bulk transfer tasks report to the network manager
+pub enum BulkEvent {
+    /// A peer opened a stream and asked for a range; the manager decides whether to serve it
+    Incoming { peer: PeerId, request: BulkRequest, stream: Stream },
+    /// A chunk arrived on a download stream
+    Chunk { peer: PeerId, response: FileTransferResponse },
+    /// The serving peer refused the download or the file changed under it
+    Refused { peer: PeerId, error: TransferError },
+    /// A download stream ended, before its last chunk if `result` is an error
+    /// `unsupported` is set when the peer doesn't speak the protocol at all.
+    Downloaded { peer: PeerId, key: (String, String), result: Result<(), String>, unsupported: bool },
+    /// A chunk went out on a stream we serve
+    Served { peer: PeerId, observer: String, path: String, offset: u64, len: u64, is_last_chunk: bool },
+    /// A stream we served ended, after its last chunk or not
+    ServeEnded { peer: PeerId, observer: String, path: String },
+}
+
+/// Accept incoming bulk streams, reading each one's request before handing it to the manager
+pub fn spawn_acceptor(mut control: Control, events: mpsc::Sender<BulkEvent>) -> Result<(), String> {
+    let mut incoming = control.accept(PROTOCOL)
+        .map_err(|e| format!("Bulk transfer protocol already registered: {}", e))?;
+    tokio::spawn(async move {
+        while let Some((peer, mut stream)) = incoming.next().await {
+            let events = events.clone();
+            tokio::spawn(async move {
+                match timed(read_header::<_, BulkRequest>(&mut stream)).await.and_then(|request| validate(&request).map(|_| request)) {
+                    Ok(request) => {
+                        let _ = events.send(BulkEvent::Incoming { peer, request, stream }).await;
+                    }
+                    Err(e) => debug!(peer = %peer, error = %e, "[bulk] Dropping stream without a valid request"),
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Whether a stream may be served to `peer`, given who is pulling its file from us
+/// Streams only continue transfers a chunk request started, which were admitted
+/// against the serving capacity and had their version hashed then; anything
+/// else would skip both on the event loop.
+pub fn admits(peer: &PeerId, pulling: Option<&HashSet<PeerId>>, open_streams: usize) -> bool {
+    pulling.is_some_and(|peers| peers.contains(peer)) && open_streams < MAX_STREAMS_PER_PEER
+}
+
+fn validate(request: &BulkRequest) -> io::Result<()> {
+    let as_chunk = FileChunkRequest {
+        observer: request.observer.clone(),
+        path: request.path.clone(),
+        offset: request.offset,
+        hash: request.hash.clone(),
+        length: None,
+        encoding: None,
+    };
+    wire::validate_request(&SyndactylRequest::FileChunk(as_chunk))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Where a served file lives and the version the stream must keep to
+pub struct ServedFile {
+    pub storage: Arc<dyn StorageBackend>,
+    pub total_size: u64,
+    pub modified_time: u64,
+    /// Bytes per frame, from the observer's transfer tuning
+    pub chunk_size: usize,
+    /// Signs frames of versions not hashed yet, when the observer has a shared secret
+    pub secret: Option<Secret>,
+}
+
+/// Stream a file to a peer from the requested offset, keeping at most WINDOW bytes unacknowledged
+/// Stops with a FileChanged error if the file is modified while it is being sent.
+pub fn spawn_serve(peer: PeerId, mut stream: Stream, request: BulkRequest, file: ServedFile, events: mpsc::Sender<BulkEvent>) {
+    tokio::spawn(async move {
+        if let Err(e) = serve(peer, &mut stream, &request, &file, &events).await {
+            debug!(peer = %peer, observer = %request.observer, path = %request.path, error = %e, "[bulk] Serving stream ended early");
+        }
+        let _ = stream.close().await;
+        let _ = events.send(BulkEvent::ServeEnded { peer, observer: request.observer, path: request.path }).await;
+    });
+}
+
+async fn serve(peer: PeerId, stream: &mut Stream, request: &BulkRequest, file: &ServedFile, events: &mpsc::Sender<BulkEvent>) -> io::Result<()> {
+    let path = PathBuf::from(&request.path);
+    let mut offset = request.offset;
+    let mut acked = request.offset;
+    loop {
+        while offset - acked < WINDOW {
+            let unchanged = file.storage.size(&path).ok() == Some(file.total_size)
+                && file.storage.modified_time(&path).ok() == Some(file.modified_time);
+            if !unchanged {
+                let error = TransferError {
+                    observer: request.observer.clone(),
+                    path: request.path.clone(),
+                    requested_hash: request.hash.clone(),
+                    kind: TransferErrorKind::FileChanged { current_size: None, current_hash: None },
+                };
+                return write_header(stream, &Frame::Error(error)).await;
+            }
+            let storage = file.storage.clone();
+            let chunk_path = path.clone();
+            let chunk_size = file.chunk_size;
+            let data = tokio::task::spawn_blocking(move || storage.read_chunk(&chunk_path, offset, chunk_size)).await
+                .map_err(io::Error::other)??;
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended before its size"));
+            }
+            let len = data.len() as u64;
+            let is_last_chunk = offset + len >= file.total_size;
+            let segment_hash = segment_hash(&data);
+            let segment_hmac = file.secret.as_ref()
+                .filter(|_| hash_pool::parse_pending_version(&request.hash).is_some())
+                .map(|secret| auth::segment_hmac(secret.expose(), &request.observer, &request.path, &request.hash, offset, &segment_hash));
+            let frame = Frame::Data {
+                offset,
+                len: data.len() as u32,
+                total_size: file.total_size,
+                is_last_chunk,
+                segment_hash,
+                segment_hmac,
+            };
+            timed(write_header(stream, &frame)).await?;
+            timed(stream.write_all(&data)).await?;
+            let _ = events.send(BulkEvent::Served {
+                peer,
+                observer: request.observer.clone(),
+                path: request.path.clone(),
+                offset,
+                len,
+                is_last_chunk,
+            }).await;
+            if is_last_chunk {
+                return stream.flush().await;
+            }
+            offset += len;
+        }
+        stream.flush().await?;
+        let ack: Ack = timed(read_header(stream)).await?;
+        acked = ack.received_to.clamp(acked, offset);
+    }
+}
+
+/// Download a file from `request.offset` on over a new stream, reporting each chunk
+/// Sending chunks waits while the manager is busy, which holds back acks and so
+/// the sender; a slow disk throttles the peer instead of piling up in memory.
+pub fn spawn_download(mut control: Control, peer: PeerId, request: BulkRequest, events: mpsc::Sender<BulkEvent>) -> tokio::task::AbortHandle {
+    tokio::spawn(async move {
+        let key = (request.observer.clone(), request.path.clone());
+        let (result, unsupported) = match control.open_stream(peer, PROTOCOL).await {
+            Ok(mut stream) => {
+                let result = download(peer, &mut stream, &request, &events).await.map_err(|e| e.to_string());
+                let _ = stream.close().await;
+                (result, false)
+            }
+            Err(OpenStreamError::UnsupportedProtocol(_)) => (Err("peer does not support bulk streams".to_string()), true),
+            Err(e) => (Err(e.to_string()), false),
+        };
+        let _ = events.send(BulkEvent::Downloaded { peer, key, result, unsupported }).await;
+    }).abort_handle()
+}
+
+async fn download(peer: PeerId, stream: &mut Stream, request: &BulkRequest, events: &mpsc::Sender<BulkEvent>) -> io::Result<()> {
+    write_header(stream, request).await?;
+    stream.flush().await?;
+    let mut unacked = 0;
+    loop {
+        match timed(read_header::<_, Frame>(stream)).await? {
+            Frame::Data { offset, len, total_size, is_last_chunk, segment_hash, segment_hmac } => {
+                if len as usize > MAX_CHUNK_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("chunk of {} bytes exceeds {}", len, MAX_CHUNK_SIZE)));
+                }
+                let mut data = vec![0; len as usize];
+                timed(stream.read_exact(&mut data)).await?;
+                let response = FileTransferResponse {
+                    observer: request.observer.clone(),
+                    path: request.path.clone(),
+                    data,
+                    offset,
+                    total_size,
+                    hash: request.hash.clone(),
+                    is_last_chunk,
+                    segment_hash: Some(segment_hash),
+                    segment_hmac,
+                    encoding: None,
+                };
+                if events.send(BulkEvent::Chunk { peer, response }).await.is_err() || is_last_chunk {
+                    return Ok(());
+                }
+                unacked += len as u64;
+                if unacked >= ACK_EVERY {
+                    unacked = 0;
+                    write_header(stream, &Ack { received_to: offset + len as u64 }).await?;
+                    stream.flush().await?;
+                }
+            }
+            Frame::Error(error) => {
+                let _ = events.send(BulkEvent::Refused { peer, error }).await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Fail an operation on a stream that stalls for longer than IDLE_TIMEOUT
+async fn timed<T>(operation: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    tokio::time::timeout(IDLE_TIMEOUT, operation).await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "bulk stream stalled")))
+}
+
+/// Write a length-prefixed JSON header
+async fn write_header<W: AsyncWrite + Unpin>(writer: &mut W, header: &impl Serialize) -> io::Result<()> {
+    let json = serde_json::to_vec(header).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    writer.write_all(&(json.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&json).await
+}
+
+/// Read a length-prefixed JSON header, refusing oversized ones before reading them
+async fn read_header<R: AsyncRead + Unpin, T: DeserializeOwned>(reader: &mut R) -> io::Result<T> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_HEADER_BYTES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("header of {} bytes exceeds {}", len, MAX_HEADER_BYTES)));
+    }
+    let mut json = vec![0; len];
+    reader.read_exact(&mut json).await?;
+    serde_json::from_slice(&json).map_err(|e| {
+        warn!(error = %e, "[bulk] Malformed header");
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_headers_round_trip_and_oversized_ones_are_refused() {
+        futures::executor::block_on(async {
+            let request = BulkRequest { observer: "docs".to_string(), path: "a.bin".to_string(), hash: "h".to_string(), offset: 1 << 20 };
+            let frame = Frame::Data { offset: 0, len: 3, total_size: 3, is_last_chunk: true, segment_hash: segment_hash(b"abc"), segment_hmac: None };
+            let mut buffer = Cursor::new(Vec::new());
+            write_header(&mut buffer, &request).await.unwrap();
+            write_header(&mut buffer, &frame).await.unwrap();
+            write_header(&mut buffer, &Ack { received_to: 7 }).await.unwrap();
+
+            let mut reader = Cursor::new(buffer.into_inner());
+            assert_eq!(read_header::<_, BulkRequest>(&mut reader).await.unwrap(), request);
+            assert_eq!(read_header::<_, Frame>(&mut reader).await.unwrap(), frame);
+            assert_eq!(read_header::<_, Ack>(&mut reader).await.unwrap(), Ack { received_to: 7 });
+            assert!(read_header::<_, Ack>(&mut reader).await.is_err());
+
+            let mut oversized = Cursor::new(((MAX_HEADER_BYTES + 1) as u32).to_be_bytes().to_vec());
+            assert_eq!(read_header::<_, Ack>(&mut oversized).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        });
+        assert!(validate(&BulkRequest { observer: "docs".to_string(), path: "../etc/passwd".to_string(), hash: "h".to_string(), offset: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_streams_only_continue_admitted_transfers() {
+        let (pulling, stranger) = (PeerId::random(), PeerId::random());
+        let peers = HashSet::from([pulling]);
+        assert!(admits(&pulling, Some(&peers), 0));
+        assert!(!admits(&stranger, Some(&peers), 0));
+        assert!(!admits(&pulling, None, 0));
+        assert!(!admits(&pulling, Some(&peers), MAX_STREAMS_PER_PEER));
+    }
+}
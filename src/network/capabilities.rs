@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use libp2p::PeerId;
+use crate::core::models::Capabilities;
+use crate::network::chunk_codec;
+use crate::network::peer_stats;
+use crate::network::transfer::{CHUNK_SIZE, MAX_CHUNK_SIZE, MAX_FILE_SIZE};
+
+/// Downloads stream over the bulk protocol
+pub const BULK: &str = "bulk";
+/// Chunk requests may ask for a length other than CHUNK_SIZE
+pub const CHUNK_LENGTH: &str = "chunk_length";
+/// Byte ranges can be read without starting a transfer
+pub const RANGE_READ: &str = "range_read";
+/// Observers' Merkle trees are served for reconciliation
+pub const TREE_NODE: &str = "tree_node";
+/// Directory listings can be served in pages
+pub const TREE_PAGES: &str = "tree_pages";
+/// Tree roots are exchanged directly on connecting
+pub const HEADS: &str = "heads";
+
+/// Content hash algorithm of every announced version
+pub const HASH_ALGORITHM: &str = "sha256";
+
+/// What peers that predate capabilities documents are taken to support
+const LEGACY_FEATURES: &[&str] = &[RANGE_READ, TREE_NODE];
+
+/// Most entries in each list of a received document
+pub const MAX_ENTRIES: usize = 32;
+
+/// Chunks from a peer that fail to decode before it is only asked for raw ones
+const MAX_CODEC_ERRORS: u32 = 3;
+
+/// Our capabilities document
+/// Bulk streams are left out when disabled, so peers don't open them.
+pub fn local(bulk_transfer: bool) -> Capabilities {
+    let features = [BULK, CHUNK_LENGTH, RANGE_READ, TREE_NODE, TREE_PAGES, HEADS].into_iter()
+        .filter(|feature| bulk_transfer || *feature != BULK)
+        .map(str::to_string)
+        .collect();
+    Capabilities {
+        version: peer_stats::agent_version(),
+        features,
+        max_chunk_size: MAX_CHUNK_SIZE as u64,
+        max_file_size: MAX_FILE_SIZE,
+        hash_algorithms: vec![HASH_ALGORITHM.to_string()],
+        compression: chunk_codec::CODECS.iter().map(|codec| codec.to_string()).collect(),
+    }
+}
+
+/// What a peer that doesn't understand the capabilities request supports
+fn legacy() -> Capabilities {
+    Capabilities {
+        version: String::new(),
+        features: LEGACY_FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        max_chunk_size: CHUNK_SIZE as u64,
+        max_file_size: MAX_FILE_SIZE,
+        hash_algorithms: vec![HASH_ALGORITHM.to_string()],
+        compression: Vec::new(),
+    }
+}
+
+/// Capabilities of connected peers, consulted before using optional features
+/// A peer not heard from yet is assumed to have everything: subsystems already
+/// fall back when a peer turns out not to, and the document follows shortly.
+#[derive(Default)]
+pub struct PeerCapabilities {
+    peers: HashMap<PeerId, Capabilities>,
+    /// Chunks from each peer that failed to decode, kept when its document is replaced
+    codec_errors: HashMap<PeerId, u32>,
+}
+
+impl PeerCapabilities {
+    /// Remember a peer's document, returning an error if we can't sync with it at all
+    pub fn record(&mut self, peer: PeerId, capabilities: Capabilities) -> Result<(), String> {
+        let compatible = capabilities.hash_algorithms.iter().any(|algorithm| algorithm == HASH_ALGORITHM);
+        self.peers.insert(peer, capabilities);
+        if !compatible {
+            return Err(format!("peer doesn't support {} content hashes", HASH_ALGORITHM));
+        }
+        Ok(())
+    }
+
+    /// Remember that a peer predates capabilities documents
+    pub fn record_legacy(&mut self, peer: PeerId) {
+        self.peers.insert(peer, legacy());
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.codec_errors.remove(peer);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Capabilities> {
+        self.peers.get(peer)
+    }
+
+    /// Whether `feature` may be used with `peer`
+    pub fn allows(&self, peer: &PeerId, feature: &str) -> bool {
+        self.peers.get(peer).is_none_or(|capabilities| capabilities.features.iter().any(|f| f == feature))
+    }
+
+    /// Whether `peer`'s document lists `feature`, false until it arrives
+    /// For features a peer without them would misread rather than refuse.
+    pub fn confirms(&self, peer: &PeerId, feature: &str) -> bool {
+        self.peers.get(peer).is_some_and(|capabilities| capabilities.features.iter().any(|f| f == feature))
+    }
+
+    /// Largest chunk to ask `peer` for, at most `wanted`
+    /// Peers that can't be asked for a length always send CHUNK_SIZE.
+    pub fn chunk_size(&self, peer: &PeerId, wanted: u64) -> u64 {
+        if !self.allows(peer, CHUNK_LENGTH) {
+            return CHUNK_SIZE as u64;
+        }
+        // A limit of 0 was left out of the document
+        let limit = self.peers.get(peer).map_or(0, |capabilities| capabilities.max_chunk_size);
+        if limit == 0 {
+            return wanted.min(MAX_CHUNK_SIZE as u64);
+        }
+        wanted.min(limit)
+    }
+
+    /// Codec to accept chunks from `peer` in, if it lists one we have and hasn't failed with it
+    /// Peers whose document hasn't arrived are asked for raw chunks, which every version sends.
+    pub fn codec(&self, peer: &PeerId) -> Option<&'static str> {
+        if self.codec_errors.get(peer).is_some_and(|errors| *errors >= MAX_CODEC_ERRORS) {
+            return None;
+        }
+        let capabilities = self.peers.get(peer)?;
+        chunk_codec::CODECS.iter().copied().find(|codec| capabilities.compression.iter().any(|c| c == codec))
+    }
+
+    /// Count a chunk from `peer` that failed to decode, returning true once it is downgraded to raw chunks
+    pub fn codec_failed(&mut self, peer: PeerId) -> bool {
+        let errors = self.codec_errors.entry(peer).or_default();
+        *errors += 1;
+        *errors == MAX_CODEC_ERRORS
+    }
+
+    /// Whether `peer` serves files of `size` bytes
+    pub fn serves_size(&self, peer: &PeerId, size: u64) -> bool {
+        self.peers.get(peer).is_none_or(|capabilities| capabilities.max_file_size == 0 || size <= capabilities.max_file_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_optional_features_follow_the_peers_document() {
+        let (current, old, quiet) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut capabilities = PeerCapabilities::default();
+        assert!(capabilities.record(current, local(false)).is_ok());
+        capabilities.record_legacy(old);
+
+        // Bulk streams were disabled on the current peer, and the old one never had them
+        assert!(!capabilities.allows(&current, BULK));
+        assert!(capabilities.allows(&current, CHUNK_LENGTH));
+        assert!(!capabilities.allows(&old, BULK));
+        assert!(capabilities.allows(&old, TREE_NODE));
+        assert!(capabilities.allows(&quiet, BULK));
+        assert!(capabilities.confirms(&current, TREE_PAGES) && !capabilities.confirms(&quiet, TREE_PAGES));
+        assert!(capabilities.confirms(&current, HEADS) && !capabilities.confirms(&old, HEADS));
+
+        assert_eq!(capabilities.chunk_size(&current, 2 * CHUNK_SIZE as u64), 2 * CHUNK_SIZE as u64);
+        assert_eq!(capabilities.chunk_size(&current, 8 * CHUNK_SIZE as u64), MAX_CHUNK_SIZE as u64);
+        assert_eq!(capabilities.chunk_size(&old, 2 * CHUNK_SIZE as u64), CHUNK_SIZE as u64);
+        assert!(!capabilities.serves_size(&old, MAX_FILE_SIZE + 1));
+        assert!(capabilities.serves_size(&quiet, MAX_FILE_SIZE + 1));
+
+        // Chunks are only requested compressed from peers that said they decode them, until they fail to
+        #[cfg(feature = "compression")]
+        {
+            assert_eq!(capabilities.codec(&current), Some(chunk_codec::ZSTD));
+            assert_eq!(capabilities.codec(&old), None);
+            assert_eq!(capabilities.codec(&quiet), None);
+            assert!(!capabilities.codec_failed(current) && !capabilities.codec_failed(current));
+            assert_eq!(capabilities.codec(&current), Some(chunk_codec::ZSTD));
+            assert!(capabilities.codec_failed(current));
+            assert_eq!(capabilities.codec(&current), None);
+            assert!(capabilities.record(current, local(false)).is_ok());
+            assert_eq!(capabilities.codec(&current), None);
+        }
+        #[cfg(not(feature = "compression"))]
+        assert_eq!(capabilities.codec(&current), None);
+
+        // Unknown fields from newer peers are ignored on decode
+        let newer: Capabilities = serde_json::from_str(r#"{"version":"syndactyl/9.0.0","features":["bulk","teleport"],"hash_algorithms":["blake3"],"delta":true}"#).unwrap();
+        assert!(capabilities.record(quiet, newer).is_err());
+        assert!(capabilities.allows(&quiet, BULK));
+        assert!(capabilities.serves_size(&quiet, MAX_FILE_SIZE));
+        assert_eq!(capabilities.chunk_size(&quiet, 8 * CHUNK_SIZE as u64), MAX_CHUNK_SIZE as u64);
+        capabilities.forget(&quiet);
+        assert!(capabilities.get(&quiet).is_none());
+    }
+}
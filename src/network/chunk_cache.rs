@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::core::hash_pool;
+
+/// Bytes of served chunks kept in memory by default (64 MiB)
+pub const DEFAULT_CAPACITY: u64 = 64 * 1024 * 1024;
+
+/// A chunk as it is sent to peers
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedChunk {
+    pub data: Vec<u8>,
+    pub segment_hash: String,
+}
+
+/// Recently served chunks, keyed by file version, offset and requested length
+/// A newly changed file is usually pulled by every peer in the mesh within
+/// seconds of each other; keeping its chunks in memory means it is read from
+/// disk once rather than once per peer. Least recently used chunks are evicted
+/// first once the byte budget is spent. Files not hashed yet are never cached:
+/// their pending version only names a size and mtime, which two files can share.
+pub struct ChunkCache {
+    capacity: u64,
+    used: u64,
+    /// Next use stamp; higher is more recent
+    clock: u64,
+    chunks: HashMap<(String, u64, usize), (CachedChunk, u64)>,
+    /// Chunk keys by last use
+    by_use: BTreeMap<u64, (String, u64, usize)>,
+}
+
+impl ChunkCache {
+    /// A cache holding at most `capacity` bytes; 0 disables it
+    pub fn new(capacity: u64) -> Self {
+        Self { capacity, used: 0, clock: 0, chunks: HashMap::new(), by_use: BTreeMap::new() }
+    }
+
+    /// The chunk of version `hash` read as `len` bytes at `offset`, if it was served recently
+    /// Observers split files differently, so chunks only match when asked for at the same length.
+    pub fn get(&mut self, hash: &str, offset: u64, len: usize) -> Option<CachedChunk> {
+        if hash_pool::parse_pending_version(hash).is_some() {
+            return None;
+        }
+        let key = (hash.to_string(), offset, len);
+        let stamp = self.next_stamp();
+        let (chunk, used_at) = self.chunks.get_mut(&key)?;
+        self.by_use.remove(used_at);
+        *used_at = stamp;
+        self.by_use.insert(stamp, key);
+        Some(chunk.clone())
+    }
+
+    /// Keep a chunk that was just read for serving
+    pub fn insert(&mut self, hash: &str, offset: u64, len: usize, chunk: CachedChunk) {
+        let size = chunk.data.len() as u64;
+        if size > self.capacity || hash_pool::parse_pending_version(hash).is_some() {
+            return;
+        }
+        let key = (hash.to_string(), offset, len);
+        if let Some((old, used_at)) = self.chunks.remove(&key) {
+            self.by_use.remove(&used_at);
+            self.used -= old.data.len() as u64;
+        }
+        while self.used + size > self.capacity {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.chunks.remove(&oldest) {
+                self.used -= evicted.data.len() as u64;
+            }
+        }
+        let stamp = self.next_stamp();
+        self.by_use.insert(stamp, key.clone());
+        self.chunks.insert(key, (chunk, stamp));
+        self.used += size;
+    }
+
+    /// Bytes currently held
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    fn next_stamp(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(len: usize) -> CachedChunk {
+        CachedChunk { data: vec![0; len], segment_hash: String::new() }
+    }
+
+    #[test]
+    fn test_least_recently_used_chunks_are_evicted_first() {
+        let mut cache = ChunkCache::new(30);
+        cache.insert("h1", 0, 10, chunk(10));
+        cache.insert("h1", 10, 10, chunk(10));
+        cache.insert("h2", 0, 10, chunk(10));
+        assert_eq!(cache.used(), 30);
+
+        // Touch the oldest so the next insert evicts ("h1", 10) instead
+        assert!(cache.get("h1", 0, 10).is_some());
+        cache.insert("h3", 0, 10, chunk(10));
+        assert!(cache.get("h1", 10, 10).is_none());
+        assert!(cache.get("h1", 0, 10).is_some());
+        assert!(cache.get("h2", 0, 10).is_some());
+        assert_eq!(cache.used(), 30);
+
+        // A chunk read at another length is a different chunk
+        assert!(cache.get("h1", 0, 20).is_none());
+
+        // Chunks larger than the whole budget are never kept
+        cache.insert("h4", 0, 31, chunk(31));
+        assert!(cache.get("h4", 0, 31).is_none());
+        assert!(ChunkCache::new(0).get("h1", 0, 10).is_none());
+    }
+
+    #[test]
+    fn test_pending_versions_are_not_cached() {
+        let mut cache = ChunkCache::new(100);
+        let pending = hash_pool::pending_version(10, 1_700_000_000);
+        cache.insert(&pending, 0, 10, chunk(10));
+        assert!(cache.get(&pending, 0, 10).is_none());
+        assert_eq!(cache.used(), 0);
+    }
+}
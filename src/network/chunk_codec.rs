@@ -0,0 +1,95 @@
+use crate::network::transfer::segment_hash;
+
+/// zstd, as named in capabilities documents and chunk encodings
+pub const ZSTD: &str = "zstd";
+
+/// Codecs we can encode and decode chunks with, most preferred first
+#[cfg(feature = "compression")]
+pub const CODECS: &[&str] = &[ZSTD];
+
+/// Built without compression, chunks are only ever sent and accepted raw
+#[cfg(not(feature = "compression"))]
+pub const CODECS: &[&str] = &[];
+
+/// zstd level; chunks are compressed as they are served, so speed matters most
+#[cfg(feature = "compression")]
+const LEVEL: i32 = 1;
+
+/// Encode a served chunk with the codec the requester accepts
+/// Returns the bytes to send and the codec they are encoded with, if any.
+/// Chunks that don't shrink, e.g. already compressed media, go out raw.
+pub fn encode(data: Vec<u8>, accepted: Option<&str>) -> (Vec<u8>, Option<String>) {
+    if accepted != Some(ZSTD) || data.is_empty() {
+        return (data, None);
+    }
+    match compress(&data) {
+        Ok(compressed) if compressed.len() < data.len() => (compressed, Some(ZSTD.to_string())),
+        _ => (data, None),
+    }
+}
+
+/// Decode a received chunk, checking it against its segment hash
+/// `max_len` bounds what a chunk may expand to. A chunk that decodes to the
+/// wrong bytes is a codec failure too, so it is retried raw like one that
+/// doesn't decode at all.
+pub fn decode(data: &[u8], encoding: Option<&str>, segment: Option<&str>, max_len: usize) -> Result<Vec<u8>, String> {
+    let decoded = match encoding {
+        None => return Ok(data.to_vec()),
+        Some(ZSTD) => decompress(data, max_len).map_err(|e| format!("{} chunk does not decode: {}", ZSTD, e))?,
+        Some(other) => return Err(format!("unknown chunk encoding '{}'", other)),
+    };
+    if segment.is_some_and(|expected| expected != segment_hash(&decoded)) {
+        return Err(format!("{} chunk decodes to bytes that don't match its segment hash", ZSTD));
+    }
+    Ok(decoded)
+}
+
+#[cfg(feature = "compression")]
+fn compress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::compress(data, LEVEL)
+}
+
+#[cfg(feature = "compression")]
+fn decompress(data: &[u8], max_len: usize) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::decompress(data, max_len)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compress(_data: &[u8]) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "built without the compression feature"))
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_data: &[u8], _max_len: usize) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "built without the compression feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_chunks_round_trip_and_bad_encodings_are_reported() {
+        let text = "the same line again\n".repeat(500).into_bytes();
+        let (encoded, encoding) = encode(text.clone(), Some(ZSTD));
+        assert_eq!(encoding.as_deref(), Some(ZSTD));
+        assert!(encoded.len() < text.len() / 10);
+        let hash = segment_hash(&text);
+        assert_eq!(decode(&encoded, encoding.as_deref(), Some(&hash), text.len()).unwrap(), text);
+
+        // Requesters that don't accept a codec, and data that doesn't shrink, get raw bytes
+        assert_eq!(encode(text.clone(), None), (text.clone(), None));
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        assert_eq!(encode(noise.clone(), Some(ZSTD)).1, None);
+
+        // Corrupt, oversized, mismatched and unknown encodings all fail rather than pass bytes on
+        let mut corrupt = encoded.clone();
+        corrupt.truncate(encoded.len() / 2);
+        assert!(decode(&corrupt, Some(ZSTD), Some(&hash), text.len()).is_err());
+        assert!(decode(&encoded, Some(ZSTD), Some(&hash), text.len() / 2).is_err());
+        assert!(decode(&encoded, Some(ZSTD), Some(&segment_hash(b"other")), text.len()).is_err());
+        assert!(decode(&encoded, Some("brotli"), None, text.len()).is_err());
+        assert_eq!(decode(b"raw", None, None, 3).unwrap(), b"raw");
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+
+/// Outstanding chunk requests a new peer starts with
+const INITIAL_WINDOW: f64 = 2.0;
+const MIN_WINDOW: f64 = 1.0;
+/// At 1 MiB per chunk, enough to fill a fast link without buffering a whole large file
+const MAX_WINDOW: f64 = 32.0;
+/// A round trip this much slower than the fastest seen means requests are queueing
+const QUEUEING_FACTOR: f64 = 1.5;
+/// Weight of a new sample in the smoothed round trip
+const RTT_GAIN: f64 = 0.125;
+
+struct Window {
+    size: f64,
+    /// Below this the window doubles per round trip, above it grows by one
+    threshold: f64,
+    smoothed_rtt: Option<Duration>,
+    min_rtt: Option<Duration>,
+    last_decrease: Option<Instant>,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self { size: INITIAL_WINDOW, threshold: MAX_WINDOW, smoothed_rtt: None, min_rtt: None, last_decrease: None }
+    }
+}
+
+/// AIMD congestion control of chunk requests, one window per peer
+/// The window grows while chunk round trips stay near the fastest seen, holds
+/// once they start to stretch, and halves when a request times out or fails.
+#[derive(Default)]
+pub struct CongestionControl {
+    peers: HashMap<PeerId, Window>,
+}
+
+impl CongestionControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many chunk requests may be outstanding to a peer
+    pub fn window(&self, peer: &PeerId) -> usize {
+        self.peers.get(peer).map_or(INITIAL_WINDOW, |window| window.size) as usize
+    }
+
+    /// A chunk arrived `rtt` after it was requested
+    pub fn on_ack(&mut self, peer: PeerId, rtt: Duration) {
+        let window = self.peers.entry(peer).or_default();
+        let min_rtt = *window.min_rtt.get_or_insert(rtt);
+        window.min_rtt = Some(min_rtt.min(rtt));
+        let smoothed = window.smoothed_rtt.map_or(rtt, |smoothed| smoothed.mul_f64(1.0 - RTT_GAIN) + rtt.mul_f64(RTT_GAIN));
+        window.smoothed_rtt = Some(smoothed);
+
+        if smoothed > min_rtt.mul_f64(QUEUEING_FACTOR) {
+            return;
+        }
+        window.size = if window.size < window.threshold {
+            window.size + 1.0
+        } else {
+            window.size + 1.0 / window.size
+        }
+        .min(MAX_WINDOW);
+    }
+
+    /// A chunk request timed out or failed
+    pub fn on_loss(&mut self, peer: PeerId, now: Instant) {
+        let window = self.peers.entry(peer).or_default();
+        // Requests sent in the same round trip tend to fail together; count that as one loss
+        let round_trip = window.smoothed_rtt.unwrap_or(Duration::from_secs(1));
+        if window.last_decrease.is_some_and(|at| now.saturating_duration_since(at) < round_trip) {
+            return;
+        }
+        window.size = (window.size / 2.0).max(MIN_WINDOW);
+        window.threshold = window.size;
+        window.last_decrease = Some(now);
+    }
+
+    /// Forget a peer once its last connection closes
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_grows_while_stable_and_halves_on_loss() {
+        let peer = PeerId::random();
+        let mut control = CongestionControl::new();
+        assert_eq!(control.window(&peer), 2);
+
+        for _ in 0..6 {
+            control.on_ack(peer, Duration::from_millis(20));
+        }
+        assert_eq!(control.window(&peer), 8);
+
+        // Queueing delay stops growth
+        for _ in 0..20 {
+            control.on_ack(peer, Duration::from_millis(200));
+        }
+        assert_eq!(control.window(&peer), 8);
+
+        let now = Instant::now();
+        control.on_loss(peer, now);
+        control.on_loss(peer, now + Duration::from_millis(1));
+        assert_eq!(control.window(&peer), 4);
+        control.on_loss(peer, now + Duration::from_secs(5));
+        control.on_loss(peer, now + Duration::from_secs(10));
+        control.on_loss(peer, now + Duration::from_secs(15));
+        assert_eq!(control.window(&peer), 1);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use libp2p::multiaddr::Protocol;
+use libp2p::rendezvous::{self, Cookie, Namespace};
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::{Multiaddr, PeerId, Swarm};
+use tracing::{debug, info, warn};
+use crate::core::config::NetworkConfig;
+use crate::network::bootstrap::{BootstrapPeerStatus, BootstrapTracker};
+use crate::network::syndactyl_behaviour::{SyndactylBehaviour, SyndactylEvent};
+
+pub const STATIC: &str = "static";
+pub const MDNS: &str = "mdns";
+pub const DHT: &str = "dht";
+pub const RENDEZVOUS: &str = "rendezvous";
+
+/// Backends used when none are configured
+pub const DEFAULT_BACKENDS: [&str; 2] = [STATIC, DHT];
+
+/// Namespace registered under at a rendezvous point, unless configured
+pub const DEFAULT_NAMESPACE: &str = "syndactyl";
+
+/// How often random DHT lookups refresh the routing table
+const DHT_WALK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How often a rendezvous point is asked for peers registered since the last query
+const RENDEZVOUS_DISCOVER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the registration at a rendezvous point is renewed
+/// Registrations last two hours unless the point says otherwise.
+const RENDEZVOUS_REGISTER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Delay between dials of an unreachable rendezvous point, and between failed registrations
+const RENDEZVOUS_RETRY: Duration = Duration::from_secs(30);
+
+/// What the discovery backends report in `syndactyl status`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiscoveryStatus {
+    pub bootstrap_peers: Vec<BootstrapPeerStatus>,
+    pub dht_bootstrapped: bool,
+}
+
+/// One way of finding peers to connect to
+/// Backends relying on a libp2p behaviour of their own (mDNS, rendezvous) have
+/// it enabled in the swarm when selected; `handle_event` sees its events.
+pub trait DiscoveryBackend: Send {
+    fn name(&self) -> &'static str;
+
+    /// Periodic work: redials, lookups and registrations that are due
+    fn poll(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, _now: Instant) {}
+
+    /// The first connection to `peer` opened
+    fn connected(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, _peer: &PeerId, _now: Instant) {}
+
+    /// The last connection to `peer` closed
+    fn disconnected(&mut self, _peer: &PeerId, _now: Instant) {}
+
+    fn dial_failed(&mut self, _peer: &PeerId, _error: &str) {}
+
+    /// React to a behaviour event, returning the peers it revealed and where to dial them
+    fn handle_event(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, _event: &SyndactylEvent) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        Vec::new()
+    }
+
+    /// Add what this backend knows to the daemon status
+    fn status(&self, _status: &mut DiscoveryStatus) {}
+}
+
+/// The configured bootstrap peers, dialed until reachable and redialed when lost
+pub struct StaticDiscovery {
+    tracker: BootstrapTracker,
+}
+
+impl DiscoveryBackend for StaticDiscovery {
+    fn name(&self) -> &'static str {
+        STATIC
+    }
+
+    fn poll(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        if !self.tracker.is_waiting() {
+            return;
+        }
+        for address in self.tracker.due(now) {
+            debug!(address = %address, "[syndactyl] Dialing bootstrap peer");
+            if let Err(e) = swarm.dial(address.clone()) {
+                warn!(address = %address, error = %e, "[syndactyl] Failed to dial bootstrap peer");
+                if let Some(Protocol::P2p(peer_id)) = address.iter().last() {
+                    self.tracker.dial_failed(&peer_id, e.to_string());
+                }
+            }
+        }
+    }
+
+    fn connected(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, peer: &PeerId, _now: Instant) {
+        if self.tracker.connected(peer) {
+            let (reachable, total) = self.tracker.progress();
+            info!("[syndactyl] {}/{} bootstrap peers reachable", reachable, total);
+        }
+    }
+
+    fn disconnected(&mut self, peer: &PeerId, now: Instant) {
+        if self.tracker.disconnected(peer, now) {
+            let (reachable, total) = self.tracker.progress();
+            warn!(peer_id = %peer, "[syndactyl] Lost bootstrap peer, {}/{} bootstrap peers reachable", reachable, total);
+        }
+    }
+
+    fn dial_failed(&mut self, peer: &PeerId, error: &str) {
+        self.tracker.dial_failed(peer, error.to_string());
+    }
+
+    fn status(&self, status: &mut DiscoveryStatus) {
+        status.bootstrap_peers = self.tracker.status();
+    }
+}
+
+/// The Kademlia DHT, filled through the first peer reached and refreshed by random lookups
+/// The routing table also finds providers of distributed files, so the DHT runs
+/// whether or not this backend is selected; without it, it is never walked.
+pub struct DhtDiscovery {
+    bootstrapped: bool,
+    bootstrapping: bool,
+    next_walk: Instant,
+}
+
+impl DiscoveryBackend for DhtDiscovery {
+    fn name(&self) -> &'static str {
+        DHT
+    }
+
+    fn poll(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        if !self.bootstrapped || now < self.next_walk {
+            return;
+        }
+        self.next_walk = now + DHT_WALK_INTERVAL;
+        swarm.behaviour_mut().kademlia.get_closest_peers(PeerId::random());
+    }
+
+    fn connected(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, _peer: &PeerId, _now: Instant) {
+        if self.bootstrapped || self.bootstrapping {
+            return;
+        }
+        match swarm.behaviour_mut().kademlia.bootstrap() {
+            Ok(_) => self.bootstrapping = true,
+            Err(e) => warn!(error = ?e, "[syndactyl][kademlia] Could not start DHT bootstrap"),
+        }
+    }
+
+    fn handle_event(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, event: &SyndactylEvent) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        use libp2p::kad::{Event as KademliaEvent, QueryResult};
+        if let SyndactylEvent::Kademlia(KademliaEvent::OutboundQueryProgressed { result: QueryResult::Bootstrap(result), .. }) = event {
+            match result {
+                Ok(ok) if ok.num_remaining == 0 => {
+                    info!("[syndactyl][kademlia] DHT bootstrap complete");
+                    self.bootstrapped = true;
+                    self.bootstrapping = false;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(error = %e, "[syndactyl][kademlia] DHT bootstrap failed");
+                    self.bootstrapped = false;
+                    self.bootstrapping = false;
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    fn status(&self, status: &mut DiscoveryStatus) {
+        status.dht_bootstrapped = self.bootstrapped;
+    }
+}
+
+/// Peers announcing themselves on the local network
+pub struct MdnsDiscovery;
+
+impl DiscoveryBackend for MdnsDiscovery {
+    fn name(&self) -> &'static str {
+        MDNS
+    }
+
+    fn handle_event(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, event: &SyndactylEvent) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let SyndactylEvent::Mdns(libp2p::mdns::Event::Discovered(found)) = event else {
+            return Vec::new();
+        };
+        let mut peers: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer, address) in found {
+            swarm.behaviour_mut().kademlia.add_address(peer, address.clone());
+            peers.entry(*peer).or_default().push(address.clone());
+        }
+        peers.into_iter().collect()
+    }
+}
+
+/// A rendezvous point peers register with and look each other up at
+/// Lets friends' nodes that know no stable address of one another meet
+/// through a common server, which never holds or relays any file data.
+pub struct RendezvousDiscovery {
+    point: PeerId,
+    address: Multiaddr,
+    namespace: Namespace,
+    connected: bool,
+    next_dial: Instant,
+    next_register: Instant,
+    next_discover: Instant,
+    /// Where the last lookup left off, so only new registrations are returned
+    cookie: Option<Cookie>,
+}
+
+impl RendezvousDiscovery {
+    fn register(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        let Some(client) = swarm.behaviour_mut().rendezvous.as_mut() else {
+            return;
+        };
+        self.next_register = now + RENDEZVOUS_REGISTER_INTERVAL;
+        if let Err(e) = client.register(self.namespace.clone(), self.point, None) {
+            // Usually no external address is confirmed yet, e.g. before UPnP maps the port
+            debug!(point = %self.point, error = %e, "[syndactyl][rendezvous] Not registering yet");
+            self.next_register = now + RENDEZVOUS_RETRY;
+        }
+    }
+
+    fn discover(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        let Some(client) = swarm.behaviour_mut().rendezvous.as_mut() else {
+            return;
+        };
+        self.next_discover = now + RENDEZVOUS_DISCOVER_INTERVAL;
+        client.discover(Some(self.namespace.clone()), self.cookie.clone(), None, self.point);
+    }
+}
+
+impl DiscoveryBackend for RendezvousDiscovery {
+    fn name(&self) -> &'static str {
+        RENDEZVOUS
+    }
+
+    fn poll(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        if !self.connected {
+            if now >= self.next_dial {
+                self.next_dial = now + RENDEZVOUS_RETRY;
+                debug!(address = %self.address, "[syndactyl][rendezvous] Dialing rendezvous point");
+                if let Err(e) = swarm.dial(self.address.clone()) {
+                    warn!(address = %self.address, error = %e, "[syndactyl][rendezvous] Failed to dial rendezvous point");
+                }
+            }
+            return;
+        }
+        if now >= self.next_register {
+            self.register(swarm, now);
+        }
+        if now >= self.next_discover {
+            self.discover(swarm, now);
+        }
+    }
+
+    fn connected(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, peer: &PeerId, now: Instant) {
+        if peer != &self.point {
+            return;
+        }
+        info!(point = %self.point, "[syndactyl][rendezvous] Connected to rendezvous point");
+        self.connected = true;
+        self.register(swarm, now);
+        self.discover(swarm, now);
+    }
+
+    fn disconnected(&mut self, peer: &PeerId, now: Instant) {
+        if peer == &self.point {
+            warn!(point = %self.point, "[syndactyl][rendezvous] Lost rendezvous point");
+            self.connected = false;
+            self.next_dial = now + RENDEZVOUS_RETRY;
+        }
+    }
+
+    fn handle_event(&mut self, _swarm: &mut Swarm<SyndactylBehaviour>, event: &SyndactylEvent) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        use rendezvous::client::Event;
+        let SyndactylEvent::Rendezvous(event) = event else {
+            return Vec::new();
+        };
+        match event {
+            Event::Registered { namespace, ttl, .. } => {
+                info!(namespace = %namespace, ttl, "[syndactyl][rendezvous] Registered at rendezvous point");
+            }
+            Event::RegisterFailed { error, .. } => {
+                warn!(error = ?error, "[syndactyl][rendezvous] Rendezvous point refused registration");
+                self.next_register = Instant::now() + RENDEZVOUS_RETRY;
+            }
+            Event::Discovered { registrations, cookie, .. } => {
+                self.cookie = Some(cookie.clone());
+                return registrations.iter()
+                    .map(|registration| (registration.record.peer_id(), registration.record.addresses().to_vec()))
+                    .collect();
+            }
+            Event::DiscoverFailed { error, .. } => {
+                debug!(error = ?error, "[syndactyl][rendezvous] Lookup at rendezvous point failed");
+                // The cookie may have been from before the point restarted
+                self.cookie = None;
+            }
+            Event::Expired { peer } => {
+                debug!(peer_id = %peer, "[syndactyl][rendezvous] Registration expired");
+            }
+        }
+        Vec::new()
+    }
+}
+
+/// Backends `network` selects, in order and without repeats
+pub fn selected(network: &NetworkConfig) -> Result<Vec<&'static str>, String> {
+    let configured: Vec<&str> = match &network.discovery {
+        Some(names) => names.iter().map(String::as_str).collect(),
+        None => DEFAULT_BACKENDS.to_vec(),
+    };
+    let mut backends = Vec::new();
+    for name in configured {
+        let backend = [STATIC, MDNS, DHT, RENDEZVOUS].into_iter()
+            .find(|known| known.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!("Unknown discovery backend '{}'; use static, mdns, dht or rendezvous", name))?;
+        if !backends.contains(&backend) {
+            backends.push(backend);
+        }
+    }
+    Ok(backends)
+}
+
+/// Peer ID and address of the configured rendezvous point
+pub fn rendezvous_point(network: &NetworkConfig) -> Result<(PeerId, Multiaddr), String> {
+    let address = network.rendezvous.as_ref().and_then(|rendezvous| rendezvous.address.as_deref())
+        .ok_or("The rendezvous discovery backend needs rendezvous.address")?;
+    let multiaddr: Multiaddr = address.parse()
+        .map_err(|e| format!("Invalid rendezvous address '{}': {}", address, e))?;
+    match multiaddr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok((peer_id, multiaddr)),
+        _ => Err(format!("Rendezvous address '{}' must end in /p2p/<peer ID>", address)),
+    }
+}
+
+/// The discovery backends selected in the configuration
+pub struct Discovery {
+    backends: Vec<Box<dyn DiscoveryBackend>>,
+}
+
+impl Discovery {
+    pub fn from_config(network: &NetworkConfig, now: Instant) -> Result<Self, String> {
+        let mut backends: Vec<Box<dyn DiscoveryBackend>> = Vec::new();
+        for name in selected(network)? {
+            match name {
+                STATIC => backends.push(Box::new(StaticDiscovery {
+                    tracker: BootstrapTracker::new(&network.bootstrap_peers, now),
+                })),
+                DHT => backends.push(Box::new(DhtDiscovery { bootstrapped: false, bootstrapping: false, next_walk: now })),
+                MDNS => backends.push(Box::new(MdnsDiscovery)),
+                _ => {
+                    let (point, address) = rendezvous_point(network)?;
+                    let namespace = network.rendezvous.as_ref().and_then(|rendezvous| rendezvous.namespace.clone())
+                        .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+                    let namespace = Namespace::new(namespace)
+                        .map_err(|_| "Rendezvous namespace is too long".to_string())?;
+                    backends.push(Box::new(RendezvousDiscovery {
+                        point,
+                        address,
+                        namespace,
+                        connected: false,
+                        next_dial: now,
+                        next_register: now,
+                        next_discover: now,
+                        cookie: None,
+                    }));
+                }
+            }
+        }
+        Ok(Self { backends })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.backends.iter().map(|backend| backend.name().to_string()).collect()
+    }
+
+    pub fn poll(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, now: Instant) {
+        for backend in &mut self.backends {
+            backend.poll(swarm, now);
+        }
+    }
+
+    pub fn connected(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, peer: &PeerId, now: Instant) {
+        for backend in &mut self.backends {
+            backend.connected(swarm, peer, now);
+        }
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId, now: Instant) {
+        for backend in &mut self.backends {
+            backend.disconnected(peer, now);
+        }
+    }
+
+    pub fn dial_failed(&mut self, peer: &PeerId, error: &str) {
+        for backend in &mut self.backends {
+            backend.dial_failed(peer, error);
+        }
+    }
+
+    /// Show a behaviour event to every backend, dialing the peers they find
+    pub fn handle_event(&mut self, swarm: &mut Swarm<SyndactylBehaviour>, event: &SyndactylEvent) {
+        for backend in &mut self.backends {
+            for (peer, addresses) in backend.handle_event(swarm, event) {
+                if &peer == swarm.local_peer_id() || swarm.is_connected(&peer) || addresses.is_empty() {
+                    continue;
+                }
+                debug!(peer_id = %peer, backend = backend.name(), "[syndactyl] Dialing discovered peer");
+                if let Err(e) = swarm.dial(DialOpts::peer_id(peer).addresses(addresses).build()) {
+                    debug!(peer_id = %peer, error = %e, "[syndactyl] Failed to dial discovered peer");
+                }
+            }
+        }
+    }
+
+    pub fn status(&self) -> DiscoveryStatus {
+        let mut status = DiscoveryStatus::default();
+        for backend in &self.backends {
+            backend.status(&mut status);
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(discovery: serde_json::Value, rendezvous: serde_json::Value) -> NetworkConfig {
+        serde_json::from_value(serde_json::json!({
+            "listen_addr": "0.0.0.0", "port": "4001", "dht_mode": "server", "bootstrap_peers": [],
+            "discovery": discovery, "rendezvous": rendezvous
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_backends_are_selected_and_validated() {
+        let now = Instant::now();
+        assert_eq!(selected(&network(serde_json::Value::Null, serde_json::Value::Null)).unwrap(), [STATIC, DHT]);
+        assert_eq!(selected(&network(serde_json::json!(["mDNS", "static", "mdns"]), serde_json::Value::Null)).unwrap(), [MDNS, STATIC]);
+        assert!(selected(&network(serde_json::json!(["bonjour"]), serde_json::Value::Null)).unwrap_err().contains("bonjour"));
+
+        // Rendezvous needs a point to dial, identified by its peer ID
+        assert!(Discovery::from_config(&network(serde_json::json!(["rendezvous"]), serde_json::Value::Null), now).is_err());
+        let unnamed = network(serde_json::json!(["rendezvous"]), serde_json::json!({ "address": "/ip4/10.0.0.2/tcp/4001" }));
+        assert!(rendezvous_point(&unnamed).unwrap_err().contains("/p2p/"));
+        let point = PeerId::random();
+        let named = network(serde_json::json!(["rendezvous", "static"]), serde_json::json!({ "address": format!("/ip4/10.0.0.2/tcp/4001/p2p/{}", point) }));
+        assert_eq!(rendezvous_point(&named).unwrap().0, point);
+        assert_eq!(Discovery::from_config(&named, now).unwrap().names(), ["rendezvous", "static"]);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::core::auth;
+use crate::core::config::ObserverConfig;
+
+/// Extra peers a download pulls chunks from, besides the one that announced it
+pub const MAX_HELPERS: usize = 4;
+
+/// Part an observer plays in a one-way distribution channel
+/// One publisher announces; any number of subscribers receive, serve what
+/// they hold to each other, and never announce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Publisher,
+    Subscriber,
+}
+
+impl Role {
+    /// The observer's role, if it is part of a distribution channel
+    pub fn of(observer: &ObserverConfig) -> Result<Option<Self>, String> {
+        let role = match observer.distribution.as_deref() {
+            None => return Ok(None),
+            Some("publish") => Self::Publisher,
+            Some("subscribe") => Self::Subscriber,
+            Some(other) => return Err(format!("Observer {} has unknown distribution '{}' (expected publish or subscribe)", observer.name, other)),
+        };
+        if observer.announce_only == Some(true) || observer.mount.is_some() {
+            return Err(format!("Observer {} can't be both part of a distribution channel and announce-only or mounted", observer.name));
+        }
+        Ok(Some(role))
+    }
+}
+
+/// DHT key peers holding one version of a file are advertised under
+/// Keyed by the shared secret when there is one, so the key reveals nothing
+/// about the observer, path or content.
+pub fn provider_key(observer: &str, path: &str, hash: &str, secret: Option<&str>) -> String {
+    let id = auth::sign_bytes(format!("syndactyl-provider||{}||{}||{}", observer, path, hash).as_bytes(), secret.unwrap_or(observer));
+    format!("/syndactyl/provider/{}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_and_provider_keys() {
+        let mut observer: ObserverConfig = serde_json::from_value(serde_json::json!({ "name": "releases", "path": "/srv/releases" })).unwrap();
+        assert_eq!(Role::of(&observer), Ok(None));
+        observer.distribution = Some("subscribe".to_string());
+        assert_eq!(Role::of(&observer), Ok(Some(Role::Subscriber)));
+        observer.announce_only = Some(true);
+        assert!(Role::of(&observer).is_err());
+        observer.distribution = Some("broadcast".to_string());
+        assert!(Role::of(&observer).is_err());
+
+        let key = provider_key("releases", "v1.iso", "abc", Some("secret"));
+        assert_eq!(key, provider_key("releases", "v1.iso", "abc", Some("secret")));
+        assert_ne!(key, provider_key("releases", "v1.iso", "abd", Some("secret")));
+        assert_ne!(key, provider_key("releases", "v1.iso", "abc", Some("other")));
+    }
+}
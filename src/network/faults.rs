@@ -0,0 +1,168 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// Longest a reordered chunk is held waiting for a later one to overtake it
+pub const REORDER_HOLD: Duration = Duration::from_secs(1);
+
+/// Faults to inject into received file chunks, for resilience tests
+/// Rates are probabilities between 0 and 1, drawn from a generator seeded with
+/// `seed`, so a failing run can be replayed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FaultPlan {
+    #[serde(default)]
+    pub drop_rate: f64,
+    /// Chance of a chunk having one byte flipped
+    #[serde(default)]
+    pub corrupt_rate: f64,
+    /// Chance of a chunk being held until a later one has been delivered
+    #[serde(default)]
+    pub reorder_rate: f64,
+    /// Delay applied to every delivered chunk
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Disconnect from the sending peer once this many more chunks have arrived
+    #[serde(default)]
+    pub kill_after_chunks: Option<u32>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// What happens to one received chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkFault {
+    Deliver,
+    Drop,
+    /// Deliver once the delay has passed
+    Delay(Duration),
+    /// Deliver after the next delivered chunk, or after REORDER_HOLD
+    Reorder,
+    /// Drop the chunk and disconnect from the peer that sent it
+    Kill,
+}
+
+/// Number of faults injected so far, per kind
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FaultCounts {
+    pub dropped: u64,
+    pub corrupted: u64,
+    pub reordered: u64,
+    pub delayed: u64,
+    pub killed: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    plan: FaultPlan,
+    rng: u64,
+    counts: FaultCounts,
+}
+
+impl Inner {
+    /// xorshift64*: plenty for test faults, and needs no dependency
+    fn roll(&mut self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let value = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        ((value >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+/// Handle on the faults injected by a network manager
+/// Clones share state, so a test can keep one and change the plan while the
+/// daemon runs.
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the active plan, resetting the generator and counters
+    pub fn set(&self, plan: FaultPlan) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.rng = if plan.seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { plan.seed };
+            inner.plan = plan;
+            inner.counts = FaultCounts::default();
+        }
+    }
+
+    /// Stop injecting faults
+    pub fn clear(&self) {
+        self.set(FaultPlan::default());
+    }
+
+    pub fn counts(&self) -> FaultCounts {
+        self.inner.lock().map(|inner| inner.counts.clone()).unwrap_or_default()
+    }
+
+    /// Decide the fate of a received chunk, corrupting `data` in place if chosen
+    pub fn on_chunk_received(&self, data: &mut [u8]) -> ChunkFault {
+        let Ok(mut inner) = self.inner.lock() else {
+            return ChunkFault::Deliver;
+        };
+        if let Some(remaining) = inner.plan.kill_after_chunks {
+            if remaining == 0 {
+                inner.plan.kill_after_chunks = None;
+                inner.counts.killed += 1;
+                return ChunkFault::Kill;
+            }
+            inner.plan.kill_after_chunks = Some(remaining - 1);
+        }
+        let (drop_rate, corrupt_rate, reorder_rate) = (inner.plan.drop_rate, inner.plan.corrupt_rate, inner.plan.reorder_rate);
+        if inner.roll(drop_rate) {
+            inner.counts.dropped += 1;
+            return ChunkFault::Drop;
+        }
+        if !data.is_empty() && inner.roll(corrupt_rate) {
+            let index = (inner.rng % data.len() as u64) as usize;
+            data[index] ^= 0xFF;
+            inner.counts.corrupted += 1;
+        }
+        if inner.roll(reorder_rate) {
+            inner.counts.reordered += 1;
+            return ChunkFault::Reorder;
+        }
+        if inner.plan.delay_ms > 0 {
+            inner.counts.delayed += 1;
+            return ChunkFault::Delay(Duration::from_millis(inner.plan.delay_ms));
+        }
+        ChunkFault::Deliver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults_follow_the_plan_and_replay_from_the_seed() {
+        let faults = FaultInjector::new();
+        let mut data = vec![0u8; 16];
+        assert_eq!(faults.on_chunk_received(&mut data), ChunkFault::Deliver);
+
+        faults.set(FaultPlan { kill_after_chunks: Some(1), delay_ms: 5, ..FaultPlan::default() });
+        assert_eq!(faults.on_chunk_received(&mut data), ChunkFault::Delay(Duration::from_millis(5)));
+        assert_eq!(faults.on_chunk_received(&mut data), ChunkFault::Kill);
+        assert_eq!(faults.counts().killed, 1);
+
+        faults.set(FaultPlan { corrupt_rate: 1.0, ..FaultPlan::default() });
+        assert_eq!(faults.on_chunk_received(&mut data), ChunkFault::Deliver);
+        assert_eq!(data.iter().filter(|byte| **byte == 0xFF).count(), 1);
+
+        let outcomes = |faults: &FaultInjector| {
+            faults.set(FaultPlan { drop_rate: 0.3, reorder_rate: 0.3, seed: 42, ..FaultPlan::default() });
+            (0..64).map(|_| faults.on_chunk_received(&mut [0u8; 4])).collect::<Vec<_>>()
+        };
+        let first = outcomes(&faults);
+        assert_eq!(first, outcomes(&faults));
+        assert!(first.contains(&ChunkFault::Drop) && first.contains(&ChunkFault::Reorder) && first.contains(&ChunkFault::Deliver));
+    }
+}
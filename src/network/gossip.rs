@@ -0,0 +1,90 @@
+use std::time::Duration;
+use libp2p::gossipsub::{Config as GossipsubConfig, ConfigBuilder};
+use crate::core::config::GossipsubTuningConfig;
+
+/// Smallest message size allowed, so heartbeats with many observers still fit
+const MIN_MESSAGE_KB: usize = 16;
+
+/// Heartbeats more often than this flood the mesh with control messages
+const MIN_HEARTBEAT_MS: u64 = 100;
+
+/// Outbound mesh peers kept by default; lowered for meshes too small to hold them
+const DEFAULT_MESH_OUTBOUND_MIN: usize = 2;
+
+/// Build the gossipsub config from libp2p's defaults and any configured overrides
+/// Mesh sizes are checked against each other here so a bad combination names the
+/// settings involved rather than failing inside libp2p.
+pub fn config(tuning: Option<&GossipsubTuningConfig>) -> Result<GossipsubConfig, String> {
+    let Some(tuning) = tuning else {
+        return Ok(GossipsubConfig::default());
+    };
+    let defaults = GossipsubConfig::default();
+    let mesh_n = tuning.mesh_n.unwrap_or(defaults.mesh_n());
+    let mesh_n_low = tuning.mesh_n_low.unwrap_or(defaults.mesh_n_low().min(mesh_n));
+    let mesh_n_high = tuning.mesh_n_high.unwrap_or(defaults.mesh_n_high().max(mesh_n));
+    if mesh_n == 0 {
+        return Err("gossipsub.mesh_n must be at least 1".to_string());
+    }
+    if !(mesh_n_low <= mesh_n && mesh_n <= mesh_n_high) {
+        return Err(format!(
+            "gossipsub mesh sizes must satisfy mesh_n_low <= mesh_n <= mesh_n_high, got {} <= {} <= {}",
+            mesh_n_low, mesh_n, mesh_n_high
+        ));
+    }
+
+    let mut builder = ConfigBuilder::default();
+    builder
+        .mesh_n(mesh_n)
+        .mesh_n_low(mesh_n_low)
+        .mesh_n_high(mesh_n_high)
+        .mesh_outbound_min(DEFAULT_MESH_OUTBOUND_MIN.min(mesh_n_low).min(mesh_n / 2));
+    if let Some(kb) = tuning.max_message_kb {
+        if kb < MIN_MESSAGE_KB {
+            return Err(format!("gossipsub.max_message_kb must be at least {}", MIN_MESSAGE_KB));
+        }
+        builder.max_transmit_size(kb * 1024);
+    }
+    if let Some(ms) = tuning.heartbeat_interval_ms {
+        if ms < MIN_HEARTBEAT_MS {
+            return Err(format!("gossipsub.heartbeat_interval_ms must be at least {}", MIN_HEARTBEAT_MS));
+        }
+        builder.heartbeat_interval(Duration::from_millis(ms));
+    }
+    if let Some(flood_publish) = tuning.flood_publish {
+        builder.flood_publish(flood_publish);
+    }
+    builder.build().map_err(|e| format!("Invalid gossipsub settings: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_are_applied_and_checked() {
+        assert_eq!(config(None).unwrap().mesh_n(), GossipsubConfig::default().mesh_n());
+
+        let tuning = GossipsubTuningConfig {
+            max_message_kb: Some(256),
+            heartbeat_interval_ms: Some(500),
+            mesh_n: Some(12),
+            mesh_n_high: Some(20),
+            flood_publish: Some(false),
+            ..GossipsubTuningConfig::default()
+        };
+        let built = config(Some(&tuning)).unwrap();
+        assert_eq!(built.max_transmit_size(), 256 * 1024);
+        assert_eq!(built.heartbeat_interval(), Duration::from_millis(500));
+        assert_eq!((built.mesh_n_low(), built.mesh_n(), built.mesh_n_high()), (5, 12, 20));
+        assert!(!built.flood_publish());
+
+        // A small mesh gets matching defaults instead of failing on them
+        let small = GossipsubTuningConfig { mesh_n: Some(2), ..GossipsubTuningConfig::default() };
+        assert_eq!(config(Some(&small)).unwrap().mesh_n_low(), 2);
+
+        let inverted = GossipsubTuningConfig { mesh_n_low: Some(8), mesh_n: Some(6), ..GossipsubTuningConfig::default() };
+        assert!(config(Some(&inverted)).unwrap_err().contains("mesh_n_low"));
+        let tiny = GossipsubTuningConfig { max_message_kb: Some(1), ..GossipsubTuningConfig::default() };
+        assert!(config(Some(&tiny)).unwrap_err().contains("max_message_kb"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use libp2p::PeerId;
+use crate::core::models::ObserverDigest;
+
+/// How long to wait before reconciling again against a root that is still different
+pub const RECONCILE_RETRY: Duration = Duration::from_secs(300);
+
+/// Peers owed our tree roots once they are known to understand them
+/// Only the dialing side sends heads, so each connection exchanges them once:
+/// the peer answers with its own, and both sides reconcile the trees that differ
+/// without waiting for the next heartbeat.
+#[derive(Debug, Default)]
+pub struct HeadsExchange {
+    due: HashSet<PeerId>,
+}
+
+impl HeadsExchange {
+    /// The first connection to `peer` opened
+    pub fn connected(&mut self, peer: PeerId, dialer: bool) {
+        if dialer {
+            self.due.insert(peer);
+        }
+    }
+
+    /// The peer's capabilities arrived; returns whether to send it our heads now
+    /// Older peers would drop a heads request they can't decode.
+    pub fn capabilities_known(&mut self, peer: &PeerId, understands_heads: bool) -> bool {
+        self.due.remove(peer) && understands_heads
+    }
+
+    /// The last connection to `peer` closed
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.due.remove(peer);
+    }
+}
+
+/// What a peer's root for an observer calls for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    InSync,
+    /// Already reconciling towards this root, recently enough to let it finish
+    Reconciling,
+    /// Reconcile from the top of the tree
+    Reconcile,
+}
+
+/// Compare a peer's root with ours
+/// `under_way` is the root a running reconciliation with the peer started from, and how long ago.
+pub fn compare(local_root: &str, theirs: &ObserverDigest, under_way: Option<(&str, Duration)>) -> Comparison {
+    if theirs.root == local_root {
+        return Comparison::InSync;
+    }
+    match under_way {
+        Some((root, elapsed)) if root == theirs.root && elapsed < RECONCILE_RETRY => Comparison::Reconciling,
+        _ => Comparison::Reconcile,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::core::models::Heartbeat;
+
+    fn heads(roots: &[(&str, &str)]) -> Heartbeat {
+        let observers = roots.iter()
+            .map(|(observer, root)| ObserverDigest {
+                observer: observer.to_string(),
+                observer_id: None,
+                root: root.to_string(),
+                file_count: 1,
+                hmac: None,
+                available_bytes: None,
+            })
+            .collect();
+        Heartbeat { observers, removed: Vec::new(), load: None }
+    }
+
+    /// Observers one side starts reconciling after taking in the other's heads
+    fn reconcile(local: &Heartbeat, theirs: &Heartbeat, under_way: &HashMap<String, (String, Duration)>) -> Vec<String> {
+        theirs.observers.iter()
+            .filter(|digest| {
+                let Some(ours) = local.observers.iter().find(|ours| ours.observer == digest.observer) else {
+                    return false;
+                };
+                let running = under_way.get(&digest.observer).map(|(root, elapsed)| (root.as_str(), *elapsed));
+                compare(&ours.root, digest, running) == Comparison::Reconcile
+            })
+            .map(|digest| digest.observer.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_a_dialed_peer_gets_our_roots_and_both_sides_reconcile_from_the_answer() {
+        let (dialer, listener) = (PeerId::random(), PeerId::random());
+        let (mut ours, mut theirs) = (HeadsExchange::default(), HeadsExchange::default());
+        ours.connected(listener, true);
+        theirs.connected(dialer, false);
+
+        // Only the dialing side sends, and only once it knows the peer understands heads
+        assert!(!theirs.capabilities_known(&dialer, true));
+        assert!(ours.capabilities_known(&listener, true));
+        assert!(!ours.capabilities_known(&listener, true));
+
+        let local = heads(&[("docs", "r1"), ("photos", "p1")]);
+        let remote = heads(&[("docs", "r2"), ("photos", "p1"), ("music", "m1")]);
+        let none = HashMap::new();
+        // The listener answers the request with its own heads and reconciles from ours...
+        assert_eq!(reconcile(&remote, &local, &none), vec!["docs"]);
+        // ...and the dialer reconciles from the answer, skipping observers it doesn't sync
+        assert_eq!(reconcile(&local, &remote, &none), vec!["docs"]);
+
+        // A reconciliation towards the same root isn't restarted until it has had time to finish
+        let running = HashMap::from([("docs".to_string(), ("r2".to_string(), Duration::from_secs(10)))]);
+        assert!(reconcile(&local, &remote, &running).is_empty());
+        let stale = HashMap::from([("docs".to_string(), ("r2".to_string(), RECONCILE_RETRY))]);
+        assert_eq!(reconcile(&local, &remote, &stale), vec!["docs"]);
+        let moved_on = HashMap::from([("docs".to_string(), ("r0".to_string(), Duration::from_secs(10)))]);
+        assert_eq!(reconcile(&local, &remote, &moved_on), vec!["docs"]);
+    }
+
+    #[test]
+    fn test_peers_without_heads_or_gone_are_not_sent_any() {
+        let peer = PeerId::random();
+        let mut exchange = HeadsExchange::default();
+        exchange.connected(peer, true);
+        assert!(!exchange.capabilities_known(&peer, false));
+
+        exchange.connected(peer, true);
+        exchange.disconnected(&peer);
+        assert!(!exchange.capabilities_known(&peer, true));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::{HashMap, HashSet};
+use libp2p::PeerId;
+use libp2p::identity::PublicKey;
+use crate::core::config::ObserverConfig;
+
+/// Public keys each observer is pinned to
+/// Observers without `pinned_keys` trust every peer the other checks let through.
+/// A pinned observer only syncs with the holders of its keys, and only accepts
+/// announcements they published, however many peers relayed them and however
+/// correctly they are signed with the shared secret.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyPins {
+    observers: HashMap<String, HashSet<PeerId>>,
+}
+
+impl KeyPins {
+    /// Decode every observer's `pinned_keys`
+    pub fn from_config(observers: &[ObserverConfig]) -> Result<Self, String> {
+        let mut pinned = HashMap::new();
+        for observer in observers {
+            let Some(keys) = &observer.pinned_keys else {
+                continue;
+            };
+            if keys.is_empty() {
+                return Err(format!("Observer {} has an empty pinned_keys list; remove it or pin at least one key", observer.name));
+            }
+            let peers = keys.iter()
+                .map(|key| decode(key).map(|key| key.to_peer_id()).map_err(|e| format!("Observer {}: {}", observer.name, e)))
+                .collect::<Result<HashSet<_>, _>>()?;
+            pinned.insert(observer.name.clone(), peers);
+        }
+        Ok(Self { observers: pinned })
+    }
+
+    /// Whether `peer` holds one of the keys `observer` is pinned to
+    pub fn allows(&self, observer: &str, peer: &PeerId) -> bool {
+        self.observers.get(observer).is_none_or(|peers| peers.contains(peer))
+    }
+
+    /// Whether an announcement published by `origin` may be accepted for `observer`
+    /// Unsigned announcements have no known publisher and never pass a pin.
+    pub fn allows_origin(&self, observer: &str, origin: Option<&PeerId>) -> bool {
+        match origin {
+            Some(origin) => self.allows(observer, origin),
+            None => !self.is_pinned(observer),
+        }
+    }
+
+    pub fn is_pinned(&self, observer: &str) -> bool {
+        self.observers.contains_key(observer)
+    }
+
+    /// Every peer pinned by at least one observer
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.observers.values().flatten()
+    }
+}
+
+/// A public key as written in `pinned_keys`: its protobuf encoding, hex encoded
+pub fn encode(key: &PublicKey) -> String {
+    key.encode_protobuf().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn decode(key: &str) -> Result<PublicKey, String> {
+    let key = key.trim();
+    let invalid = || format!("'{}' is not a public key; copy the one a peer logs when it starts", key);
+    if key.len() % 2 != 0 || !key.is_ascii() {
+        return Err(invalid());
+    }
+    let bytes = (0..key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&key[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    PublicKey::try_decode_protobuf(&bytes).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    fn observer(name: &str, pinned_keys: Option<Vec<String>>) -> ObserverConfig {
+        serde_json::from_value(serde_json::json!({ "name": name, "path": "/tmp", "pinned_keys": pinned_keys })).unwrap()
+    }
+
+    #[test]
+    fn test_pinned_observers_only_accept_their_keys() {
+        let trusted = Keypair::generate_ed25519().public();
+        let stranger = Keypair::generate_ed25519().public();
+        assert_eq!(decode(&encode(&trusted)).unwrap(), trusted);
+
+        let pins = KeyPins::from_config(&[
+            observer("vault", Some(vec![encode(&trusted)])),
+            observer("music", None),
+        ]).unwrap();
+        assert!(pins.allows("vault", &trusted.to_peer_id()));
+        assert!(!pins.allows("vault", &stranger.to_peer_id()));
+        assert!(pins.allows("music", &stranger.to_peer_id()));
+        assert!(!pins.allows_origin("vault", None));
+        assert!(pins.allows_origin("music", None));
+
+        assert!(KeyPins::from_config(&[observer("vault", Some(vec![]))]).is_err());
+        assert!(KeyPins::from_config(&[observer("vault", Some(vec!["not-a-key".to_string()]))]).is_err());
+    }
+}
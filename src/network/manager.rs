@@ -1,58 +1,872 @@
-use crate::network::syndactyl_p2p::{SyndactylP2P, SyndactylP2PEvent};
-use crate::network::transfer::{FileTransferTracker, generate_first_chunk, CHUNK_SIZE};
+use crate::network::syndactyl_p2p::{SyndactylP2P, SyndactylP2PEvent, HEARTBEAT_TOPIC};
+use crate::network::transfer::{FileTransferTracker, TrackerLimits, EvictedTransfer, generate_first_chunk, segment_hash, CHUNK_SIZE, MAX_CHUNK_SIZE};
+use crate::network::served::{Served, ServedVersion};
+use crate::network::tuning::{self, TransferTuning};
+use crate::network::capabilities::{self, PeerCapabilities};
 use crate::network::syndactyl_behaviour::SyndactylEvent;
-use crate::core::models::{FileTransferRequest, FileTransferResponse, FileChunkRequest, FileEventMessage};
-use crate::core::config::{Config, ObserverConfig};
-use crate::core::{file_handler, auth};
+use crate::network::scheduler::{FairQueue, Priority, PriorityQueue, DEFAULT_MAX_INCOMING_PER_PEER, DEFAULT_MAX_QUEUED_PER_PEER};
+use crate::network::wire;
+use crate::network::transport;
+use crate::network::bootstrap;
+use crate::network::discovery::Discovery;
+use crate::network::bulk::{self, BulkEvent, BulkRequest, ServedFile};
+use crate::network::peer_stats::{self, PeerStats};
+use crate::network::congestion::CongestionControl;
+use crate::network::distribution::{self, Role, MAX_HELPERS};
+use crate::network::status_feed::StatusFeed;
+use crate::network::tray_feed::{Activity, TrayFeed};
+use crate::network::key_pins::KeyPins;
+use crate::network::security::ConnectionPolicy;
+use crate::network::anti_entropy;
+use crate::network::heads::{self, Comparison, HeadsExchange};
+use crate::network::peer_diff::{DiffReport, PeerDiff};
+use crate::network::peer_resources::{self, PeerResources};
+use crate::network::chunk_codec;
+use crate::network::nat_pmp;
+use crate::network::chunk_cache::{self, CachedChunk, ChunkCache};
+#[cfg(feature = "fault-injection")]
+use crate::network::faults::{ChunkFault, FaultInjector, REORDER_HOLD};
+use crate::bridge::BridgeSet;
+use crate::node::Subsystems;
+use crate::core::models::{Capabilities, FileTransferRequest, FileTransferResponse, FileChunkRequest, CancelTransferRequest, EventType, FileEventMessage, AppendInfo, Heartbeat, ObserverDigest, PeerLoad, RangeReadRequest, RangeReadResponse, SyndactylResponse, TransferError, TransferErrorKind, TombstoneEntry, TreeEntry, TreeNodeRequest, TreeNodeResponse};
+use crate::core::config::{Config, ObserverConfig, Owner};
+use crate::core::tenant;
+use crate::core::setup;
+use crate::core::log_throttle::LogThrottle;
+use crate::core::observer;
+use crate::core::lifecycle::{self, Lifecycles, Transition};
+use crate::core::file_handler;
+use crate::core::auth;
+use crate::core::audit::AuditLog;
+use crate::core::storage::{self, StorageBackend};
+use crate::core::transaction::{TransactionTracker, TRANSACTION_TIMEOUT};
+use crate::core::state::StateStore;
+#[cfg(feature = "metrics")]
+use crate::core::metrics::{self, Metrics};
+#[cfg(feature = "otlp")]
+use crate::core::otlp::MetricsExport;
+use crate::core::schedule::{RateLimiter, Schedule, TransferPolicy};
+use crate::core::power::{PowerMonitor, PowerStatus};
+use crate::core::catalog::{CatalogRequest, RemoteEntry, SharedCatalog};
+use crate::core::path_encoding::SharedLocalNames;
+use crate::core::profile::{self, Profile};
+use crate::core::sync_groups::{self, SyncGroups};
+use crate::core::sync_id::{sync_id, traced};
+use crate::core::deletion_guard::DeletionGuard;
+use crate::core::auth_failures::{AuthFailure, AuthFailures};
+use crate::core::tombstone::{self, unix_now};
+use crate::core::seen_events;
+use crate::core::event_order;
+use crate::core::removed_observers;
+use crate::core::address_book;
+use crate::core::disk_space::{self, DiskSpaceAlert};
+use crate::core::secret::Secret;
+use crate::core::secret_guard::SecretGuard;
+use crate::core::in_use::{self, InUsePolicy, Replaced};
+use crate::core::hash_pool::{self, HashPool};
+use crate::core::conflict::ConflictResolver;
+use crate::core::merge::ThreeWayMerge;
+use crate::core::version_store::VersionStore;
+use crate::core::merkle::{self, IndexedFile, MerkleTree};
+use crate::core::journal::{self, Intent, Journal, JournaledStorage, SharedJournal};
+use crate::control::{self, ControlCommand, ControlRequest, ControlResponse, DaemonStatus, FileStatus, PathStatus, Preview, SyncState};
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use libp2p::PeerId;
+use libp2p::request_response::OutboundRequestId;
 use tokio::sync::mpsc as tokio_mpsc;
 use futures::StreamExt;
-use tracing::{info, error, warn};
+use tracing::{debug, info, info_span, error, warn};
+
+/// Maximum queued serve requests handled per event loop iteration
+const SERVE_BATCH_SIZE: usize = 8;
+
+/// Maximum queued announcements started per event loop iteration
+const INTAKE_BATCH_SIZE: usize = 16;
+
+/// How often observer tree roots are gossiped
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay between a local change and the heartbeat announcing the new root
+/// Batches bursts of changes into one heartbeat while still letting peers
+/// notice divergence within seconds.
+const HEARTBEAT_DEBOUNCE: Duration = Duration::from_secs(3);
+
+/// Failed chunk requests a download survives before it is abandoned
+const MAX_CHUNK_RETRIES: u32 = 3;
+
+/// Quiet time after a watcher overflow before the missed subtree is rescanned
+/// Overflows come in bursts; rescanning mid-burst would only overflow again.
+const STALE_RESCAN_DELAY: Duration = Duration::from_secs(2);
+
+/// Bytes `syndactyl peek` reads when no size is given
+const DEFAULT_PEEK_BYTES: u32 = 4096;
+
+/// Other announcers of a version kept per download, to fall back on if its source fails
+const MAX_FALLBACK_SOURCES: usize = 8;
+
+/// How often a peer's heartbeats refresh when it was last seen syncing an observer
+const REPLICA_REFRESH: Duration = Duration::from_secs(60 * 60);
+
+/// A download request held back by a sync window or rate limit
+enum PacedRequest {
+    /// The request starting a download, answered with its first chunk
+    File(FileTransferRequest),
+    Chunk(FileChunkRequest),
+}
+
+/// A file or chunk request waiting to be served
+enum ServeRequest {
+    FileTransfer(FileTransferRequest, libp2p::request_response::ResponseChannel<SyndactylResponse>),
+    FileChunk(FileChunkRequest, libp2p::request_response::ResponseChannel<SyndactylResponse>),
+    RangeRead(RangeReadRequest, libp2p::request_response::ResponseChannel<SyndactylResponse>),
+}
+
+impl ServeRequest {
+    /// Observer and path this request refers to
+    fn target(&self) -> (&str, &str) {
+        match self {
+            ServeRequest::FileTransfer(request, _) => (&request.observer, &request.path),
+            ServeRequest::FileChunk(request, _) => (&request.observer, &request.path),
+            ServeRequest::RangeRead(request, _) => (&request.observer, &request.path),
+        }
+    }
+
+    /// Answer the request with an error instead of serving it
+    fn into_error(self, kind: TransferErrorKind) -> (libp2p::request_response::ResponseChannel<SyndactylResponse>, TransferError) {
+        let (observer, path, requested_hash, channel) = match self {
+            ServeRequest::FileTransfer(request, channel) => (request.observer, request.path, request.hash, channel),
+            ServeRequest::FileChunk(request, channel) => (request.observer, request.path, request.hash, channel),
+            ServeRequest::RangeRead(request, channel) => (request.observer, request.path, request.hash, channel),
+        };
+        (channel, TransferError { observer, path, requested_hash, kind })
+    }
+}
+
+/// A chunk request waiting for its response
+struct ChunkInFlight {
+    peer: PeerId,
+    /// (observer, path) of the download
+    key: (String, String),
+    offset: u64,
+    /// Bytes asked for
+    len: u64,
+    sent: Instant,
+}
+
+/// A `syndactyl peek` waiting for its range read
+struct PendingPeek {
+    peer: PeerId,
+    request: RangeReadRequest,
+    lines: Option<usize>,
+    /// Already retried against the version the peer said it holds
+    retried: bool,
+    reply: tokio::sync::oneshot::Sender<ControlResponse>,
+}
+
+/// A download its source refused as busy, to ask for again once the hint passes
+struct BusyRetry {
+    peer: PeerId,
+    hash: String,
+    size: u64,
+    at: Instant,
+}
+
+/// A download written aside until the local copy it replaces is no longer in use
+struct InUseRetry {
+    /// Version to record as applied once it is in place, if known
+    hash: Option<String>,
+    /// Attempts made so far, indexing `in_use::RETRY_DELAYS`
+    attempt: usize,
+    at: Instant,
+}
+
+/// A `syndactyl diff` waiting for a peer's listings
+struct PendingDiff {
+    peer: PeerId,
+    /// Walks still in progress, by observer
+    sessions: HashMap<String, PeerDiff>,
+    reports: Vec<DiffReport>,
+    reply: tokio::sync::oneshot::Sender<ControlResponse>,
+}
+
+/// An observer whose watcher dropped events, until a rescan has caught up
+struct StaleObserver {
+    /// Wire paths of the subtrees still to rescan ("" is the whole observer)
+    subtrees: BTreeSet<String>,
+    /// When the watcher last reported dropping events
+    reported: Instant,
+    rescan: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// A tree reconciliation with one peer for one observer
+struct Reconciliation {
+    /// Peer's root when it started
+    root: String,
+    started: Instant,
+    /// Differing files found to be identical on disk, e.g. restored from a backup
+    matched_files: u64,
+    matched_bytes: u64,
+    /// Differing files handed on to be fetched
+    fetched_files: u64,
+    /// Entries of the peer's tree listed so far, counting every page
+    listed_entries: u64,
+    /// Differing files of the peer's size whose local copy is being hashed
+    checking_files: u64,
+}
+
+/// A local file hashed on the hash pool, and what the hash was wanted for
+struct HashChecked {
+    observer: String,
+    path: String,
+    purpose: CheckPurpose,
+    hash: std::io::Result<String>,
+}
+
+/// Why a local file was hashed off the event loop
+enum CheckPurpose {
+    /// A reconciled peer lists it with `entry`: skipped if identical, fetched otherwise
+    Reconcile { peer: PeerId, entry: TreeEntry },
+    /// Announcing `msg` waits on whether its first `offset` bytes still hash to `expected`
+    Append { msg: String, offset: u64, expected: String, check: u64 },
+    /// Requests for the version of `size` and `modified_nanos` wait in `parked_serves`
+    Serve { size: u64, modified_nanos: u128 },
+}
+
+/// How far a download has got in requesting its chunks
+struct ChunkCursor {
+    /// First offset not requested yet
+    next_offset: u64,
+    total_size: u64,
+    /// Offsets whose request failed, requested again before any new ones
+    retry: Vec<u64>,
+    /// Offsets whose compressed chunk failed to decode, requested raw
+    raw: HashSet<u64>,
+    failures: u32,
+    /// Hash to request chunks of until the tracker knows the announced one
+    hash: String,
+    /// Bytes asked for per request, from the observer's transfer tuning
+    chunk_size: u64,
+}
+
+/// A download streaming over the bulk protocol instead of chunk requests
+struct BulkDownload {
+    peer: PeerId,
+    /// Every byte before this offset has been received
+    received_to: u64,
+    task: tokio::task::AbortHandle,
+}
+
+impl ChunkCursor {
+    fn take_next(&mut self) -> Option<u64> {
+        if let Some(offset) = self.retry.pop() {
+            return Some(offset);
+        }
+        if self.next_offset >= self.total_size {
+            return None;
+        }
+        let offset = self.next_offset;
+        self.next_offset += self.chunk_size;
+        Some(offset)
+    }
+}
 
 /// Manages the P2P network, file transfers, and observer event integration
 pub struct NetworkManager {
     p2p: SyndactylP2P,
     observer_configs: HashMap<String, ObserverConfig>,
+    storages: HashMap<String, Arc<dyn StorageBackend>>,
     connected_peers: Vec<PeerId>,
+    /// The selected ways of finding peers
+    discovery: Discovery,
+    /// Ping times and recent throughput per connected peer
+    peer_stats: PeerStats,
     transfer_tracker: FileTransferTracker,
     event_receiver: tokio_mpsc::Receiver<SyndactylP2PEvent>,
+    audit_log: Option<AuditLog>,
+    log_throttle: LogThrottle,
+    /// (observer, remote identity) pairs already reported as misconfigured
+    mismatched_observers: HashSet<(String, String)>,
+    serve_queue: FairQueue<PeerId, ServeRequest>,
+    /// Peer each in-progress download is being pulled from, keyed by (observer, path)
+    download_sources: HashMap<(String, String), PeerId>,
+    /// Outstanding chunk requests allowed per peer
+    congestion: CongestionControl,
+    /// Chunk requests waiting for a response
+    chunk_requests: HashMap<OutboundRequestId, ChunkInFlight>,
+    /// Next chunks to request for each in-progress download, keyed by (observer, path)
+    chunk_cursors: HashMap<(String, String), ChunkCursor>,
+    /// Opens bulk transfer streams and accepts them from peers
+    bulk_control: libp2p::stream::Control,
+    /// Whether downloads stream over the bulk protocol when the peer supports it
+    bulk_downloads_enabled: bool,
+    /// Events from bulk transfer tasks
+    bulk_tx: tokio_mpsc::Sender<BulkEvent>,
+    bulk_rx: Option<tokio_mpsc::Receiver<BulkEvent>>,
+    /// Hashes local files the event loop would otherwise read in full
+    hash_pool: HashPool,
+    /// Files hashed on `hash_pool`
+    hash_checks_tx: tokio_mpsc::Sender<HashChecked>,
+    hash_checks_rx: Option<tokio_mpsc::Receiver<HashChecked>>,
+    /// Where NAT-PMP mapping reports external addresses; taken when the first port is listened on
+    nat_pmp_tx: Option<tokio_mpsc::Sender<nat_pmp::AddressChange>>,
+    nat_pmp_rx: Option<tokio_mpsc::Receiver<nat_pmp::AddressChange>>,
+    /// The mapping task, awaited on shutdown while it deletes the mapping
+    nat_pmp_task: Option<tokio::task::JoinHandle<()>>,
+    /// Latest append check per (observer, path) whose announcement is held for it
+    pending_appends: HashMap<(String, String), u64>,
+    append_checks: u64,
+    /// Downloads streaming over the bulk protocol, keyed by (observer, path)
+    bulk_downloads: HashMap<(String, String), BulkDownload>,
+    /// Peers that don't speak the bulk protocol; downloads from them use chunk requests
+    bulk_unsupported: HashSet<PeerId>,
+    /// Bulk streams each peer has us serving
+    bulk_serving: HashMap<PeerId, usize>,
+    /// What we support, sent to each peer that connects
+    capabilities: Capabilities,
+    /// What connected peers support
+    peer_capabilities: PeerCapabilities,
+    /// Load and free space connected peers advertise
+    peer_resources: PeerResources,
+    /// Transfers served at once before new ones are refused, 0 for no limit
+    max_serving_transfers: u32,
+    /// Downloads waiting for a busy source, keyed by (observer, path)
+    busy_retries: HashMap<(String, String), BusyRetry>,
+    /// Downloads waiting to replace a local copy that is in use, keyed by (observer, path)
+    in_use_retries: HashMap<(String, String), InUseRetry>,
+    /// Capabilities requests awaiting an answer
+    capability_requests: HashSet<OutboundRequestId>,
+    /// Peers we dialed, to send our tree roots once their capabilities arrive
+    heads: HeadsExchange,
+    /// Peers besides the announcing one each download pulls chunks from, keyed by (observer, path)
+    download_helpers: HashMap<(String, String), HashSet<PeerId>>,
+    /// Peers that announced the same version after each download started, tried in turn if
+    /// its source fails, keyed by (observer, path)
+    download_fallbacks: HashMap<(String, String), Vec<PeerId>>,
+    /// Part each observer in a distribution channel plays
+    roles: HashMap<String, Role>,
+    /// DHT key we advertise for each distributed file we hold, keyed by (observer, path)
+    provided: HashMap<(String, String), String>,
+    /// Downloads waiting on a provider lookup, by DHT key
+    provider_lookups: HashMap<String, (String, String)>,
+    /// Peers currently pulling each (observer, path) from us
+    serving_peers: HashMap<(String, String), HashSet<PeerId>>,
+    /// Last hashed version of each file being served, keyed by (observer, path)
+    served_versions: HashMap<(String, String), ServedVersion>,
+    /// Requests held until the version they ask for is hashed, by (observer, path)
+    parked_serves: HashMap<(String, String), Vec<(PeerId, ServeRequest)>>,
+    /// Chunks recently sent to peers, so a file pulled by several peers is read once
+    chunk_cache: ChunkCache,
+    /// Refuses to serve files that look like credentials, when configured
+    secret_guard: Option<SecretGuard>,
+    /// Merkle trees built from the file index, dropped when an observer's files change
+    merkle_trees: HashMap<String, MerkleTree>,
+    /// Tree reconciliations under way, by peer and observer
+    reconciling: HashMap<(PeerId, String), Reconciliation>,
+    /// How often a random sample of current versions is re-announced; None when disabled
+    anti_entropy_interval: Option<Duration>,
+    /// Files re-announced per observer each time
+    anti_entropy_sample: usize,
+    /// Observers peers told us they no longer sync, by heartbeat or a NotServing error
+    departed: HashSet<(PeerId, String)>,
+    /// When to send a heartbeat ahead of the regular interval, after local changes or a new peer
+    heartbeat_due: Option<Instant>,
+    /// Per-file sync states for file manager overlay icons
+    status_feed: StatusFeed,
+    /// Peer count and observer activity for tray icons
+    tray_feed: TrayFeed,
+    #[cfg(feature = "fault-injection")]
+    faults: FaultInjector,
+    /// Received chunks held back by injected delays or reordering, with when to
+    /// deliver them and whether a later chunk may release them early
+    #[cfg(feature = "fault-injection")]
+    held_chunks: Vec<(Instant, bool, PeerId, FileTransferResponse)>,
+    /// Incoming transactions waiting for all of their files to be staged
+    transactions: TransactionTracker,
+    /// Transaction each in-progress download belongs to, keyed by (observer, path)
+    download_transactions: HashMap<(String, String), String>,
+    /// Persisted state (bandwidth accounting etc.)
+    state: StateStore,
+    /// Write-ahead log of file operations, shared with the journaled storages
+    journal: SharedJournal,
+    #[cfg(feature = "metrics")]
+    metrics_file: Option<std::path::PathBuf>,
+    /// Where to serve metrics over HTTP, and the page served there
+    #[cfg(feature = "metrics")]
+    metrics_http: Option<(std::net::SocketAddr, Arc<RwLock<String>>)>,
+    /// The same metrics, exported to an OpenTelemetry collector
+    #[cfg(feature = "otlp")]
+    otlp_metrics: Option<MetricsExport>,
+    /// Where to serve the control API over gRPC
+    #[cfg(feature = "grpc")]
+    grpc: Option<std::net::SocketAddr>,
+    /// Sync windows per observer
+    schedules: HashMap<String, Schedule>,
+    /// Chunk size and parallelism per observer
+    transfer_tuning: HashMap<String, TransferTuning>,
+    /// Observers whose watcher dropped events, possibly out of date until rescanned
+    stale_observers: HashMap<String, StaleObserver>,
+    /// Announcements waiting for room in the transfer tracker, metadata and small files first
+    incoming: PriorityQueue<(String, String), PeerId, (PeerId, FileEventMessage)>,
+    /// Announcements held back by an active sync window, latest per (observer, path)
+    deferred_events: HashMap<(String, String), (PeerId, FileEventMessage)>,
+    /// Download requests waiting for a sync window to end or for rate limiting, with the earliest send time
+    pending_chunks: HashMap<(String, String), (PeerId, PacedRequest, Instant)>,
+    /// When each peer's rate-limited downloads may next request bytes
+    download_rates: RateLimiter<PeerId>,
+    power: PowerMonitor,
+    /// Last power reading, when power awareness is configured
+    power_status: Option<PowerStatus>,
+    /// Control socket path, and the file holding the token its requests must carry
+    control_socket: Option<(std::path::PathBuf, std::path::PathBuf)>,
+    /// Tenant user the socket, token and received files are handed to
+    owner: Option<Owner>,
+    /// Requests from the control socket and command-accepting bridges
+    control_tx: tokio_mpsc::Sender<ControlCommand>,
+    control_rx: Option<tokio_mpsc::Receiver<ControlCommand>>,
+    /// Where rescans feed their announcements, alongside the watcher threads
+    local_events: Option<tokio_mpsc::Sender<String>>,
+    /// Downloads paused by hand for every observer
+    paused_all: bool,
+    /// Observers whose downloads are paused by hand
+    paused_observers: HashSet<String>,
+    /// Lifecycle state of each watched observer
+    lifecycles: Lifecycles,
+    /// Rescans started by request or after a state loss, with the observers they cover
+    rescans: Vec<(Vec<String>, tokio::task::JoinHandle<()>)>,
+    /// Holds local deletions back when too many happen at once
+    deletion_guard: DeletionGuard,
+    /// Failed authentications per peer, alerted on past a threshold
+    auth_failures: AuthFailures,
+    /// How long tombstones of deleted files are kept
+    tombstone_retention: Duration,
+    /// How long unacknowledged tombstones are kept at most
+    tombstone_max_retention: Duration,
+    /// Set by a shutdown request; the event loop stops after the current event
+    shutdown_requested: bool,
+    /// Observers whose directory is missing, e.g. on an unmounted drive
+    /// Downloads into them wait, so files don't land where the drive should be.
+    unavailable_observers: HashSet<String>,
+    /// Observers whose volume is below its free space threshold; downloads into them wait
+    low_space_observers: HashSet<String>,
+    /// Configured sync profiles, the one used when none is picked, and the one in effect
+    profiles: HashMap<String, Profile>,
+    default_profile: Option<String>,
+    profile: Profile,
+    /// Peers each observer is shared with
+    sync_groups: SyncGroups,
+    /// Public keys of the only peers pinned observers sync with
+    key_pins: KeyPins,
+    /// Peers and transports allowed to stay connected, when strict_security is on
+    connection_policy: Option<ConnectionPolicy>,
+    /// External systems verified announcements are forwarded to
+    bridges: BridgeSet,
+    /// Remote trees of on-demand observers, shared with their mounts
+    catalog: SharedCatalog,
+    catalog_dirty: bool,
+    /// Local spellings of paths that differ from their wire form
+    local_names: SharedLocalNames,
+    /// Fetches and range reads from mounts
+    catalog_rx: Option<tokio_mpsc::Receiver<CatalogRequest>>,
+    /// Mounts waiting for each file being fetched
+    fetch_waiters: HashMap<(String, String), Vec<std::sync::mpsc::Sender<Result<(), String>>>>,
+    /// Outstanding range reads
+    range_reads: HashMap<OutboundRequestId, std::sync::mpsc::Sender<Result<Vec<u8>, String>>>,
+    /// Outstanding previews of remote files
+    peeks: HashMap<OutboundRequestId, PendingPeek>,
+    /// Dry-run comparisons with peers, by number
+    diffs: HashMap<u64, PendingDiff>,
+    next_diff: u64,
+    /// Tree listings requested for a comparison, with its number and observer
+    diff_requests: HashMap<OutboundRequestId, (u64, String)>,
+    /// Mounted on-demand observers; dropping a session unmounts it
+    #[cfg(feature = "fuse")]
+    _mounts: Vec<fuser::BackgroundSession>,
 }
 
 impl NetworkManager {
     /// Create a new NetworkManager from configuration
-    pub async fn new(config: Config) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Metrics, bridges and mounts are started only when registered in `subsystems`.
+    pub async fn new(config: Config, power: PowerMonitor, subsystems: Subsystems, audit_log: Option<AuditLog>) -> Result<Self, Box<dyn std::error::Error>> {
+        let control_socket = config.control_socket_path().ok().zip(config.control_token_path().ok());
+        let owner = config.owner;
+        let state_path = config.state_path()?;
+        let journal_path = config.journal_path()?;
+        let versions_dir = config.versions_dir()?;
+        let sync_groups = SyncGroups::from_config(&config.observers, config.peer_groups.as_ref().unwrap_or(&HashMap::new()))?;
+        let key_pins = KeyPins::from_config(&config.observers)?;
+        let mut state = StateStore::open(&state_path)?;
+        match state.recovered_from() {
+            Some(moved) => error!(
+                path = %state_path.display(),
+                moved_to = %moved.display(),
+                "Daemon state was corrupt and has been set aside; rebuilding it from a rescan and peers' trees"
+            ),
+            None => info!(path = %state_path.display(), "Loaded daemon state"),
+        }
+        // Observers dropped since the last run are announced in heartbeats for a while
+        let mut removed = state.state().removed_observers.clone();
+        for observer in removed.update(config.observers.iter().map(|obs| obs.name.as_str()), unix_now()) {
+            info!(observer = %observer, "Observer removed from the configuration, telling peers to stop expecting it");
+        }
+        if removed != state.state().removed_observers {
+            state.state_mut().removed_observers = removed;
+        }
+        // Deletions held when the daemon stopped stay held until confirmed or discarded
+        let mut held_deletions = state.state().held_deletions.clone();
+        held_deletions.retain(|observer, _| config.observers.iter().any(|obs| &obs.name == observer));
+        for (observer, held) in &held_deletions {
+            warn!(observer = %observer, count = held.len(), "Deletions are still held from before the restart; confirm or discard them");
+        }
+        let deletion_guard = DeletionGuard::new(&config.deletion_guard.clone().unwrap_or_default()).with_held(held_deletions);
+
+        let keypair_path = config.keypair_path();
         let network_config = config.network
             .ok_or("Network configuration is required")?;
+        if network_config.outbound_only == Some(true) {
+            if network_config.upnp == Some(true) || network_config.nat_pmp == Some(true) {
+                return Err("upnp and nat_pmp can't be combined with outbound_only: there is no listening port to forward".into());
+            }
+            if !network_config.bootstrap_peers.iter().any(bootstrap::is_configured) {
+                return Err("outbound_only needs at least one bootstrap peer to dial".into());
+            }
+        }
+        transport::validate(&network_config)?;
+        bootstrap::validate(&network_config.bootstrap_peers)?;
+        let connection_policy = ConnectionPolicy::from_config(&network_config, &config.observers, &key_pins)?;
+        if connection_policy.is_some() {
+            info!("[syndactyl] Strict security: only pinned and bootstrap peers may connect");
+        }
+        let max_queued_per_peer = network_config.max_queued_requests_per_peer
+            .unwrap_or(DEFAULT_MAX_QUEUED_PER_PEER);
+        let tracker_limits = {
+            let configured = network_config.transfer_limits.clone().unwrap_or_default();
+            let defaults = TrackerLimits::default();
+            TrackerLimits {
+                max_transfers: configured.max_transfers.unwrap_or(defaults.max_transfers),
+                max_transfers_per_peer: configured.max_transfers_per_peer.unwrap_or(defaults.max_transfers_per_peer),
+                max_buffered_bytes: configured.max_buffered_mb.map(|mb| mb * 1024 * 1024).unwrap_or(defaults.max_buffered_bytes),
+                max_buffered_bytes_per_peer: configured.max_buffered_mb_per_peer.map(|mb| mb * 1024 * 1024).unwrap_or(defaults.max_buffered_bytes_per_peer),
+                idle_ttl: configured.idle_ttl_secs.map(Duration::from_secs).unwrap_or(defaults.idle_ttl),
+            }
+        };
+
+        let serve_cache_bytes = network_config.serve_cache_mb
+            .map(|mb| mb * 1024 * 1024)
+            .unwrap_or(chunk_cache::DEFAULT_CAPACITY);
+        let anti_entropy_interval = match network_config.anti_entropy_interval_secs {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(anti_entropy::DEFAULT_INTERVAL),
+        };
+        let anti_entropy_sample = network_config.anti_entropy_sample.unwrap_or(anti_entropy::DEFAULT_SAMPLE_SIZE);
+        let bulk_downloads_enabled = network_config.bulk_transfer != Some(false);
+        let nat_pmp = network_config.nat_pmp == Some(true);
+        if nat_pmp && !nat_pmp::GATEWAY_LOOKUP {
+            return Err("nat_pmp is not supported on this platform: syndactyl can't find the default gateway here".into());
+        }
+
+        let local_names: SharedLocalNames = Arc::new(RwLock::new(state.state().local_names.clone()));
 
         // Build a map of observer name -> ObserverConfig for authentication and file operations
         let mut observer_configs: HashMap<String, ObserverConfig> = HashMap::new();
+        let mut storages: HashMap<String, Arc<dyn StorageBackend>> = HashMap::new();
+        let mut schedules: HashMap<String, Schedule> = HashMap::new();
+        let mut transfer_tuning: HashMap<String, TransferTuning> = HashMap::new();
         for obs in &config.observers {
             observer_configs.insert(obs.name.clone(), obs.clone());
+            if obs.announce_only == Some(true) {
+                info!(observer = %obs.name, "Observer is announce-only, forwarding events to bridges without storing files");
+                continue;
+            }
+            InUsePolicy::from_config(obs.in_use.as_deref())
+                .map_err(|e| format!("Invalid configuration for observer {}: {}", obs.name, e))?;
+            let storage = storage::for_observer(obs, local_names.clone(), config.owner)
+                .map_err(|e| format!("Invalid configuration for observer {}: {}", obs.name, e))?;
+            storages.insert(obs.name.clone(), storage);
+            let tuning = tuning::resolve(network_config.transfer_tuning.as_ref(), obs.transfer_tuning.as_ref())
+                .map_err(|e| format!("Invalid transfer_tuning for observer {}: {}", obs.name, e))?;
+            transfer_tuning.insert(obs.name.clone(), tuning);
+            let windows = obs.schedule.as_ref().or(config.schedule.as_ref());
+            let schedule = Schedule::from_config(windows.map(|w| w.as_slice()).unwrap_or(&[]))
+                .map_err(|e| format!("Invalid schedule for observer {}: {}", obs.name, e))?;
+            if !schedule.is_empty() {
+                info!(observer = %obs.name, "Observer has sync windows configured");
+            }
+            schedules.insert(obs.name.clone(), schedule);
+            if obs.at_rest_key.is_some() {
+                info!(observer = %obs.name, "Observer stores files encrypted at rest");
+            }
+            if obs.compress_history == Some(true) && cfg!(not(feature = "compression")) {
+                return Err(format!("Observer {} compresses its history but syndactyl was built without the compression feature", obs.name).into());
+            }
+            if obs.mount.is_some() && cfg!(not(feature = "fuse")) {
+                return Err(format!("Observer {} has a mount but syndactyl was built without the fuse feature", obs.name).into());
+            }
         }
+        if config.metrics_file.is_some() && cfg!(not(feature = "metrics")) {
+            return Err("metrics_file is set but syndactyl was built without the metrics feature".into());
+        }
+        if config.metrics_http.is_some() && cfg!(not(feature = "metrics")) {
+            return Err("metrics_http is set but syndactyl was built without the metrics feature".into());
+        }
+        if config.otlp.is_some() && cfg!(not(feature = "otlp")) {
+            return Err("otlp is set but syndactyl was built without the otlp feature".into());
+        }
+        if config.grpc.is_some() && cfg!(not(feature = "grpc")) {
+            return Err("grpc is set but syndactyl was built without the grpc feature".into());
+        }
+        #[cfg(feature = "grpc")]
+        let grpc = match &config.grpc {
+            Some(grpc) => Some(crate::grpc::listen_addr(grpc)?),
+            None => None,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics_http = match config.metrics_http.as_ref().filter(|_| subsystems.metrics) {
+            Some(http) => Some((crate::core::listen::resolve("Metrics endpoint", &http.listen, http.public == Some(true))?, Arc::new(RwLock::new(String::new())))),
+            None => None,
+        };
+        #[cfg(feature = "otlp")]
+        let otlp_metrics = match config.otlp.as_ref().filter(|_| subsystems.metrics) {
+            Some(otlp) => MetricsExport::start(otlp)?,
+            None => None,
+        };
+
+        // Finish or undo file operations a crash interrupted, before anything else touches the files
+        let mut journal = Journal::open(&journal_path)?;
+        let (resolved, removed) = journal::recover(&mut journal, &storages);
+        if resolved > 0 || removed > 0 {
+            warn!(interrupted = resolved, leftovers = removed, "Recovered from an interrupted run");
+        }
+        let journal: SharedJournal = Arc::new(Mutex::new(journal));
+        let storages: HashMap<String, Arc<dyn StorageBackend>> = storages.into_iter()
+            .map(|(name, storage)| {
+                let journaled: Arc<dyn StorageBackend> = Arc::new(JournaledStorage::new(storage, &name, journal.clone()));
+                (name, journaled)
+            })
+            .collect();
+
+        let mut roles = HashMap::new();
+        for obs in observer_configs.values() {
+            if let Some(role) = Role::of(obs)? {
+                info!(observer = %obs.name, role = ?role, "Observer is part of a distribution channel");
+                roles.insert(obs.name.clone(), role);
+            }
+        }
+
+        // Text files of observers with merge_extensions are merged against their last common version
+        let mut transfer_tracker = FileTransferTracker::with_limits(tracker_limits);
+        let merge_extensions: HashMap<String, Vec<String>> = observer_configs.values()
+            .filter_map(|obs| Some((obs.name.clone(), obs.merge_extensions.clone()?)))
+            .filter(|(_, extensions)| !extensions.is_empty())
+            .collect();
+        if !merge_extensions.is_empty() {
+            for (name, extensions) in &merge_extensions {
+                info!(observer = %name, extensions = ?extensions, "Merging concurrent text edits");
+                transfer_tracker.register_conflict_resolver(Some(name), Arc::new(ThreeWayMerge::new(extensions.clone())));
+            }
+            let compressed = observer_configs.values()
+                .filter(|obs| obs.compress_history == Some(true))
+                .map(|obs| obs.name.clone())
+                .collect();
+            transfer_tracker.set_version_store(Arc::new(VersionStore::new(&versions_dir, merge_extensions).with_compression(compressed)));
+        }
+
+        // A profile picked at runtime survives restarts, as long as it is still configured
+        let known_observers: HashSet<String> = observer_configs.keys().cloned().collect();
+        let profiles = profile::load_profiles(&config.profiles.clone().unwrap_or_default(), &known_observers)?;
+        let profile = profile::initial(&profiles, state.state().active_profile.as_deref(), config.default_profile.as_deref())?;
+        if state.state().active_profile.is_some() && profile.name.is_empty() {
+            state.state_mut().active_profile = None;
+        }
+        if !profile.name.is_empty() {
+            info!(profile = %profile.name, "Using sync profile");
+        }
+
+        let catalog: SharedCatalog = Arc::new(RwLock::new(state.state().catalog.clone()));
+        let (catalog_tx, catalog_rx) = tokio_mpsc::channel::<CatalogRequest>(32);
+        #[cfg(feature = "fuse")]
+        let mounts = {
+            let mut mounts = Vec::new();
+            for obs in config.observers.iter().filter(|obs| obs.mount.is_some() && subsystems.mounts) {
+                let mountpoint = std::path::PathBuf::from(obs.mount.as_deref().unwrap_or_default());
+                let session = crate::mount::mount(&obs.name, std::path::Path::new(&obs.path), &mountpoint, catalog.clone(), catalog_tx.clone())
+                    .map_err(|e| format!("Failed to mount observer {} at {}: {}", obs.name, mountpoint.display(), e))?;
+                mounts.push(session);
+            }
+            mounts
+        };
 
         // Create P2P node
         let (event_sender, event_receiver) = tokio_mpsc::channel(32);
-        let p2p = SyndactylP2P::new(network_config, event_sender).await?;
+        let discovery = Discovery::from_config(&network_config, Instant::now())?;
+        info!(backends = ?discovery.names(), "[syndactyl] Peer discovery");
+        let mut p2p = SyndactylP2P::new(network_config, keypair_path, event_sender).await?;
+        // Every observer's own topic, which peers sharing it with a group announce on
+        for obs in observer_configs.values() {
+            p2p.subscribe_topic(&sync_groups::topic(&obs.name, obs.shared_secret.as_ref().map(Secret::expose)))?;
+        }
+
+        let bulk_control = p2p.swarm.behaviour().stream.new_control();
+        let (bulk_tx, bulk_rx) = tokio_mpsc::channel::<BulkEvent>(32);
+        let (hash_checks_tx, hash_checks_rx) = tokio_mpsc::channel::<HashChecked>(32);
+        let (nat_pmp_tx, nat_pmp_rx) = tokio_mpsc::channel::<nat_pmp::AddressChange>(4);
+
+        let (control_tx, control_rx) = tokio_mpsc::channel::<ControlCommand>(8);
+        let bridge_configs = config.bridges.as_deref().filter(|_| subsystems.bridges).unwrap_or(&[]);
+        let bridges = BridgeSet::start(bridge_configs, &p2p.peer_id().to_string(), control_tx.clone())?;
 
         Ok(Self {
             p2p,
             observer_configs,
+            storages,
+            journal,
             connected_peers: Vec::new(),
-            transfer_tracker: FileTransferTracker::new(),
+            discovery,
+            peer_stats: PeerStats::new(),
+            transfer_tracker,
             event_receiver,
+            audit_log,
+            log_throttle: LogThrottle::new(&config.logging.unwrap_or_default()),
+            mismatched_observers: HashSet::new(),
+            serve_queue: FairQueue::new(max_queued_per_peer),
+            download_sources: HashMap::new(),
+            congestion: CongestionControl::new(),
+            chunk_requests: HashMap::new(),
+            chunk_cursors: HashMap::new(),
+            bulk_control,
+            bulk_downloads_enabled,
+            bulk_tx,
+            bulk_rx: Some(bulk_rx),
+            hash_pool: HashPool::new(hash_pool::HASH_WORKERS, power.clone()),
+            hash_checks_tx,
+            hash_checks_rx: Some(hash_checks_rx),
+            nat_pmp_tx: nat_pmp.then_some(nat_pmp_tx),
+            nat_pmp_rx: Some(nat_pmp_rx),
+            nat_pmp_task: None,
+            pending_appends: HashMap::new(),
+            append_checks: 0,
+            bulk_downloads: HashMap::new(),
+            bulk_unsupported: HashSet::new(),
+            bulk_serving: HashMap::new(),
+            capabilities: capabilities::local(bulk_downloads_enabled),
+            peer_capabilities: PeerCapabilities::default(),
+            peer_resources: PeerResources::default(),
+            max_serving_transfers: network_config.max_serving_transfers.unwrap_or(peer_resources::DEFAULT_MAX_SERVING_TRANSFERS),
+            busy_retries: HashMap::new(),
+            in_use_retries: HashMap::new(),
+            capability_requests: HashSet::new(),
+            heads: HeadsExchange::default(),
+            download_helpers: HashMap::new(),
+            download_fallbacks: HashMap::new(),
+            roles,
+            provided: HashMap::new(),
+            provider_lookups: HashMap::new(),
+            serving_peers: HashMap::new(),
+            served_versions: HashMap::new(),
+            parked_serves: HashMap::new(),
+            chunk_cache: ChunkCache::new(serve_cache_bytes),
+            secret_guard: SecretGuard::from_config(config.secret_guard.as_ref()),
+            merkle_trees: HashMap::new(),
+            reconciling: HashMap::new(),
+            anti_entropy_interval,
+            anti_entropy_sample,
+            departed: HashSet::new(),
+            heartbeat_due: None,
+            status_feed: StatusFeed::new(),
+            tray_feed: TrayFeed::new(),
+            #[cfg(feature = "fault-injection")]
+            faults: FaultInjector::new(),
+            #[cfg(feature = "fault-injection")]
+            held_chunks: Vec::new(),
+            transactions: TransactionTracker::new(),
+            download_transactions: HashMap::new(),
+            state,
+            #[cfg(feature = "metrics")]
+            metrics_file: config.metrics_file.filter(|_| subsystems.metrics).map(std::path::PathBuf::from),
+            #[cfg(feature = "metrics")]
+            metrics_http,
+            #[cfg(feature = "otlp")]
+            otlp_metrics,
+            #[cfg(feature = "grpc")]
+            grpc,
+            schedules,
+            transfer_tuning,
+            stale_observers: HashMap::new(),
+            incoming: PriorityQueue::new(DEFAULT_MAX_INCOMING_PER_PEER),
+            deferred_events: HashMap::new(),
+            pending_chunks: HashMap::new(),
+            download_rates: RateLimiter::default(),
+            power_status: power.refresh(),
+            power,
+            control_socket,
+            owner,
+            control_tx,
+            control_rx: Some(control_rx),
+            local_events: None,
+            paused_all: false,
+            paused_observers: HashSet::new(),
+            lifecycles: Lifecycles::default(),
+            rescans: Vec::new(),
+            deletion_guard,
+            auth_failures: AuthFailures::new(&config.auth_alerts.unwrap_or_default()),
+            tombstone_retention: config.tombstone_retention_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(tombstone::DEFAULT_RETENTION),
+            tombstone_max_retention: config.tombstone_max_retention_days
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+                .unwrap_or(tombstone::DEFAULT_MAX_RETENTION),
+            shutdown_requested: false,
+            unavailable_observers: HashSet::new(),
+            low_space_observers: HashSet::new(),
+            profiles,
+            default_profile: config.default_profile.clone(),
+            sync_groups,
+            key_pins,
+            connection_policy,
+            profile,
+            bridges,
+            catalog,
+            catalog_dirty: false,
+            local_names,
+            catalog_rx: Some(catalog_rx),
+            fetch_waiters: HashMap::new(),
+            range_reads: HashMap::new(),
+            peeks: HashMap::new(),
+            diffs: HashMap::new(),
+            next_diff: 0,
+            diff_requests: HashMap::new(),
+            #[cfg(feature = "fuse")]
+            _mounts: mounts,
         })
     }
 
+    /// Use `resolver` instead of last-writer-wins when a received file differs from the local copy
+    /// Registers it for one observer, or for every observer without its own when `observer` is None.
+    pub fn register_conflict_resolver(&mut self, observer: Option<&str>, resolver: Arc<dyn ConflictResolver>) {
+        self.transfer_tracker.register_conflict_resolver(observer, resolver);
+    }
+
     /// Run the network manager event loop, integrating observer events
     pub async fn run(mut self, observer_rx: std::sync::mpsc::Receiver<String>) {
         // Use a tokio channel to bridge observer events into the async context
         let (obs_tx, mut obs_rx) = tokio_mpsc::channel::<String>(32);
-        
+        self.local_events = Some(obs_tx.clone());
+
+        // Repopulate the file index lost with a corrupt state file, and compare trees with peers straight away
+        if self.state.recovered_from().is_some() {
+            self.start_rescan(None);
+            self.schedule_heartbeat();
+        }
+
+        // Peers from earlier runs are usually still where they were
+        self.dial_known_peers();
+        self.provide_held_files();
+
         // Spawn a thread to forward std_mpsc observer_rx to async obs_tx
         let _observer_thread_forward = thread::spawn(move || {
             while let Ok(msg) = observer_rx.recv() {
@@ -60,8 +874,76 @@ impl NetworkManager {
             }
         });
 
+        // Serve CLI requests such as `syndactyl status`
+        let (Some(mut control_rx), Some(mut catalog_rx), Some(mut bulk_rx), Some(mut hash_checks_rx), Some(mut nat_pmp_rx)) =
+            (self.control_rx.take(), self.catalog_rx.take(), self.bulk_rx.take(), self.hash_checks_rx.take(), self.nat_pmp_rx.take()) else {
+            return;
+        };
+        // Peers stream chunks from us whenever they support it, whether or not we download that way
+        if let Err(e) = bulk::spawn_acceptor(self.bulk_control.clone(), self.bulk_tx.clone()) {
+            warn!(error = %e, "Failed to accept bulk transfer streams");
+        }
+        // A tenant's user reaches its daemon with the socket and token, which are only theirs
+        let control_token = self.control_socket.as_ref().and_then(|(_, token_path)| match control::load_or_create_token(token_path)
+            .and_then(|token| tenant::hand_over(token_path, self.owner).map(|()| token)) {
+            Ok(token) => Some(token),
+            Err(e) => {
+                warn!(path = %token_path.display(), error = %e, "Failed to read control token, not starting control socket");
+                None
+            }
+        });
+        if let (Some((path, _)), Some(token)) = (&self.control_socket, &control_token) {
+            let started = control::spawn_server(path.clone(), token.clone(), self.control_tx.clone(), self.status_feed.sender(), self.tray_feed.clone())
+                .and_then(|()| tenant::hand_over(path, self.owner));
+            if let Err(e) = started {
+                warn!(path = %path.display(), error = %e, "Failed to start control socket");
+            }
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = self.grpc {
+            match control_token {
+                Some(token) => {
+                    if let Err(e) = crate::grpc::spawn(addr, token, self.control_tx.clone(), self.status_feed.sender()).await {
+                        warn!(listen = %addr, error = %e, "Failed to start gRPC server");
+                    }
+                }
+                None => warn!(listen = %addr, "No control token, not starting gRPC server"),
+            }
+        }
+        #[cfg(feature = "metrics")]
+        if let Some((addr, page)) = self.metrics_http.clone() {
+            if let Err(e) = metrics::serve_http(addr, page).await {
+                warn!(listen = %addr, error = %e, "Failed to start metrics endpoint");
+            }
+        }
+
         info!("[NetworkManager] Starting event loop");
 
+        // Periodically emit aggregate summaries for throttled log subsystems
+        let mut log_flush = tokio::time::interval(self.log_throttle.window());
+
+        // Periodically apply transactions that never completed and persist state
+        let mut housekeeping = tokio::time::interval(Duration::from_secs(30));
+
+        // Let discovery backends redial, register and look up peers
+        let mut discovery_check = tokio::time::interval(Duration::from_secs(1));
+
+        // Release work held back by sync windows and rate limits
+        let mut schedule_check = tokio::time::interval(Duration::from_millis(250));
+
+        // Let peers compare their observer trees with ours
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut heartbeat_check = tokio::time::interval(Duration::from_secs(1));
+
+        // Re-announce a few current versions, for peers that missed them
+        let mut anti_entropy = tokio::time::interval(self.anti_entropy_interval.unwrap_or(anti_entropy::DEFAULT_INTERVAL));
+
+        // Tell file manager extensions about files starting or finishing a transfer
+        let mut status_feed_check = tokio::time::interval(Duration::from_millis(500));
+
+        // Keep the tray state current; watching trays are only sent what changed
+        let mut tray_check = tokio::time::interval(Duration::from_secs(1));
+
         // Main async loop: handle both observer events, P2P events, and swarm events
         loop {
             tokio::select! {
@@ -71,332 +953,3730 @@ impl NetworkManager {
                 Some(event) = self.event_receiver.recv() => {
                     self.handle_p2p_event(event).await;
                 },
+                Some(command) = control_rx.recv() => {
+                    self.handle_control_command(command);
+                },
+                Some(request) = catalog_rx.recv() => {
+                    self.handle_catalog_request(request);
+                },
+                Some(event) = bulk_rx.recv() => {
+                    self.handle_bulk_event(event);
+                },
+                Some(checked) = hash_checks_rx.recv() => {
+                    self.handle_hash_checked(checked);
+                },
+                Some(change) = nat_pmp_rx.recv() => match change {
+                    nat_pmp::AddressChange::Added(address) => self.p2p.swarm.add_external_address(address),
+                    nat_pmp::AddressChange::Removed(address) => self.p2p.swarm.remove_external_address(&address),
+                },
                 swarm_event = self.p2p.swarm.select_next_some() => {
                     self.handle_swarm_event(swarm_event).await;
                 },
+                _ = log_flush.tick() => {
+                    self.log_throttle.flush();
+                },
+                _ = housekeeping.tick() => {
+                    self.expire_transactions();
+                    let idle = self.transfer_tracker.expire_idle(Instant::now());
+                    self.handle_evictions(idle);
+                    self.refresh_power();
+                    self.check_observer_roots();
+                    self.check_disk_space();
+                    self.expire_tombstones();
+                    self.expire_seen_events();
+                    self.state.state_mut().event_order.expire(unix_now(), event_order::DEFAULT_TTL);
+                    if self.state.state().removed_observers.names().next().is_some() {
+                        self.state.state_mut().removed_observers.expire(unix_now(), removed_observers::NOTICE_PERIOD);
+                    }
+                    self.auth_failures.expire(Instant::now());
+                    self.state.state_mut().address_book.expire(unix_now(), address_book::RETENTION);
+                    self.persist_state();
+                },
+                _ = discovery_check.tick() => {
+                    self.discovery.poll(&mut self.p2p.swarm, Instant::now());
+                },
+                _ = heartbeat.tick() => {
+                    self.publish_heartbeat();
+                },
+                _ = anti_entropy.tick(), if self.anti_entropy_interval.is_some() => {
+                    self.reannounce_sample();
+                },
+                _ = heartbeat_check.tick(), if self.heartbeat_due.is_some_and(|due| due <= Instant::now()) => {
+                    self.publish_heartbeat();
+                },
+                _ = status_feed_check.tick(), if self.status_feed.has_subscribers() => {
+                    self.update_status_feed();
+                },
+                _ = tray_check.tick() => {
+                    self.update_tray_feed();
+                },
+                _ = schedule_check.tick(), if self.has_scheduled_work() => {
+                    self.release_scheduled_work();
+                },
+                _ = std::future::ready(()), if !self.serve_queue.is_empty() => {
+                    self.serve_queued_requests();
+                },
                 else => {
                     info!("[NetworkManager] All channels closed, shutting down");
                     break;
                 }
             }
+            if !self.incoming.is_empty() && self.transfer_tracker.has_capacity() {
+                self.start_incoming();
+            }
+            if self.shutdown_requested {
+                break;
+            }
+        }
+
+        self.log_throttle.flush_all();
+        self.persist_state();
+        // Closing the channel has the mapping task delete the port mapping
+        drop(nat_pmp_rx);
+        if let Some(task) = self.nat_pmp_task.take() {
+            let _ = tokio::time::timeout(nat_pmp::UNMAP_TIMEOUT, task).await;
+        }
+        if let Some(audit_log) = self.audit_log.clone() {
+            // Waits for the writer thread to sync what was served last
+            let _ = tokio::task::spawn_blocking(move || audit_log.flush()).await;
+        }
+        #[cfg(feature = "otlp")]
+        if let Some(export) = self.otlp_metrics.take() {
+            // Flushing blocks on the exporter's HTTP client
+            let _ = tokio::task::spawn_blocking(move || export.shutdown()).await;
+        }
+    }
+
+    /// Dial the peers remembered from earlier runs, alongside the discovery backends
+    fn dial_known_peers(&mut self) {
+        let known: Vec<(PeerId, Vec<libp2p::Multiaddr>)> = self.state.state().address_book.peers().into_iter()
+            .filter_map(|(peer, known)| {
+                let peer: PeerId = peer.parse().ok()?;
+                let addresses = known.addresses.iter().filter_map(|address| address.parse().ok()).collect();
+                Some((peer, addresses))
+            })
+            .collect();
+        if known.is_empty() {
+            return;
+        }
+        info!(peers = known.len(), "[syndactyl] Dialing peers from earlier runs");
+        let local_peer_id = *self.p2p.swarm.local_peer_id();
+        for (peer, addresses) in known {
+            if peer == local_peer_id || addresses.is_empty() {
+                continue;
+            }
+            for address in &addresses {
+                self.p2p.swarm.behaviour_mut().kademlia.add_address(&peer, address.clone());
+            }
+            if let Err(e) = self.p2p.swarm.dial(libp2p::swarm::dial_opts::DialOpts::peer_id(peer).addresses(addresses).build()) {
+                debug!(peer_id = %peer, error = %e, "[syndactyl] Failed to dial known peer");
+            }
+        }
+    }
+
+    /// Save daemon state and refresh the metrics file
+    fn persist_state(&mut self) {
+        if self.catalog_dirty {
+            if let Ok(catalog) = self.catalog.read() {
+                self.state.state_mut().catalog = catalog.clone();
+                self.catalog_dirty = false;
+            }
+        }
+        if let Ok(mut local_names) = self.local_names.write() {
+            if local_names.dirty {
+                local_names.dirty = false;
+                self.state.state_mut().local_names = local_names.clone();
+            }
+        }
+        if let Err(e) = self.state.save() {
+            error!(error = %e, "Failed to save daemon state");
+        }
+        #[cfg(feature = "metrics")]
+        self.refresh_metrics();
+    }
+
+    /// Render the metrics for the textfile, /metrics and the OTLP exporter
+    #[cfg(feature = "metrics")]
+    fn refresh_metrics(&mut self) {
+        #[cfg(feature = "otlp")]
+        let otlp = self.otlp_metrics.is_some();
+        #[cfg(not(feature = "otlp"))]
+        let otlp = false;
+        if self.metrics_file.is_some() || self.metrics_http.is_some() || otlp {
+            let mut metrics = Metrics::new();
+            metrics.bandwidth(&self.state.state().bandwidth);
+            self.transfer_tracker.stats().write_metrics(&mut metrics);
+            self.auth_failures.write_metrics(&mut metrics);
+            #[cfg(feature = "otlp")]
+            if let Some(export) = &mut self.otlp_metrics {
+                export.update(metrics.samples());
+            }
+            let rendered = metrics.render();
+            if let Some(path) = &self.metrics_file {
+                if let Err(e) = metrics::write_textfile(path, &rendered) {
+                    error!(path = %path.display(), error = %e, "Failed to write metrics file");
+                }
+            }
+            if let Some((_, page)) = &self.metrics_http {
+                if let Ok(mut page) = page.write() {
+                    *page = rendered;
+                }
+            }
         }
     }
 
     /// Handle observer file change messages
-    fn handle_observer_message(&mut self, msg: String) {
-        info!(msg = %msg, "Forwarding observer event to P2P");
-        let _ = self.p2p.publish_gossipsub(msg.into_bytes());
+    fn handle_observer_message(&mut self, mut msg: String) {
+        if self.log_throttle.allow("publish") {
+            info!(msg = %msg, "Forwarding observer event to P2P");
+        }
+
+        if let Ok(file_event) = serde_json::from_str::<FileEventMessage>(&msg) {
+            let sync = sync_id(&file_event.observer, &file_event.path, file_event.hash.as_deref());
+            let _span = traced(info_span!("publish", sync = %sync), &sync).entered();
+            if file_event.event_type == EventType::Rescan {
+                self.mark_stale(&file_event.observer, file_event.path);
+                return;
+            }
+            if file_event.event_type == EventType::Lifecycle {
+                if let Some((state, reason)) = lifecycle::parse(&file_event) {
+                    let first = self.lifecycles.state(&file_event.observer).is_none();
+                    log_lifecycle(self.lifecycles.report(&file_event.observer, state, &reason, unix_now()));
+                    // Pauses requested before the watcher first reported
+                    if first && (self.paused_all || self.paused_observers.contains(&file_event.observer)) {
+                        log_lifecycle(self.lifecycles.set_paused(&file_event.observer, true, unix_now()));
+                    }
+                }
+                return;
+            }
+            // Changes to observers outside the active profile are picked up by a rescan when it is re-enabled
+            if !self.profile.allows_observer(&file_event.observer) {
+                debug!(observer = %file_event.observer, path = %file_event.path, "Observer disabled by sync profile, not announcing");
+                return;
+            }
+            // A local delete aborts any transfer of that file in either direction
+            if file_event.event_type == EventType::Remove {
+                self.cancel_transfers_for(&file_event.observer, &file_event.path, "deleted locally");
+                match self.admit_deletion(&file_event.observer, msg) {
+                    Some(admitted) => msg = admitted,
+                    None => return,
+                }
+            }
+            if file_event.event_type == EventType::Modify && self.check_appended(&file_event, &msg) {
+                return;
+            }
+            // Announced now, ahead of any earlier append still being checked
+            self.pending_appends.remove(&(file_event.observer.clone(), file_event.path.clone()));
+        }
+
+        self.announce_local_event(msg);
+    }
+
+    /// Hash the start of a modified file that only grew, announcing it once done
+    /// Peers holding its last announced version then fetch just the appended bytes.
+    /// Returns false if the file didn't grow, so there is nothing to check.
+    fn check_appended(&mut self, file_event: &FileEventMessage, msg: &str) -> bool {
+        let (Some(size), Some(storage)) = (file_event.size, self.storages.get(&file_event.observer).cloned()) else {
+            return false;
+        };
+        let Some(previous) = self.state.state().index.get(&file_event.observer, &file_event.path) else {
+            return false;
+        };
+        if file_event.transaction.is_some() || previous.size == 0 || previous.size >= size {
+            return false;
+        }
+        if previous.size < self.tuning(&file_event.observer).delta_min_size {
+            return false;
+        }
+        let (offset, expected) = (previous.size, previous.hash.clone());
+        self.append_checks += 1;
+        let check = self.append_checks;
+        self.pending_appends.insert((file_event.observer.clone(), file_event.path.clone()), check);
+        let purpose = CheckPurpose::Append { msg: msg.to_string(), offset, expected, check };
+        let (tx, observer, path) = (self.hash_checks_tx.clone(), file_event.observer.clone(), file_event.path.clone());
+        self.hash_pool.check_prefix(storage, std::path::PathBuf::from(&path), offset, move |hash| {
+            let _ = tx.blocking_send(HashChecked { observer, path, purpose, hash });
+        });
+        true
+    }
+
+    /// Number a local operation one past the newest seen on its file, signing it again
+    fn stamp_sequence(&self, file_event: &mut FileEventMessage) {
+        if file_event.event_type != EventType::Remove && !file_event.event_type.has_content() {
+            return;
+        }
+        file_event.sequence = Some(self.state.state().event_order.next_sequence(&file_event.observer, &file_event.path));
+        if let Some(secret) = self.observer_configs.get(&file_event.observer).and_then(|config| config.shared_secret.as_ref()) {
+            file_event.hmac = Some(auth::compute_hmac(file_event, secret.expose()));
+        }
+    }
+
+    /// Publish a local event to bridges and peers
+    fn announce_local_event(&mut self, mut msg: String) {
+        let mut observer = None;
+        if let Ok(mut file_event) = serde_json::from_str::<FileEventMessage>(&msg) {
+            self.stamp_sequence(&mut file_event);
+            msg = serde_json::to_string(&file_event).unwrap_or(msg);
+            self.bridges.publish(&file_event);
+            self.update_tombstones(&file_event);
+            self.state.state_mut().event_order.record_local(&file_event, unix_now());
+            // A local edit or removal makes any version, even the one last applied, new again
+            if file_event.event_type.has_content() {
+                self.state.state_mut().seen_events.changed(&file_event.observer, &file_event.path, file_event.hash.as_deref());
+            } else if file_event.event_type == EventType::Remove {
+                self.state.state_mut().seen_events.changed(&file_event.observer, &file_event.path, None);
+            }
+            // A download under way now conflicts with the local edit
+            let synced = self.state.state().seen_events.applied(&file_event.observer, &file_event.path);
+            self.transfer_tracker.set_local_synced(&(file_event.observer.clone(), file_event.path.clone()), synced);
+            if self.state.state_mut().index.record(&file_event) {
+                self.merkle_trees.remove(&file_event.observer);
+                self.schedule_heartbeat();
+            }
+            if let Some(role) = self.roles.get(&file_event.observer).copied() {
+                self.update_provided(&file_event);
+                // Subscribers only receive; what they hold is found through the DHT
+                if role == Role::Subscriber {
+                    return;
+                }
+            }
+            observer = Some(file_event.observer);
+        }
+        self.gossip_announcement(observer.as_deref(), msg);
+    }
+
+    /// Publish an announcement to peers
+    fn gossip_announcement(&mut self, observer: Option<&str>, msg: String) {
+        // Observers shared with a group are announced only on their own topic
+        let topic = observer.filter(|observer| self.sync_groups.is_restricted(observer)).map(|observer| {
+            let secret = self.observer_configs.get(observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose));
+            sync_groups::topic(observer, secret)
+        });
+        let _ = match topic {
+            Some(topic) => self.p2p.publish_to_topic(&topic, msg.into_bytes()),
+            None => self.p2p.publish_gossipsub(msg.into_bytes()),
+        };
+    }
+
+    /// Re-announce a random sample of the versions we hold in each observer
+    /// Peers that missed an announcement or lost their state fetch the file again,
+    /// without waiting for a heartbeat to show their tree differs.
+    fn reannounce_sample(&mut self) {
+        if self.connected_peers.is_empty() || self.anti_entropy_sample == 0 {
+            return;
+        }
+        // Only publishers announce in a distribution channel
+        let names: Vec<String> = self.observer_configs.keys()
+            .filter(|name| self.reconcilable(name) && self.roles.get(*name) != Some(&Role::Subscriber))
+            .cloned()
+            .collect();
+        let mut announced = 0;
+        for name in names {
+            let secret = self.observer_configs.get(&name).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose));
+            let files = self.state.state().index.files(&name);
+            let messages: Vec<FileEventMessage> = anti_entropy::sample(files, self.anti_entropy_sample, |bound| u64::from_le_bytes(setup::random_bytes()[..8].try_into().unwrap_or_default()) % bound)
+                .into_iter()
+                .map(|(path, file)| anti_entropy::announcement(&name, path, file, secret))
+                .collect();
+            for msg in messages {
+                match serde_json::to_string(&msg) {
+                    Ok(json) => {
+                        self.gossip_announcement(Some(&name), json);
+                        announced += 1;
+                    }
+                    Err(e) => warn!(error = %e, "Failed to encode re-announcement"),
+                }
+            }
+        }
+        if announced > 0 {
+            debug!(announced, "Re-announced a sample of current versions");
+        }
+    }
+
+    /// Remember what a local deletion removed, or forget a deletion the file came back from
+    /// Runs before the index drops the deleted files, as it knows their content.
+    fn update_tombstones(&mut self, file_event: &FileEventMessage) {
+        let state = self.state.state_mut();
+        match file_event.event_type {
+            event_type if event_type.has_content() => {
+                state.tombstones.clear(&file_event.observer, &file_event.path);
+            }
+            EventType::Remove => {
+                let node = self.p2p.peer_id().to_string();
+                let now = unix_now();
+                for (path, file) in state.index.under(&file_event.observer, &file_event.path) {
+                    state.tombstones.record(&file_event.observer, &path, file.hash, &node, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn expire_tombstones(&mut self) {
+        let (retention, max_retention, now) = (self.tombstone_retention, self.tombstone_max_retention, unix_now());
+        if self.state.state().tombstones.is_empty() {
+            return;
+        }
+        let state = self.state.state_mut();
+        state.replicas.expire(now, max_retention);
+        let replicas = &state.replicas;
+        let pruned = state.tombstones.prune(now, retention, max_retention, |observer| replicas.active(observer, now, max_retention));
+        if pruned > 0 {
+            debug!(pruned, "Dropped tombstones every peer acknowledged, or past their maximum retention");
+        }
+    }
+
+    /// Note that `peer` syncs `observer`, so deletions there wait for it to acknowledge them
+    /// Refreshed at most hourly, rather than dirtying the state on every heartbeat.
+    fn record_replica(&mut self, observer: &str, peer: &PeerId) {
+        let (peer, now) = (peer.to_string(), unix_now());
+        let seen = self.state.state().replicas.last_seen(observer, &peer);
+        if seen.is_none_or(|seen| now.saturating_sub(seen) >= REPLICA_REFRESH.as_secs()) {
+            self.state.state_mut().replicas.record(observer, &peer, now);
+        }
+    }
+
+    /// Forget applied announcements too old for peers to still be forwarding
+    fn expire_seen_events(&mut self) {
+        if self.state.state().seen_events.is_empty() {
+            return;
+        }
+        let expired = self.state.state_mut().seen_events.expire(unix_now(), seen_events::DEFAULT_TTL);
+        if expired > 0 {
+            debug!(expired, "Forgot announcements applied over an hour ago");
+        }
+    }
+
+    /// Count a failed authentication, alerting bridges once the peer crosses the threshold
+    fn auth_failed(&mut self, peer: &PeerId, observer: &str, failure: AuthFailure) {
+        let Some(alert) = self.auth_failures.record(&peer.to_string(), observer, failure, Instant::now()) else {
+            return;
+        };
+        error!(
+            peer = %alert.peer,
+            failures = alert.failures,
+            window_secs = alert.window_secs,
+            reasons = ?alert.reasons,
+            "Peer keeps failing authentication - it may be probing, or hold a wrong secret or key"
+        );
+        self.bridges.alert(&alert);
+    }
+
+    /// Advertise the version of a distributed file we now hold, withdrawing the one it replaces
+    fn update_provided(&mut self, file_event: &FileEventMessage) {
+        let key = (file_event.observer.clone(), file_event.path.clone());
+        let current = match (file_event.event_type, &file_event.hash) {
+            (event_type, Some(hash)) if event_type.has_content() && hash_pool::parse_pending_version(hash).is_none() => {
+                let secret = self.observer_configs.get(&file_event.observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose));
+                Some(distribution::provider_key(&file_event.observer, &file_event.path, hash, secret))
+            }
+            _ => None,
+        };
+        if self.provided.get(&key) == current.as_ref() {
+            return;
+        }
+        if let Some(previous) = self.provided.remove(&key) {
+            self.p2p.stop_providing(&previous);
+        }
+        if let Some(current) = current {
+            self.p2p.start_providing(&current);
+            self.provided.insert(key, current);
+        }
+    }
+
+    /// Advertise the distributed files held from before the restart
+    /// Local events only come with changes, so unchanged files would otherwise go unadvertised.
+    fn provide_held_files(&mut self) {
+        let mut held = Vec::new();
+        for observer in self.roles.keys() {
+            let secret = self.observer_configs.get(observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose));
+            for (path, file) in self.state.state().index.files(observer) {
+                if hash_pool::parse_pending_version(&file.hash).is_none() {
+                    held.push(((observer.clone(), path.clone()), distribution::provider_key(observer, path, &file.hash, secret)));
+                }
+            }
+        }
+        if !held.is_empty() {
+            info!(files = held.len(), "Advertising distributed files already held");
+        }
+        for (key, provider_key) in held {
+            self.p2p.start_providing(&provider_key);
+            self.provided.insert(key, provider_key);
+        }
+    }
+
+    /// Look for subscribers already holding the version being downloaded, to pull chunks from them too
+    fn find_helpers(&mut self, key: &(String, String), hash: &str) {
+        if self.roles.get(&key.0) != Some(&Role::Subscriber) || hash_pool::parse_pending_version(hash).is_some() {
+            return;
+        }
+        let secret = self.observer_configs.get(&key.0).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose));
+        let provider_key = distribution::provider_key(&key.0, &key.1, hash, secret);
+        self.p2p.get_providers(&provider_key);
+        self.provider_lookups.insert(provider_key, key.clone());
+    }
+
+    /// Start pulling chunks from providers found for a download in progress
+    fn handle_providers_found(&mut self, provider_key: String, providers: Vec<PeerId>) {
+        self.provider_lookups.retain(|_, key| self.download_sources.contains_key(key));
+        let Some(key) = self.provider_lookups.get(&provider_key).cloned() else {
+            return;
+        };
+        let Some(source) = self.download_sources.get(&key).copied() else {
+            return;
+        };
+        let local = *self.p2p.peer_id();
+        let mut added = Vec::new();
+        // Busy peers would only slow the download down
+        for provider in self.peer_resources.rank(&key.0, providers, Instant::now()) {
+            let helpers = self.download_helpers.entry(key.clone()).or_default();
+            if helpers.len() >= MAX_HELPERS {
+                break;
+            }
+            let allowed = self.sync_groups.allows(&key.0, &provider.to_string())
+                && self.key_pins.allows(&key.0, &provider)
+                && !self.departed.contains(&(provider, key.0.clone()))
+                && self.profile.allows_peer(&provider.to_string());
+            if provider != local && provider != source && allowed && helpers.insert(provider) {
+                added.push(provider);
+            }
+        }
+        if added.is_empty() {
+            return;
+        }
+        info!(observer = %key.0, path = %key.1, helpers = added.len(), "Pulling chunks from other subscribers too");
+        for helper in added {
+            self.request_more_chunks(helper);
+        }
+    }
+
+    /// Stop pulling from a helper after a failed chunk request, handing its chunks back
+    /// Returns false if the peer isn't helping with that download.
+    fn drop_helper(&mut self, chunk: &ChunkInFlight) -> bool {
+        if !self.download_helpers.get_mut(&chunk.key).is_some_and(|helpers| helpers.remove(&chunk.peer)) {
+            return false;
+        }
+        debug!(peer = %chunk.peer, observer = %chunk.key.0, path = %chunk.key.1, "Helper failed, no longer pulling chunks from it");
+        // Anything still outstanding with the helper is ignored if it arrives
+        let mut offsets: Vec<u64> = self.chunk_requests.values()
+            .filter(|other| other.peer == chunk.peer && other.key == chunk.key)
+            .map(|other| other.offset)
+            .collect();
+        offsets.push(chunk.offset);
+        if let Some(cursor) = self.chunk_cursors.get_mut(&chunk.key) {
+            cursor.retry.extend(offsets);
+        }
+        if let Some(source) = self.download_sources.get(&chunk.key).copied() {
+            self.request_more_chunks(source);
+        }
+        true
+    }
+
+    /// Whether an observer's tree is gossiped and reconciled: it must keep a full local copy
+    fn reconcilable(&self, observer: &str) -> bool {
+        self.storages.contains_key(observer)
+            && self.observer_configs.get(observer).is_some_and(|obs| obs.mount.is_none())
+            && self.profile.allows_observer(observer)
+            && !self.unavailable_observers.contains(observer)
+    }
+
+    fn merkle_tree(&mut self, observer: &str) -> &MerkleTree {
+        let index = &self.state.state().index;
+        self.merkle_trees.entry(observer.to_string()).or_insert_with(|| index.tree(observer))
+    }
+
+    /// Send a heartbeat shortly, unless one is already scheduled
+    fn schedule_heartbeat(&mut self) {
+        self.heartbeat_due.get_or_insert_with(|| Instant::now() + HEARTBEAT_DEBOUNCE);
+    }
+
+    /// Gossip the root and file count of every reconcilable observer's tree
+    /// Observers recently removed from the configuration are listed too, so peers stop expecting them.
+    fn publish_heartbeat(&mut self) {
+        self.heartbeat_due = None;
+        if self.connected_peers.is_empty() {
+            return;
+        }
+        let heartbeat = self.local_heads(None);
+        if heartbeat.observers.is_empty() && heartbeat.removed.is_empty() {
+            return;
+        }
+        match serde_json::to_vec(&heartbeat) {
+            Ok(data) => {
+                if let Err(e) = self.p2p.publish_heartbeat(data) {
+                    debug!(error = %e, "Failed to publish heartbeat");
+                }
+            }
+            Err(e) => warn!(error = %e, "Failed to encode heartbeat"),
+        }
+    }
+
+    /// The roots we advertise, limited to the observers `peer` may sync if given
+    fn local_heads(&mut self, peer: Option<PeerId>) -> Heartbeat {
+        let shares = |manager: &Self, name: &str| peer.is_none_or(|peer| {
+            manager.profile.allows_peer(&peer.to_string())
+                && manager.sync_groups.allows(name, &peer.to_string())
+                && manager.key_pins.allows(name, &peer)
+        });
+        // Only publishers' trees are reconciled against in a distribution channel
+        let names: Vec<String> = self.observer_configs.keys()
+            .filter(|name| self.reconcilable(name) && self.roles.get(*name) != Some(&Role::Subscriber) && shares(self, name))
+            .cloned()
+            .collect();
+        let mut observers = Vec::new();
+        for name in names {
+            let secret = self.observer_configs.get(&name).and_then(|obs| obs.shared_secret.clone());
+            let available_bytes = self.observer_configs.get(&name)
+                .and_then(|obs| disk_space::available_bytes(std::path::Path::new(&obs.path)).ok());
+            let tree = self.merkle_tree(&name);
+            let (root, file_count) = (tree.root().to_string(), tree.file_count() as u64);
+            observers.push(ObserverDigest {
+                observer_id: secret.as_ref().map(|secret| auth::observer_id(&name, secret.expose())),
+                hmac: secret.as_ref().map(|secret| merkle::sign_root(&name, &root, file_count, secret.expose())),
+                observer: name,
+                root,
+                file_count,
+                available_bytes,
+            });
+        }
+        let removed: Vec<String> = self.state.state().removed_observers.names().cloned().collect();
+        Heartbeat { observers, removed, load: Some(self.serving_load()) }
+    }
+
+    /// Send a peer we just dialed our tree roots, so it can pull what it's missing straight away
+    /// Its answer carries its roots. Peers sharing no observers with us are sent nothing.
+    fn send_heads(&mut self, peer: PeerId) {
+        let heads = self.local_heads(Some(peer));
+        if heads.observers.is_empty() {
+            debug!(peer = %peer, "No observers shared with peer, not sending heads");
+            return;
+        }
+        self.p2p.request_heads(peer, heads);
+    }
+
+    /// Transfers we are serving, as advertised in heartbeats
+    fn serving_load(&self) -> PeerLoad {
+        let serving = self.serving_peers.values().map(HashSet::len).sum::<usize>();
+        PeerLoad { serving: serving.min(u32::MAX as usize) as u32, capacity: self.max_serving_transfers }
+    }
+
+    /// Take in the tree roots of a gossiped heartbeat
+    fn handle_heartbeat(&mut self, source: PeerId, data: &[u8]) {
+        match wire::decode_heartbeat(data) {
+            Ok(heartbeat) => self.apply_heads(source, heartbeat),
+            Err(e) => warn!(peer = %source, error = %e, "Rejected heartbeat"),
+        }
+    }
+
+    /// Compare a peer's tree roots with ours and start reconciling any that differ
+    /// They arrive gossiped in heartbeats, or directly from peers exchanging heads on connecting.
+    fn apply_heads(&mut self, source: PeerId, heartbeat: Heartbeat) {
+        // Trees are fetched over a direct connection
+        if !self.connected_peers.contains(&source) || !self.profile.allows_peer(&source.to_string()) {
+            return;
+        }
+        for observer in &heartbeat.removed {
+            if self.observer_configs.contains_key(observer) {
+                self.peer_left_observer(source, observer);
+            }
+        }
+        let now = Instant::now();
+        let available = heartbeat.observers.iter()
+            .filter_map(|digest| digest.available_bytes.map(|bytes| (digest.observer.clone(), bytes)));
+        self.peer_resources.record(source, heartbeat.load, available, now);
+        for digest in heartbeat.observers {
+            // Added back on the peer's side
+            self.departed.remove(&(source, digest.observer.clone()));
+            if !self.reconcilable(&digest.observer)
+                || !self.sync_groups.allows(&digest.observer, &source.to_string())
+                || !self.key_pins.allows(&digest.observer, &source)
+            {
+                continue;
+            }
+            if self.roles.get(&digest.observer) == Some(&Role::Publisher) {
+                continue;
+            }
+            if let Some(secret) = self.observer_configs.get(&digest.observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose)) {
+                let expected = merkle::sign_root(&digest.observer, &digest.root, digest.file_count, secret);
+                let same_mesh = digest.observer_id.as_deref() == Some(auth::observer_id(&digest.observer, secret).as_str());
+                let authentic = same_mesh && digest.hmac.as_deref().is_some_and(|hmac| auth::constant_time_compare(hmac, &expected));
+                if !authentic {
+                    debug!(peer = %source, observer = %digest.observer, "Ignoring heartbeat root not signed with the observer's secret");
+                    // Another mesh reusing the name is expected; a forged root for ours is not
+                    if same_mesh {
+                        self.auth_failed(&source, &digest.observer, AuthFailure::BadSignature);
+                    }
+                    continue;
+                }
+            }
+            self.record_replica(&digest.observer, &source);
+            let key = (source, digest.observer.clone());
+            let tree = self.merkle_tree(&digest.observer);
+            let (local_root, local_files) = (tree.root().to_string(), tree.file_count());
+            let under_way = self.reconciling.get(&key).map(|r| (r.root.as_str(), now.duration_since(r.started)));
+            let comparison = heads::compare(&local_root, &digest, under_way);
+            if comparison == Comparison::Reconciling {
+                continue;
+            }
+            if comparison == Comparison::InSync {
+                // Same tree as ours, so the peer holds none of the files we deleted
+                if self.state.state().tombstones.awaits(&digest.observer, &source.to_string()) {
+                    self.state.state_mut().tombstones.acknowledge(&digest.observer, &source.to_string(), |_| true);
+                }
+                if let Some(done) = self.reconciling.remove(&key) {
+                    info!(
+                        peer = %source,
+                        observer = %digest.observer,
+                        already_present = done.matched_files,
+                        skipped_bytes = done.matched_bytes,
+                        fetched = done.fetched_files,
+                        listed = done.listed_entries,
+                        "Observer tree reconciled with peer's"
+                    );
+                }
+                continue;
+            }
+            if !self.peer_capabilities.allows(&source, capabilities::TREE_NODE) {
+                continue;
+            }
+            info!(
+                peer = %source,
+                observer = %digest.observer,
+                local_files,
+                peer_files = digest.file_count,
+                "Observer tree differs from peer's, reconciling"
+            );
+            // A retry carries on counting from where the last attempt got to
+            let (matched_files, matched_bytes, fetched_files, checking_files) = self.reconciling.get(&key)
+                .map_or((0, 0, 0, 0), |r| (r.matched_files, r.matched_bytes, r.fetched_files, r.checking_files));
+            self.reconciling.insert(key, Reconciliation {
+                root: digest.root,
+                started: now,
+                matched_files,
+                matched_bytes,
+                fetched_files,
+                listed_entries: 0,
+                checking_files,
+            });
+            self.request_tree_node(source, &digest.observer, String::new(), None);
+        }
+    }
+
+    /// Stop expecting anything from a peer for an observer it no longer syncs
+    /// Downloads it was serving are dropped; a later announcement from it brings it back.
+    fn peer_left_observer(&mut self, peer: PeerId, observer: &str) {
+        if !self.departed.insert((peer, observer.to_string())) {
+            return;
+        }
+        info!(peer = %peer, observer = %observer, "Peer no longer syncs observer");
+        self.reconciling.remove(&(peer, observer.to_string()));
+        // Deletions there no longer wait for it to acknowledge them
+        if self.state.state().replicas.last_seen(observer, &peer.to_string()).is_some() {
+            self.state.state_mut().replicas.forget(observer, &peer.to_string());
+        }
+        for (key, helpers) in self.download_helpers.iter_mut() {
+            if key.0 == observer {
+                helpers.remove(&peer);
+            }
+        }
+        let sourced: Vec<(String, String)> = self.download_sources.iter()
+            .filter(|(key, source)| key.0 == observer && **source == peer)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (key, fallbacks) in self.download_fallbacks.iter_mut() {
+            if key.0 == observer {
+                fallbacks.retain(|fallback| *fallback != peer);
+            }
+        }
+        for key in sourced {
+            let hash = self.transfer_tracker.expected_hash(&key.0, &key.1).map(str::to_string);
+            let size = self.transfer_tracker.total_size(&key.0, &key.1).unwrap_or(0);
+            self.download_sources.remove(&key);
+            self.pending_chunks.remove(&key);
+            self.transfer_tracker.cancel_transfer(&key.0, &key.1);
+            if hash.is_some_and(|hash| self.restart_from_fallback(&key, &hash, size)) {
+                continue;
+            }
+            self.abandon_transaction_member(&key);
+            self.finish_fetch(&key, Err("peer no longer syncs the observer".to_string()));
+        }
+    }
+
+    fn request_tree_node(&mut self, peer: PeerId, observer: &str, dir: String, after: Option<String>) -> OutboundRequestId {
+        let hmac = self.observer_configs.get(observer)
+            .and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose))
+            .map(|secret| merkle::sign_node_request(observer, &dir, &self.p2p.peer_id().to_string(), secret));
+        self.p2p.request_tree_node(peer, TreeNodeRequest { observer: observer.to_string(), dir, hmac, after })
+    }
+
+    /// Serve one directory of our tree to a peer holding the observer's secret
+    fn handle_tree_node_request(
+        &mut self,
+        peer: PeerId,
+        request: TreeNodeRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    ) {
+        // Dropping the response channel fails the request on the peer's side
+        if !self.reconcilable(&request.observer) || !self.profile.allows_peer(&peer.to_string()) {
+            debug!(peer = %peer, observer = %request.observer, "Not serving tree outside the active sync profile");
+            return;
+        }
+        if !self.sync_groups.allows(&request.observer, &peer.to_string()) {
+            warn!(peer = %peer, observer = %request.observer, "Peer is not in the observer's peers, refusing tree request");
+            self.auth_failed(&peer, &request.observer, AuthFailure::Unauthorized);
+            return;
+        }
+        if !self.key_pins.allows(&request.observer, &peer) {
+            warn!(peer = %peer, observer = %request.observer, "Peer's key is not pinned for the observer, refusing tree request");
+            self.auth_failed(&peer, &request.observer, AuthFailure::UnpinnedKey);
+            return;
+        }
+        let secret = self.observer_configs.get(&request.observer).and_then(|obs| obs.shared_secret.clone());
+        if let Some(secret) = &secret {
+            let expected = merkle::sign_node_request(&request.observer, &request.dir, &peer.to_string(), secret.expose());
+            if !request.hmac.as_deref().is_some_and(|hmac| auth::constant_time_compare(hmac, &expected)) {
+                warn!(peer = %peer, observer = %request.observer, "Tree request not signed with the observer's secret, refusing");
+                self.auth_failed(&peer, &request.observer, AuthFailure::BadSignature);
+                return;
+            }
+        }
+        self.merkle_tree(&request.observer);
+        let tree = &self.merkle_trees[&request.observer];
+        let total_entries = tree.entries(&request.dir).len() as u64;
+        // Peers that predate pages would take the first one for the whole directory
+        let (entries, next) = if self.peer_capabilities.confirms(&peer, capabilities::TREE_PAGES) {
+            let (entries, next) = tree.page(&request.dir, request.after.as_deref(), merkle::PAGE_ENTRIES);
+            (entries.to_vec(), next)
+        } else {
+            (tree.entries(&request.dir).to_vec(), None)
+        };
+        let mut tombstones = match request.after {
+            None => self.state.state().tombstones.served_in(&request.observer, &request.dir, |dir| tree.has_dir(dir)),
+            Some(_) => Vec::new(),
+        };
+        tombstones.truncate(wire::MAX_TREE_ENTRIES);
+        let hmac = secret.as_ref().map(|secret| merkle::sign_node(&request.observer, &request.dir, &entries, &tombstones, next.as_deref(), secret));
+        self.p2p.send_tree_node_response(channel, TreeNodeResponse {
+            observer: request.observer,
+            dir: request.dir,
+            entries,
+            hmac,
+            tombstones,
+            next,
+            total_entries,
+        });
+    }
+
+    /// Descend into the subdirectories that differ and fetch the files we lack or hold an older copy of
+    /// Files only the local side has are left alone: the peer picks them up when
+    /// it reconciles against us, and deletions travel as announcements. Files
+    /// deleted here are kept from coming back by their tombstones.
+    fn handle_tree_node(&mut self, peer: PeerId, node: TreeNodeResponse) {
+        if !self.reconcilable(&node.observer) || !self.reconciling.contains_key(&(peer, node.observer.clone())) {
+            debug!(peer = %peer, observer = %node.observer, dir = %node.dir, "Ignoring unrequested tree node");
+            return;
+        }
+        if let Some(secret) = self.observer_configs.get(&node.observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose)) {
+            let expected = merkle::sign_node(&node.observer, &node.dir, &node.entries, &node.tombstones, node.next.as_deref(), secret);
+            if !node.hmac.as_deref().is_some_and(|hmac| auth::constant_time_compare(hmac, &expected)) {
+                warn!(peer = %peer, observer = %node.observer, dir = %node.dir, "Tree node not signed with the observer's secret, ignoring");
+                self.auth_failed(&peer, &node.observer, AuthFailure::BadSignature);
+                return;
+            }
+        }
+        self.adopt_tombstones(peer, &node);
+        self.acknowledge_listed(peer, &node);
+        let local = self.merkle_tree(&node.observer).entries(&node.dir).to_vec();
+        let differing: Vec<TreeEntry> = merkle::differing(&local, &node.entries).into_iter().cloned().collect();
+        let (mut fetching, mut checking) = (0, 0);
+        for entry in differing {
+            let path = merkle::join(&node.dir, &entry.name);
+            if entry.is_dir {
+                self.request_tree_node(peer, &node.observer, path, None);
+                continue;
+            }
+            // Announced changes still downloading show up as differences too
+            if self.download_sources.contains_key(&(node.observer.clone(), path.clone())) {
+                continue;
+            }
+            // Both copies changed: the newer one wins, so only one side fetches
+            let newer = match self.state.state().index.get(&node.observer, &path) {
+                Some(local) => entry.modified_time.is_some_and(|remote| remote > local.modified_time),
+                None => true,
+            };
+            if !newer {
+                continue;
+            }
+            // Content we may already hold, e.g. restored from a backup, is hashed before fetching
+            if self.check_reconciled(peer, &node.observer, &path, &entry) {
+                checking += 1;
+                continue;
+            }
+            fetching += 1;
+            self.fetch_reconciled(peer, &node.observer, path, entry);
+        }
+        if let Some(progress) = self.reconciling.get_mut(&(peer, node.observer.clone())) {
+            progress.fetched_files += fetching;
+            progress.checking_files += checking;
+            progress.listed_entries += node.entries.len() as u64;
+        }
+        // Large directories come in pages; each is compared as it arrives
+        if let Some(next) = node.next {
+            debug!(peer = %peer, observer = %node.observer, dir = %node.dir, page = node.entries.len(), total = node.total_entries, "Listed a page of the peer's directory, requesting the next");
+            self.request_tree_node(peer, &node.observer, node.dir.clone(), Some(next));
+        }
+        if checking > 0 {
+            debug!(peer = %peer, observer = %node.observer, dir = %node.dir, checking, "Hashing local files the size of the peer's before fetching them");
+        }
+        if fetching > 0 {
+            info!(peer = %peer, observer = %node.observer, dir = %node.dir, fetching, "Fetching files that differ from the peer's tree");
+        }
+    }
+
+    /// Hash the local copy of a file a peer's tree lists, if it could have the peer's content
+    /// Sizes are compared first so files that plainly differ aren't hashed. Returns
+    /// false if there is nothing to compare and the file should just be fetched.
+    fn check_reconciled(&self, peer: PeerId, observer: &str, path: &str, entry: &TreeEntry) -> bool {
+        let Some(storage) = self.storages.get(observer) else {
+            return false;
+        };
+        let relative_path = std::path::PathBuf::from(path);
+        if !entry.size.is_some_and(|size| storage.size(&relative_path).is_ok_and(|local| local == size)) {
+            return false;
+        }
+        let (tx, observer, path, entry) = (self.hash_checks_tx.clone(), observer.to_string(), path.to_string(), entry.clone());
+        self.hash_pool.check(storage.clone(), relative_path, move |hash| {
+            let _ = tx.blocking_send(HashChecked { observer, path, purpose: CheckPurpose::Reconcile { peer, entry }, hash });
+        });
+        true
+    }
+
+    /// Fetch a file a peer's tree lists with content we don't hold
+    fn fetch_reconciled(&mut self, peer: PeerId, observer: &str, path: String, entry: TreeEntry) {
+        self.dispatch_file_event(peer, FileEventMessage {
+            observer: observer.to_string(),
+            event_type: EventType::Modify,
+            path,
+            details: Some("Reconcile".to_string()),
+            hash: Some(entry.hash),
+            size: entry.size,
+            modified_time: entry.modified_time,
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        });
+    }
+
+    /// Act on a local file hashed off the event loop
+    fn handle_hash_checked(&mut self, checked: HashChecked) {
+        let HashChecked { observer, path, purpose, hash } = checked;
+        match purpose {
+            CheckPurpose::Reconcile { peer, entry } => {
+                // Reconciliation with the peer ended or was dropped meanwhile
+                let Some(progress) = self.reconciling.get_mut(&(peer, observer.clone())) else {
+                    return;
+                };
+                progress.checking_files = progress.checking_files.saturating_sub(1);
+                let identical = hash.is_ok_and(|hash| hash == entry.hash);
+                if identical {
+                    progress.matched_files += 1;
+                    progress.matched_bytes += entry.size.unwrap_or(0);
+                } else {
+                    progress.fetched_files += 1;
+                }
+                let (checking, matched, fetched) = (progress.checking_files, progress.matched_files, progress.fetched_files);
+                if checking == 0 {
+                    info!(peer = %peer, observer = %observer, already_present = matched, fetched, "Finished comparing local files with the peer's tree");
+                }
+                if !identical {
+                    self.fetch_reconciled(peer, &observer, path, entry);
+                    return;
+                }
+                if let Some(storage) = self.storages.get(&observer) {
+                    self.transfer_tracker.record_synced(&observer, &path, storage.as_ref());
+                }
+                self.state.state_mut().seen_events.record(&observer, &path, &entry.hash, unix_now());
+                debug!(peer = %peer, observer = %observer, path = %path, "File already identical on disk, marked synced without fetching");
+            }
+            CheckPurpose::Serve { size, modified_nanos } => {
+                let key = (observer, path);
+                if let Some(version) = self.served_versions.get_mut(&key) {
+                    version.hashed(size, modified_nanos, hash.ok());
+                }
+                // Requests for a version replaced meanwhile are checked against the new one
+                for (peer, request) in self.parked_serves.remove(&key).unwrap_or_default() {
+                    self.serve_request(peer, request);
+                }
+            }
+            CheckPurpose::Append { mut msg, offset, expected, check } => {
+                let key = (observer, path);
+                // Overtaken by a later change to the file, announced on its own
+                if self.pending_appends.get(&key) != Some(&check) {
+                    return;
+                }
+                self.pending_appends.remove(&key);
+                if let (Ok(prefix), Ok(mut file_event)) = (hash, serde_json::from_str::<FileEventMessage>(&msg)) {
+                    if prefix == expected {
+                        file_event.append = Some(AppendInfo { offset, prefix_hash: prefix });
+                        msg = serde_json::to_string(&file_event).unwrap_or(msg);
+                    }
+                }
+                self.announce_local_event(msg);
+            }
+        }
+    }
+
+    /// Learn the deletions a peer served with its tree, so copies others still hold aren't fetched
+    /// Files we hold ourselves are left alone: a peer's tombstone never deletes them.
+    fn adopt_tombstones(&mut self, peer: PeerId, node: &TreeNodeResponse) {
+        let state = self.state.state_mut();
+        let unheld: Vec<TombstoneEntry> = node.tombstones.iter()
+            .filter(|entry| state.index.get(&node.observer, &entry.path).is_none())
+            .cloned()
+            .collect();
+        let adopted = state.tombstones.adopt(&node.observer, &peer.to_string(), unheld, unix_now());
+        if adopted > 0 {
+            debug!(peer = %peer, observer = %node.observer, dir = %node.dir, adopted, "Learned deletions from peer's tree");
+        }
+    }
+
+    /// Note the deletions in or below a directory the peer listed in full without them
+    /// A page of a longer listing can't show a file is missing, so only complete ones count.
+    fn acknowledge_listed(&mut self, peer: PeerId, node: &TreeNodeResponse) {
+        if node.next.is_some() || node.entries.len() as u64 != node.total_entries {
+            return;
+        }
+        let peer = peer.to_string();
+        if !self.state.state().tombstones.awaits(&node.observer, &peer) {
+            return;
+        }
+        let listed: HashSet<&str> = node.entries.iter().map(|entry| entry.name.as_str()).collect();
+        let deleted_here = |path: &str| tombstone::missing_from_listing(&node.dir, &listed, path);
+        let acknowledged = self.state.state_mut().tombstones.acknowledge(&node.observer, &peer, deleted_here);
+        if acknowledged > 0 {
+            debug!(peer = %peer, observer = %node.observer, dir = %node.dir, acknowledged, "Peer no longer holds files deleted here");
+        }
+    }
+
+    /// Pass a local deletion through the mass-deletion guard, returning it if it may be announced
+    fn admit_deletion(&mut self, observer: &str, msg: String) -> Option<String> {
+        let was_holding = self.deletion_guard.is_holding(observer);
+        // The index still lists the file, as the deletion hasn't been announced yet
+        let index = &self.state.state().index;
+        let admitted = self.deletion_guard.admit(observer, msg, Instant::now(), || index.file_count(observer));
+        if admitted.is_none() {
+            self.save_held_deletions();
+        }
+        if admitted.is_none() && !was_holding {
+            let (deleted, files) = self.deletion_guard.window_counts(observer).unwrap_or_default();
+            warn!(
+                observer = %observer,
+                deleted,
+                files,
+                "Mass deletion detected, holding further deletions; run `syndactyl deletions confirm` to announce them or `syndactyl deletions discard` to keep peers' copies"
+            );
+        }
+        admitted
+    }
+
+    fn save_held_deletions(&mut self) {
+        if self.state.state().held_deletions != *self.deletion_guard.held() {
+            self.state.state_mut().held_deletions = self.deletion_guard.held().clone();
+        }
+    }
+
+    /// Cancel every in-flight transfer of a file, notifying the peers involved
+    fn cancel_transfers_for(&mut self, observer: &str, path: &str, reason: &str) {
+        let key = (observer.to_string(), path.to_string());
+        let cancel = CancelTransferRequest {
+            observer: observer.to_string(),
+            path: path.to_string(),
+            reason: reason.to_string(),
+        };
+
+        // We are downloading it
+        self.cancel_download(&key, reason);
+
+        // We are serving it: drop queued work and tell the requesters to give up
+        if let Some(peers) = self.serving_peers.remove(&key) {
+            for peer in peers {
+                self.serve_queue.retain_for(&peer, |queued| queued.target() != (observer, path));
+                self.p2p.request_cancel_transfer(peer, cancel.clone());
+            }
+        }
+    }
+
+    /// Stop downloading a file, dropping tracker state and telling the source to stop
+    fn cancel_download(&mut self, key: &(String, String), reason: &str) -> bool {
+        let Some(source) = self.download_sources.remove(key) else {
+            return false;
+        };
+        self.transfer_tracker.cancel_transfer(&key.0, &key.1);
+        self.pending_chunks.remove(key);
+        self.abandon_transaction_member(key);
+        self.p2p.request_cancel_transfer(source, CancelTransferRequest {
+            observer: key.0.clone(),
+            path: key.1.clone(),
+            reason: reason.to_string(),
+        });
+        true
+    }
+
+    /// Handle a CancelTransfer request from a peer, cleaning up whichever side of the transfer we hold
+    fn handle_cancel_transfer(
+        &mut self,
+        peer: PeerId,
+        cancel: CancelTransferRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    ) {
+        let key = (cancel.observer.clone(), cancel.path.clone());
+        info!(peer = %peer, observer = %cancel.observer, path = %cancel.path, reason = %cancel.reason, "Peer cancelled transfer");
+
+        // The peer is the source of our download
+        if self.download_sources.get(&key) == Some(&peer) {
+            self.download_sources.remove(&key);
+            self.transfer_tracker.cancel_transfer(&cancel.observer, &cancel.path);
+            self.pending_chunks.remove(&key);
+            self.abandon_transaction_member(&key);
+        }
+
+        // The peer was downloading from us
+        let dropped = self.serve_queue.retain_for(&peer, |queued| {
+            queued.target() != (cancel.observer.as_str(), cancel.path.as_str())
+        });
+        if let Some(peers) = self.serving_peers.get_mut(&key) {
+            peers.remove(&peer);
+            if peers.is_empty() {
+                self.serving_peers.remove(&key);
+            }
+        }
+        if dropped > 0 {
+            info!(peer = %peer, observer = %cancel.observer, path = %cancel.path, dropped, "Dropped queued requests for cancelled transfer");
+        }
+
+        self.p2p.send_cancel_ack(channel, &cancel);
+    }
+
+    /// Read a chunk of a file version already checked by `check_served_version`
+    /// Served from memory when the same version was sent to another peer recently.
+    fn read_served_chunk(
+        &mut self,
+        storage: &dyn StorageBackend,
+        relative_path: &std::path::Path,
+        hash: &str,
+        offset: u64,
+        len: usize,
+    ) -> std::io::Result<CachedChunk> {
+        if let Some(chunk) = self.chunk_cache.get(hash, offset, len) {
+            return Ok(chunk);
+        }
+        let data = storage.read_chunk(relative_path, offset, len)?;
+        let chunk = CachedChunk { segment_hash: segment_hash(&data), data };
+        self.chunk_cache.insert(hash, offset, len, chunk.clone());
+        Ok(chunk)
+    }
+
+    /// Sign a chunk of a version not hashed yet, so the receiver can trust it before the hash arrives
+    fn sign_segment(&self, response: &mut FileTransferResponse) {
+        if hash_pool::parse_pending_version(&response.hash).is_none() {
+            return;
+        }
+        let secret = self.observer_configs.get(&response.observer).and_then(|config| config.shared_secret.as_ref());
+        if let (Some(secret), Some(segment_hash)) = (secret, &response.segment_hash) {
+            response.segment_hmac = Some(auth::segment_hmac(secret.expose(), &response.observer, &response.path, &response.hash, response.offset, segment_hash));
+        }
+    }
+
+    /// Check the file being served still matches the version the peer asked for
+    /// Hashing runs on the hash pool once per size and nanosecond mtime, so chunk
+    /// requests for an unchanged file cost a single stat.
+    fn check_served_version(
+        &mut self,
+        storage: &Arc<dyn StorageBackend>,
+        observer: &str,
+        path: &str,
+        requested_hash: &str,
+    ) -> Result<Served, TransferErrorKind> {
+        let relative_path = std::path::Path::new(path);
+        if file_handler::is_internal_path(relative_path) || !storage.exists(relative_path) {
+            return Err(TransferErrorKind::NotFound);
+        }
+        let (size, modified_nanos) = match (storage.size(relative_path), storage.modified_nanos(relative_path)) {
+            (Ok(size), Ok(mtime)) => (size, mtime),
+            _ => return Err(TransferErrorKind::NotFound),
+        };
+
+        let key = (observer.to_string(), path.to_string());
+        let known = self.served_versions.get(&key).is_some_and(|version| version.is(size, modified_nanos));
+        if !known {
+            let blocked = self.secret_guard.as_ref().and_then(|guard| guard.check(storage.as_ref(), relative_path));
+            if let Some(reason) = &blocked {
+                warn!(observer = %observer, path = %path, reason = %reason, "Refusing to serve file that looks like a secret");
+            }
+            self.served_versions.insert(key.clone(), ServedVersion::new(size, modified_nanos, blocked));
+        }
+        let Some(version) = self.served_versions.get_mut(&key) else {
+            return Err(TransferErrorKind::NotFound);
+        };
+        let served = version.check(requested_hash)?;
+        if served == Served::Hash {
+            let (tx, observer, path) = (self.hash_checks_tx.clone(), observer.to_string(), path.to_string());
+            self.hash_pool.check(storage.clone(), std::path::PathBuf::from(&path), move |hash| {
+                let _ = tx.blocking_send(HashChecked { observer, path, purpose: CheckPurpose::Serve { size, modified_nanos }, hash });
+            });
+        }
+        Ok(served)
+    }
+
+    /// Handle a structured error from the peer serving one of our downloads
+    fn handle_transfer_error(&mut self, peer: PeerId, error: TransferError) {
+        let key = (error.observer.clone(), error.path.clone());
+
+        // Ignore errors for a transfer we already replaced with a newer version
+        let is_current = self.download_sources.get(&key) == Some(&peer)
+            && self.transfer_tracker.expected_hash(&error.observer, &error.path) == Some(error.requested_hash.as_str());
+        if !is_current {
+            // Helpers can stop syncing the observer too
+            if error.kind == TransferErrorKind::NotServing {
+                self.peer_left_observer(peer, &error.observer);
+            }
+            debug!(peer = %peer, observer = %error.observer, path = %error.path, "Ignoring error for stale transfer");
+            return;
+        }
+
+        let size = self.transfer_tracker.total_size(&error.observer, &error.path).unwrap_or(0);
+        self.download_sources.remove(&key);
+        self.pending_chunks.remove(&key);
+        self.end_bulk_download(&key);
+        self.transfer_tracker.cancel_transfer(&error.observer, &error.path);
+
+        match error.kind {
+            TransferErrorKind::FileChanged { current_size: Some(size), current_hash: Some(hash) } => {
+                // Restart against the version the peer has now; the final hash check still applies
+                info!(peer = %peer, observer = %error.observer, path = %error.path, "File changed during transfer, restarting");
+                if let Some(storage) = self.storages.get(&error.observer).cloned() {
+                    let started = self.transfer_tracker.start_transfer(
+                        error.observer.clone(),
+                        error.path.clone(),
+                        peer,
+                        size,
+                        hash.clone(),
+                        storage,
+                        self.download_transactions.get(&key).cloned(),
+                    );
+                    match started {
+                        Ok(evicted) => {
+                            self.handle_evictions(evicted);
+                            // Other announcers offered the old version
+                            self.download_fallbacks.remove(&key);
+                            self.download_sources.insert(key, peer);
+                            self.request_file(peer, FileTransferRequest {
+                                observer: error.observer,
+                                path: error.path,
+                                hash,
+                            });
+                        }
+                        Err(e) => {
+                            warn!(peer = %peer, observer = %error.observer, path = %error.path, error = %e, "Cannot restart transfer");
+                            self.abandon_transaction_member(&key);
+                        }
+                    }
+                }
+            }
+            TransferErrorKind::FileChanged { .. } => {
+                info!(peer = %peer, observer = %error.observer, path = %error.path, "File changed during transfer, waiting for new announcement");
+                if !self.restart_from_fallback(&key, &error.requested_hash, size) {
+                    self.abandon_transaction_member(&key);
+                }
+            }
+            TransferErrorKind::NotFound => {
+                warn!(peer = %peer, observer = %error.observer, path = %error.path, "File no longer available on peer");
+                if !self.restart_from_fallback(&key, &error.requested_hash, size) {
+                    self.abandon_transaction_member(&key);
+                }
+            }
+            TransferErrorKind::NotServing => {
+                if !self.restart_from_fallback(&key, &error.requested_hash, size) {
+                    self.abandon_transaction_member(&key);
+                }
+                self.peer_left_observer(peer, &error.observer);
+            }
+            TransferErrorKind::Busy { retry_after_secs } => {
+                let at = self.peer_resources.mark_busy(peer, retry_after_secs, Instant::now());
+                if !self.restart_from_fallback(&key, &error.requested_hash, size) {
+                    info!(peer = %peer, observer = %error.observer, path = %error.path, retry_after_secs, "Peer is busy, asking again later");
+                    self.busy_retries.insert(key.clone(), BusyRetry { peer, hash: error.requested_hash.clone(), size, at });
+                }
+            }
+        }
+        if !self.download_sources.contains_key(&key) && !self.busy_retries.contains_key(&key) {
+            self.finish_fetch(&key, Err("peer could not serve the file".to_string()));
+        }
+    }
+
+    /// Keep a peer that announced the version being downloaded, in case the source fails
+    fn add_fallback_source(&mut self, key: &(String, String), peer: PeerId) {
+        self.download_fallbacks.retain(|key, _| self.download_sources.contains_key(key));
+        if self.download_sources.get(key) == Some(&peer) {
+            return;
+        }
+        let fallbacks = self.download_fallbacks.entry(key.clone()).or_default();
+        if fallbacks.len() < MAX_FALLBACK_SOURCES && !fallbacks.contains(&peer) {
+            fallbacks.push(peer);
+            debug!(peer = %peer, observer = %key.0, path = %key.1, "Already downloading this version, keeping peer as a fallback");
+        }
+    }
+
+    /// Download `hash` again from the next fallback still connected, once its source has failed
+    /// Returns false if there is none left to try.
+    /// Fallbacks are tried least loaded first, and busy ones not at all.
+    fn restart_from_fallback(&mut self, key: &(String, String), hash: &str, size: u64) -> bool {
+        let Some(fallbacks) = self.download_fallbacks.remove(key) else {
+            return false;
+        };
+        let mut fallbacks = self.peer_resources.rank(&key.0, fallbacks, Instant::now()).into_iter()
+            .filter(|peer| self.connected_peers.contains(peer) && !self.departed.contains(&(*peer, key.0.clone())));
+        let Some(peer) = fallbacks.next() else {
+            return false;
+        };
+        let rest: Vec<PeerId> = fallbacks.collect();
+        match self.restart_download(key, peer, hash, size) {
+            Ok(()) => {
+                info!(peer = %peer, observer = %key.0, path = %key.1, "Retrying download from another peer that announced it");
+                if !rest.is_empty() {
+                    self.download_fallbacks.insert(key.clone(), rest);
+                }
+                true
+            }
+            Err(e) => {
+                warn!(peer = %peer, observer = %key.0, path = %key.1, error = %e, "Cannot restart transfer");
+                false
+            }
+        }
+    }
+
+    /// Download `hash` from `peer` from the start, replacing what was under way
+    fn restart_download(&mut self, key: &(String, String), peer: PeerId, hash: &str, size: u64) -> Result<(), String> {
+        let storage = self.storages.get(&key.0).cloned().ok_or("observer has no local copy")?;
+        let evicted = self.transfer_tracker.start_transfer(
+            key.0.clone(),
+            key.1.clone(),
+            peer,
+            size,
+            hash.to_string(),
+            storage,
+            self.download_transactions.get(key).cloned(),
+        )?;
+        self.handle_evictions(evicted);
+        self.chunk_cursors.remove(key);
+        self.download_helpers.remove(key);
+        self.download_sources.insert(key.clone(), peer);
+        self.find_helpers(key, hash);
+        self.request_file(peer, FileTransferRequest {
+            observer: key.0.clone(),
+            path: key.1.clone(),
+            hash: hash.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Ask busy sources again for the downloads they refused, once their hint has passed
+    fn retry_busy_downloads(&mut self, now: Instant) {
+        let due: Vec<(String, String)> = self.busy_retries.iter()
+            .filter(|(_, retry)| retry.at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in due {
+            let Some(retry) = self.busy_retries.remove(&key) else {
+                continue;
+            };
+            // Another announcer may have served it meanwhile
+            if self.download_sources.contains_key(&key) {
+                continue;
+            }
+            let result = if self.connected_peers.contains(&retry.peer) {
+                self.restart_download(&key, retry.peer, &retry.hash, retry.size)
+            } else {
+                Err("peer disconnected while busy".to_string())
+            };
+            match result {
+                Ok(()) => info!(peer = %retry.peer, observer = %key.0, path = %key.1, "Asking busy peer for the download again"),
+                Err(e) => {
+                    warn!(peer = %retry.peer, observer = %key.0, path = %key.1, error = %e, "Giving up on download from busy peer");
+                    self.abandon_transaction_member(&key);
+                    self.finish_fetch(&key, Err(e));
+                }
+            }
+        }
+    }
+
+    /// Track which peers are mid-way through pulling a file, so a local delete can cancel them
+    fn track_serving(&mut self, peer: PeerId, key: (String, String), is_last_chunk: bool) {
+        if is_last_chunk {
+            if let Some(peers) = self.serving_peers.get_mut(&key) {
+                peers.remove(&peer);
+                if peers.is_empty() {
+                    self.serving_peers.remove(&key);
+                }
+            }
+            if !self.serving_peers.contains_key(&key) {
+                self.served_versions.remove(&key);
+            }
+        } else {
+            self.serving_peers.entry(key).or_default().insert(peer);
+        }
+    }
+
+    /// Handle P2P events from the event channel
+    async fn handle_p2p_event(&mut self, event: SyndactylP2PEvent) {
+        match event {
+            SyndactylP2PEvent::GossipsubMessage { source, origin, data } => {
+                self.handle_gossipsub_message(source, origin, data);
+            }
+            SyndactylP2PEvent::ProvidersFound { key, providers } => {
+                self.handle_providers_found(key, providers);
+            }
+            SyndactylP2PEvent::KademliaEvent(info) => {
+                if self.log_throttle.allow("kademlia") {
+                    info!(%info, "Kademlia event");
+                }
+            }
+            SyndactylP2PEvent::NewListenAddr(addr) => {
+                info!(%addr, "Listening on");
+            }
+            SyndactylP2PEvent::FileTransferRequest { peer, request, channel } => {
+                self.handle_file_transfer_request(peer, request, channel);
+            }
+            SyndactylP2PEvent::FileTransferResponse { peer, response } => {
+                self.handle_file_transfer_response(peer, response);
+            }
+            SyndactylP2PEvent::FileChunkRequest { peer, request, channel } => {
+                self.handle_file_chunk_request(peer, request, channel);
+            }
+            SyndactylP2PEvent::RangeReadRequest { peer, request, channel } => {
+                self.enqueue_serve_request(peer, ServeRequest::RangeRead(request, channel));
+            }
+            SyndactylP2PEvent::CancelTransfer { peer, request, channel } => {
+                self.handle_cancel_transfer(peer, request, channel);
+            }
+            SyndactylP2PEvent::TransferError { peer, error } => {
+                self.handle_transfer_error(peer, error);
+            }
+        }
+    }
+
+    /// Handle Gossipsub messages (file events from other peers)
+    /// `source` relayed the message to us; `origin` published it, if it was signed
+    fn handle_gossipsub_message(&mut self, source: PeerId, origin: Option<PeerId>, data: Vec<u8>) {
+        let log_event = self.log_throttle.allow("gossip");
+        match wire::decode_file_event(&data) {
+            Ok(file_event) => {
+                if log_event {
+                    info!(peer = %source, event = ?file_event, "Received FileEventMessage from P2P");
+                }
+                
+                // Newer peers' event types mean nothing here, and their HMAC can't be checked
+                // once the name is lost, so they are dropped rather than counted as forgeries
+                if file_event.event_type == EventType::Other {
+                    debug!(peer = %source, observer = %file_event.observer, path = %file_event.path, "Event type not understood, ignoring");
+                    return;
+                }
+
+                // Verify HMAC if we have a shared secret for this observer
+                if let Some(observer_config) = self.observer_configs.get(&file_event.observer) {
+                    // A different identity is another mesh reusing the name, not an attack
+                    if let Some(remote_id) = &file_event.observer_id {
+                        let local_id = observer_config.shared_secret.as_ref().map(|secret| auth::observer_id(&file_event.observer, secret.expose()));
+                        if local_id.as_ref() != Some(remote_id) {
+                            if self.mismatched_observers.insert((file_event.observer.clone(), remote_id.clone())) {
+                                warn!(
+                                    peer = %source,
+                                    observer = %file_event.observer,
+                                    "Peer uses this observer name with a different shared secret - ignoring its events; rename one of the observers or align the secrets"
+                                );
+                            }
+                            return;
+                        }
+                    }
+                    if let Some(ref secret) = observer_config.shared_secret {
+                        // Verify HMAC
+                        if !auth::verify_hmac(&file_event, secret.expose()) {
+                            warn!(
+                                peer = %source,
+                                origin = ?origin,
+                                observer = %file_event.observer,
+                                "HMAC verification failed - rejecting unauthorized file event"
+                            );
+                            // Relays forward messages without checking them; only the publisher is to blame
+                            if let Some(origin) = origin {
+                                self.auth_failed(&origin, &file_event.observer, AuthFailure::BadSignature);
+                            }
+                            return;
+                        }
+                        if log_event {
+                            info!(peer = %source, observer = %file_event.observer, "HMAC verified successfully");
+                        }
+                    } else {
+                        warn!(
+                            peer = %source,
+                            observer = %file_event.observer,
+                            "No shared secret configured for observer - accepting unauthenticated message (INSECURE)"
+                        );
+                    }
+                } else {
+                    info!(observer = %file_event.observer, "Observer not configured locally, ignoring event");
+                    return;
+                }
+
+                // The shared secret may have leaked; a pinned observer also wants the right publisher
+                if !self.key_pins.allows_origin(&file_event.observer, origin.as_ref()) {
+                    warn!(
+                        peer = %source,
+                        origin = ?origin,
+                        observer = %file_event.observer,
+                        "Announcement was not published by a pinned key - rejecting it despite a valid HMAC"
+                    );
+                    self.auth_failed(&origin.unwrap_or(source), &file_event.observer, AuthFailure::UnpinnedKey);
+                    return;
+                }
+                // Whoever published it syncs the observer, even if it said otherwise before
+                if let Some(origin) = origin {
+                    self.departed.remove(&(origin, file_event.observer.clone()));
+                }
+                // Fetch from the pinned publisher rather than whoever relayed the announcement
+                let peer = match origin {
+                    Some(origin) if self.key_pins.is_pinned(&file_event.observer) => origin,
+                    _ => source,
+                };
+                self.dispatch_file_event(peer, file_event);
+            },
+            Err(e) => {
+                warn!(peer = %source, error = %e, size = data.len(), "Rejected FileEventMessage from P2P");
+            }
+        }
+    }
+
+    /// Route a verified remote file event
+    fn dispatch_file_event(&mut self, peer: PeerId, mut file_event: FileEventMessage) {
+        let sync = sync_id(&file_event.observer, &file_event.path, file_event.hash.as_deref());
+        let _span = traced(info_span!("receive", sync = %sync), &sync).entered();
+        // Forward to external systems before deciding whether to sync; re-announcements aren't news to them
+        let reannounced = file_event.details.as_deref() == Some(anti_entropy::DETAILS);
+        if !reannounced {
+            self.bridges.publish(&file_event);
+        }
+        // Gossip doesn't keep order; an operation older than one already seen would undo it
+        if !self.state.state_mut().event_order.admit(&file_event, unix_now()) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, event = %file_event.event_type, "Older than an operation already seen on the file, ignoring");
+            if let Some(info) = &file_event.transaction {
+                self.transactions.announce(&file_event.observer, info, &file_event.path, false);
+                self.try_commit_transaction(&info.id);
+            }
+            return;
+        }
+        // A download of the version it removed would bring the file back
+        if file_event.event_type == EventType::Remove && file_event.modified_time.is_some() {
+            let key = (file_event.observer.clone(), file_event.path.clone());
+            if self.cancel_download(&key, "removed by a newer operation") {
+                info!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "File removed on a peer, cancelled download of the older version");
+                self.finish_fetch(&key, Err("file was removed".to_string()));
+            }
+        }
+        // Large files are announced before they are hashed. They are fetched straight
+        // away, checked chunk by chunk, and written once the completing announcement
+        // supplies the hash; catalogued and transactional files wait for it instead.
+        if file_event.event_type.has_content() && file_event.hash.is_none() {
+            let on_demand = self.observer_configs.get(&file_event.observer).is_some_and(|obs| obs.mount.is_some());
+            match (file_event.size, file_event.modified_time) {
+                (Some(size), Some(modified_time)) if !on_demand && file_event.transaction.is_none() => {
+                    file_event.hash = Some(hash_pool::pending_version(size, modified_time));
+                }
+                _ => {
+                    debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Hash pending, waiting for the completing announcement");
+                    return;
+                }
+            }
+        } else if self.confirm_pending_download(&file_event) {
+            return;
+        }
+        if self.observer_configs.get(&file_event.observer).is_some_and(|obs| obs.announce_only == Some(true)) {
+            return;
+        }
+        // On-demand observers only record what exists; files are fetched when opened
+        if self.observer_configs.get(&file_event.observer).is_some_and(|obs| obs.mount.is_some()) {
+            self.record_catalog_event(peer, &file_event);
+            return;
+        }
+
+        // Check if this is a Create or Modify event with a file we should sync
+        if file_event.event_type.has_content() {
+            // Peers re-forward announcements after a restart; a version applied before isn't applied again
+            let seen = file_event.hash.as_deref()
+                .is_some_and(|hash| self.state.state().seen_events.contains(&file_event.observer, &file_event.path, hash));
+            if seen {
+                debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Version already applied, ignoring announcement");
+                return;
+            }
+            // A re-announced version only matters if we hold nothing newer, as when reconciling
+            if reannounced {
+                let current = self.state.state().index.get(&file_event.observer, &file_event.path).is_some_and(|local| {
+                    file_event.hash.as_ref() == Some(&local.hash)
+                        || file_event.modified_time.is_none_or(|remote| remote <= local.modified_time)
+                });
+                if current {
+                    return;
+                }
+            }
+            self.queue_incoming(peer, file_event);
+        } else if let Some(info) = &file_event.transaction {
+            // Other members still count towards the transaction being complete
+            self.transactions.announce(&file_event.observer, info, &file_event.path, false);
+            self.try_commit_transaction(&info.id);
+        }
+    }
+
+    /// Queue an announcement to be processed once the transfer tracker has room
+    /// Bulk transfers then can't hold up small files announced after them.
+    fn queue_incoming(&mut self, peer: PeerId, file_event: FileEventMessage) {
+        let key = (file_event.observer.clone(), file_event.path.clone());
+        let priority = Priority::classify(file_event.event_type, file_event.size);
+        if let Err((_, file_event)) = self.incoming.push(key, peer, priority, (peer, file_event)) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Peer has too many announcements queued, dropping");
+            return;
+        }
+        self.start_incoming();
+    }
+
+    /// Process queued announcements, most urgent first, while their sources have room
+    /// They wait in the queue rather than evicting transfers already under way.
+    fn start_incoming(&mut self) {
+        for _ in 0..INTAKE_BATCH_SIZE {
+            let tracker = &self.transfer_tracker;
+            let Some((_, (peer, file_event))) = self.incoming.pop_where(|(peer, _)| tracker.has_room(peer)) else {
+                break;
+            };
+            self.process_file_event(peer, file_event);
+        }
+    }
+
+    /// Process a file event and potentially request the file
+    fn process_file_event(&mut self, peer: PeerId, file_event: FileEventMessage) {
+        let sync = sync_id(&file_event.observer, &file_event.path, file_event.hash.as_deref());
+        let _span = traced(info_span!("request", sync = %sync), &sync).entered();
+        if !self.profile.allows_peer(&peer.to_string()) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Peer not synced with under the active profile, ignoring");
+            return;
+        }
+        if !self.sync_groups.allows(&file_event.observer, &peer.to_string()) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Peer is not in the observer's peers, ignoring");
+            self.auth_failed(&peer, &file_event.observer, AuthFailure::Unauthorized);
+            return;
+        }
+        if !self.key_pins.allows(&file_event.observer, &peer) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Peer's key is not pinned for the observer, ignoring");
+            self.auth_failed(&peer, &file_event.observer, AuthFailure::UnpinnedKey);
+            return;
+        }
+        if self.roles.get(&file_event.observer) == Some(&Role::Publisher) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "We publish this observer, ignoring announcement");
+            return;
+        }
+        if file_handler::is_internal_path(std::path::Path::new(&file_event.path)) {
+            warn!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Announcement for syndactyl's internal state, ignoring");
+            return;
+        }
+        // A newer operation may have arrived while this one was queued or deferred
+        if self.state.state().event_order.is_stale(&file_event) {
+            debug!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Superseded while queued, ignoring");
+            if let Some(info) = &file_event.transaction {
+                self.transactions.announce(&file_event.observer, info, &file_event.path, false);
+                self.try_commit_transaction(&info.id);
+            }
+            return;
+        }
+        // Check if we have this observer configured locally
+        if let Some(storage) = self.storages.get(&file_event.observer).cloned() {
+            // Hold the announcement until the sync window ends or AC power returns
+            if self.transfer_policy(&file_event.observer, file_event.size.unwrap_or(0)) == TransferPolicy::Paused {
+                if self.log_throttle.allow("schedule") {
+                    info!(observer = %file_event.observer, path = %file_event.path, "Transfers paused by sync window, low battery or request, deferring transfer");
+                }
+                let key = (file_event.observer.clone(), file_event.path.clone());
+                self.deferred_events.insert(key, (peer, file_event));
+                return;
+            }
+
+            let transaction = file_event.transaction.clone();
+            let mut started = false;
+            let relative_path = std::path::Path::new(&file_event.path);
+            let buried = self.state.state().tombstones.get(&file_event.observer, &file_event.path)
+                .zip(file_event.hash.as_deref())
+                .is_some_and(|(tombstone, hash)| tombstone.buries(hash, file_event.modified_time));
+            let too_large = !self.peer_capabilities.serves_size(&peer, file_event.size.unwrap_or(0));
+            // The same change is gossiped by several peers; later announcers of a version
+            // already downloading are kept as fallbacks rather than starting over
+            let key = (file_event.observer.clone(), file_event.path.clone());
+            let downloading = self.download_sources.contains_key(&key)
+                && file_event.hash.is_some()
+                && self.transfer_tracker.expected_hash(&key.0, &key.1) == file_event.hash.as_deref();
+            let mut local_hash = None;
+            
+            // Check if we need to request this file
+            let should_request = if buried {
+                info!(peer = %peer, observer = %file_event.observer, path = %file_event.path, "Peer holds a copy of a file deleted here, not fetching it");
+                false
+            } else if too_large {
+                warn!(peer = %peer, observer = %file_event.observer, path = %file_event.path, size = file_event.size, "File is larger than the peer serves, not fetching it");
+                false
+            } else if downloading {
+                self.add_fallback_source(&key, peer);
+                started = true;
+                false
+            } else if storage.exists(relative_path) {
+                // File exists, check if hash is different
+                if let Some(remote_hash) = &file_event.hash {
+                    if let Ok(hash) = storage.hash(relative_path) {
+                        let differs = &hash != remote_hash;
+                        local_hash = Some(hash);
+                        differs
+                    } else {
+                        true // Can't calculate local hash, request file
+                    }
+                } else {
+                    false // No hash provided, skip
+                }
+            } else {
+                true // File doesn't exist, request it
+            };
+            
+            // Only the appended bytes are fetched when our copy is the version appended to
+            let append_base = file_event.append.as_ref()
+                .filter(|append| transaction.is_none() && local_hash.as_deref() == Some(append.prefix_hash.as_str()))
+                .filter(|append| storage.size(relative_path).ok() == Some(append.offset))
+                .map(|append| append.offset);
+
+            if let Some((hash, base)) = file_event.hash.clone().zip(append_base).filter(|_| should_request) {
+                let size = file_event.size.unwrap_or(0);
+                match self.transfer_tracker.start_append(key.0.clone(), key.1.clone(), peer, size, hash.clone(), storage, base) {
+                    Ok(evicted) => {
+                        info!(observer = %key.0, path = %key.1, offset = base, appended = size.saturating_sub(base), "Requesting appended bytes from peer");
+                        self.handle_evictions(evicted);
+                        self.download_sources.insert(key.clone(), peer);
+                        self.download_fallbacks.remove(&key);
+                        self.fetch_appended(peer, &key, base, size);
+                    }
+                    Err(e) => {
+                        warn!(observer = %key.0, path = %key.1, error = %e, "Not requesting appended bytes");
+                    }
+                }
+            } else if let Some(hash) = file_event.hash.clone().filter(|_| should_request && transaction.is_none() && self.peer_resources.is_busy(&peer, Instant::now())) {
+                // Asked for once the peer has room, unless another announcer starts it sooner
+                info!(peer = %peer, observer = %key.0, path = %key.1, "Peer is busy serving, holding the download until it has room");
+                let at = self.peer_resources.retry_at(&peer, Instant::now());
+                self.busy_retries.insert(key.clone(), BusyRetry { peer, hash, size: file_event.size.unwrap_or(0), at });
+            } else if should_request {
+                if let Some(hash) = file_event.hash {
+                    info!(
+                        observer = %file_event.observer,
+                        path = %file_event.path,
+                        "Requesting file from peer"
+                    );
+                    
+                    let request = FileTransferRequest {
+                        observer: file_event.observer.clone(),
+                        path: file_event.path.clone(),
+                        hash: hash.clone(),
+                    };
+                    
+                    // Start tracking this transfer; chunks for untracked transfers are ignored,
+                    // so only request the file once the tracker has accepted it
+                    let size = file_event.size.unwrap_or(0);
+                    match self.transfer_tracker.start_transfer(
+                        file_event.observer.clone(),
+                        file_event.path.clone(),
+                        peer,
+                        size,
+                        hash,
+                        storage,
+                        transaction.as_ref().map(|t| t.id.clone()),
+                    ) {
+                        Ok(evicted) => {
+                            self.handle_evictions(evicted);
+                            if let Some(info) = &transaction {
+                                self.download_transactions.insert(key.clone(), info.id.clone());
+                            }
+                            self.download_sources.insert(key.clone(), peer);
+                            self.download_fallbacks.remove(&key);
+                            started = true;
+                            
+                            // Send request to the peer who sent the event
+                            self.find_helpers(&key, &request.hash);
+                            self.request_file(peer, request);
+                        }
+                        Err(e) => {
+                            warn!(observer = %file_event.observer, path = %file_event.path, error = %e, "Not requesting file");
+                        }
+                    }
+                } else {
+                    warn!(observer = %file_event.observer, path = %file_event.path, "No hash provided in file event");
+                }
+            } else if !buried && !downloading && !too_large {
+                info!(observer = %file_event.observer, path = %file_event.path, "File already up to date, skipping");
+                if let Some(hash) = &file_event.hash {
+                    self.transfer_tracker.record_synced(&file_event.observer, &file_event.path, storage.as_ref());
+                    self.state.state_mut().seen_events.record(&file_event.observer, &file_event.path, hash, unix_now());
+                }
+            }
+
+            if let Some(info) = &transaction {
+                self.transactions.announce(&file_event.observer, info, &file_event.path, started);
+                self.try_commit_transaction(&info.id);
+            }
+        } else {
+            info!(observer = %file_event.observer, "Observer not configured locally, ignoring event");
+        }
+    }
+
+    /// Queue an incoming file transfer request for fair serving
+    fn handle_file_transfer_request(
+        &mut self,
+        peer: PeerId,
+        request: FileTransferRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    ) {
+        self.enqueue_serve_request(peer, ServeRequest::FileTransfer(request, channel));
+    }
+
+    /// Queue an incoming file chunk request for fair serving
+    fn handle_file_chunk_request(
+        &mut self,
+        peer: PeerId,
+        request: FileChunkRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    ) {
+        self.enqueue_serve_request(peer, ServeRequest::FileChunk(request, channel));
+    }
+
+    /// Add a serve request to the peer's queue, refusing it if the peer already has too much outstanding work
+    fn enqueue_serve_request(&mut self, peer: PeerId, request: ServeRequest) {
+        if !self.observer_configs.contains_key(request.target().0) {
+            // Tell the peer to stop asking rather than leaving its request to time out
+            let (channel, error) = request.into_error(TransferErrorKind::NotServing);
+            debug!(peer = %peer, observer = %error.observer, "Observer not configured locally, telling peer");
+            self.p2p.send_transfer_error(channel, error);
+            return;
+        }
+        let (observer, path) = request.target();
+        if !self.may_serve(&peer, observer, path) {
+            return;
+        }
+        if let Err(rejected) = self.serve_queue.push(peer, request) {
+            // Dropping the response channel fails the request on the peer's side
+            let (observer, path) = rejected.target();
+            warn!(
+                peer = %peer,
+                observer = %observer,
+                path = %path,
+                queued = self.serve_queue.queued_for(&peer),
+                "Peer has too many queued requests, rejecting"
+            );
+        }
+    }
+
+    /// Whether `peer` may pull files of `observer` under the profile, sync groups and key pins
+    /// Refusals by the latter two count as failed authentications.
+    fn may_serve(&mut self, peer: &PeerId, observer: &str, path: &str) -> bool {
+        if !self.profile.allows_observer(observer) || !self.profile.allows_peer(&peer.to_string()) {
+            debug!(peer = %peer, observer = %observer, path = %path, "Not serving outside the active sync profile");
+            return false;
+        }
+        if !self.sync_groups.allows(observer, &peer.to_string()) {
+            warn!(peer = %peer, observer = %observer, path = %path, "Peer is not in the observer's peers, refusing");
+            self.auth_failed(peer, observer, AuthFailure::Unauthorized);
+            return false;
+        }
+        if !self.key_pins.allows(observer, peer) {
+            warn!(peer = %peer, observer = %observer, path = %path, "Peer's key is not pinned for the observer, refusing");
+            self.auth_failed(peer, observer, AuthFailure::UnpinnedKey);
+            return false;
+        }
+        true
+    }
+
+    /// Serve up to SERVE_BATCH_SIZE queued requests, rotating between peers
+    fn serve_queued_requests(&mut self) {
+        for _ in 0..SERVE_BATCH_SIZE {
+            let Some((peer, request)) = self.serve_queue.pop() else {
+                break;
+            };
+            self.serve_request(peer, request);
+        }
+    }
+
+    fn serve_request(&mut self, peer: PeerId, request: ServeRequest) {
+        match request {
+            ServeRequest::FileTransfer(request, channel) => self.serve_file_transfer_request(peer, request, channel),
+            ServeRequest::FileChunk(request, channel) => self.serve_file_chunk_request(peer, request, channel),
+            ServeRequest::RangeRead(request, channel) => self.serve_range_read(peer, request, channel),
+        }
+    }
+
+    /// Hold a request until the version it asks for is hashed
+    fn park_serve(&mut self, peer: PeerId, request: ServeRequest) {
+        let (observer, path) = request.target();
+        let key = (observer.to_string(), path.to_string());
+        self.parked_serves.entry(key).or_default().push((peer, request));
+    }
+
+    /// Serve a file transfer request with the first chunk of the file
+    fn serve_file_transfer_request(
+        &mut self,
+        peer: PeerId,
+        request: FileTransferRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    ) {
+        let sync = sync_id(&request.observer, &request.path, Some(&request.hash));
+        let _span = traced(info_span!("serve", sync = %sync), &sync).entered();
+        let log_event = self.log_throttle.allow("serve");
+        if log_event {
+            info!(peer = %peer, observer = %request.observer, path = %request.path, "Received file transfer request");
+        }
+
+        // Transfers already under way go on; new ones wait until there is room
+        let key = (request.observer.clone(), request.path.clone());
+        let continuing = self.serving_peers.get(&key).is_some_and(|peers| peers.contains(&peer));
+        let admitted = if continuing { Ok(()) } else { peer_resources::admit(self.serving_load().serving as usize, self.max_serving_transfers) };
+        if let Err(retry_after_secs) = admitted {
+            debug!(peer = %peer, observer = %request.observer, path = %request.path, retry_after_secs, "Serving too many transfers, refusing as busy");
+            self.p2p.send_transfer_error(channel, TransferError {
+                observer: request.observer.clone(),
+                path: request.path.clone(),
+                requested_hash: request.hash.clone(),
+                kind: TransferErrorKind::Busy { retry_after_secs },
+            });
+            return;
+        }
+        
+        // Check if we have this observer configured
+        if let Some(observer_config) = self.observer_configs.get(&request.observer) {
+            // Peers outside the observer's sync group were turned away when the request was queued
+            if observer_config.shared_secret.is_some() {
+                if log_event {
+                    info!(peer = %peer, observer = %request.observer, "Observer has authentication enabled");
+                }
+            } else {
+                warn!(peer = %peer, observer = %request.observer, "Observer has no authentication - serving file (INSECURE)");
+            }
+            
+            // Announce-only observers have no files to serve
+            let Some(storage) = self.storages.get(&request.observer).cloned() else {
+                return;
+            };
+            let total_size = match self.check_served_version(&storage, &request.observer, &request.path, &request.hash) {
+                Ok(Served::Ready(size)) => size,
+                Ok(_) => {
+                    self.park_serve(peer, ServeRequest::FileTransfer(request, channel));
+                    return;
+                }
+                Err(kind) => {
+                    self.p2p.send_transfer_error(channel, TransferError {
+                        observer: request.observer.clone(),
+                        path: request.path.clone(),
+                        requested_hash: request.hash.clone(),
+                        kind,
+                    });
+                    return;
+                }
+            };
+
+            // Generate only the first chunk for initial response
+            let relative_path = std::path::Path::new(&request.path);
+            let chunk_size = self.tuning(&request.observer).chunk_size;
+            let first_chunk = match self.chunk_cache.get(&request.hash, 0, chunk_size) {
+                Some(cached) => Ok(FileTransferResponse {
+                    observer: request.observer.clone(),
+                    path: request.path.clone(),
+                    is_last_chunk: cached.data.len() as u64 >= total_size,
+                    segment_hash: Some(cached.segment_hash),
+                    segment_hmac: None,
+                    data: cached.data,
+                    offset: 0,
+                    total_size,
+                    hash: request.hash.clone(),
+                    encoding: None,
+                }),
+                None => generate_first_chunk(&request.observer, relative_path, storage.as_ref(), &request.hash, chunk_size)
+                    .inspect(|chunk| self.chunk_cache.insert(&request.hash, 0, chunk_size, CachedChunk {
+                        data: chunk.data.clone(),
+                        segment_hash: chunk.segment_hash.clone().unwrap_or_default(),
+                    })),
+            };
+            match first_chunk {
+                Ok(mut first_chunk) => {
+                    self.sign_segment(&mut first_chunk);
+                    if log_event {
+                        info!(
+                            observer = %request.observer,
+                            path = %request.path,
+                            size = first_chunk.total_size,
+                            is_last = first_chunk.is_last_chunk,
+                            "Sending first file chunk"
+                        );
+                    }
+                    self.record_served(&peer, &first_chunk.observer, &first_chunk.path, first_chunk.offset, first_chunk.data.len() as u64);
+                    self.track_serving(peer, (first_chunk.observer.clone(), first_chunk.path.clone()), first_chunk.is_last_chunk);
+                    self.p2p.send_file_response(channel, first_chunk);
+                }
+                Err(e) => {
+                    error!(
+                        observer = %request.observer,
+                        path = %request.path,
+                        error = %e,
+                        "Failed to generate first chunk"
+                    );
+                }
+            }
+        } else {
+            warn!(observer = %request.observer, "Observer not configured locally");
+        }
+    }
+
+    /// Handle file transfer response
+    fn handle_file_transfer_response(&mut self, peer: PeerId, response: FileTransferResponse) {
+        let sync = sync_id(&response.observer, &response.path, Some(&response.hash));
+        let _span = traced(info_span!("write", sync = %sync), &sync).entered();
+        if self.log_throttle.allow("transfer") {
+            info!(
+                peer = %peer,
+                observer = %response.observer,
+                path = %response.path,
+                offset = response.offset,
+                size = response.data.len(),
+                is_last = response.is_last_chunk,
+                "Received file transfer response"
+            );
+        }
+
+        // Chunks can still arrive for a transfer that was just cancelled, and only
+        // the peer we are downloading from may supply them
+        let key = (response.observer.clone(), response.path.clone());
+        let helping = self.download_helpers.get(&key).is_some_and(|helpers| helpers.contains(&peer));
+        if self.download_sources.get(&key) != Some(&peer) && !helping {
+            debug!(peer = %peer, observer = %response.observer, path = %response.path, "Ignoring chunk for transfer no longer in progress");
+            return;
+        }
+        self.state.state_mut().bandwidth.record_received(&response.observer, &peer.to_string(), response.data.len() as u64);
+        self.peer_stats.record_received(peer, response.data.len() as u64, Instant::now());
+
+        if response.segment_hash.as_ref().is_some_and(|expected| *expected != segment_hash(&response.data)) {
+            self.transfer_tracker.cancel_transfer(&response.observer, &response.path);
+            let error = format!("Chunk at offset {} does not match its segment hash", response.offset);
+            self.download_finished(&key, &response.hash, Err(error));
+            return;
+        }
+        // Nothing else vouches for a version not hashed yet until its completing announcement
+        let secret = self.observer_configs.get(&response.observer).and_then(|config| config.shared_secret.as_ref());
+        let pending = hash_pool::parse_pending_version(&response.hash).is_some();
+        if let Some(secret) = secret.filter(|_| pending) {
+            if !auth::verify_segment_hmac(&response, secret.expose()) {
+                self.transfer_tracker.cancel_transfer(&response.observer, &response.path);
+                let error = format!("Chunk at offset {} is not signed with the observer's secret", response.offset);
+                self.download_finished(&key, &response.hash, Err(error));
+                return;
+            }
+        }
+        
+        // Add chunk to transfer tracker
+        let added = match self.transfer_tracker.add_chunk(
+            &response.observer,
+            &response.path,
+            response.offset,
+            response.data.clone(),
+            response.is_last_chunk,
+        ) {
+            Ok((completed, evicted)) => {
+                self.handle_evictions(evicted);
+                Ok(completed)
+            }
+            Err(e) => Err(e),
+        };
+        match added {
+            Ok(Some(file_path)) => self.download_finished(&key, &response.hash, Ok(file_path)),
+            Ok(None) if self.transfer_tracker.is_awaiting_hash(&response.observer, &response.path) => {
+                info!(observer = %response.observer, path = %response.path, "All chunks received, waiting for the file's hash");
+            }
+            Ok(None) => {
+                if self.log_throttle.allow("transfer") {
+                    info!(
+                        observer = %response.observer,
+                        path = %response.path,
+                        "Chunk received, requesting more chunks"
+                    );
+                }
+                // The first chunk comes with the transfer; the rest are streamed or requested from here on
+                let next_offset = response.offset + response.data.len() as u64;
+                if self.bulk_downloads.contains_key(&key) || self.start_bulk_download(peer, &key, next_offset, &response.hash) {
+                    return;
+                }
+                let total_size = self.transfer_tracker.total_size(&response.observer, &response.path).unwrap_or(next_offset);
+                // Helpers found before the first chunk arrived start now
+                let helpers: Vec<PeerId> = if self.chunk_cursors.contains_key(&key) {
+                    Vec::new()
+                } else {
+                    self.download_helpers.get(&key).map(|helpers| helpers.iter().copied().collect()).unwrap_or_default()
+                };
+                if !self.chunk_cursors.contains_key(&key) {
+                    let chunk_size = self.peer_capabilities.chunk_size(&peer, self.tuning(&key.0).chunk_size as u64);
+                    self.transfer_tracker.set_chunk_layout(&key.0, &key.1, next_offset, chunk_size);
+                    self.chunk_cursors.insert(key, ChunkCursor {
+                        next_offset,
+                        total_size,
+                        retry: Vec::new(),
+                        raw: HashSet::new(),
+                        failures: 0,
+                        hash: response.hash.clone(),
+                        chunk_size,
+                    });
+                }
+                self.request_more_chunks(peer);
+                for helper in helpers {
+                    self.request_more_chunks(helper);
+                }
+            }
+            Err(e) => self.download_finished(&key, &response.hash, Err(e)),
+        }
+    }
+
+    /// Fetch the bytes a peer appended to a file from `offset` on
+    /// There is no first chunk to wait for, so chunk requests start straight away;
+    /// the source only streams transfers a chunk request started.
+    fn fetch_appended(&mut self, peer: PeerId, key: &(String, String), offset: u64, total_size: u64) {
+        let chunk_size = self.peer_capabilities.chunk_size(&peer, self.tuning(&key.0).chunk_size as u64);
+        self.chunk_cursors.insert(key.clone(), ChunkCursor {
+            next_offset: offset,
+            total_size,
+            retry: Vec::new(),
+            raw: HashSet::new(),
+            failures: 0,
+            hash: hash.to_string(),
+            chunk_size,
+        });
+        self.request_more_chunks(peer);
+    }
+
+    /// Wrap up a download that was written (or staged), or that failed
+    fn download_finished(&mut self, key: &(String, String), hash: &str, result: Result<std::path::PathBuf, String>) {
+        let (observer, path) = key;
+        self.download_sources.remove(key);
+        self.download_fallbacks.remove(key);
+        self.chunk_cursors.remove(key);
+        self.end_bulk_download(key);
+        self.download_helpers.remove(key);
+        self.in_use_retries.remove(key);
+        match result {
+            Ok(file_path) => {
+                self.state.state_mut().seen_events.record(observer, path, hash, unix_now());
+                if self.observer_configs.get(observer).is_some_and(|obs| obs.mount.is_some()) {
+                    if let Ok(mut catalog) = self.catalog.write() {
+                        catalog.mark_cached(observer, path, hash);
+                        self.catalog_dirty = true;
+                    }
+                    self.finish_fetch(key, Ok(()));
+                }
+                if let Some(id) = self.download_transactions.remove(key) {
+                    info!(observer = %observer, path = %path, transaction = %id, "File transfer completed and staged");
+                    self.transactions.staged(&id, path);
+                    self.try_commit_transaction(&id);
+                } else {
+                    info!(observer = %observer, path = %path, file = %file_path.display(), "File transfer completed and written to disk");
+                }
+            }
+            Err(e) if self.transfer_tracker.take_in_use(key) => {
+                info!(observer = %observer, path = %path, error = %e, "Local copy is in use, replacing it once it is released");
+                self.retry_in_use_later(key.clone(), Some(hash.to_string()));
+            }
+            Err(e) => {
+                self.abandon_transaction_member(key);
+                self.finish_fetch(key, Err(e.clone()));
+                error!(observer = %observer, path = %path, error = %e, "Failed to process file chunk");
+            }
+        }
+    }
+
+    fn retry_in_use_later(&mut self, key: (String, String), hash: Option<String>) {
+        let at = Instant::now() + in_use::RETRY_DELAYS[0];
+        self.in_use_retries.insert(key, InUseRetry { hash, attempt: 0, at });
+    }
+
+    /// Try again to move downloads over local copies that were in use
+    /// Each attempt is a single rename, so the event loop never waits for a file
+    /// to be released; the observer's in_use policy decides what the last one does.
+    fn retry_in_use_replacements(&mut self, now: Instant) {
+        let due: Vec<(String, String)> = self.in_use_retries.iter()
+            .filter(|(_, retry)| retry.at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in due {
+            let Some(mut retry) = self.in_use_retries.remove(&key) else {
+                continue;
+            };
+            let Some(storage) = self.storages.get(&key.0).cloned() else {
+                continue;
+            };
+            let next_delay = in_use::RETRY_DELAYS.get(retry.attempt + 1).copied();
+            match (storage.retry_replace(std::path::Path::new(&key.1), next_delay.is_none()), next_delay) {
+                (Ok(Replaced::Now), _) => {
+                    if let Some(hash) = &retry.hash {
+                        self.state.state_mut().seen_events.record(&key.0, &key.1, hash, unix_now());
+                    }
+                    info!(observer = %key.0, path = %key.1, "Local copy released, replaced it with the download");
+                }
+                (Ok(Replaced::Later), Some(delay)) => {
+                    retry.attempt += 1;
+                    retry.at = now + delay;
+                    self.in_use_retries.insert(key, retry);
+                }
+                (Ok(Replaced::AtReboot), _) => {
+                    warn!(observer = %key.0, path = %key.1, "Local copy is still in use, it will be replaced at the next reboot");
+                }
+                (Ok(Replaced::Later), None) => {
+                    error!(observer = %key.0, path = %key.1, "Local copy stayed in use, gave up replacing it");
+                }
+                (Err(e), _) => {
+                    error!(observer = %key.0, path = %key.1, error = %e, "Failed to replace local copy that was in use");
+                }
+            }
+        }
+    }
+
+    /// Hand the hash from a completing announcement to a download started before it was known
+    /// Returns false if no download of that version is in progress.
+    fn confirm_pending_download(&mut self, file_event: &FileEventMessage) -> bool {
+        let (Some(hash), Some(size), Some(modified_time)) = (&file_event.hash, file_event.size, file_event.modified_time) else {
+            return false;
+        };
+        let pending = hash_pool::pending_version(size, modified_time);
+        if self.transfer_tracker.expected_hash(&file_event.observer, &file_event.path) != Some(pending.as_str()) {
+            return false;
+        }
+        let key = (file_event.observer.clone(), file_event.path.clone());
+        match self.transfer_tracker.confirm_hash(&file_event.observer, &file_event.path, hash.clone()) {
+            Ok(None) => debug!(observer = %file_event.observer, path = %file_event.path, "Hash arrived while downloading"),
+            Ok(Some(file_path)) => self.download_finished(&key, hash, Ok(file_path)),
+            Err(e) => self.download_finished(&key, hash, Err(e)),
+        }
+        true
+    }
+
+    /// Current policy for a transfer of `size` bytes, from sync windows and battery state
+    fn transfer_policy(&self, observer: &str, size: u64) -> TransferPolicy {
+        if self.paused_all
+            || self.paused_observers.contains(observer)
+            || self.unavailable_observers.contains(observer)
+            || self.low_space_observers.contains(observer)
+            || !self.profile.allows_observer(observer)
+            || self.power.should_defer_transfer(size)
+        {
+            return TransferPolicy::Paused;
+        }
+        self.schedules.get(observer)
+            .map(|schedule| schedule.current_policy())
+            .unwrap_or(TransferPolicy::Normal)
+    }
+
+    /// Chunk size, parallelism, compression and append threshold configured for an observer's transfers
+    fn tuning(&self, observer: &str) -> TransferTuning {
+        self.transfer_tuning.get(observer).copied().unwrap_or_default()
+    }
+
+    /// Policy for an in-progress download
+    fn download_policy(&self, observer: &str, path: &str) -> TransferPolicy {
+        let size = self.transfer_tracker.total_size(observer, path).unwrap_or(0);
+        self.transfer_policy(observer, size)
+    }
+
+    /// Note observers whose directory disappeared or came back
+    /// Their watcher threads restart watching and reconcile on their own.
+    fn check_observer_roots(&mut self) {
+        for (name, observer) in &self.observer_configs {
+            if observer.announce_only == Some(true) {
+                continue;
+            }
+            let available = file_handler::root_available(std::path::Path::new(&observer.path), observer.removable == Some(true));
+            if !available && self.unavailable_observers.insert(name.clone()) {
+                warn!(observer = %name, path = %observer.path, "Observer directory unavailable, holding downloads until it returns");
+            } else if available && self.unavailable_observers.remove(name) {
+                info!(observer = %name, path = %observer.path, "Observer directory available again");
+            }
+        }
+    }
+
+    /// Pause downloads into observers whose volume is running out of space, and resume them once it's freed
+    /// Paused announcements are queued like any other and replayed on resume.
+    fn check_disk_space(&mut self) {
+        for (name, observer) in &self.observer_configs {
+            if observer.announce_only == Some(true) || observer.mount.is_some() || self.unavailable_observers.contains(name) {
+                continue;
+            }
+            let min_free = observer.min_free_mb.unwrap_or(disk_space::DEFAULT_MIN_FREE_MB).saturating_mul(1024 * 1024);
+            let available = match disk_space::available_bytes(std::path::Path::new(&observer.path)) {
+                Ok(available) => available,
+                Err(e) => {
+                    debug!(observer = %name, error = %e, "Could not read free disk space");
+                    continue;
+                }
+            };
+            let was_low = self.low_space_observers.contains(name);
+            let low = disk_space::is_low(was_low, available, min_free);
+            if low == was_low {
+                continue;
+            }
+            if low {
+                self.low_space_observers.insert(name.clone());
+                warn!(observer = %name, path = %observer.path, available_bytes = available, min_free_bytes = min_free, "Low on disk space, pausing downloads until space is freed");
+            } else {
+                self.low_space_observers.remove(name);
+                info!(observer = %name, path = %observer.path, available_bytes = available, "Disk space freed, resuming downloads");
+            }
+            self.bridges.disk_space(&DiskSpaceAlert {
+                observer: name.clone(),
+                path: observer.path.clone(),
+                low,
+                available_bytes: available,
+                min_free_bytes: min_free,
+                timestamp: unix_now(),
+            });
+        }
+    }
+
+    /// Re-read the power source, logging when throttling starts or stops
+    fn refresh_power(&mut self) {
+        let status = self.power.refresh();
+        let was_constrained = self.power_status.as_ref().is_some_and(|s| s.constrained);
+        if let Some(current) = &status {
+            if current.constrained && !was_constrained {
+                info!(battery_percent = ?current.battery_percent, "Low battery, throttling hashing and pausing large transfers");
+            } else if !current.constrained && was_constrained {
+                info!(on_battery = current.on_battery, "Power restored, resuming large transfers");
+            }
+        }
+        self.power_status = status;
+    }
+
+    /// Answer a request from the control socket
+    fn handle_control_command(&mut self, command: ControlCommand) {
+        let response = match command.request {
+            // Answered once the peer replies
+            ControlRequest::Peek { observer, path, peer, bytes, lines } => {
+                self.start_peek(observer, path, peer, bytes, lines, command.reply);
+                return;
+            }
+            ControlRequest::Diff { observer, peer } => {
+                self.start_diff(observer, peer, command.reply);
+                return;
+            }
+            ControlRequest::Status => {
+                let discovery = self.discovery.status();
+                ControlResponse::Status(DaemonStatus {
+                    peer_id: self.p2p.peer_id().to_string(),
+                    connected_peers: self.connected_peers.len(),
+                    active_downloads: self.download_sources.len(),
+                    queued_serve_requests: self.serve_queue.len(),
+                    deferred_transfers: self.deferred_events.len(),
+                    queued_downloads: self.incoming.len(),
+                    stale_observers: {
+                        let mut names: Vec<String> = self.stale_observers.keys().cloned().collect();
+                        names.sort();
+                        names
+                    },
+                    power: self.power_status.clone(),
+                    profile: Some(self.profile.name.clone()).filter(|name| !name.is_empty()),
+                    listen_addresses: self.p2p.swarm.listeners().map(|a| a.to_string()).collect(),
+                    external_addresses: self.p2p.swarm.external_addresses().map(|a| a.to_string()).collect(),
+                    held_deletions: self.deletion_guard.held_counts(),
+                    bootstrap_peers: discovery.bootstrap_peers,
+                    dht_bootstrapped: discovery.dht_bootstrapped,
+                    discovery: self.discovery.names(),
+                    auth_failures: self.auth_failures.total(),
+                    observer_states: self.lifecycles.statuses(),
+                    unavailable_observers: {
+                        let mut names: Vec<String> = self.unavailable_observers.iter().cloned().collect();
+                        names.sort();
+                        names
+                    },
+                    low_space_observers: {
+                        let mut names: Vec<String> = self.low_space_observers.iter().cloned().collect();
+                        names.sort();
+                        names
+                    },
+                })
+            }
+            ControlRequest::Peers => {
+                let now = Instant::now();
+                ControlResponse::Peers {
+                    peers: self.connected_peers.iter().map(|peer| self.peer_stats.info(peer, now)).collect(),
+                }
+            }
+            ControlRequest::UseProfile { name } => self.use_profile(name),
+            ControlRequest::ClearProfile => self.clear_profile(),
+            ControlRequest::Transfers => ControlResponse::Transfers {
+                transfers: self.transfer_tracker.progress(Instant::now()),
+            },
+            ControlRequest::Conflicts => ControlResponse::Conflicts {
+                conflicts: self.transfer_tracker.recent_conflicts(),
+            },
+            ControlRequest::Pause { observer } => self.set_paused(observer, true),
+            ControlRequest::Resume { observer } => self.set_paused(observer, false),
+            ControlRequest::Rescan { observer } => self.start_rescan(observer),
+            ControlRequest::ConfirmDeletions { observer } => {
+                let released = self.deletion_guard.confirm(observer.as_deref());
+                self.save_held_deletions();
+                let count = released.len();
+                for msg in released {
+                    self.announce_local_event(msg);
+                }
+                info!(count, "Held deletions confirmed");
+                ControlResponse::Done { message: format!("Announced {} held deletion(s)", count) }
+            }
+            ControlRequest::PathStatus { path } => ControlResponse::PathStatus(self.path_status(std::path::Path::new(&path))),
+            ControlRequest::FileStatus { paths } => ControlResponse::FileStatuses {
+                files: paths.iter().map(|path| self.file_status(path)).collect(),
+            },
+            ControlRequest::WatchFiles => ControlResponse::Error {
+                message: "File status can only be watched on the control socket or over gRPC".to_string(),
+            },
+            ControlRequest::WatchTray => ControlResponse::Error {
+                message: "Tray state can only be watched on the control socket".to_string(),
+            },
+            ControlRequest::DiscardDeletions { observer } => {
+                let count = self.deletion_guard.discard(observer.as_deref());
+                self.save_held_deletions();
+                info!(count, "Held deletions discarded");
+                ControlResponse::Done { message: format!("Discarded {} held deletion(s); peers keep their copies", count) }
+            }
+            ControlRequest::Shutdown => {
+                info!("Shutdown requested over the control socket");
+                self.shutdown_requested = true;
+                ControlResponse::Done { message: "Shutting down".to_string() }
+            }
+            #[cfg(feature = "fault-injection")]
+            ControlRequest::InjectFaults { plan } => {
+                warn!(plan = ?plan, "Injecting faults into received chunks");
+                self.faults.set(plan);
+                ControlResponse::Done { message: "Fault plan applied".to_string() }
+            }
+        };
+        let _ = command.reply.send(response);
+    }
+
+    /// Switch sync profile, rescanning observers it re-enables so peers catch up on local changes
+    /// Announcements deferred while an observer was disabled are replayed by the schedule check.
+    fn use_profile(&mut self, name: String) -> ControlResponse {
+        let Some(profile) = self.profiles.get(&name).cloned() else {
+            return ControlResponse::Error { message: format!("Unknown profile '{}'", name) };
+        };
+        self.switch_profile(profile, Some(name.clone()));
+        info!(profile = %name, "Switched sync profile");
+        ControlResponse::Done { message: format!("Using profile {}", name) }
+    }
+
+    /// Drop the profile picked at runtime, going back to the default profile or to syncing everything
+    fn clear_profile(&mut self) -> ControlResponse {
+        let profile = self.default_profile.as_ref()
+            .and_then(|name| self.profiles.get(name).cloned())
+            .unwrap_or_else(Profile::unrestricted);
+        let message = match profile.name.as_str() {
+            "" => "Syncing every observer with every peer".to_string(),
+            name => format!("Using default profile {}", name),
+        };
+        self.switch_profile(profile, None);
+        info!("Cleared sync profile");
+        ControlResponse::Done { message }
+    }
+
+    fn switch_profile(&mut self, profile: Profile, selected: Option<String>) {
+        let reenabled: Vec<String> = self.observer_configs.keys()
+            .filter(|observer| !self.profile.allows_observer(observer) && profile.allows_observer(observer))
+            .cloned()
+            .collect();
+        self.profile = profile;
+        self.state.state_mut().active_profile = selected;
+
+        for observer in reenabled {
+            if let ControlResponse::Error { message } = self.start_rescan(Some(observer.clone())) {
+                warn!(observer = %observer, error = %message, "Could not rescan re-enabled observer");
+            }
+        }
+    }
+
+    /// Observer whose directory holds `path`, and the path's wire form relative to it
+    /// The innermost observer wins when directories are nested.
+    fn locate(&self, path: &std::path::Path) -> Option<(String, String)> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.observer_configs.values()
+            .filter(|obs| !obs.path.is_empty())
+            .filter_map(|obs| {
+                let root = std::path::Path::new(&obs.path);
+                let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+                let relative = path.strip_prefix(&root).ok()?;
+                let relative = relative.components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Some((root.components().count(), obs.name.clone(), relative))
+            })
+            .max_by_key(|(depth, _, _)| *depth)
+            .map(|(_, observer, relative)| (observer, relative))
+    }
+
+    /// Sync state of the observer directory holding `path`, or of the part below it
+    fn path_status(&self, path: &std::path::Path) -> PathStatus {
+        let Some((observer, relative)) = self.locate(path) else {
+            return PathStatus { observer: None, state: SyncState::Unwatched, downloading: 0, pending: 0 };
+        };
+
+        let below = |(obs, file): &(String, String)| {
+            *obs == observer && (relative.is_empty() || *file == relative || file.starts_with(&format!("{}/", relative)))
+        };
+        let downloading = self.download_sources.keys().filter(|key| below(key)).count();
+        let pending = self.deferred_events.keys().filter(|key| below(key)).count()
+            + self.incoming.keys().filter(|key| below(key)).count()
+            + self.pending_chunks.keys().filter(|key| below(key)).count()
+            + self.deletion_guard.held_below(&observer, &relative);
+        let state = if self.unavailable_observers.contains(&observer) {
+            SyncState::Unavailable
+        } else if self.paused_all || self.paused_observers.contains(&observer) {
+            SyncState::Paused
+        } else if downloading > 0 {
+            SyncState::Syncing
+        } else if pending > 0 {
+            SyncState::Pending
+        } else if self.connected_peers.is_empty() {
+            SyncState::Offline
+        } else {
+            SyncState::Synced
+        };
+        PathStatus { observer: Some(observer), state, downloading, pending }
+    }
+
+    /// Sync state of one file, for overlay icons; directories get their path_status
+    fn file_status(&self, path: &str) -> FileStatus {
+        let absolute = std::path::Path::new(path);
+        let Some((observer, relative)) = self.locate(absolute) else {
+            return FileStatus { path: path.to_string(), observer: String::new(), state: SyncState::Unwatched };
+        };
+        let state = if absolute.is_dir() {
+            self.path_status(absolute).state
+        } else if let Some(state) = self.status_feed.state(&observer, &relative) {
+            state
+        } else if absolute.exists() {
+            SyncState::Synced
+        } else {
+            SyncState::Unwatched
+        };
+        FileStatus { path: path.to_string(), observer, state }
+    }
+
+    /// Publish files that started or finished transferring since the last update
+    fn update_status_feed(&mut self) {
+        let mut current = HashMap::new();
+        for key in self.deferred_events.keys().chain(self.pending_chunks.keys()) {
+            current.insert(key.clone(), SyncState::Pending);
+        }
+        for key in self.download_sources.keys().chain(self.serving_peers.keys()) {
+            current.insert(key.clone(), SyncState::Syncing);
+        }
+        let roots: HashMap<&str, &str> = self.observer_configs.values()
+            .map(|obs| (obs.name.as_str(), obs.path.as_str()))
+            .collect();
+        let absolute = |observer: &str, path: &str| {
+            let root = roots.get(observer).copied().unwrap_or_default();
+            std::path::Path::new(root).join(path).to_string_lossy().into_owned()
+        };
+        let settled = |observer: &str, path: &str| {
+            if std::path::Path::new(&absolute(observer, path)).exists() { SyncState::Synced } else { SyncState::Unwatched }
+        };
+        self.status_feed.update(current, settled, absolute);
+    }
+
+    /// Publish the peer count and what each observer is doing, if either changed
+    fn update_tray_feed(&self) {
+        let busy: HashSet<&str> = self.download_sources.keys()
+            .chain(self.serving_peers.keys())
+            .chain(self.pending_chunks.keys())
+            .map(|(observer, _)| observer.as_str())
+            .chain(self.reconciling.keys().map(|(_, observer)| observer.as_str()))
+            .chain(self.stale_observers.keys().map(String::as_str))
+            .collect();
+        let observers = self.observer_configs.keys()
+            .map(|name| {
+                let activity = if self.unavailable_observers.contains(name) || self.low_space_observers.contains(name) {
+                    Activity::Error
+                } else if busy.contains(name.as_str()) {
+                    Activity::Syncing
+                } else {
+                    Activity::Idle
+                };
+                (name.clone(), activity)
+            })
+            .collect();
+        self.tray_feed.update(self.connected_peers.len(), observers);
+    }
+
+    /// Pause or resume downloads for one observer, or all of them
+    /// Paused announcements are deferred like those outside a sync window.
+    fn set_paused(&mut self, observer: Option<String>, paused: bool) -> ControlResponse {
+        let action = if paused { "paused" } else { "resumed" };
+        match observer {
+            Some(name) => {
+                if !self.observer_configs.contains_key(&name) {
+                    return ControlResponse::Error { message: format!("Unknown observer '{}'", name) };
+                }
+                if paused {
+                    self.paused_observers.insert(name.clone());
+                } else {
+                    self.paused_observers.remove(&name);
+                }
+                info!(observer = %name, "Downloads {} by request", action);
+                self.update_paused_lifecycles();
+                ControlResponse::Done { message: format!("Downloads {} for {}", action, name) }
+            }
+            None => {
+                self.paused_all = paused;
+                if !paused {
+                    self.paused_observers.clear();
+                }
+                info!("Downloads {} by request", action);
+                self.update_paused_lifecycles();
+                ControlResponse::Done { message: format!("Downloads {} for all observers", action) }
+            }
+        }
+    }
+
+    /// Show observers whose downloads are paused by hand as paused, and the others as they are
+    fn update_paused_lifecycles(&mut self) {
+        let now = unix_now();
+        for name in self.observer_configs.keys() {
+            let paused = self.paused_all || self.paused_observers.contains(name);
+            log_lifecycle(self.lifecycles.set_paused(name, paused, now));
+        }
+    }
+
+    /// Re-announce local files in the background so peers pick up anything they missed
+    fn start_rescan(&mut self, observer: Option<String>) -> ControlResponse {
+        let Some(local_events) = self.local_events.clone() else {
+            return ControlResponse::Error { message: "Daemon is not running".to_string() };
+        };
+        let observers: Vec<ObserverConfig> = match &observer {
+            Some(name) => match self.observer_configs.get(name) {
+                Some(config) => vec![config.clone()],
+                None => return ControlResponse::Error { message: format!("Unknown observer '{}'", name) },
+            },
+            None => self.observer_configs.values().cloned().collect(),
+        };
+        // Only observers that watch a plain local tree are rescanned
+        let observers: Vec<ObserverConfig> = observers.into_iter()
+            .filter(|obs| obs.announce_only != Some(true) && obs.at_rest_key.is_none() && obs.mount.is_none())
+            .collect();
+        let count = observers.len();
+        // The rescan announces every file again, so files deleted meanwhile drop out of the index
+        for obs in &observers {
+            self.state.state_mut().index.clear(&obs.name);
+            self.merkle_trees.remove(&obs.name);
+            log_lifecycle(self.lifecycles.scan_started(&obs.name, unix_now()));
+        }
+
+        let names = observers.iter().map(|obs| obs.name.clone()).collect();
+        let rescan = tokio::task::spawn_blocking(move || {
+            for obs in observers {
+                let result = observer::rescan(&obs, |msg| {
+                    let _ = local_events.blocking_send(msg);
+                });
+                if let Err(e) = result {
+                    warn!(observer = %obs.name, error = %e, "Rescan failed");
+                }
+            }
+        });
+        self.rescans.push((names, rescan));
+        ControlResponse::Done { message: format!("Rescanning {} observer(s)", count) }
+    }
+
+    /// Note that an observer's watcher dropped events below `subtree`
+    fn mark_stale(&mut self, observer: &str, subtree: String) {
+        let stale = self.stale_observers.entry(observer.to_string()).or_insert_with(|| {
+            warn!(observer = %observer, "Observer may be out of date after its watcher dropped events; rescanning once it settles");
+            StaleObserver { subtrees: BTreeSet::new(), reported: Instant::now(), rescan: None }
+        });
+        observer::add_subtree(&mut stale.subtrees, subtree);
+        stale.reported = Instant::now();
+    }
+
+    /// Rescan stale observers whose watcher has settled, and clear those that caught up
+    /// Only the subtrees the watcher reported are listed, and only files that
+    /// differ from the index are hashed and announced.
+    fn rescan_stale_observers(&mut self, now: Instant) {
+        let Some(local_events) = self.local_events.clone() else {
+            return;
+        };
+        let mut caught_up = Vec::new();
+        for (name, stale) in &mut self.stale_observers {
+            if stale.rescan.as_ref().is_some_and(|rescan| rescan.is_finished()) {
+                stale.rescan = None;
+                log_lifecycle(self.lifecycles.scan_finished(name, unix_now()));
+            }
+            if stale.rescan.is_some() {
+                continue;
+            }
+            if stale.subtrees.is_empty() {
+                caught_up.push(name.clone());
+                continue;
+            }
+            if now.duration_since(stale.reported) < STALE_RESCAN_DELAY {
+                continue;
+            }
+            let Some(config) = self.observer_configs.get(name).cloned() else {
+                caught_up.push(name.clone());
+                continue;
+            };
+            let subtrees: Vec<(String, HashMap<String, IndexedFile>)> = std::mem::take(&mut stale.subtrees).into_iter()
+                .map(|subtree| {
+                    let known = self.state.state().index.under(name, &subtree).into_iter().collect();
+                    (subtree, known)
+                })
+                .collect();
+            let local_events = local_events.clone();
+            log_lifecycle(self.lifecycles.scan_started(name, unix_now()));
+            stale.rescan = Some(tokio::task::spawn_blocking(move || {
+                for (subtree, known) in subtrees {
+                    let result = observer::rescan_subtree(&config, &subtree, &known, |msg| {
+                        let _ = local_events.blocking_send(msg);
+                    });
+                    if let Err(e) = result {
+                        warn!(observer = %config.name, subtree = %subtree, error = %e, "Rescan after dropped events failed");
+                    }
+                }
+            }));
+        }
+        for name in caught_up {
+            self.stale_observers.remove(&name);
+            info!(observer = %name, "Observer caught up after its watcher dropped events");
+        }
+    }
+
+    /// Return observers whose requested rescan has finished to their watcher's state
+    fn finish_rescans(&mut self) {
+        let (finished, running) = std::mem::take(&mut self.rescans).into_iter()
+            .partition::<Vec<_>, _>(|(_, rescan)| rescan.is_finished());
+        self.rescans = running;
+        for name in finished.into_iter().flat_map(|(names, _)| names) {
+            log_lifecycle(self.lifecycles.scan_finished(&name, unix_now()));
+        }
+    }
+
+    /// Request the next chunk now, or hold it back if a sync window or low battery pauses or limits downloads
+    /// Under a rate limit the chunk waits its turn among everything requested from the peer.
+    fn request_next_chunk(&mut self, peer: PeerId, request: FileChunkRequest) {
+        let len = request.length.map_or(CHUNK_SIZE as u64, u64::from);
+        let remaining = self.transfer_tracker.total_size(&request.observer, &request.path)
+            .map_or(len, |total| total.saturating_sub(request.offset));
+        let key = (request.observer.clone(), request.path.clone());
+        self.pace(peer, key, PacedRequest::Chunk(request), len.min(remaining));
+    }
+
+    /// Ask a peer for a file, holding the request back like chunk requests
+    /// The first chunk comes with the answer, so it counts against the rate limit too.
+    fn request_file(&mut self, peer: PeerId, request: FileTransferRequest) {
+        let len = self.tuning(&request.observer).chunk_size as u64;
+        let first_chunk = self.transfer_tracker.total_size(&request.observer, &request.path).map_or(len, |total| total.min(len));
+        let key = (request.observer.clone(), request.path.clone());
+        // Conflict resolvers only see local copies edited since the last version applied from a peer
+        let synced = self.state.state().seen_events.applied(&request.observer, &request.path);
+        self.transfer_tracker.set_local_synced(&key, synced);
+        self.pace(peer, key, PacedRequest::File(request), first_chunk);
+    }
+
+    /// Send a download request of `len` bytes now if its policy allows, or queue it
+    fn pace(&mut self, peer: PeerId, key: (String, String), request: PacedRequest, len: u64) {
+        let now = Instant::now();
+        let send_at = match self.download_policy(&key.0, &key.1) {
+            TransferPolicy::Normal => now,
+            TransferPolicy::Limited(bytes_per_sec) => self.download_rates.reserve(&peer, len, bytes_per_sec, now),
+            TransferPolicy::Paused => {
+                self.pending_chunks.insert(key, (peer, request, now));
+                return;
+            }
+        };
+        if send_at > now {
+            self.pending_chunks.insert(key, (peer, request, send_at));
+        } else {
+            self.send_paced(peer, request);
+        }
+    }
+
+    fn send_paced(&mut self, peer: PeerId, request: PacedRequest) {
+        match request {
+            PacedRequest::File(request) => self.p2p.request_file(peer, request),
+            PacedRequest::Chunk(request) => self.send_chunk_request(peer, request),
+        }
+    }
+
+    /// Fill the peer's congestion window with chunk requests for the downloads it serves
+    /// Downloads under a rate limit or sync window keep to one request at a
+    /// time, paced through `request_next_chunk`.
+    fn request_more_chunks(&mut self, peer: PeerId) {
+        self.chunk_cursors.retain(|key, _| self.download_sources.contains_key(key));
+        self.download_helpers.retain(|key, _| self.download_sources.contains_key(key));
+        let keys: Vec<(String, String)> = self.download_sources.iter()
+            .filter(|(key, source)| {
+                let helping = self.download_helpers.get(*key).is_some_and(|helpers| helpers.contains(&peer));
+                (**source == peer || helping) && self.chunk_cursors.contains_key(*key)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        let window = self.congestion.window(&peer);
+        let mut outstanding = self.chunk_requests.values().filter(|chunk| chunk.peer == peer).count();
+
+        // One chunk per download per round, so concurrent downloads share the window
+        let mut done = HashSet::new();
+        while outstanding < window && done.len() < keys.len() {
+            for key in &keys {
+                if outstanding >= window {
+                    break;
+                }
+                if done.contains(key) {
+                    continue;
+                }
+                let paced = self.download_policy(&key.0, &key.1) != TransferPolicy::Normal;
+                // Helpers only join in at full speed
+                if paced && self.download_sources.get(key) != Some(&peer) {
+                    done.insert(key.clone());
+                    continue;
+                }
+                let in_flight = self.chunk_requests.values().filter(|chunk| &chunk.key == key).count();
+                let at_limit = self.tuning(&key.0).max_parallel_chunks.is_some_and(|limit| in_flight >= limit);
+                let waiting = self.pending_chunks.contains_key(key) || at_limit || (paced && in_flight > 0);
+                let offset = if waiting { None } else { self.chunk_cursors.get_mut(key).and_then(ChunkCursor::take_next) };
+                let Some(request) = offset.and_then(|offset| self.chunk_request(key, offset)) else {
+                    done.insert(key.clone());
+                    continue;
+                };
+                if paced {
+                    self.request_next_chunk(peer, request);
+                    done.insert(key.clone());
+                } else {
+                    self.send_chunk_request(peer, request);
+                    outstanding += 1;
+                }
+            }
+        }
+    }
+
+    /// Stream the rest of a download from its source over the bulk protocol, if it can be
+    /// Only downloads at full speed without helpers stream; the others keep to chunk
+    /// requests, which pace and spread. Returns whether a stream was started.
+    fn start_bulk_download(&mut self, peer: PeerId, key: &(String, String), offset: u64, hash: &str) -> bool {
+        let eligible = self.bulk_downloads_enabled
+            && !self.bulk_unsupported.contains(&peer)
+            && self.peer_capabilities.allows(&peer, capabilities::BULK)
+            && self.download_sources.get(key) == Some(&peer)
+            && !self.chunk_cursors.contains_key(key)
+            && self.download_helpers.get(key).is_none_or(|helpers| helpers.is_empty())
+            && self.download_policy(&key.0, &key.1) == TransferPolicy::Normal;
+        if !eligible {
+            return false;
+        }
+        let request = BulkRequest {
+            observer: key.0.clone(),
+            path: key.1.clone(),
+            hash: self.transfer_tracker.expected_hash(&key.0, &key.1).unwrap_or(hash).to_string(),
+            offset,
+        };
+        debug!(peer = %peer, observer = %key.0, path = %key.1, offset, "Streaming the rest of the file");
+        let task = bulk::spawn_download(self.bulk_control.clone(), peer, request, self.bulk_tx.clone());
+        self.bulk_downloads.insert(key.clone(), BulkDownload { peer, received_to: offset, task });
+        true
+    }
+
+    /// Stop a download's bulk stream, if it has one
+    fn end_bulk_download(&mut self, key: &(String, String)) -> Option<BulkDownload> {
+        let download = self.bulk_downloads.remove(key)?;
+        download.task.abort();
+        Some(download)
+    }
+
+    /// Continue a download with chunk requests from where its bulk stream got to
+    fn fall_back_to_chunks(&mut self, key: &(String, String)) {
+        let Some(download) = self.end_bulk_download(key) else {
+            return;
+        };
+        if self.download_sources.get(key) != Some(&download.peer) {
+            return;
+        }
+        let Some(hash) = self.transfer_tracker.expected_hash(&key.0, &key.1).map(str::to_string) else {
+            return;
+        };
+        let total_size = self.transfer_tracker.total_size(&key.0, &key.1).unwrap_or(download.received_to);
+        self.chunk_cursors.insert(key.clone(), ChunkCursor {
+            next_offset: download.received_to,
+            total_size,
+            retry: Vec::new(),
+            raw: HashSet::new(),
+            failures: 0,
+            hash,
+            chunk_size: self.peer_capabilities.chunk_size(&download.peer, self.tuning(&key.0).chunk_size as u64),
+        });
+        self.request_more_chunks(download.peer);
+    }
+
+    fn handle_bulk_event(&mut self, event: BulkEvent) {
+        match event {
+            BulkEvent::Incoming { peer, request, stream } => self.serve_bulk_stream(peer, request, stream),
+            BulkEvent::Chunk { peer, response } => {
+                let key = (response.observer.clone(), response.path.clone());
+                // Chunks still queued from a stream we stopped are requested again from where it got to
+                let Some(download) = self.bulk_downloads.get_mut(&key).filter(|download| download.peer == peer) else {
+                    debug!(peer = %peer, observer = %key.0, path = %key.1, offset = response.offset, "Ignoring chunk from a stopped stream");
+                    return;
+                };
+                download.received_to = response.offset + response.data.len() as u64;
+                self.handle_file_transfer_response(peer, response);
+                // Paused or rate limited since the stream started
+                if self.bulk_downloads.contains_key(&key) && self.download_policy(&key.0, &key.1) != TransferPolicy::Normal {
+                    self.fall_back_to_chunks(&key);
+                }
+            }
+            BulkEvent::Refused { peer, error } => self.handle_transfer_error(peer, error),
+            BulkEvent::Downloaded { peer, key, result, unsupported } => {
+                if unsupported && self.bulk_unsupported.insert(peer) {
+                    debug!(peer = %peer, "Peer doesn't support bulk streams, using chunk requests");
+                }
+                if let Err(e) = result {
+                    if self.bulk_downloads.get(&key).is_some_and(|download| download.peer == peer) {
+                        debug!(peer = %peer, observer = %key.0, path = %key.1, error = %e, "Bulk stream failed, continuing with chunk requests");
+                        self.fall_back_to_chunks(&key);
+                    }
+                }
+            }
+            BulkEvent::Served { peer, observer, path, offset, len, is_last_chunk } => {
+                self.record_served(&peer, &observer, &path, offset, len);
+                self.track_serving(peer, (observer, path), is_last_chunk);
+            }
+            BulkEvent::ServeEnded { peer, observer, path } => {
+                if let Some(count) = self.bulk_serving.get_mut(&peer) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.bulk_serving.remove(&peer);
+                    }
+                }
+                // A stream that ended early leaves the peer pulling the file no more than one that finished
+                self.track_serving(peer, (observer, path), true);
+            }
+        }
+    }
+
+    fn chunk_request(&self, key: &(String, String), offset: u64) -> Option<FileChunkRequest> {
+        let cursor = self.chunk_cursors.get(key)?;
+        // Switches from a pending version to the real hash once it has been announced
+        let hash = self.transfer_tracker.expected_hash(&key.0, &key.1).unwrap_or(&cursor.hash).to_string();
+        let length = Some(cursor.chunk_size as u32);
+        Some(FileChunkRequest { observer: key.0.clone(), path: key.1.clone(), offset, hash, length, encoding: None })
+    }
+
+    fn send_chunk_request(&mut self, peer: PeerId, mut request: FileChunkRequest) {
+        let key = (request.observer.clone(), request.path.clone());
+        let raw = !self.tuning(&key.0).compression || self.chunk_cursors.get(&key).is_some_and(|cursor| cursor.raw.contains(&request.offset));
+        request.encoding = if raw { None } else { self.peer_capabilities.codec(&peer).map(str::to_string) };
+        let offset = request.offset;
+        let len = request.length.map_or(CHUNK_SIZE as u64, u64::from);
+        let request_id = self.p2p.request_file_chunk(peer, request);
+        self.chunk_requests.insert(request_id, ChunkInFlight { peer, key, offset, len, sent: Instant::now() });
+    }
+
+    /// Count a chunk's round trip towards its peer's congestion window
+    /// Returns the request, or None for a response nothing is waiting for any more.
+    fn chunk_answered(&mut self, request_id: OutboundRequestId) -> Option<ChunkInFlight> {
+        let chunk = self.chunk_requests.remove(&request_id)?;
+        self.congestion.on_ack(chunk.peer, chunk.sent.elapsed());
+        Some(chunk)
+    }
+
+    /// Make a chunk from a peer that ignores requested lengths fit the download's layout
+    /// Peers that predate chunk lengths always send CHUNK_SIZE. Anything past what
+    /// was asked for is dropped; a shorter chunk means the rest of the range, and
+    /// every later chunk, is asked for in pieces of the size the peer does send.
+    fn fit_chunk(&mut self, response: &mut FileTransferResponse, requested: u64) {
+        let len = response.data.len() as u64;
+        if len > requested {
+            response.data.truncate(requested as usize);
+            response.is_last_chunk = response.offset + requested >= response.total_size;
+            return;
+        }
+        let end = (response.offset + requested).min(response.total_size);
+        if len == 0 || response.offset + len >= end {
+            return;
+        }
+        let key = (response.observer.clone(), response.path.clone());
+        if let Some(cursor) = self.chunk_cursors.get_mut(&key) {
+            debug!(observer = %key.0, path = %key.1, asked = requested, sent = len, "Peer sends smaller chunks than asked, requesting the rest");
+            cursor.chunk_size = cursor.chunk_size.min(len);
+            cursor.retry.extend((response.offset + len..end).step_by(len as usize));
+        }
+    }
+
+    /// Ask again for a chunk that failed to decode, uncompressed this time
+    /// Only chunks asked of the download's current source count: a late answer to a
+    /// request since abandoned says nothing about the download in progress. A peer
+    /// whose chunks keep failing is only asked for raw ones from then on.
+    fn chunk_codec_failed(&mut self, peer: PeerId, chunk: Option<ChunkInFlight>, response: &FileTransferResponse, error: String) {
+        let chunk = match chunk {
+            Some(chunk) if chunk.peer == peer && self.download_sources.get(&chunk.key) == Some(&peer) => chunk,
+            // A helper's chunk is asked of the source instead
+            Some(chunk) if self.drop_helper(&chunk) => return,
+            _ => {
+                debug!(peer = %peer, observer = %response.observer, path = %response.path, offset = response.offset, error = %error, "Ignoring a stale chunk that failed to decode");
+                return;
+            }
+        };
+        if self.peer_capabilities.codec_failed(peer) {
+            warn!(peer = %peer, "Chunks from peer keep failing to decode, requesting them uncompressed from now on");
+        }
+        let key = chunk.key;
+        let Some(cursor) = self.chunk_cursors.get_mut(&key) else {
+            return;
+        };
+        // Only an encoded chunk can fail to decode, and a raw one was asked for already
+        if !cursor.raw.insert(chunk.offset) {
+            let hash = cursor.hash.clone();
+            self.transfer_tracker.cancel_transfer(&key.0, &key.1);
+            let error = format!("Chunk at offset {} came back encoded when asked for raw: {}", chunk.offset, error);
+            self.download_finished(&key, &hash, Err(error));
+            return;
+        }
+        warn!(peer = %peer, observer = %key.0, path = %key.1, offset = chunk.offset, error = %error, "Chunk failed to decode, requesting it uncompressed");
+        cursor.retry.push(chunk.offset);
+        self.request_more_chunks(peer);
+    }
+
+    /// Back off after a chunk request timed out or failed, and ask for the chunk again
+    fn chunk_failed(&mut self, chunk: ChunkInFlight) {
+        self.congestion.on_loss(chunk.peer, Instant::now());
+        if self.drop_helper(&chunk) {
+            return;
+        }
+        if self.download_sources.get(&chunk.key) != Some(&chunk.peer) {
+            return;
+        }
+        let Some(cursor) = self.chunk_cursors.get_mut(&chunk.key) else {
+            return;
+        };
+        cursor.failures += 1;
+        if cursor.failures > MAX_CHUNK_RETRIES {
+            let hash = cursor.hash.clone();
+            let size = cursor.total_size;
+            self.transfer_tracker.cancel_transfer(&chunk.key.0, &chunk.key.1);
+            self.download_sources.remove(&chunk.key);
+            if self.restart_from_fallback(&chunk.key, &hash, size) {
+                return;
+            }
+            let error = format!("Chunk requests failed {} times, last at offset {}", MAX_CHUNK_RETRIES + 1, chunk.offset);
+            self.download_finished(&chunk.key, &hash, Err(error));
+            return;
+        }
+        cursor.retry.push(chunk.offset);
+        debug!(peer = %chunk.peer, observer = %chunk.key.0, path = %chunk.key.1, offset = chunk.offset, window = self.congestion.window(&chunk.peer), "Chunk request failed, retrying");
+        self.request_more_chunks(chunk.peer);
+    }
+
+    /// Send held-back chunk requests and replay deferred announcements that are now allowed
+    /// Whether the schedule check has anything to release
+    fn has_scheduled_work(&self) -> bool {
+        #[cfg(feature = "fault-injection")]
+        if !self.held_chunks.is_empty() {
+            return true;
+        }
+        !self.deferred_events.is_empty()
+            || !self.pending_chunks.is_empty()
+            || !self.stale_observers.is_empty()
+            || !self.rescans.is_empty()
+            || !self.busy_retries.is_empty()
+    }
+
+    /// Apply the fault plan to a received chunk, returning it if it is to be handled now
+    #[cfg(feature = "fault-injection")]
+    fn inject_chunk_fault(&mut self, peer: PeerId, mut response: FileTransferResponse) -> Option<FileTransferResponse> {
+        let now = Instant::now();
+        match self.faults.on_chunk_received(&mut response.data) {
+            ChunkFault::Deliver => {
+                // Anything held for reordering goes out right after this chunk
+                for (deliver_at, _, _, _) in self.held_chunks.iter_mut().filter(|(_, reordered, _, _)| *reordered) {
+                    *deliver_at = now;
+                }
+                Some(response)
+            }
+            ChunkFault::Drop => {
+                debug!(peer = %peer, observer = %response.observer, path = %response.path, offset = response.offset, "[faults] Dropping chunk");
+                None
+            }
+            ChunkFault::Delay(delay) => {
+                self.held_chunks.push((now + delay, false, peer, response));
+                None
+            }
+            ChunkFault::Reorder => {
+                self.held_chunks.push((now + REORDER_HOLD, true, peer, response));
+                None
+            }
+            ChunkFault::Kill => {
+                warn!(peer = %peer, observer = %response.observer, path = %response.path, "[faults] Killing connection mid-transfer");
+                let _ = self.p2p.swarm.disconnect_peer_id(peer);
+                None
+            }
+        }
+    }
+
+    /// Deliver held chunks whose time has come
+    #[cfg(feature = "fault-injection")]
+    fn release_held_chunks(&mut self, now: Instant) {
+        let (due, held): (Vec<_>, Vec<_>) = std::mem::take(&mut self.held_chunks)
+            .into_iter()
+            .partition(|(deliver_at, _, _, _)| *deliver_at <= now);
+        self.held_chunks = held;
+        for (_, _, peer, response) in due {
+            self.handle_file_transfer_response(peer, response);
+        }
+    }
+
+    fn release_scheduled_work(&mut self) {
+        let now = Instant::now();
+        self.rescan_stale_observers(now);
+        self.finish_rescans();
+        self.retry_busy_downloads(now);
+        self.retry_in_use_replacements(now);
+        #[cfg(feature = "fault-injection")]
+        self.release_held_chunks(now);
+
+        let ready: Vec<(String, String)> = self.pending_chunks.iter()
+            .filter(|(key, (_, _, send_at))| {
+                *send_at <= now && self.download_policy(&key.0, &key.1) != TransferPolicy::Paused
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in ready {
+            if let Some((peer, request, _)) = self.pending_chunks.remove(&key) {
+                self.send_paced(peer, request);
+            }
+        }
+
+        let resumed: Vec<(String, String)> = self.deferred_events.iter()
+            .filter(|(key, (_, event))| {
+                self.transfer_policy(&key.0, event.size.unwrap_or(0)) != TransferPolicy::Paused
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        if !resumed.is_empty() {
+            info!(count = resumed.len(), "Resuming deferred transfers");
+        }
+        for key in resumed {
+            if let Some((peer, file_event)) = self.deferred_events.remove(&key) {
+                self.queue_incoming(peer, file_event);
+            }
+        }
+    }
+
+    /// Clean up downloads the tracker dropped, telling their sources to stop sending
+    fn handle_evictions(&mut self, evicted: Vec<EvictedTransfer>) {
+        for transfer in evicted {
+            let key = (transfer.observer.clone(), transfer.path.clone());
+            self.pending_chunks.remove(&key);
+            self.download_fallbacks.remove(&key);
+            if self.download_sources.remove(&key).is_some() {
+                self.p2p.request_cancel_transfer(transfer.peer, CancelTransferRequest {
+                    observer: transfer.observer,
+                    path: transfer.path,
+                    reason: format!("evicted ({:?})", transfer.reason).to_lowercase(),
+                });
+            }
+            self.abandon_transaction_member(&key);
+            self.finish_fetch(&key, Err("transfer evicted".to_string()));
+        }
+    }
+
+    /// Track an announcement for an on-demand observer
+    fn record_catalog_event(&mut self, peer: PeerId, file_event: &FileEventMessage) {
+        let Ok(mut catalog) = self.catalog.write() else {
+            return;
+        };
+        match file_event.event_type {
+            event_type if event_type.has_content() => {
+                if !catalog.record(file_event, &peer.to_string()) {
+                    warn!(observer = %file_event.observer, path = %file_event.path, "Announcement without hash or size, not cataloguing");
+                    return;
+                }
+            }
+            EventType::Remove => {
+                catalog.remove(&file_event.observer, &file_event.path);
+            }
+            _ => return,
+        }
+        self.catalog_dirty = true;
+    }
+
+    /// Handle a fetch or range read from a mount
+    fn handle_catalog_request(&mut self, request: CatalogRequest) {
+        match request {
+            CatalogRequest::Fetch { observer, path, reply } => self.start_fetch(observer, path, reply),
+            CatalogRequest::Read { observer, path, offset, length, reply } => {
+                let entry = self.catalog.read().ok().and_then(|catalog| catalog.get(&observer, &path).cloned());
+                let Some(entry) = entry else {
+                    let _ = reply.send(Err("file is not in the catalog".to_string()));
+                    return;
+                };
+                let Some(peer) = self.catalog_source(&entry) else {
+                    let _ = reply.send(Err("no peers connected".to_string()));
+                    return;
+                };
+                if !self.peer_capabilities.allows(&peer, capabilities::RANGE_READ) {
+                    let _ = reply.send(Err("peer doesn't support range reads".to_string()));
+                    return;
+                }
+                let request_id = self.p2p.request_range(peer, RangeReadRequest { observer, path, offset, length, hash: entry.hash });
+                self.range_reads.insert(request_id, reply);
+            }
+        }
+    }
+
+    /// Peer to download a catalogued file from, preferring the one that announced it
+    /// and otherwise the connected peer that has been fastest recently
+    fn catalog_source(&self, entry: &RemoteEntry) -> Option<PeerId> {
+        let announcer = entry.peer.parse::<PeerId>().ok().filter(|peer| self.connected_peers.contains(peer));
+        announcer.or_else(|| self.peer_stats.fastest(&self.connected_peers, Instant::now()))
+    }
+
+    /// Start downloading a catalogued file for a mount, replying once it is cached
+    fn start_fetch(&mut self, observer: String, path: String, reply: std::sync::mpsc::Sender<Result<(), String>>) {
+        let key = (observer.clone(), path.clone());
+        if self.download_sources.contains_key(&key) || self.deferred_events.contains_key(&key) {
+            self.fetch_waiters.entry(key).or_default().push(reply);
+            return;
+        }
+
+        let entry = self.catalog.read().ok()
+            .and_then(|catalog| catalog.get(&observer, &path).cloned());
+        let (Some(entry), Some(storage)) = (entry, self.storages.get(&observer).cloned()) else {
+            let _ = reply.send(Err("file is not in the catalog".to_string()));
+            return;
+        };
+
+        // The cache may already hold this version, e.g. after the state file was lost
+        let relative_path = std::path::Path::new(&path);
+        if storage.exists(relative_path) && storage.hash(relative_path).is_ok_and(|hash| hash == entry.hash) {
+            if let Ok(mut catalog) = self.catalog.write() {
+                catalog.mark_cached(&observer, &path, &entry.hash);
+                self.catalog_dirty = true;
+            }
+            let _ = reply.send(Ok(()));
+            return;
+        }
+
+        let Some(peer) = self.catalog_source(&entry) else {
+            let _ = reply.send(Err("no peers connected".to_string()));
+            return;
+        };
+
+        info!(observer = %observer, path = %path, peer = %peer, "Fetching file on demand");
+        self.process_file_event(peer, FileEventMessage {
+            observer,
+            event_type: EventType::Modify,
+            path,
+            details: Some("Fetch".to_string()),
+            hash: Some(entry.hash),
+            size: Some(entry.size),
+            modified_time: Some(entry.modified_time),
+            hmac: None,
+            transaction: None,
+            observer_id: None,
+            append: None,
+            sequence: None,
+        });
+        if self.download_sources.contains_key(&key) || self.deferred_events.contains_key(&key) {
+            self.fetch_waiters.entry(key).or_default().push(reply);
+        } else {
+            let _ = reply.send(Err("could not start download".to_string()));
+        }
+    }
+
+    /// Answer a pending range read with the peer's response
+    fn finish_range_read(&mut self, request_id: OutboundRequestId, result: Result<Vec<u8>, String>) {
+        if let Some(reply) = self.range_reads.remove(&request_id) {
+            let _ = reply.send(result);
+        }
+    }
+
+    /// Read the start of a peer's copy of a file for `syndactyl peek`
+    /// The peer's version isn't known up front, so a read for the wrong one is
+    /// answered with the version it holds, and retried once against that.
+    fn start_peek(
+        &mut self,
+        observer: String,
+        path: String,
+        peer: Option<String>,
+        bytes: Option<u32>,
+        lines: Option<usize>,
+        reply: tokio::sync::oneshot::Sender<ControlResponse>,
+    ) {
+        let fail = |reply: tokio::sync::oneshot::Sender<ControlResponse>, message: String| {
+            let _ = reply.send(ControlResponse::Error { message });
+        };
+        if !self.observer_configs.contains_key(&observer) {
+            return fail(reply, format!("Unknown observer '{}'", observer));
+        }
+        let peer = match peer {
+            Some(peer) => match peer.parse::<PeerId>() {
+                Ok(peer) if self.connected_peers.contains(&peer) => peer,
+                Ok(_) => return fail(reply, format!("Peer {} is not connected", peer)),
+                Err(_) => return fail(reply, format!("'{}' is not a peer ID", peer)),
+            },
+            None => {
+                let candidates: Vec<PeerId> = self.connected_peers.iter()
+                    .filter(|peer| self.sync_groups.allows(&observer, &peer.to_string()) && self.key_pins.allows(&observer, peer))
+                    .filter(|peer| !self.departed.contains(&(**peer, observer.clone())))
+                    .copied()
+                    .collect();
+                match self.peer_stats.fastest(&candidates, Instant::now()) {
+                    Some(peer) => peer,
+                    None => return fail(reply, format!("No connected peer syncs '{}'", observer)),
+                }
+            }
+        };
+        // A version the catalog knows of saves the round trip to learn the peer's
+        let hash = self.catalog.read().ok()
+            .and_then(|catalog| catalog.get(&observer, &path).map(|entry| entry.hash.clone()))
+            .unwrap_or_default();
+        let request = RangeReadRequest {
+            observer,
+            path,
+            offset: 0,
+            length: bytes.unwrap_or(DEFAULT_PEEK_BYTES).min(CHUNK_SIZE as u32),
+            hash,
+        };
+        let request_id = self.p2p.request_range(peer, request.clone());
+        self.peeks.insert(request_id, PendingPeek { peer, request, lines, retried: false, reply });
+    }
+
+    fn finish_peek(&mut self, request_id: OutboundRequestId, response: SyndactylResponse) {
+        let Some(mut peek) = self.peeks.remove(&request_id) else {
+            return;
+        };
+        let response = match response {
+            SyndactylResponse::Range(range) => {
+                self.state.state_mut().bandwidth.record_received(&range.observer, &peek.peer.to_string(), range.data.len() as u64);
+                self.peer_stats.record_received(peek.peer, range.data.len() as u64, Instant::now());
+                ControlResponse::Preview(Preview::new(
+                    range.observer,
+                    range.path,
+                    peek.peer.to_string(),
+                    range.hash,
+                    range.total_size,
+                    &range.data,
+                    peek.lines,
+                ))
+            }
+            SyndactylResponse::Error(TransferError { kind: TransferErrorKind::FileChanged { current_hash: Some(hash), .. }, .. }) if !peek.retried => {
+                peek.request.hash = hash;
+                peek.retried = true;
+                let request_id = self.p2p.request_range(peek.peer, peek.request.clone());
+                self.peeks.insert(request_id, peek);
+                return;
+            }
+            SyndactylResponse::Error(error) => {
+                let message = match error.kind {
+                    TransferErrorKind::NotFound => "The peer doesn't have that file".to_string(),
+                    TransferErrorKind::NotServing => "The peer doesn't sync that observer".to_string(),
+                    TransferErrorKind::Busy { retry_after_secs } => format!("The peer is busy; try again in {}s", retry_after_secs),
+                    TransferErrorKind::FileChanged { .. } => "The file kept changing on the peer; try again".to_string(),
+                };
+                ControlResponse::Error { message }
+            }
+            _ => ControlResponse::Error { message: "Unexpected response to range read".to_string() },
+        };
+        let _ = peek.reply.send(response);
+    }
+
+    /// Start comparing one observer, or every reconcilable one, with a peer's tree
+    /// Answered once the peer has listed every directory that differs.
+    fn start_diff(&mut self, observer: Option<String>, peer: String, reply: tokio::sync::oneshot::Sender<ControlResponse>) {
+        let fail = |reply: tokio::sync::oneshot::Sender<ControlResponse>, message: String| {
+            let _ = reply.send(ControlResponse::Error { message });
+        };
+        let peer = match peer.parse::<PeerId>() {
+            Ok(peer) if self.connected_peers.contains(&peer) => peer,
+            Ok(_) => return fail(reply, format!("Peer {} is not connected", peer)),
+            Err(_) => return fail(reply, format!("'{}' is not a peer ID", peer)),
+        };
+        let shared = |manager: &Self, observer: &str| {
+            manager.reconcilable(observer)
+                && manager.sync_groups.allows(observer, &peer.to_string())
+                && manager.key_pins.allows(observer, &peer)
+        };
+        let observers: Vec<String> = match observer {
+            Some(observer) if !self.observer_configs.contains_key(&observer) => {
+                return fail(reply, format!("Unknown observer '{}'", observer));
+            }
+            Some(observer) if !shared(self, &observer) => {
+                return fail(reply, format!("'{}' isn't synced with {} right now", observer, peer));
+            }
+            Some(observer) => vec![observer],
+            None => self.observer_configs.keys().filter(|observer| shared(self, observer.as_str())).cloned().collect(),
+        };
+        if observers.is_empty() {
+            return fail(reply, format!("No observer is synced with {} right now", peer));
+        }
+
+        let id = self.next_diff;
+        self.next_diff += 1;
+        let mut sessions = HashMap::new();
+        for observer in observers {
+            self.merkle_tree(&observer);
+            let mut session = PeerDiff::new(&observer, &peer.to_string());
+            let request_id = self.request_tree_node(peer, &observer, String::new(), None);
+            session.requested();
+            self.diff_requests.insert(request_id, (id, observer.clone()));
+            sessions.insert(observer, session);
+        }
+        info!(peer = %peer, observers = sessions.len(), "Comparing with peer's tree");
+        self.diffs.insert(id, PendingDiff { peer, sessions, reports: Vec::new(), reply });
+    }
+
+    /// Take in a listing for a comparison, asking for the directories it shows differ
+    fn continue_diff(&mut self, request_id: OutboundRequestId, response: SyndactylResponse) {
+        let Some((id, observer)) = self.diff_requests.remove(&request_id) else {
+            return;
+        };
+        let Some(peer) = self.diffs.get(&id).map(|diff| diff.peer) else {
+            return;
+        };
+        let node = match response {
+            SyndactylResponse::TreeNode(node) if node.observer == observer => node,
+            SyndactylResponse::Error(error) => return self.fail_diff(id, format!("Peer refused to list '{}': {:?}", observer, error.kind)),
+            _ => return self.fail_diff(id, "Unexpected response to tree listing".to_string()),
+        };
+        if let Some(secret) = self.observer_configs.get(&observer).and_then(|obs| obs.shared_secret.as_ref().map(Secret::expose)) {
+            let expected = merkle::sign_node(&node.observer, &node.dir, &node.entries, &node.tombstones, node.next.as_deref(), secret);
+            if !node.hmac.as_deref().is_some_and(|hmac| auth::constant_time_compare(hmac, &expected)) {
+                warn!(peer = %peer, observer = %observer, dir = %node.dir, "Tree node not signed with the observer's secret, abandoning diff");
+                self.auth_failed(&peer, &observer, AuthFailure::BadSignature);
+                return self.fail_diff(id, format!("Peer's listing of '{}' isn't signed with the observer's secret", observer));
+            }
+        }
+
+        self.merkle_tree(&observer);
+        let Some(session) = self.diffs.get_mut(&id).and_then(|diff| diff.sessions.get_mut(&observer)) else {
+            return;
+        };
+        let state = self.state.state();
+        let descend = session.add(&node, &self.merkle_trees[&observer], &state.index, &state.seen_events);
+        let mut listings: Vec<(String, Option<String>)> = descend.into_iter().map(|dir| (dir, None)).collect();
+        if let Some(next) = node.next {
+            listings.push((node.dir, Some(next)));
+        }
+        for (dir, after) in listings {
+            let request_id = self.request_tree_node(peer, &observer, dir, after);
+            self.diff_requests.insert(request_id, (id, observer.clone()));
+            if let Some(session) = self.diffs.get_mut(&id).and_then(|diff| diff.sessions.get_mut(&observer)) {
+                session.requested();
+            }
+        }
+
+        let Some(diff) = self.diffs.get_mut(&id) else {
+            return;
+        };
+        if diff.sessions.get(&observer).is_some_and(PeerDiff::is_done) {
+            let session = diff.sessions.remove(&observer).expect("session checked above");
+            diff.reports.push(session.finish(self.merkle_trees[&observer].file_count()));
+        }
+        if diff.sessions.is_empty() {
+            let mut diff = self.diffs.remove(&id).expect("diff checked above");
+            diff.reports.sort_by(|a, b| a.observer.cmp(&b.observer));
+            let _ = diff.reply.send(ControlResponse::Diff { reports: diff.reports });
+        }
+    }
+
+    /// Give up on a comparison; listings still in flight are ignored when they arrive
+    fn fail_diff(&mut self, id: u64, message: String) {
+        if let Some(diff) = self.diffs.remove(&id) {
+            let _ = diff.reply.send(ControlResponse::Error { message });
+        }
+    }
+
+    /// Answer everyone waiting for a fetched file
+    fn finish_fetch(&mut self, key: &(String, String), result: Result<(), String>) {
+        for waiter in self.fetch_waiters.remove(key).unwrap_or_default() {
+            let _ = waiter.send(result.clone());
+        }
+    }
+
+    /// Drop a failed or cancelled download from its transaction so the rest can still apply
+    fn abandon_transaction_member(&mut self, key: &(String, String)) {
+        if let Some(id) = self.download_transactions.remove(key) {
+            warn!(observer = %key.0, path = %key.1, transaction = %id, "Transfer abandoned, transaction will apply without it");
+            self.transactions.abandon(&id, &key.1);
+            self.try_commit_transaction(&id);
+        }
     }
 
-    /// Handle P2P events from the event channel
-    async fn handle_p2p_event(&mut self, event: SyndactylP2PEvent) {
-        match event {
-            SyndactylP2PEvent::GossipsubMessage { source, data } => {
-                self.handle_gossipsub_message(source, data);
-            }
-            SyndactylP2PEvent::KademliaEvent(info) => {
-                info!(%info, "Kademlia event");
-            }
-            SyndactylP2PEvent::NewListenAddr(addr) => {
-                info!(%addr, "Listening on");
-            }
-            SyndactylP2PEvent::FileTransferRequest { peer, request, channel } => {
-                self.handle_file_transfer_request(peer, request, channel);
-            }
-            SyndactylP2PEvent::FileTransferResponse { peer, response } => {
-                self.handle_file_transfer_response(peer, response);
-            }
-            SyndactylP2PEvent::FileChunkRequest { peer, request, channel } => {
-                self.handle_file_chunk_request(peer, request, channel);
-            }
+    /// Move every staged file of a transaction into place once all of its members have arrived
+    fn try_commit_transaction(&mut self, id: &str) {
+        if let Some(transaction) = self.transactions.take_ready(id) {
+            self.commit_transaction(id, transaction.observer, transaction.staged);
         }
     }
 
-    /// Handle Gossipsub messages (file events from other peers)
-    fn handle_gossipsub_message(&mut self, source: PeerId, data: Vec<u8>) {
-        match serde_json::from_slice::<FileEventMessage>(&data) {
-            Ok(file_event) => {
-                info!(peer = %source, event = ?file_event, "Received FileEventMessage from P2P");
-                
-                // Verify HMAC if we have a shared secret for this observer
-                if let Some(observer_config) = self.observer_configs.get(&file_event.observer) {
-                    if let Some(ref secret) = observer_config.shared_secret {
-                        // Verify HMAC
-                        if !auth::verify_hmac(&file_event, secret) {
-                            warn!(
-                                peer = %source,
-                                observer = %file_event.observer,
-                                "HMAC verification failed - rejecting unauthorized file event"
-                            );
-                            return;
-                        }
-                        info!(peer = %source, observer = %file_event.observer, "HMAC verified successfully");
-                    } else {
-                        warn!(
-                            peer = %source,
-                            observer = %file_event.observer,
-                            "No shared secret configured for observer - accepting unauthenticated message (INSECURE)"
-                        );
-                    }
-                } else {
-                    info!(observer = %file_event.observer, "Observer not configured locally, ignoring event");
-                    return;
-                }
-                
-                // Check if this is a Create or Modify event with a file we should sync
-                if matches!(file_event.event_type.as_str(), "Create" | "Modify") {
-                    self.process_file_event(source, file_event);
-                }
-            },
-            Err(e) => {
-                warn!(peer = %source, error = ?e, raw = %String::from_utf8_lossy(&data), "Failed to parse FileEventMessage from P2P");
+    /// Apply transactions that timed out, keeping whatever content was staged
+    fn expire_transactions(&mut self) {
+        for (id, transaction) in self.transactions.take_expired(Instant::now(), TRANSACTION_TIMEOUT) {
+            warn!(
+                transaction = %id,
+                observer = %transaction.observer,
+                missing = transaction.expected.saturating_sub(transaction.announced.len()) + transaction.awaiting.len(),
+                "Transaction timed out, applying partial transaction"
+            );
+            self.download_transactions.retain(|_, tx| tx != &id);
+            for path in &transaction.awaiting {
+                let key = (transaction.observer.clone(), path.clone());
+                self.download_sources.remove(&key);
+                self.pending_chunks.remove(&key);
+                self.transfer_tracker.cancel_transfer(&transaction.observer, path);
             }
+            self.commit_transaction(&id, transaction.observer, transaction.staged);
         }
     }
 
-    /// Process a file event and potentially request the file
-    fn process_file_event(&mut self, peer: PeerId, file_event: FileEventMessage) {
-        // Check if we have this observer configured locally
-        if let Some(observer_config) = self.observer_configs.get(&file_event.observer) {
-            let base_path = PathBuf::from(&observer_config.path);
-            let relative_path = std::path::Path::new(&file_event.path);
-            let absolute_path = file_handler::to_absolute_path(relative_path, &base_path);
-            
-            // Check if we need to request this file
-            let should_request = if absolute_path.exists() {
-                // File exists, check if hash is different
-                if let Some(remote_hash) = &file_event.hash {
-                    if let Ok(local_hash) = file_handler::calculate_file_hash(&absolute_path) {
-                        &local_hash != remote_hash
-                    } else {
-                        true // Can't calculate local hash, request file
-                    }
-                } else {
-                    false // No hash provided, skip
-                }
-            } else {
-                true // File doesn't exist, request it
-            };
-            
-            if should_request {
-                if let Some(hash) = file_event.hash {
-                    info!(
-                        observer = %file_event.observer,
-                        path = %file_event.path,
-                        "Requesting file from peer"
-                    );
-                    
-                    let request = FileTransferRequest {
-                        observer: file_event.observer.clone(),
-                        path: file_event.path.clone(),
-                        hash: hash.clone(),
-                    };
-                    
-                    // Start tracking this transfer
-                    if let Some(size) = file_event.size {
-                        self.transfer_tracker.start_transfer(
-                            file_event.observer.clone(),
-                            file_event.path.clone(),
-                            size,
-                            hash,
-                            base_path.clone(),
-                        );
-                    }
-                    
-                    // Send request to the peer who sent the event
-                    self.p2p.request_file(peer, request);
-                } else {
-                    warn!(observer = %file_event.observer, path = %file_event.path, "No hash provided in file event");
+    /// Rename a transaction's staged files into place and clear its staging area
+    fn commit_transaction(&mut self, id: &str, observer: String, staged: HashSet<String>) {
+        let Some(storage) = self.storages.get(&observer).cloned() else {
+            return;
+        };
+        // A crash part way through is rolled forward at the next start
+        let intent = Intent::Commit { observer: observer.clone(), transaction: id.to_string(), paths: staged.iter().cloned().collect() };
+        let begun = match self.journal.lock() {
+            Ok(mut journal) => journal.begin(intent),
+            Err(_) => Err(std::io::Error::other("journal lock poisoned")),
+        };
+        let entry = match begun {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!(observer = %observer, transaction = %id, error = %e, "Failed to journal transaction, leaving it staged");
+                return;
+            }
+        };
+        let mut applied = 0;
+        for path in &staged {
+            match storage.commit_staged(id, std::path::Path::new(path)) {
+                Ok(_) => applied += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::ResourceBusy => {
+                    info!(observer = %observer, path = %path, transaction = %id, "Local copy is in use, replacing it once it is released");
+                    self.retry_in_use_later((observer.clone(), path.clone()), None);
                 }
-            } else {
-                info!(observer = %file_event.observer, path = %file_event.path, "File already up to date, skipping");
+                Err(e) => error!(observer = %observer, path = %path, transaction = %id, error = %e, "Failed to apply staged file"),
+            }
+        }
+        if let Err(e) = storage.discard_staged(id) {
+            warn!(observer = %observer, transaction = %id, error = %e, "Failed to clean up transaction staging");
+        }
+        if let Ok(mut journal) = self.journal.lock() {
+            if let Err(e) = journal.end(entry) {
+                warn!(path = %journal.path().display(), error = %e, "Failed to update journal");
             }
-        } else {
-            info!(observer = %file_event.observer, "Observer not configured locally, ignoring event");
         }
+        info!(observer = %observer, transaction = %id, files = applied, "Transaction applied");
     }
 
-    /// Handle file transfer request
-    fn handle_file_transfer_request(
+    /// Serve a file chunk request
+    fn serve_file_chunk_request(
         &mut self,
         peer: PeerId,
-        request: FileTransferRequest,
-        channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        request: FileChunkRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
     ) {
-        info!(peer = %peer, observer = %request.observer, path = %request.path, "Received file transfer request");
+        let sync = sync_id(&request.observer, &request.path, Some(&request.hash));
+        let _span = traced(info_span!("serve", sync = %sync), &sync).entered();
+        let log_event = self.log_throttle.allow("serve");
+        if log_event {
+            info!(
+                peer = %peer,
+                observer = %request.observer,
+                path = %request.path,
+                offset = request.offset,
+                "Received file chunk request"
+            );
+        }
         
         // Check if we have this observer configured
         if let Some(observer_config) = self.observer_configs.get(&request.observer) {
-            // TODO: In the next task, we'll add peer allowlist checking here
-            // For now, we log that authorization should be checked
-            if observer_config.shared_secret.is_some() {
+            // Peers outside the observer's sync group were turned away when the request was queued
+            if observer_config.shared_secret.is_some() && log_event {
                 info!(peer = %peer, observer = %request.observer, "Observer has authentication enabled");
-                // Note: Peer allowlist will be checked in the next implementation phase
-            } else {
-                warn!(peer = %peer, observer = %request.observer, "Observer has no authentication - serving file (INSECURE)");
             }
             
-            let base_path = PathBuf::from(&observer_config.path);
+            // Announce-only observers have no files to serve
+            let Some(storage) = self.storages.get(&request.observer).cloned() else {
+                return;
+            };
+            // Refuse to serve bytes from a different version than the one being assembled
+            let total_size = match self.check_served_version(&storage, &request.observer, &request.path, &request.hash) {
+                Ok(Served::Ready(size)) => size,
+                Ok(_) => {
+                    self.park_serve(peer, ServeRequest::FileChunk(request, channel));
+                    return;
+                }
+                Err(kind) => {
+                    self.p2p.send_transfer_error(channel, TransferError {
+                        observer: request.observer.clone(),
+                        path: request.path.clone(),
+                        requested_hash: request.hash.clone(),
+                        kind,
+                    });
+                    return;
+                }
+            };
+
+            // Requesters choose the chunk size; older ones expect CHUNK_SIZE without saying so
             let relative_path = std::path::Path::new(&request.path);
-            let absolute_path = file_handler::to_absolute_path(relative_path, &base_path);
-            
-            if absolute_path.exists() && absolute_path.is_file() {
-                // Generate only the first chunk for initial response
-                match generate_first_chunk(
-                    &request.observer,
-                    relative_path,
-                    &absolute_path,
-                    &request.hash,
-                ) {
-                    Ok(first_chunk) => {
-                        info!(
-                            observer = %request.observer,
-                            path = %request.path,
-                            size = first_chunk.total_size,
-                            is_last = first_chunk.is_last_chunk,
-                            "Sending first file chunk"
-                        );
-                        self.p2p.send_file_response(channel, first_chunk);
-                    }
-                    Err(e) => {
-                        error!(
-                            observer = %request.observer,
-                            path = %request.path,
-                            error = %e,
-                            "Failed to generate first chunk"
-                        );
-                    }
+            let len = request.length.map_or(CHUNK_SIZE, |length| length as usize).min(MAX_CHUNK_SIZE);
+            match self.read_served_chunk(storage.as_ref(), relative_path, &request.hash, request.offset, len) {
+                Ok(chunk) => {
+                    let is_last_chunk = request.offset + chunk.data.len() as u64 >= total_size;
+                    // Our setting for the observer applies to what we serve too
+                    let codec = request.encoding.as_deref().filter(|_| self.tuning(&request.observer).compression);
+                    let (data, encoding) = chunk_codec::encode(chunk.data, codec);
+                    let mut response = FileTransferResponse {
+                        observer: request.observer.clone(),
+                        path: request.path.clone(),
+                        segment_hash: Some(chunk.segment_hash),
+                        segment_hmac: None,
+                        data,
+                        offset: request.offset,
+                        total_size,
+                        hash: request.hash.clone(),
+                        is_last_chunk,
+                        encoding,
+                    };
+                    self.sign_segment(&mut response);
+                    self.record_served(&peer, &response.observer, &response.path, response.offset, response.data.len() as u64);
+                    self.track_serving(peer, (response.observer.clone(), response.path.clone()), response.is_last_chunk);
+                    self.p2p.send_file_response(channel, response);
+                }
+                Err(e) => {
+                    error!(
+                        observer = %request.observer,
+                        path = %request.path,
+                        error = %e,
+                        "Failed to read file chunk"
+                    );
                 }
-            } else {
-                warn!(
-                    observer = %request.observer,
-                    path = %request.path,
-                    "File not found or not a file"
-                );
             }
         } else {
-            warn!(observer = %request.observer, "Observer not configured locally");
+            warn!(observer = %request.observer, "Observer not configured locally for chunk request");
         }
     }
 
-    /// Handle file transfer response
-    fn handle_file_transfer_response(&mut self, peer: PeerId, response: FileTransferResponse) {
-        info!(
-            peer = %peer,
-            observer = %response.observer,
-            path = %response.path,
-            offset = response.offset,
-            size = response.data.len(),
-            is_last = response.is_last_chunk,
-            "Received file transfer response"
-        );
-        
-        // Add chunk to transfer tracker
-        match self.transfer_tracker.add_chunk(
-            &response.observer,
-            &response.path,
-            response.offset,
-            response.data.clone(),
-            response.is_last_chunk,
-        ) {
-            Ok(Some(file_path)) => {
-                info!(
-                    observer = %response.observer,
-                    path = %response.path,
-                    file = %file_path.display(),
-                    "File transfer completed and written to disk"
-                );
-            }
-            Ok(None) => {
-                info!(
-                    observer = %response.observer,
-                    path = %response.path,
-                    "Chunk received, requesting next chunk"
-                );
-                // Request next chunk if not last
-                if !response.is_last_chunk {
-                    let next_offset = response.offset + response.data.len() as u64;
-                    let chunk_request = FileChunkRequest {
-                        observer: response.observer.clone(),
-                        path: response.path.clone(),
-                        offset: next_offset,
-                        hash: response.hash.clone(),
-                    };
-                    self.p2p.request_file_chunk(peer, chunk_request);
-                }
+    /// Stream a file to a peer that asked for it over the bulk protocol
+    /// Refused streams are dropped; the peer then falls back to chunk requests,
+    /// which get the specific error.
+    fn serve_bulk_stream(&mut self, peer: PeerId, request: BulkRequest, stream: libp2p::Stream) {
+        let sync = sync_id(&request.observer, &request.path, Some(&request.hash));
+        let _span = traced(info_span!("serve", sync = %sync), &sync).entered();
+        if !self.may_serve(&peer, &request.observer, &request.path) {
+            return;
+        }
+        let key = (request.observer.clone(), request.path.clone());
+        let open_streams = self.bulk_serving.get(&peer).copied().unwrap_or(0);
+        if !bulk::admits(&peer, self.serving_peers.get(&key), open_streams) {
+            debug!(peer = %peer, observer = %request.observer, path = %request.path, open_streams, "Bulk stream doesn't continue an admitted transfer, refusing");
+            return;
+        }
+        let Some(storage) = self.storages.get(&request.observer).cloned() else {
+            return;
+        };
+        // The transfer this continues hashed the version already, unless it changed since
+        let total_size = match self.check_served_version(&storage, &request.observer, &request.path, &request.hash) {
+            Ok(Served::Ready(size)) => size,
+            Ok(_) => {
+                debug!(peer = %peer, observer = %request.observer, path = %request.path, "File is being hashed, not streaming it");
+                return;
             }
-            Err(e) => {
-                error!(
-                    observer = %response.observer,
-                    path = %response.path,
-                    error = %e,
-                    "Failed to process file chunk"
-                );
+            Err(kind) => {
+                debug!(peer = %peer, observer = %request.observer, path = %request.path, error = ?kind, "Not streaming file");
+                return;
             }
+        };
+        let Some(modified_time) = self.served_versions.get(&key).map(|version| version.modified_time) else {
+            return;
+        };
+        if self.log_throttle.allow("serve") {
+            info!(peer = %peer, observer = %request.observer, path = %request.path, offset = request.offset, "Streaming file");
         }
+        *self.bulk_serving.entry(peer).or_default() += 1;
+        self.serving_peers.entry(key).or_default().insert(peer);
+        let chunk_size = self.tuning(&request.observer).chunk_size;
+        let secret = self.observer_configs.get(&request.observer).and_then(|config| config.shared_secret.clone());
+        bulk::spawn_serve(peer, stream, request, ServedFile { storage, total_size, modified_time, chunk_size, secret }, self.bulk_tx.clone());
     }
 
-    /// Handle file chunk request
-    fn handle_file_chunk_request(
+    /// Serve a one-off range read, outside of any transfer
+    fn serve_range_read(
         &mut self,
         peer: PeerId,
-        request: FileChunkRequest,
-        channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        request: RangeReadRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
     ) {
-        info!(
-            peer = %peer,
-            observer = %request.observer,
-            path = %request.path,
-            offset = request.offset,
-            "Received file chunk request"
-        );
-        
-        // Check if we have this observer configured
-        if let Some(observer_config) = self.observer_configs.get(&request.observer) {
-            // TODO: In the next task, we'll add peer allowlist checking here
-            if observer_config.shared_secret.is_some() {
-                info!(peer = %peer, observer = %request.observer, "Observer has authentication enabled");
-                // Note: Peer allowlist will be checked in the next implementation phase
+        let sync = sync_id(&request.observer, &request.path, Some(&request.hash));
+        let _span = traced(info_span!("serve", sync = %sync), &sync).entered();
+        let Some(storage) = self.storages.get(&request.observer).cloned() else {
+            warn!(observer = %request.observer, "Observer not configured locally for range read");
+            return;
+        };
+
+        // Ranges must come from the version the reader expects, like chunks do
+        let total_size = match self.check_served_version(&storage, &request.observer, &request.path, &request.hash) {
+            Ok(Served::Ready(size)) => size,
+            Ok(_) => {
+                self.park_serve(peer, ServeRequest::RangeRead(request, channel));
+                return;
             }
-            
-            let base_path = PathBuf::from(&observer_config.path);
-            let relative_path = std::path::Path::new(&request.path);
-            let absolute_path = file_handler::to_absolute_path(relative_path, &base_path);
-            if absolute_path.exists() && absolute_path.is_file() {
-                match file_handler::read_file_chunk(&absolute_path, request.offset, CHUNK_SIZE) {
-                    Ok(data) => {
-                        let total_size = absolute_path.metadata().map(|m| m.len()).unwrap_or(0);
-                        let is_last_chunk = request.offset + data.len() as u64 >= total_size;
-                        let response = FileTransferResponse {
-                            observer: request.observer.clone(),
-                            path: request.path.clone(),
-                            data,
-                            offset: request.offset,
-                            total_size,
-                            hash: request.hash.clone(),
-                            is_last_chunk,
-                        };
-                        self.p2p.send_file_response(channel, response);
-                    }
-                    Err(e) => {
-                        error!(
-                            observer = %request.observer,
-                            path = %request.path,
-                            error = %e,
-                            "Failed to read file chunk"
-                        );
-                    }
-                }
-            } else {
-                warn!(
-                    observer = %request.observer,
-                    path = %request.path,
-                    "File not found or not a file for chunk request"
-                );
+            Err(kind) => {
+                self.p2p.send_transfer_error(channel, TransferError {
+                    observer: request.observer,
+                    path: request.path,
+                    requested_hash: request.hash,
+                    kind,
+                });
+                return;
             }
+        };
+
+        let length = (request.length as usize).min(CHUNK_SIZE);
+        let data = if request.offset >= total_size {
+            Vec::new()
         } else {
-            warn!(observer = %request.observer, "Observer not configured locally for chunk request");
-        }
+            match storage.read_chunk(std::path::Path::new(&request.path), request.offset, length) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!(observer = %request.observer, path = %request.path, error = %e, "Failed to read range");
+                    return;
+                }
+            }
+        };
+        self.record_served(&peer, &request.observer, &request.path, request.offset, data.len() as u64);
+        self.p2p.send_range_response(channel, RangeReadResponse {
+            observer: request.observer,
+            path: request.path,
+            offset: request.offset,
+            data,
+            total_size,
+            hash: request.hash,
+        });
     }
 
     /// Handle swarm events directly
@@ -404,41 +4684,150 @@ impl NetworkManager {
         use libp2p::swarm::SwarmEvent;
         use libp2p::gossipsub::Event as GossipsubEvent;
 
+        if let SwarmEvent::Behaviour(event) = &event {
+            self.discovery.handle_event(&mut self.p2p.swarm, event);
+        }
+
         match event {
             SwarmEvent::Behaviour(SyndactylEvent::Gossipsub(GossipsubEvent::Message { propagation_source, message_id: _, message })) => {
-                // Try to deserialize as FileEventMessage
-                match serde_json::from_slice::<FileEventMessage>(&message.data) {
-                    Ok(file_event) => {
-                        info!(peer = %propagation_source, event = ?file_event, "[syndactyl][gossipsub] Received FileEventMessage");
-                        
-                        // Check if this is a Create or Modify event with a file we should sync
-                        if matches!(file_event.event_type.as_str(), "Create" | "Modify") {
-                            self.process_file_event(propagation_source, file_event);
-                        }
-                    },
-                    Err(e) => {
-                        warn!(peer = %propagation_source, error = ?e, raw = %String::from_utf8_lossy(&message.data), "[syndactyl][gossipsub] Failed to parse FileEventMessage");
-                    }
+                if message.topic == libp2p::gossipsub::IdentTopic::new(HEARTBEAT_TOPIC).hash() {
+                    // Forwarded heartbeats are reconciled against the peer that published them
+                    self.handle_heartbeat(message.source.unwrap_or(propagation_source), &message.data);
+                    return;
                 }
+                // Decoding and HMAC verification are shared with the event channel path
+                self.handle_gossipsub_message(propagation_source, message.source, message.data);
             }
             SwarmEvent::Behaviour(SyndactylEvent::Kademlia(event)) => {
-                info!(event = ?event, "[syndactyl][kademlia] Event");
+                use libp2p::kad::{Event as KademliaEvent, QueryResult};
+                match event {
+                    // Followed by the DHT discovery backend
+                    KademliaEvent::OutboundQueryProgressed { result: QueryResult::Bootstrap(_), .. } => {}
+                    event => {
+                        if self.log_throttle.allow("kademlia") {
+                            info!(event = ?event, "[syndactyl][kademlia] Event");
+                        }
+                    }
+                }
             }
             SwarmEvent::Behaviour(SyndactylEvent::FileTransfer(event)) => {
                 self.handle_file_transfer_swarm_event(event);
             }
+            SwarmEvent::Behaviour(SyndactylEvent::Identify(event)) => {
+                if let libp2p::identify::Event::Received { peer_id, info, .. } = *event {
+                    debug!(peer_id = %peer_id, observed = %info.observed_addr, agent = %info.agent_version, "[syndactyl][identify] Peer identified");
+                    if peer_stats::compatible(&info.agent_version) == Some(false) {
+                        warn!(peer_id = %peer_id, agent = %info.agent_version, ours = %peer_stats::agent_version(), "[syndactyl][identify] Peer runs an incompatible version");
+                    }
+                    self.peer_stats.record_agent_version(peer_id, info.agent_version);
+                    let reachable: Vec<String> = info.listen_addrs.iter()
+                        .map(|address| address.to_string())
+                        .filter(|address| !address_book::is_loopback(address))
+                        .collect();
+                    self.state.state_mut().address_book.record(&peer_id.to_string(), reachable.iter().map(String::as_str), unix_now());
+                    // Lets the DHT reach peers that moved port or connected to us first
+                    for address in info.listen_addrs {
+                        self.p2p.swarm.behaviour_mut().kademlia.add_address(&peer_id, address);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(SyndactylEvent::Upnp(event)) => {
+                use libp2p::upnp::Event as UpnpEvent;
+                match event {
+                    UpnpEvent::NewExternalAddr(address) => info!(address = %address, "[syndactyl][upnp] Port mapped"),
+                    UpnpEvent::ExpiredExternalAddr(address) => warn!(address = %address, "[syndactyl][upnp] Port mapping expired"),
+                    UpnpEvent::GatewayNotFound => warn!("[syndactyl][upnp] No UPnP gateway found"),
+                    UpnpEvent::NonRoutableGateway => warn!("[syndactyl][upnp] Gateway is not publicly routable, port mapping disabled"),
+                }
+            }
+            SwarmEvent::Behaviour(SyndactylEvent::Ping(event)) => match event.result {
+                Ok(rtt) => self.peer_stats.record_rtt(event.peer, rtt),
+                Err(e) => debug!(peer_id = %event.peer, error = %e, "[syndactyl][ping] Ping failed"),
+            },
+            SwarmEvent::Behaviour(SyndactylEvent::RendezvousPoint(event)) => {
+                use libp2p::rendezvous::server::Event as RendezvousEvent;
+                match event {
+                    RendezvousEvent::PeerRegistered { peer, registration } => {
+                        debug!(peer_id = %peer, namespace = %registration.namespace, "[syndactyl][rendezvous] Peer registered with us");
+                    }
+                    RendezvousEvent::PeerNotRegistered { peer, error, .. } => {
+                        debug!(peer_id = %peer, error = ?error, "[syndactyl][rendezvous] Refused a registration");
+                    }
+                    _ => {}
+                }
+            }
             SwarmEvent::NewListenAddr { address, .. } => {
                 info!(address = %address, "[syndactyl][swarm] Listening on");
+                // Every interface shares the TCP port, so one mapping covers them; websocket ports aren't mapped
+                use libp2p::multiaddr::Protocol;
+                let websocket = address.iter().any(|protocol| matches!(protocol, Protocol::Ws(_)));
+                let port = address.iter().find_map(|protocol| match protocol {
+                    Protocol::Tcp(port) => Some(port),
+                    _ => None,
+                });
+                if let (Some(port), false) = (port, websocket) {
+                    if let Some(tx) = self.nat_pmp_tx.take() {
+                        self.nat_pmp_task = Some(tokio::spawn(nat_pmp::maintain(port, tx)));
+                    }
+                }
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                info!(address = %address, "[syndactyl][swarm] Reachable at");
             }
             SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
                 info!(peer_id = %peer_id, endpoint = ?endpoint, "[syndactyl][swarm] Connection established");
+                let refused = self.connection_policy.as_ref()
+                    .and_then(|policy| policy.check(&peer_id, endpoint.get_remote_address()).err());
+                if let Some(reason) = refused {
+                    warn!(peer_id = %peer_id, reason = %reason, "[syndactyl][swarm] Closing connection refused by strict security");
+                    let _ = self.p2p.swarm.disconnect_peer_id(peer_id);
+                    return;
+                }
+                // Addresses we dialed are worth dialing again; a listener only sees the peer's ephemeral port
+                if endpoint.is_dialer() {
+                    let address = endpoint.get_remote_address().to_string();
+                    self.state.state_mut().address_book.record(&peer_id.to_string(), [address.as_str()], unix_now());
+                }
                 if !self.connected_peers.contains(&peer_id) {
                     self.connected_peers.push(peer_id);
+                    let request_id = self.p2p.request_capabilities(peer_id, self.capabilities.clone());
+                    self.capability_requests.insert(request_id);
+                    // Peers without heads compare trees from the next heartbeat
+                    self.heads.connected(peer_id, endpoint.is_dialer());
+                    self.schedule_heartbeat();
+                    self.discovery.connected(&mut self.p2p.swarm, &peer_id, Instant::now());
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+                debug!(peer_id = %peer_id, error = %error, "[syndactyl][swarm] Outgoing connection failed");
+                self.discovery.dial_failed(&peer_id, &error.to_string());
+            }
+            SwarmEvent::ConnectionClosed { peer_id, cause, num_established, .. } => {
                 warn!(peer_id = %peer_id, ?cause, "[syndactyl][swarm] Connection closed");
+                if num_established == 0 {
+                    self.peer_stats.remove(&peer_id);
+                    self.congestion.remove(&peer_id);
+                    self.peer_capabilities.forget(&peer_id);
+                    self.peer_resources.forget(&peer_id);
+                    self.download_rates.forget(&peer_id);
+                    self.heads.disconnected(&peer_id);
+                    // Streams still open end on their own; their count no longer matters
+                    self.bulk_serving.remove(&peer_id);
+                    for key in peer_resources::forget_serving(&mut self.serving_peers, &peer_id) {
+                        self.served_versions.remove(&key);
+                    }
+                }
+                if num_established == 0 {
+                    self.discovery.disconnected(&peer_id, Instant::now());
+                }
                 self.connected_peers.retain(|p| p != &peer_id);
+                if num_established == 0 {
+                    self.reconciling.retain(|(peer, _), _| peer != &peer_id);
+                }
+                let dropped = self.serve_queue.remove_peer(&peer_id);
+                if dropped > 0 {
+                    info!(peer_id = %peer_id, dropped, "Dropped queued requests for disconnected peer");
+                }
             }
             _ => {
                 // Other swarm events
@@ -451,7 +4840,7 @@ impl NetworkManager {
         &mut self,
         event: libp2p::request_response::Event<
             crate::core::models::SyndactylRequest,
-            FileTransferResponse,
+            SyndactylResponse,
         >,
     ) {
         use libp2p::request_response::Event as RREvent;
@@ -460,181 +4849,179 @@ impl NetworkManager {
 
         match event {
             RREvent::Message { peer, message, .. } => {
+                // Payloads come from untrusted peers; bound every field before acting on it
+                let valid = match &message {
+                    Message::Request { request, .. } => wire::validate_request(request),
+                    Message::Response { response, .. } => wire::validate_response(response),
+                };
+                if let Err(e) = valid {
+                    warn!(peer = %peer, error = %e, "[swarm] Rejected invalid file transfer message");
+                    return;
+                }
                 match message {
                     Message::Request { request, channel, .. } => {
                         // Handle incoming file transfer requests
                         match request {
                             SyndactylRequest::FileTransfer(req) => {
-                                info!(
-                                    peer = %peer,
-                                    observer = %req.observer,
-                                    path = %req.path,
-                                    "[swarm] Received file transfer request"
-                                );
-                                
-                                // Check if we have this observer configured
-                                if let Some(observer_config) = self.observer_configs.get(&req.observer) {
-                                    let base_path = PathBuf::from(&observer_config.path);
-                                    let relative_path = std::path::Path::new(&req.path);
-                                    let absolute_path = file_handler::to_absolute_path(relative_path, &base_path);
-                                    
-                                    if absolute_path.exists() && absolute_path.is_file() {
-                                        // Generate only the first chunk for initial response
-                                        match generate_first_chunk(
-                                            &req.observer,
-                                            relative_path,
-                                            &absolute_path,
-                                            &req.hash,
-                                        ) {
-                                            Ok(first_chunk) => {
-                                                info!(
-                                                    observer = %req.observer,
-                                                    path = %req.path,
-                                                    size = first_chunk.total_size,
-                                                    is_last = first_chunk.is_last_chunk,
-                                                    "Sending first file chunk"
-                                                );
-                                                self.p2p.send_file_response(channel, first_chunk);
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    observer = %req.observer,
-                                                    path = %req.path,
-                                                    error = %e,
-                                                    "Failed to generate first chunk"
-                                                );
-                                            }
-                                        }
-                                    } else {
-                                        warn!(
-                                            observer = %req.observer,
-                                            path = %req.path,
-                                            "File not found or not a file"
-                                        );
-                                    }
-                                } else {
-                                    warn!(observer = %req.observer, "Observer not configured locally");
-                                }
+                                self.handle_file_transfer_request(peer, req, channel);
                             }
                             SyndactylRequest::FileChunk(chunk_req) => {
-                                info!(
-                                    peer = %peer,
-                                    observer = %chunk_req.observer,
-                                    path = %chunk_req.path,
-                                    offset = chunk_req.offset,
-                                    "[swarm] Received file chunk request"
-                                );
-                                
-                                // Check if we have this observer configured
-                                if let Some(observer_config) = self.observer_configs.get(&chunk_req.observer) {
-                                    let base_path = PathBuf::from(&observer_config.path);
-                                    let relative_path = std::path::Path::new(&chunk_req.path);
-                                    let absolute_path = file_handler::to_absolute_path(relative_path, &base_path);
-                                    if absolute_path.exists() && absolute_path.is_file() {
-                                        match file_handler::read_file_chunk(&absolute_path, chunk_req.offset, CHUNK_SIZE) {
-                                            Ok(data) => {
-                                                let total_size = absolute_path.metadata().map(|m| m.len()).unwrap_or(0);
-                                                let is_last_chunk = chunk_req.offset + data.len() as u64 >= total_size;
-                                                let response = FileTransferResponse {
-                                                    observer: chunk_req.observer.clone(),
-                                                    path: chunk_req.path.clone(),
-                                                    data,
-                                                    offset: chunk_req.offset,
-                                                    total_size,
-                                                    hash: chunk_req.hash.clone(),
-                                                    is_last_chunk,
-                                                };
-                                                self.p2p.send_file_response(channel, response);
-                                            }
-                                            Err(e) => {
-                                                error!(
-                                                    observer = %chunk_req.observer,
-                                                    path = %chunk_req.path,
-                                                    error = %e,
-                                                    "Failed to read file chunk"
-                                                );
-                                            }
-                                        }
-                                    } else {
-                                        warn!(
-                                            observer = %chunk_req.observer,
-                                            path = %chunk_req.path,
-                                            "File not found or not a file for chunk request"
-                                        );
-                                    }
-                                } else {
-                                    warn!(observer = %chunk_req.observer, "Observer not configured locally for chunk request");
-                                }
+                                self.handle_file_chunk_request(peer, chunk_req, channel);
+                            }
+                            SyndactylRequest::CancelTransfer(cancel) => {
+                                self.handle_cancel_transfer(peer, cancel, channel);
+                            }
+                            SyndactylRequest::RangeRead(range_req) => {
+                                self.enqueue_serve_request(peer, ServeRequest::RangeRead(range_req, channel));
+                            }
+                            SyndactylRequest::TreeNode(tree_req) => {
+                                self.handle_tree_node_request(peer, tree_req, channel);
+                            }
+                            SyndactylRequest::Capabilities(theirs) => {
+                                self.record_capabilities(peer, theirs);
+                                self.p2p.send_capabilities_response(channel, self.capabilities.clone());
+                            }
+                            SyndactylRequest::Heads(theirs) => {
+                                let ours = self.local_heads(Some(peer));
+                                self.p2p.send_heads_response(channel, ours);
+                                self.apply_heads(peer, theirs);
                             }
                         }
                     }
-                    Message::Response { response, .. } => {
-                        // Handle incoming file transfer responses
-                        info!(
-                            peer = %peer,
-                            observer = %response.observer,
-                            path = %response.path,
-                            offset = response.offset,
-                            size = response.data.len(),
-                            is_last = response.is_last_chunk,
-                            "[swarm] Received file transfer response"
-                        );
-                        
-                        // Add chunk to transfer tracker
-                        match self.transfer_tracker.add_chunk(
-                            &response.observer,
-                            &response.path,
-                            response.offset,
-                            response.data.clone(),
-                            response.is_last_chunk,
-                        ) {
-                            Ok(Some(file_path)) => {
-                                info!(
-                                    observer = %response.observer,
-                                    path = %response.path,
-                                    file = %file_path.display(),
-                                    "File transfer completed and written to disk"
-                                );
-                            }
-                            Ok(None) => {
-                                info!(
-                                    observer = %response.observer,
-                                    path = %response.path,
-                                    "Chunk received, requesting next chunk"
-                                );
-                                // Request next chunk if not last
-                                if !response.is_last_chunk {
-                                    let next_offset = response.offset + response.data.len() as u64;
-                                    let chunk_request = FileChunkRequest {
-                                        observer: response.observer.clone(),
-                                        path: response.path.clone(),
-                                        offset: next_offset,
-                                        hash: response.hash.clone(),
-                                    };
-                                    self.p2p.request_file_chunk(peer, chunk_request);
-                                }
+                    Message::Response { request_id, response } if self.peeks.contains_key(&request_id) => {
+                        self.finish_peek(request_id, response);
+                    }
+                    Message::Response { request_id, response } if self.diff_requests.contains_key(&request_id) => {
+                        self.continue_diff(request_id, response);
+                    }
+                    Message::Response { request_id, response } if self.range_reads.contains_key(&request_id) => {
+                        let result = match response {
+                            SyndactylResponse::Range(range) => {
+                                self.state.state_mut().bandwidth.record_received(&range.observer, &peer.to_string(), range.data.len() as u64);
+                                self.peer_stats.record_received(peer, range.data.len() as u64, Instant::now());
+                                Ok(range.data)
                             }
-                            Err(e) => {
-                                error!(
-                                    observer = %response.observer,
-                                    path = %response.path,
-                                    error = %e,
-                                    "Failed to process file chunk"
-                                );
+                            SyndactylResponse::Error(error) => Err(format!("peer refused range read: {:?}", error.kind)),
+                            _ => Err("unexpected response to range read".to_string()),
+                        };
+                        self.finish_range_read(request_id, result);
+                    }
+                    Message::Response { response: SyndactylResponse::Range(range), .. } => {
+                        debug!(peer = %peer, observer = %range.observer, path = %range.path, "[swarm] Ignoring unrequested range response");
+                    }
+                    Message::Response { request_id, response: SyndactylResponse::Chunk(mut response) } => {
+                        let answered = self.chunk_answered(request_id);
+                        if let Some(encoding) = response.encoding.take() {
+                            let max_len = (response.total_size - response.offset).min(MAX_CHUNK_SIZE as u64) as usize;
+                            match chunk_codec::decode(&response.data, Some(&encoding), response.segment_hash.as_deref(), max_len) {
+                                Ok(data) => response.data = data,
+                                Err(e) => return self.chunk_codec_failed(peer, answered, &response, e),
                             }
                         }
+                        if let Some(chunk) = &answered {
+                            self.fit_chunk(&mut response, chunk.len);
+                        }
+                        #[cfg(feature = "fault-injection")]
+                        let Some(response) = self.inject_chunk_fault(peer, response) else {
+                            return;
+                        };
+                        // Handle incoming file transfer responses
+                        self.handle_file_transfer_response(peer, response);
+                    }
+                    Message::Response { response: SyndactylResponse::CancelAck { observer, path }, .. } => {
+                        info!(peer = %peer, observer = %observer, path = %path, "[swarm] Cancellation acknowledged");
+                    }
+                    Message::Response { response: SyndactylResponse::TreeNode(node), .. } => {
+                        self.handle_tree_node(peer, node);
+                    }
+                    Message::Response { request_id, response: SyndactylResponse::Capabilities(theirs) } => {
+                        self.capability_requests.remove(&request_id);
+                        self.record_capabilities(peer, theirs);
+                    }
+                    Message::Response { response: SyndactylResponse::Heads(theirs), .. } => {
+                        self.apply_heads(peer, theirs);
+                    }
+                    Message::Response { request_id, response: SyndactylResponse::Error(error) } => {
+                        if let Some(chunk) = self.chunk_requests.remove(&request_id) {
+                            self.drop_helper(&chunk);
+                        }
+                        self.handle_transfer_error(peer, error);
                     }
                 }
             }
             RREvent::OutboundFailure { peer, request_id, error, .. } => {
                 error!(peer = %peer, request_id = ?request_id, error = ?error, "[swarm] File transfer outbound failure");
+                if let Some(chunk) = self.chunk_requests.remove(&request_id) {
+                    self.chunk_failed(chunk);
+                }
+                self.finish_range_read(request_id, Err(format!("request failed: {}", error)));
+                if let Some(peek) = self.peeks.remove(&request_id) {
+                    let _ = peek.reply.send(ControlResponse::Error { message: format!("Peer did not answer: {}", error) });
+                }
+                if let Some((diff, observer)) = self.diff_requests.remove(&request_id) {
+                    self.fail_diff(diff, format!("Peer did not list '{}': {}", observer, error));
+                }
+                // A peer that can't decode the request drops the stream; one that timed out may just be slow
+                if self.capability_requests.remove(&request_id)
+                    && matches!(error, libp2p::request_response::OutboundFailure::Io(_) | libp2p::request_response::OutboundFailure::UnsupportedProtocols)
+                {
+                    debug!(peer = %peer, "Peer predates capabilities documents, assuming the features it had");
+                    self.peer_capabilities.record_legacy(peer);
+                    self.enforce_capabilities(peer, None);
+                }
             }
             RREvent::InboundFailure { peer, error, .. } => {
                 error!(peer = %peer, error = ?error, "[swarm] File transfer inbound failure");
             }
             RREvent::ResponseSent { peer, .. } => {
-                info!(peer = %peer, "[swarm] File transfer response sent");
+                if self.log_throttle.allow("serve") {
+                    info!(peer = %peer, "[swarm] File transfer response sent");
+                }
+            }
+        }
+    }
+
+    /// Remember what a peer supports, warning if it can't sync with us
+    fn record_capabilities(&mut self, peer: PeerId, theirs: Capabilities) {
+        debug!(peer = %peer, version = %theirs.version, features = ?theirs.features, "Peer capabilities");
+        if let Err(e) = self.peer_capabilities.record(peer, theirs.clone()) {
+            warn!(peer = %peer, error = %e, "Peer can't verify the files we sync");
+        }
+        if !self.enforce_capabilities(peer, Some(&theirs)) {
+            return;
+        }
+        if self.heads.capabilities_known(&peer, self.peer_capabilities.confirms(&peer, capabilities::HEADS)) {
+            self.send_heads(peer);
+        }
+    }
+
+    /// Disconnect a peer whose capabilities fall below what strict security requires, returning false if it was
+    fn enforce_capabilities(&mut self, peer: PeerId, theirs: Option<&Capabilities>) -> bool {
+        let refused = self.connection_policy.as_ref().and_then(|policy| policy.check_capabilities(theirs).err());
+        let Some(reason) = refused else {
+            return true;
+        };
+        warn!(peer_id = %peer, reason = %reason, "[syndactyl][swarm] Closing connection refused by strict security");
+        let _ = self.p2p.swarm.disconnect_peer_id(peer);
+        false
+    }
+
+    /// Account a served chunk in bandwidth stats and the audit log, if auditing is enabled
+    fn record_served(&mut self, peer: &PeerId, observer: &str, path: &str, offset: u64, len: u64) {
+        self.state.state_mut().bandwidth.record_sent(observer, &peer.to_string(), len);
+        self.peer_stats.record_sent(*peer, len, Instant::now());
+
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = audit_log.record(&peer.to_string(), observer, path, offset, len) {
+                error!(peer = %peer, observer = %observer, path = %path, error = %e, "Failed to write audit log entry");
             }
         }
     }
 }
+
+/// Log an observer's lifecycle change, if there was one
+fn log_lifecycle(transition: Option<Transition>) {
+    if let Some(transition) = transition {
+        transition.log();
+    }
+}
@@ -3,4 +3,31 @@
 pub mod syndactyl_behaviour;
 pub mod syndactyl_p2p;
 pub mod transfer;
+pub mod served;
 pub mod manager;
+pub mod scheduler;
+pub mod wire;
+pub mod bootstrap;
+pub mod peer_stats;
+pub mod peer_resources;
+pub mod congestion;
+pub mod distribution;
+pub mod proxy;
+pub mod transport;
+pub mod status_feed;
+pub mod tray_feed;
+pub mod key_pins;
+pub mod chunk_cache;
+pub mod chunk_codec;
+pub mod security;
+pub mod anti_entropy;
+pub mod peer_diff;
+pub mod discovery;
+pub mod bulk;
+pub mod gossip;
+pub mod tuning;
+pub mod capabilities;
+pub mod heads;
+pub mod nat_pmp;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
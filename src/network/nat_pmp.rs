@@ -0,0 +1,263 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+use libp2p::Multiaddr;
+use libp2p::multiaddr::Protocol;
+use tokio::sync::mpsc::Sender;
+use tracing::{info, warn};
+
+/// Port NAT-PMP gateways answer on (RFC 6886)
+const GATEWAY_PORT: u16 = 5351;
+
+/// Lifetime asked for a mapping, the one the RFC recommends; renewed halfway through
+pub const MAPPING_LIFETIME: Duration = Duration::from_secs(7200);
+
+/// How long shutdown waits for the gateway to drop the mapping
+pub const UNMAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the default gateway can be found on this platform
+pub const GATEWAY_LOOKUP: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+));
+
+/// Delay before trying again after the gateway refused or didn't answer
+const RETRY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// First wait for an answer, doubled on each of the retries
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const ATTEMPTS: u32 = 4;
+
+const OP_EXTERNAL_ADDRESS: u8 = 0;
+const OP_MAP_TCP: u8 = 2;
+
+/// A port forwarded by the gateway
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    pub external: SocketAddrV4,
+    pub lifetime: Duration,
+}
+
+impl Mapping {
+    pub fn multiaddr(&self) -> Multiaddr {
+        Multiaddr::empty()
+            .with(Protocol::Ip4(*self.external.ip()))
+            .with(Protocol::Tcp(self.external.port()))
+    }
+}
+
+/// External address reported by `maintain`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressChange {
+    Added(Multiaddr),
+    /// The mapping moved or lapsed, so peers can no longer reach us there
+    Removed(Multiaddr),
+}
+
+/// Default IPv4 gateway, from the kernel's routing table
+#[cfg(target_os = "linux")]
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    parse_route_table(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+/// Default IPv4 gateway, as `route` reports it; blocks while the command runs
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    let output = std::process::Command::new("route").args(["-n", "get", "default"]).output().ok()?;
+    parse_route_get(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Default IPv4 gateway; not looked up here, so `nat_pmp` is refused at startup
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly")))]
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    None
+}
+
+/// Gateway in the output of `route -n get default` on macOS and the BSDs
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn parse_route_get(output: &str) -> Option<Ipv4Addr> {
+    output.lines().find_map(|line| line.trim().strip_prefix("gateway:")?.trim().parse().ok())
+}
+
+/// Gateway of the default route in /proc/net/route, whose addresses are little-endian hex
+fn parse_route_table(table: &str) -> Option<Ipv4Addr> {
+    table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[1] != "00000000" {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields[2], 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.swap_bytes()))
+    })
+}
+
+/// A mapping request; a zero lifetime asks for the mapping to be deleted
+fn encode_map_request(internal_port: u16, lifetime: Duration) -> [u8; 12] {
+    let mut request = [0u8; 12];
+    request[1] = OP_MAP_TCP;
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    // Ask for the same port outside; the gateway picks another if it's taken.
+    // Deletions must suggest port 0.
+    let external_port = if lifetime.is_zero() { 0 } else { internal_port };
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&(lifetime.as_secs().min(u32::MAX as u64) as u32).to_be_bytes());
+    request
+}
+
+/// Check a response's header, returning its body past the epoch
+fn check_response(response: &[u8], op: u8) -> io::Result<&[u8]> {
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    if response.len() < 8 || response[0] != 0 || response[1] != op | 0x80 {
+        return Err(invalid(format!("unexpected NAT-PMP response to opcode {}", op)));
+    }
+    match u16::from_be_bytes([response[2], response[3]]) {
+        0 => Ok(&response[8..]),
+        1 => Err(invalid("gateway doesn't support this NAT-PMP version".to_string())),
+        2 => Err(io::Error::new(io::ErrorKind::PermissionDenied, "gateway refused the mapping")),
+        3 => Err(invalid("gateway has no external address".to_string())),
+        code => Err(invalid(format!("gateway failed with result code {}", code))),
+    }
+}
+
+fn decode_external_address(response: &[u8]) -> io::Result<Ipv4Addr> {
+    let body = check_response(response, OP_EXTERNAL_ADDRESS)?;
+    let ip: [u8; 4] = body.get(..4)
+        .and_then(|ip| ip.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short external address response"))?;
+    Ok(Ipv4Addr::from(ip))
+}
+
+/// The mapped external port and the lifetime granted
+fn decode_map_response(response: &[u8], internal_port: u16) -> io::Result<(u16, Duration)> {
+    let body = check_response(response, OP_MAP_TCP)?;
+    if body.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short mapping response"));
+    }
+    if u16::from_be_bytes([body[0], body[1]]) != internal_port {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "mapping response for another port"));
+    }
+    let external_port = u16::from_be_bytes([body[2], body[3]]);
+    let lifetime = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+    Ok((external_port, Duration::from_secs(lifetime as u64)))
+}
+
+/// Send a request to the gateway, resending with a doubling timeout until it answers
+fn exchange(socket: &UdpSocket, request: &[u8]) -> io::Result<Vec<u8>> {
+    let mut buf = [0u8; 16];
+    let mut timeout = INITIAL_TIMEOUT;
+    for _ in 0..ATTEMPTS {
+        socket.send(request)?;
+        socket.set_read_timeout(Some(timeout))?;
+        match socket.recv(&mut buf) {
+            Ok(len) => return Ok(buf[..len].to_vec()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => timeout *= 2,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "no answer from the NAT-PMP gateway"))
+}
+
+/// Ask `gateway` to forward TCP `port` to us; blocks for up to a few seconds
+pub fn map_tcp(gateway: Ipv4Addr, port: u16, lifetime: Duration) -> io::Result<Mapping> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(SocketAddrV4::new(gateway, GATEWAY_PORT))?;
+    let ip = decode_external_address(&exchange(&socket, &[0, OP_EXTERNAL_ADDRESS])?)?;
+    let (external_port, lifetime) = decode_map_response(&exchange(&socket, &encode_map_request(port, lifetime))?, port)?;
+    Ok(Mapping { external: SocketAddrV4::new(ip, external_port), lifetime })
+}
+
+/// Ask `gateway` to stop forwarding TCP `port`; blocks for up to a few seconds
+pub fn unmap_tcp(gateway: Ipv4Addr, port: u16) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(SocketAddrV4::new(gateway, GATEWAY_PORT))?;
+    decode_map_response(&exchange(&socket, &encode_map_request(port, Duration::ZERO))?, port).map(|_| ())
+}
+
+/// Keep TCP `port` forwarded by the default gateway, reporting the external address as it changes
+/// Returns when there is no gateway, or once the receiver is gone and the mapping is deleted.
+pub async fn maintain(port: u16, changes: Sender<AddressChange>) {
+    let Some(gateway) = tokio::task::spawn_blocking(default_gateway).await.ok().flatten() else {
+        warn!("[syndactyl][nat-pmp] No default gateway found, port mapping disabled");
+        return;
+    };
+    let mut current: Option<(Mapping, Instant)> = None;
+    'renewals: loop {
+        let mapped = tokio::task::spawn_blocking(move || map_tcp(gateway, port, MAPPING_LIFETIME)).await;
+        let wait = match mapped {
+            Ok(Ok(mapping)) => {
+                let previous = current.replace((mapping, Instant::now())).map(|(previous, _)| previous);
+                if previous.map(|previous| previous.external) != Some(mapping.external) {
+                    info!(address = %mapping.external, lifetime_secs = mapping.lifetime.as_secs(), "[syndactyl][nat-pmp] Port mapped");
+                    let removed = previous.map(|previous| AddressChange::Removed(previous.multiaddr()));
+                    for change in removed.into_iter().chain([AddressChange::Added(mapping.multiaddr())]) {
+                        if changes.send(change).await.is_err() {
+                            break 'renewals;
+                        }
+                    }
+                }
+                (mapping.lifetime / 2).max(Duration::from_secs(60))
+            }
+            Ok(Err(e)) => {
+                warn!(gateway = %gateway, error = %e, "[syndactyl][nat-pmp] Port mapping failed");
+                // Unless renewed in time, the gateway drops the mapping
+                if let Some((lapsed, _)) = current.take_if(|(mapping, since)| since.elapsed() >= mapping.lifetime) {
+                    let _ = changes.send(AddressChange::Removed(lapsed.multiaddr())).await;
+                }
+                RETRY_INTERVAL
+            }
+            Err(_) => return,
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = changes.closed() => break,
+        }
+    }
+    if current.is_some() {
+        match tokio::task::spawn_blocking(move || unmap_tcp(gateway, port)).await {
+            Ok(Ok(())) => info!(gateway = %gateway, "[syndactyl][nat-pmp] Port mapping removed"),
+            Ok(Err(e)) => warn!(gateway = %gateway, error = %e, "[syndactyl][nat-pmp] Failed to remove port mapping"),
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_messages_follow_rfc_6886() {
+        let table = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\n";
+        assert_eq!(parse_route_table(table), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(parse_route_table("Iface\tDestination\tGateway\n"), None);
+
+        assert_eq!(encode_map_request(4001, MAPPING_LIFETIME), [0, 2, 0, 0, 0x0f, 0xa1, 0x0f, 0xa1, 0, 0, 0x1c, 0x20]);
+        // Deleting a mapping suggests no external port and asks for no lifetime
+        assert_eq!(encode_map_request(4001, Duration::ZERO), [0, 2, 0, 0, 0x0f, 0xa1, 0, 0, 0, 0, 0, 0]);
+
+        let route_get = "   route to: default\ndestination: default\n       mask: default\n    gateway: 10.0.0.1\n  interface: en0\n";
+        assert_eq!(parse_route_get(route_get), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(parse_route_get("route: writing to routing socket: not in table\n"), None);
+
+        let external = [0, 128, 0, 0, 0, 0, 0, 9, 203, 0, 113, 7];
+        assert_eq!(decode_external_address(&external).unwrap(), Ipv4Addr::new(203, 0, 113, 7));
+
+        // The gateway may hand out another external port and a shorter lifetime
+        let mapped = [0, 130, 0, 0, 0, 0, 0, 9, 0x0f, 0xa1, 0x13, 0x88, 0, 0, 0x0e, 0x10];
+        assert_eq!(decode_map_response(&mapped, 4001).unwrap(), (5000, Duration::from_secs(3600)));
+        assert!(decode_map_response(&mapped, 4002).is_err());
+
+        let refused = [0, 130, 0, 2, 0, 0, 0, 9, 0x0f, 0xa1, 0, 0, 0, 0, 0, 0];
+        assert_eq!(decode_map_response(&refused, 4001).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(decode_external_address(&mapped).is_err());
+
+        let mapping = Mapping { external: SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), 5000), lifetime: MAPPING_LIFETIME };
+        assert_eq!(mapping.multiaddr().to_string(), "/ip4/203.0.113.7/tcp/5000");
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::core::merkle::{self, FileIndex, MerkleTree};
+use crate::core::models::{TreeEntry, TreeNodeResponse};
+use crate::core::seen_events::SeenEvents;
+
+/// One file that differs between our copy of an observer and a peer's
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiffEntry {
+    pub path: String,
+    #[serde(default)]
+    pub local_size: Option<u64>,
+    #[serde(default)]
+    pub remote_size: Option<u64>,
+    #[serde(default)]
+    pub local_modified: Option<u64>,
+    #[serde(default)]
+    pub remote_modified: Option<u64>,
+}
+
+/// How an observer differs from a peer's copy, for `syndactyl diff`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DiffReport {
+    pub observer: String,
+    pub peer: String,
+    /// Files only the peer has
+    pub missing_here: Vec<DiffEntry>,
+    /// Files only we have
+    pub missing_there: Vec<DiffEntry>,
+    /// Files whose content differs, one side holding a version the other had before
+    pub differing: Vec<DiffEntry>,
+    /// Files whose content differs with neither version known to have come from the other side
+    pub conflicts: Vec<DiffEntry>,
+    /// Files with the same content on both sides
+    pub identical: u64,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing_here.is_empty() && self.missing_there.is_empty() && self.differing.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// A walk of a peer's tree for one observer, comparing each directory with ours
+/// Only directories whose hashes differ are listed, so trees that mostly match
+/// take few round trips. Nothing is fetched or recorded.
+pub struct PeerDiff {
+    report: DiffReport,
+    /// Listings requested and not yet answered
+    pending: usize,
+    /// Entries of directories listed in pages, until the last page arrives
+    pages: HashMap<String, Vec<TreeEntry>>,
+}
+
+impl PeerDiff {
+    pub fn new(observer: &str, peer: &str) -> Self {
+        Self {
+            report: DiffReport { observer: observer.to_string(), peer: peer.to_string(), ..DiffReport::default() },
+            pending: 0,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Note a listing was requested
+    pub fn requested(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Take in one page of the peer's listing, returning the subdirectories to list next
+    /// A directory is compared once all of its pages have arrived, as only the whole
+    /// listing shows which of our files the peer lacks.
+    pub fn add(&mut self, node: &TreeNodeResponse, tree: &MerkleTree, index: &FileIndex, seen: &SeenEvents) -> Vec<String> {
+        self.pending = self.pending.saturating_sub(1);
+        let mut remote = self.pages.remove(&node.dir).unwrap_or_default();
+        remote.extend(node.entries.iter().cloned());
+        if node.next.is_some() {
+            self.pages.insert(node.dir.clone(), remote);
+            return Vec::new();
+        }
+
+        let observer = self.report.observer.clone();
+        let local = tree.entries(&node.dir);
+        let local_by_name: HashMap<&str, &TreeEntry> = local.iter().map(|entry| (entry.name.as_str(), entry)).collect();
+        let remote_names: HashMap<&str, &TreeEntry> = remote.iter().map(|entry| (entry.name.as_str(), entry)).collect();
+        let mut descend = Vec::new();
+        for entry in merkle::differing(local, &remote) {
+            let path = merkle::join(&node.dir, &entry.name);
+            let ours = local_by_name.get(entry.name.as_str()).copied().filter(|local| !local.is_dir);
+            match (entry.is_dir, ours) {
+                (true, _) => descend.push(path),
+                (false, None) => self.report.missing_here.push(diff_entry(path, None, Some(entry))),
+                (false, Some(ours)) => {
+                    // A version that arrived from a peer wasn't edited here
+                    let known = seen.contains(&observer, &path, &ours.hash) || seen.contains(&observer, &path, &entry.hash);
+                    let diff = diff_entry(path, Some(ours), Some(entry));
+                    if known {
+                        self.report.differing.push(diff);
+                    } else {
+                        self.report.conflicts.push(diff);
+                    }
+                }
+            }
+        }
+        for entry in local {
+            let path = merkle::join(&node.dir, &entry.name);
+            match remote_names.get(entry.name.as_str()) {
+                Some(remote) if remote.is_dir == entry.is_dir => {}
+                // A local directory where the peer has a file, or nothing, is ours alone
+                _ if entry.is_dir => {
+                    for (path, file) in index.under(&observer, &path) {
+                        self.report.missing_there.push(DiffEntry {
+                            path,
+                            local_size: Some(file.size),
+                            remote_size: None,
+                            local_modified: Some(file.modified_time),
+                            remote_modified: None,
+                        });
+                    }
+                }
+                _ => self.report.missing_there.push(diff_entry(path, Some(entry), None)),
+            }
+        }
+        descend
+    }
+
+    /// Whether every listing asked for has been answered
+    pub fn is_done(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// The differences found, counting the rest of our `local_files` as identical
+    pub fn finish(mut self, local_files: usize) -> DiffReport {
+        let report = &mut self.report;
+        let ours = report.missing_there.len() + report.differing.len() + report.conflicts.len();
+        report.identical = local_files.saturating_sub(ours) as u64;
+        for list in [&mut report.missing_here, &mut report.missing_there, &mut report.differing, &mut report.conflicts] {
+            list.sort_by(|a, b| a.path.cmp(&b.path));
+        }
+        self.report
+    }
+}
+
+fn diff_entry(path: String, local: Option<&TreeEntry>, remote: Option<&TreeEntry>) -> DiffEntry {
+    DiffEntry {
+        path,
+        local_size: local.and_then(|entry| entry.size),
+        remote_size: remote.and_then(|entry| entry.size),
+        local_modified: local.and_then(|entry| entry.modified_time),
+        remote_modified: remote.and_then(|entry| entry.modified_time),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::FileEventMessage;
+
+    fn event(path: &str, hash: &str) -> FileEventMessage {
+        serde_json::from_value(serde_json::json!({
+            "observer": "docs", "event_type": "Modify", "path": path, "details": null,
+            "hash": hash, "size": 4, "modified_time": 100, "hmac": null,
+        })).unwrap()
+    }
+
+    fn listing(index: &FileIndex, dir: &str) -> TreeNodeResponse {
+        let tree = index.tree("docs");
+        TreeNodeResponse {
+            observer: "docs".to_string(),
+            dir: dir.to_string(),
+            entries: tree.entries(dir).to_vec(),
+            hmac: None,
+            tombstones: Vec::new(),
+            next: None,
+            total_entries: tree.entries(dir).len() as u64,
+        }
+    }
+
+    #[test]
+    fn test_diff_sorts_differences_without_fetching() {
+        let (mut ours, mut theirs) = (FileIndex::default(), FileIndex::default());
+        for (path, hash) in [("same.txt", "h1"), ("notes/mine.txt", "h2"), ("notes/edited.txt", "h3"), ("notes/synced.txt", "h4"), ("local/a.txt", "h5")] {
+            ours.record(&event(path, hash));
+        }
+        for (path, hash) in [("same.txt", "h1"), ("notes/theirs.txt", "h6"), ("notes/edited.txt", "h7"), ("notes/synced.txt", "h8")] {
+            theirs.record(&event(path, hash));
+        }
+        let mut seen = SeenEvents::default();
+        seen.record("docs", "notes/synced.txt", "h4", 100);
+
+        let tree = ours.tree("docs");
+        let mut diff = PeerDiff::new("docs", "peer");
+        diff.requested();
+        let mut paged = listing(&theirs, "");
+        let rest = paged.entries.split_off(1);
+        paged.next = Some(paged.entries[0].name.clone());
+        assert!(diff.add(&paged, &tree, &ours, &seen).is_empty());
+        paged.entries = rest;
+        paged.next = None;
+        let descend = diff.add(&paged, &tree, &ours, &seen);
+        assert_eq!(descend, vec!["notes".to_string()]);
+        assert!(diff.is_done());
+
+        diff.requested();
+        assert!(diff.add(&listing(&theirs, "notes"), &tree, &ours, &seen).is_empty());
+        let report = diff.finish(tree.file_count());
+        let paths = |list: &[DiffEntry]| list.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&report.missing_here), vec!["notes/theirs.txt"]);
+        assert_eq!(paths(&report.missing_there), vec!["local/a.txt", "notes/mine.txt"]);
+        assert_eq!(paths(&report.differing), vec!["notes/synced.txt"]);
+        assert_eq!(paths(&report.conflicts), vec!["notes/edited.txt"]);
+        assert_eq!(report.identical, 1);
+        assert!(!report.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use crate::core::models::PeerLoad;
+
+/// Transfers served at once before new ones are refused, unless configured
+pub const DEFAULT_MAX_SERVING_TRANSFERS: u32 = 64;
+
+/// How long a refused requester is asked to wait before trying again
+pub const BUSY_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Longest wait a peer's retry-after hint is honoured for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Advertisements older than this no longer say how busy a peer is
+/// Heartbeats go out every minute, so a few may be missed.
+const STALE_AFTER: Duration = Duration::from_secs(180);
+
+/// Load ranked for peers that don't advertise theirs, in thousandths of capacity
+const UNKNOWN_LOAD: u32 = 500;
+
+/// Whether a new transfer may be served with `serving` already under way
+/// Returns how many seconds the requester should wait otherwise.
+pub fn admit(serving: usize, capacity: u32) -> Result<(), u64> {
+    if capacity > 0 && serving >= capacity as usize {
+        return Err(BUSY_RETRY_AFTER.as_secs());
+    }
+    Ok(())
+}
+
+/// Stop counting `peer` among those pulling each file from us
+/// Returns the files nobody pulls anymore, whose served state can go too.
+pub fn forget_serving(serving: &mut HashMap<(String, String), HashSet<PeerId>>, peer: &PeerId) -> Vec<(String, String)> {
+    let mut idle = Vec::new();
+    serving.retain(|key, peers| {
+        if peers.remove(peer) && peers.is_empty() {
+            idle.push(key.clone());
+            return false;
+        }
+        !peers.is_empty()
+    });
+    idle
+}
+
+struct Advertised {
+    load: Option<PeerLoad>,
+    /// Free space per observer
+    available: HashMap<String, u64>,
+    at: Instant,
+}
+
+/// Load and free space peers advertise, and which refused transfers for now
+/// Consulted to pick whom to download from; a peer that turns out busy answers
+/// with a retry-after hint, kept here until it passes.
+#[derive(Default)]
+pub struct PeerResources {
+    peers: HashMap<PeerId, Advertised>,
+    busy_until: HashMap<PeerId, Instant>,
+}
+
+impl PeerResources {
+    /// Remember what a peer's heartbeat advertised
+    pub fn record(&mut self, peer: PeerId, load: Option<PeerLoad>, available: impl IntoIterator<Item = (String, u64)>, now: Instant) {
+        let available = available.into_iter().collect();
+        self.peers.insert(peer, Advertised { load, available, at: now });
+    }
+
+    /// Remember that a peer refused a transfer, for `retry_after_secs`
+    pub fn mark_busy(&mut self, peer: PeerId, retry_after_secs: u64, now: Instant) -> Instant {
+        let until = now + Duration::from_secs(retry_after_secs).min(MAX_RETRY_AFTER);
+        self.busy_until.insert(peer, until);
+        until
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.busy_until.remove(peer);
+    }
+
+    fn fresh(&self, peer: &PeerId, now: Instant) -> Option<&Advertised> {
+        self.peers.get(peer).filter(|advertised| now.saturating_duration_since(advertised.at) < STALE_AFTER)
+    }
+
+    /// Whether `peer` would refuse a new transfer now, as far as we know
+    pub fn is_busy(&self, peer: &PeerId, now: Instant) -> bool {
+        if self.busy_until.get(peer).is_some_and(|until| *until > now) {
+            return true;
+        }
+        let load = self.fresh(peer, now).and_then(|advertised| advertised.load);
+        load.is_some_and(|load| admit(load.serving as usize, load.capacity).is_err())
+    }
+
+    /// When a transfer from a busy `peer` is worth asking for again
+    pub fn retry_at(&self, peer: &PeerId, now: Instant) -> Instant {
+        self.busy_until.get(peer).copied().filter(|until| *until > now).unwrap_or(now + BUSY_RETRY_AFTER)
+    }
+
+    /// The peers that aren't busy, least loaded first, then with the most space free for `observer`
+    /// Ties keep the order they came in.
+    pub fn rank(&self, observer: &str, candidates: impl IntoIterator<Item = PeerId>, now: Instant) -> Vec<PeerId> {
+        let mut ranked: Vec<PeerId> = candidates.into_iter().filter(|peer| !self.is_busy(peer, now)).collect();
+        ranked.sort_by_key(|peer| {
+            let advertised = self.fresh(peer, now);
+            let load = advertised.and_then(|advertised| advertised.load).map_or(UNKNOWN_LOAD, |load| {
+                match load.capacity {
+                    0 => 0,
+                    capacity => (load.serving.min(capacity) as u64 * 1000 / capacity as u64) as u32,
+                }
+            });
+            let available = advertised.and_then(|advertised| advertised.available.get(observer).copied()).unwrap_or(0);
+            (load, std::cmp::Reverse(available))
+        });
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busy_peers_are_skipped_and_idle_ones_preferred() {
+        assert_eq!(admit(3, 4), Ok(()));
+        assert_eq!(admit(4, 4), Err(BUSY_RETRY_AFTER.as_secs()));
+        assert_eq!(admit(1000, 0), Ok(()));
+
+        let now = Instant::now();
+        let (idle, loaded, full, roomy, quiet) = (PeerId::random(), PeerId::random(), PeerId::random(), PeerId::random(), PeerId::random());
+        let mut resources = PeerResources::default();
+        resources.record(idle, Some(PeerLoad { serving: 0, capacity: 8 }), vec![("docs".to_string(), 10)], now);
+        resources.record(roomy, Some(PeerLoad { serving: 0, capacity: 8 }), vec![("docs".to_string(), 500)], now);
+        resources.record(loaded, Some(PeerLoad { serving: 6, capacity: 8 }), Vec::new(), now);
+        resources.record(full, Some(PeerLoad { serving: 8, capacity: 8 }), Vec::new(), now);
+
+        assert!(resources.is_busy(&full, now) && !resources.is_busy(&loaded, now));
+        assert_eq!(resources.rank("docs", [full, loaded, quiet, idle, roomy], now), vec![roomy, idle, quiet, loaded]);
+
+        // A refusal holds until its hint passes; a stale advertisement stops counting
+        let until = resources.mark_busy(idle, 20, now);
+        assert!(resources.is_busy(&idle, now + Duration::from_secs(19)));
+        assert_eq!(resources.retry_at(&idle, now), until);
+        let later = now + STALE_AFTER;
+        assert!(!resources.is_busy(&idle, later) && !resources.is_busy(&full, later));
+        assert_eq!(resources.retry_at(&full, later), later + BUSY_RETRY_AFTER);
+        resources.forget(&full);
+        assert!(!resources.is_busy(&full, now));
+    }
+
+    #[test]
+    fn test_disconnected_peer_stops_counting_as_served() {
+        let (gone, staying) = (PeerId::random(), PeerId::random());
+        let shared = ("docs".to_string(), "shared.txt".to_string());
+        let only = ("docs".to_string(), "only.txt".to_string());
+        let mut serving: HashMap<(String, String), HashSet<PeerId>> = HashMap::new();
+        serving.entry(shared.clone()).or_default().extend([gone, staying]);
+        serving.entry(only.clone()).or_default().insert(gone);
+
+        assert_eq!(forget_serving(&mut serving, &gone), vec![only.clone()]);
+        assert_eq!(serving.len(), 1);
+        assert_eq!(serving[&shared], HashSet::from([staying]));
+        assert!(forget_serving(&mut serving, &gone).is_empty());
+        assert_eq!(forget_serving(&mut serving, &staying), vec![shared]);
+        assert!(serving.is_empty());
+    }
+}
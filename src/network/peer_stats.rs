@@ -0,0 +1,167 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+
+/// How far back throughput is averaged
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(30);
+
+/// Agent version this build sends over identify, e.g. "syndactyl/0.1.0"
+pub fn agent_version() -> String {
+    format!("syndactyl/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Whether a peer's agent version speaks the same protocols as this build
+/// Releases are compatible within a major version, or within a minor version
+/// before 1.0. None when the agent isn't a syndactyl version at all.
+pub fn compatible(agent_version: &str) -> Option<bool> {
+    let parse = |agent: &str| -> Option<(u64, u64)> {
+        let version = agent.strip_prefix("syndactyl/")?;
+        let mut parts = version.split(['.', '-', '+']);
+        Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+    };
+    let (major, minor) = parse(agent_version)?;
+    let (our_major, our_minor) = parse(&self::agent_version())?;
+    Some(major == our_major && (major > 0 || minor == our_minor))
+}
+
+/// Latency and recent throughput of one connected peer, for `syndactyl peers`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    /// Last ping round trip, once one has completed
+    pub rtt_ms: Option<u64>,
+    /// Bytes per second received from the peer over the last 30 seconds
+    pub download_bps: u64,
+    /// Bytes per second sent to the peer over the last 30 seconds
+    pub upload_bps: u64,
+    /// What the peer reported over identify, e.g. "syndactyl/0.1.0"
+    pub agent_version: Option<String>,
+    /// Whether that version is compatible with ours; None if unknown
+    pub compatible: Option<bool>,
+}
+
+#[derive(Default)]
+struct Stat {
+    rtt: Option<Duration>,
+    received: VecDeque<(Instant, u64)>,
+    sent: VecDeque<(Instant, u64)>,
+    agent_version: Option<String>,
+}
+
+/// Rolling per-peer measurements collected by the network manager
+#[derive(Default)]
+pub struct PeerStats {
+    peers: HashMap<PeerId, Stat>,
+}
+
+fn record(samples: &mut VecDeque<(Instant, u64)>, bytes: u64, now: Instant) {
+    samples.push_back((now, bytes));
+    while samples.front().is_some_and(|(at, _)| now.duration_since(*at) > THROUGHPUT_WINDOW) {
+        samples.pop_front();
+    }
+}
+
+fn rate(samples: &VecDeque<(Instant, u64)>, now: Instant) -> u64 {
+    let bytes: u64 = samples.iter()
+        .filter(|(at, _)| now.duration_since(*at) <= THROUGHPUT_WINDOW)
+        .map(|(_, bytes)| bytes)
+        .sum();
+    bytes / THROUGHPUT_WINDOW.as_secs()
+}
+
+impl PeerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_rtt(&mut self, peer: PeerId, rtt: Duration) {
+        self.peers.entry(peer).or_default().rtt = Some(rtt);
+    }
+
+    pub fn record_received(&mut self, peer: PeerId, bytes: u64, now: Instant) {
+        record(&mut self.peers.entry(peer).or_default().received, bytes, now);
+    }
+
+    pub fn record_sent(&mut self, peer: PeerId, bytes: u64, now: Instant) {
+        record(&mut self.peers.entry(peer).or_default().sent, bytes, now);
+    }
+
+    pub fn record_agent_version(&mut self, peer: PeerId, agent_version: String) {
+        self.peers.entry(peer).or_default().agent_version = Some(agent_version);
+    }
+
+    /// Forget a peer once its last connection closes
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    /// The candidate that delivered data fastest recently, falling back to the
+    /// lowest ping and then to the first candidate
+    pub fn fastest<'a>(&self, candidates: impl IntoIterator<Item = &'a PeerId>, now: Instant) -> Option<PeerId> {
+        candidates.into_iter()
+            .enumerate()
+            .max_by_key(|(index, peer)| {
+                let stat = self.peers.get(peer);
+                let download = stat.map_or(0, |stat| rate(&stat.received, now));
+                // Unmeasured peers rank after measured ones, and earlier candidates win ties
+                let rtt = stat.and_then(|stat| stat.rtt).unwrap_or(Duration::MAX);
+                (download, std::cmp::Reverse(rtt), std::cmp::Reverse(*index))
+            })
+            .map(|(_, peer)| *peer)
+    }
+
+    pub fn info(&self, peer: &PeerId, now: Instant) -> PeerInfo {
+        let stat = self.peers.get(peer);
+        let agent_version = stat.and_then(|stat| stat.agent_version.clone());
+        PeerInfo {
+            peer_id: peer.to_string(),
+            compatible: agent_version.as_deref().and_then(compatible),
+            agent_version,
+            rtt_ms: stat.and_then(|stat| stat.rtt).map(|rtt| rtt.as_millis() as u64),
+            download_bps: stat.map_or(0, |stat| rate(&stat.received, now)),
+            upload_bps: stat.map_or(0, |stat| rate(&stat.sent, now)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fastest_prefers_throughput_then_latency() {
+        let (slow, fast, unmeasured) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut stats = PeerStats::new();
+        let now = Instant::now();
+        stats.record_rtt(slow, Duration::from_millis(20));
+        stats.record_rtt(fast, Duration::from_millis(80));
+        assert_eq!(stats.fastest([&unmeasured, &fast, &slow], now), Some(slow));
+
+        stats.record_received(fast, 3_000_000, now);
+        stats.record_received(slow, 300_000, now);
+        assert_eq!(stats.fastest([&slow, &fast], now), Some(fast));
+        assert_eq!(stats.info(&fast, now).download_bps, 100_000);
+
+        // Old samples age out of the window
+        let later = now + THROUGHPUT_WINDOW + Duration::from_secs(1);
+        assert_eq!(stats.info(&fast, later).download_bps, 0);
+        assert_eq!(stats.fastest([&unmeasured, &fast, &slow], later), Some(slow));
+    }
+
+    #[test]
+    fn test_agent_versions_are_checked_for_compatibility() {
+        let peer = PeerId::random();
+        let mut stats = PeerStats::new();
+        assert_eq!(stats.info(&peer, Instant::now()).compatible, None);
+        stats.record_agent_version(peer, agent_version());
+        let info = stats.info(&peer, Instant::now());
+        assert_eq!(info.agent_version, Some(agent_version()));
+        assert_eq!(info.compatible, Some(true));
+
+        assert_eq!(compatible("rust-libp2p/0.56.0"), None);
+        assert_eq!(compatible("syndactyl/99.0.0"), Some(false));
+        let (major, minor) = (env!("CARGO_PKG_VERSION_MAJOR"), env!("CARGO_PKG_VERSION_MINOR"));
+        assert_eq!(compatible(&format!("syndactyl/{}.{}.99-beta", major, minor)), Some(true));
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use crate::core::models::EventType;
+
+/// Default number of queued serve requests allowed per peer
+pub const DEFAULT_MAX_QUEUED_PER_PEER: usize = 16;
+
+/// Default number of announcements waiting to be processed per peer
+/// Reconciliation picks up anything refused once the queue drains.
+pub const DEFAULT_MAX_INCOMING_PER_PEER: usize = 10_000;
+
+/// Files up to this size go ahead of bulk transfers (1 MiB)
+pub const SMALL_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Round-robin fair queue of pending work, keyed by peer
+/// Each peer gets its own bounded FIFO; `pop` takes one item from each peer
+/// in turn, so a peer that floods requests cannot starve the others.
+pub struct FairQueue<K, T> {
+    queues: HashMap<K, VecDeque<T>>,
+    /// Peers with pending work, in round-robin order
+    order: VecDeque<K>,
+    max_per_peer: usize,
+    len: usize,
+}
+
+impl<K: Eq + Hash + Clone, T> FairQueue<K, T> {
+    pub fn new(max_per_peer: usize) -> Self {
+        Self {
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            max_per_peer,
+            len: 0,
+        }
+    }
+
+    /// Queue an item for a peer
+    /// Returns the item back if the peer already has `max_per_peer` items outstanding
+    pub fn push(&mut self, peer: K, item: T) -> Result<(), T> {
+        let queue = self.queues.entry(peer.clone()).or_default();
+        if queue.len() >= self.max_per_peer {
+            return Err(item);
+        }
+        if queue.is_empty() {
+            self.order.push_back(peer);
+        }
+        queue.push_back(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Take the next item, rotating between peers
+    pub fn pop(&mut self) -> Option<(K, T)> {
+        let peer = self.order.pop_front()?;
+        let queue = self.queues.get_mut(&peer)?;
+        let item = queue.pop_front()?;
+        self.len -= 1;
+
+        if queue.is_empty() {
+            self.queues.remove(&peer);
+        } else {
+            self.order.push_back(peer.clone());
+        }
+        Some((peer, item))
+    }
+
+    /// Drop all queued work for a peer (e.g. on disconnect), returning how many items were removed
+    pub fn remove_peer(&mut self, peer: &K) -> usize {
+        let removed = self.queues.remove(peer).map(|q| q.len()).unwrap_or(0);
+        self.order.retain(|p| p != peer);
+        self.len -= removed;
+        removed
+    }
+
+    /// Keep only the peer's queued items matching the predicate, returning how many were removed
+    pub fn retain_for(&mut self, peer: &K, keep: impl FnMut(&T) -> bool) -> usize {
+        let Some(queue) = self.queues.get_mut(peer) else {
+            return 0;
+        };
+        let before = queue.len();
+        queue.retain(keep);
+        let removed = before - queue.len();
+        self.len -= removed;
+
+        if queue.is_empty() {
+            self.queues.remove(peer);
+            self.order.retain(|p| p != peer);
+        }
+        removed
+    }
+
+    /// Number of items queued for a peer
+    pub fn queued_for(&self, peer: &K) -> usize {
+        self.queues.get(peer).map(|q| q.len()).unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Scheduling class of incoming work, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Deletions, renames and empty files: nothing to transfer
+    Metadata,
+    /// Config files, notes and the like, up to `SMALL_FILE_BYTES`
+    Small,
+    /// Media and other bulk content
+    Large,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Metadata, Priority::Small, Priority::Large];
+
+    /// Class of an announcement; files of unknown size are assumed small
+    pub fn classify(event_type: EventType, size: Option<u64>) -> Self {
+        match size {
+            _ if !event_type.has_content() => Self::Metadata,
+            Some(0) => Self::Metadata,
+            Some(size) if size > SMALL_FILE_BYTES => Self::Large,
+            _ => Self::Small,
+        }
+    }
+}
+
+/// Pending work in priority classes, keyed so newer work replaces older
+/// `pop_where` takes the oldest ready item of the most urgent class, so a
+/// backlog of large files never holds up small ones behind it. Each source
+/// may have at most `max_per_source` items queued.
+pub struct PriorityQueue<K, S, T> {
+    classes: [VecDeque<(K, T)>; 3],
+    /// Class and source of each queued key
+    queued: HashMap<K, (Priority, S)>,
+    /// Number of items queued by each source
+    per_source: HashMap<S, usize>,
+    max_per_source: usize,
+}
+
+impl<K: Eq + Hash + Clone, S: Eq + Hash + Clone, T> PriorityQueue<K, S, T> {
+    pub fn new(max_per_source: usize) -> Self {
+        Self { classes: Default::default(), queued: HashMap::new(), per_source: HashMap::new(), max_per_source }
+    }
+
+    /// Queue an item, replacing one already queued under `key`
+    /// Returns the item back if `source` already has `max_per_source` other items queued.
+    pub fn push(&mut self, key: K, source: S, priority: Priority, item: T) -> Result<(), T> {
+        let replaces_own = self.queued.get(&key).is_some_and(|(_, queued_by)| *queued_by == source);
+        if !replaces_own && self.queued_by(&source) >= self.max_per_source {
+            return Err(item);
+        }
+        if let Some((previous, queued_by)) = self.queued.remove(&key) {
+            self.classes[previous as usize].retain(|(queued, _)| queued != &key);
+            self.release(&queued_by);
+        }
+        *self.per_source.entry(source.clone()).or_default() += 1;
+        self.queued.insert(key.clone(), (priority, source));
+        self.classes[priority as usize].push_back((key, item));
+        Ok(())
+    }
+
+    /// Take the most urgent item `ready` accepts; items it turns down keep their place
+    pub fn pop_where(&mut self, mut ready: impl FnMut(&T) -> bool) -> Option<(K, T)> {
+        for class in &mut self.classes {
+            if let Some(index) = class.iter().position(|(_, item)| ready(item)) {
+                let (key, item) = class.remove(index)?;
+                if let Some((_, source)) = self.queued.remove(&key) {
+                    self.release(&source);
+                }
+                return Some((key, item));
+            }
+        }
+        None
+    }
+
+    fn release(&mut self, source: &S) {
+        if let Some(count) = self.per_source.get_mut(source) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_source.remove(source);
+            }
+        }
+    }
+
+    /// Number of items queued by a source
+    pub fn queued_by(&self, source: &S) -> usize {
+        self.per_source.get(source).copied().unwrap_or(0)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.queued.keys()
+    }
+
+    /// Number of items queued in a class
+    pub fn queued_in(&self, priority: Priority) -> usize {
+        self.classes[priority as usize].len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_robin_between_peers() {
+        let mut queue = FairQueue::new(10);
+        // Peer "a" floods first, "b" and "c" arrive later
+        for i in 0..5 {
+            queue.push("a", i).unwrap();
+        }
+        queue.push("b", 100).unwrap();
+        queue.push("c", 200).unwrap();
+        queue.push("b", 101).unwrap();
+
+        let served: Vec<(&str, i32)> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            served,
+            vec![("a", 0), ("b", 100), ("c", 200), ("a", 1), ("b", 101), ("a", 2), ("a", 3), ("a", 4)]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_per_peer_bound() {
+        let mut queue = FairQueue::new(2);
+        queue.push("a", 1).unwrap();
+        queue.push("a", 2).unwrap();
+        assert_eq!(queue.push("a", 3), Err(3));
+        // Other peers are unaffected
+        queue.push("b", 1).unwrap();
+
+        assert_eq!(queue.remove_peer(&"a"), 2);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.pop(), Some(("b", 1)));
+    }
+
+    #[test]
+    fn test_small_files_overtake_bulk_transfers() {
+        let mut queue = PriorityQueue::new(10);
+        queue.push("video.mkv", "a", Priority::classify(EventType::Create, Some(4 * SMALL_FILE_BYTES)), "a").unwrap();
+        queue.push("notes.md", "a", Priority::classify(EventType::Modify, Some(2048)), "a").unwrap();
+        queue.push("empty", "b", Priority::classify(EventType::Create, Some(0)), "b").unwrap();
+        queue.push("config.toml", "b", Priority::classify(EventType::Modify, None), "b").unwrap();
+        // A newer announcement replaces the queued one, in its own class
+        queue.push("notes.md", "b", Priority::classify(EventType::Modify, Some(8 * SMALL_FILE_BYTES)), "b").unwrap();
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.queued_in(Priority::Large), 2);
+        assert_eq!((queue.queued_by(&"a"), queue.queued_by(&"b")), (1, 3));
+
+        // Items from a busy source wait without blocking the rest
+        assert_eq!(queue.pop_where(|source| *source != "b"), Some(("video.mkv", "a")));
+        let order: Vec<&str> = std::iter::from_fn(|| queue.pop_where(|_| true)).map(|(key, _)| key).collect();
+        assert_eq!(order, vec!["empty", "config.toml", "notes.md"]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.queued_by(&"b"), 0);
+    }
+
+    #[test]
+    fn test_one_source_cannot_fill_the_priority_queue() {
+        let mut queue = PriorityQueue::new(2);
+        queue.push("a.txt", "flood", Priority::Small, 1).unwrap();
+        queue.push("b.txt", "flood", Priority::Small, 2).unwrap();
+        assert_eq!(queue.push("c.txt", "flood", Priority::Small, 3), Err(3));
+        // Replacing its own item still works, and other sources have their own room
+        queue.push("a.txt", "flood", Priority::Large, 4).unwrap();
+        queue.push("c.txt", "other", Priority::Small, 5).unwrap();
+        assert_eq!(queue.len(), 3);
+
+        // Taking over another source's key frees a place for it
+        queue.push("b.txt", "other", Priority::Small, 6).unwrap();
+        assert_eq!((queue.queued_by(&"flood"), queue.queued_by(&"other")), (1, 2));
+        queue.push("d.txt", "flood", Priority::Small, 7).unwrap();
+        assert_eq!(queue.pop_where(|item| *item == 5), Some(("c.txt", 5)));
+        assert_eq!(queue.queued_by(&"other"), 1);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use crate::core::config::{NetworkConfig, ObserverConfig};
+use crate::core::models::Capabilities;
+use crate::network::bootstrap;
+use crate::network::capabilities;
+use crate::network::discovery;
+use crate::network::key_pins::KeyPins;
+
+/// Features a peer must list to stay connected in strict mode
+/// Without one we fall back to what older peers did, so a peer could otherwise
+/// pick the weaker behaviour by claiming to be older than it is.
+pub const REQUIRED_FEATURES: &[&str] = &[capabilities::TREE_NODE, capabilities::TREE_PAGES, capabilities::HEADS];
+
+/// Who may connect when `strict_security` is on
+/// Every connection is already Noise-encrypted; strict mode also insists that
+/// the peer's static key is one we know (pinned by an observer, a configured
+/// bootstrap peer or the rendezvous point), that it arrived over the transports
+/// we build, and that no observer can fall back to unauthenticated announcements
+/// or serving.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionPolicy {
+    known: HashSet<PeerId>,
+}
+
+impl ConnectionPolicy {
+    /// The policy `network` asks for, or None when strict mode is off
+    /// Fails if an observer would weaken it by going without a shared secret or pinned keys.
+    pub fn from_config(network: &NetworkConfig, observers: &[ObserverConfig], key_pins: &KeyPins) -> Result<Option<Self>, String> {
+        if network.strict_security != Some(true) {
+            return Ok(None);
+        }
+        for observer in observers {
+            if observer.shared_secret.is_none() {
+                return Err(format!("strict_security: observer {} needs a shared_secret", observer.name));
+            }
+            if !key_pins.is_pinned(&observer.name) {
+                return Err(format!("strict_security: observer {} needs pinned_keys", observer.name));
+            }
+        }
+        let mut known: HashSet<PeerId> = key_pins.peers().copied().collect();
+        for peer in &network.bootstrap_peers {
+            let (peer_id, _) = bootstrap::target(peer).map_err(|e| format!("strict_security: {}", e))?;
+            known.insert(peer_id);
+        }
+        // The rendezvous point introduces peers; nothing is synced with it
+        if network.rendezvous.as_ref().is_some_and(|rendezvous| rendezvous.address.is_some()) {
+            known.insert(discovery::rendezvous_point(network)?.0);
+        }
+        Ok(Some(Self { known }))
+    }
+
+    /// Why a connection to `peer` at `address` must be closed, if it must
+    pub fn check(&self, peer: &PeerId, address: &Multiaddr) -> Result<(), String> {
+        if !self.known.contains(peer) {
+            return Err("peer's key is not pinned by any observer nor a bootstrap peer".to_string());
+        }
+        let unexpected = address.iter().find(|protocol| !matches!(
+            protocol,
+            Protocol::Ip4(_) | Protocol::Ip6(_) | Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_)
+                | Protocol::Tcp(_) | Protocol::Ws(_) | Protocol::Wss(_) | Protocol::Tls | Protocol::P2p(_)
+        ));
+        match unexpected {
+            Some(protocol) => Err(format!("connection uses unexpected transport {}", protocol)),
+            None => Ok(()),
+        }
+    }
+
+    /// Why a peer with `theirs` must be disconnected, if it must
+    /// A peer that sent no document at all is passed `None` and refused too.
+    pub fn check_capabilities(&self, theirs: Option<&Capabilities>) -> Result<(), String> {
+        let Some(theirs) = theirs else {
+            return Err("peer predates capabilities documents".to_string());
+        };
+        if !theirs.hash_algorithms.iter().any(|algorithm| algorithm == capabilities::HASH_ALGORITHM) {
+            return Err(format!("peer doesn't support {} content hashes", capabilities::HASH_ALGORITHM));
+        }
+        let missing: Vec<&str> = REQUIRED_FEATURES.iter().copied()
+            .filter(|feature| !theirs.features.iter().any(|f| f == feature))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("peer lacks required features: {}", missing.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use crate::network::key_pins;
+
+    #[test]
+    fn test_strict_mode_needs_authenticated_observers_and_known_peers() {
+        let pinned = Keypair::generate_ed25519().public();
+        let stranger = PeerId::random();
+        let network: NetworkConfig = serde_json::from_value(serde_json::json!({
+            "listen_addr": "0.0.0.0", "port": "4001", "dht_mode": "server", "bootstrap_peers": [], "strict_security": true
+        })).unwrap();
+        let observer = |secret: Option<&str>| -> ObserverConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": "vault", "path": "/tmp", "shared_secret": secret, "pinned_keys": [key_pins::encode(&pinned)]
+            })).unwrap()
+        };
+        let observers = [observer(None)];
+        let pins = KeyPins::from_config(&observers).unwrap();
+        assert!(ConnectionPolicy::from_config(&network, &observers, &pins).unwrap_err().contains("shared_secret"));
+
+        let observers = [observer(Some("secret"))];
+        let pins = KeyPins::from_config(&observers).unwrap();
+        let policy = ConnectionPolicy::from_config(&network, &observers, &pins).unwrap().unwrap();
+        let tcp: Multiaddr = "/ip4/192.168.1.2/tcp/4001".parse().unwrap();
+        assert!(policy.check(&pinned.to_peer_id(), &tcp).is_ok());
+        assert!(policy.check(&stranger, &tcp).is_err());
+        let relayed: Multiaddr = "/ip4/192.168.1.2/tcp/4001/p2p-circuit".parse().unwrap();
+        assert!(policy.check(&pinned.to_peer_id(), &relayed).is_err());
+
+        let relaxed = NetworkConfig { strict_security: None, ..network };
+        assert!(ConnectionPolicy::from_config(&relaxed, &[observer(None)], &KeyPins::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_strict_mode_refuses_peers_below_the_required_features() {
+        let policy = ConnectionPolicy::default();
+        let current = capabilities::local(false);
+        assert!(policy.check_capabilities(Some(&current)).is_ok());
+
+        // Claiming to be older doesn't get a peer the older, weaker behaviour
+        assert!(policy.check_capabilities(None).is_err());
+        let downgraded = Capabilities { features: vec![capabilities::TREE_NODE.to_string()], ..current.clone() };
+        assert!(policy.check_capabilities(Some(&downgraded)).unwrap_err().contains(capabilities::HEADS));
+        let other_hash = Capabilities { hash_algorithms: vec!["md5".to_string()], ..current };
+        assert!(policy.check_capabilities(Some(&other_hash)).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::core::hash_pool;
+use crate::core::models::TransferErrorKind;
+
+/// Hash of a served version, worked out on the hash pool the first time it's asked for
+#[derive(Debug, Clone, PartialEq)]
+pub enum VersionHash {
+    Unknown,
+    Hashing,
+    Known(String),
+    /// The file couldn't be read to hash it
+    Unreadable,
+}
+
+/// A local file version being served, identified by size and modification time
+/// Nanosecond mtimes tell apart rewrites within one second that keep the size;
+/// pending-version tokens only carry seconds, as announced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServedVersion {
+    pub size: u64,
+    pub modified_time: u64,
+    pub modified_nanos: u128,
+    pub hash: VersionHash,
+    /// Why the secret guard refuses to serve this version, if it does
+    pub blocked: Option<String>,
+}
+
+/// Whether a request for a version can be answered now
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Served {
+    /// The version matches; it is this many bytes long
+    Ready(u64),
+    /// The file must be hashed first; the caller queues the hashing and holds the request
+    Hash,
+    /// Already being hashed; the request waits for it
+    Hashing,
+}
+
+impl ServedVersion {
+    pub fn new(size: u64, modified_nanos: u128, blocked: Option<String>) -> Self {
+        Self {
+            size,
+            modified_time: (modified_nanos / 1_000_000_000) as u64,
+            modified_nanos,
+            hash: VersionHash::Unknown,
+            blocked,
+        }
+    }
+
+    pub fn is(&self, size: u64, modified_nanos: u128) -> bool {
+        self.size == size && self.modified_nanos == modified_nanos
+    }
+
+    /// Check a request for `requested_hash` against this version
+    pub fn check(&mut self, requested_hash: &str) -> Result<Served, TransferErrorKind> {
+        // Peers are told it is gone, the same as for a file that was never there
+        if self.blocked.is_some() {
+            return Err(TransferErrorKind::NotFound);
+        }
+        // Fetched before it was hashed: the announced size and mtime identify the version
+        if let Some(pending) = hash_pool::parse_pending_version(requested_hash) {
+            if pending != (self.size, self.modified_time) {
+                return Err(TransferErrorKind::FileChanged { current_size: Some(self.size), current_hash: None });
+            }
+            return Ok(Served::Ready(self.size));
+        }
+        match &self.hash {
+            VersionHash::Unknown => {
+                self.hash = VersionHash::Hashing;
+                Ok(Served::Hash)
+            }
+            VersionHash::Hashing => Ok(Served::Hashing),
+            VersionHash::Unreadable => Err(TransferErrorKind::NotFound),
+            VersionHash::Known(hash) if hash != requested_hash => Err(TransferErrorKind::FileChanged {
+                current_size: Some(self.size),
+                current_hash: Some(hash.clone()),
+            }),
+            VersionHash::Known(_) => Ok(Served::Ready(self.size)),
+        }
+    }
+
+    /// Record the hash worked out for the version of `size` and `modified_nanos`
+    /// A result for a version replaced meanwhile is dropped.
+    pub fn hashed(&mut self, size: u64, modified_nanos: u128, hash: Option<String>) {
+        if self.is(size, modified_nanos) && self.hash == VersionHash::Hashing {
+            self.hash = hash.map_or(VersionHash::Unreadable, VersionHash::Known);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_served_versions_are_hashed_once_and_told_apart_within_a_second() {
+        let mtime = 1_700_000_000_123_456_789u128;
+        let mut version = ServedVersion::new(5, mtime, None);
+        assert_eq!(version.check("h1"), Ok(Served::Hash));
+        assert_eq!(version.check("h1"), Ok(Served::Hashing));
+
+        // A hash worked out for an earlier rewrite in the same second doesn't count
+        version.hashed(5, mtime - 1000, Some("h0".to_string()));
+        assert_eq!(version.check("h1"), Ok(Served::Hashing));
+        version.hashed(5, mtime, Some("h1".to_string()));
+        assert_eq!(version.check("h1"), Ok(Served::Ready(5)));
+        assert_eq!(version.check("h2"), Err(TransferErrorKind::FileChanged { current_size: Some(5), current_hash: Some("h1".to_string()) }));
+        assert!(!version.is(5, mtime + 1));
+
+        // Pending versions are named by size and whole seconds, without hashing
+        let mut unhashed = ServedVersion::new(5, mtime, None);
+        assert_eq!(unhashed.check(&hash_pool::pending_version(5, 1_700_000_000)), Ok(Served::Ready(5)));
+        assert!(unhashed.check(&hash_pool::pending_version(5, 1_700_000_001)).is_err());
+        assert_eq!(unhashed.hash, VersionHash::Unknown);
+
+        let mut unreadable = ServedVersion::new(5, mtime, None);
+        assert_eq!(unreadable.check("h1"), Ok(Served::Hash));
+        unreadable.hashed(5, mtime, None);
+        assert_eq!(unreadable.check("h1"), Err(TransferErrorKind::NotFound));
+
+        let mut blocked = ServedVersion::new(5, mtime, Some("name matches '*.pem'".to_string()));
+        assert_eq!(blocked.check("h1"), Err(TransferErrorKind::NotFound));
+    }
+}
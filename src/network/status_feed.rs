@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+use crate::control::{FileStatus, SyncState};
+
+/// Updates buffered per subscriber before it is told to resync
+const FEED_CAPACITY: usize = 1024;
+
+/// Per-file sync states, published to file manager extensions as they change
+/// Only files that are not in sync are tracked; everything else is Synced, or
+/// Unwatched if it doesn't exist locally.
+pub struct StatusFeed {
+    sender: broadcast::Sender<FileStatus>,
+    /// Current state of each file that is not in sync, by (observer, path)
+    states: HashMap<(String, String), SyncState>,
+}
+
+impl Default for StatusFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatusFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Self { sender, states: HashMap::new() }
+    }
+
+    /// Handle for the control socket to subscribe connections with
+    pub fn sender(&self) -> broadcast::Sender<FileStatus> {
+        self.sender.clone()
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Tracked state of a file, if it is not in sync
+    pub fn state(&self, observer: &str, path: &str) -> Option<SyncState> {
+        self.states.get(&(observer.to_string(), path.to_string())).copied()
+    }
+
+    /// Replace the tracked states, publishing every file whose state changed
+    /// `settled` gives the state of a file that is no longer tracked, and
+    /// `absolute` its local path.
+    pub fn update(
+        &mut self,
+        current: HashMap<(String, String), SyncState>,
+        settled: impl Fn(&str, &str) -> SyncState,
+        absolute: impl Fn(&str, &str) -> String,
+    ) {
+        let mut changes = Vec::new();
+        for (key, state) in &current {
+            if self.states.get(key) != Some(state) {
+                changes.push((key.clone(), *state));
+            }
+        }
+        for key in self.states.keys().filter(|key| !current.contains_key(*key)) {
+            changes.push((key.clone(), settled(&key.0, &key.1)));
+        }
+        self.states = current;
+        for ((observer, path), state) in changes {
+            // No subscribers is not an error; they query current states when they connect
+            let _ = self.sender.send(FileStatus { path: absolute(&observer, &path), observer, state });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_published_once_and_settle() {
+        let mut feed = StatusFeed::new();
+        let mut rx = feed.sender().subscribe();
+        let absolute = |observer: &str, path: &str| format!("/{}/{}", observer, path);
+        let key = |path: &str| ("docs".to_string(), path.to_string());
+
+        feed.update(HashMap::from([(key("a.txt"), SyncState::Pending)]), |_, _| SyncState::Synced, absolute);
+        feed.update(HashMap::from([(key("a.txt"), SyncState::Syncing)]), |_, _| SyncState::Synced, absolute);
+        feed.update(HashMap::from([(key("a.txt"), SyncState::Syncing)]), |_, _| SyncState::Synced, absolute);
+        assert_eq!(feed.state("docs", "a.txt"), Some(SyncState::Syncing));
+        feed.update(HashMap::new(), |_, _| SyncState::Synced, absolute);
+        assert_eq!(feed.state("docs", "a.txt"), None);
+
+        let published: Vec<FileStatus> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let states: Vec<SyncState> = published.iter().map(|status| status.state).collect();
+        assert_eq!(states, [SyncState::Pending, SyncState::Syncing, SyncState::Synced]);
+        assert_eq!(published[0].path, "/docs/a.txt");
+    }
+}
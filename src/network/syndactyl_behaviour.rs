@@ -1,16 +1,23 @@
 use libp2p_swarm_derive::NetworkBehaviour;
 use libp2p::{
     gossipsub::{Behaviour as Gossipsub, Event as GossipsubEvent},
+    identify::{Behaviour as Identify, Event as IdentifyEvent},
     kad::{Behaviour as Kademlia, store::MemoryStore, Event as KademliaEvent},
+    mdns::{tokio::Behaviour as Mdns, Event as MdnsEvent},
+    ping::{Behaviour as Ping, Event as PingEvent},
     request_response::{
         Event as RequestResponseEvent,
         cbor::Behaviour as CborBehaviour,
     },
+    swarm::behaviour::toggle::Toggle,
+    rendezvous,
+    stream::Behaviour as Stream,
+    upnp::{tokio::Behaviour as Upnp, Event as UpnpEvent},
 };
-use crate::core::models::{SyndactylRequest, FileTransferResponse};
+use crate::core::models::{SyndactylRequest, SyndactylResponse};
 
 /// Type alias for our file transfer request-response behaviour
-pub type FileTransferBehaviour = CborBehaviour<SyndactylRequest, FileTransferResponse>;
+pub type FileTransferBehaviour = CborBehaviour<SyndactylRequest, SyndactylResponse>;
 
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "SyndactylEvent")]
@@ -18,12 +25,34 @@ pub struct SyndactylBehaviour {
     pub gossipsub: Gossipsub,
     pub kademlia: Kademlia<MemoryStore>,
     pub file_transfer: FileTransferBehaviour,
+    /// Exchanges listen and observed addresses with connected peers
+    pub identify: Identify,
+    /// Port mapping on the local router, when enabled
+    pub upnp: Toggle<Upnp>,
+    /// Measures round-trip time to connected peers
+    pub ping: Ping,
+    /// Finds peers on the local network, with the "mdns" discovery backend
+    pub mdns: Toggle<Mdns>,
+    /// Registers with and queries a rendezvous point, with the "rendezvous" discovery backend
+    pub rendezvous: Toggle<rendezvous::client::Behaviour>,
+    /// Serves as a rendezvous point for other peers, when configured
+    pub rendezvous_point: Toggle<rendezvous::server::Behaviour>,
+    /// Raw streams for bulk chunk transfer; opened and accepted through its Control
+    pub stream: Stream,
 }
 
 pub enum SyndactylEvent {
     Gossipsub(GossipsubEvent),
     Kademlia(KademliaEvent),
-    FileTransfer(RequestResponseEvent<SyndactylRequest, FileTransferResponse>),
+    FileTransfer(RequestResponseEvent<SyndactylRequest, SyndactylResponse>),
+    Identify(Box<IdentifyEvent>),
+    Upnp(UpnpEvent),
+    Ping(PingEvent),
+    Mdns(MdnsEvent),
+    Rendezvous(rendezvous::client::Event),
+    RendezvousPoint(rendezvous::server::Event),
+    /// The stream behaviour emits no events; streams arrive through its Control
+    Stream,
 }
 
 impl From<GossipsubEvent> for SyndactylEvent {
@@ -38,8 +67,50 @@ impl From<KademliaEvent> for SyndactylEvent {
     }
 }
 
-impl From<RequestResponseEvent<SyndactylRequest, FileTransferResponse>> for SyndactylEvent {
-    fn from(event: RequestResponseEvent<SyndactylRequest, FileTransferResponse>) -> Self {
+impl From<RequestResponseEvent<SyndactylRequest, SyndactylResponse>> for SyndactylEvent {
+    fn from(event: RequestResponseEvent<SyndactylRequest, SyndactylResponse>) -> Self {
         SyndactylEvent::FileTransfer(event)
     }
 }
+
+impl From<IdentifyEvent> for SyndactylEvent {
+    fn from(event: IdentifyEvent) -> Self {
+        SyndactylEvent::Identify(Box::new(event))
+    }
+}
+
+impl From<UpnpEvent> for SyndactylEvent {
+    fn from(event: UpnpEvent) -> Self {
+        SyndactylEvent::Upnp(event)
+    }
+}
+
+impl From<PingEvent> for SyndactylEvent {
+    fn from(event: PingEvent) -> Self {
+        SyndactylEvent::Ping(event)
+    }
+}
+
+impl From<MdnsEvent> for SyndactylEvent {
+    fn from(event: MdnsEvent) -> Self {
+        SyndactylEvent::Mdns(event)
+    }
+}
+
+impl From<rendezvous::client::Event> for SyndactylEvent {
+    fn from(event: rendezvous::client::Event) -> Self {
+        SyndactylEvent::Rendezvous(event)
+    }
+}
+
+impl From<rendezvous::server::Event> for SyndactylEvent {
+    fn from(event: rendezvous::server::Event) -> Self {
+        SyndactylEvent::RendezvousPoint(event)
+    }
+}
+
+impl From<()> for SyndactylEvent {
+    fn from(_: ()) -> Self {
+        SyndactylEvent::Stream
+    }
+}
@@ -1,56 +1,91 @@
 use crate::core::config::NetworkConfig;
+use crate::core::sync_groups;
+use crate::network::key_pins;
 use libp2p::{
-    core::upgrade,
     gossipsub::{
         Behaviour as Gossipsub,
-        Config as GossipsubConfig,
         Event as GossipsubEvent,
         MessageAuthenticity,
         IdentTopic as Topic,
     },
+    identify,
     identity,
-    swarm::{Swarm, Config as SwarmConfig},
+    swarm::{Swarm, Config as SwarmConfig, behaviour::toggle::Toggle},
     kad::{
         Behaviour as Kademlia,
         Config as KademliaConfig,
         store::MemoryStore,
     },
-    tcp::tokio::Transport as TokioTcpTransport,
-    yamux::Config as YamuxConfig,
-    PeerId, Transport,
-    noise::Config as NoiseConfig,
+    PeerId,
 };
 use std::error::Error;
 use futures::StreamExt;
 use tokio::sync::mpsc::Sender;
-use std::str::FromStr;
 use crate::network::syndactyl_behaviour::{SyndactylBehaviour, SyndactylEvent};
-use tracing::{info, warn, error};
-use crate::core::models::{FileEventMessage, FileTransferRequest, FileTransferResponse, FileChunkRequest, SyndactylRequest};
-use serde_json;
+use crate::network::wire;
+use crate::network::transport;
+use crate::network::discovery;
+use crate::network::bootstrap;
+use crate::network::gossip;
+use crate::network::peer_stats;
+use tracing::{debug, info, warn, error};
+use crate::core::models::{Capabilities, FileTransferRequest, FileTransferResponse, FileChunkRequest, CancelTransferRequest, Heartbeat, RangeReadRequest, RangeReadResponse, TransferError, TreeNodeRequest, TreeNodeResponse, SyndactylRequest, SyndactylResponse};
+use libp2p::request_response::OutboundRequestId;
+
+/// Gossipsub topic carrying heartbeats, kept apart from file announcements
+pub const HEARTBEAT_TOPIC: &str = "syndactyl-heartbeat";
+
+/// Request-response protocol for transfers
+/// 2.0.0 wraps responses in `SyndactylResponse` and adds CancelTransfer, which
+/// 1.0.0 peers can't decode, so the two don't negotiate with each other.
+pub const FILE_TRANSFER_PROTOCOL: &str = "/syndactyl/file-transfer/2.0.0";
 
 /// Events emitted by the SyndactylP2P node.
 pub enum SyndactylP2PEvent {
     /// Received a Gossipsub message.
     GossipsubMessage {
         source: PeerId,
+        /// Peer that published the message, when it was signed
+        origin: Option<PeerId>,
         data: Vec<u8>,
     },
     /// Received a Kademlia event.
     KademliaEvent(String),
+    /// A provider lookup found peers holding the content under `key`.
+    ProvidersFound {
+        key: String,
+        providers: Vec<PeerId>,
+    },
     /// Node is listening on a new address.
     NewListenAddr(String),
     /// Received a file transfer request from a peer.
     FileTransferRequest {
         peer: PeerId,
         request: FileTransferRequest,
-        channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
     },
     /// Received a file chunk request from a peer.
     FileChunkRequest {
         peer: PeerId,
         request: FileChunkRequest,
-        channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    },
+    /// Received a range read request from a peer.
+    RangeReadRequest {
+        peer: PeerId,
+        request: RangeReadRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    },
+    /// Received a transfer cancellation from a peer.
+    CancelTransfer {
+        peer: PeerId,
+        request: CancelTransferRequest,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+    },
+    /// A peer could not serve one of our requests.
+    TransferError {
+        peer: PeerId,
+        error: TransferError,
     },
     /// Received a file transfer response from a peer.
     FileTransferResponse {
@@ -63,12 +98,18 @@ pub enum SyndactylP2PEvent {
 impl std::fmt::Debug for SyndactylP2PEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::GossipsubMessage { source, data } => f
+            Self::GossipsubMessage { source, origin, data } => f
                 .debug_struct("GossipsubMessage")
                 .field("source", source)
+                .field("origin", origin)
                 .field("data_len", &data.len())
                 .finish(),
             Self::KademliaEvent(e) => f.debug_tuple("KademliaEvent").field(e).finish(),
+            Self::ProvidersFound { key, providers } => f
+                .debug_struct("ProvidersFound")
+                .field("key", key)
+                .field("providers", providers)
+                .finish(),
             Self::NewListenAddr(addr) => f.debug_tuple("NewListenAddr").field(addr).finish(),
             Self::FileTransferRequest { peer, request, .. } => f
                 .debug_struct("FileTransferRequest")
@@ -85,6 +126,21 @@ impl std::fmt::Debug for SyndactylP2PEvent {
                 .field("peer", peer)
                 .field("request", request)
                 .finish(),
+            Self::TransferError { peer, error } => f
+                .debug_struct("TransferError")
+                .field("peer", peer)
+                .field("error", error)
+                .finish(),
+            Self::RangeReadRequest { peer, request, .. } => f
+                .debug_struct("RangeReadRequest")
+                .field("peer", peer)
+                .field("request", request)
+                .finish(),
+            Self::CancelTransfer { peer, request, .. } => f
+                .debug_struct("CancelTransfer")
+                .field("peer", peer)
+                .field("request", request)
+                .finish(),
         }
     }
 }
@@ -99,19 +155,22 @@ pub struct SyndactylP2P {
 
 impl SyndactylP2P {
     /// Create a new SyndactylP2P node with the given config and event sender.
-    pub async fn new(network_config: NetworkConfig, event_sender: Sender<SyndactylP2PEvent>) -> Result<Self, Box<dyn Error>> {
+    /// The keypair is kept at `keypair_path`, or in the user's config directory if None.
+    pub async fn new(network_config: NetworkConfig, keypair_path: Option<std::path::PathBuf>, event_sender: Sender<SyndactylP2PEvent>) -> Result<Self, Box<dyn Error>> {
         use std::fs;
 
         // Try to load keypair from disk, or generate and save if not present
-        let config_dir = std::env::var("XDG_CONFIG_HOME")
-            .map(std::path::PathBuf::from)
-            .unwrap_or_else(|_| {
-                let home = std::env::var("HOME").expect("HOME not set");
-                std::path::PathBuf::from(home).join(".config")
-            });
-        let syndactyl_dir = config_dir.join("syndactyl");
-        let keypair_path = syndactyl_dir.join("syndactyl_keypair.key");
-        if !syndactyl_dir.exists() {
+        let keypair_path = keypair_path.unwrap_or_else(|| {
+            let config_dir = std::env::var("XDG_CONFIG_HOME")
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(|_| {
+                    let home = std::env::var("HOME").expect("HOME not set");
+                    std::path::PathBuf::from(home).join(".config")
+                });
+            config_dir.join("syndactyl").join("syndactyl_keypair.key")
+        });
+        let syndactyl_dir = keypair_path.parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+        if !syndactyl_dir.as_os_str().is_empty() && !syndactyl_dir.exists() {
             std::fs::create_dir_all(&syndactyl_dir).map_err(|e| {
                 eprintln!("[syndactyl][error] Failed to create config dir: {}", e);
                 e
@@ -140,39 +199,58 @@ impl SyndactylP2P {
         };
         let peer_id = PeerId::from(id_keys.public());
         info!(peer_id = %peer_id, "[syndactyl] Local PeerId");
+        info!(public_key = %key_pins::encode(&id_keys.public()), "[syndactyl] Public key, for peers' pinned_keys");
         info!(key_path = %keypair_path.display(), "[syndactyl] Your persistent key is stored at");
 
-        // Set up Noise config from identity keypair
-        let noise_config = NoiseConfig::new(&id_keys).unwrap();
-
-        // Set up an encrypted TCP transport using Noise and Yamux
-        let transport = TokioTcpTransport::default()
-            .upgrade(upgrade::Version::V1)
-            .authenticate(noise_config)
-            .multiplex(YamuxConfig::default())
-            .boxed();
+        // Set up an encrypted transport using Noise and Yamux
+        let transport = transport::build(&network_config, &id_keys)?;
 
         // Create a Gossipsub topic
-        let topic = Topic::new("syndactyl-gossip");
+        let topic = Topic::new(sync_groups::DEFAULT_TOPIC);
+
+        // Set up Identify, so peers learn each other's listen and observed addresses and versions
+        let identify = identify::Behaviour::new(identify::Config::new(
+            "/syndactyl/id/1.0.0".to_string(),
+            id_keys.public(),
+        ).with_agent_version(peer_stats::agent_version()));
+
+        // Enable the behaviours of the selected discovery backends
+        let backends = discovery::selected(&network_config)?;
+        let mdns = if backends.contains(&discovery::MDNS) {
+            Some(libp2p::mdns::tokio::Behaviour::new(libp2p::mdns::Config::default(), peer_id)?)
+        } else {
+            None
+        };
+        let rendezvous = backends.contains(&discovery::RENDEZVOUS)
+            .then(|| libp2p::rendezvous::client::Behaviour::new(id_keys.clone()));
+        let serve_rendezvous = network_config.rendezvous.as_ref().and_then(|rendezvous| rendezvous.serve) == Some(true);
+        if serve_rendezvous {
+            info!("[syndactyl][rendezvous] Serving as a rendezvous point");
+        }
+        let rendezvous_point = serve_rendezvous
+            .then(|| libp2p::rendezvous::server::Behaviour::new(libp2p::rendezvous::server::Config::default()));
 
         // Set up Gossipsub
-        let gossipsub_config = GossipsubConfig::default();
+        let gossipsub_config = gossip::config(network_config.gossipsub.as_ref())?;
         let mut gossipsub = Gossipsub::new(MessageAuthenticity::Signed(id_keys), gossipsub_config)?;
         gossipsub.subscribe(&topic)?;
+        gossipsub.subscribe(&Topic::new(HEARTBEAT_TOPIC))?;
 
         // Set up Kademlia
         let kad_config = KademliaConfig::default();
         let store = MemoryStore::new(peer_id.clone());
         let mut kademlia = Kademlia::with_config(peer_id.clone(), store, kad_config);
+        let outbound_only = network_config.outbound_only == Some(true);
+        if outbound_only {
+            // Nobody can dial us, so don't offer to serve DHT queries
+            kademlia.set_mode(Some(libp2p::kad::Mode::Client));
+        }
 
         // Add bootstrap peers
-        for peer in &network_config.bootstrap_peers {
-            let addr = format!("/ip4/{}/tcp/{}/p2p/{}", peer.ip, peer.port, peer.peer_id);
-            if let Ok(multiaddr) = addr.parse::<libp2p::Multiaddr>() {
-                if let Ok(peer_id) = PeerId::from_str(&peer.peer_id) {
-                    kademlia.add_address(&peer_id, multiaddr.clone());
-                    info!(peer_id = %peer_id, addr = %multiaddr, "Added bootstrap peer");
-                }
+        for peer in network_config.bootstrap_peers.iter().filter(|peer| bootstrap::is_configured(peer)) {
+            if let Ok((peer_id, multiaddr)) = bootstrap::target(peer) {
+                kademlia.add_address(&peer_id, multiaddr.clone());
+                info!(peer_id = %peer_id, addr = %multiaddr, "Added bootstrap peer");
             }
         }
 
@@ -180,8 +258,8 @@ impl SyndactylP2P {
         use libp2p::request_response::{ProtocolSupport, cbor};
         use libp2p::StreamProtocol;
         
-        let file_transfer_protocol = StreamProtocol::new("/syndactyl/file-transfer/1.0.0");
-        let file_transfer = cbor::Behaviour::<SyndactylRequest, FileTransferResponse>::new(
+        let file_transfer_protocol = StreamProtocol::new(FILE_TRANSFER_PROTOCOL);
+        let file_transfer = cbor::Behaviour::<SyndactylRequest, SyndactylResponse>::new(
             [(file_transfer_protocol, ProtocolSupport::Full)],
             libp2p::request_response::Config::default(),
         );
@@ -191,35 +269,47 @@ impl SyndactylP2P {
             gossipsub,
             kademlia,
             file_transfer,
+            identify,
+            upnp: Toggle::from((network_config.upnp == Some(true)).then(libp2p::upnp::tokio::Behaviour::default)),
+            ping: libp2p::ping::Behaviour::default(),
+            mdns: Toggle::from(mdns),
+            rendezvous: Toggle::from(rendezvous),
+            rendezvous_point: Toggle::from(rendezvous_point),
+            stream: libp2p::stream::Behaviour::new(),
         };
 
         // Create a Swarm to manage peers and events
         let mut swarm = Swarm::new(transport, behaviour, peer_id, SwarmConfig::with_tokio_executor());
 
+        if outbound_only {
+            info!("[syndactyl] Outbound-only mode, not listening for connections");
+            return Ok(Self { peer_id, swarm, event_sender });
+        }
+
         // Listen on the address and port specified in network_config
         let listen_addr = format!(
             "/ip4/{}/tcp/{}",
             network_config.listen_addr, network_config.port
         );
-        let listen_addr = listen_addr.parse()?;
-        swarm.listen_on(listen_addr)?;
-
-        // Dial bootstrap peers to establish connections
-        for peer in &network_config.bootstrap_peers {
-            // Skip empty peer configurations
-            if peer.ip.is_empty() || peer.peer_id.is_empty() {
-                continue;
-            }
-            
-            let addr = format!("/ip4/{}/tcp/{}/p2p/{}", peer.ip, peer.port, peer.peer_id);
-            if let Ok(multiaddr) = addr.parse::<libp2p::Multiaddr>() {
-                match swarm.dial(multiaddr.clone()) {
-                    Ok(_) => info!(addr = %multiaddr, "Dialing bootstrap peer"),
-                    Err(e) => error!(addr = %multiaddr, error = ?e, "Failed to dial bootstrap peer"),
-                }
+        let listen_addr: libp2p::Multiaddr = listen_addr.parse()?;
+        if let Err(e) = swarm.listen_on(listen_addr.clone()) {
+            if network_config.port == "0" {
+                return Err(e.into());
             }
+            // Peers that connect to us learn the new port through identify
+            warn!(addr = %listen_addr, error = %e, "[syndactyl] Configured port unavailable, letting the OS pick one");
+            let fallback = format!("/ip4/{}/tcp/0", network_config.listen_addr).parse()?;
+            swarm.listen_on(fallback)?;
+        }
+
+        if let Some(port) = network_config.websocket.as_ref().and_then(|ws| ws.listen_port.as_ref()) {
+            let ws_addr: libp2p::Multiaddr = format!("/ip4/{}/tcp/{}/wss", network_config.listen_addr, port).parse()?;
+            swarm.listen_on(ws_addr.clone())?;
+            info!(addr = %ws_addr, "[syndactyl] Accepting WebSocket+TLS connections");
         }
 
+        // Bootstrap peers are dialed (and redialed) by the network manager
+
         Ok(Self { peer_id, swarm, event_sender })
     }
 
@@ -230,8 +320,18 @@ impl SyndactylP2P {
 
     /// Publish a message to the default Gossipsub topic.
     pub fn publish_gossipsub(&mut self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
-        let topic = Topic::new("syndactyl-gossip");
-        self.swarm.behaviour_mut().gossipsub.publish(topic, data)?;
+        self.publish_to_topic(sync_groups::DEFAULT_TOPIC, data)
+    }
+
+    /// Publish a message to a Gossipsub topic, such as an observer's own.
+    pub fn publish_to_topic(&mut self, topic_name: &str, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        self.swarm.behaviour_mut().gossipsub.publish(Topic::new(topic_name), data)?;
+        Ok(())
+    }
+
+    /// Publish a heartbeat to the heartbeat topic.
+    pub fn publish_heartbeat(&mut self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        self.swarm.behaviour_mut().gossipsub.publish(Topic::new(HEARTBEAT_TOPIC), data)?;
         Ok(())
     }
 
@@ -275,6 +375,26 @@ impl SyndactylP2P {
         self.swarm.behaviour_mut().kademlia.get_record(key);
     }
 
+    /// Advertise in the Kademlia DHT that we hold the content under `key`.
+    pub fn start_providing(&mut self, key: &str) {
+        use libp2p::kad::RecordKey;
+        if let Err(e) = self.swarm.behaviour_mut().kademlia.start_providing(RecordKey::new(&key)) {
+            error!(%e, "[syndactyl][error] Failed to advertise provider record");
+        }
+    }
+
+    /// Stop advertising the content under `key`.
+    pub fn stop_providing(&mut self, key: &str) {
+        use libp2p::kad::RecordKey;
+        self.swarm.behaviour_mut().kademlia.stop_providing(&RecordKey::new(&key));
+    }
+
+    /// Look up peers holding the content under `key`; answered with `ProvidersFound` events.
+    pub fn get_providers(&mut self, key: &str) {
+        use libp2p::kad::RecordKey;
+        self.swarm.behaviour_mut().kademlia.get_providers(RecordKey::new(&key));
+    }
+
     /// Request a file from a peer
     pub fn request_file(&mut self, peer: PeerId, request: FileTransferRequest) {
         let syndactyl_request = SyndactylRequest::FileTransfer(request.clone());
@@ -288,11 +408,11 @@ impl SyndactylP2P {
         );
     }
 
-    /// Request a specific chunk from a peer
-    pub fn request_file_chunk(&mut self, peer: PeerId, chunk_request: FileChunkRequest) {
+    /// Request a specific chunk from a peer; the response is matched by the returned id
+    pub fn request_file_chunk(&mut self, peer: PeerId, chunk_request: FileChunkRequest) -> OutboundRequestId {
         let syndactyl_request = SyndactylRequest::FileChunk(chunk_request.clone());
         let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, syndactyl_request);
-        info!(
+        debug!(
             peer = %peer,
             observer = %chunk_request.observer,
             path = %chunk_request.path,
@@ -300,18 +420,154 @@ impl SyndactylP2P {
             request_id = ?request_id,
             "[syndactyl][file-transfer] Requesting file chunk"
         );
+        request_id
+    }
+
+    /// Read a byte range of a peer's file; the response is matched by the returned id
+    pub fn request_range(&mut self, peer: PeerId, request: RangeReadRequest) -> OutboundRequestId {
+        let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, SyndactylRequest::RangeRead(request.clone()));
+        debug!(
+            peer = %peer,
+            observer = %request.observer,
+            path = %request.path,
+            offset = request.offset,
+            length = request.length,
+            request_id = ?request_id,
+            "[syndactyl][file-transfer] Requesting range"
+        );
+        request_id
     }
 
+    /// Send the bytes for a range read
+    pub fn send_range_response(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        response: RangeReadResponse,
+    ) {
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::Range(response.clone())).is_err() {
+            error!(
+                observer = %response.observer,
+                path = %response.path,
+                "[syndactyl][file-transfer] Failed to send range response"
+            );
+        }
+    }
+
+    /// Ask a peer for one directory of an observer's Merkle tree
+    pub fn request_tree_node(&mut self, peer: PeerId, request: TreeNodeRequest) -> OutboundRequestId {
+        let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, SyndactylRequest::TreeNode(request.clone()));
+        debug!(
+            peer = %peer,
+            observer = %request.observer,
+            dir = %request.dir,
+            request_id = ?request_id,
+            "[syndactyl][file-transfer] Requesting tree node"
+        );
+        request_id
+    }
+
+    /// Send a directory listing of an observer's Merkle tree
+    pub fn send_tree_node_response(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        response: TreeNodeResponse,
+    ) {
+        let (observer, dir) = (response.observer.clone(), response.dir.clone());
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::TreeNode(response)).is_err() {
+            error!(observer = %observer, dir = %dir, "[syndactyl][file-transfer] Failed to send tree node");
+        }
+    }
+
+    /// Send our capabilities to a peer, which answers with its own
+    pub fn request_capabilities(&mut self, peer: PeerId, capabilities: Capabilities) -> OutboundRequestId {
+        let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, SyndactylRequest::Capabilities(capabilities));
+        debug!(peer = %peer, request_id = ?request_id, "[syndactyl][file-transfer] Exchanging capabilities");
+        request_id
+    }
+
+    /// Answer a peer's capabilities with ours
+    pub fn send_capabilities_response(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        capabilities: Capabilities,
+    ) {
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::Capabilities(capabilities)).is_err() {
+            warn!("[syndactyl][file-transfer] Failed to send capabilities");
+        }
+    }
+
+    /// Send a newly connected peer our tree roots, asking for theirs
+    pub fn request_heads(&mut self, peer: PeerId, heads: Heartbeat) -> OutboundRequestId {
+        let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, SyndactylRequest::Heads(heads));
+        debug!(peer = %peer, request_id = ?request_id, "[syndactyl][file-transfer] Exchanging heads");
+        request_id
+    }
+
+    /// Answer a peer's heads with ours
+    pub fn send_heads_response(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        heads: Heartbeat,
+    ) {
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::Heads(heads)).is_err() {
+            warn!("[syndactyl][file-transfer] Failed to send heads");
+        }
+    }
+
+    /// Ask a peer to abort an in-flight transfer
+    pub fn request_cancel_transfer(&mut self, peer: PeerId, cancel: CancelTransferRequest) {
+        let request_id = self.swarm.behaviour_mut().file_transfer.send_request(&peer, SyndactylRequest::CancelTransfer(cancel.clone()));
+        info!(
+            peer = %peer,
+            observer = %cancel.observer,
+            path = %cancel.path,
+            reason = %cancel.reason,
+            request_id = ?request_id,
+            "[syndactyl][file-transfer] Cancelling transfer"
+        );
+    }
+
+    /// Acknowledge a cancellation request
+    pub fn send_cancel_ack(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        cancel: &CancelTransferRequest,
+    ) {
+        let ack = SyndactylResponse::CancelAck {
+            observer: cancel.observer.clone(),
+            path: cancel.path.clone(),
+        };
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, ack).is_err() {
+            warn!(observer = %cancel.observer, path = %cancel.path, "[syndactyl][file-transfer] Failed to acknowledge cancellation");
+        }
+    }
+
+    /// Tell a peer its request could not be served
+    pub fn send_transfer_error(
+        &mut self,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
+        error: TransferError,
+    ) {
+        warn!(
+            observer = %error.observer,
+            path = %error.path,
+            kind = ?error.kind,
+            "[syndactyl][file-transfer] Refusing request"
+        );
+        if self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::Error(error)).is_err() {
+            error!("[syndactyl][file-transfer] Failed to send error response");
+        }
+    }
 
     /// Send a file response to a peer
     pub fn send_file_response(
         &mut self,
-        channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
         response: FileTransferResponse,
     ) {
-        let result = self.swarm.behaviour_mut().file_transfer.send_response(channel, response.clone());
+        let result = self.swarm.behaviour_mut().file_transfer.send_response(channel, SyndactylResponse::Chunk(response.clone()));
         if result.is_ok() {
-            info!(
+            debug!(
                 observer = %response.observer,
                 path = %response.path,
                 offset = response.offset,
@@ -334,7 +590,7 @@ impl SyndactylP2P {
         &mut self,
         _peer: PeerId,
         _request: FileChunkRequest,
-        _channel: libp2p::request_response::ResponseChannel<FileTransferResponse>,
+        _channel: libp2p::request_response::ResponseChannel<SyndactylResponse>,
     ) {
         // TODO: Generate the requested chunk from the file and respond
         // Use request.observer, request.path, request.offset, request.hash
@@ -347,21 +603,38 @@ impl SyndactylP2P {
         loop {
             match self.swarm.select_next_some().await {
                 SwarmEvent::Behaviour(SyndactylEvent::Gossipsub(GossipsubEvent::Message { propagation_source, message_id: _, message })) => {
+                    // Heartbeats are handled by the network manager
+                    if message.topic == Topic::new(HEARTBEAT_TOPIC).hash() {
+                        continue;
+                    }
                     // Try to deserialize as FileEventMessage
-                    match serde_json::from_slice::<FileEventMessage>(&message.data) {
+                    match wire::decode_file_event(&message.data) {
                         Ok(file_event) => {
                             info!(peer = %propagation_source, event = ?file_event, "[syndactyl][gossipsub] Received FileEventMessage");
                             // Here you can add logic to process/apply the event
                         },
                         Err(e) => {
-                            warn!(peer = %propagation_source, error = ?e, raw = %String::from_utf8_lossy(&message.data), "[syndactyl][gossipsub] Failed to parse FileEventMessage");
+                            warn!(peer = %propagation_source, error = %e, size = message.data.len(), "[syndactyl][gossipsub] Rejected FileEventMessage");
+                            continue;
                         }
                     }
                     let _ = self.event_sender.send(SyndactylP2PEvent::GossipsubMessage {
                         source: propagation_source,
+                        origin: message.source,
                         data: message.data,
                     }).await;
                 }
+                SwarmEvent::Behaviour(SyndactylEvent::Kademlia(libp2p::kad::Event::OutboundQueryProgressed {
+                    result: libp2p::kad::QueryResult::GetProviders(Ok(libp2p::kad::GetProvidersOk::FoundProviders { key, providers })),
+                    ..
+                })) => {
+                    let key = String::from_utf8_lossy(&key.to_vec()).into_owned();
+                    debug!(key = %key, count = providers.len(), "[syndactyl][kademlia] Found providers");
+                    let _ = self.event_sender.send(SyndactylP2PEvent::ProvidersFound {
+                        key,
+                        providers: providers.into_iter().collect(),
+                    }).await;
+                }
                 SwarmEvent::Behaviour(SyndactylEvent::Kademlia(event)) => {
                     info!(event = ?event, "[syndactyl][kademlia] Event");
                     let _ = self.event_sender.send(SyndactylP2PEvent::KademliaEvent(format!("{:?}", event))).await;
@@ -371,6 +644,15 @@ impl SyndactylP2P {
                     match event {
                         RREvent::Message { peer, message, connection_id: _ } => {
                             use libp2p::request_response::Message;
+                            // Payloads come from untrusted peers; bound every field before acting on it
+                            let valid = match &message {
+                                Message::Request { request, .. } => wire::validate_request(request),
+                                Message::Response { response, .. } => wire::validate_response(response),
+                            };
+                            if let Err(e) = valid {
+                                warn!(peer = %peer, error = %e, "[syndactyl][file-transfer] Rejected invalid message");
+                                continue;
+                            }
                             // Handle SyndactylRequest (FileTransfer or FileChunk)
                             match message {
                                 Message::Request { request, channel, .. } => {
@@ -403,9 +685,68 @@ impl SyndactylP2P {
                                                 channel,
                                             }).await;
                                         }
+                                        SyndactylRequest::RangeRead(range_request) => {
+                                            debug!(
+                                                peer = %peer,
+                                                observer = %range_request.observer,
+                                                path = %range_request.path,
+                                                offset = range_request.offset,
+                                                "[syndactyl][file-transfer] Received range read request"
+                                            );
+                                            let _ = self.event_sender.send(SyndactylP2PEvent::RangeReadRequest {
+                                                peer,
+                                                request: range_request,
+                                                channel,
+                                            }).await;
+                                        }
+                                        SyndactylRequest::TreeNode(tree_request) => {
+                                            // Trees are served by the network manager
+                                            debug!(peer = %peer, observer = %tree_request.observer, dir = %tree_request.dir, "[syndactyl][file-transfer] Ignoring tree node request");
+                                        }
+                                        SyndactylRequest::Capabilities(_) => {
+                                            // Capabilities are exchanged by the network manager
+                                            debug!(peer = %peer, "[syndactyl][file-transfer] Ignoring capabilities request");
+                                        }
+                                        SyndactylRequest::Heads(_) => {
+                                            // Heads are exchanged by the network manager
+                                            debug!(peer = %peer, "[syndactyl][file-transfer] Ignoring heads request");
+                                        }
+                                        SyndactylRequest::CancelTransfer(cancel) => {
+                                            info!(
+                                                peer = %peer,
+                                                observer = %cancel.observer,
+                                                path = %cancel.path,
+                                                "[syndactyl][file-transfer] Received transfer cancellation"
+                                            );
+                                            let _ = self.event_sender.send(SyndactylP2PEvent::CancelTransfer {
+                                                peer,
+                                                request: cancel,
+                                                channel,
+                                            }).await;
+                                        }
                                     }
                                 }
-                                Message::Response { response, .. } => {
+                                Message::Response { response: SyndactylResponse::CancelAck { observer, path }, .. } => {
+                                    info!(peer = %peer, observer = %observer, path = %path, "[syndactyl][file-transfer] Cancellation acknowledged");
+                                }
+                                Message::Response { response: SyndactylResponse::Range(range), .. } => {
+                                    // Range reads are issued and matched by the network manager
+                                    debug!(peer = %peer, observer = %range.observer, path = %range.path, "[syndactyl][file-transfer] Ignoring range response");
+                                }
+                                Message::Response { response: SyndactylResponse::TreeNode(node), .. } => {
+                                    debug!(peer = %peer, observer = %node.observer, dir = %node.dir, "[syndactyl][file-transfer] Ignoring tree node");
+                                }
+                                Message::Response { response: SyndactylResponse::Capabilities(_), .. } => {
+                                    debug!(peer = %peer, "[syndactyl][file-transfer] Ignoring capabilities");
+                                }
+                                Message::Response { response: SyndactylResponse::Heads(_), .. } => {
+                                    debug!(peer = %peer, "[syndactyl][file-transfer] Ignoring heads");
+                                }
+                                Message::Response { response: SyndactylResponse::Error(error), .. } => {
+                                    warn!(peer = %peer, observer = %error.observer, path = %error.path, kind = ?error.kind, "[syndactyl][file-transfer] Request failed on peer");
+                                    let _ = self.event_sender.send(SyndactylP2PEvent::TransferError { peer, error }).await;
+                                }
+                                Message::Response { response: SyndactylResponse::Chunk(response), .. } => {
                                     // CBOR automatically deserializes the response
                                     info!(
                                         peer = %peer,
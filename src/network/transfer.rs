@@ -1,68 +1,314 @@
 use crate::core::models::FileTransferResponse;
 use crate::core::file_handler;
+use crate::core::storage::{self, StorageBackend};
+use crate::core::hash_pool;
+use crate::core::conflict::{ConflictResolver, ConflictResolvers, ResolvedConflict};
+use crate::core::version_store::VersionStore;
+#[cfg(feature = "metrics")]
+use crate::core::metrics::Metrics;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
-use tracing::{info, error};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, warn, error};
 
 /// Chunk size for file transfers (1MB)
 pub const CHUNK_SIZE: usize = 1024 * 1024;
 
+/// Largest chunk an observer can be tuned to send, and that peers accept (4MB)
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
 /// Maximum file size to transfer (10GB - effectively unlimited for most use cases)
 pub const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024 * 1024;
 
+/// Limits on what the tracker holds in memory
+/// A transfer's chunks are buffered until the whole file has arrived, so the
+/// byte limits also cap the largest file that can be received, except for
+/// versions fetched before they were hashed, which are written aside as they arrive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackerLimits {
+    pub max_transfers: usize,
+    pub max_transfers_per_peer: usize,
+    pub max_buffered_bytes: u64,
+    pub max_buffered_bytes_per_peer: u64,
+    /// Transfers that receive nothing for this long are dropped
+    pub idle_ttl: Duration,
+}
+
+impl Default for TrackerLimits {
+    fn default() -> Self {
+        Self {
+            max_transfers: 64,
+            max_transfers_per_peer: 16,
+            max_buffered_bytes: 4 * 1024 * 1024 * 1024,
+            max_buffered_bytes_per_peer: 2 * 1024 * 1024 * 1024,
+            idle_ttl: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Why a transfer was dropped by the tracker rather than completed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvictionReason {
+    /// Made room for a newer transfer or chunk (least recently active first)
+    Capacity,
+    /// Nothing received within the idle TTL
+    Idle,
+}
+
+/// A transfer dropped by the tracker; the caller owns any cleanup beyond the tracker
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvictedTransfer {
+    pub observer: String,
+    pub path: String,
+    pub peer: PeerId,
+    pub reason: EvictionReason,
+}
+
+/// Point-in-time tracker counters for metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrackerStats {
+    pub active_transfers: usize,
+    pub buffered_bytes: u64,
+    pub evicted_capacity: u64,
+    pub evicted_idle: u64,
+}
+
+impl TrackerStats {
+    #[cfg(feature = "metrics")]
+    pub fn write_metrics(&self, metrics: &mut Metrics) {
+        metrics.describe("syndactyl_tracked_transfers", "gauge", "Downloads currently being buffered");
+        metrics.sample("syndactyl_tracked_transfers", &[], self.active_transfers as u64);
+        metrics.describe("syndactyl_transfer_buffered_bytes", "gauge", "Bytes of downloads buffered in memory");
+        metrics.sample("syndactyl_transfer_buffered_bytes", &[], self.buffered_bytes);
+        metrics.describe("syndactyl_transfer_evictions_total", "counter", "Downloads dropped before completing, by reason");
+        metrics.sample("syndactyl_transfer_evictions_total", &[("reason", "capacity")], self.evicted_capacity);
+        metrics.sample("syndactyl_transfer_evictions_total", &[("reason", "idle")], self.evicted_idle);
+    }
+}
+
+/// Progress of one download, for the control API
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TransferProgress {
+    pub observer: String,
+    pub path: String,
+    /// Peer ID the file is downloaded from
+    pub peer: String,
+    pub total_size: u64,
+    pub chunks_received: usize,
+    pub total_chunks: usize,
+    pub elapsed_secs: u64,
+}
+
 /// In-progress file transfer tracking
 pub struct FileTransferTracker {
     /// Map of (observer, path) -> received chunks
     transfers: HashMap<(String, String), TransferState>,
+    limits: TrackerLimits,
+    buffered_bytes: u64,
+    evicted_capacity: u64,
+    evicted_idle: u64,
+    /// Consulted before a received file replaces a differing local copy
+    resolvers: ConflictResolvers,
+    /// Downloads written aside because the local copy they replace is in use
+    in_use: HashSet<(String, String)>,
 }
 
 struct TransferState {
     observer: String,
     path: String,
+    peer: PeerId,
     total_size: u64,
     expected_hash: String,
     chunks: HashMap<u64, Vec<u8>>, // offset -> data
-    base_path: PathBuf,
-    start_time: std::time::Instant,
+    storage: Arc<dyn StorageBackend>,
+    transaction: Option<String>,   // Stage instead of writing in place when set
+    start_time: Instant,
+    last_activity: Instant,
+    buffered_bytes: u64,
     chunks_received: usize,
     total_chunks: usize,
+    /// The chunk ending the file has arrived, though pipelined earlier ones may not have
+    last_received: bool,
+    /// Every chunk has arrived but the expected hash is still a pending version
+    awaiting_hash: bool,
+    /// Bytes of the local copy the file continues, when only the appended tail is fetched
+    base: u64,
+    /// Set when chunks go to the storage's incoming area instead of staying in `chunks`
+    on_disk: Option<OnDisk>,
+    /// The local copy is the version last applied from a peer, so no resolver is consulted
+    local_synced: bool,
+}
+
+/// Progress of a transfer written aside as it arrives
+/// Only chunks arriving out of order wait in memory, until the gap before them fills.
+struct OnDisk {
+    /// Every byte before this offset is written and hashed
+    written_to: u64,
+    hasher: sha2::Sha256,
+}
+
+impl TransferState {
+    fn new(observer: String, path: String, peer: PeerId, total_size: u64, expected_hash: String, storage: Arc<dyn StorageBackend>) -> Self {
+        let now = Instant::now();
+        Self {
+            observer,
+            path,
+            peer,
+            total_size,
+            expected_hash,
+            chunks: HashMap::new(),
+            storage,
+            transaction: None,
+            start_time: now,
+            last_activity: now,
+            buffered_bytes: 0,
+            chunks_received: 0,
+            total_chunks: 0,
+            last_received: false,
+            awaiting_hash: false,
+            base: 0,
+            on_disk: None,
+            local_synced: false,
+        }
+    }
 }
 
 impl FileTransferTracker {
     pub fn new() -> Self {
+        Self::with_limits(TrackerLimits::default())
+    }
+
+    pub fn with_limits(limits: TrackerLimits) -> Self {
         Self {
             transfers: HashMap::new(),
+            limits,
+            buffered_bytes: 0,
+            evicted_capacity: 0,
+            evicted_idle: 0,
+            resolvers: ConflictResolvers::new(),
+            in_use: HashSet::new(),
+        }
+    }
+
+    /// Whether a download that failed was written aside to replace an in-use local copy later
+    pub fn take_in_use(&mut self, key: &(String, String)) -> bool {
+        self.in_use.remove(key)
+    }
+
+    /// Merge received files into differing local copies with `resolver`, for one observer or all
+    pub fn register_conflict_resolver(&mut self, observer: Option<&str>, resolver: Arc<dyn ConflictResolver>) {
+        self.resolvers.register(observer, resolver);
+    }
+
+    /// Keep the last common version of mergeable files in `versions`
+    pub fn set_version_store(&mut self, versions: Arc<VersionStore>) {
+        self.resolvers.set_version_store(versions);
+    }
+
+    /// Note whether the local copy a download replaces is the version last applied from a peer
+    /// Only a local copy edited since conflicts with the download.
+    pub fn set_local_synced(&mut self, key: &(String, String), synced: bool) {
+        if let Some(state) = self.transfers.get_mut(key) {
+            state.local_synced = synced;
         }
     }
+
+    /// Note that a peer announced the content we already have
+    pub fn record_synced(&self, observer: &str, path: &str, storage: &dyn StorageBackend) {
+        self.resolvers.record_synced(observer, path, storage);
+    }
     
-    /// Start tracking a new file transfer
+    /// Start tracking a new file transfer from `peer`
+    /// Least recently active transfers are evicted to stay within the transfer
+    /// count limits (the peer's own first); they are returned for cleanup.
     pub fn start_transfer(
         &mut self,
         observer: String,
         path: String,
+        peer: PeerId,
         total_size: u64,
         hash: String,
-        base_path: PathBuf,
-    ) {
-        let key = (observer.clone(), path.clone());
-        
-        // Calculate total number of chunks
-        let total_chunks = ((total_size + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64) as usize;
-        
-        let state = TransferState {
-            observer: observer.clone(),
-            path: path.clone(),
-            total_size,
-            expected_hash: hash,
-            chunks: HashMap::new(),
-            base_path,
-            start_time: std::time::Instant::now(),
-            chunks_received: 0,
-            total_chunks,
-        };
+        storage: Arc<dyn StorageBackend>,
+        transaction: Option<String>,
+    ) -> Result<Vec<EvictedTransfer>, String> {
+        let mut state = TransferState::new(observer, path, peer, total_size, hash, storage);
+        state.transaction = transaction;
+        self.begin(state)
+    }
+
+    /// Start tracking the bytes `peer` appended to a local copy of `base` bytes
+    /// Only the tail is buffered; it is appended once the whole file is known to
+    /// hash to `hash`.
+    pub fn start_append(
+        &mut self,
+        observer: String,
+        path: String,
+        peer: PeerId,
+        total_size: u64,
+        hash: String,
+        storage: Arc<dyn StorageBackend>,
+        base: u64,
+    ) -> Result<Vec<EvictedTransfer>, String> {
+        if base >= total_size {
+            return Err(format!("Nothing appended past {} bytes", base));
+        }
+        let mut state = TransferState::new(observer, path, peer, total_size, hash, storage);
+        state.base = base;
+        self.begin(state)
+    }
+
+    fn begin(&mut self, mut state: TransferState) -> Result<Vec<EvictedTransfer>, String> {
+        // Whole files fetched before they were hashed can be larger than any buffer; the rest
+        // are staged, appended or handed to a conflict resolver whole
+        let streamed = hash_pool::parse_pending_version(&state.expected_hash).is_some()
+            && state.transaction.is_none()
+            && state.base == 0
+            && !self.resolvers.applies(&state.observer)
+            && state.storage.supports_partial();
+        if streamed {
+            use sha2::Digest;
+            state.on_disk = Some(OnDisk { written_to: 0, hasher: sha2::Sha256::new() });
+        }
+
+        let fetched = state.total_size - state.base;
+        if !streamed && fetched > self.limits.max_buffered_bytes_per_peer.min(self.limits.max_buffered_bytes) {
+            return Err(format!("File too large to buffer: {} bytes", fetched));
+        }
+
+        let key = (state.observer.clone(), state.path.clone());
+        // A restart replaces the old transfer rather than competing with it
+        self.remove(&key);
+
+        let peer = state.peer;
+        let mut evicted = Vec::new();
+        while self.transfers_for(&peer) >= self.limits.max_transfers_per_peer {
+            match self.evict_lru(|state| state.peer == peer) {
+                Some(transfer) => evicted.push(transfer),
+                None => break,
+            }
+        }
+        while self.transfers.len() >= self.limits.max_transfers {
+            match self.evict_lru(|_| true) {
+                Some(transfer) => evicted.push(transfer),
+                None => break,
+            }
+        }
         
+        // Calculate total number of chunks; an empty file still arrives as one empty chunk
+        state.total_chunks = fetched.div_ceil(CHUNK_SIZE as u64).max(1) as usize;
+        info!(
+            observer = %state.observer,
+            path = %state.path,
+            size = state.total_size,
+            appended_to = state.base,
+            total_chunks = state.total_chunks,
+            "Started tracking file transfer"
+        );
         self.transfers.insert(key, state);
-        info!(observer = %observer, path = %path, size = total_size, total_chunks = total_chunks, "Started tracking file transfer");
+        Ok(evicted)
     }
     
     /// Add a chunk to an in-progress transfer
@@ -73,18 +319,84 @@ impl FileTransferTracker {
         offset: u64,
         data: Vec<u8>,
         is_last_chunk: bool,
-    ) -> Result<Option<PathBuf>, String> {
+    ) -> Result<(Option<PathBuf>, Vec<EvictedTransfer>), String> {
         let key = (observer.to_string(), path.to_string());
         
-        let state = self.transfers.get_mut(&key)
+        let state = self.transfers.get(&key)
             .ok_or_else(|| format!("No transfer in progress for {}/{}", observer, path))?;
+        let peer = state.peer;
+        let (total_size, base) = (state.total_size, state.base);
+        
+        // Chunks must lie inside the announced file, past what we already hold of it
+        let end = offset.checked_add(data.len() as u64);
+        if end.is_none_or(|end| end > total_size) {
+            self.remove(&key);
+            return Err(format!("Chunk at offset {} extends past file size {}", offset, total_size));
+        }
+        if offset < base {
+            self.remove(&key);
+            return Err(format!("Chunk at offset {} overlaps the {} bytes held locally", offset, base));
+        }
+        
+        // Make room for the chunk by evicting the peer's other transfers; one peer's
+        // downloads never push out another's, so past the global limit it is refused
+        let added = data.len() as u64;
+        let mut evicted = Vec::new();
+        while self.buffered_for(&peer) + added > self.limits.max_buffered_bytes_per_peer
+            || self.buffered_bytes + added > self.limits.max_buffered_bytes
+        {
+            let own = |state: &TransferState| {
+                state.peer == peer && state.buffered_bytes > 0 && (&state.observer, &state.path) != (&key.0, &key.1)
+            };
+            match self.evict_lru(own) {
+                Some(transfer) => evicted.push(transfer),
+                None => break,
+            }
+        }
+        if self.buffered_for(&peer) + added > self.limits.max_buffered_bytes_per_peer
+            || self.buffered_bytes + added > self.limits.max_buffered_bytes
+        {
+            self.remove(&key);
+            return Err(format!("Buffer limit reached receiving {}/{}", observer, path));
+        }
         
-        // Add chunk
-        state.chunks.insert(offset, data);
+        // Add chunk, replacing a duplicate at the same offset
+        let state = self.transfers.get_mut(&key)
+            .ok_or_else(|| format!("No transfer in progress for {}/{}", observer, path))?;
+        // A repeat of a chunk already written aside changes nothing
+        if state.on_disk.as_ref().is_some_and(|on_disk| offset < on_disk.written_to) {
+            return Ok((None, evicted));
+        }
+        let replaced = state.chunks.insert(offset, data).map(|old| old.len() as u64).unwrap_or(0);
+        state.buffered_bytes = state.buffered_bytes + added - replaced;
+        self.buffered_bytes = self.buffered_bytes + added - replaced;
+        state.last_activity = Instant::now();
         state.chunks_received += 1;
+
+        // Chunks written aside leave memory as soon as they follow on from what is written
+        if let Some(on_disk) = state.on_disk.as_mut() {
+            use sha2::Digest;
+            while let Some(data) = state.chunks.remove(&on_disk.written_to) {
+                let len = data.len() as u64;
+                state.buffered_bytes -= len;
+                self.buffered_bytes -= len;
+                if let Err(e) = state.storage.write_partial(Path::new(&state.path), on_disk.written_to, &data) {
+                    error!(path = %state.path, error = ?e, "Failed to write received chunk");
+                    self.remove(&key);
+                    return Err(format!("Failed to write received chunk: {}", e));
+                }
+                on_disk.hasher.update(&data);
+                on_disk.written_to += len;
+                // Empty files arrive as a single empty chunk
+                if len == 0 {
+                    break;
+                }
+            }
+        }
+        let written = state.on_disk.as_ref().map_or(0, |on_disk| on_disk.written_to);
         
-        // Log progress
-        info!(
+        // Per-chunk progress is debug-only; the manager aggregates transfer logging
+        debug!(
             observer = %observer,
             path = %path,
             chunk = state.chunks_received,
@@ -94,18 +406,143 @@ impl FileTransferTracker {
             state.total_chunks
         );
         
-        if is_last_chunk {
+        state.last_received |= is_last_chunk;
+        if state.last_received && written + state.buffered_bytes == total_size - base {
+            // Held until the completing announcement says what the file must hash to
+            if hash_pool::parse_pending_version(&state.expected_hash).is_some() {
+                state.awaiting_hash = true;
+                return Ok((None, evicted));
+            }
             // All chunks received, assemble file
-            return self.complete_transfer(&key);
+            return self.complete_transfer(&key).map(|path| (path, evicted));
         }
         
-        Ok(None)
+        Ok((None, evicted))
+    }
+
+    /// Drop transfers that have received nothing within the idle TTL
+    pub fn expire_idle(&mut self, now: Instant) -> Vec<EvictedTransfer> {
+        let ttl = self.limits.idle_ttl;
+        let expired: Vec<(String, String)> = self.transfers.iter()
+            .filter(|(_, state)| now.saturating_duration_since(state.last_activity) >= ttl)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired.into_iter()
+            .filter_map(|key| self.remove(&key))
+            .map(|state| {
+                self.evicted_idle += 1;
+                warn!(observer = %state.observer, path = %state.path, peer = %state.peer, "Dropping idle transfer");
+                EvictedTransfer {
+                    observer: state.observer,
+                    path: state.path,
+                    peer: state.peer,
+                    reason: EvictionReason::Idle,
+                }
+            })
+            .collect()
+    }
+
+    pub fn stats(&self) -> TrackerStats {
+        TrackerStats {
+            active_transfers: self.transfers.len(),
+            buffered_bytes: self.buffered_bytes,
+            evicted_capacity: self.evicted_capacity,
+            evicted_idle: self.evicted_idle,
+        }
+    }
+
+    /// Downloads being buffered, by observer and path
+    pub fn progress(&self, now: Instant) -> Vec<TransferProgress> {
+        let mut progress: Vec<TransferProgress> = self.transfers.values()
+            .map(|state| TransferProgress {
+                observer: state.observer.clone(),
+                path: state.path.clone(),
+                peer: state.peer.to_string(),
+                total_size: state.total_size,
+                chunks_received: state.chunks_received,
+                total_chunks: state.total_chunks,
+                elapsed_secs: now.saturating_duration_since(state.start_time).as_secs(),
+            })
+            .collect();
+        progress.sort_by(|a, b| (&a.observer, &a.path).cmp(&(&b.observer, &b.path)));
+        progress
+    }
+
+    /// Conflicts resolvers settled recently, newest last
+    pub fn recent_conflicts(&self) -> Vec<ResolvedConflict> {
+        self.resolvers.recent()
+    }
+
+    /// Recount a transfer's chunks once it is known how they are split
+    /// The first chunk is as large as the sender chose; the rest are `chunk_size`.
+    pub fn set_chunk_layout(&mut self, observer: &str, path: &str, first_chunk: u64, chunk_size: u64) {
+        if let Some(state) = self.transfers.get_mut(&(observer.to_string(), path.to_string())) {
+            state.total_chunks = 1 + state.total_size.saturating_sub(first_chunk).div_ceil(chunk_size.max(1)) as usize;
+        }
+    }
+
+    /// Whether another transfer can start without evicting one
+    pub fn has_capacity(&self) -> bool {
+        self.transfers.len() < self.limits.max_transfers
+    }
+
+    /// Whether a transfer from `peer` can start without evicting another
+    pub fn has_room(&self, peer: &PeerId) -> bool {
+        self.has_capacity() && self.transfers_for(peer) < self.limits.max_transfers_per_peer
+    }
+
+    fn transfers_for(&self, peer: &PeerId) -> usize {
+        self.transfers.values().filter(|state| &state.peer == peer).count()
+    }
+
+    fn buffered_for(&self, peer: &PeerId) -> u64 {
+        self.transfers.values()
+            .filter(|state| &state.peer == peer)
+            .map(|state| state.buffered_bytes)
+            .sum()
+    }
+
+    /// Evict the least recently active transfer matching `filter`
+    fn evict_lru(&mut self, filter: impl Fn(&TransferState) -> bool) -> Option<EvictedTransfer> {
+        let key = self.transfers.iter()
+            .filter(|(_, state)| filter(state))
+            .min_by_key(|(_, state)| state.last_activity)
+            .map(|(key, _)| key.clone())?;
+        let state = self.remove(&key)?;
+        self.evicted_capacity += 1;
+        warn!(observer = %state.observer, path = %state.path, peer = %state.peer, "Evicting transfer to stay within tracker limits");
+        Some(EvictedTransfer {
+            observer: state.observer,
+            path: state.path,
+            peer: state.peer,
+            reason: EvictionReason::Capacity,
+        })
+    }
+
+    /// Remove a transfer, releasing its buffered bytes and anything it wrote aside
+    fn remove(&mut self, key: &(String, String)) -> Option<TransferState> {
+        let state = self.take(key)?;
+        if state.on_disk.is_some() {
+            if let Err(e) = state.storage.discard_incoming(Path::new(&state.path)) {
+                warn!(path = %state.path, error = %e, "Failed to remove partly received file");
+            }
+        }
+        Some(state)
+    }
+
+    fn take(&mut self, key: &(String, String)) -> Option<TransferState> {
+        let state = self.transfers.remove(key)?;
+        self.buffered_bytes -= state.buffered_bytes;
+        Some(state)
     }
     
     /// Complete a file transfer by assembling all chunks
     fn complete_transfer(&mut self, key: &(String, String)) -> Result<Option<PathBuf>, String> {
-        let state = self.transfers.remove(key)
+        let mut state = self.take(key)
             .ok_or_else(|| "Transfer not found".to_string())?;
+        if let Some(on_disk) = state.on_disk.take() {
+            return complete_on_disk(state, on_disk, &mut self.in_use);
+        }
         
         // Calculate elapsed time
         let elapsed = state.start_time.elapsed();
@@ -124,18 +561,24 @@ impl FileTransferTracker {
         }
         
         // Verify size
-        if file_content.len() != state.total_size as usize {
+        if file_content.len() as u64 != state.total_size - state.base {
             error!(
-                expected = state.total_size,
+                expected = state.total_size - state.base,
                 received = file_content.len(),
                 "File size mismatch"
             );
             return Err("File size mismatch".to_string());
         }
         
-        // Verify hash
+        // Verify hash, of the local copy followed by the tail for appends
         use sha2::{Sha256, Digest};
         let mut hasher = Sha256::new();
+        if state.base > 0 {
+            if let Err(e) = storage::hash_stored_prefix(&mut hasher, state.storage.as_ref(), Path::new(&state.path), state.base) {
+                warn!(path = %state.path, error = %e, "Local copy changed while appended bytes were fetched");
+                return Err(format!("Local copy changed while appended bytes were fetched: {}", e));
+            }
+        }
         hasher.update(&file_content);
         let calculated_hash = format!("{:x}", hasher.finalize());
         
@@ -148,13 +591,52 @@ impl FileTransferTracker {
             return Err("File hash mismatch".to_string());
         }
         
-        // Write file to disk
-        let absolute_path = file_handler::to_absolute_path(Path::new(&state.path), &state.base_path);
-        
-        if let Err(e) = file_handler::write_file_content(&absolute_path, &file_content) {
-            error!(path = %absolute_path.display(), error = ?e, "Failed to write file");
-            return Err(format!("Failed to write file: {}", e));
+        // The local copy is the start of the received version, so there is nothing to resolve
+        if state.base > 0 {
+            return match state.storage.append_file(Path::new(&state.path), state.base, &file_content) {
+                Ok(path) => {
+                    info!(
+                        observer = %state.observer,
+                        path = %state.path,
+                        size = state.total_size,
+                        appended = file_content.len(),
+                        elapsed_secs = format!("{:.2}", elapsed_secs),
+                        "Appended to file"
+                    );
+                    Ok(Some(path))
+                }
+                Err(e) => {
+                    error!(path = %state.path, error = ?e, "Failed to append to file");
+                    Err(format!("Failed to append to file: {}", e))
+                }
+            };
         }
+
+        let file_content = match self.resolvers.resolve(&state.observer, &state.path, state.storage.as_ref(), file_content, state.local_synced) {
+            Ok(content) => content,
+            Err(e) => {
+                error!(path = %state.path, error = ?e, "Failed to read local copy for conflict resolution");
+                return Err(format!("Failed to resolve conflict: {}", e));
+            }
+        };
+        
+        // Write file to disk through the observer's storage backend
+        // Transaction members are staged and moved into place when the whole transaction has arrived
+        let written = match &state.transaction {
+            Some(transaction) => state.storage.write_staged(transaction, Path::new(&state.path), &file_content),
+            None => state.storage.write_file(Path::new(&state.path), &file_content),
+        };
+        let absolute_path = match written {
+            Ok(path) => path,
+            Err(e) if e.kind() == std::io::ErrorKind::ResourceBusy => {
+                self.in_use.insert(key.clone());
+                return Err(e.to_string());
+            }
+            Err(e) => {
+                error!(path = %state.path, error = ?e, "Failed to write file");
+                return Err(format!("Failed to write file: {}", e));
+            }
+        };
         
         // Calculate transfer speed
         let size_mb = state.total_size as f64 / (1024.0 * 1024.0);
@@ -175,15 +657,94 @@ impl FileTransferTracker {
         Ok(Some(absolute_path))
     }
     
+    /// Expected hash of an in-progress transfer, if one is being tracked
+    pub fn expected_hash(&self, observer: &str, path: &str) -> Option<&str> {
+        let key = (observer.to_string(), path.to_string());
+        self.transfers.get(&key).map(|state| state.expected_hash.as_str())
+    }
+    
+    /// Total size of an in-progress transfer, if one is being tracked
+    pub fn total_size(&self, observer: &str, path: &str) -> Option<u64> {
+        let key = (observer.to_string(), path.to_string());
+        self.transfers.get(&key).map(|state| state.total_size)
+    }
+    
+    /// Whether a transfer has every chunk and only waits for its hash to be announced
+    pub fn is_awaiting_hash(&self, observer: &str, path: &str) -> bool {
+        let key = (observer.to_string(), path.to_string());
+        self.transfers.get(&key).is_some_and(|state| state.awaiting_hash)
+    }
+    
+    /// Supply the hash for a transfer started against a pending version
+    /// Completes the transfer if every chunk has already arrived.
+    pub fn confirm_hash(&mut self, observer: &str, path: &str, hash: String) -> Result<Option<PathBuf>, String> {
+        let key = (observer.to_string(), path.to_string());
+        let state = self.transfers.get_mut(&key)
+            .ok_or_else(|| format!("No transfer in progress for {}/{}", observer, path))?;
+        state.expected_hash = hash;
+        if state.awaiting_hash {
+            return self.complete_transfer(&key);
+        }
+        Ok(None)
+    }
+
     /// Cancel a transfer
     pub fn cancel_transfer(&mut self, observer: &str, path: &str) {
         let key = (observer.to_string(), path.to_string());
-        if self.transfers.remove(&key).is_some() {
+        if self.remove(&key).is_some() {
             info!(observer = %observer, path = %path, "Cancelled file transfer");
         }
     }
 }
 
+/// Move a transfer written aside into place, once it is known to hash as announced
+/// A local copy in use leaves the file aside and the transfer's key in `in_use`.
+fn complete_on_disk(state: TransferState, on_disk: OnDisk, in_use: &mut HashSet<(String, String)>) -> Result<Option<PathBuf>, String> {
+    use sha2::Digest;
+    let relative_path = Path::new(&state.path);
+    let calculated_hash = format!("{:x}", on_disk.hasher.finalize());
+    let written = if on_disk.written_to != state.total_size {
+        Err(format!("File size mismatch: received {} of {} bytes", on_disk.written_to, state.total_size))
+    } else if calculated_hash != state.expected_hash {
+        error!(expected = %state.expected_hash, calculated = %calculated_hash, "File hash mismatch");
+        Err("File hash mismatch".to_string())
+    } else {
+        match state.storage.commit_partial(relative_path, &calculated_hash) {
+            Err(e) if e.kind() == std::io::ErrorKind::ResourceBusy => {
+                in_use.insert((state.observer.clone(), state.path.clone()));
+                return Err(e.to_string());
+            }
+            written => written.map_err(|e| {
+                error!(path = %state.path, error = ?e, "Failed to write file");
+                format!("Failed to write file: {}", e)
+            }),
+        }
+    };
+    match written {
+        Ok(path) => {
+            info!(
+                observer = %state.observer,
+                path = %state.path,
+                size = state.total_size,
+                chunks = state.chunks_received,
+                elapsed_secs = format!("{:.2}", state.start_time.elapsed().as_secs_f64()),
+                "File transfer completed, written as it arrived"
+            );
+            Ok(Some(path))
+        }
+        Err(e) => {
+            let _ = state.storage.discard_incoming(relative_path);
+            Err(e)
+        }
+    }
+}
+
+/// SHA-256 of one chunk, sent alongside it
+pub fn segment_hash(data: &[u8]) -> String {
+    use sha2::{Sha256, Digest};
+    format!("{:x}", Sha256::digest(data))
+}
+
 /// Generate file transfer response chunks for a file
 pub fn generate_file_chunks(
     observer: &str,
@@ -204,7 +765,8 @@ pub fn generate_file_chunks(
     let mut chunks = Vec::new();
     let mut offset = 0u64;
     
-    while offset < total_size {
+    // An empty file is sent as a single empty last chunk, so the receiver still completes
+    while offset < total_size || chunks.is_empty() {
         let chunk_data = file_handler::read_file_chunk(absolute_path, offset, CHUNK_SIZE)
             .map_err(|e| format!("Failed to read file chunk: {}", e))?;
         
@@ -218,10 +780,16 @@ pub fn generate_file_chunks(
             total_size,
             hash: hash.to_string(),
             is_last_chunk: is_last,
+            segment_hash: Some(segment_hash(&chunk_data)),
+            segment_hmac: None,
+            encoding: None,
         };
         
         chunks.push(response);
         offset += chunk_data.len() as u64;
+        if is_last {
+            break;
+        }
     }
     
     Ok(chunks)
@@ -232,21 +800,20 @@ pub fn generate_file_chunks(
 pub fn generate_first_chunk(
     observer: &str,
     relative_path: &Path,
-    absolute_path: &Path,
+    storage: &dyn StorageBackend,
     hash: &str,
+    chunk_size: usize,
 ) -> Result<FileTransferResponse, String> {
-    // Get file metadata
-    let metadata = file_handler::get_file_metadata(absolute_path)
+    // Get file size from the storage backend
+    let total_size = storage.size(relative_path)
         .map_err(|e| format!("Failed to get file metadata: {}", e))?;
     
-    let total_size = metadata.0;
-    
     if total_size > MAX_FILE_SIZE {
         return Err(format!("File too large: {} bytes (max: {})", total_size, MAX_FILE_SIZE));
     }
     
     // Read only the first chunk
-    let chunk_data = file_handler::read_file_chunk(absolute_path, 0, CHUNK_SIZE)
+    let chunk_data = storage.read_chunk(relative_path, 0, chunk_size)
         .map_err(|e| format!("Failed to read first chunk: {}", e))?;
     
     let is_last = chunk_data.len() as u64 >= total_size;
@@ -254,11 +821,14 @@ pub fn generate_first_chunk(
     let response = FileTransferResponse {
         observer: observer.to_string(),
         path: relative_path.display().to_string(),
+        segment_hash: Some(segment_hash(&chunk_data)),
+        segment_hmac: None,
         data: chunk_data,
         offset: 0,
         total_size,
         hash: hash.to_string(),
         is_last_chunk: is_last,
+        encoding: None,
     };
     
     Ok(response)
@@ -267,6 +837,9 @@ pub fn generate_first_chunk(
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "encryption")]
+    use crate::core::storage::EncryptedStorage;
+    use crate::core::storage::PlainStorage;
     use tempfile::TempDir;
     use std::fs::File;
     use std::io::Write;
@@ -289,10 +862,12 @@ mod tests {
         tracker.start_transfer(
             observer.clone(),
             path.clone(),
+            PeerId::random(),
             content.len() as u64,
             hash.clone(),
-            temp_dir.path().to_path_buf(),
-        );
+            Arc::new(PlainStorage::new(temp_dir.path())),
+            None,
+        ).unwrap();
         
         let result = tracker.add_chunk(
             &observer,
@@ -303,10 +878,266 @@ mod tests {
         );
         
         assert!(result.is_ok());
-        let file_path = result.unwrap().unwrap();
+        let file_path = result.unwrap().0.unwrap();
         
         // Verify file was written
         let written_content = std::fs::read(&file_path).unwrap();
         assert_eq!(written_content, content);
+        assert_eq!(tracker.stats().buffered_bytes, 0);
+    }
+
+    #[test]
+    fn test_tracker_limits_evict_lru() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let mut tracker = FileTransferTracker::with_limits(TrackerLimits {
+            max_transfers: 3,
+            max_transfers_per_peer: 2,
+            max_buffered_bytes: 100,
+            max_buffered_bytes_per_peer: 100,
+            idle_ttl: Duration::from_secs(60),
+        });
+        let flooder = PeerId::random();
+        let other = PeerId::random();
+        let start = |tracker: &mut FileTransferTracker, path: &str, peer: PeerId| {
+            tracker.start_transfer("docs".to_string(), path.to_string(), peer, 60, "h".to_string(), storage.clone(), None)
+        };
+
+        // A peer over its own limit only evicts its own oldest transfer
+        assert!(start(&mut tracker, "a", flooder).unwrap().is_empty());
+        assert!(start(&mut tracker, "b", other).unwrap().is_empty());
+        assert!(start(&mut tracker, "c", flooder).unwrap().is_empty());
+        let evicted = start(&mut tracker, "d", flooder).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!((evicted[0].path.as_str(), evicted[0].reason), ("a", EvictionReason::Capacity));
+
+        // Chunks outside the announced size abort the transfer
+        assert!(tracker.add_chunk("docs", "d", 50, vec![0; 20], false).is_err());
+        assert!(tracker.total_size("docs", "d").is_none());
+
+        // Past the global byte limit a peer only displaces its own transfers, then is refused
+        assert!(start(&mut tracker, "d", flooder).unwrap().is_empty());
+        tracker.add_chunk("docs", "b", 0, vec![0; 50], false).unwrap();
+        tracker.add_chunk("docs", "c", 0, vec![0; 30], false).unwrap();
+        let (_, evicted) = tracker.add_chunk("docs", "d", 0, vec![0; 40], false).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].path, "c");
+        assert!(tracker.add_chunk("docs", "d", 40, vec![0; 20], false).is_err());
+        assert!(tracker.total_size("docs", "d").is_none());
+        assert_eq!(tracker.total_size("docs", "b"), Some(60));
+        assert_eq!(tracker.stats().buffered_bytes, 50);
+
+        let expired = tracker.expire_idle(Instant::now() + Duration::from_secs(61));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].reason, EvictionReason::Idle);
+        assert_eq!(tracker.stats(), TrackerStats { active_transfers: 0, buffered_bytes: 0, evicted_capacity: 2, evicted_idle: 1 });
+    }
+
+    #[test]
+    fn test_pending_version_completes_once_hash_arrives() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let mut tracker = FileTransferTracker::new();
+        let content = b"announced before hashing".to_vec();
+        let pending = hash_pool::pending_version(content.len() as u64, 1_700_000_000);
+        tracker.start_transfer("docs".to_string(), "big.iso".to_string(), PeerId::random(), content.len() as u64, pending, storage.clone(), None).unwrap();
+
+        let (written, _) = tracker.add_chunk("docs", "big.iso", 0, content.clone(), true).unwrap();
+        assert!(written.is_none());
+        assert!(!temp_dir.path().join("big.iso").exists());
+
+        let written = tracker.confirm_hash("docs", "big.iso", segment_hash(&content)).unwrap();
+        assert_eq!(written, Some(temp_dir.path().join("big.iso")));
+        assert_eq!(std::fs::read(temp_dir.path().join("big.iso")).unwrap(), content);
+
+        // A hash the content doesn't match is refused
+        let pending = hash_pool::pending_version(content.len() as u64, 1_700_000_001);
+        tracker.start_transfer("docs".to_string(), "big.iso".to_string(), PeerId::random(), content.len() as u64, pending, storage, None).unwrap();
+        tracker.add_chunk("docs", "big.iso", 0, content, true).unwrap();
+        assert!(tracker.confirm_hash("docs", "big.iso", "0".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_pending_versions_are_written_aside_as_they_arrive() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let mut tracker = FileTransferTracker::with_limits(TrackerLimits {
+            max_buffered_bytes: 20,
+            max_buffered_bytes_per_peer: 20,
+            ..TrackerLimits::default()
+        });
+        let content: Vec<u8> = (0..90).collect();
+        let pending = hash_pool::pending_version(content.len() as u64, 1_700_000_000);
+        tracker.start_transfer("docs".to_string(), "big.iso".to_string(), PeerId::random(), 90, pending.clone(), storage.clone(), None).unwrap();
+
+        // Larger than the buffer, yet only the chunk arriving early waits in memory
+        tracker.add_chunk("docs", "big.iso", 0, content[..30].to_vec(), false).unwrap();
+        assert_eq!(tracker.stats().buffered_bytes, 0);
+        tracker.add_chunk("docs", "big.iso", 45, content[45..60].to_vec(), false).unwrap();
+        assert_eq!(tracker.stats().buffered_bytes, 15);
+        tracker.add_chunk("docs", "big.iso", 30, content[30..45].to_vec(), false).unwrap();
+        tracker.add_chunk("docs", "big.iso", 0, content[..30].to_vec(), false).unwrap();
+        let (written, _) = tracker.add_chunk("docs", "big.iso", 60, content[60..].to_vec(), true).unwrap();
+        assert!(written.is_none() && tracker.is_awaiting_hash("docs", "big.iso"));
+        assert_eq!(tracker.stats().buffered_bytes, 0);
+        assert!(!temp_dir.path().join("big.iso").exists());
+
+        let written = tracker.confirm_hash("docs", "big.iso", segment_hash(&content)).unwrap();
+        assert_eq!(std::fs::read(written.unwrap()).unwrap(), content);
+
+        // A cancelled transfer leaves nothing behind in the incoming area
+        tracker.start_transfer("docs".to_string(), "other.iso".to_string(), PeerId::random(), 90, pending, storage, None).unwrap();
+        tracker.add_chunk("docs", "other.iso", 0, content[..30].to_vec(), false).unwrap();
+        tracker.cancel_transfer("docs", "other.iso");
+        let incoming = temp_dir.path().join(".syndactyl/tmp/incoming");
+        assert_eq!(std::fs::read_dir(incoming).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_empty_files_are_created_and_truncate_existing_ones() {
+        let temp_dir = TempDir::new().unwrap();
+        let empty_hash = segment_hash(&[]);
+        let plain: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(&temp_dir.path().join("plain")));
+        #[cfg(feature = "encryption")]
+        let encrypted: Arc<dyn StorageBackend> = Arc::new(EncryptedStorage::new(&temp_dir.path().join("encrypted"), "docs", "passphrase"));
+        #[cfg(feature = "encryption")]
+        let storages = [plain, encrypted];
+        #[cfg(not(feature = "encryption"))]
+        let storages = [plain];
+        for storage in storages {
+            let mut tracker = FileTransferTracker::new();
+            storage.write_file(Path::new("notes.txt"), b"to be emptied").unwrap();
+            storage.write_file(Path::new("empty.txt"), b"").unwrap();
+            assert_eq!(storage.hash(Path::new("empty.txt")).unwrap(), empty_hash);
+
+            // Served as a single empty last chunk
+            let chunk = generate_first_chunk("docs", Path::new("empty.txt"), storage.as_ref(), &empty_hash, CHUNK_SIZE).unwrap();
+            assert!(chunk.data.is_empty() && chunk.is_last_chunk);
+            assert_eq!(chunk.total_size, 0);
+
+            for path in ["new.txt", "notes.txt"] {
+                tracker.start_transfer("docs".to_string(), path.to_string(), PeerId::random(), 0, empty_hash.clone(), storage.clone(), None).unwrap();
+                let (written, _) = tracker.add_chunk("docs", path, 0, chunk.data.clone(), chunk.is_last_chunk).unwrap();
+                assert!(written.is_some());
+                assert_eq!(storage.size(Path::new(path)).unwrap(), 0);
+                assert_eq!(storage.hash(Path::new(path)).unwrap(), empty_hash);
+            }
+            assert_eq!(tracker.stats().active_transfers, 0);
+        }
+
+        let empty = temp_dir.path().join("plain").join("empty.txt");
+        let chunks = generate_file_chunks("docs", Path::new("empty.txt"), &empty, &empty_hash).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].data.is_empty() && chunks[0].is_last_chunk);
+    }
+
+    #[test]
+    fn test_pipelined_chunks_complete_once_all_have_arrived() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let mut tracker = FileTransferTracker::new();
+        let content = b"first half, second half".to_vec();
+        let (head, tail) = content.split_at(12);
+        tracker.start_transfer("docs".to_string(), "a.txt".to_string(), PeerId::random(), content.len() as u64, segment_hash(&content), storage, None).unwrap();
+
+        // The last chunk overtakes an earlier one
+        let (written, _) = tracker.add_chunk("docs", "a.txt", 12, tail.to_vec(), true).unwrap();
+        assert!(written.is_none());
+        let (written, _) = tracker.add_chunk("docs", "a.txt", 0, head.to_vec(), false).unwrap();
+        assert_eq!(std::fs::read(written.unwrap()).unwrap(), content);
+    }
+
+    #[test]
+    fn test_appended_tail_is_fetched_and_appended_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+        let log = Path::new("app.log");
+        storage.write_file(log, b"line 1\nline 2\n").unwrap();
+        let grown = b"line 1\nline 2\nline 3\n".to_vec();
+
+        // The sender finds the previous version at the start of the grown file
+        let sender = TempDir::new().unwrap();
+        let sender_storage = PlainStorage::new(sender.path());
+        sender_storage.write_file(log, &grown).unwrap();
+        assert_eq!(storage::prefix_hash(&sender_storage, log, 14).unwrap(), storage.hash(log).unwrap());
+
+        let mut tracker = FileTransferTracker::new();
+        let start = |tracker: &mut FileTransferTracker| {
+            tracker.start_append("docs".to_string(), "app.log".to_string(), PeerId::random(), grown.len() as u64, segment_hash(&grown), storage.clone(), 14)
+        };
+        start(&mut tracker).unwrap();
+        assert!(tracker.add_chunk("docs", "app.log", 0, grown[..14].to_vec(), false).is_err());
+        start(&mut tracker).unwrap();
+        let (written, _) = tracker.add_chunk("docs", "app.log", 14, grown[14..].to_vec(), true).unwrap();
+        assert_eq!(std::fs::read(written.unwrap()).unwrap(), grown);
+
+        // A tail that doesn't continue the local copy is never appended
+        let longer = b"line 1\nline 2\nline 3\nline 4\n".to_vec();
+        tracker.start_append("docs".to_string(), "app.log".to_string(), PeerId::random(), longer.len() as u64, segment_hash(&longer), storage.clone(), 14).unwrap();
+        assert!(tracker.add_chunk("docs", "app.log", 14, longer[14..].to_vec(), true).is_err());
+        assert_eq!(storage.read_chunk(log, 0, 64).unwrap(), grown);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn test_transfers_survive_injected_faults_or_fail_cleanly() {
+        use crate::network::faults::{ChunkFault, FaultInjector, FaultPlan};
+
+        let source_dir = TempDir::new().unwrap();
+        let content: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(source_dir.path().join("big.bin"), &content).unwrap();
+        let hash = segment_hash(&content);
+        let chunks = generate_file_chunks("docs", Path::new("big.bin"), &source_dir.path().join("big.bin"), &hash).unwrap();
+
+        // Feed the served chunks through the injector the way the manager does, returning
+        // what was written, or the error, and the offsets that were dropped
+        let receive = |plan: FaultPlan| {
+            let temp_dir = TempDir::new().unwrap();
+            let storage: Arc<dyn StorageBackend> = Arc::new(PlainStorage::new(temp_dir.path()));
+            let mut tracker = FileTransferTracker::new();
+            tracker.start_transfer("docs".to_string(), "big.bin".to_string(), PeerId::random(), content.len() as u64, hash.clone(), storage, None).unwrap();
+            let faults = FaultInjector::new();
+            faults.set(plan);
+            let (mut held, mut dropped, mut result) = (Vec::new(), Vec::new(), Ok(None));
+            let deliver = |tracker: &mut FileTransferTracker, chunk: FileTransferResponse| {
+                tracker.add_chunk("docs", "big.bin", chunk.offset, chunk.data, chunk.is_last_chunk).map(|(written, _)| written)
+            };
+            for mut chunk in chunks.clone() {
+                match faults.on_chunk_received(&mut chunk.data) {
+                    ChunkFault::Drop | ChunkFault::Kill => dropped.push(chunk.offset),
+                    ChunkFault::Reorder => held.push(chunk),
+                    ChunkFault::Deliver | ChunkFault::Delay(_) => {
+                        result = result.and(deliver(&mut tracker, chunk));
+                        // Held chunks follow the next one delivered
+                        for held in held.drain(..) {
+                            result = result.and(deliver(&mut tracker, held));
+                        }
+                    }
+                }
+            }
+            for held in held.drain(..) {
+                result = result.and(deliver(&mut tracker, held));
+            }
+            // Dropped chunks are requested again, as the manager's retry does
+            for offset in &dropped {
+                let chunk = chunks.iter().find(|chunk| chunk.offset == *offset).unwrap().clone();
+                result = result.and(deliver(&mut tracker, chunk));
+            }
+            (result.map(|written| written.map(|path| std::fs::read(path).unwrap())), dropped, faults.counts(), temp_dir)
+        };
+
+        let (written, _, counts, _dir) = receive(FaultPlan { reorder_rate: 0.5, seed: 7, ..FaultPlan::default() });
+        assert!(counts.reordered > 0);
+        assert_eq!(written.unwrap().as_deref(), Some(content.as_slice()));
+
+        let (written, dropped, counts, _dir) = receive(FaultPlan { drop_rate: 0.5, seed: 11, ..FaultPlan::default() });
+        assert!(!dropped.is_empty() && counts.dropped == dropped.len() as u64);
+        assert_eq!(written.unwrap().as_deref(), Some(content.as_slice()));
+
+        // A corrupted byte never reaches the destination
+        let (written, _, counts, dir) = receive(FaultPlan { corrupt_rate: 1.0, ..FaultPlan::default() });
+        assert!(counts.corrupted > 0);
+        assert!(written.is_err());
+        assert!(!dir.path().join("big.bin").exists());
     }
 }
@@ -0,0 +1,132 @@
+use std::error::Error;
+use futures::{AsyncRead, AsyncWrite};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::Boxed;
+use libp2p::core::upgrade;
+use libp2p::tcp::tokio::{Transport as TokioTcpTransport, TcpStream};
+use libp2p::multiaddr::Protocol;
+use libp2p::{dns, identity, websocket, PeerId, Transport};
+use libp2p::noise::Config as NoiseConfig;
+use libp2p::yamux::Config as YamuxConfig;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use tracing::info;
+use crate::core::config::{NetworkConfig, WebSocketConfig};
+use crate::network::bootstrap;
+use crate::network::proxy::{ProxyConfig, ProxyTransport};
+
+/// Transport for the swarm: plain TCP, plus WebSocket+TLS when configured
+/// Both go through the proxy if one is set. Connections are encrypted with
+/// Noise and multiplexed with Yamux either way; the TLS layer only makes
+/// wss connections look like ordinary HTTPS to middleboxes.
+pub fn build(network_config: &NetworkConfig, keypair: &identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error>> {
+    let proxy = network_config.proxy.as_ref().map(|proxy| ProxyConfig::parse(proxy.expose())).transpose()?;
+    if let Some(proxy) = &proxy {
+        info!(host = %proxy.host, port = proxy.port, kind = ?proxy.kind, "[syndactyl] Dialing peers through proxy");
+    }
+    let tcp = || -> Result<Boxed<TcpStream>, Box<dyn Error>> {
+        Ok(match &proxy {
+            Some(proxy) => ProxyTransport::new(TokioTcpTransport::default(), proxy.clone()).boxed(),
+            None => TokioTcpTransport::default().boxed(),
+        })
+    };
+    let plain = secure(tcp()?, keypair)?;
+    if !uses_websocket(network_config) {
+        return Ok(plain);
+    }
+
+    // wss peers are usually named by hostname, so resolve names unless the proxy does
+    let inner = match &proxy {
+        Some(_) => tcp()?,
+        None => dns::tokio::Transport::system(TokioTcpTransport::default())?.boxed(),
+    };
+    let mut ws = websocket::Config::new(inner);
+    if let Some(config) = network_config.websocket.as_ref().filter(|config| config.listen_port.is_some()) {
+        ws.set_tls_config(server_tls(config)?);
+    }
+    let ws = secure(ws, keypair)?;
+    Ok(plain.or_transport(ws).map(|output, _| output.into_inner()).boxed())
+}
+
+/// Whether the WebSocket transport is needed, to listen or to dial a peer
+pub fn uses_websocket(network_config: &NetworkConfig) -> bool {
+    network_config.websocket.is_some()
+        || network_config.bootstrap_peers.iter().any(|peer| {
+            peer.websocket == Some(true) || bootstrap::target(peer).is_ok_and(|(_, address)| {
+                address.iter().any(|protocol| matches!(protocol, Protocol::Ws(_) | Protocol::Wss(_)))
+            })
+        })
+}
+
+/// Check the WebSocket settings, so mistakes surface at startup
+pub fn validate(network_config: &NetworkConfig) -> Result<(), String> {
+    let Some(config) = &network_config.websocket else {
+        return Ok(());
+    };
+    if config.listen_port.is_some() && (config.tls_cert.is_none() || config.tls_key.is_none()) {
+        return Err("websocket.listen_port needs tls_cert and tls_key: wss can't be served without a certificate".to_string());
+    }
+    if network_config.outbound_only == Some(true) && config.listen_port.is_some() {
+        return Err("websocket.listen_port can't be combined with outbound_only".to_string());
+    }
+    Ok(())
+}
+
+/// Encrypt and multiplex connections made over `transport`
+fn secure<T>(transport: T, keypair: &identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error>>
+where
+    T: Transport + Send + Unpin + 'static,
+    T::Output: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T::Error: Send + Sync + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+{
+    Ok(transport
+        .upgrade(upgrade::Version::V1)
+        .authenticate(NoiseConfig::new(keypair)?)
+        .multiplex(YamuxConfig::default())
+        .boxed())
+}
+
+fn server_tls(config: &WebSocketConfig) -> Result<websocket::tls::Config, Box<dyn Error>> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+        return Err("websocket.listen_port needs tls_cert and tls_key".into());
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| format!("Failed to read {}: {}", cert_path, e))?
+        .map(|cert| cert.map(|cert| websocket::tls::Certificate::new(cert.to_vec())))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate in {}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path).into());
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Failed to read private key {}: {}", key_path, e))?;
+    let key = websocket::tls::PrivateKey::new(key.secret_der().to_vec());
+    Ok(websocket::tls::Config::new(key, certs)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websocket_peers_and_settings() {
+        let mut network_config: NetworkConfig = serde_json::from_str(r#"{
+            "listen_addr": "0.0.0.0",
+            "port": "4001",
+            "dht_mode": "server",
+            "bootstrap_peers": [{"ip": "relay.example.com", "port": "443", "peer_id": "12D3KooWRelay", "websocket": true}]
+        }"#).unwrap();
+        assert!(uses_websocket(&network_config));
+        assert_eq!(network_config.bootstrap_peers[0].multiaddr(), "/dns/relay.example.com/tcp/443/wss/p2p/12D3KooWRelay");
+        assert!(validate(&network_config).is_ok());
+
+        network_config.websocket = Some(WebSocketConfig { listen_port: Some("443".to_string()), ..WebSocketConfig::default() });
+        assert!(validate(&network_config).unwrap_err().contains("tls_cert"));
+
+        network_config.bootstrap_peers[0].websocket = None;
+        network_config.bootstrap_peers[0].ip = "10.0.0.2".to_string();
+        assert_eq!(network_config.bootstrap_peers[0].multiaddr(), "/ip4/10.0.0.2/tcp/443/p2p/12D3KooWRelay");
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+/// Deltas buffered per subscriber; one that falls further behind is sent a fresh snapshot
+const FEED_CAPACITY: usize = 64;
+
+/// What a tray icon shows for one observer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Activity {
+    Idle,
+    /// Files are being downloaded, served, reconciled or rescanned
+    Syncing,
+    /// The directory is missing or its volume is low on space
+    Error,
+}
+
+/// Everything a tray icon shows, as sent when a subscription starts
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TrayState {
+    /// Number of the last change included; each delta carries the next one
+    pub seq: u64,
+    pub peers: usize,
+    pub observers: BTreeMap<String, Activity>,
+}
+
+/// What changed in the update numbered `seq`; unchanged fields are left out
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TrayDelta {
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peers: Option<usize>,
+    /// Observers that were added or whose activity changed
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub observers: BTreeMap<String, Activity>,
+    /// Observers no longer configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl TrayState {
+    /// Apply the next delta, returning false if one was missed and a new snapshot is needed
+    /// Deltas already included in the state are ignored.
+    pub fn apply(&mut self, delta: &TrayDelta) -> bool {
+        if delta.seq <= self.seq {
+            return true;
+        }
+        if delta.seq != self.seq + 1 {
+            return false;
+        }
+        self.seq = delta.seq;
+        if let Some(peers) = delta.peers {
+            self.peers = peers;
+        }
+        self.observers.extend(delta.observers.iter().map(|(name, activity)| (name.clone(), *activity)));
+        for name in &delta.removed {
+            self.observers.remove(name);
+        }
+        true
+    }
+
+    /// The delta from this state to one with `peers` and `observers`, if anything differs
+    fn delta_to(&self, peers: usize, observers: &BTreeMap<String, Activity>) -> Option<TrayDelta> {
+        let delta = TrayDelta {
+            seq: self.seq + 1,
+            peers: (peers != self.peers).then_some(peers),
+            observers: observers.iter()
+                .filter(|(name, activity)| self.observers.get(*name) != Some(*activity))
+                .map(|(name, activity)| (name.clone(), *activity))
+                .collect(),
+            removed: self.observers.keys().filter(|name| !observers.contains_key(*name)).cloned().collect(),
+        };
+        let changed = delta.peers.is_some() || !delta.observers.is_empty() || !delta.removed.is_empty();
+        changed.then_some(delta)
+    }
+}
+
+/// Peer count and per-observer activity, published as compact deltas for tray icons
+/// Cheap to clone; the manager updates it and the control socket subscribes to it.
+#[derive(Clone)]
+pub struct TrayFeed {
+    sender: broadcast::Sender<TrayDelta>,
+    state: Arc<Mutex<TrayState>>,
+}
+
+impl Default for TrayFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrayFeed {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        Self { sender, state: Arc::new(Mutex::new(TrayState::default())) }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TrayState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The current state, and a receiver for every delta after it
+    pub fn subscribe(&self) -> (TrayState, broadcast::Receiver<TrayDelta>) {
+        // Updates publish under the same lock, so none falls between the two
+        let state = self.lock();
+        (state.clone(), self.sender.subscribe())
+    }
+
+    /// Replace the state, publishing what changed
+    pub fn update(&self, peers: usize, observers: BTreeMap<String, Activity>) {
+        let mut state = self.lock();
+        let Some(delta) = state.delta_to(peers, &observers) else {
+            return;
+        };
+        state.apply(&delta);
+        // No subscribers is not an error; they get the state when they subscribe
+        let _ = self.sender.send(delta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deltas_carry_only_changes_and_rebuild_the_state() {
+        let feed = TrayFeed::new();
+        feed.update(1, BTreeMap::from([("docs".to_string(), Activity::Idle)]));
+        let (mut mirror, mut rx) = feed.subscribe();
+        assert_eq!(mirror.seq, 1);
+
+        feed.update(1, BTreeMap::from([("docs".to_string(), Activity::Idle)]));
+        feed.update(2, BTreeMap::from([("docs".to_string(), Activity::Syncing), ("photos".to_string(), Activity::Idle)]));
+        feed.update(2, BTreeMap::from([("photos".to_string(), Activity::Error)]));
+
+        let deltas: Vec<TrayDelta> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(serde_json::to_string(&deltas[1]).unwrap(), r#"{"seq":3,"observers":{"photos":"error"},"removed":["docs"]}"#);
+        for delta in &deltas {
+            assert!(mirror.apply(delta));
+        }
+        assert_eq!(mirror, feed.subscribe().0);
+
+        // A gap means the mirror can't be trusted any more
+        let mut stale = TrayState::default();
+        assert!(!stale.apply(&deltas[1]));
+    }
+}
@@ -0,0 +1,82 @@
+use crate::core::config::TransferTuningConfig;
+use crate::network::transfer::{CHUNK_SIZE, MAX_CHUNK_SIZE};
+
+/// Smallest chunk size an observer can be tuned to (64 KiB)
+const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How one observer's files are split and pulled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferTuning {
+    pub chunk_size: usize,
+    /// Chunk requests one download keeps in flight; None leaves it to the congestion window
+    pub max_parallel_chunks: Option<usize>,
+    /// Whether chunks are compressed in transit
+    pub compression: bool,
+    /// Bytes a file must already have for an append to be sent alone
+    pub delta_min_size: u64,
+}
+
+impl Default for TransferTuning {
+    fn default() -> Self {
+        Self { chunk_size: CHUNK_SIZE, max_parallel_chunks: None, compression: true, delta_min_size: 0 }
+    }
+}
+
+/// An observer's tuning: each of its settings, else the network's, else the default
+pub fn resolve(network: Option<&TransferTuningConfig>, observer: Option<&TransferTuningConfig>) -> Result<TransferTuning, String> {
+    fn setting<T>(network: Option<&TransferTuningConfig>, observer: Option<&TransferTuningConfig>, pick: fn(&TransferTuningConfig) -> Option<T>) -> Option<T> {
+        observer.and_then(pick).or_else(|| network.and_then(pick))
+    }
+    let defaults = TransferTuning::default();
+    let chunk_size = match setting(network, observer, |tuning| tuning.chunk_size_kb) {
+        Some(kb) => {
+            let bytes = kb.saturating_mul(1024);
+            if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&bytes) {
+                return Err(format!(
+                    "chunk_size_kb must be between {} and {}, got {}",
+                    MIN_CHUNK_SIZE / 1024, MAX_CHUNK_SIZE / 1024, kb
+                ));
+            }
+            // Peers that ignore requested lengths send CHUNK_SIZE, which must then divide ours
+            if bytes > CHUNK_SIZE && bytes % CHUNK_SIZE != 0 {
+                return Err(format!("chunk_size_kb above {} must be a multiple of it, got {}", CHUNK_SIZE / 1024, kb));
+            }
+            bytes
+        }
+        None => CHUNK_SIZE,
+    };
+    let max_parallel_chunks = setting(network, observer, |tuning| tuning.max_parallel_chunks);
+    if max_parallel_chunks == Some(0) {
+        return Err("max_parallel_chunks must be at least 1".to_string());
+    }
+    let compression = setting(network, observer, |tuning| tuning.compression).unwrap_or(defaults.compression);
+    let delta_min_size = setting(network, observer, |tuning| tuning.delta_min_size_kb)
+        .map_or(defaults.delta_min_size, |kb| kb.saturating_mul(1024));
+    Ok(TransferTuning { chunk_size, max_parallel_chunks, compression, delta_min_size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observer_settings_override_network_ones() {
+        assert_eq!(resolve(None, None).unwrap(), TransferTuning::default());
+
+        let network = TransferTuningConfig { chunk_size_kb: Some(256), max_parallel_chunks: Some(4), delta_min_size_kb: Some(64), ..Default::default() };
+        let videos = TransferTuningConfig { chunk_size_kb: Some(4096), compression: Some(false), ..Default::default() };
+        let expected = TransferTuning { chunk_size: 256 * 1024, max_parallel_chunks: Some(4), compression: true, delta_min_size: 64 * 1024 };
+        assert_eq!(resolve(Some(&network), None).unwrap(), expected);
+        assert_eq!(
+            resolve(Some(&network), Some(&videos)).unwrap(),
+            TransferTuning { chunk_size: MAX_CHUNK_SIZE, compression: false, ..expected }
+        );
+
+        let tiny = TransferTuningConfig { chunk_size_kb: Some(4), ..Default::default() };
+        assert!(resolve(None, Some(&tiny)).unwrap_err().contains("chunk_size_kb"));
+        let uneven = TransferTuningConfig { chunk_size_kb: Some(1536), ..Default::default() };
+        assert!(resolve(None, Some(&uneven)).unwrap_err().contains("multiple"));
+        let stalled = TransferTuningConfig { max_parallel_chunks: Some(0), ..Default::default() };
+        assert!(resolve(Some(&stalled), None).is_err());
+    }
+}
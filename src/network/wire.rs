@@ -0,0 +1,513 @@
+use std::fmt;
+use std::path::{Component, Path};
+use crate::core::file_handler;
+use crate::core::models::{Capabilities, FileEventMessage, Heartbeat, SyndactylRequest, SyndactylResponse, TransferErrorKind};
+use crate::network::capabilities;
+use crate::network::transfer::{CHUNK_SIZE, MAX_CHUNK_SIZE};
+
+/// Largest gossip payload we will attempt to parse
+pub const MAX_GOSSIP_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Deepest JSON nesting accepted in a gossip payload (real messages nest 2 levels)
+pub const MAX_JSON_DEPTH: usize = 8;
+
+/// Limit for short identifier-like fields: observer names, event types, hashes, ids
+pub const MAX_NAME_LEN: usize = 256;
+
+/// Limit for relative paths
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Limit for free-form text such as event details and cancel reasons
+pub const MAX_TEXT_LEN: usize = 4096;
+
+/// Most children a served directory listing may carry
+pub const MAX_TREE_ENTRIES: usize = 65536;
+
+/// Most observers one heartbeat may describe
+pub const MAX_HEARTBEAT_OBSERVERS: usize = 256;
+
+/// Why a payload from a peer was rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    TooLarge { size: usize, max: usize },
+    TooDeep { max: usize },
+    Malformed(String),
+    InvalidField { field: &'static str, reason: String },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooLarge { size, max } => write!(f, "payload of {} bytes exceeds limit of {}", size, max),
+            DecodeError::TooDeep { max } => write!(f, "payload nested deeper than {} levels", max),
+            DecodeError::Malformed(e) => write!(f, "malformed payload: {}", e),
+            DecodeError::InvalidField { field, reason } => write!(f, "invalid {}: {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decode a gossiped FileEventMessage from an untrusted peer
+/// Size and nesting are checked before parsing, and every field is bounded after.
+pub fn decode_file_event(data: &[u8]) -> Result<FileEventMessage, DecodeError> {
+    if data.len() > MAX_GOSSIP_MESSAGE_BYTES {
+        return Err(DecodeError::TooLarge { size: data.len(), max: MAX_GOSSIP_MESSAGE_BYTES });
+    }
+    check_depth(data, MAX_JSON_DEPTH)?;
+    let msg: FileEventMessage = serde_json::from_slice(data)
+        .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    validate_file_event(&msg)?;
+    Ok(msg)
+}
+
+pub fn validate_file_event(msg: &FileEventMessage) -> Result<(), DecodeError> {
+    check_len("observer", &msg.observer, MAX_NAME_LEN)?;
+    check_path("path", &msg.path)?;
+    check_opt_len("details", &msg.details, MAX_TEXT_LEN)?;
+    check_opt_len("hash", &msg.hash, MAX_NAME_LEN)?;
+    check_opt_len("hmac", &msg.hmac, MAX_NAME_LEN)?;
+    check_opt_len("observer_id", &msg.observer_id, MAX_NAME_LEN)?;
+    if let Some(transaction) = &msg.transaction {
+        check_len("transaction.id", &transaction.id, MAX_NAME_LEN)?;
+        // The id names a staging directory that is removed whole
+        if transaction.id.is_empty() || !transaction.id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(DecodeError::InvalidField { field: "transaction.id", reason: "not a hex id".to_string() });
+        }
+    }
+    if let Some(append) = &msg.append {
+        check_len("append.prefix_hash", &append.prefix_hash, MAX_NAME_LEN)?;
+    }
+    Ok(())
+}
+
+/// Decode a heartbeat gossiped by an untrusted peer
+pub fn decode_heartbeat(data: &[u8]) -> Result<Heartbeat, DecodeError> {
+    if data.len() > MAX_GOSSIP_MESSAGE_BYTES {
+        return Err(DecodeError::TooLarge { size: data.len(), max: MAX_GOSSIP_MESSAGE_BYTES });
+    }
+    check_depth(data, MAX_JSON_DEPTH)?;
+    let heartbeat: Heartbeat = serde_json::from_slice(data)
+        .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    check_heartbeat(&heartbeat)?;
+    Ok(heartbeat)
+}
+
+fn check_heartbeat(heartbeat: &Heartbeat) -> Result<(), DecodeError> {
+    if heartbeat.observers.len() > MAX_HEARTBEAT_OBSERVERS {
+        return Err(DecodeError::TooLarge { size: heartbeat.observers.len(), max: MAX_HEARTBEAT_OBSERVERS });
+    }
+    for digest in &heartbeat.observers {
+        check_len("observer", &digest.observer, MAX_NAME_LEN)?;
+        check_opt_len("observer_id", &digest.observer_id, MAX_NAME_LEN)?;
+        check_len("root", &digest.root, MAX_NAME_LEN)?;
+        check_opt_len("hmac", &digest.hmac, MAX_NAME_LEN)?;
+    }
+    if heartbeat.removed.len() > MAX_HEARTBEAT_OBSERVERS {
+        return Err(DecodeError::TooLarge { size: heartbeat.removed.len(), max: MAX_HEARTBEAT_OBSERVERS });
+    }
+    for observer in &heartbeat.removed {
+        check_len("removed", observer, MAX_NAME_LEN)?;
+    }
+    Ok(())
+}
+
+/// Validate a request_response request after the codec has decoded it
+pub fn validate_request(request: &SyndactylRequest) -> Result<(), DecodeError> {
+    match request {
+        SyndactylRequest::FileTransfer(req) => {
+            check_len("observer", &req.observer, MAX_NAME_LEN)?;
+            check_path("path", &req.path)?;
+            check_len("hash", &req.hash, MAX_NAME_LEN)
+        }
+        SyndactylRequest::FileChunk(req) => {
+            check_len("observer", &req.observer, MAX_NAME_LEN)?;
+            check_path("path", &req.path)?;
+            check_len("hash", &req.hash, MAX_NAME_LEN)?;
+            check_opt_len("encoding", &req.encoding, MAX_NAME_LEN)?;
+            if let Some(length) = req.length.filter(|length| *length as usize > MAX_CHUNK_SIZE) {
+                return Err(DecodeError::TooLarge { size: length as usize, max: MAX_CHUNK_SIZE });
+            }
+            Ok(())
+        }
+        SyndactylRequest::CancelTransfer(req) => {
+            check_len("observer", &req.observer, MAX_NAME_LEN)?;
+            check_path("path", &req.path)?;
+            check_len("reason", &req.reason, MAX_TEXT_LEN)
+        }
+        SyndactylRequest::RangeRead(req) => {
+            check_len("observer", &req.observer, MAX_NAME_LEN)?;
+            check_path("path", &req.path)?;
+            check_len("hash", &req.hash, MAX_NAME_LEN)?;
+            if req.length as usize > CHUNK_SIZE {
+                return Err(DecodeError::TooLarge { size: req.length as usize, max: CHUNK_SIZE });
+            }
+            Ok(())
+        }
+        SyndactylRequest::TreeNode(req) => {
+            check_len("observer", &req.observer, MAX_NAME_LEN)?;
+            check_dir("dir", &req.dir)?;
+            check_opt_len("hmac", &req.hmac, MAX_NAME_LEN)?;
+            check_opt_len("after", &req.after, MAX_NAME_LEN)
+        }
+        SyndactylRequest::Capabilities(caps) => check_capabilities(caps),
+        SyndactylRequest::Heads(heads) => check_heartbeat(heads),
+    }
+}
+
+/// Validate a request_response response after the codec has decoded it
+pub fn validate_response(response: &SyndactylResponse) -> Result<(), DecodeError> {
+    match response {
+        SyndactylResponse::Chunk(chunk) => {
+            check_len("observer", &chunk.observer, MAX_NAME_LEN)?;
+            check_path("path", &chunk.path)?;
+            check_len("hash", &chunk.hash, MAX_NAME_LEN)?;
+            if let Some(segment_hash) = &chunk.segment_hash {
+                check_len("segment_hash", segment_hash, MAX_NAME_LEN)?;
+            }
+            check_opt_len("segment_hmac", &chunk.segment_hmac, MAX_NAME_LEN)?;
+            check_opt_len("encoding", &chunk.encoding, MAX_NAME_LEN)?;
+            if chunk.data.len() > MAX_CHUNK_SIZE {
+                return Err(DecodeError::TooLarge { size: chunk.data.len(), max: MAX_CHUNK_SIZE });
+            }
+            if chunk.offset.checked_add(chunk.data.len() as u64).is_none_or(|end| end > chunk.total_size) {
+                return Err(DecodeError::InvalidField { field: "offset", reason: "chunk extends past total_size".to_string() });
+            }
+            Ok(())
+        }
+        SyndactylResponse::CancelAck { observer, path } => {
+            check_len("observer", observer, MAX_NAME_LEN)?;
+            check_path("path", path)
+        }
+        SyndactylResponse::Range(range) => {
+            check_len("observer", &range.observer, MAX_NAME_LEN)?;
+            check_path("path", &range.path)?;
+            check_len("hash", &range.hash, MAX_NAME_LEN)?;
+            if range.data.len() > CHUNK_SIZE {
+                return Err(DecodeError::TooLarge { size: range.data.len(), max: CHUNK_SIZE });
+            }
+            if range.offset.checked_add(range.data.len() as u64).is_none_or(|end| end > range.total_size) {
+                return Err(DecodeError::InvalidField { field: "offset", reason: "range extends past total_size".to_string() });
+            }
+            Ok(())
+        }
+        SyndactylResponse::TreeNode(node) => {
+            check_len("observer", &node.observer, MAX_NAME_LEN)?;
+            check_dir("dir", &node.dir)?;
+            check_opt_len("hmac", &node.hmac, MAX_NAME_LEN)?;
+            check_opt_len("next", &node.next, MAX_NAME_LEN)?;
+            if node.entries.len() > MAX_TREE_ENTRIES {
+                return Err(DecodeError::TooLarge { size: node.entries.len(), max: MAX_TREE_ENTRIES });
+            }
+            for entry in &node.entries {
+                check_name("entries.name", &entry.name)?;
+                check_len("entries.hash", &entry.hash, MAX_NAME_LEN)?;
+            }
+            if node.tombstones.len() > MAX_TREE_ENTRIES {
+                return Err(DecodeError::TooLarge { size: node.tombstones.len(), max: MAX_TREE_ENTRIES });
+            }
+            for tombstone in &node.tombstones {
+                check_path("tombstones.path", &tombstone.path)?;
+                check_len("tombstones.hash", &tombstone.hash, MAX_NAME_LEN)?;
+                for (node, _) in tombstone.version.iter() {
+                    check_len("tombstones.version", node, MAX_NAME_LEN)?;
+                }
+            }
+            Ok(())
+        }
+        SyndactylResponse::Capabilities(caps) => check_capabilities(caps),
+        SyndactylResponse::Heads(heads) => check_heartbeat(heads),
+        SyndactylResponse::Error(error) => {
+            check_len("observer", &error.observer, MAX_NAME_LEN)?;
+            check_path("path", &error.path)?;
+            check_len("requested_hash", &error.requested_hash, MAX_NAME_LEN)?;
+            if let TransferErrorKind::FileChanged { current_hash, .. } = &error.kind {
+                check_opt_len("current_hash", current_hash, MAX_NAME_LEN)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check_capabilities(caps: &Capabilities) -> Result<(), DecodeError> {
+    check_len("version", &caps.version, MAX_NAME_LEN)?;
+    for (field, values) in [("features", &caps.features), ("hash_algorithms", &caps.hash_algorithms), ("compression", &caps.compression)] {
+        if values.len() > capabilities::MAX_ENTRIES {
+            return Err(DecodeError::TooLarge { size: values.len(), max: capabilities::MAX_ENTRIES });
+        }
+        for value in values {
+            check_len(field, value, MAX_NAME_LEN)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_len(field: &'static str, value: &str, max: usize) -> Result<(), DecodeError> {
+    if value.len() > max {
+        return Err(DecodeError::InvalidField { field, reason: format!("{} bytes exceeds limit of {}", value.len(), max) });
+    }
+    Ok(())
+}
+
+fn check_opt_len(field: &'static str, value: &Option<String>, max: usize) -> Result<(), DecodeError> {
+    match value {
+        Some(value) => check_len(field, value, max),
+        None => Ok(()),
+    }
+}
+
+/// Paths must stay inside the observer: relative, no `..`, no NUL bytes
+fn check_path(field: &'static str, path: &str) -> Result<(), DecodeError> {
+    check_len(field, path, MAX_PATH_LEN)?;
+    let invalid = |reason: &str| Err(DecodeError::InvalidField { field, reason: reason.to_string() });
+    if path.is_empty() {
+        return invalid("empty path");
+    }
+    if path.contains('\0') {
+        return invalid("contains NUL byte");
+    }
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            Component::ParentDir => return invalid("contains '..'"),
+            Component::RootDir | Component::Prefix(_) => return invalid("must be relative"),
+        }
+    }
+    if file_handler::is_internal_path(Path::new(path)) {
+        return invalid("inside syndactyl's internal directory");
+    }
+    Ok(())
+}
+
+/// Directories are paths, except that "" names the observer root
+fn check_dir(field: &'static str, dir: &str) -> Result<(), DecodeError> {
+    if dir.is_empty() {
+        return Ok(());
+    }
+    check_path(field, dir)
+}
+
+/// A single path component: one name, not a path
+fn check_name(field: &'static str, name: &str) -> Result<(), DecodeError> {
+    check_path(field, name)?;
+    if name.contains('/') || name == "." {
+        return Err(DecodeError::InvalidField { field, reason: "not a single name".to_string() });
+    }
+    Ok(())
+}
+
+/// Reject deeply nested JSON before handing it to serde
+/// Brackets inside strings are skipped, so this only counts real structure.
+fn check_depth(data: &[u8], max: usize) -> Result<(), DecodeError> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in data {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max {
+                    return Err(DecodeError::TooDeep { max });
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::{
+        CancelTransferRequest, EventType, FileChunkRequest, FileTransferRequest, FileTransferResponse,
+        RangeReadRequest, RangeReadResponse, TransactionInfo, TransferError, TreeEntry,
+        TreeNodeRequest, TreeNodeResponse,
+    };
+    use proptest::prelude::*;
+
+    const PATH: &str = "[a-zA-Z0-9_ -]{1,12}(/[a-zA-Z0-9_ -]{1,12}){0,3}(\\.[a-z]{1,4})?";
+    const NAME: &str = "[a-zA-Z0-9_-]{1,32}";
+
+    fn file_event() -> impl Strategy<Value = FileEventMessage> {
+        (
+            // Metadata and Rename are sent as Modify, so they don't read back as themselves
+            (NAME, proptest::sample::select(vec![EventType::Create, EventType::Modify, EventType::Remove, EventType::Other]), PATH, proptest::option::of(".{0,64}")),
+            (proptest::option::of("[0-9a-f]{64}"), any::<Option<u64>>(), any::<Option<u64>>()),
+            (proptest::option::of("[0-9a-f]{64}"), proptest::option::of(("[0-9a-f]{16}", any::<u32>())), proptest::option::of("[0-9a-f]{32}")),
+        )
+            .prop_map(|((observer, event_type, path, details), (hash, size, modified_time), (hmac, transaction, observer_id))| {
+                FileEventMessage {
+                    observer,
+                    event_type,
+                    path,
+                    details,
+                    hash,
+                    size,
+                    modified_time,
+                    hmac,
+                    transaction: transaction.map(|(id, size)| TransactionInfo { id, size }),
+                    observer_id,
+                    append: None,
+                    sequence: None,
+                }
+            })
+    }
+
+    fn request() -> impl Strategy<Value = SyndactylRequest> {
+        prop_oneof![
+            (NAME, PATH, NAME).prop_map(|(observer, path, hash)| {
+                SyndactylRequest::FileTransfer(FileTransferRequest { observer, path, hash })
+            }),
+            (NAME, PATH, any::<u64>(), NAME, proptest::option::of(0..=MAX_CHUNK_SIZE as u32), proptest::option::of(NAME))
+                .prop_map(|(observer, path, offset, hash, length, encoding)| {
+                    SyndactylRequest::FileChunk(FileChunkRequest { observer, path, offset, hash, length, encoding })
+                }),
+            (NAME, PATH, ".{0,64}").prop_map(|(observer, path, reason)| {
+                SyndactylRequest::CancelTransfer(CancelTransferRequest { observer, path, reason })
+            }),
+            (NAME, PATH, any::<u64>(), 0..=CHUNK_SIZE as u32, NAME).prop_map(|(observer, path, offset, length, hash)| {
+                SyndactylRequest::RangeRead(RangeReadRequest { observer, path, offset, length, hash })
+            }),
+            (NAME, proptest::option::of(PATH), proptest::option::of(NAME)).prop_map(|(observer, dir, hmac)| {
+                SyndactylRequest::TreeNode(TreeNodeRequest { observer, dir: dir.unwrap_or_default(), hmac, after: None })
+            }),
+            (NAME, proptest::collection::vec(NAME, 0..8), any::<u64>()).prop_map(|(version, features, max_file_size)| {
+                SyndactylRequest::Capabilities(Capabilities { version, features, max_file_size, ..Capabilities::default() })
+            }),
+            proptest::collection::vec(NAME, 0..8).prop_map(|removed| {
+                SyndactylRequest::Heads(Heartbeat { observers: Vec::new(), removed, load: None })
+            }),
+        ]
+    }
+
+    fn response() -> impl Strategy<Value = SyndactylResponse> {
+        prop_oneof![
+            (NAME, PATH, proptest::collection::vec(any::<u8>(), 0..256), 0u64..1024, 0u64..1024, NAME, any::<bool>(), proptest::option::of(NAME))
+                .prop_map(|(observer, path, data, offset, extra, hash, is_last_chunk, segment_hash)| {
+                    let total_size = offset + data.len() as u64 + extra;
+                    let encoding = segment_hash.as_ref().map(|_| "zstd".to_string());
+                    SyndactylResponse::Chunk(FileTransferResponse { observer, path, data, offset, total_size, hash, is_last_chunk, segment_hash, segment_hmac: None, encoding })
+                }),
+            (NAME, PATH).prop_map(|(observer, path)| SyndactylResponse::CancelAck { observer, path }),
+            (NAME, PATH, proptest::collection::vec(any::<u8>(), 0..256), 0u64..1024, 0u64..1024, NAME)
+                .prop_map(|(observer, path, data, offset, extra, hash)| {
+                    let total_size = offset + data.len() as u64 + extra;
+                    SyndactylResponse::Range(RangeReadResponse { observer, path, offset, data, total_size, hash })
+                }),
+            (NAME, PATH, NAME, proptest::option::of((any::<Option<u64>>(), proptest::option::of(NAME))))
+                .prop_map(|(observer, path, requested_hash, changed)| {
+                    let kind = match changed {
+                        Some((current_size, current_hash)) => TransferErrorKind::FileChanged { current_size, current_hash },
+                        None => TransferErrorKind::NotFound,
+                    };
+                    SyndactylResponse::Error(TransferError { observer, path, requested_hash, kind })
+                }),
+            (NAME, PATH, proptest::collection::vec((NAME, NAME, any::<bool>(), any::<Option<u64>>()), 0..8))
+                .prop_map(|(observer, dir, entries)| {
+                    let entries = entries.into_iter()
+                        .map(|(name, hash, is_dir, size)| TreeEntry { name, hash, is_dir, size, modified_time: size })
+                        .collect();
+                    SyndactylResponse::TreeNode(TreeNodeResponse { observer, dir, entries, hmac: None, tombstones: Vec::new(), next: None, total_entries: 0 })
+                }),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn file_event_roundtrip(msg in file_event()) {
+            let json = serde_json::to_vec(&msg).unwrap();
+            prop_assert_eq!(decode_file_event(&json).unwrap(), msg);
+        }
+
+        #[test]
+        fn request_roundtrip(request in request()) {
+            let json = serde_json::to_vec(&request).unwrap();
+            let decoded: SyndactylRequest = serde_json::from_slice(&json).unwrap();
+            prop_assert!(validate_request(&decoded).is_ok());
+            prop_assert_eq!(decoded, request);
+        }
+
+        #[test]
+        fn response_roundtrip(response in response()) {
+            let json = serde_json::to_vec(&response).unwrap();
+            let decoded: SyndactylResponse = serde_json::from_slice(&json).unwrap();
+            prop_assert!(validate_response(&decoded).is_ok());
+            prop_assert_eq!(decoded, response);
+        }
+
+        #[test]
+        fn decode_never_panics(data in proptest::collection::vec(any::<u8>(), 0..4096)) {
+            let _ = decode_file_event(&data);
+        }
+    }
+
+    #[test]
+    fn test_rejects_hostile_payloads() {
+        let oversized = vec![b' '; MAX_GOSSIP_MESSAGE_BYTES + 1];
+        assert!(matches!(decode_file_event(&oversized), Err(DecodeError::TooLarge { .. })));
+
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        assert!(matches!(decode_file_event(nested.as_bytes()), Err(DecodeError::TooDeep { .. })));
+
+        // Brackets inside strings do not count towards depth
+        let msg = br#"{"observer":"docs","event_type":"Create","path":"[[[[[[[[[[.txt","details":null,"hash":null,"size":null,"modified_time":null,"hmac":null}"#;
+        assert!(decode_file_event(msg).is_ok());
+
+        for path in ["../etc/passwd", "/etc/passwd", "a/../../b", "", ".syndactyl/trash/notes.txt.1700000000"] {
+            let msg = format!(r#"{{"observer":"docs","event_type":"Create","path":"{}","details":null,"hash":null,"size":null,"modified_time":null,"hmac":null}}"#, path);
+            assert!(matches!(decode_file_event(msg.as_bytes()), Err(DecodeError::InvalidField { field: "path", .. })), "{}", path);
+        }
+
+        // Transaction ids name a staging directory, so only hex ids get through
+        for id in ["../..", "a/b", "", "ab12"] {
+            let msg = format!(r#"{{"observer":"docs","event_type":"Create","path":"a.txt","details":null,"hash":null,"size":null,"modified_time":null,"hmac":null,"transaction":{{"id":"{}","size":2}}}}"#, id);
+            let rejected = matches!(decode_file_event(msg.as_bytes()), Err(DecodeError::InvalidField { field: "transaction.id", .. }));
+            assert_eq!(rejected, id != "ab12", "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_cancellations_round_trip_in_both_directions() {
+        let cancel = SyndactylRequest::CancelTransfer(CancelTransferRequest {
+            observer: "docs".to_string(),
+            path: "a/b.txt".to_string(),
+            reason: "deleted locally".to_string(),
+        });
+        let json = serde_json::to_string(&cancel).unwrap();
+        assert_eq!(json, r#"{"CancelTransfer":{"observer":"docs","path":"a/b.txt","reason":"deleted locally"}}"#);
+        let decoded: SyndactylRequest = serde_json::from_str(&json).unwrap();
+        assert!(validate_request(&decoded).is_ok());
+        assert_eq!(decoded, cancel);
+
+        let ack = SyndactylResponse::CancelAck { observer: "docs".to_string(), path: "a/b.txt".to_string() };
+        let decoded: SyndactylResponse = serde_json::from_str(&serde_json::to_string(&ack).unwrap()).unwrap();
+        assert!(validate_response(&decoded).is_ok());
+        assert_eq!(decoded, ack);
+
+        // Cancelling a path outside the observer or with an unbounded reason is refused
+        let escape = SyndactylRequest::CancelTransfer(CancelTransferRequest {
+            observer: "docs".to_string(),
+            path: "../secrets".to_string(),
+            reason: String::new(),
+        });
+        assert!(validate_request(&escape).is_err());
+        let rambling = SyndactylRequest::CancelTransfer(CancelTransferRequest {
+            observer: "docs".to_string(),
+            path: "a.txt".to_string(),
+            reason: "x".repeat(MAX_TEXT_LEN + 1),
+        });
+        assert!(matches!(validate_request(&rambling), Err(DecodeError::InvalidField { field: "reason", .. })));
+    }
+}
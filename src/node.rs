@@ -0,0 +1,193 @@
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+use tracing::{error, info};
+use crate::core::audit::AuditLog;
+use crate::core::config::Config;
+use crate::core::observer;
+use crate::core::power::PowerMonitor;
+use crate::network::manager::NetworkManager;
+
+/// Subsystems the network manager starts, as registered on the node
+/// A configured subsystem that isn't registered is never constructed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Subsystems {
+    pub metrics: bool,
+    pub bridges: bool,
+    pub mounts: bool,
+}
+
+/// A syndactyl daemon for one configuration, with its optional subsystems
+/// Heavyweight subsystems are cargo features and run only when registered with
+/// a `with_*` method, so embedders start just the parts they need.
+pub struct SyndactylNode {
+    config: Config,
+    browser: bool,
+    metrics: bool,
+    bridges: bool,
+    mounts: bool,
+}
+
+impl SyndactylNode {
+    /// A node running observers and networking only
+    pub fn new(config: Config) -> Self {
+        Self { config, browser: false, metrics: false, bridges: false, mounts: false }
+    }
+
+    /// Serve the read-only file browser, the dashboard, if one is configured
+    #[cfg(feature = "browser")]
+    pub fn with_browser(mut self) -> Self {
+        self.browser = true;
+        self
+    }
+
+    /// Write and serve metrics, and export them over OTLP when built with it
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Publish events to the configured webhook and MQTT bridges
+    #[cfg(feature = "bridges")]
+    pub fn with_bridges(mut self) -> Self {
+        self.bridges = true;
+        self
+    }
+
+    /// Mount on-demand observers with FUSE
+    #[cfg(feature = "fuse")]
+    pub fn with_mounts(mut self) -> Self {
+        self.mounts = true;
+        self
+    }
+
+    /// Register every subsystem this build includes
+    pub fn with_all_features(self) -> Self {
+        Self {
+            browser: cfg!(feature = "browser"),
+            metrics: cfg!(feature = "metrics"),
+            bridges: cfg!(feature = "bridges"),
+            mounts: cfg!(feature = "fuse"),
+            ..self
+        }
+    }
+
+    /// Refuse configuration sections of subsystems that are built in but not registered
+    /// Sections of subsystems left out of the build are refused where they're started.
+    fn check_registered(&self) -> Result<(), String> {
+        let refuse = |section: &str| Err(format!("{} is configured but its subsystem isn't registered on this node", section));
+        if self.config.browser.is_some() && cfg!(not(feature = "browser")) {
+            return Err("A file browser is configured but syndactyl was built without the browser feature".to_string());
+        }
+        if self.config.browser.is_some() && !self.browser {
+            return refuse("browser");
+        }
+        let metrics = [
+            ("metrics_file", self.config.metrics_file.is_some()),
+            ("metrics_http", self.config.metrics_http.is_some()),
+            ("otlp", self.config.otlp.is_some()),
+        ];
+        if let Some((section, _)) = metrics.iter().find(|(_, set)| *set && cfg!(feature = "metrics") && !self.metrics) {
+            return refuse(section);
+        }
+        if self.config.bridges.as_ref().is_some_and(|bridges| !bridges.is_empty()) && cfg!(feature = "bridges") && !self.bridges {
+            return refuse("bridges");
+        }
+        if let Some(obs) = self.config.observers.iter().find(|obs| obs.mount.is_some() && cfg!(feature = "fuse") && !self.mounts) {
+            return refuse(&format!("The mount of observer {}", obs.name));
+        }
+        Ok(())
+    }
+
+    /// Run the observers, the registered subsystems and the network manager until stopped
+    pub async fn run(self) {
+        if let Err(e) = self.check_registered() {
+            error!(%e, "Not starting");
+            return;
+        }
+        let subsystems = Subsystems { metrics: self.metrics, bridges: self.bridges, mounts: self.mounts };
+        let configuration = self.config;
+
+        // One log for files served to peers and downloaded through the browser
+        let audit_log = match &configuration.audit_log {
+            Some(path) => match AuditLog::open(std::path::Path::new(path)) {
+                Ok(audit_log) => {
+                    info!(path = %path, "Auditing served files");
+                    Some(audit_log)
+                }
+                Err(e) => {
+                    error!(path = %path, error = %e, "Failed to open audit log");
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        // Spawn Observer and set up channel for file events
+        let (observer_tx, observer_rx) = std_mpsc::channel::<String>();
+        let observer_config = configuration.observers.clone();
+        let logging_config = configuration.logging.clone().unwrap_or_default();
+        let power = PowerMonitor::new(configuration.power.as_ref());
+        power.refresh();
+        let observer_power = power.clone();
+        let observer_thread = thread::spawn(move || {
+            let _observer = observer::event_listener(observer_config, logging_config, observer_power, observer_tx);
+            info!("Observer started");
+        });
+
+        // Read-only HTTP access for devices that can't run syndactyl
+        #[cfg(feature = "browser")]
+        if let Some(browser_config) = configuration.browser.as_ref().filter(|_| self.browser) {
+            let control = configuration.control_socket_path().ok().zip(configuration.control_token_path().ok());
+            if let Err(e) = crate::browser::spawn(browser_config, &configuration.observers, control, audit_log.clone()).await {
+                error!(%e, "Failed to start file browser");
+                return;
+            }
+        }
+
+        // P2P networking and encryption (async)
+        if configuration.network.is_some() {
+            // Create and run the network manager
+            match NetworkManager::new(configuration, power, subsystems, audit_log).await {
+                Ok(network_manager) => {
+                    info!("Network manager created successfully");
+                    // Run the network manager with observer events
+                    network_manager.run(observer_rx).await;
+                    // Stopped, e.g. for --takeover: release the state directory rather than wait on the observer thread
+                    return;
+                }
+                Err(e) => {
+                    error!(%e, "Failed to create network manager");
+                    return;
+                }
+            }
+        }
+
+        // Wait for observer thread to finish, without holding up other tenants
+        let _ = tokio::task::spawn_blocking(move || observer_thread.join()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_subsystems_must_be_registered() {
+        let config = |json: &str| -> Config { serde_json::from_str(json).unwrap() };
+        assert!(SyndactylNode::new(config(r#"{"observers": [], "network": null}"#)).check_registered().is_ok());
+
+        // Metrics are a default feature
+        let metrics = config(r#"{"observers": [], "network": null, "metrics_file": "/tmp/syndactyl.prom"}"#);
+        #[cfg(feature = "metrics")]
+        {
+            assert!(SyndactylNode::new(metrics.clone()).check_registered().unwrap_err().contains("metrics_file"));
+            assert!(SyndactylNode::new(metrics.clone()).with_metrics().check_registered().is_ok());
+        }
+        assert!(SyndactylNode::new(metrics).with_all_features().check_registered().is_ok());
+
+        let browsed = config(r#"{"observers": [], "network": null, "browser": {"listen": "127.0.0.1:8080", "token": "t", "observers": []}}"#);
+        assert!(SyndactylNode::new(browsed.clone()).check_registered().is_err());
+        assert_eq!(SyndactylNode::new(browsed).with_all_features().check_registered().is_ok(), cfg!(feature = "browser"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use syndactyl::control;
+use syndactyl::core::config;
+use syndactyl::core::setup::{self, NewObserver, DEFAULT_PORT};
+
+/// Ask a question on the terminal, returning the trimmed answer or `default` when it is left empty
+fn ask(question: &str, default: Option<&str>) -> io::Result<String> {
+    match default {
+        Some(default) => print!("{} [{}]: ", question, default),
+        None => print!("{}: ", question),
+    }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input closed"));
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default.unwrap_or_default().to_string() } else { answer.to_string() })
+}
+
+fn confirm(question: &str, default: bool) -> io::Result<bool> {
+    let answer = ask(&format!("{} ({})", question, if default { "Y/n" } else { "y/N" }), None)?;
+    Ok(match answer.to_ascii_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+/// Ask until `check` accepts the answer, printing why it didn't
+fn ask_until<T>(question: &str, default: Option<&str>, check: impl Fn(&str) -> Result<T, String>) -> io::Result<T> {
+    loop {
+        match check(&ask(question, default)?) {
+            Ok(value) => return Ok(value),
+            Err(e) => println!("  {}", e),
+        }
+    }
+}
+
+fn ask_observer(taken: &[NewObserver]) -> io::Result<NewObserver> {
+    let name = ask_until("Observer name (the same on every peer)", None, |name| {
+        setup::check_name(name, taken).map(|_| name.to_string())
+    })?;
+    let path = loop {
+        let path = ask_until("Directory to sync", None, |path| setup::resolve_path(path, taken))?;
+        if path.is_dir() {
+            break path;
+        }
+        if confirm(&format!("{} does not exist. Create it?", path.display()), true)? {
+            match fs::create_dir_all(&path) {
+                Ok(()) => break path,
+                Err(e) => println!("  Could not create {}: {}", path.display(), e),
+            }
+        }
+    };
+    let secret = ask("Shared secret from a peer already syncing it (leave empty to generate one)", None)?;
+    let shared_secret = if secret.is_empty() {
+        let secret = setup::generate_secret();
+        println!("  Generated secret: {}", secret);
+        println!("  Give it to every peer that should sync {}; anyone holding it can read and change the files.", name);
+        secret
+    } else {
+        secret
+    };
+    Ok(NewObserver { name, path, shared_secret })
+}
+
+fn wizard() -> Result<i32, Box<dyn std::error::Error>> {
+    let config_path = config::config_path()?;
+    println!("This creates {}.\n", config_path.display());
+    if config_path.exists() && !confirm(&format!("{} already exists. Replace it?", config_path.display()), false)? {
+        println!("Left the existing configuration alone");
+        return Ok(1);
+    }
+
+    let mut observers = Vec::new();
+    loop {
+        observers.push(ask_observer(&observers)?);
+        if !confirm("Add another observer?", false)? {
+            break;
+        }
+    }
+
+    let port = ask_until("Port to listen on", Some(&DEFAULT_PORT.to_string()), setup::check_port)?;
+    let bootstrap = if confirm("Connect to a peer that is already running?", true)? {
+        println!("  `syndactyl id` on that peer prints its pairing code and addresses.");
+        Some(ask_until("Pairing code or address, e.g. /ip4/192.168.1.10/tcp/4001/p2p/12D3KooW...", None, setup::parse_peer)?)
+    } else {
+        None
+    };
+
+    let contents = setup::render(&observers, port, bootstrap.as_ref())?;
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // The file holds the shared secrets, so it is never readable by other users, even briefly
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&config_path)?;
+    // A file being replaced keeps its mode, so tighten it before writing
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    // CLI commands present this token to the daemon; other users can't read it
+    let token_path = serde_json::from_str::<config::Config>(&contents)?.control_token_path()?;
+    control::load_or_create_token(&token_path)?;
+    println!("\nWrote {}. Run `syndactyl` to start syncing.", config_path.display());
+    Ok(0)
+}
+
+/// Create a configuration by asking questions, returning the process exit code
+pub fn run() -> i32 {
+    match wizard() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("\nSetup stopped: {}", e);
+            1
+        }
+    }
+}